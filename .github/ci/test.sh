@@ -9,7 +9,9 @@ export CARGO_HOME=/ci/cache/cargo
 export CARGO_TARGET_DIR=/ci/cache/target
 
 cargo test --manifest-path ./embassy-futures/Cargo.toml
+cargo test --manifest-path ./embassy-futures/Cargo.toml --features time,embedded-hal-async
 cargo test --manifest-path ./embassy-sync/Cargo.toml
+cargo test --manifest-path ./embassy-sync/Cargo.toml --features time
 cargo test --manifest-path ./embassy-embedded-hal/Cargo.toml
 cargo test --manifest-path ./embassy-hal-internal/Cargo.toml
 cargo test --manifest-path ./embassy-time/Cargo.toml --features generic-queue,mock-driver
@@ -0,0 +1,409 @@
+//! Bluetooth HCI transport over the shared bus.
+//!
+//! The CYW43439 runs its Bluetooth controller behind the same gSPI bus as WiFi. The host
+//! exchanges HCI packets with it through two ring buffers in the chip's RAM, and signals new
+//! data through a pair of mailbox registers. The [`Runner`](crate::Runner) drives both WiFi
+//! and Bluetooth, so a BLE host stack can run on top of [`BtDriver`] alongside `embassy-net`.
+//!
+//! Packets are exchanged in the UART (H4) format: one packet indicator byte, followed by the
+//! HCI packet itself.
+
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::zerocopy_channel;
+use embassy_time::Timer;
+use embedded_hal_1::digital::OutputPin;
+
+use crate::bus::Bus;
+use crate::consts::*;
+use crate::fmt::Bytes;
+use crate::SpiBusCyw43;
+
+/// Maximum size of an HCI packet, including the packet indicator byte.
+pub const BT_HCI_MTU: usize = 1024;
+
+const HEADER_SIZE: u32 = 4;
+
+pub(crate) struct BtPacketBuf {
+    len: usize,
+    buf: [u8; BT_HCI_MTU],
+}
+
+impl BtPacketBuf {
+    const NEW: Self = Self {
+        len: 0,
+        buf: [0; BT_HCI_MTU],
+    };
+}
+
+/// Bluetooth state, part of [`State`](crate::State).
+pub(crate) struct BtState {
+    rx: [BtPacketBuf; 4],
+    tx: [BtPacketBuf; 4],
+    inner: MaybeUninit<BtStateInner<'static>>,
+}
+
+impl BtState {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx: [BtPacketBuf::NEW; 4],
+            tx: [BtPacketBuf::NEW; 4],
+            inner: MaybeUninit::uninit(),
+        }
+    }
+}
+
+struct BtStateInner<'d> {
+    rx: zerocopy_channel::Channel<'d, NoopRawMutex, BtPacketBuf>,
+    tx: zerocopy_channel::Channel<'d, NoopRawMutex, BtPacketBuf>,
+}
+
+/// Error returned by [`BtDriver`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The packet is larger than [`BT_HCI_MTU`], or the provided buffer is too small for it.
+    Overflow,
+    /// The packet is empty, or its packet indicator is not a valid HCI packet type.
+    InvalidPacket,
+}
+
+/// HCI transport to the Bluetooth controller of the chip.
+///
+/// Packets are in the UART (H4) format. Reading and writing can be done concurrently from
+/// different tasks.
+pub struct BtDriver<'d> {
+    rx: Mutex<NoopRawMutex, zerocopy_channel::Receiver<'d, NoopRawMutex, BtPacketBuf>>,
+    tx: Mutex<NoopRawMutex, zerocopy_channel::Sender<'d, NoopRawMutex, BtPacketBuf>>,
+}
+
+impl<'d> BtDriver<'d> {
+    /// Read an HCI packet from the controller into `buf`.
+    ///
+    /// Returns the length of the packet, including the packet indicator byte.
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut rx = self.rx.lock().await;
+        let packet = rx.receive().await;
+        let len = packet.len;
+        let res = match buf.get_mut(..len) {
+            Some(buf) => {
+                buf.copy_from_slice(&packet.buf[..len]);
+                Ok(len)
+            }
+            None => Err(Error::Overflow),
+        };
+        rx.receive_done();
+        res
+    }
+
+    /// Write an HCI packet, starting with its packet indicator byte, to the controller.
+    pub async fn write(&self, packet: &[u8]) -> Result<(), Error> {
+        if packet.len() > BT_HCI_MTU {
+            return Err(Error::Overflow);
+        }
+        match packet.first() {
+            Some(0x01..=0x05) => {}
+            _ => return Err(Error::InvalidPacket),
+        }
+
+        let mut tx = self.tx.lock().await;
+        let buf = tx.send().await;
+        buf.buf[..packet.len()].copy_from_slice(packet);
+        buf.len = packet.len();
+        tx.send_done();
+        Ok(())
+    }
+}
+
+pub(crate) struct BtRunner<'d> {
+    rx: zerocopy_channel::Sender<'d, NoopRawMutex, BtPacketBuf>,
+    tx: zerocopy_channel::Receiver<'d, NoopRawMutex, BtPacketBuf>,
+
+    // Base address of the shared ring buffers.
+    addr: u32,
+    h2b_write_pointer: u32,
+    b2h_read_pointer: u32,
+}
+
+pub(crate) fn new<'d>(state: &'d mut BtState) -> (BtRunner<'d>, BtDriver<'d>) {
+    // safety: this is a self-referential struct, however:
+    // - it can't move while the `'d` borrow is active.
+    // - when the borrow ends, the dangling references inside the MaybeUninit will never be used again.
+    let inner: *mut MaybeUninit<BtStateInner<'d>> =
+        (&mut state.inner as *mut MaybeUninit<BtStateInner<'static>>).cast();
+    let inner = unsafe { &mut *inner }.write(BtStateInner {
+        rx: zerocopy_channel::Channel::new(&mut state.rx[..]),
+        tx: zerocopy_channel::Channel::new(&mut state.tx[..]),
+    });
+
+    let (rx_sender, rx_receiver) = inner.rx.split();
+    let (tx_sender, tx_receiver) = inner.tx.split();
+
+    (
+        BtRunner {
+            rx: rx_sender,
+            tx: tx_receiver,
+            addr: 0,
+            h2b_write_pointer: 0,
+            b2h_read_pointer: 0,
+        },
+        BtDriver {
+            rx: Mutex::new(rx_receiver),
+            tx: Mutex::new(tx_sender),
+        },
+    )
+}
+
+impl<'d> BtRunner<'d> {
+    pub(crate) async fn init<PWR, SPI>(&mut self, bus: &mut Bus<PWR, SPI>, firmware: &[u8])
+    where
+        PWR: OutputPin,
+        SPI: SpiBusCyw43,
+    {
+        debug!("bluetooth init");
+
+        bus.bp_write32(BT2WLAN_PWRUP_ADDR, BT2WLAN_PWRUP_WAKE).await;
+        Timer::after_millis(2).await;
+
+        self.upload_firmware(bus, firmware).await;
+
+        debug!("waiting for bluetooth firmware...");
+        while bus.bp_read32(BT_CTRL_REG_ADDR).await & BTSDIO_REG_FW_RDY_BITMASK == 0 {
+            Timer::after_millis(1).await;
+        }
+
+        self.addr = bus.bp_read32(WLAN_RAM_BASE_REG_ADDR).await;
+        self.h2b_write_pointer = 0;
+        self.b2h_read_pointer = 0;
+        bus.bp_write32(self.addr + BTSDIO_OFFSET_HOST2BT_IN, 0).await;
+        bus.bp_write32(self.addr + BTSDIO_OFFSET_HOST2BT_OUT, 0).await;
+        bus.bp_write32(self.addr + BTSDIO_OFFSET_BT2HOST_IN, 0).await;
+        bus.bp_write32(self.addr + BTSDIO_OFFSET_BT2HOST_OUT, 0).await;
+
+        Self::wake(bus).await;
+
+        let val = bus.bp_read32(HOST_CTRL_REG_ADDR).await;
+        bus.bp_write32(HOST_CTRL_REG_ADDR, val | BTSDIO_REG_SW_RDY_BITMASK)
+            .await;
+        Self::toggle_intr(bus).await;
+
+        debug!("bluetooth init done");
+    }
+
+    async fn upload_firmware<PWR, SPI>(&mut self, bus: &mut Bus<PWR, SPI>, firmware: &[u8])
+    where
+        PWR: OutputPin,
+        SPI: SpiBusCyw43,
+    {
+        debug!("loading bluetooth fw");
+
+        // The image starts with a length-prefixed version string, followed by one extra byte.
+        let version_len = firmware[0] as usize;
+        let mut data = &firmware[1 + version_len + 1..];
+
+        let mut base = BTFW_MEM_OFFSET;
+        // Each record is a line of an Intel HEX file in binary form:
+        // byte count, big endian 16-bit address, record type, data.
+        while data.len() >= 4 {
+            let len = data[0] as usize;
+            let offset = u16::from_be_bytes([data[1], data[2]]) as u32;
+            let kind = data[3];
+            let record = &data[4..4 + len];
+            data = &data[4 + len..];
+
+            match kind {
+                BTFW_HEX_LINE_TYPE_DATA => write_unaligned(bus, base + offset, record).await,
+                BTFW_HEX_LINE_TYPE_EXTENDED_SEGMENT_ADDRESS => {
+                    base = BTFW_MEM_OFFSET + ((u16::from_be_bytes([record[0], record[1]]) as u32) << 4);
+                }
+                BTFW_HEX_LINE_TYPE_EXTENDED_ADDRESS => {
+                    base = BTFW_MEM_OFFSET + ((u16::from_be_bytes([record[0], record[1]]) as u32) << 16);
+                }
+                BTFW_HEX_LINE_TYPE_ABSOLUTE_32BIT_ADDRESS => {
+                    base = u32::from_be_bytes([record[0], record[1], record[2], record[3]]);
+                }
+                BTFW_HEX_LINE_TYPE_END_OF_DATA => break,
+                _ => warn!("unknown bluetooth fw record type {}", kind),
+            }
+        }
+    }
+
+    async fn wake<PWR, SPI>(bus: &mut Bus<PWR, SPI>)
+    where
+        PWR: OutputPin,
+        SPI: SpiBusCyw43,
+    {
+        if bus.bp_read32(BT_CTRL_REG_ADDR).await & BTSDIO_REG_BT_AWAKE_BITMASK != 0 {
+            return;
+        }
+
+        let val = bus.bp_read32(HOST_CTRL_REG_ADDR).await;
+        bus.bp_write32(HOST_CTRL_REG_ADDR, val | BTSDIO_REG_WAKE_BT_BITMASK)
+            .await;
+        while bus.bp_read32(BT_CTRL_REG_ADDR).await & BTSDIO_REG_BT_AWAKE_BITMASK == 0 {
+            Timer::after_millis(1).await;
+        }
+    }
+
+    async fn toggle_intr<PWR, SPI>(bus: &mut Bus<PWR, SPI>)
+    where
+        PWR: OutputPin,
+        SPI: SpiBusCyw43,
+    {
+        let val = bus.bp_read32(HOST_CTRL_REG_ADDR).await;
+        bus.bp_write32(HOST_CTRL_REG_ADDR, val ^ BTSDIO_REG_DATA_VALID_BITMASK)
+            .await;
+    }
+
+    /// Wait until the host has queued a packet for the controller.
+    pub(crate) async fn tx_ready(&mut self) {
+        poll_fn(|cx| self.tx.poll_receive(cx).map(|_| ())).await
+    }
+
+    /// Write the queued packet into the host to controller ring buffer.
+    pub(crate) async fn hci_write<PWR, SPI>(&mut self, bus: &mut Bus<PWR, SPI>)
+    where
+        PWR: OutputPin,
+        SPI: SpiBusCyw43,
+    {
+        let Some(packet) = self.tx.try_receive() else {
+            return;
+        };
+        let packet = &packet.buf[..packet.len];
+        trace!("bt tx {:02x}", Bytes(&packet[..packet.len().min(48)]));
+
+        // The shared bus header is the 24-bit payload length and the packet indicator.
+        let payload_len = packet.len() as u32 - 1;
+        let header = (payload_len | (packet[0] as u32) << 24).to_le_bytes();
+        let total_len = (HEADER_SIZE + payload_len + 3) & !3;
+
+        Self::wake(bus).await;
+
+        // Wait for the controller to free up enough space.
+        loop {
+            let read_pointer = bus.bp_read32(self.addr + BTSDIO_OFFSET_HOST2BT_OUT).await;
+            let free = read_pointer.wrapping_sub(self.h2b_write_pointer + 4) % BTSDIO_FWBUF_SIZE;
+            if free >= total_len {
+                break;
+            }
+            Timer::after_micros(100).await;
+        }
+
+        let base = self.addr + BTSDIO_OFFSET_HOST_WRITE_BUF;
+        let mut pointer = self.h2b_write_pointer;
+        pointer = ring_write(bus, base, pointer, &header).await;
+        ring_write(bus, base, pointer, &packet[1..]).await;
+
+        self.h2b_write_pointer = (self.h2b_write_pointer + total_len) % BTSDIO_FWBUF_SIZE;
+        bus.bp_write32(self.addr + BTSDIO_OFFSET_HOST2BT_IN, self.h2b_write_pointer)
+            .await;
+        Self::toggle_intr(bus).await;
+
+        self.tx.receive_done();
+    }
+
+    /// Handle a mailbox interrupt from the controller, reading all pending packets.
+    pub(crate) async fn handle_irq<PWR, SPI>(&mut self, bus: &mut Bus<PWR, SPI>)
+    where
+        PWR: OutputPin,
+        SPI: SpiBusCyw43,
+    {
+        let base = self.addr + BTSDIO_OFFSET_HOST_READ_BUF;
+        let write_pointer = bus.bp_read32(self.addr + BTSDIO_OFFSET_BT2HOST_IN).await;
+
+        while self.b2h_read_pointer != write_pointer {
+            let mut header = [0; HEADER_SIZE as usize];
+            bus.bp_read(base + self.b2h_read_pointer, &mut header).await;
+            let header = u32::from_le_bytes(header);
+            let payload_len = header & 0x00ff_ffff;
+            let kind = (header >> 24) as u8;
+            let payload_pointer = (self.b2h_read_pointer + HEADER_SIZE) % BTSDIO_FWBUF_SIZE;
+
+            if payload_len as usize + 1 > BT_HCI_MTU {
+                warn!("bt rx packet too large: {}", payload_len);
+            } else {
+                match self.rx.try_send() {
+                    Some(buf) => {
+                        buf.buf[0] = kind;
+                        ring_read(bus, base, payload_pointer, &mut buf.buf[1..][..payload_len as usize]).await;
+                        buf.len = payload_len as usize + 1;
+                        trace!("bt rx {:02x}", Bytes(&buf.buf[..buf.len.min(48)]));
+                        self.rx.send_done();
+                    }
+                    None => warn!("failed to push bt rxd packet to the channel."),
+                }
+            }
+
+            let total_len = (HEADER_SIZE + payload_len + 3) & !3;
+            self.b2h_read_pointer = (self.b2h_read_pointer + total_len) % BTSDIO_FWBUF_SIZE;
+        }
+
+        bus.bp_write32(self.addr + BTSDIO_OFFSET_BT2HOST_OUT, self.b2h_read_pointer)
+            .await;
+        Self::toggle_intr(bus).await;
+    }
+}
+
+/// Write `data` to a ring buffer at `pointer`, returning the pointer past the written data.
+async fn ring_write<PWR, SPI>(bus: &mut Bus<PWR, SPI>, base: u32, pointer: u32, data: &[u8]) -> u32
+where
+    PWR: OutputPin,
+    SPI: SpiBusCyw43,
+{
+    let first = data.len().min((BTSDIO_FWBUF_SIZE - pointer) as usize);
+    bus.bp_write(base + pointer, &data[..first]).await;
+    if first < data.len() {
+        bus.bp_write(base, &data[first..]).await;
+    }
+    (pointer + data.len() as u32) % BTSDIO_FWBUF_SIZE
+}
+
+/// Read `data` from a ring buffer at `pointer`.
+async fn ring_read<PWR, SPI>(bus: &mut Bus<PWR, SPI>, base: u32, pointer: u32, data: &mut [u8])
+where
+    PWR: OutputPin,
+    SPI: SpiBusCyw43,
+{
+    let first = data.len().min((BTSDIO_FWBUF_SIZE - pointer) as usize);
+    bus.bp_read(base + pointer, &mut data[..first]).await;
+    if first < data.len() {
+        bus.bp_read(base, &mut data[first..]).await;
+    }
+}
+
+/// Backplane write to an address that may not be word aligned.
+async fn write_unaligned<PWR, SPI>(bus: &mut Bus<PWR, SPI>, mut addr: u32, mut data: &[u8])
+where
+    PWR: OutputPin,
+    SPI: SpiBusCyw43,
+{
+    // Merge a partial first or last word with the current memory contents.
+    while !data.is_empty() && (addr % 4 != 0 || data.len() < 4) {
+        let word_addr = addr & !3;
+        let start = (addr - word_addr) as usize;
+        let len = data.len().min(4 - start);
+
+        let mut word = bus.bp_read32(word_addr).await.to_le_bytes();
+        word[start..start + len].copy_from_slice(&data[..len]);
+        bus.bp_write32(word_addr, u32::from_le_bytes(word)).await;
+
+        addr += len as u32;
+        data = &data[len..];
+    }
+
+    let aligned = data.len() & !3;
+    if aligned != 0 {
+        bus.bp_write(addr, &data[..aligned]).await;
+        addr += aligned as u32;
+        data = &data[aligned..];
+    }
+
+    if !data.is_empty() {
+        let mut word = bus.bp_read32(addr).await.to_le_bytes();
+        word[..data.len()].copy_from_slice(data);
+        bus.bp_write32(addr, u32::from_le_bytes(word)).await;
+    }
+}
@@ -92,13 +92,53 @@ pub(crate) const IRQ_F1_INTR: u16 = 0x2000;
 pub(crate) const IRQ_F2_INTR: u16 = 0x4000;
 pub(crate) const IRQ_F3_INTR: u16 = 0x8000;
 
+// SDIO core registers, used by the bluetooth shared bus mailbox.
+pub(crate) const SDIO_INT_STATUS: u32 = 0x20;
+pub(crate) const SDIO_INT_HOST_MASK: u32 = 0x24;
+pub(crate) const I_HMB_FC_CHANGE: u32 = 1 << 5;
+
+// Bluetooth shared bus registers and memory layout.
+pub(crate) const BT_CTRL_REG_ADDR: u32 = 0x18000c7c;
+pub(crate) const HOST_CTRL_REG_ADDR: u32 = 0x18000d6c;
+pub(crate) const WLAN_RAM_BASE_REG_ADDR: u32 = 0x18000d68;
+pub(crate) const BT2WLAN_PWRUP_ADDR: u32 = 0x19640894;
+pub(crate) const BT2WLAN_PWRUP_WAKE: u32 = 3;
+pub(crate) const BTFW_MEM_OFFSET: u32 = 0x19000000;
+
+pub(crate) const BTSDIO_REG_DATA_VALID_BITMASK: u32 = 1 << 1;
+pub(crate) const BTSDIO_REG_BT_AWAKE_BITMASK: u32 = 1 << 8;
+pub(crate) const BTSDIO_REG_WAKE_BT_BITMASK: u32 = 1 << 17;
+pub(crate) const BTSDIO_REG_SW_RDY_BITMASK: u32 = 1 << 24;
+pub(crate) const BTSDIO_REG_FW_RDY_BITMASK: u32 = 1 << 24;
+
+pub(crate) const BTSDIO_FWBUF_SIZE: u32 = 0x1000;
+pub(crate) const BTSDIO_OFFSET_HOST_WRITE_BUF: u32 = 0;
+pub(crate) const BTSDIO_OFFSET_HOST_READ_BUF: u32 = BTSDIO_FWBUF_SIZE;
+pub(crate) const BTSDIO_OFFSET_HOST2BT_IN: u32 = 0x2000;
+pub(crate) const BTSDIO_OFFSET_HOST2BT_OUT: u32 = 0x2004;
+pub(crate) const BTSDIO_OFFSET_BT2HOST_IN: u32 = 0x2008;
+pub(crate) const BTSDIO_OFFSET_BT2HOST_OUT: u32 = 0x200C;
+
+// Record types of the bluetooth firmware image.
+pub(crate) const BTFW_HEX_LINE_TYPE_DATA: u8 = 0;
+pub(crate) const BTFW_HEX_LINE_TYPE_END_OF_DATA: u8 = 1;
+pub(crate) const BTFW_HEX_LINE_TYPE_EXTENDED_SEGMENT_ADDRESS: u8 = 2;
+pub(crate) const BTFW_HEX_LINE_TYPE_EXTENDED_ADDRESS: u8 = 4;
+pub(crate) const BTFW_HEX_LINE_TYPE_ABSOLUTE_32BIT_ADDRESS: u8 = 5;
+
 pub(crate) const IOCTL_CMD_UP: u32 = 2;
 pub(crate) const IOCTL_CMD_DOWN: u32 = 3;
+pub(crate) const IOCTL_CMD_GET_RATE: u32 = 12;
+pub(crate) const IOCTL_CMD_GET_BSSID: u32 = 23;
 pub(crate) const IOCTL_CMD_SET_SSID: u32 = 26;
 pub(crate) const IOCTL_CMD_SET_CHANNEL: u32 = 30;
 pub(crate) const IOCTL_CMD_DISASSOC: u32 = 52;
 pub(crate) const IOCTL_CMD_ANTDIV: u32 = 64;
+pub(crate) const IOCTL_CMD_SET_PM: u32 = 86;
+pub(crate) const IOCTL_CMD_GET_RSSI: u32 = 127;
 pub(crate) const IOCTL_CMD_SET_AP: u32 = 118;
+pub(crate) const IOCTL_CMD_GET_ASSOCLIST: u32 = 159;
+pub(crate) const IOCTL_CMD_SCB_DEAUTHENTICATE_FOR_REASON: u32 = 201;
 pub(crate) const IOCTL_CMD_SET_VAR: u32 = 263;
 pub(crate) const IOCTL_CMD_GET_VAR: u32 = 262;
 pub(crate) const IOCTL_CMD_SET_PASSPHRASE: u32 = 268;
@@ -116,6 +156,16 @@ pub(crate) const FIXED_ADDR: bool = false;
 pub(crate) const AES_ENABLED: u32 = 0x0004;
 pub(crate) const WPA2_SECURITY: u32 = 0x00400000;
 
+pub(crate) const WPA2_AUTH_PSK: u32 = 0x0080;
+pub(crate) const WPA3_AUTH_SAE_PSK: u32 = 0x40000;
+
+pub(crate) const AUTH_OPEN: u32 = 0;
+pub(crate) const AUTH_SAE: u32 = 3;
+
+pub(crate) const MFP_NONE: u32 = 0;
+pub(crate) const MFP_CAPABLE: u32 = 1;
+pub(crate) const MFP_REQUIRED: u32 = 2;
+
 pub(crate) const MIN_PSK_LEN: usize = 8;
 pub(crate) const MAX_PSK_LEN: usize = 64;
 
@@ -10,7 +10,7 @@ use crate::events::{Event, EventSubscriber, Events};
 use crate::fmt::Bytes;
 use crate::ioctl::{IoctlState, IoctlType};
 use crate::structs::*;
-use crate::{countries, events, PowerManagementMode};
+use crate::{countries, events, IdlePolicy, PowerManagementConfig, PowerManagementMode, PowerSaveMode};
 
 /// Control errors.
 #[derive(Debug)]
@@ -35,6 +35,46 @@ pub struct Control<'a> {
     ioctl_state: &'a IoctlState,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum JoinAuth {
+    Wpa2,
+    Wpa3,
+    Wpa2Wpa3,
+}
+
+/// Statistics of the link to a peer, see [`Control::link_stats`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkStats {
+    /// Time since the last frame from the peer, in seconds.
+    pub idle_secs: u32,
+    /// Unicast frames sent successfully.
+    pub tx_packets: u32,
+    /// Unicast frames dropped after exhausting all retries.
+    pub tx_failures: u32,
+    /// Unicast frames received.
+    pub rx_packets: u32,
+    /// PHY rate of the last frame sent, in kbit/s.
+    pub tx_rate_kbps: u32,
+    /// PHY rate of the last frame received, in kbit/s.
+    pub rx_rate_kbps: u32,
+    /// Received frames that failed to decrypt.
+    pub rx_decrypt_failures: u32,
+}
+
+/// Station event on an access point.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StationEvent {
+    /// A station associated with the access point.
+    ///
+    /// This is the place to hook captive portal logic, e.g. to start serving DHCP and DNS
+    /// responses that point the new station to a provisioning page.
+    Joined([u8; 6]),
+    /// A station disassociated or was deauthenticated.
+    Left([u8; 6]),
+}
+
 #[derive(Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ScanType {
@@ -199,15 +239,80 @@ impl<'a> Control<'a> {
 
     /// Set power management mode.
     pub async fn set_power_management(&mut self, mode: PowerManagementMode) {
-        // power save mode
-        let mode_num = mode.mode();
-        if mode_num == 2 {
-            self.set_iovar_u32("pm2_sleep_ret", mode.sleep_ret_ms() as u32).await;
-            self.set_iovar_u32("bcn_li_bcn", mode.beacon_period() as u32).await;
-            self.set_iovar_u32("bcn_li_dtim", mode.dtim_period() as u32).await;
-            self.set_iovar_u32("assoc_listen", mode.assoc() as u32).await;
+        self.set_power_management_config(mode.into()).await
+    }
+
+    /// Set power management configuration.
+    ///
+    /// The listen interval only takes effect on the next join.
+    pub async fn set_power_management_config(&mut self, config: PowerManagementConfig) {
+        if config.mode == PowerSaveMode::Pm2 {
+            self.set_iovar_u32("pm2_sleep_ret", config.sleep_ret_ms as u32).await;
+        }
+        if config.mode != PowerSaveMode::Off {
+            self.set_iovar_u32("bcn_li_bcn", config.beacon_period as u32).await;
+            self.set_iovar_u32("bcn_li_dtim", config.dtim_period as u32).await;
+            self.set_iovar_u32("assoc_listen", config.listen_interval as u32).await;
+        }
+        self.ioctl_set_u32(IOCTL_CMD_SET_PM, 0, config.mode as u32).await;
+    }
+
+    /// Set the radio policy while not associated to a network.
+    ///
+    /// Battery powered devices that only connect from time to time should use [`IdlePolicy::RadioOff`].
+    pub async fn set_idle_policy(&mut self, policy: IdlePolicy) {
+        // mpc = minimum power consumption
+        self.set_iovar_u32("mpc", (policy == IdlePolicy::RadioOff) as u32).await;
+    }
+
+    /// Get the RSSI of the access point we are associated with, in dBm.
+    pub async fn rssi(&mut self) -> i32 {
+        let mut buf = [0; 4];
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_RSSI, 0, &mut buf).await;
+        i32::from_le_bytes(buf)
+    }
+
+    /// Get the BSSID of the access point we are associated with.
+    pub async fn bssid(&mut self) -> [u8; 6] {
+        let mut buf = [0; 6];
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_BSSID, 0, &mut buf).await;
+        buf
+    }
+
+    /// Get the current transmit PHY rate, in kbit/s.
+    pub async fn tx_rate(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_RATE, 0, &mut buf).await;
+        // In units of 500 kbit/s.
+        u32::from_le_bytes(buf) * 500
+    }
+
+    /// Get statistics of the link to a peer.
+    ///
+    /// In station mode, `address` is the BSSID of the access point, see [`bssid`](Self::bssid). In
+    /// access point mode, it is the address of an associated station, see [`station_list`](Self::station_list).
+    /// The peer must be associated.
+    pub async fn link_stats(&mut self, address: [u8; 6]) -> LinkStats {
+        let mut buf = [0; 512];
+        let len = self.get_iovar_with_param("sta_info", &address, &mut buf).await;
+        let info = &buf[..len];
+
+        let u32_at = |offset: usize| {
+            info.get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .unwrap_or(0)
+        };
+
+        // Offsets in `sta_info_t`.
+        LinkStats {
+            idle_secs: u32_at(12),
+            tx_packets: u32_at(52),
+            tx_failures: u32_at(56),
+            rx_packets: u32_at(60),
+            tx_rate_kbps: u32_at(68),
+            rx_rate_kbps: u32_at(72),
+            rx_decrypt_failures: u32_at(80),
         }
-        self.ioctl_set_u32(86, 0, mode_num).await;
     }
 
     /// Join an unprotected network with the provided ssid.
@@ -230,27 +335,63 @@ impl<'a> Control<'a> {
 
     /// Join an protected network with the provided ssid and passphrase.
     pub async fn join_wpa2(&mut self, ssid: &str, passphrase: &str) -> Result<(), Error> {
+        self.join_protected(ssid, passphrase, JoinAuth::Wpa2).await
+    }
+
+    /// Join a WPA3-SAE protected network with the provided ssid and passphrase.
+    ///
+    /// Management frame protection is required, as mandated by WPA3.
+    pub async fn join_wpa3(&mut self, ssid: &str, passphrase: &str) -> Result<(), Error> {
+        self.join_protected(ssid, passphrase, JoinAuth::Wpa3).await
+    }
+
+    /// Join a network in WPA2/WPA3 transition mode, which accepts both WPA2-PSK and WPA3-SAE.
+    ///
+    /// The firmware picks SAE if the access point supports it.
+    pub async fn join_wpa2_wpa3(&mut self, ssid: &str, passphrase: &str) -> Result<(), Error> {
+        self.join_protected(ssid, passphrase, JoinAuth::Wpa2Wpa3).await
+    }
+
+    async fn join_protected(&mut self, ssid: &str, passphrase: &str, auth: JoinAuth) -> Result<(), Error> {
         self.set_iovar_u32("ampdu_ba_wsize", 8).await;
 
-        self.ioctl_set_u32(134, 0, 4).await; // wsec = wpa2
+        self.ioctl_set_u32(134, 0, AES_ENABLED).await; // wsec = aes
         self.set_iovar_u32x2("bsscfg:sup_wpa", 0, 1).await;
         self.set_iovar_u32x2("bsscfg:sup_wpa2_eapver", 0, 0xFFFF_FFFF).await;
         self.set_iovar_u32x2("bsscfg:sup_wpa_tmo", 0, 2500).await;
 
         Timer::after_millis(100).await;
 
-        let mut pfi = PassphraseInfo {
-            len: passphrase.len() as _,
-            flags: 1,
-            passphrase: [0; 64],
+        if auth != JoinAuth::Wpa3 {
+            let mut pfi = PassphraseInfo {
+                len: passphrase.len() as _,
+                flags: 1,
+                passphrase: [0; 64],
+            };
+            pfi.passphrase[..passphrase.len()].copy_from_slice(passphrase.as_bytes());
+            self.ioctl(IoctlType::Set, IOCTL_CMD_SET_PASSPHRASE, 0, &mut pfi.to_bytes())
+                .await; // WLC_SET_WSEC_PMK
+        }
+
+        if auth != JoinAuth::Wpa2 {
+            let mut sae = SaePassphraseInfo {
+                len: passphrase.len() as _,
+                passphrase: [0; 128],
+            };
+            sae.passphrase[..passphrase.len()].copy_from_slice(passphrase.as_bytes());
+            self.set_iovar_v::<256>("sae_password", &sae.to_bytes()).await;
+        }
+
+        let (auth_mode, wpa_auth, mfp) = match auth {
+            JoinAuth::Wpa2 => (AUTH_OPEN, WPA2_AUTH_PSK, MFP_NONE),
+            JoinAuth::Wpa3 => (AUTH_SAE, WPA3_AUTH_SAE_PSK, MFP_REQUIRED),
+            JoinAuth::Wpa2Wpa3 => (AUTH_SAE, WPA3_AUTH_SAE_PSK | WPA2_AUTH_PSK, MFP_CAPABLE),
         };
-        pfi.passphrase[..passphrase.len()].copy_from_slice(passphrase.as_bytes());
-        self.ioctl(IoctlType::Set, IOCTL_CMD_SET_PASSPHRASE, 0, &mut pfi.to_bytes())
-            .await; // WLC_SET_WSEC_PMK
 
         self.ioctl_set_u32(20, 0, 1).await; // set_infra = 1
-        self.ioctl_set_u32(22, 0, 0).await; // set_auth = 0 (open)
-        self.ioctl_set_u32(165, 0, 0x80).await; // set_wpa_auth
+        self.ioctl_set_u32(22, 0, auth_mode).await; // set_auth
+        self.set_iovar_u32("mfp", mfp).await;
+        self.ioctl_set_u32(165, 0, wpa_auth).await; // set_wpa_auth
 
         let mut i = SsidInfo {
             len: ssid.len() as _,
@@ -372,6 +513,69 @@ impl<'a> Control<'a> {
         self.set_iovar_u32x2("bss", 0, 1).await; // bss = BSS_UP
     }
 
+    /// Retrieve the list of stations associated with the access point.
+    ///
+    /// Returns the number of stations, which may be more than fit in `result`.
+    pub async fn station_list(&mut self, result: &mut [[u8; 6]]) -> usize {
+        const MAX_STATIONS: usize = 10;
+
+        let mut buf = [0; 4 + MAX_STATIONS * 6];
+        buf[..4].copy_from_slice(&(MAX_STATIONS as u32).to_le_bytes());
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_ASSOCLIST, 0, &mut buf).await;
+
+        let n = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+        let used = &buf[4..][..min(n, MAX_STATIONS) * 6];
+
+        for (addr, output) in zip(used.chunks(6), result.iter_mut()) {
+            output.copy_from_slice(addr)
+        }
+
+        n
+    }
+
+    /// Deauthenticate a station associated with the access point.
+    pub async fn disconnect_station(&mut self, address: [u8; 6]) {
+        let scb = ScbVal {
+            val: 1, // DOT11_RC_UNSPECIFIED
+            ea: address,
+        };
+        self.ioctl(
+            IoctlType::Set,
+            IOCTL_CMD_SCB_DEAUTHENTICATE_FOR_REASON,
+            0,
+            &mut scb.to_bytes(),
+        )
+        .await;
+    }
+
+    /// Wait for a station to join or leave the access point.
+    ///
+    /// # Note
+    /// Device events are currently implemented using a bounded queue.
+    /// Events that happen while this isn't being awaited may be missed, use
+    /// [`station_list`](Self::station_list) to resynchronize.
+    pub async fn wait_for_station_event(&mut self) -> StationEvent {
+        self.events.mask.enable(&[
+            Event::ASSOC_IND,
+            Event::REASSOC_IND,
+            Event::DISASSOC_IND,
+            Event::DEAUTH_IND,
+        ]);
+        let mut subscriber = self.events.queue.subscriber().unwrap();
+
+        let event = loop {
+            let msg = subscriber.next_message_pure().await;
+            match msg.header.event_type {
+                Event::ASSOC_IND | Event::REASSOC_IND => break StationEvent::Joined(msg.header.addr),
+                Event::DISASSOC_IND | Event::DEAUTH_IND => break StationEvent::Left(msg.header.addr),
+                _ => {}
+            }
+        };
+
+        self.events.mask.disable_all();
+        event
+    }
+
     /// Add specified address to the list of hardware addresses the device
     /// listens on. The address must be a Group address (I/G bit set). Up
     /// to 10 addresses are supported by the firmware. Returns the number of
@@ -473,6 +677,18 @@ impl<'a> Control<'a> {
         out_len
     }
 
+    async fn get_iovar_with_param(&mut self, name: &str, param: &[u8], res: &mut [u8]) -> usize {
+        debug!("get {} {:02x}", name, Bytes(param));
+
+        let name_len = name.len() + 1;
+        assert!(name_len + param.len() <= res.len());
+        res[..name.len()].copy_from_slice(name.as_bytes());
+        res[name.len()] = 0;
+        res[name_len..][..param.len()].copy_from_slice(param);
+
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_VAR, 0, res).await
+    }
+
     async fn ioctl_set_u32(&mut self, cmd: u32, iface: u32, val: u32) {
         let mut buf = val.to_le_bytes();
         self.ioctl(IoctlType::Set, cmd, iface, &mut buf).await;
@@ -508,6 +724,39 @@ impl<'a> Control<'a> {
     /// Device events are currently implemented using a bounded queue.
     /// To not miss any events, you should make sure to always await the stream.
     pub async fn scan(&mut self, scan_opts: ScanOptions) -> Scanner<'_> {
+        let subscriber = self.events.queue.subscriber().unwrap();
+        self.start_escan(&scan_opts).await;
+
+        Scanner {
+            subscriber,
+            events: &self.events,
+        }
+    }
+
+    /// Start scanning periodically in the background.
+    ///
+    /// A new scan with `scan_opts` is started `period` after the previous one completed. The
+    /// first scan starts immediately. Results and scan completions are reported as
+    /// [`ScanEvent`]s, which is useful for keeping a network picker up to date or for roaming
+    /// decisions.
+    ///
+    /// # Note
+    /// Device events are currently implemented using a bounded queue.
+    /// To not miss any events, you should make sure to always await the stream.
+    pub async fn background_scan(&mut self, scan_opts: ScanOptions, period: Duration) -> BackgroundScanner<'_, 'a> {
+        let subscriber = self.events.queue.subscriber().unwrap();
+        self.start_escan(&scan_opts).await;
+
+        BackgroundScanner {
+            control: self,
+            subscriber,
+            scan_opts,
+            period,
+            idle: false,
+        }
+    }
+
+    async fn start_escan(&mut self, scan_opts: &ScanOptions) {
         const SCANTYPE_ACTIVE: u8 = 0;
         const SCANTYPE_PASSIVE: u8 = 1;
 
@@ -542,6 +791,7 @@ impl<'a> Control<'a> {
             ssid_len: scan_opts.ssid.as_ref().map(|e| e.as_bytes().len() as u32).unwrap_or(0),
             ssid: scan_opts
                 .ssid
+                .as_ref()
                 .map(|e| {
                     let mut ssid = [0; 32];
                     ssid[..e.as_bytes().len()].copy_from_slice(e.as_bytes());
@@ -560,14 +810,9 @@ impl<'a> Control<'a> {
         };
 
         self.events.mask.enable(&[Event::ESCAN_RESULT]);
-        let subscriber = self.events.queue.subscriber().unwrap();
         self.set_iovar_v::<256>("escan", &scan_params.to_bytes()).await;
-
-        Scanner {
-            subscriber,
-            events: &self.events,
-        }
     }
+
     /// Leave the wifi, with which we are currently associated.
     pub async fn leave(&mut self) {
         self.ioctl(IoctlType::Set, IOCTL_CMD_DISASSOC, 0, &mut []).await;
@@ -591,14 +836,19 @@ pub struct Scanner<'a> {
 impl Scanner<'_> {
     /// Wait for the next found network.
     pub async fn next(&mut self) -> Option<BssInfo> {
+        self.next_result().await.map(|r| r.bss)
+    }
+
+    /// Wait for the next found network, including its security.
+    pub async fn next_result(&mut self) -> Option<ScanResult> {
         let event = self.subscriber.next_message_pure().await;
         if event.header.status != EStatus::PARTIAL {
             self.events.mask.disable_all();
             return None;
         }
 
-        if let events::Payload::BssInfo(bss) = event.payload {
-            Some(bss)
+        if let events::Payload::BssInfo(bss, security) = event.payload {
+            Some(ScanResult { bss, security })
         } else {
             None
         }
@@ -610,3 +860,65 @@ impl Drop for Scanner<'_> {
         self.events.mask.disable_all();
     }
 }
+
+/// Network found by a scan.
+#[derive(Clone, Copy)]
+pub struct ScanResult {
+    /// Information reported by the firmware: SSID, BSSID, channel, RSSI...
+    pub bss: BssInfo,
+    /// Security, parsed from the information elements of the beacon or probe response.
+    pub security: ScanSecurity,
+}
+
+/// Background scan event.
+#[derive(Clone, Copy)]
+pub enum ScanEvent {
+    /// A network was found by the current scan.
+    Found(ScanResult),
+    /// The current scan completed. The next one starts after the scan period.
+    Done,
+}
+
+/// Periodic background WiFi scanner, see [`Control::background_scan`].
+pub struct BackgroundScanner<'c, 'a> {
+    control: &'c mut Control<'a>,
+    subscriber: EventSubscriber<'a>,
+    scan_opts: ScanOptions,
+    period: Duration,
+    idle: bool,
+}
+
+impl BackgroundScanner<'_, '_> {
+    /// Wait for the next scan event.
+    ///
+    /// After a [`ScanEvent::Done`], this waits for the scan period and starts the next scan.
+    pub async fn next(&mut self) -> ScanEvent {
+        if self.idle {
+            Timer::after(self.period).await;
+            self.control.start_escan(&self.scan_opts).await;
+            self.idle = false;
+        }
+
+        loop {
+            let event = self.subscriber.next_message_pure().await;
+            if event.header.event_type != Event::ESCAN_RESULT {
+                continue;
+            }
+
+            if event.header.status != EStatus::PARTIAL {
+                self.idle = true;
+                return ScanEvent::Done;
+            }
+
+            if let events::Payload::BssInfo(bss, security) = event.payload {
+                return ScanEvent::Found(ScanResult { bss, security });
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundScanner<'_, '_> {
+    fn drop(&mut self) {
+        self.control.events.mask.disable_all();
+    }
+}
@@ -6,7 +6,7 @@ use core::cell::RefCell;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::pubsub::{PubSubChannel, Subscriber};
 
-use crate::structs::BssInfo;
+use crate::structs::{BssInfo, ScanSecurity};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum::FromPrimitive)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -309,12 +309,14 @@ impl Events {
 pub struct Status {
     pub event_type: Event,
     pub status: u32,
+    pub reason: u32,
+    pub addr: [u8; 6],
 }
 
 #[derive(Copy, Clone)]
 pub enum Payload {
     None,
-    BssInfo(BssInfo),
+    BssInfo(BssInfo, ScanSecurity),
 }
 
 #[derive(Copy, Clone)]
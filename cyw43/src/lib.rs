@@ -8,6 +8,8 @@
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(feature = "bluetooth")]
+pub mod bluetooth;
 mod bus;
 mod consts;
 mod countries;
@@ -28,9 +30,12 @@ use ioctl::IoctlState;
 
 use crate::bus::Bus;
 pub use crate::bus::SpiBusCyw43;
-pub use crate::control::{AddMulticastAddressError, Control, Error as ControlError, Scanner};
+pub use crate::control::{
+    AddMulticastAddressError, BackgroundScanner, Control, Error as ControlError, LinkStats, ScanEvent, ScanResult,
+    Scanner, StationEvent,
+};
 pub use crate::runner::Runner;
-pub use crate::structs::BssInfo;
+pub use crate::structs::{BssInfo, ScanSecurity};
 
 const MTU: usize = 1514;
 
@@ -109,6 +114,8 @@ pub struct State {
     ioctl_state: IoctlState,
     ch: ch::State<MTU, 4, 4>,
     events: Events,
+    #[cfg(feature = "bluetooth")]
+    bt: bluetooth::BtState,
 }
 
 impl State {
@@ -118,6 +125,8 @@ impl State {
             ioctl_state: IoctlState::new(),
             ch: ch::State::new(),
             events: Events::new(),
+            #[cfg(feature = "bluetooth")]
+            bt: bluetooth::BtState::new(),
         }
     }
 }
@@ -199,15 +208,79 @@ impl PowerManagementMode {
         }
     }
 
-    fn mode(&self) -> u32 {
+    fn mode(&self) -> PowerSaveMode {
         match self {
-            PowerManagementMode::ThroughputThrottling => 1,
-            PowerManagementMode::None => 0,
-            _ => 2,
+            PowerManagementMode::ThroughputThrottling => PowerSaveMode::Pm1,
+            PowerManagementMode::None => PowerSaveMode::Off,
+            _ => PowerSaveMode::Pm2,
         }
     }
 }
 
+/// 802.11 power save mode of the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerSaveMode {
+    /// Power save disabled, the radio is always on.
+    Off = 0,
+    /// PM1: the chip dozes between beacons, and polls the access point with PS-Poll frames for
+    /// each buffered frame. Lowest power, at the cost of a much lower throughput.
+    Pm1 = 1,
+    /// PM2: the chip leaves power save while there is traffic, and goes back to doze after
+    /// being idle for [`PowerManagementConfig::sleep_ret`].
+    Pm2 = 2,
+}
+
+/// Power management configuration.
+///
+/// Fine-grained alternative to [`PowerManagementMode`], see [`Control::set_power_management_config`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerManagementConfig {
+    /// Power save mode.
+    pub mode: PowerSaveMode,
+    /// PM2 only: time the chip stays awake after the last frame, before going back to doze.
+    pub sleep_ret_ms: u16,
+    /// Wake up every `beacon_period` beacons. 0 leaves the firmware default.
+    pub beacon_period: u8,
+    /// Wake up every `dtim_period` DTIM beacons, for buffered broadcast and multicast
+    /// frames. 0 leaves the firmware default.
+    pub dtim_period: u8,
+    /// Listen interval announced to the access point when joining, in beacons. The access point
+    /// buffers frames for at least this long while the chip dozes.
+    pub listen_interval: u8,
+}
+
+impl Default for PowerManagementConfig {
+    fn default() -> Self {
+        PowerManagementMode::default().into()
+    }
+}
+
+impl From<PowerManagementMode> for PowerManagementConfig {
+    fn from(mode: PowerManagementMode) -> Self {
+        Self {
+            mode: mode.mode(),
+            sleep_ret_ms: mode.sleep_ret_ms(),
+            beacon_period: mode.beacon_period(),
+            dtim_period: mode.dtim_period(),
+            listen_interval: mode.assoc(),
+        }
+    }
+}
+
+/// Radio policy while not associated to a network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IdlePolicy {
+    /// Keep the radio powered, so scans and joins start right away.
+    AlwaysOn,
+    /// Power down the radio while not associated and not scanning. It's powered back up on
+    /// demand, which adds some latency to scans and joins.
+    RadioOff,
+}
+
 /// Embassy-net driver.
 pub type NetDriver<'a> = ch::Device<'a, MTU>;
 
@@ -228,12 +301,71 @@ where
     let (ch_runner, device) = ch::new(&mut state.ch, ch::driver::HardwareAddress::Ethernet([0; 6]));
     let state_ch = ch_runner.state_runner();
 
-    let mut runner = Runner::new(ch_runner, Bus::new(pwr, spi), &state.ioctl_state, &state.events);
+    let mut runner = Runner::new(
+        ch_runner,
+        Bus::new(pwr, spi),
+        &state.ioctl_state,
+        &state.events,
+        #[cfg(feature = "bluetooth")]
+        None,
+    );
+
+    runner
+        .init(
+            firmware,
+            #[cfg(feature = "bluetooth")]
+            None,
+        )
+        .await;
+
+    (
+        device,
+        Control::new(state_ch, &state.events, &state.ioctl_state),
+        runner,
+    )
+}
+
+/// Create a new instance of the CYW43 driver, with Bluetooth enabled.
+///
+/// `bt_firmware` is the Bluetooth controller firmware, which is not part of the WiFi
+/// firmware blob. It is uploaded after the WiFi firmware is up.
+///
+/// Returns a handle to the network device, the HCI transport to the Bluetooth controller,
+/// control handle and a runner for driving the low level stack.
+#[cfg(feature = "bluetooth")]
+pub async fn new_with_bluetooth<'a, PWR, SPI>(
+    state: &'a mut State,
+    pwr: PWR,
+    spi: SPI,
+    firmware: &[u8],
+    bt_firmware: &[u8],
+) -> (
+    NetDriver<'a>,
+    bluetooth::BtDriver<'a>,
+    Control<'a>,
+    Runner<'a, PWR, SPI>,
+)
+where
+    PWR: OutputPin,
+    SPI: SpiBusCyw43,
+{
+    let (ch_runner, device) = ch::new(&mut state.ch, ch::driver::HardwareAddress::Ethernet([0; 6]));
+    let state_ch = ch_runner.state_runner();
+
+    let (bt_runner, bt_driver) = bluetooth::new(&mut state.bt);
+    let mut runner = Runner::new(
+        ch_runner,
+        Bus::new(pwr, spi),
+        &state.ioctl_state,
+        &state.events,
+        Some(bt_runner),
+    );
 
-    runner.init(firmware).await;
+    runner.init(firmware, Some(bt_firmware)).await;
 
     (
         device,
+        bt_driver,
         Control::new(state_ch, &state.events, &state.ioctl_state),
         runner,
     )
@@ -1,9 +1,11 @@
-use embassy_futures::select::{select3, Either3};
+use embassy_futures::select::{select4, Either4};
 use embassy_net_driver_channel as ch;
 use embassy_sync::pubsub::PubSubBehavior;
 use embassy_time::{block_for, Duration, Timer};
 use embedded_hal_1::digital::OutputPin;
 
+#[cfg(feature = "bluetooth")]
+use crate::bluetooth::BtRunner;
 use crate::bus::Bus;
 pub use crate::bus::SpiBusCyw43;
 use crate::consts::*;
@@ -48,6 +50,9 @@ pub struct Runner<'a, PWR, SPI> {
 
     #[cfg(feature = "firmware-logs")]
     log: LogState,
+
+    #[cfg(feature = "bluetooth")]
+    bt: Option<BtRunner<'a>>,
 }
 
 impl<'a, PWR, SPI> Runner<'a, PWR, SPI>
@@ -60,6 +65,7 @@ where
         bus: Bus<PWR, SPI>,
         ioctl_state: &'a IoctlState,
         events: &'a Events,
+        #[cfg(feature = "bluetooth")] bt: Option<BtRunner<'a>>,
     ) -> Self {
         Self {
             ch,
@@ -71,10 +77,12 @@ where
             events,
             #[cfg(feature = "firmware-logs")]
             log: LogState::default(),
+            #[cfg(feature = "bluetooth")]
+            bt,
         }
     }
 
-    pub(crate) async fn init(&mut self, firmware: &[u8]) {
+    pub(crate) async fn init(&mut self, firmware: &[u8], #[cfg(feature = "bluetooth")] bt_firmware: Option<&[u8]>) {
         self.bus.init().await;
 
         // Init ALP (Active Low Power) clock
@@ -122,9 +130,20 @@ where
         // "Set up the interrupt mask and enable interrupts"
         // self.bus.bp_write32(CHIP.sdiod_core_base_address + 0x24, 0xF0).await;
 
-        self.bus
-            .write16(FUNC_BUS, REG_BUS_INTERRUPT_ENABLE, IRQ_F2_PACKET_AVAILABLE)
-            .await;
+        #[allow(unused_mut)]
+        let mut irq_mask = IRQ_F2_PACKET_AVAILABLE;
+
+        // The bluetooth controller signals new data through the SDIO core mailbox, which
+        // raises an F1 interrupt.
+        #[cfg(feature = "bluetooth")]
+        if bt_firmware.is_some() {
+            self.bus
+                .bp_write32(CHIP.sdiod_core_base_address + SDIO_INT_HOST_MASK, I_HMB_FC_CHANGE)
+                .await;
+            irq_mask |= IRQ_F1_INTR;
+        }
+
+        self.bus.write16(FUNC_BUS, REG_BUS_INTERRUPT_ENABLE, irq_mask).await;
 
         // "Lower F2 Watermark to avoid DMA Hang in F2 when SD Clock is stopped."
         // Sounds scary...
@@ -167,6 +186,11 @@ where
         self.log_init().await;
 
         debug!("wifi init done");
+
+        #[cfg(feature = "bluetooth")]
+        if let (Some(bt), Some(bt_firmware)) = (&mut self.bt, bt_firmware) {
+            bt.init(&mut self.bus, bt_firmware).await;
+        }
     }
 
     #[cfg(feature = "firmware-logs")]
@@ -234,9 +258,13 @@ where
                 let ioctl = self.ioctl_state.wait_pending();
                 let tx = self.ch.tx_buf();
                 let ev = self.bus.wait_for_event();
+                #[cfg(feature = "bluetooth")]
+                let bt = bt_tx_ready(&mut self.bt);
+                #[cfg(not(feature = "bluetooth"))]
+                let bt = core::future::pending::<()>();
 
-                match select3(ioctl, tx, ev).await {
-                    Either3::First(PendingIoctl {
+                match select4(ioctl, tx, ev, bt).await {
+                    Either4::First(PendingIoctl {
                         buf: iobuf,
                         kind,
                         cmd,
@@ -245,7 +273,7 @@ where
                         self.send_ioctl(kind, cmd, iface, unsafe { &*iobuf }, &mut buf).await;
                         self.check_status(&mut buf).await;
                     }
-                    Either3::Second(packet) => {
+                    Either4::Second(packet) => {
                         trace!("tx pkt {:02x}", Bytes(&packet[..packet.len().min(48)]));
 
                         let buf8 = slice8_mut(&mut buf);
@@ -299,9 +327,16 @@ where
                         self.ch.tx_done();
                         self.check_status(&mut buf).await;
                     }
-                    Either3::Third(()) => {
+                    Either4::Third(()) => {
                         self.handle_irq(&mut buf).await;
                     }
+                    Either4::Fourth(()) =>
+                    {
+                        #[cfg(feature = "bluetooth")]
+                        if let Some(bt) = &mut self.bt {
+                            bt.hci_write(&mut self.bus).await;
+                        }
+                    }
                 }
             } else {
                 warn!("TX stalled");
@@ -326,6 +361,17 @@ where
             warn!("IRQ DATA_UNAVAILABLE, clearing...");
             self.bus.write16(FUNC_BUS, REG_BUS_INTERRUPT, 1).await;
         }
+
+        #[cfg(feature = "bluetooth")]
+        if let Some(bt) = &mut self.bt {
+            let status = self.bus.bp_read32(CHIP.sdiod_core_base_address + SDIO_INT_STATUS).await;
+            if status & I_HMB_FC_CHANGE != 0 {
+                self.bus
+                    .bp_write32(CHIP.sdiod_core_base_address + SDIO_INT_STATUS, I_HMB_FC_CHANGE)
+                    .await;
+                bt.handle_irq(&mut self.bus).await;
+            }
+        }
     }
 
     /// Handle F2 events while status register is set
@@ -425,10 +471,17 @@ where
                             let Some((_, bss_info)) = ScanResults::parse(evt_data) else {
                                 return;
                             };
-                            let Some(bss_info) = BssInfo::parse(bss_info) else {
+                            let Some(bss) = BssInfo::parse(bss_info) else {
                                 return;
                             };
-                            events::Payload::BssInfo(*bss_info)
+                            let bss = *bss;
+
+                            let ie_start = bss.ie_offset as usize;
+                            let ie_end = ie_start + bss.ie_length as usize;
+                            let ies = bss_info.get(ie_start..ie_end).unwrap_or(&[]);
+                            let security = ScanSecurity::parse(bss.capability, ies);
+
+                            events::Payload::BssInfo(bss, security)
                         }
                         Event::ESCAN_RESULT => events::Payload::None,
                         _ => events::Payload::None,
@@ -442,6 +495,8 @@ where
                         Status {
                             event_type: evt_type,
                             status,
+                            reason: event_packet.msg.reason,
+                            addr: event_packet.msg.addr,
                         },
                         event_payload,
                     ));
@@ -583,3 +638,11 @@ where
         true
     }
 }
+
+#[cfg(feature = "bluetooth")]
+async fn bt_tx_ready(bt: &mut Option<BtRunner<'_>>) {
+    match bt {
+        Some(bt) => bt.tx_ready().await,
+        None => core::future::pending().await,
+    }
+}
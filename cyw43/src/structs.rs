@@ -394,6 +394,24 @@ pub struct PassphraseInfo {
 }
 impl_bytes!(PassphraseInfo);
 
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct SaePassphraseInfo {
+    pub len: u16,
+    pub passphrase: [u8; 128],
+}
+impl_bytes!(SaePassphraseInfo);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct ScbVal {
+    pub val: u32,
+    pub ea: [u8; 6],
+}
+impl_bytes!(ScbVal);
+
 #[derive(Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
@@ -543,4 +561,96 @@ impl BssInfo {
             packet[..BssInfo::SIZE].as_mut().try_into().unwrap(),
         ))
     }
+
+    /// SSID of the network, as raw bytes.
+    pub fn ssid(&self) -> &[u8] {
+        &self.ssid[..(self.ssid_len as usize).min(32)]
+    }
+
+    /// Primary channel number of the network.
+    pub fn channel(&self) -> u8 {
+        (self.chanspec & 0xff) as u8
+    }
+}
+
+/// Security of a network found by a scan.
+///
+/// Several fields can be set for networks that accept more than one mode, like WPA2/WPA3
+/// transition networks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct ScanSecurity {
+    /// WEP, a network that sets the privacy bit without any WPA information element.
+    pub wep: bool,
+    /// WPA (version 1).
+    pub wpa: bool,
+    /// WPA2 with a pre-shared key.
+    pub wpa2: bool,
+    /// WPA3 with SAE.
+    pub wpa3: bool,
+    /// WPA/WPA2/WPA3 with 802.1X authentication.
+    pub enterprise: bool,
+}
+
+impl ScanSecurity {
+    const CAPABILITY_PRIVACY: u16 = 1 << 4;
+
+    const IE_RSN: u8 = 48;
+    const IE_VENDOR: u8 = 221;
+    const WPA_OUI_TYPE: [u8; 4] = [0x00, 0x50, 0xf2, 0x01];
+
+    const AKM_8021X: [u8; 4] = [0x00, 0x0f, 0xac, 0x01];
+    const AKM_PSK: [u8; 4] = [0x00, 0x0f, 0xac, 0x02];
+    const AKM_SAE: [u8; 4] = [0x00, 0x0f, 0xac, 0x08];
+
+    /// Parse the security from the capability field and the information elements of a scan result.
+    pub(crate) fn parse(capability: u16, mut ies: &[u8]) -> Self {
+        let mut this = Self::default();
+
+        while ies.len() >= 2 {
+            let (id, len) = (ies[0], ies[1] as usize);
+            let Some(data) = ies.get(2..2 + len) else {
+                break;
+            };
+            ies = &ies[2 + len..];
+
+            match id {
+                Self::IE_RSN => this.parse_rsn(data),
+                Self::IE_VENDOR if data.starts_with(&Self::WPA_OUI_TYPE) => this.wpa = true,
+                _ => {}
+            }
+        }
+
+        if capability & Self::CAPABILITY_PRIVACY != 0 && this == Self::default() {
+            this.wep = true;
+        }
+
+        this
+    }
+
+    // RSN element: version (2), group cipher (4), pairwise count (2) + suites (4 each),
+    // AKM count (2) + suites (4 each), ...
+    fn parse_rsn(&mut self, rsn: &[u8]) {
+        let Some(pairwise_count) = rsn.get(6..8) else {
+            return;
+        };
+        let akm_start = 8 + 4 * u16::from_le_bytes([pairwise_count[0], pairwise_count[1]]) as usize;
+        let Some(akm_count) = rsn.get(akm_start..akm_start + 2) else {
+            return;
+        };
+        let akm_count = u16::from_le_bytes([akm_count[0], akm_count[1]]) as usize;
+        let Some(akms) = rsn.get(akm_start + 2..akm_start + 2 + 4 * akm_count) else {
+            return;
+        };
+
+        for akm in akms.chunks_exact(4) {
+            match akm.try_into().unwrap() {
+                Self::AKM_8021X => self.enterprise = true,
+                Self::AKM_PSK => self.wpa2 = true,
+                Self::AKM_SAE => self.wpa3 = true,
+                _ => {}
+            }
+        }
+    }
 }
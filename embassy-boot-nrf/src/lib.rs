@@ -5,7 +5,7 @@ mod fmt;
 
 pub use embassy_boot::{
     AlignedBuffer, BlockingFirmwareState, BlockingFirmwareUpdater, BootError, BootLoaderConfig, FirmwareState,
-    FirmwareUpdater, FirmwareUpdaterConfig,
+    FirmwareUpdater, FirmwareUpdaterConfig, KeyStore, Manifest, Verifier, VerifyError,
 };
 use embassy_nrf::nvmc::PAGE_SIZE;
 use embassy_nrf::peripherals::WDT;
@@ -33,6 +33,21 @@ impl<const BUFFER_SIZE: usize> BootLoader<BUFFER_SIZE> {
         Ok(Self)
     }
 
+    /// Inspect the bootloader state and perform actions required before booting, such as swapping firmware.
+    ///
+    /// An update is only swapped in if it is signed with one of the `keys`, otherwise it is cancelled
+    /// and the error is returned. See [`embassy_boot::BootLoader::prepare_boot_verified`].
+    pub fn try_prepare_verified<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash, V: Verifier, K: KeyStore + ?Sized>(
+        config: BootLoaderConfig<ACTIVE, DFU, STATE>,
+        verifier: &mut V,
+        keys: &K,
+    ) -> Result<Self, BootError> {
+        let mut aligned_buf = AlignedBuffer([0; BUFFER_SIZE]);
+        let mut boot = embassy_boot::BootLoader::new(config);
+        let _state = boot.prepare_boot_verified(aligned_buf.as_mut(), verifier, keys)?;
+        Ok(Self)
+    }
+
     /// Boots the application without softdevice mechanisms.
     ///
     /// # Safety
@@ -0,0 +1,166 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use super::{
+    parse_record, word_offset, word_size, AbFirmwareUpdaterConfig, Record, Slot, SlotState, CONFIRMED_MAGIC,
+    CONFIRMED_WORD, VALID_MAGIC, VALID_WORD, VERSION_WORD,
+};
+use crate::{FirmwareUpdaterError, STATE_ERASE_VALUE};
+
+/// Blocking FirmwareUpdater for the A/B update strategy, see the [module documentation](crate::ab).
+///
+/// Updates are written to the slot the application is not running from.
+pub struct BlockingAbFirmwareUpdater<'d, SLOT: NorFlash, STATE: NorFlash> {
+    slot_a: SLOT,
+    slot_b: SLOT,
+    state: STATE,
+    running: Slot,
+    aligned: &'d mut [u8],
+    update_started: bool,
+    last_erased_sector_index: Option<usize>,
+}
+
+impl<'d, SLOT: NorFlash, STATE: NorFlash> BlockingAbFirmwareUpdater<'d, SLOT, STATE> {
+    const WORD_SIZE: usize = word_size(STATE::WRITE_SIZE, STATE::READ_SIZE);
+
+    /// Create a firmware updater for an application running from the `running` slot.
+    ///
+    /// The `aligned` buffer must be `max(4, STATE::WRITE_SIZE)` bytes long, and follow the
+    /// alignment rules for the state partition.
+    pub fn new(config: AbFirmwareUpdaterConfig<SLOT, STATE>, running: Slot, aligned: &'d mut [u8]) -> Self {
+        assert_eq!(aligned.len(), Self::WORD_SIZE);
+        assert!(config.state.capacity() >= 2 * STATE::ERASE_SIZE);
+        Self {
+            slot_a: config.slot_a,
+            slot_b: config.slot_b,
+            state: config.state,
+            running,
+            aligned,
+            update_started: false,
+            last_erased_sector_index: None,
+        }
+    }
+
+    /// The slot the application is running from.
+    pub fn running_slot(&self) -> Slot {
+        self.running
+    }
+
+    /// Obtain the state of a slot.
+    ///
+    /// If the running slot is on [`SlotState::Trial`], the application should do its self-tests and
+    /// call [`mark_booted`](Self::mark_booted), or it is rolled back on the next boot.
+    pub fn slot_state(&mut self, slot: Slot) -> Result<SlotState, FirmwareUpdaterError> {
+        Ok(self.read_record(slot)?.state())
+    }
+
+    /// Obtain the version of the image in a slot.
+    pub fn slot_version(&mut self, slot: Slot) -> Result<u32, FirmwareUpdaterError> {
+        Ok(self.read_record(slot)?.version)
+    }
+
+    /// Writes firmware data to the slot the application is not running from.
+    ///
+    /// The first write invalidates the image in that slot. The running image must be confirmed, so
+    /// that it can't be rolled back to the slot being written.
+    pub fn write_firmware(&mut self, offset: usize, data: &[u8]) -> Result<(), FirmwareUpdaterError> {
+        self.start_update()?;
+
+        let slot = match self.running.other() {
+            Slot::A => &mut self.slot_a,
+            Slot::B => &mut self.slot_b,
+        };
+
+        let mut remaining_data = data;
+        let mut offset = offset;
+        while !remaining_data.is_empty() {
+            let current_sector = offset / SLOT::ERASE_SIZE;
+            let sector_start = current_sector * SLOT::ERASE_SIZE;
+            let sector_end = sector_start + SLOT::ERASE_SIZE;
+            if self.last_erased_sector_index != Some(current_sector) {
+                slot.erase(sector_start as u32, sector_end as u32)?;
+                self.last_erased_sector_index = Some(current_sector);
+            }
+
+            let write_size = core::cmp::min(remaining_data.len(), sector_end - offset);
+            let (data_chunk, rest) = remaining_data.split_at(write_size);
+            slot.write(offset as u32, data_chunk)?;
+
+            remaining_data = rest;
+            offset += write_size;
+        }
+
+        Ok(())
+    }
+
+    /// Mark the written image valid, to be tried on next boot.
+    ///
+    /// The version must be higher than the version of the running image.
+    pub fn mark_updated(&mut self, version: u32) -> Result<(), FirmwareUpdaterError> {
+        self.start_update()?;
+        if version <= self.read_record(self.running)?.version {
+            return Err(FirmwareUpdaterError::BadState);
+        }
+
+        let target = self.running.other();
+        self.aligned.fill(STATE_ERASE_VALUE);
+        self.aligned[..4].copy_from_slice(&version.to_le_bytes());
+        self.write_word(target, VERSION_WORD)?;
+        self.aligned.fill(VALID_MAGIC);
+        self.write_word(target, VALID_WORD)?;
+
+        self.update_started = false;
+        self.last_erased_sector_index = None;
+        Ok(())
+    }
+
+    /// Mark the running image as working, and stop rollback on reset.
+    ///
+    /// An image programmed without the updater is marked valid with version 0.
+    pub fn mark_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
+        let record = self.read_record(self.running)?;
+        if !record.valid {
+            self.aligned.fill(VALID_MAGIC);
+            self.write_word(self.running, VALID_WORD)?;
+        }
+        if !record.confirmed {
+            self.aligned.fill(CONFIRMED_MAGIC);
+            self.write_word(self.running, CONFIRMED_WORD)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the running image is confirmed, and invalidate the other slot before the first write.
+    fn start_update(&mut self) -> Result<(), FirmwareUpdaterError> {
+        if self.update_started {
+            return Ok(());
+        }
+        if self.read_record(self.running)?.state() != SlotState::Confirmed {
+            return Err(FirmwareUpdaterError::BadState);
+        }
+
+        let offset = word_offset(self.running.other(), 0, STATE::ERASE_SIZE, Self::WORD_SIZE);
+        self.state.erase(offset, offset + STATE::ERASE_SIZE as u32)?;
+        self.update_started = true;
+        Ok(())
+    }
+
+    fn read_record(&mut self, slot: Slot) -> Result<Record, FirmwareUpdaterError> {
+        let mut words = [[0; 4]; super::WORD_COUNT];
+        for (index, word) in words.iter_mut().enumerate() {
+            self.state.read(
+                word_offset(slot, index, STATE::ERASE_SIZE, Self::WORD_SIZE),
+                self.aligned,
+            )?;
+            word.copy_from_slice(&self.aligned[..4]);
+        }
+        parse_record(|index| Ok(words[index]))
+    }
+
+    fn write_word(&mut self, slot: Slot, index: usize) -> Result<(), FirmwareUpdaterError> {
+        self.state.write(
+            word_offset(slot, index, STATE::ERASE_SIZE, Self::WORD_SIZE),
+            self.aligned,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use super::{
+    parse_record, word_offset, word_size, Record, Slot, SlotState, INVALID_MAGIC, INVALID_WORD, TRIED_MAGIC, TRIED_WORD,
+};
+use crate::BootError;
+
+/// Bootloader for the A/B update strategy, see the [module documentation](crate::ab).
+///
+/// The bootloader only selects the slot to boot, loading it is left to the platform specific
+/// bootloader, which jumps to the start address of the slot.
+pub struct AbBootLoader<STATE: NorFlash> {
+    state: STATE,
+}
+
+impl<STATE: NorFlash> AbBootLoader<STATE> {
+    const WORD_SIZE: usize = word_size(STATE::WRITE_SIZE, STATE::READ_SIZE);
+
+    /// Create a new instance of a bootloader with the state partition.
+    ///
+    /// The state partition must be at least two erase sectors long.
+    pub fn new(state: STATE) -> Self {
+        assert!(state.capacity() >= 2 * STATE::ERASE_SIZE);
+        Self { state }
+    }
+
+    /// Select the slot to boot.
+    ///
+    /// A slot that was tried on the previous boot, but not confirmed by the application, is
+    /// invalidated. The slot with the highest version among the pending and confirmed ones is then
+    /// selected, and marked tried if it is pending. If no slot can be booted, slot A is selected.
+    ///
+    /// The provided aligned_buf argument must satisfy any alignment requirements given by the
+    /// state partition, and be at least `max(4, STATE::WRITE_SIZE)` bytes long.
+    pub fn prepare_boot(&mut self, aligned_buf: &mut [u8]) -> Result<Slot, BootError> {
+        let mut records = [
+            self.read_record(Slot::A, aligned_buf)?,
+            self.read_record(Slot::B, aligned_buf)?,
+        ];
+
+        for (slot, record) in [Slot::A, Slot::B].into_iter().zip(records.iter_mut()) {
+            if record.state() == SlotState::Trial {
+                warn!("Slot {:?} was not confirmed, rolling back", slot);
+                self.write_marker(slot, INVALID_WORD, INVALID_MAGIC, aligned_buf)?;
+                record.invalid = true;
+            }
+        }
+
+        let [a, b] = &records;
+        let slot = match (a.is_bootable(), b.is_bootable()) {
+            (true, true) if (b.version, b.confirmed) > (a.version, a.confirmed) => Slot::B,
+            (false, true) => Slot::B,
+            _ => Slot::A,
+        };
+
+        if records[slot.index()].state() == SlotState::Pending {
+            trace!("Trying slot {:?}", slot);
+            self.write_marker(slot, TRIED_WORD, TRIED_MAGIC, aligned_buf)?;
+        }
+        Ok(slot)
+    }
+
+    fn read_record(&mut self, slot: Slot, aligned_buf: &mut [u8]) -> Result<Record, BootError> {
+        let word = &mut aligned_buf[..Self::WORD_SIZE];
+        parse_record(|index| {
+            self.state
+                .read(word_offset(slot, index, STATE::ERASE_SIZE, Self::WORD_SIZE), word)?;
+            Ok([word[0], word[1], word[2], word[3]])
+        })
+    }
+
+    fn write_marker(&mut self, slot: Slot, index: usize, magic: u8, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+        let word = &mut aligned_buf[..Self::WORD_SIZE];
+        word.fill(magic);
+        self.state
+            .write(word_offset(slot, index, STATE::ERASE_SIZE, Self::WORD_SIZE), word)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+//! A/B update strategy, where the bootloader boots one of two slots without swapping them.
+//!
+//! Each slot holds a complete image, linked to execute in place from that slot. The application
+//! writes updates to the slot it is not running from, and the bootloader boots the slot with the
+//! highest version, trying new images once before they are confirmed. This avoids the swap, which
+//! takes long and erases each page twice on large images, at the cost of building the application
+//! once for each slot.
+//!
+//! The state partition is two erase sectors, one for each slot, so that the state of a slot can be
+//! erased without touching the state of the other. Each sector holds the following words, of
+//! `max(4, WRITE_SIZE)` bytes:
+//!
+//! | Word | Description                                                             |
+//! |------|-------------------------------------------------------------------------|
+//! | 0    | Version of the image, in little endian                                  |
+//! | 1    | Valid marker, written once the image and its version are written         |
+//! | 2    | Tried marker, written by the bootloader when it first boots the image    |
+//! | 3    | Confirmed marker, written by the application when the image works        |
+//! | 4    | Invalid marker, written by the bootloader when the image failed its try  |
+mod asynch;
+mod blocking;
+mod boot_loader;
+
+pub use asynch::AbFirmwareUpdater;
+pub use blocking::BlockingAbFirmwareUpdater;
+pub use boot_loader::AbBootLoader;
+
+use crate::STATE_ERASE_VALUE;
+
+const VALID_MAGIC: u8 = 0xA0;
+const TRIED_MAGIC: u8 = 0xA1;
+const CONFIRMED_MAGIC: u8 = 0xA2;
+const INVALID_MAGIC: u8 = 0xA3;
+
+const VERSION_WORD: usize = 0;
+const VALID_WORD: usize = 1;
+const TRIED_WORD: usize = 2;
+const CONFIRMED_WORD: usize = 3;
+const INVALID_WORD: usize = 4;
+const WORD_COUNT: usize = 5;
+
+/// A slot of the A/B update strategy.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slot {
+    /// Slot A, booted if no slot holds a valid image, as on a freshly programmed device.
+    A,
+    /// Slot B.
+    B,
+}
+
+impl Slot {
+    /// The other slot.
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+/// The state of the image in a slot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlotState {
+    /// The slot holds no valid image, or one being written.
+    Empty,
+    /// The image is waiting to be tried on the next boot.
+    Pending,
+    /// The image is being tried, it is invalidated on the next boot unless the application
+    /// confirms it.
+    Trial,
+    /// The image was confirmed by the application.
+    Confirmed,
+    /// The image failed its try.
+    Invalid,
+}
+
+/// Configuration of the flash used by the A/B firmware updater.
+pub struct AbFirmwareUpdaterConfig<SLOT, STATE> {
+    /// The partition of slot A.
+    pub slot_a: SLOT,
+    /// The partition of slot B.
+    pub slot_b: SLOT,
+    /// The state partition, two erase sectors long.
+    pub state: STATE,
+}
+
+/// The state words of a slot.
+struct Record {
+    version: u32,
+    valid: bool,
+    tried: bool,
+    confirmed: bool,
+    invalid: bool,
+}
+
+impl Record {
+    fn state(&self) -> SlotState {
+        if self.invalid {
+            SlotState::Invalid
+        } else if !self.valid {
+            SlotState::Empty
+        } else if self.confirmed {
+            SlotState::Confirmed
+        } else if self.tried {
+            SlotState::Trial
+        } else {
+            SlotState::Pending
+        }
+    }
+
+    /// Whether the bootloader may boot the image.
+    fn is_bootable(&self) -> bool {
+        matches!(self.state(), SlotState::Pending | SlotState::Confirmed)
+    }
+}
+
+/// Size of the state words for a flash with the given write and read sizes.
+const fn word_size(write_size: usize, read_size: usize) -> usize {
+    let size = if write_size > read_size { write_size } else { read_size };
+    if size > 4 {
+        size
+    } else {
+        4
+    }
+}
+
+/// Offset of a state word in the state partition.
+fn word_offset(slot: Slot, word: usize, erase_size: usize, word_size: usize) -> u32 {
+    assert!(WORD_COUNT * word_size <= erase_size);
+    (slot.index() * erase_size + word * word_size) as u32
+}
+
+/// Parse the state words of a slot, read with `read_word`.
+fn parse_record<E>(mut read_word: impl FnMut(usize) -> Result<[u8; 4], E>) -> Result<Record, E> {
+    let is_written = |word: [u8; 4]| word.iter().any(|&b| b != STATE_ERASE_VALUE);
+    let has_magic = |word: [u8; 4], magic: u8| word.iter().all(|&b| b == magic);
+
+    let version = read_word(VERSION_WORD)?;
+    Ok(Record {
+        // An erased version is version 0, for images programmed without the updater.
+        version: if is_written(version) {
+            u32::from_le_bytes(version)
+        } else {
+            0
+        },
+        valid: has_magic(read_word(VALID_WORD)?, VALID_MAGIC),
+        // A partially written marker is considered written, to never boot an image twice on trial.
+        tried: is_written(read_word(TRIED_WORD)?),
+        confirmed: has_magic(read_word(CONFIRMED_WORD)?, CONFIRMED_MAGIC),
+        invalid: is_written(read_word(INVALID_WORD)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::ReadNorFlash;
+
+    use super::*;
+    use crate::mem_flash::MemFlash;
+    use crate::test_flash::BlockingTestFlash;
+    use crate::{BootLoaderConfig, FirmwareUpdaterError};
+
+    const IMAGE_SIZE: usize = 8192;
+
+    fn flash(
+    ) -> BlockingTestFlash<MemFlash<IMAGE_SIZE, 4096, 4>, MemFlash<IMAGE_SIZE, 4096, 4>, MemFlash<8192, 4096, 4>> {
+        BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::default(),
+            dfu: MemFlash::default(),
+            state: MemFlash::default(),
+        })
+    }
+
+    #[test]
+    fn boots_slot_a_when_empty() {
+        let flash = flash();
+        let mut bootloader = AbBootLoader::new(flash.state());
+        let mut aligned = [0; 4];
+        assert_eq!(Slot::A, bootloader.prepare_boot(&mut aligned).unwrap());
+    }
+
+    #[test]
+    fn update_is_tried_then_confirmed() {
+        // Slot A is the active partition and slot B the DFU partition of the test flash.
+        let flash = flash();
+        let mut aligned = [0; 4];
+        let mut bootloader = AbBootLoader::new(flash.state());
+
+        let mut updater = BlockingAbFirmwareUpdater::new(
+            AbFirmwareUpdaterConfig {
+                slot_a: flash.active(),
+                slot_b: flash.dfu(),
+                state: flash.state(),
+            },
+            Slot::A,
+            &mut aligned,
+        );
+        updater.mark_booted().unwrap();
+        updater.write_firmware(0, &[0xAA; IMAGE_SIZE]).unwrap();
+        updater.mark_updated(1).unwrap();
+        assert_eq!(SlotState::Pending, updater.slot_state(Slot::B).unwrap());
+
+        let mut read_buf = [0; IMAGE_SIZE];
+        flash.dfu().read(0, &mut read_buf).unwrap();
+        assert_eq!([0xAA; IMAGE_SIZE], read_buf);
+
+        let mut buf = [0; 4];
+        assert_eq!(Slot::B, bootloader.prepare_boot(&mut buf).unwrap());
+
+        let mut aligned = [0; 4];
+        let mut updater = BlockingAbFirmwareUpdater::new(
+            AbFirmwareUpdaterConfig {
+                slot_a: flash.active(),
+                slot_b: flash.dfu(),
+                state: flash.state(),
+            },
+            Slot::B,
+            &mut aligned,
+        );
+        assert_eq!(SlotState::Trial, updater.slot_state(Slot::B).unwrap());
+        // Updates can't be written before the running image is confirmed.
+        assert!(matches!(
+            updater.write_firmware(0, &[0x55; 4]),
+            Err(FirmwareUpdaterError::BadState)
+        ));
+        updater.mark_booted().unwrap();
+        assert_eq!(SlotState::Confirmed, updater.slot_state(Slot::B).unwrap());
+
+        assert_eq!(Slot::B, bootloader.prepare_boot(&mut buf).unwrap());
+        assert_eq!(Slot::B, bootloader.prepare_boot(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn failed_update_is_rolled_back() {
+        let flash = flash();
+        let mut aligned = [0; 4];
+        let mut bootloader = AbBootLoader::new(flash.state());
+
+        let mut updater = BlockingAbFirmwareUpdater::new(
+            AbFirmwareUpdaterConfig {
+                slot_a: flash.active(),
+                slot_b: flash.dfu(),
+                state: flash.state(),
+            },
+            Slot::A,
+            &mut aligned,
+        );
+        updater.mark_booted().unwrap();
+        updater.write_firmware(0, &[0xAA; 4096]).unwrap();
+        // Older versions than the running image are refused.
+        assert!(matches!(updater.mark_updated(0), Err(FirmwareUpdaterError::BadState)));
+        updater.mark_updated(2).unwrap();
+
+        let mut buf = [0; 4];
+        assert_eq!(Slot::B, bootloader.prepare_boot(&mut buf).unwrap());
+        // The update is not confirmed before the next boot.
+        assert_eq!(Slot::A, bootloader.prepare_boot(&mut buf).unwrap());
+        assert_eq!(SlotState::Invalid, updater.slot_state(Slot::B).unwrap());
+        assert_eq!(SlotState::Confirmed, updater.slot_state(Slot::A).unwrap());
+        assert_eq!(Slot::A, bootloader.prepare_boot(&mut buf).unwrap());
+    }
+}
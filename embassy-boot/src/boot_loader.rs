@@ -5,6 +5,9 @@ use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
 
+use crate::partition_table::{PartitionKind, PartitionTable, PartitionTableError};
+use crate::report::{BlockingBootReport, BootEvent, RollbackReason};
+use crate::verify::{self, KeyStore, Manifest, Verifier, VerifyError};
 use crate::{State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
 
 /// Errors returned by bootloader
@@ -14,6 +17,8 @@ pub enum BootError {
     Flash(NorFlashErrorKind),
     /// Invalid bootloader magic
     BadMagic,
+    /// The update failed verification and was cancelled.
+    Verify(VerifyError),
 }
 
 #[cfg(feature = "defmt")]
@@ -22,6 +27,7 @@ impl defmt::Format for BootError {
         match self {
             BootError::Flash(_) => defmt::write!(fmt, "BootError::Flash(_)"),
             BootError::BadMagic => defmt::write!(fmt, "BootError::BadMagic"),
+            BootError::Verify(e) => defmt::write!(fmt, "BootError::Verify({})", e),
         }
     }
 }
@@ -127,6 +133,41 @@ impl<'a, ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash>
 
         Self { active, dfu, state }
     }
+
+    /// Constructs a `BootLoaderConfig` instance from flash memory and a partition table read at runtime.
+    ///
+    /// This is the runtime counterpart of [`BootLoaderConfig::from_linkerfile_blocking`], so that one
+    /// bootloader binary can serve several memory layouts. The table must have an active, a DFU and a
+    /// state partition, see the [`partition_table`](crate::partition_table) module.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let layout = Flash::new_blocking(p.FLASH).into_blocking_regions();
+    /// let flash = Mutex::new(RefCell::new(layout.bank1_region));
+    ///
+    /// let mut aligned = AlignedBuffer([0; 256]);
+    /// let table = flash.lock(|f| PartitionTable::read_blocking(&mut *f.borrow_mut(), TABLE_OFFSET, &mut aligned.0))?;
+    /// let config = BootLoaderConfig::from_partition_table_blocking(&table, &flash, &flash, &flash)?;
+    /// ```
+    pub fn from_partition_table_blocking(
+        table: &PartitionTable,
+        active_flash: &'a Mutex<NoopRawMutex, RefCell<ACTIVE>>,
+        dfu_flash: &'a Mutex<NoopRawMutex, RefCell<DFU>>,
+        state_flash: &'a Mutex<NoopRawMutex, RefCell<STATE>>,
+    ) -> Result<Self, PartitionTableError> {
+        let active = table.require(PartitionKind::Active)?;
+        let dfu = table.require(PartitionKind::Dfu)?;
+        let state = table.require(PartitionKind::State)?;
+        trace!("ACTIVE: 0x{:x} - 0x{:x}", active.offset, active.offset + active.size);
+        trace!("DFU: 0x{:x} - 0x{:x}", dfu.offset, dfu.offset + dfu.size);
+        trace!("STATE: 0x{:x} - 0x{:x}", state.offset, state.offset + state.size);
+
+        Ok(Self {
+            active: BlockingPartition::new(active_flash, active.offset, active.size),
+            dfu: BlockingPartition::new(dfu_flash, dfu.offset, dfu.size),
+            state: BlockingPartition::new(state_flash, state.offset, state.size),
+        })
+    }
 }
 
 /// BootLoader works with any flash implementing embedded_storage.
@@ -140,6 +181,8 @@ pub struct BootLoader<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> {
     /// | 1..2     | Progress validity. ERASE_VALUE means valid, !ERASE_VALUE means invalid.          |
     /// | 2..2 + N | Progress index used while swapping or reverting      
     state: STATE,
+    /// Offset of the image version in the active partition, if the image has one.
+    version_offset: Option<u32>,
 }
 
 impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, STATE> {
@@ -159,9 +202,38 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
             active: config.active,
             dfu: config.dfu,
             state: config.state,
+            version_offset: None,
         }
     }
 
+    /// Read the version of the image from the active partition, as a little endian `u32` at
+    /// `offset`, e.g. in a header placed by the linker script.
+    ///
+    /// `offset` must be a multiple of the read size of the active partition. The version is
+    /// reported by [`active_version`](Self::active_version) and [`prepare_boot_images`].
+    pub fn with_version_offset(mut self, offset: u32) -> Self {
+        assert_eq!(0, offset as usize % ACTIVE::READ_SIZE);
+        self.version_offset = Some(offset);
+        self
+    }
+
+    /// Get the version of the image in the active partition.
+    ///
+    /// Returns `None` if no version offset was set with
+    /// [`with_version_offset`](Self::with_version_offset), or if the version is erased.
+    pub fn active_version(&mut self, aligned_buf: &mut [u8]) -> Result<Option<u32>, BootError> {
+        let Some(offset) = self.version_offset else {
+            return Ok(None);
+        };
+        let len = 4usize.next_multiple_of(ACTIVE::READ_SIZE);
+        let buf = &mut aligned_buf[..len];
+        self.active.read(offset, buf)?;
+        Ok(match u32::from_le_bytes(buf[..4].try_into().unwrap()) {
+            u32::MAX => None,
+            version => Some(version),
+        })
+    }
+
     /// Perform necessary boot preparations like swapping images.
     ///
     /// The DFU partition is assumed to be 1 page bigger than the active partition for the swap
@@ -280,6 +352,158 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
         Ok(state)
     }
 
+    /// Perform boot preparations like [`prepare_boot`](Self::prepare_boot), but only swap in an
+    /// update signed with one of the `keys`.
+    ///
+    /// The signature is checked against the [`Manifest`] at the end of the DFU partition before the
+    /// swap starts. If the check fails, the update is cancelled and the error is returned: the active
+    /// partition is left untouched and the next boot doesn't try the update again. An interrupted
+    /// swap or a revert was verified already, and is resumed without checking again.
+    ///
+    /// The provided aligned_buf argument must satisfy any alignment requirements
+    /// given by the partition flashes. All flash operations will use this buffer.
+    pub fn prepare_boot_verified<V: Verifier, K: KeyStore + ?Sized>(
+        &mut self,
+        aligned_buf: &mut [u8],
+        verifier: &mut V,
+        keys: &K,
+    ) -> Result<State, BootError> {
+        if self.read_state(aligned_buf)? == State::Swap && self.current_progress(aligned_buf)? == 0 {
+            if let Err(e) = self.verify_update(aligned_buf, verifier, keys) {
+                warn!("Update verification failed, cancelling update");
+                self.cancel_update(aligned_buf)?;
+                return Err(BootError::Verify(e));
+            }
+        }
+
+        self.prepare_boot(aligned_buf)
+    }
+
+    /// Perform boot preparations like [`prepare_boot`](Self::prepare_boot), and record the boot in
+    /// the `report`, for the application to read with a [`BootReport`](crate::report::BootReport).
+    ///
+    /// The boot is counted, along with the update about to be swapped in or the rollback about to
+    /// be done. Set `watchdog_reset` if the device was reset by the watchdog, to report it as the
+    /// reason of a rollback. The event is recorded before the swap or revert starts, so that it is
+    /// not counted again if they are interrupted by a power loss.
+    ///
+    /// The provided aligned_buf argument must satisfy any alignment requirements
+    /// given by the partition flashes. All flash operations will use this buffer.
+    pub fn prepare_boot_reported<REPORT: NorFlash>(
+        &mut self,
+        aligned_buf: &mut [u8],
+        report: &mut BlockingBootReport<'_, REPORT>,
+        watchdog_reset: bool,
+    ) -> Result<State, BootError> {
+        let event = self.pending_event(aligned_buf, watchdog_reset)?;
+        report.record(event)?;
+        self.prepare_boot(aligned_buf)
+    }
+
+    /// Perform boot preparations like [`prepare_boot_verified`](Self::prepare_boot_verified), and
+    /// record the boot like [`prepare_boot_reported`](Self::prepare_boot_reported).
+    ///
+    /// An update failing verification is reported as a rollback.
+    pub fn prepare_boot_verified_reported<V: Verifier, K: KeyStore + ?Sized, REPORT: NorFlash>(
+        &mut self,
+        aligned_buf: &mut [u8],
+        verifier: &mut V,
+        keys: &K,
+        report: &mut BlockingBootReport<'_, REPORT>,
+        watchdog_reset: bool,
+    ) -> Result<State, BootError> {
+        if self.read_state(aligned_buf)? == State::Swap && self.current_progress(aligned_buf)? == 0 {
+            if let Err(e) = self.verify_update(aligned_buf, verifier, keys) {
+                warn!("Update verification failed, cancelling update");
+                report.record(BootEvent::Rollback(RollbackReason::VerificationFailed))?;
+                self.cancel_update(aligned_buf)?;
+                return Err(BootError::Verify(e));
+            }
+        }
+
+        self.prepare_boot_reported(aligned_buf, report, watchdog_reset)
+    }
+
+    /// The event to record for this boot, before preparing it.
+    fn pending_event(&mut self, aligned_buf: &mut [u8], watchdog_reset: bool) -> Result<BootEvent, BootError> {
+        if self.read_state(aligned_buf)? != State::Swap {
+            return Ok(BootEvent::Boot);
+        }
+
+        let page_count = self.active.capacity() / Self::PAGE_SIZE as usize;
+        let progress = self.current_progress(aligned_buf)?;
+        Ok(if progress == 0 {
+            BootEvent::Update
+        } else if progress == page_count * 2 {
+            // Swapped, but the application didn't mark the update booted.
+            BootEvent::Rollback(if watchdog_reset {
+                RollbackReason::Watchdog
+            } else {
+                RollbackReason::NotConfirmed
+            })
+        } else {
+            // Resuming an interrupted swap or revert.
+            BootEvent::Boot
+        })
+    }
+
+    fn verify_update<V: Verifier, K: KeyStore + ?Sized>(
+        &mut self,
+        aligned_buf: &mut [u8],
+        verifier: &mut V,
+        keys: &K,
+    ) -> Result<Manifest, VerifyError> {
+        let max_image_len = self.active.capacity() as u32;
+        verify::verify_update_blocking(&mut self.dfu, max_image_len, verifier, keys, aligned_buf)
+    }
+
+    /// Receive an image over YMODEM on a serial port, and mark it to be swapped in on this boot.
+    ///
+    /// This is a recovery channel for devices whose application is broken, or doesn't update
+    /// itself: the bootloader calls it when the application is invalid or a button is held, before
+    /// [`prepare_boot`](Self::prepare_boot). The receiver requests the transfer every second until
+    /// the sender starts or `start_timeout_ms` passes. The image is written to the DFU partition, and
+    /// the next `prepare_boot` swaps it in like any other update, so the application must mark it
+    /// booted or it is reverted.
+    ///
+    /// Returns the length of the received image. On error, the state partition is left untouched.
+    /// The provided aligned_buf argument must be at least 1024 bytes long, and satisfy any alignment
+    /// requirements given by the partition flashes.
+    #[cfg(feature = "ymodem")]
+    pub fn recover_ymodem<S, D>(
+        &mut self,
+        serial: &mut S,
+        delay: &mut D,
+        aligned_buf: &mut [u8],
+        start_timeout_ms: u32,
+    ) -> Result<usize, crate::RecoveryError>
+    where
+        S: embedded_io::Read + embedded_io::ReadReady + embedded_io::Write,
+        D: embedded_hal::delay::DelayNs,
+    {
+        let max_len = self.active.capacity();
+        let len = crate::ymodem::receive(serial, delay, &mut self.dfu, max_len, aligned_buf, start_timeout_ms)?;
+
+        // Clear magic and progress, then mark the image for swap
+        let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
+        self.state.erase(0, self.state.capacity() as u32)?;
+        state_word.fill(SWAP_MAGIC);
+        self.state.write(0, state_word)?;
+        Ok(len)
+    }
+
+    fn cancel_update(&mut self, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+        let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
+
+        // Clear magic and progress
+        self.state.erase(0, self.state.capacity() as u32)?;
+
+        // Set magic
+        state_word.fill(BOOT_MAGIC);
+        self.state.write(0, state_word)?;
+        Ok(())
+    }
+
     fn is_swapped(&mut self, aligned_buf: &mut [u8]) -> Result<bool, BootError> {
         let page_count = self.active.capacity() / Self::PAGE_SIZE as usize;
         let progress = self.current_progress(aligned_buf)?;
@@ -414,6 +638,68 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
     }
 }
 
+/// An image managed by the bootloader, with its own active, DFU and state partitions.
+///
+/// Images are updated and rolled back independently of each other, with their own
+/// [`FirmwareUpdater`](crate::FirmwareUpdater) in the application. This allows managing several
+/// firmwares, like the application and the firmware of a radio coprocessor or of an external
+/// module, with [`prepare_boot_images`]. Images other than the application are usually installed
+/// by the application from their active partition, after which it marks them booted.
+///
+/// This trait is implemented by [`BootLoader`], and can be implemented by wrappers to prepare an
+/// image differently, for example with [`BootLoader::prepare_boot_verified`].
+pub trait BootImage {
+    /// Perform necessary boot preparations for the image, like swapping or reverting it.
+    ///
+    /// See [`BootLoader::prepare_boot`].
+    fn prepare_boot(&mut self, aligned_buf: &mut [u8]) -> Result<State, BootError>;
+
+    /// Get the version of the image in the active partition, if known.
+    ///
+    /// See [`BootLoader::active_version`].
+    fn active_version(&mut self, _aligned_buf: &mut [u8]) -> Result<Option<u32>, BootError> {
+        Ok(None)
+    }
+}
+
+impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootImage for BootLoader<ACTIVE, DFU, STATE> {
+    fn prepare_boot(&mut self, aligned_buf: &mut [u8]) -> Result<State, BootError> {
+        BootLoader::prepare_boot(self, aligned_buf)
+    }
+
+    fn active_version(&mut self, aligned_buf: &mut [u8]) -> Result<Option<u32>, BootError> {
+        BootLoader::active_version(self, aligned_buf)
+    }
+}
+
+/// State and version of an image prepared by [`prepare_boot_images`].
+#[derive(PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageRecord {
+    /// State of the image, see [`BootLoader::prepare_boot`].
+    pub state: State,
+    /// Version of the image booted from the active partition, after a swap or a revert.
+    pub version: Option<u32>,
+}
+
+/// Perform necessary boot preparations for several images, in order.
+///
+/// An error preparing an image doesn't prevent preparing the next ones, the record of each image is
+/// returned in the same order as the images.
+///
+/// The provided aligned_buf argument must satisfy the requirements of every image, see
+/// [`BootLoader::prepare_boot`].
+pub fn prepare_boot_images<const N: usize>(
+    images: [&mut dyn BootImage; N],
+    aligned_buf: &mut [u8],
+) -> [Result<ImageRecord, BootError>; N] {
+    images.map(|image| {
+        let state = image.prepare_boot(aligned_buf)?;
+        let version = image.active_version(aligned_buf)?;
+        Ok(ImageRecord { state, version })
+    })
+}
+
 fn assert_partitions<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash>(
     active: &ACTIVE,
     dfu: &DFU,
@@ -1,11 +1,11 @@
 use digest::Digest;
-#[cfg(target_os = "none")]
 use embassy_embedded_hal::flash::partition::Partition;
-#[cfg(target_os = "none")]
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embedded_storage_async::nor_flash::NorFlash;
 
 use super::FirmwareUpdaterConfig;
+use crate::partition_table::{PartitionKind, PartitionTable, PartitionTableError};
+use crate::verify::{self, KeyStore, Manifest, Verifier, MANIFEST_SIZE};
 use crate::{FirmwareUpdaterError, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
 
 /// FirmwareUpdater is an application API for interacting with the BootLoader without the ability to
@@ -51,6 +51,30 @@ impl<'a, DFU: NorFlash, STATE: NorFlash>
     }
 }
 
+impl<'a, DFU: NorFlash, STATE: NorFlash>
+    FirmwareUpdaterConfig<Partition<'a, NoopRawMutex, DFU>, Partition<'a, NoopRawMutex, STATE>>
+{
+    /// Create a firmware updater config from the flash and a partition table read at runtime.
+    ///
+    /// This is the runtime counterpart of `FirmwareUpdaterConfig::from_linkerfile`. The table must have a DFU
+    /// and a state partition, see the [`partition_table`](crate::partition_table) module.
+    pub fn from_partition_table(
+        table: &PartitionTable,
+        dfu_flash: &'a embassy_sync::mutex::Mutex<NoopRawMutex, DFU>,
+        state_flash: &'a embassy_sync::mutex::Mutex<NoopRawMutex, STATE>,
+    ) -> Result<Self, PartitionTableError> {
+        let dfu = table.require(PartitionKind::Dfu)?;
+        let state = table.require(PartitionKind::State)?;
+        trace!("DFU: 0x{:x} - 0x{:x}", dfu.offset, dfu.offset + dfu.size);
+        trace!("STATE: 0x{:x} - 0x{:x}", state.offset, state.offset + state.size);
+
+        Ok(Self {
+            dfu: Partition::new(dfu_flash, dfu.offset, dfu.size),
+            state: Partition::new(state_flash, state.offset, state.size),
+        })
+    }
+}
+
 impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
     /// Create a firmware updater instance with partition ranges for the update and state partitions.
     pub fn new(config: FirmwareUpdaterConfig<DFU, STATE>, aligned: &'d mut [u8]) -> Self {
@@ -140,6 +164,36 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
         self.state.mark_updated().await
     }
 
+    /// Verify the signed update in DFU against the [`Manifest`] at the end of the DFU partition.
+    /// Mark to trigger firmware swap on next boot if verify succeeds.
+    ///
+    /// The bootloader checks the signature again before swapping if it uses
+    /// `prepare_boot_verified`, this allows rejecting a bad update before rebooting.
+    ///
+    /// `chunk_buf` is used to read the DFU partition, it must satisfy its alignment requirements.
+    pub async fn verify_manifest_and_mark_updated<V: Verifier, K: KeyStore + ?Sized>(
+        &mut self,
+        verifier: &mut V,
+        keys: &K,
+        chunk_buf: &mut [u8],
+    ) -> Result<Manifest, FirmwareUpdaterError> {
+        self.state.verify_booted().await?;
+
+        let max_image_len = (self.dfu.capacity() - MANIFEST_SIZE) as u32;
+        let manifest = verify::verify_update(&mut self.dfu, max_image_len, verifier, keys, chunk_buf)
+            .await
+            .map_err(FirmwareUpdaterError::Verify)?;
+
+        self.state.mark_updated().await?;
+        Ok(manifest)
+    }
+
+    /// Write the [`Manifest`] of a signed update to the end of the DFU partition.
+    pub async fn write_manifest(&mut self, manifest: &Manifest) -> Result<(), FirmwareUpdaterError> {
+        let offset = self.dfu.capacity() - MANIFEST_SIZE;
+        self.write_firmware(offset, &manifest.to_bytes()).await
+    }
+
     /// Verify the update in DFU with any digest.
     pub async fn hash<D: Digest>(
         &mut self,
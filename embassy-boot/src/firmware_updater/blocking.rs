@@ -1,11 +1,11 @@
 use digest::Digest;
-#[cfg(target_os = "none")]
 use embassy_embedded_hal::flash::partition::BlockingPartition;
-#[cfg(target_os = "none")]
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embedded_storage::nor_flash::NorFlash;
 
 use super::FirmwareUpdaterConfig;
+use crate::partition_table::{PartitionKind, PartitionTable, PartitionTableError};
+use crate::verify::{self, KeyStore, Manifest, Verifier, MANIFEST_SIZE};
 use crate::{FirmwareUpdaterError, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
 
 /// Blocking FirmwareUpdater is an application API for interacting with the BootLoader without the ability to
@@ -81,6 +81,30 @@ impl<'a, DFU: NorFlash, STATE: NorFlash>
     }
 }
 
+impl<'a, DFU: NorFlash, STATE: NorFlash>
+    FirmwareUpdaterConfig<BlockingPartition<'a, NoopRawMutex, DFU>, BlockingPartition<'a, NoopRawMutex, STATE>>
+{
+    /// Create a firmware updater config from the flash and a partition table read at runtime.
+    ///
+    /// This is the runtime counterpart of `FirmwareUpdaterConfig::from_linkerfile_blocking`. The table must have a DFU
+    /// and a state partition, see the [`partition_table`](crate::partition_table) module.
+    pub fn from_partition_table_blocking(
+        table: &PartitionTable,
+        dfu_flash: &'a embassy_sync::blocking_mutex::Mutex<NoopRawMutex, core::cell::RefCell<DFU>>,
+        state_flash: &'a embassy_sync::blocking_mutex::Mutex<NoopRawMutex, core::cell::RefCell<STATE>>,
+    ) -> Result<Self, PartitionTableError> {
+        let dfu = table.require(PartitionKind::Dfu)?;
+        let state = table.require(PartitionKind::State)?;
+        trace!("DFU: 0x{:x} - 0x{:x}", dfu.offset, dfu.offset + dfu.size);
+        trace!("STATE: 0x{:x} - 0x{:x}", state.offset, state.offset + state.size);
+
+        Ok(Self {
+            dfu: BlockingPartition::new(dfu_flash, dfu.offset, dfu.size),
+            state: BlockingPartition::new(state_flash, state.offset, state.size),
+        })
+    }
+}
+
 impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE> {
     /// Create a firmware updater instance with partition ranges for the update and state partitions.
     ///
@@ -175,6 +199,35 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
         self.state.mark_updated()
     }
 
+    /// Verify the signed update in DFU against the [`Manifest`] at the end of the DFU partition.
+    /// Mark to trigger firmware swap on next boot if verify succeeds.
+    ///
+    /// The bootloader checks the signature again before swapping if it uses
+    /// `prepare_boot_verified`, this allows rejecting a bad update before rebooting.
+    ///
+    /// `chunk_buf` is used to read the DFU partition, it must satisfy its alignment requirements.
+    pub fn verify_manifest_and_mark_updated<V: Verifier, K: KeyStore + ?Sized>(
+        &mut self,
+        verifier: &mut V,
+        keys: &K,
+        chunk_buf: &mut [u8],
+    ) -> Result<Manifest, FirmwareUpdaterError> {
+        self.state.verify_booted()?;
+
+        let max_image_len = (self.dfu.capacity() - MANIFEST_SIZE) as u32;
+        let manifest = verify::verify_update_blocking(&mut self.dfu, max_image_len, verifier, keys, chunk_buf)
+            .map_err(FirmwareUpdaterError::Verify)?;
+
+        self.state.mark_updated()?;
+        Ok(manifest)
+    }
+
+    /// Write the [`Manifest`] of a signed update to the end of the DFU partition.
+    pub fn write_manifest(&mut self, manifest: &Manifest) -> Result<(), FirmwareUpdaterError> {
+        let offset = self.dfu.capacity() - MANIFEST_SIZE;
+        self.write_firmware(offset, &manifest.to_bytes())
+    }
+
     /// Verify the update in DFU with any digest.
     pub fn hash<D: Digest>(
         &mut self,
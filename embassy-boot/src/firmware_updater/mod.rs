@@ -5,6 +5,8 @@ pub use asynch::{FirmwareState, FirmwareUpdater};
 pub use blocking::{BlockingFirmwareState, BlockingFirmwareUpdater};
 use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind};
 
+use crate::VerifyError;
+
 /// Firmware updater flash configuration holding the two flashes used by the updater
 ///
 /// If only a single flash is actually used, then that flash should be partitioned into two partitions before use.
@@ -26,6 +28,8 @@ pub enum FirmwareUpdaterError {
     Signature(signature::Error),
     /// Bad state.
     BadState,
+    /// The update failed verification against its manifest.
+    Verify(VerifyError),
 }
 
 #[cfg(feature = "defmt")]
@@ -35,6 +39,7 @@ impl defmt::Format for FirmwareUpdaterError {
             FirmwareUpdaterError::Flash(_) => defmt::write!(fmt, "FirmwareUpdaterError::Flash(_)"),
             FirmwareUpdaterError::Signature(_) => defmt::write!(fmt, "FirmwareUpdaterError::Signature(_)"),
             FirmwareUpdaterError::BadState => defmt::write!(fmt, "FirmwareUpdaterError::BadState"),
+            FirmwareUpdaterError::Verify(e) => defmt::write!(fmt, "FirmwareUpdaterError::Verify({})", e),
         }
     }
 }
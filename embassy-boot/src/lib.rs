@@ -4,22 +4,37 @@
 #![doc = include_str!("../README.md")]
 mod fmt;
 
+pub mod ab;
 mod boot_loader;
 mod digest_adapters;
 mod firmware_updater;
 #[cfg(test)]
 mod mem_flash;
+pub mod partition_table;
+pub mod report;
 #[cfg(test)]
 mod test_flash;
+mod verify;
+#[cfg(feature = "ymodem")]
+mod ymodem;
 
 // The expected value of the flash after an erase
 // TODO: Use the value provided by NorFlash when available
 pub(crate) const STATE_ERASE_VALUE: u8 = 0xFF;
-pub use boot_loader::{BootError, BootLoader, BootLoaderConfig};
+pub use boot_loader::{prepare_boot_images, BootError, BootImage, BootLoader, BootLoaderConfig, ImageRecord};
 pub use firmware_updater::{
     BlockingFirmwareState, BlockingFirmwareUpdater, FirmwareState, FirmwareUpdater, FirmwareUpdaterConfig,
     FirmwareUpdaterError,
 };
+#[cfg(feature = "ecdsa-p256")]
+pub use verify::EcdsaP256;
+#[cfg(feature = "ed25519-dalek")]
+pub use verify::Ed25519Dalek;
+#[cfg(feature = "ed25519-salty")]
+pub use verify::Ed25519Salty;
+pub use verify::{KeyStore, Manifest, Verifier, VerifyError, MANIFEST_SIZE, MAX_SIGNATURE_LEN};
+#[cfg(feature = "ymodem")]
+pub use ymodem::RecoveryError;
 
 pub(crate) const BOOT_MAGIC: u8 = 0xD0;
 pub(crate) const SWAP_MAGIC: u8 = 0xF0;
@@ -269,6 +284,87 @@ mod tests {
         assert_eq!(ORIGINAL, read_buf);
     }
 
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_multiple_images() {
+        const FIRMWARE_SIZE: usize = 8192;
+        let app = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<FIRMWARE_SIZE, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+        let radio = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<FIRMWARE_SIZE, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+
+        // The version is in the first word of each image.
+        let image = |version: u32, fill: u8| {
+            let mut image = [fill; FIRMWARE_SIZE];
+            image[..4].copy_from_slice(&version.to_le_bytes());
+            image
+        };
+        let (app_image, app_update, radio_image) = (image(1, 0x55), image(2, 0xAA), image(7, 0x11));
+        app.active().write(0, &app_image).unwrap();
+        radio.active().write(0, &radio_image).unwrap();
+        app.state().write(0, &[BOOT_MAGIC; 4]).unwrap();
+        radio.state().write(0, &[BOOT_MAGIC; 4]).unwrap();
+
+        // Only update the application.
+        let mut aligned = [0; 4];
+        let mut updater = BlockingFirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: app.dfu(),
+                state: app.state(),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(0, &app_update).unwrap();
+        updater.mark_updated().unwrap();
+
+        let mut app_bootloader = BootLoader::new(BootLoaderConfig {
+            active: app.active(),
+            dfu: app.dfu(),
+            state: app.state(),
+        })
+        .with_version_offset(0);
+        let mut radio_bootloader = BootLoader::new(BootLoaderConfig {
+            active: radio.active(),
+            dfu: radio.dfu(),
+            state: radio.state(),
+        })
+        .with_version_offset(0);
+
+        let record = |state, version| {
+            Ok(ImageRecord {
+                state,
+                version: Some(version),
+            })
+        };
+
+        let mut page = [0; 1024];
+        let [app_record, radio_record] = prepare_boot_images([&mut app_bootloader, &mut radio_bootloader], &mut page);
+        assert_eq!(record(State::Swap, 2), app_record);
+        assert_eq!(record(State::Boot, 7), radio_record);
+
+        let mut read_buf = [0; FIRMWARE_SIZE];
+        app.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(app_update, read_buf);
+        radio.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(radio_image, read_buf);
+
+        // The application update is not marked booted, so only the application is reverted.
+        let [app_record, radio_record] = prepare_boot_images([&mut app_bootloader, &mut radio_bootloader], &mut page);
+        assert_eq!(record(State::Swap, 1), app_record);
+        assert_eq!(record(State::Boot, 7), radio_record);
+
+        app.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(app_image, read_buf);
+        radio.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(radio_image, read_buf);
+    }
+
     #[test]
     #[cfg(feature = "_verify")]
     fn test_verify() {
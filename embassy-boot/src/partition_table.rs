@@ -0,0 +1,434 @@
+//! Partition table, to find the partitions at runtime instead of from the linkerfile.
+//!
+//! With a partition table, one bootloader binary can serve devices or products with different
+//! memory layouts: the table is written to flash with the application, at an address agreed with
+//! the bootloader, and the configs are created with
+//! [`BootLoaderConfig::from_partition_table_blocking`](crate::BootLoaderConfig::from_partition_table_blocking)
+//! and [`FirmwareUpdaterConfig::from_partition_table`](crate::FirmwareUpdaterConfig::from_partition_table).
+//!
+//! The table starts with a header:
+//!
+//! | Bytes | Description                                  |
+//! |-------|----------------------------------------------|
+//! | 0..4  | Table magic, `EBPT`                          |
+//! | 4..6  | Length of the entries, in little endian      |
+//! | 6..8  | Reserved                                     |
+//! | 8..12 | CRC-32 of the entries, in little endian      |
+//!
+//! followed by the entries, each a tag byte, a length byte and `length` bytes of value. Entries
+//! with an unknown tag are skipped, so newer tables can be read by older bootloaders. A partition
+//! entry has tag 1 and a value of at least 12 bytes:
+//!
+//! | Bytes  | Description                                   |
+//! |--------|-----------------------------------------------|
+//! | 0      | Partition kind, see [`PartitionKind`]         |
+//! | 1..4   | Reserved                                      |
+//! | 4..8   | Offset in the flash, in little endian         |
+//! | 8..12  | Size, in little endian                        |
+use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use embedded_storage_async::nor_flash::ReadNorFlash as AsyncReadNorFlash;
+
+const TABLE_MAGIC: [u8; 4] = *b"EBPT";
+const HEADER_LEN: usize = 12;
+const PARTITION_TAG: u8 = 1;
+const PARTITION_LEN: usize = 12;
+
+/// Maximum number of partitions in a table.
+pub const MAX_PARTITIONS: usize = 16;
+
+/// Errors returned when reading or writing a partition table.
+#[derive(PartialEq, Eq, Debug)]
+pub enum PartitionTableError {
+    /// Error from flash.
+    Flash(NorFlashErrorKind),
+    /// The table doesn't start with the table magic, e.g. because it was never written.
+    BadMagic,
+    /// The entries don't match the checksum in the header.
+    BadChecksum,
+    /// An entry is truncated or too short for its tag.
+    Malformed,
+    /// The table holds more than [`MAX_PARTITIONS`] partitions.
+    TooManyPartitions,
+    /// The buffer is too small for the table.
+    BufferTooSmall,
+    /// The table has no partition of the given kind.
+    MissingPartition(PartitionKind),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PartitionTableError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            PartitionTableError::Flash(_) => defmt::write!(fmt, "PartitionTableError::Flash(_)"),
+            PartitionTableError::BadMagic => defmt::write!(fmt, "PartitionTableError::BadMagic"),
+            PartitionTableError::BadChecksum => defmt::write!(fmt, "PartitionTableError::BadChecksum"),
+            PartitionTableError::Malformed => defmt::write!(fmt, "PartitionTableError::Malformed"),
+            PartitionTableError::TooManyPartitions => defmt::write!(fmt, "PartitionTableError::TooManyPartitions"),
+            PartitionTableError::BufferTooSmall => defmt::write!(fmt, "PartitionTableError::BufferTooSmall"),
+            PartitionTableError::MissingPartition(kind) => {
+                defmt::write!(fmt, "PartitionTableError::MissingPartition({})", kind)
+            }
+        }
+    }
+}
+
+impl<E> From<E> for PartitionTableError
+where
+    E: NorFlashError,
+{
+    fn from(error: E) -> Self {
+        PartitionTableError::Flash(error.kind())
+    }
+}
+
+/// What a partition is used for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PartitionKind {
+    /// The active partition, booted by the bootloader.
+    Active,
+    /// The DFU partition, written by the application and swapped in by the bootloader.
+    Dfu,
+    /// The bootloader state partition.
+    State,
+    /// The boot metrics partition, see [`report`](crate::report).
+    Report,
+    /// Slot A of the [A/B update strategy](crate::ab).
+    SlotA,
+    /// Slot B of the [A/B update strategy](crate::ab).
+    SlotB,
+    /// An application-defined partition, from 128 to 255.
+    Custom(u8),
+}
+
+impl PartitionKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            PartitionKind::Active => 1,
+            PartitionKind::Dfu => 2,
+            PartitionKind::State => 3,
+            PartitionKind::Report => 4,
+            PartitionKind::SlotA => 5,
+            PartitionKind::SlotB => 6,
+            PartitionKind::Custom(byte) => byte,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => PartitionKind::Active,
+            2 => PartitionKind::Dfu,
+            3 => PartitionKind::State,
+            4 => PartitionKind::Report,
+            5 => PartitionKind::SlotA,
+            6 => PartitionKind::SlotB,
+            byte => PartitionKind::Custom(byte),
+        }
+    }
+}
+
+/// A partition of the flash.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Partition {
+    /// What the partition is used for.
+    pub kind: PartitionKind,
+    /// Offset of the partition in the flash.
+    pub offset: u32,
+    /// Size of the partition.
+    pub size: u32,
+}
+
+/// Partitions read from a partition table, see the [module documentation](crate::partition_table).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PartitionTable {
+    partitions: [Partition; MAX_PARTITIONS],
+    len: usize,
+}
+
+impl PartitionTable {
+    /// Parse a table from its bytes, starting with the header.
+    ///
+    /// Bytes after the entries are ignored.
+    pub fn parse(bytes: &[u8]) -> Result<Self, PartitionTableError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(PartitionTableError::BufferTooSmall);
+        }
+        if bytes[0..4] != TABLE_MAGIC {
+            return Err(PartitionTableError::BadMagic);
+        }
+        let entries_len = u16::from_le_bytes([bytes[4], bytes[5]]) as usize;
+        let crc = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        let entries = bytes
+            .get(HEADER_LEN..HEADER_LEN + entries_len)
+            .ok_or(PartitionTableError::BufferTooSmall)?;
+        if crc32(entries) != crc {
+            return Err(PartitionTableError::BadChecksum);
+        }
+
+        let mut table = Self {
+            partitions: [Partition {
+                kind: PartitionKind::Custom(0),
+                offset: 0,
+                size: 0,
+            }; MAX_PARTITIONS],
+            len: 0,
+        };
+        let mut rest = entries;
+        while !rest.is_empty() {
+            if rest.len() < 2 || rest.len() < 2 + rest[1] as usize {
+                return Err(PartitionTableError::Malformed);
+            }
+            let (tag, value) = (rest[0], &rest[2..2 + rest[1] as usize]);
+            rest = &rest[2 + value.len()..];
+
+            if tag != PARTITION_TAG {
+                continue;
+            }
+            if value.len() < PARTITION_LEN {
+                return Err(PartitionTableError::Malformed);
+            }
+            if table.len == MAX_PARTITIONS {
+                return Err(PartitionTableError::TooManyPartitions);
+            }
+            table.partitions[table.len] = Partition {
+                kind: PartitionKind::from_byte(value[0]),
+                offset: u32::from_le_bytes([value[4], value[5], value[6], value[7]]),
+                size: u32::from_le_bytes([value[8], value[9], value[10], value[11]]),
+            };
+            table.len += 1;
+        }
+
+        Ok(table)
+    }
+
+    /// Read a table from `offset` in a flash.
+    ///
+    /// The `aligned` buffer must hold the whole table, rounded up to `FLASH::READ_SIZE`, and follow
+    /// the alignment rules for the flash.
+    pub fn read_blocking<FLASH: ReadNorFlash>(
+        flash: &mut FLASH,
+        offset: u32,
+        aligned: &mut [u8],
+    ) -> Result<Self, PartitionTableError> {
+        let header_len = read_len(HEADER_LEN, FLASH::READ_SIZE, aligned.len())?;
+        flash.read(offset, &mut aligned[..header_len])?;
+        let table_len = read_len(table_len(aligned)?, FLASH::READ_SIZE, aligned.len())?;
+        if table_len > header_len {
+            flash.read(offset, &mut aligned[..table_len])?;
+        }
+        Self::parse(aligned)
+    }
+
+    /// Read a table from `offset` in a flash.
+    ///
+    /// The `aligned` buffer must hold the whole table, rounded up to `FLASH::READ_SIZE`, and follow
+    /// the alignment rules for the flash.
+    pub async fn read<FLASH: AsyncReadNorFlash>(
+        flash: &mut FLASH,
+        offset: u32,
+        aligned: &mut [u8],
+    ) -> Result<Self, PartitionTableError> {
+        let header_len = read_len(HEADER_LEN, FLASH::READ_SIZE, aligned.len())?;
+        flash.read(offset, &mut aligned[..header_len]).await?;
+        let table_len = read_len(table_len(aligned)?, FLASH::READ_SIZE, aligned.len())?;
+        if table_len > header_len {
+            flash.read(offset, &mut aligned[..table_len]).await?;
+        }
+        Self::parse(aligned)
+    }
+
+    /// Encode a table with `partitions` into `buf`, returning the length of the table.
+    ///
+    /// The rest of `buf` is left untouched.
+    pub fn encode(partitions: &[Partition], buf: &mut [u8]) -> Result<usize, PartitionTableError> {
+        if partitions.len() > MAX_PARTITIONS {
+            return Err(PartitionTableError::TooManyPartitions);
+        }
+        let entries_len = partitions.len() * (2 + PARTITION_LEN);
+        let table = buf
+            .get_mut(..HEADER_LEN + entries_len)
+            .ok_or(PartitionTableError::BufferTooSmall)?;
+
+        for (entry, partition) in table[HEADER_LEN..].chunks_exact_mut(2 + PARTITION_LEN).zip(partitions) {
+            entry[0] = PARTITION_TAG;
+            entry[1] = PARTITION_LEN as u8;
+            entry[2] = partition.kind.to_byte();
+            entry[3..6].fill(0);
+            entry[6..10].copy_from_slice(&partition.offset.to_le_bytes());
+            entry[10..14].copy_from_slice(&partition.size.to_le_bytes());
+        }
+        let crc = crc32(&table[HEADER_LEN..]);
+        table[0..4].copy_from_slice(&TABLE_MAGIC);
+        table[4..6].copy_from_slice(&(entries_len as u16).to_le_bytes());
+        table[6..8].fill(0);
+        table[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        Ok(table.len())
+    }
+
+    /// The first partition of the given kind.
+    pub fn get(&self, kind: PartitionKind) -> Option<&Partition> {
+        self.iter().find(|partition| partition.kind == kind)
+    }
+
+    /// The first partition of the given kind, or an error if there is none.
+    pub fn require(&self, kind: PartitionKind) -> Result<&Partition, PartitionTableError> {
+        self.get(kind).ok_or(PartitionTableError::MissingPartition(kind))
+    }
+
+    /// Iterate over the partitions, in table order.
+    pub fn iter(&self) -> impl Iterator<Item = &Partition> {
+        self.partitions[..self.len].iter()
+    }
+
+    /// Number of partitions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the table has no partitions.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Length of the table, from its header.
+fn table_len(header: &[u8]) -> Result<usize, PartitionTableError> {
+    if header[0..4] != TABLE_MAGIC {
+        return Err(PartitionTableError::BadMagic);
+    }
+    Ok(HEADER_LEN + u16::from_le_bytes([header[4], header[5]]) as usize)
+}
+
+/// Length to read for `len` bytes, rounded up to the read size and checked against the buffer.
+fn read_len(len: usize, read_size: usize, buf_len: usize) -> Result<usize, PartitionTableError> {
+    let len = len.div_ceil(read_size) * read_size;
+    if len > buf_len {
+        return Err(PartitionTableError::BufferTooSmall);
+    }
+    Ok(len)
+}
+
+/// CRC-32 (IEEE 802.3), as computed by `crc32` in zlib and most tools.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::NorFlash;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::mem_flash::MemFlash;
+
+    const PARTITIONS: [Partition; 3] = [
+        Partition {
+            kind: PartitionKind::Active,
+            offset: 0x8000,
+            size: 0x10000,
+        },
+        Partition {
+            kind: PartitionKind::Dfu,
+            offset: 0x18000,
+            size: 0x11000,
+        },
+        Partition {
+            kind: PartitionKind::State,
+            offset: 0x6000,
+            size: 0x1000,
+        },
+    ];
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn encode_parse_roundtrip() {
+        let mut buf = [0xFF; 64];
+        let len = PartitionTable::encode(&PARTITIONS, &mut buf).unwrap();
+        assert_eq!(HEADER_LEN + 3 * 14, len);
+
+        let table = PartitionTable::parse(&buf).unwrap();
+        assert!(table.iter().eq(PARTITIONS.iter()));
+        assert_eq!(Some(&PARTITIONS[1]), table.get(PartitionKind::Dfu));
+        assert_eq!(
+            Err(PartitionTableError::MissingPartition(PartitionKind::Report)),
+            table.require(PartitionKind::Report)
+        );
+
+        buf[HEADER_LEN + 5] ^= 1;
+        assert_eq!(Err(PartitionTableError::BadChecksum), PartitionTable::parse(&buf));
+        assert_eq!(Err(PartitionTableError::BadMagic), PartitionTable::parse(&[0xFF; 64]));
+    }
+
+    #[test]
+    fn unknown_entries_are_skipped() {
+        // A table from a newer tool, with an unknown entry and a longer partition entry.
+        let mut entries = [0; 4 + 2 + 16];
+        entries[0..4].copy_from_slice(&[0x7F, 2, 0xAB, 0xCD]);
+        entries[4..6].copy_from_slice(&[PARTITION_TAG, 16]);
+        entries[6] = 200;
+        entries[10..14].copy_from_slice(&0x1000u32.to_le_bytes());
+        entries[14..18].copy_from_slice(&0x2000u32.to_le_bytes());
+
+        let mut buf = [0; HEADER_LEN + 22];
+        buf[0..4].copy_from_slice(&TABLE_MAGIC);
+        buf[4..6].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        buf[8..12].copy_from_slice(&crc32(&entries).to_le_bytes());
+        buf[HEADER_LEN..].copy_from_slice(&entries);
+
+        let table = PartitionTable::parse(&buf).unwrap();
+        assert_eq!(1, table.len());
+        assert_eq!(
+            Some(&Partition {
+                kind: PartitionKind::Custom(200),
+                offset: 0x1000,
+                size: 0x2000,
+            }),
+            table.iter().next()
+        );
+
+        // Truncated entry.
+        buf[4] -= 1;
+        buf[8..12].copy_from_slice(&crc32(&entries[..21]).to_le_bytes());
+        assert_eq!(Err(PartitionTableError::Malformed), PartitionTable::parse(&buf));
+    }
+
+    #[test]
+    fn read_from_flash() {
+        let mut flash = MemFlash::<4096, 1024, 8>::default();
+        let mut buf = [0xFF; 64];
+        PartitionTable::encode(&PARTITIONS, &mut buf).unwrap();
+        flash.write(1024, &buf).unwrap();
+
+        let mut aligned = [0; 64];
+        let table = PartitionTable::read_blocking(&mut flash, 1024, &mut aligned).unwrap();
+        assert!(table.iter().eq(PARTITIONS.iter()));
+
+        let mut aligned = [0; 64];
+        let table = block_on(PartitionTable::read(&mut flash, 1024, &mut aligned)).unwrap();
+        assert!(table.iter().eq(PARTITIONS.iter()));
+
+        let mut aligned = [0; 32];
+        assert_eq!(
+            Err(PartitionTableError::BufferTooSmall),
+            PartitionTable::read_blocking(&mut flash, 1024, &mut aligned)
+        );
+        assert_eq!(
+            Err(PartitionTableError::BadMagic),
+            PartitionTable::read_blocking(&mut flash, 0, &mut aligned)
+        );
+    }
+}
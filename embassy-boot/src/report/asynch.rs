@@ -0,0 +1,48 @@
+use embedded_storage_async::nor_flash::NorFlash;
+
+use super::{record_size, BootMetrics, Scanner};
+use crate::FirmwareUpdaterError;
+
+/// Reader of the boot metrics, see the [module documentation](crate::report).
+///
+/// The bootloader records the metrics with
+/// [`BootLoader::prepare_boot_reported`](crate::BootLoader::prepare_boot_reported).
+pub struct BootReport<'d, REPORT: NorFlash> {
+    report: REPORT,
+    aligned: &'d mut [u8],
+}
+
+impl<'d, REPORT: NorFlash> BootReport<'d, REPORT> {
+    const RECORD_SIZE: usize = record_size(REPORT::WRITE_SIZE, REPORT::READ_SIZE);
+
+    /// Create a boot report with the report partition, two erase sectors long.
+    ///
+    /// The `aligned` buffer must be at least `max(16, REPORT::WRITE_SIZE, REPORT::READ_SIZE)` bytes
+    /// long, and follow the alignment rules for the report partition.
+    pub fn new(report: REPORT, aligned: &'d mut [u8]) -> Self {
+        assert!(aligned.len() >= Self::RECORD_SIZE);
+        assert!(Self::RECORD_SIZE <= REPORT::ERASE_SIZE);
+        assert!(report.capacity() >= 2 * REPORT::ERASE_SIZE);
+        Self { report, aligned }
+    }
+
+    /// Read the metrics recorded by the bootloader.
+    ///
+    /// The metrics are all zero if the bootloader never recorded any.
+    pub async fn read(&mut self) -> Result<BootMetrics, FirmwareUpdaterError> {
+        let slots_per_sector = REPORT::ERASE_SIZE / Self::RECORD_SIZE;
+        let mut scanner = Scanner::new();
+        for sector in 0..2 {
+            for slot in 0..slots_per_sector {
+                let record = &mut self.aligned[..Self::RECORD_SIZE];
+                self.report
+                    .read((sector * REPORT::ERASE_SIZE + slot * Self::RECORD_SIZE) as u32, record)
+                    .await?;
+                if !scanner.feed(sector, slot, record) {
+                    break;
+                }
+            }
+        }
+        Ok(scanner.finish(slots_per_sector).latest.unwrap_or_default())
+    }
+}
@@ -0,0 +1,317 @@
+//! Boot metrics and rollback reasons, recorded by the bootloader for the application.
+//!
+//! The report partition is two erase sectors long, and holds a log of records. On each boot, the
+//! bootloader appends a record with the updated metrics after the latest one, moving to the other
+//! sector when the current one is full. This erases each sector once every
+//! `ERASE_SIZE / RECORD_SIZE` boots, and never erases the latest record, so the metrics survive a
+//! power loss. Each record is `max(16, WRITE_SIZE, READ_SIZE)` bytes long:
+//!
+//! | Bytes  | Description                                   |
+//! |--------|-----------------------------------------------|
+//! | 0      | Record magic                                  |
+//! | 1      | Reason of the last rollback, 0 if none        |
+//! | 2      | Checksum of the other bytes                   |
+//! | 3      | Reserved                                      |
+//! | 4..8   | Boot count, in little endian                  |
+//! | 8..12  | Update count, in little endian                |
+//! | 12..16 | Rollback count, in little endian              |
+mod asynch;
+mod blocking;
+
+pub use asynch::BootReport;
+pub use blocking::BlockingBootReport;
+
+use crate::STATE_ERASE_VALUE;
+
+const RECORD_MAGIC: u8 = 0xB0;
+const RECORD_LEN: usize = 16;
+
+/// The reason why the bootloader rolled back, or refused, an update.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RollbackReason {
+    /// The watchdog reset the device before the application marked the update booted.
+    Watchdog,
+    /// The device was reset before the application marked the update booted.
+    NotConfirmed,
+    /// The update failed signature verification, and was cancelled before the swap.
+    VerificationFailed,
+}
+
+impl RollbackReason {
+    fn to_byte(self) -> u8 {
+        match self {
+            RollbackReason::Watchdog => 1,
+            RollbackReason::NotConfirmed => 2,
+            RollbackReason::VerificationFailed => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(RollbackReason::Watchdog),
+            2 => Some(RollbackReason::NotConfirmed),
+            3 => Some(RollbackReason::VerificationFailed),
+            _ => None,
+        }
+    }
+}
+
+/// Metrics recorded by the bootloader, see the [module documentation](crate::report).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BootMetrics {
+    /// Number of boots, including the current one.
+    pub boot_count: u32,
+    /// Number of updates swapped in.
+    pub update_count: u32,
+    /// Number of updates rolled back or refused.
+    pub rollback_count: u32,
+    /// The reason of the last rollback.
+    pub last_rollback: Option<RollbackReason>,
+}
+
+/// What the bootloader does on a boot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum BootEvent {
+    Boot,
+    Update,
+    Rollback(RollbackReason),
+}
+
+impl BootMetrics {
+    fn record(&mut self, event: BootEvent) {
+        self.boot_count = self.boot_count.wrapping_add(1);
+        match event {
+            BootEvent::Boot => {}
+            BootEvent::Update => self.update_count = self.update_count.wrapping_add(1),
+            BootEvent::Rollback(reason) => {
+                self.rollback_count = self.rollback_count.wrapping_add(1);
+                self.last_rollback = Some(reason);
+            }
+        }
+    }
+
+    fn encode(&self, record: &mut [u8]) {
+        record.fill(STATE_ERASE_VALUE);
+        record[0] = RECORD_MAGIC;
+        record[1] = self.last_rollback.map_or(0, RollbackReason::to_byte);
+        record[3] = 0;
+        record[4..8].copy_from_slice(&self.boot_count.to_le_bytes());
+        record[8..12].copy_from_slice(&self.update_count.to_le_bytes());
+        record[12..16].copy_from_slice(&self.rollback_count.to_le_bytes());
+        record[2] = checksum(record);
+    }
+
+    fn decode(record: &[u8]) -> Option<Self> {
+        if record[0] != RECORD_MAGIC || record[2] != checksum(record) {
+            return None;
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                record[offset],
+                record[offset + 1],
+                record[offset + 2],
+                record[offset + 3],
+            ])
+        };
+        Some(Self {
+            boot_count: word(4),
+            update_count: word(8),
+            rollback_count: word(12),
+            last_rollback: RollbackReason::from_byte(record[1]),
+        })
+    }
+}
+
+/// Checksum of a record, skipping the checksum byte.
+fn checksum(record: &[u8]) -> u8 {
+    record[..RECORD_LEN]
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != 2)
+        .fold(0x5A, |sum, (_, &byte)| sum.rotate_left(1) ^ byte)
+}
+
+/// Size of a record for a flash with the given write and read sizes.
+const fn record_size(write_size: usize, read_size: usize) -> usize {
+    let size = if write_size > read_size { write_size } else { read_size };
+    if size > RECORD_LEN {
+        size
+    } else {
+        RECORD_LEN
+    }
+}
+
+/// Finds the latest record and the position of the next one, from the records of both sectors.
+struct Scanner {
+    latest: Option<(BootMetrics, usize)>,
+    last_written: [Option<usize>; 2],
+}
+
+/// Where to write the next record.
+struct NextRecord {
+    latest: Option<BootMetrics>,
+    sector: usize,
+    slot: usize,
+    erase: bool,
+}
+
+impl Scanner {
+    fn new() -> Self {
+        Self {
+            latest: None,
+            last_written: [None; 2],
+        }
+    }
+
+    /// Feed a record, returning `false` if it is erased and the rest of the sector can be skipped.
+    fn feed(&mut self, sector: usize, slot: usize, record: &[u8]) -> bool {
+        if record.iter().all(|&b| b == STATE_ERASE_VALUE) {
+            return false;
+        }
+        // Records that fail to decode were partially written, and are skipped.
+        self.last_written[sector] = Some(slot);
+        if let Some(metrics) = BootMetrics::decode(record) {
+            let newer = match self.latest {
+                Some((latest, _)) => metrics.boot_count >= latest.boot_count,
+                None => true,
+            };
+            if newer {
+                self.latest = Some((metrics, sector));
+            }
+        }
+        true
+    }
+
+    fn finish(self, slots_per_sector: usize) -> NextRecord {
+        let (latest, sector) = match self.latest {
+            Some((metrics, sector)) => (Some(metrics), sector),
+            None => (None, 0),
+        };
+        let slot = self.last_written[sector].map_or(0, |slot| slot + 1);
+        if slot < slots_per_sector {
+            NextRecord {
+                latest,
+                sector,
+                slot,
+                erase: false,
+            }
+        } else {
+            NextRecord {
+                latest,
+                sector: 1 - sector,
+                slot: 0,
+                erase: true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::NorFlash;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::mem_flash::MemFlash;
+    #[cfg(not(feature = "_verify"))]
+    use crate::test_flash::BlockingTestFlash;
+    #[cfg(not(feature = "_verify"))]
+    use crate::{BlockingFirmwareUpdater, BootLoader, BootLoaderConfig, FirmwareUpdaterConfig, State};
+
+    #[test]
+    fn empty_report_has_no_boots() {
+        let mut aligned = [0; 16];
+        let mut report = BlockingBootReport::new(MemFlash::<128, 64, 4>::default(), &mut aligned);
+        assert_eq!(BootMetrics::default(), report.read().unwrap());
+    }
+
+    #[test]
+    fn records_move_to_other_sector() {
+        let mut flash = MemFlash::<128, 64, 4>::default();
+        let mut aligned = [0; 16];
+        // Four records per sector: the first sector is erased for the ninth record.
+        {
+            let mut report = BlockingBootReport::new(&mut flash, &mut aligned);
+            for _ in 0..10 {
+                report.record(BootEvent::Boot).unwrap();
+            }
+            report.record(BootEvent::Rollback(RollbackReason::Watchdog)).unwrap();
+        }
+        // A record partially written on power loss is skipped.
+        flash.write(48, &[RECORD_MAGIC, 0, 0, 0]).unwrap();
+
+        let mut report = BlockingBootReport::new(&mut flash, &mut aligned);
+        report.record(BootEvent::Update).unwrap();
+        let expected = BootMetrics {
+            boot_count: 12,
+            update_count: 1,
+            rollback_count: 1,
+            last_rollback: Some(RollbackReason::Watchdog),
+        };
+        assert_eq!(expected, report.read().unwrap());
+
+        let mut aligned = [0; 16];
+        let mut report = BootReport::new(&mut flash, &mut aligned);
+        assert_eq!(expected, block_on(report.read()).unwrap());
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn bootloader_reports_update_and_rollback() {
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<8192, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+        let mut report_flash = MemFlash::<8192, 4096, 4>::default();
+        let mut report_buf = [0; 16];
+        let mut report = BlockingBootReport::new(&mut report_flash, &mut report_buf);
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let mut page = [0; 4096];
+        assert_eq!(
+            State::Boot,
+            bootloader.prepare_boot_reported(&mut page, &mut report, false).unwrap()
+        );
+
+        let mut aligned = [0; 4];
+        let mut updater = BlockingFirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: flash.dfu(),
+                state: flash.state(),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(0, &[0xAA; 8192]).unwrap();
+        updater.mark_updated().unwrap();
+
+        // The update is swapped in, then rolled back because it wasn't marked booted.
+        assert_eq!(
+            State::Swap,
+            bootloader.prepare_boot_reported(&mut page, &mut report, false).unwrap()
+        );
+        assert_eq!(
+            State::Swap,
+            bootloader.prepare_boot_reported(&mut page, &mut report, true).unwrap()
+        );
+        assert_eq!(
+            State::Boot,
+            bootloader.prepare_boot_reported(&mut page, &mut report, false).unwrap()
+        );
+
+        assert_eq!(
+            BootMetrics {
+                boot_count: 4,
+                update_count: 1,
+                rollback_count: 1,
+                last_rollback: Some(RollbackReason::Watchdog),
+            },
+            report.read().unwrap()
+        );
+    }
+}
@@ -0,0 +1,525 @@
+use digest::Digest;
+use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use embedded_storage_async::nor_flash::ReadNorFlash as AsyncReadNorFlash;
+
+/// Size of the [`Manifest`], stored in the last bytes of the DFU partition.
+pub const MANIFEST_SIZE: usize = 128;
+
+const MANIFEST_MAGIC: [u8; 4] = *b"EBM1";
+
+/// Length of the manifest header covered by the signature.
+const SIGNED_HEADER_LEN: usize = 16;
+
+const SIGNATURE_OFFSET: usize = SIGNED_HEADER_LEN;
+
+/// Maximum length of a signature in the manifest.
+pub const MAX_SIGNATURE_LEN: usize = 64;
+
+/// Errors returned when verifying an update.
+#[derive(PartialEq, Eq, Debug)]
+pub enum VerifyError {
+    /// Error from flash.
+    Flash(NorFlashErrorKind),
+    /// No valid manifest at the end of the DFU partition.
+    NoManifest,
+    /// The manifest was signed with a different algorithm than the verifier's.
+    UnsupportedAlgorithm,
+    /// The key used to sign the update is not in the key store.
+    UnknownKey,
+    /// The image is longer than the partition it must fit in.
+    ImageTooLong,
+    /// The signature doesn't match the image.
+    InvalidSignature,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for VerifyError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            VerifyError::Flash(_) => defmt::write!(fmt, "VerifyError::Flash(_)"),
+            VerifyError::NoManifest => defmt::write!(fmt, "VerifyError::NoManifest"),
+            VerifyError::UnsupportedAlgorithm => defmt::write!(fmt, "VerifyError::UnsupportedAlgorithm"),
+            VerifyError::UnknownKey => defmt::write!(fmt, "VerifyError::UnknownKey"),
+            VerifyError::ImageTooLong => defmt::write!(fmt, "VerifyError::ImageTooLong"),
+            VerifyError::InvalidSignature => defmt::write!(fmt, "VerifyError::InvalidSignature"),
+        }
+    }
+}
+
+impl<E> From<E> for VerifyError
+where
+    E: NorFlashError,
+{
+    fn from(error: E) -> Self {
+        VerifyError::Flash(error.kind())
+    }
+}
+
+/// Manifest describing a signed update.
+///
+/// The manifest is stored in the last [`MANIFEST_SIZE`] bytes of the DFU partition, which the image
+/// never uses since the DFU partition is at least one page bigger than the active partition. It has
+/// the following format, with integers in little endian:
+///
+/// | Range    | Description                                                        |
+/// | 0..4     | Magic, `EBM1`                                                      |
+/// | 4..8     | Image length in bytes                                              |
+/// | 8..12    | Image version, for the application to prevent downgrades           |
+/// | 12       | ID of the signing key in the [`KeyStore`]                          |
+/// | 13       | Signature algorithm, see [`Verifier::ALGORITHM`]                   |
+/// | 14..16   | Signature length                                                   |
+/// | 16..80   | Signature                                                          |
+/// | 80..128  | Reserved, erased                                                   |
+///
+/// The signature covers the digest of the image followed by the first 16 bytes of the manifest,
+/// so that the length, version and key can't be tampered with.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Manifest {
+    /// Image length in bytes.
+    pub image_len: u32,
+    /// Image version.
+    pub version: u32,
+    /// ID of the signing key.
+    pub key_id: u8,
+    /// Signature algorithm.
+    pub algorithm: u8,
+    signature: [u8; MAX_SIGNATURE_LEN],
+    signature_len: usize,
+}
+
+impl Manifest {
+    /// Create a manifest for a signed image.
+    ///
+    /// This is mostly useful for tooling: the manifest is usually created when signing the image
+    /// on the host, and written to the DFU partition along with the image.
+    ///
+    /// Panics if the signature is longer than [`MAX_SIGNATURE_LEN`].
+    pub fn new(image_len: u32, version: u32, key_id: u8, algorithm: u8, signature: &[u8]) -> Self {
+        assert!(signature.len() <= MAX_SIGNATURE_LEN);
+        let mut sig = [0; MAX_SIGNATURE_LEN];
+        sig[..signature.len()].copy_from_slice(signature);
+        Self {
+            image_len,
+            version,
+            key_id,
+            algorithm,
+            signature: sig,
+            signature_len: signature.len(),
+        }
+    }
+
+    /// Parse a manifest, returning `None` if it is not valid.
+    pub fn parse(buf: &[u8; MANIFEST_SIZE]) -> Option<Self> {
+        if buf[0..4] != MANIFEST_MAGIC {
+            return None;
+        }
+        let signature_len = u16::from_le_bytes([buf[14], buf[15]]) as usize;
+        if signature_len > MAX_SIGNATURE_LEN {
+            return None;
+        }
+
+        let mut signature = [0; MAX_SIGNATURE_LEN];
+        signature[..signature_len].copy_from_slice(&buf[SIGNATURE_OFFSET..][..signature_len]);
+        Some(Self {
+            image_len: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            version: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            key_id: buf[12],
+            algorithm: buf[13],
+            signature,
+            signature_len,
+        })
+    }
+
+    /// Serialize the manifest, to write it to the DFU partition.
+    pub fn to_bytes(&self) -> [u8; MANIFEST_SIZE] {
+        let mut buf = [crate::STATE_ERASE_VALUE; MANIFEST_SIZE];
+        buf[..SIGNED_HEADER_LEN].copy_from_slice(&self.signed_header());
+        buf[SIGNATURE_OFFSET..][..MAX_SIGNATURE_LEN].copy_from_slice(&self.signature);
+        buf
+    }
+
+    /// The signature of the image.
+    pub fn signature(&self) -> &[u8] {
+        &self.signature[..self.signature_len]
+    }
+
+    /// The part of the manifest covered by the signature.
+    fn signed_header(&self) -> [u8; SIGNED_HEADER_LEN] {
+        let mut buf = [0; SIGNED_HEADER_LEN];
+        buf[0..4].copy_from_slice(&MANIFEST_MAGIC);
+        buf[4..8].copy_from_slice(&self.image_len.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.version.to_le_bytes());
+        buf[12] = self.key_id;
+        buf[13] = self.algorithm;
+        buf[14..16].copy_from_slice(&(self.signature_len as u16).to_le_bytes());
+        buf
+    }
+}
+
+/// A signature scheme for firmware images.
+///
+/// Implementations are provided for software ed25519 (`ed25519-dalek` and `ed25519-salty` features)
+/// and ECDSA P-256 (`ecdsa-p256` feature). Hardware accelerators can be used by implementing this
+/// trait on top of their driver.
+pub trait Verifier {
+    /// Digest of the image and manifest header that is signed.
+    type Digest: Digest;
+
+    /// Identifier of the algorithm, stored in the manifest.
+    ///
+    /// `1` is ed25519 over a SHA-512 digest, `2` is ECDSA P-256 over a SHA-256 digest. Values from
+    /// `0x80` are free for custom schemes.
+    const ALGORITHM: u8;
+
+    /// Verify the signature of `digest` with `public_key`.
+    fn verify(&mut self, public_key: &[u8], digest: &[u8], signature: &[u8]) -> Result<(), signature::Error>;
+}
+
+/// Storage of the public keys that updates can be signed with.
+///
+/// Keys are identified by an ID, so that a compromised key can be revoked while updates signed
+/// with the others are still accepted. A slice or array of keys is a key store where the ID is
+/// the index of the key.
+pub trait KeyStore {
+    /// The public key with the given ID, or `None` if there is no such key or it is revoked.
+    fn public_key(&self, key_id: u8) -> Option<&[u8]>;
+}
+
+impl KeyStore for [&[u8]] {
+    fn public_key(&self, key_id: u8) -> Option<&[u8]> {
+        self.get(key_id as usize).copied()
+    }
+}
+
+impl<const N: usize> KeyStore for [&[u8]; N] {
+    fn public_key(&self, key_id: u8) -> Option<&[u8]> {
+        self.get(key_id as usize).copied()
+    }
+}
+
+/// Verifies the signed update in the DFU partition.
+///
+/// `max_image_len` is the capacity of the active partition. `chunk_buf` is used to read the flash,
+/// it must satisfy its alignment requirements.
+pub(crate) fn verify_update_blocking<F: ReadNorFlash, V: Verifier, K: KeyStore + ?Sized>(
+    dfu: &mut F,
+    max_image_len: u32,
+    verifier: &mut V,
+    keys: &K,
+    chunk_buf: &mut [u8],
+) -> Result<Manifest, VerifyError> {
+    let mut buf = [0; MANIFEST_SIZE];
+    let manifest_offset = (dfu.capacity() - MANIFEST_SIZE) as u32;
+    for (i, chunk) in buf.chunks_mut(chunk_buf.len()).enumerate() {
+        let offset = manifest_offset + (i * chunk_buf.len()) as u32;
+        dfu.read(offset, &mut chunk_buf[..chunk.len()])?;
+        chunk.copy_from_slice(&chunk_buf[..chunk.len()]);
+    }
+    let manifest = check_manifest::<V>(&buf, max_image_len)?;
+
+    let mut digest = V::Digest::new();
+    for offset in (0..manifest.image_len).step_by(chunk_buf.len()) {
+        dfu.read(offset, chunk_buf)?;
+        let len = core::cmp::min((manifest.image_len - offset) as usize, chunk_buf.len());
+        digest.update(&chunk_buf[..len]);
+    }
+
+    check_signature(manifest, digest, verifier, keys)
+}
+
+/// Async version of [`verify_update_blocking`].
+pub(crate) async fn verify_update<F: AsyncReadNorFlash, V: Verifier, K: KeyStore + ?Sized>(
+    dfu: &mut F,
+    max_image_len: u32,
+    verifier: &mut V,
+    keys: &K,
+    chunk_buf: &mut [u8],
+) -> Result<Manifest, VerifyError> {
+    let mut buf = [0; MANIFEST_SIZE];
+    let manifest_offset = (dfu.capacity() - MANIFEST_SIZE) as u32;
+    for (i, chunk) in buf.chunks_mut(chunk_buf.len()).enumerate() {
+        let offset = manifest_offset + (i * chunk_buf.len()) as u32;
+        dfu.read(offset, &mut chunk_buf[..chunk.len()]).await?;
+        chunk.copy_from_slice(&chunk_buf[..chunk.len()]);
+    }
+    let manifest = check_manifest::<V>(&buf, max_image_len)?;
+
+    let mut digest = V::Digest::new();
+    for offset in (0..manifest.image_len).step_by(chunk_buf.len()) {
+        dfu.read(offset, chunk_buf).await?;
+        let len = core::cmp::min((manifest.image_len - offset) as usize, chunk_buf.len());
+        digest.update(&chunk_buf[..len]);
+    }
+
+    check_signature(manifest, digest, verifier, keys)
+}
+
+fn check_manifest<V: Verifier>(buf: &[u8; MANIFEST_SIZE], max_image_len: u32) -> Result<Manifest, VerifyError> {
+    let manifest = Manifest::parse(buf).ok_or(VerifyError::NoManifest)?;
+    if manifest.algorithm != V::ALGORITHM {
+        return Err(VerifyError::UnsupportedAlgorithm);
+    }
+    if manifest.image_len > max_image_len {
+        return Err(VerifyError::ImageTooLong);
+    }
+    Ok(manifest)
+}
+
+fn check_signature<V: Verifier, K: KeyStore + ?Sized>(
+    manifest: Manifest,
+    mut digest: V::Digest,
+    verifier: &mut V,
+    keys: &K,
+) -> Result<Manifest, VerifyError> {
+    let public_key = keys.public_key(manifest.key_id).ok_or(VerifyError::UnknownKey)?;
+    digest.update(manifest.signed_header());
+    let digest = digest.finalize();
+
+    verifier
+        .verify(public_key, digest.as_slice(), manifest.signature())
+        .map_err(|_| VerifyError::InvalidSignature)?;
+    Ok(manifest)
+}
+
+/// Software ed25519 verifier, using `ed25519-dalek`.
+#[cfg(feature = "ed25519-dalek")]
+pub struct Ed25519Dalek;
+
+#[cfg(feature = "ed25519-dalek")]
+impl Verifier for Ed25519Dalek {
+    type Digest = crate::digest_adapters::ed25519_dalek::Sha512;
+    const ALGORITHM: u8 = 1;
+
+    fn verify(&mut self, public_key: &[u8], digest: &[u8], signature: &[u8]) -> Result<(), signature::Error> {
+        use ed25519_dalek::{Signature, Verifier as _, VerifyingKey};
+
+        let public_key = public_key.try_into().map_err(|_| signature::Error::new())?;
+        let public_key = VerifyingKey::from_bytes(public_key)?;
+        let signature = Signature::from_slice(signature)?;
+        public_key.verify(digest, &signature)
+    }
+}
+
+/// Software ed25519 verifier, using `salty`.
+#[cfg(feature = "ed25519-salty")]
+pub struct Ed25519Salty;
+
+#[cfg(feature = "ed25519-salty")]
+impl Verifier for Ed25519Salty {
+    type Digest = crate::digest_adapters::salty::Sha512;
+    const ALGORITHM: u8 = 1;
+
+    fn verify(&mut self, public_key: &[u8], digest: &[u8], signature: &[u8]) -> Result<(), signature::Error> {
+        use salty::{PublicKey, Signature};
+
+        let public_key: &[u8; 32] = public_key.try_into().map_err(|_| signature::Error::new())?;
+        let signature: &[u8; 64] = signature.try_into().map_err(|_| signature::Error::new())?;
+        let public_key = PublicKey::try_from(public_key).map_err(|_| signature::Error::new())?;
+        let signature = Signature::try_from(signature).map_err(|_| signature::Error::new())?;
+        public_key
+            .verify(digest, &signature)
+            .map_err(|_| signature::Error::new())
+    }
+}
+
+/// Software ECDSA P-256 verifier, using `p256`.
+///
+/// Public keys are SEC1 encoded, compressed or not. Signatures are the 64 bytes of `r` and `s`.
+#[cfg(feature = "ecdsa-p256")]
+pub struct EcdsaP256;
+
+#[cfg(feature = "ecdsa-p256")]
+impl Verifier for EcdsaP256 {
+    type Digest = sha2::Sha256;
+    const ALGORITHM: u8 = 2;
+
+    fn verify(&mut self, public_key: &[u8], digest: &[u8], signature: &[u8]) -> Result<(), signature::Error> {
+        use p256::ecdsa::signature::hazmat::PrehashVerifier;
+        use p256::ecdsa::{Signature, VerifyingKey};
+
+        let public_key = VerifyingKey::from_sec1_bytes(public_key)?;
+        let signature = Signature::from_slice(signature)?;
+        public_key.verify_prehash(digest, &signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+    use super::*;
+    use crate::mem_flash::MemFlash;
+    use crate::test_flash::BlockingTestFlash;
+    use crate::{BootError, BootLoader, BootLoaderConfig, State, SWAP_MAGIC};
+
+    /// "Signs" by using the SHA-1 digest as signature, to test the update flow.
+    struct DigestVerifier;
+
+    impl Verifier for DigestVerifier {
+        type Digest = sha1::Sha1;
+        const ALGORITHM: u8 = 0x80;
+
+        fn verify(&mut self, public_key: &[u8], digest: &[u8], signature: &[u8]) -> Result<(), signature::Error> {
+            if public_key == b"key" && digest == signature {
+                Ok(())
+            } else {
+                Err(signature::Error::new())
+            }
+        }
+    }
+
+    fn sign(image: &[u8], version: u32, key_id: u8) -> Manifest {
+        let unsigned = Manifest::new(image.len() as u32, version, key_id, DigestVerifier::ALGORITHM, &[0; 20]);
+        let mut digest = sha1::Sha1::new();
+        digest.update(image);
+        digest.update(unsigned.signed_header());
+        let signature = digest.finalize();
+        Manifest::new(
+            image.len() as u32,
+            version,
+            key_id,
+            DigestVerifier::ALGORITHM,
+            &signature,
+        )
+    }
+
+    #[test]
+    fn manifest_roundtrip() {
+        let manifest = Manifest::new(1234, 5, 1, 2, &[0xAB; 64]);
+        let bytes = manifest.to_bytes();
+        assert_eq!(&bytes[0..4], b"EBM1");
+        assert!(bytes[80..].iter().all(|&b| b == 0xFF));
+        assert_eq!(Manifest::parse(&bytes), Some(manifest));
+
+        let mut bytes = bytes;
+        bytes[0] = 0xFF;
+        assert_eq!(Manifest::parse(&bytes), None);
+    }
+
+    #[test]
+    fn verified_update_is_swapped() {
+        const UPDATE: [u8; 5000] = [0xAA; 5000];
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<8192, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+
+        let manifest = sign(&UPDATE, 1, 1);
+        flash.dfu().write(0, &UPDATE).unwrap();
+        flash
+            .dfu()
+            .write(12288 - MANIFEST_SIZE as u32, &manifest.to_bytes())
+            .unwrap();
+        flash.state().write(0, &[SWAP_MAGIC; 4]).unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let keys: [&[u8]; 2] = [b"revoked", b"key"];
+        let mut page = [0; 1024];
+        assert_eq!(
+            State::Swap,
+            bootloader
+                .prepare_boot_verified(&mut page, &mut DigestVerifier, &keys)
+                .unwrap()
+        );
+
+        let mut read_buf = [0; 5000];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(UPDATE, read_buf);
+    }
+
+    #[test]
+    fn bad_update_is_cancelled() {
+        const UPDATE: [u8; 4096] = [0xAA; 4096];
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<8192, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+
+        let manifest = sign(&UPDATE, 1, 0);
+        let mut tampered = UPDATE;
+        tampered[100] = 0x55;
+        flash.active().write(0, &[0x11; 4096]).unwrap();
+        flash.dfu().write(0, &tampered).unwrap();
+        flash
+            .dfu()
+            .write(12288 - MANIFEST_SIZE as u32, &manifest.to_bytes())
+            .unwrap();
+        flash.state().write(0, &[SWAP_MAGIC; 4]).unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let keys: [&[u8]; 1] = [b"key"];
+        let mut page = [0; 1024];
+        assert_eq!(
+            Err(BootError::Verify(VerifyError::InvalidSignature)),
+            bootloader.prepare_boot_verified(&mut page, &mut DigestVerifier, &keys)
+        );
+
+        // The active image is untouched, and the update is not retried.
+        let mut read_buf = [0; 4096];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!([0x11; 4096], read_buf);
+        assert_eq!(
+            State::Boot,
+            bootloader
+                .prepare_boot_verified(&mut page, &mut DigestVerifier, &keys)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let mut dfu = MemFlash::<8192, 4096, 4>::default();
+        let manifest = sign(&[0xAA; 64], 1, 3);
+        dfu.write(0, &[0xAA; 64]).unwrap();
+        dfu.write(8192 - MANIFEST_SIZE as u32, &manifest.to_bytes()).unwrap();
+
+        let keys: [&[u8]; 1] = [b"key"];
+        let mut chunk_buf = [0; 32];
+        assert_eq!(
+            Err(VerifyError::UnknownKey),
+            verify_update_blocking(&mut dfu, 4096, &mut DigestVerifier, &keys, &mut chunk_buf)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ed25519-dalek")]
+    fn can_verify_ed25519_manifest() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use rand::rngs::OsRng;
+
+        let keypair = SigningKey::generate(&mut OsRng {});
+        let public_key = keypair.verifying_key().to_bytes();
+        let firmware: &[u8] = b"This are bytes that would otherwise be firmware bytes for DFU.";
+
+        let mut manifest = Manifest::new(firmware.len() as u32, 7, 0, Ed25519Dalek::ALGORITHM, &[0; 64]);
+        let mut digest = <Ed25519Dalek as Verifier>::Digest::new();
+        digest.update(firmware);
+        digest.update(manifest.signed_header());
+        let signature = keypair.sign(digest.finalize().as_slice());
+        manifest = Manifest::new(manifest.image_len, 7, 0, Ed25519Dalek::ALGORITHM, &signature.to_bytes());
+
+        let mut dfu = MemFlash::<8192, 4096, 4>::default();
+        let mut image = [0xFF; 64];
+        image[..firmware.len()].copy_from_slice(firmware);
+        dfu.write(0, &image).unwrap();
+        dfu.write(8192 - MANIFEST_SIZE as u32, &manifest.to_bytes()).unwrap();
+
+        let keys: [&[u8]; 1] = [&public_key];
+        let mut chunk_buf = [0; 4];
+        assert_eq!(
+            Ok(manifest),
+            verify_update_blocking(&mut dfu, 4096, &mut Ed25519Dalek, &keys, &mut chunk_buf)
+        );
+    }
+}
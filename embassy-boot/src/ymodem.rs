@@ -0,0 +1,397 @@
+use embedded_hal::delay::DelayNs;
+use embedded_io::{Error as _, ErrorKind, Read, ReadReady, Write};
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+
+/// Time to wait for a packet from the sender.
+const PACKET_TIMEOUT_MS: u32 = 10_000;
+/// Time to wait between bytes of a packet.
+const BYTE_TIMEOUT_MS: u32 = 1_000;
+/// Consecutive bad packets after which the transfer is cancelled.
+const MAX_ERRORS: u32 = 10;
+
+/// Errors returned when receiving an image over YMODEM.
+#[derive(PartialEq, Eq, Debug)]
+pub enum RecoveryError {
+    /// Error from the serial port.
+    Serial(ErrorKind),
+    /// Error from flash.
+    Flash(NorFlashErrorKind),
+    /// The sender didn't start the transfer in time, or stopped answering.
+    Timeout,
+    /// The transfer was cancelled by the sender, or had no file.
+    Cancelled,
+    /// The image is bigger than the active partition.
+    TooLarge,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for RecoveryError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            RecoveryError::Serial(_) => defmt::write!(fmt, "RecoveryError::Serial(_)"),
+            RecoveryError::Flash(_) => defmt::write!(fmt, "RecoveryError::Flash(_)"),
+            RecoveryError::Timeout => defmt::write!(fmt, "RecoveryError::Timeout"),
+            RecoveryError::Cancelled => defmt::write!(fmt, "RecoveryError::Cancelled"),
+            RecoveryError::TooLarge => defmt::write!(fmt, "RecoveryError::TooLarge"),
+        }
+    }
+}
+
+impl<E> From<E> for RecoveryError
+where
+    E: NorFlashError,
+{
+    fn from(error: E) -> Self {
+        RecoveryError::Flash(error.kind())
+    }
+}
+
+enum Packet {
+    Data { number: u8, len: usize },
+    Eot,
+    Cancel,
+}
+
+struct Receiver<'a, S, D> {
+    serial: &'a mut S,
+    delay: &'a mut D,
+}
+
+impl<'a, S: Read + ReadReady + Write, D: DelayNs> Receiver<'a, S, D> {
+    fn send(&mut self, byte: u8) -> Result<(), RecoveryError> {
+        self.serial
+            .write_all(&[byte])
+            .and_then(|_| self.serial.flush())
+            .map_err(|e| RecoveryError::Serial(e.kind()))
+    }
+
+    fn cancel(&mut self) -> Result<(), RecoveryError> {
+        self.send(CAN)?;
+        self.send(CAN)
+    }
+
+    /// Fill `buf`, returning `false` if no byte is received for `timeout_ms`.
+    fn read_exact(&mut self, buf: &mut [u8], timeout_ms: u32) -> Result<bool, RecoveryError> {
+        let mut pos = 0;
+        let mut idle_us = 0;
+        while pos < buf.len() {
+            if self.serial.read_ready().map_err(|e| RecoveryError::Serial(e.kind()))? {
+                pos += self
+                    .serial
+                    .read(&mut buf[pos..])
+                    .map_err(|e| RecoveryError::Serial(e.kind()))?;
+                idle_us = 0;
+            } else if idle_us >= timeout_ms * 1000 {
+                return Ok(false);
+            } else {
+                self.delay.delay_us(100);
+                idle_us += 100;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Discard input until the line is idle.
+    fn purge(&mut self) -> Result<(), RecoveryError> {
+        let mut byte = [0];
+        while self.read_exact(&mut byte, 100)? {}
+        Ok(())
+    }
+
+    /// Receive a packet, returning `None` on timeout or if the packet is corrupted.
+    fn read_packet(&mut self, buf: &mut [u8], timeout_ms: u32) -> Result<Option<Packet>, RecoveryError> {
+        let mut start = [0];
+        if !self.read_exact(&mut start, timeout_ms)? {
+            return Ok(None);
+        }
+        let len = match start[0] {
+            SOH => 128,
+            STX => 1024,
+            EOT => return Ok(Some(Packet::Eot)),
+            CAN => return Ok(Some(Packet::Cancel)),
+            _ => {
+                self.purge()?;
+                return Ok(None);
+            }
+        };
+
+        let mut number = [0; 2];
+        let mut crc = [0; 2];
+        if !self.read_exact(&mut number, BYTE_TIMEOUT_MS)?
+            || !self.read_exact(&mut buf[..len], BYTE_TIMEOUT_MS)?
+            || !self.read_exact(&mut crc, BYTE_TIMEOUT_MS)?
+        {
+            return Ok(None);
+        }
+        if number[0] != !number[1] || crc16(&buf[..len]) != u16::from_be_bytes(crc) {
+            self.purge()?;
+            return Ok(None);
+        }
+        Ok(Some(Packet::Data { number: number[0], len }))
+    }
+}
+
+/// Receive a file over YMODEM, writing it to `flash`.
+///
+/// Returns the length of the file. `buf` is used to receive packets, it must be at least 1024
+/// bytes long and satisfy the alignment requirements of `flash`.
+pub(crate) fn receive<S: Read + ReadReady + Write, D: DelayNs, F: NorFlash>(
+    serial: &mut S,
+    delay: &mut D,
+    flash: &mut F,
+    max_len: usize,
+    buf: &mut [u8],
+    start_timeout_ms: u32,
+) -> Result<usize, RecoveryError> {
+    assert!(buf.len() >= 1024);
+    assert_eq!(0, 128 % F::WRITE_SIZE);
+    let mut receiver = Receiver { serial, delay };
+
+    // Request the file header packet until the sender starts.
+    let mut waited_ms = 0;
+    let len = loop {
+        receiver.send(CRC_MODE)?;
+        match receiver.read_packet(buf, 1000)? {
+            Some(Packet::Data { number: 0, len }) => break len,
+            Some(Packet::Cancel) => return Err(RecoveryError::Cancelled),
+            _ if waited_ms >= start_timeout_ms => return Err(RecoveryError::Timeout),
+            _ => waited_ms += 1000,
+        }
+    };
+
+    // The header holds the file name and its size in decimal, an empty name ends the batch.
+    if buf[0] == 0 {
+        receiver.send(ACK)?;
+        return Err(RecoveryError::Cancelled);
+    }
+    let header = &buf[..len];
+    let name_end = header.iter().position(|&b| b == 0).unwrap_or(len);
+    let size = parse_size(&header[(name_end + 1).min(len)..]);
+    if size.is_some_and(|size| size > max_len) {
+        receiver.cancel()?;
+        return Err(RecoveryError::TooLarge);
+    }
+    receiver.send(ACK)?;
+    receiver.send(CRC_MODE)?;
+
+    let mut expected = 1u8;
+    let mut offset = 0;
+    let mut erased_to = 0;
+    let mut errors = 0;
+    loop {
+        match receiver.read_packet(buf, PACKET_TIMEOUT_MS)? {
+            Some(Packet::Data { number, len }) if number == expected => {
+                if offset + len > flash.capacity() || size.is_none() && offset >= max_len {
+                    receiver.cancel()?;
+                    return Err(RecoveryError::TooLarge);
+                }
+                while erased_to < offset + len {
+                    flash.erase(erased_to as u32, (erased_to + F::ERASE_SIZE) as u32)?;
+                    erased_to += F::ERASE_SIZE;
+                }
+                flash.write(offset as u32, &buf[..len])?;
+
+                offset += len;
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                receiver.send(ACK)?;
+            }
+            // The sender didn't get our ACK, and sent the packet again.
+            Some(Packet::Data { number, .. }) if number == expected.wrapping_sub(1) => receiver.send(ACK)?,
+            Some(Packet::Eot) => {
+                // Confirm the end of the file, the sender sends EOT again after a NAK.
+                receiver.send(NAK)?;
+                if let Some(Packet::Eot) = receiver.read_packet(buf, BYTE_TIMEOUT_MS)? {
+                    receiver.send(ACK)?;
+                }
+                break;
+            }
+            Some(Packet::Cancel) => return Err(RecoveryError::Cancelled),
+            _ => {
+                errors += 1;
+                if errors > MAX_ERRORS {
+                    receiver.cancel()?;
+                    return Err(RecoveryError::Timeout);
+                }
+                receiver.send(NAK)?;
+            }
+        }
+    }
+
+    // End the batch, acknowledging the empty header packet.
+    receiver.send(CRC_MODE)?;
+    if let Some(Packet::Data { number: 0, .. }) = receiver.read_packet(buf, BYTE_TIMEOUT_MS)? {
+        receiver.send(ACK)?;
+    }
+
+    Ok(size.map_or(offset, |size| size.min(offset)))
+}
+
+fn parse_size(field: &[u8]) -> Option<usize> {
+    let digits = field.iter().take_while(|b| b.is_ascii_digit());
+    let mut size: usize = 0;
+    let mut count = 0;
+    for &digit in digits {
+        size = size.checked_mul(10)?.checked_add((digit - b'0') as usize)?;
+        count += 1;
+    }
+    (count > 0).then_some(size)
+}
+
+/// CRC-16/XMODEM.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use embedded_storage::nor_flash::ReadNorFlash;
+
+    use super::*;
+    use crate::mem_flash::MemFlash;
+
+    /// Serial port replaying the packets of a sender, without waiting for the answers.
+    struct ScriptedSerial {
+        input: Vec<u8>,
+        output: Vec<u8>,
+    }
+
+    impl embedded_io::ErrorType for ScriptedSerial {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for ScriptedSerial {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl ReadReady for ScriptedSerial {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.input.is_empty())
+        }
+    }
+
+    impl Write for ScriptedSerial {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    fn packet(input: &mut Vec<u8>, number: u8, data: &[u8]) {
+        let len = if data.len() > 128 { 1024 } else { 128 };
+        let mut payload = [0x1A; 1024];
+        payload[..data.len()].copy_from_slice(data);
+        if number == 0 {
+            payload[data.len()..].fill(0);
+        }
+        input.push(if len == 1024 { STX } else { SOH });
+        input.extend_from_slice(&[number, !number]);
+        input.extend_from_slice(&payload[..len]);
+        input.extend_from_slice(&crc16(&payload[..len]).to_be_bytes());
+    }
+
+    #[test]
+    fn crc_matches_xmodem() {
+        assert_eq!(0x31C3, crc16(b"123456789"));
+    }
+
+    #[test]
+    fn can_receive_file() {
+        let image: Vec<u8> = (0..1100u32).map(|i| i as u8).collect();
+        let mut input = Vec::new();
+        packet(&mut input, 0, b"app.bin\x001100 0");
+        packet(&mut input, 1, &image[..1024]);
+        // The sender missed our ACK, and sends the packet again.
+        packet(&mut input, 1, &image[..1024]);
+        packet(&mut input, 2, &image[1024..]);
+        input.extend_from_slice(&[EOT, EOT]);
+        packet(&mut input, 0, b"");
+
+        let mut serial = ScriptedSerial {
+            input,
+            output: Vec::new(),
+        };
+        let mut flash = MemFlash::<8192, 4096, 4>::default();
+        let mut buf = [0; 1024];
+        let len = receive(&mut serial, &mut NoDelay, &mut flash, 4096, &mut buf, 1000).unwrap();
+        assert_eq!(1100, len);
+
+        let mut read_buf = [0; 1100];
+        flash.read(0, &mut read_buf).unwrap();
+        assert_eq!(&image[..], &read_buf[..]);
+        assert_eq!(
+            &[CRC_MODE, ACK, CRC_MODE, ACK, ACK, ACK, NAK, ACK, CRC_MODE, ACK],
+            &serial.output[..]
+        );
+    }
+
+    #[test]
+    fn rejects_too_large_file() {
+        let mut input = Vec::new();
+        packet(&mut input, 0, b"app.bin\x0010000");
+
+        let mut serial = ScriptedSerial {
+            input,
+            output: Vec::new(),
+        };
+        let mut flash = MemFlash::<8192, 4096, 4>::default();
+        let mut buf = [0; 1024];
+        assert_eq!(
+            Err(RecoveryError::TooLarge),
+            receive(&mut serial, &mut NoDelay, &mut flash, 4096, &mut buf, 1000)
+        );
+        assert_eq!(&[CRC_MODE, CAN, CAN], &serial.output[..]);
+    }
+
+    #[test]
+    fn times_out_without_sender() {
+        let mut serial = ScriptedSerial {
+            input: Vec::new(),
+            output: Vec::new(),
+        };
+        let mut flash = MemFlash::<8192, 4096, 4>::default();
+        let mut buf = [0; 1024];
+        assert_eq!(
+            Err(RecoveryError::Timeout),
+            receive(&mut serial, &mut NoDelay, &mut flash, 4096, &mut buf, 3000)
+        );
+        assert_eq!(&[CRC_MODE; 4], &serial.output[..]);
+    }
+}
@@ -0,0 +1,355 @@
+use core::cell::Cell;
+use core::future::Future;
+
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embedded_hal_1::{i2c, spi};
+
+use crate::shared_bus::asynch::arbiter::Deadline;
+
+/// Error returned by [`BlockingAdapter`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlockingAdapterError<E> {
+    /// The operation of the wrapped driver failed.
+    Inner(E),
+    /// The operation didn't complete before the timeout.
+    Timeout,
+    /// The operation was started while another operation of the same adapter was in progress.
+    Reentrant,
+}
+
+impl<E: i2c::Error> i2c::Error for BlockingAdapterError<E> {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            Self::Inner(e) => e.kind(),
+            Self::Timeout => i2c::ErrorKind::Other,
+            Self::Reentrant => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl<E: spi::Error> spi::Error for BlockingAdapterError<E> {
+    fn kind(&self) -> spi::ErrorKind {
+        match self {
+            Self::Inner(e) => e.kind(),
+            Self::Timeout => spi::ErrorKind::Other,
+            Self::Reentrant => spi::ErrorKind::Other,
+        }
+    }
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for BlockingAdapterError<E> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Inner(e) => e.kind(),
+            Self::Timeout => embedded_io::ErrorKind::TimedOut,
+            Self::Reentrant => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+/// Wrapper that implements blocking traits using async implementations.
+///
+/// This allows using third-party drivers that only support the blocking embedded-hal traits with
+/// async peripheral drivers. Each operation is run to completion with
+/// [`block_on`](embassy_futures::block_on), busy-polling the async driver: the executor running the
+/// calling task, and the tasks sharing it, are blocked meanwhile, so the async driver must not
+/// depend on them to make progress. Interrupt-driven peripheral drivers are fine.
+///
+/// Only one operation of an adapter can be in progress at a time: an operation started meanwhile
+/// on the same adapter fails with [`Reentrant`](BlockingAdapterError::Reentrant) instead of
+/// spinning on an operation that may never complete. Operations of different adapters, for
+/// example on different buses, are independent. With the `time` feature,
+/// [`with_timeout`](Self::with_timeout) bounds the duration of the operations.
+///
+/// BlockingAdapter implements the blocking I2C, SPI bus, SPI device and `embedded-io` traits
+/// implemented asynchronously by the wrapped driver.
+pub struct BlockingAdapter<T> {
+    wrapped: T,
+    /// Whether an operation is in progress.
+    busy: CriticalSectionMutex<Cell<bool>>,
+    #[cfg(feature = "time")]
+    timeout: Option<embassy_time::Duration>,
+}
+
+impl<T> BlockingAdapter<T> {
+    /// Create a new instance of a wrapper for a given async driver.
+    pub fn new(wrapped: T) -> Self {
+        Self {
+            wrapped,
+            busy: CriticalSectionMutex::new(Cell::new(false)),
+            #[cfg(feature = "time")]
+            timeout: None,
+        }
+    }
+
+    /// Fail the operations that don't complete within `timeout`.
+    #[cfg(feature = "time")]
+    pub fn with_timeout(mut self, timeout: embassy_time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Consumes this wrapper, returning the async driver.
+    pub fn into_inner(self) -> T {
+        self.wrapped
+    }
+
+    fn deadline(&self) -> Deadline {
+        #[cfg(feature = "time")]
+        return Deadline::after(self.timeout);
+        #[cfg(not(feature = "time"))]
+        Deadline::default()
+    }
+}
+
+/// Marks an operation of an adapter in progress, until dropped.
+struct BlockingGuard<'a> {
+    busy: &'a CriticalSectionMutex<Cell<bool>>,
+}
+
+impl<'a> BlockingGuard<'a> {
+    fn enter(busy: &'a CriticalSectionMutex<Cell<bool>>) -> Option<Self> {
+        busy.lock(|b| (!b.replace(true)).then_some(BlockingGuard { busy }))
+    }
+}
+
+impl Drop for BlockingGuard<'_> {
+    fn drop(&mut self) {
+        self.busy.lock(|b| b.set(false));
+    }
+}
+
+fn run<R, E>(
+    busy: &CriticalSectionMutex<Cell<bool>>,
+    deadline: Deadline,
+    fut: impl Future<Output = Result<R, E>>,
+) -> Result<R, BlockingAdapterError<E>> {
+    let _guard = BlockingGuard::enter(busy).ok_or(BlockingAdapterError::Reentrant)?;
+    match block_on(deadline.run(fut)) {
+        Ok(result) => result.map_err(BlockingAdapterError::Inner),
+        Err(()) => Err(BlockingAdapterError::Timeout),
+    }
+}
+
+//
+// I2C implementations
+//
+impl<T> i2c::ErrorType for BlockingAdapter<T>
+where
+    T: i2c::ErrorType,
+{
+    type Error = BlockingAdapterError<T::Error>;
+}
+
+impl<T> i2c::I2c for BlockingAdapter<T>
+where
+    T: embedded_hal_async::i2c::I2c,
+{
+    fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.read(address, read))
+    }
+
+    fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.write(address, write))
+    }
+
+    fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        run(
+            &self.busy,
+            self.deadline(),
+            self.wrapped.write_read(address, write, read),
+        )
+    }
+
+    fn transaction(&mut self, address: u8, operations: &mut [i2c::Operation<'_>]) -> Result<(), Self::Error> {
+        run(
+            &self.busy,
+            self.deadline(),
+            self.wrapped.transaction(address, operations),
+        )
+    }
+}
+
+//
+// SPI implementations
+//
+impl<T> spi::ErrorType for BlockingAdapter<T>
+where
+    T: spi::ErrorType,
+{
+    type Error = BlockingAdapterError<T::Error>;
+}
+
+impl<T, Word: 'static + Copy> spi::SpiBus<Word> for BlockingAdapter<T>
+where
+    T: embedded_hal_async::spi::SpiBus<Word>,
+{
+    fn read(&mut self, words: &mut [Word]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.read(words))
+    }
+
+    fn write(&mut self, words: &[Word]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.write(words))
+    }
+
+    fn transfer(&mut self, read: &mut [Word], write: &[Word]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.transfer(read, write))
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [Word]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.transfer_in_place(words))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.flush())
+    }
+}
+
+impl<T, Word: 'static + Copy> spi::SpiDevice<Word> for BlockingAdapter<T>
+where
+    T: embedded_hal_async::spi::SpiDevice<Word>,
+{
+    fn transaction(&mut self, operations: &mut [spi::Operation<'_, Word>]) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.transaction(operations))
+    }
+}
+
+//
+// UART implementations
+//
+impl<T> embedded_io::ErrorType for BlockingAdapter<T>
+where
+    T: embedded_io::ErrorType,
+{
+    type Error = BlockingAdapterError<T::Error>;
+}
+
+impl<T> embedded_io::Read for BlockingAdapter<T>
+where
+    T: embedded_io_async::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.read(buf))
+    }
+}
+
+impl<T> embedded_io::Write for BlockingAdapter<T>
+where
+    T: embedded_io_async::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.write(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        run(&self.busy, self.deadline(), self.wrapped.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::yield_now;
+    use embedded_hal_1::i2c::I2c as _;
+    use embedded_io::{Read as _, Write as _};
+
+    use super::*;
+
+    /// Async I2C bus taking a few polls per operation, and failing on address 0.
+    struct YieldingI2c {
+        last_address: Option<u8>,
+    }
+
+    impl i2c::ErrorType for YieldingI2c {
+        type Error = i2c::ErrorKind;
+    }
+
+    impl embedded_hal_async::i2c::I2c for YieldingI2c {
+        async fn transaction(&mut self, address: u8, operations: &mut [i2c::Operation<'_>]) -> Result<(), Self::Error> {
+            for _ in 0..3 {
+                yield_now().await;
+            }
+            if address == 0 {
+                return Err(i2c::ErrorKind::Other);
+            }
+            for operation in operations {
+                if let i2c::Operation::Read(read) = operation {
+                    read.fill(address);
+                }
+            }
+            self.last_address = Some(address);
+            Ok(())
+        }
+    }
+
+    /// Async UART whose writes run an operation of another adapter.
+    struct NestingUart;
+
+    impl embedded_io::ErrorType for NestingUart {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl embedded_io_async::Write for NestingUart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let mut i2c = BlockingAdapter::new(YieldingI2c { last_address: None });
+            match i2c.write(0x42, buf) {
+                Ok(()) => Ok(buf.len()),
+                Err(_) => Err(embedded_io::ErrorKind::Other),
+            }
+        }
+    }
+
+    /// Async UART whose reads never complete, while time passes.
+    struct StalledUart;
+
+    impl embedded_io::ErrorType for StalledUart {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl embedded_io_async::Read for StalledUart {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            loop {
+                embassy_time::MockDriver::get().advance(embassy_time::Duration::from_millis(1));
+                yield_now().await;
+            }
+        }
+    }
+
+    #[test]
+    fn runs_operations_to_completion() {
+        let mut i2c = BlockingAdapter::new(YieldingI2c { last_address: None });
+        let mut read = [0; 2];
+        i2c.write_read(0x42, &[1], &mut read).unwrap();
+        assert_eq!([0x42; 2], read);
+        assert_eq!(
+            Err(BlockingAdapterError::Inner(i2c::ErrorKind::Other)),
+            i2c.write(0, &[1])
+        );
+        assert_eq!(Some(0x42), i2c.into_inner().last_address);
+
+        // Operations of different adapters can be nested.
+        let mut uart = BlockingAdapter::new(NestingUart);
+        assert_eq!(Ok(3), uart.write(&[1, 2, 3]));
+
+        let mut uart = BlockingAdapter::new(StalledUart).with_timeout(embassy_time::Duration::from_millis(10));
+        assert_eq!(Err(BlockingAdapterError::Timeout), uart.read(&mut [0; 4]));
+    }
+
+    #[test]
+    fn reentrant_operation() {
+        let busy = CriticalSectionMutex::new(Cell::new(false));
+        let other = CriticalSectionMutex::new(Cell::new(false));
+        let guard = BlockingGuard::enter(&busy).unwrap();
+        assert!(BlockingGuard::enter(&other).is_some());
+        assert_eq!(
+            Err(BlockingAdapterError::<()>::Reentrant),
+            run(&busy, Deadline::default(), async { Ok(()) })
+        );
+
+        // The flag is released once the operation completes.
+        drop(guard);
+        assert_eq!(Ok(()), run::<_, ()>(&busy, Deadline::default(), async { Ok(()) }));
+        assert!(BlockingGuard::enter(&busy).is_some());
+    }
+}
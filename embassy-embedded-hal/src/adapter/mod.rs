@@ -1,7 +1,9 @@
 //! Adapters between embedded-hal traits.
 
+mod blocking_adapter;
 mod blocking_async;
 mod yielding_async;
 
+pub use blocking_adapter::{BlockingAdapter, BlockingAdapterError};
 pub use blocking_async::BlockingAsync;
 pub use yielding_async::YieldingAsync;
@@ -0,0 +1,615 @@
+//! Flash-backed key-value store
+//!
+//! [`KvStore`] keeps typed values, such as settings, in a journal on a flash of at least two erase
+//! sectors, typically a [`Partition`](super::partition::Partition) of the internal or an external
+//! flash. Each change is appended to the active sector, and is only visible once committed, so a
+//! power loss never leaves a value half written. Several changes can be committed atomically with
+//! a [`Transaction`].
+//!
+//! When the active sector is full, the live values are copied to the next sector, which becomes
+//! active once the copy is complete. The sectors are used in turn, spreading the erases over them.
+//! The store is meant for small amounts of data: it keeps no index in RAM, and looks values up by
+//! scanning the active sector.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use embassy_embedded_hal::flash::kv_store::KvStore;
+//!
+//! const BRIGHTNESS: u16 = 1;
+//!
+//! let mut buf = [0; 64];
+//! let mut store = KvStore::new(partition, &mut buf);
+//! let brightness: u8 = store.get(BRIGHTNESS).await?.unwrap_or(100);
+//! store.set(BRIGHTNESS, &(brightness / 2)).await?;
+//! ```
+
+use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind};
+use embedded_storage_async::nor_flash::NorFlash;
+
+const SECTOR_MAGIC: [u8; 4] = [b'K', b'V', 1, 0];
+const ERASE_VALUE: u8 = 0xFF;
+const HEADER_LEN: usize = 12;
+
+const KIND_DATA: u8 = 1;
+const KIND_TOMBSTONE: u8 = 2;
+const KIND_COMMIT: u8 = 3;
+const KIND_COMMITTED_DATA: u8 = 4;
+
+/// Key-value store error
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    /// The live values don't fit in a sector
+    Full,
+    /// The value doesn't fit in the buffer of the store
+    BufferTooSmall,
+    /// The stored value can't be deserialized as the requested type
+    InvalidValue,
+    /// Underlying flash error
+    Flash(T),
+}
+
+impl<T: NorFlashError> NorFlashError for Error<T> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::Flash(f) => f.kind(),
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// A value that can be kept in a [`KvStore`]
+pub trait Value: Sized {
+    /// Serialize the value into `buf`, returning its length, or `None` if `buf` is too small.
+    fn serialize(&self, buf: &mut [u8]) -> Option<usize>;
+
+    /// Deserialize a value, returning `None` if `buf` doesn't hold a valid value.
+    fn deserialize(buf: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_value_for_num {
+    ($($t:ty),*) => {
+        $(
+            impl Value for $t {
+                fn serialize(&self, buf: &mut [u8]) -> Option<usize> {
+                    let bytes = self.to_le_bytes();
+                    buf.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+                    Some(bytes.len())
+                }
+
+                fn deserialize(buf: &[u8]) -> Option<Self> {
+                    Some(Self::from_le_bytes(buf.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_value_for_num!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Value for bool {
+    fn serialize(&self, buf: &mut [u8]) -> Option<usize> {
+        (*self as u8).serialize(buf)
+    }
+
+    fn deserialize(buf: &[u8]) -> Option<Self> {
+        match u8::deserialize(buf)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl<const N: usize> Value for [u8; N] {
+    fn serialize(&self, buf: &mut [u8]) -> Option<usize> {
+        buf.get_mut(..N)?.copy_from_slice(self);
+        Some(N)
+    }
+
+    fn deserialize(buf: &[u8]) -> Option<Self> {
+        buf.try_into().ok()
+    }
+}
+
+/// An entry of the journal.
+#[derive(Clone, Copy)]
+struct Entry {
+    offset: usize,
+    key: u16,
+    len: usize,
+    batch: u16,
+    kind: u8,
+}
+
+/// FNV-1a hash, checking entries for partial writes.
+fn check(header: &[u8], data: &[u8]) -> u32 {
+    header
+        .iter()
+        .chain(data)
+        .fold(0x811c_9dc5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+const fn round_up(len: usize, word: usize) -> usize {
+    len.div_ceil(word) * word
+}
+
+/// A key-value store in flash
+///
+/// See the [module documentation](self). The journal is read from the flash on the first
+/// operation, or with [`mount`](Self::mount), and formatted if no valid sector is found.
+pub struct KvStore<'a, F: NorFlash> {
+    flash: F,
+    buf: &'a mut [u8],
+    mounted: bool,
+    active: usize,
+    generation: u32,
+    write_offset: usize,
+    next_batch: u16,
+    dirty: bool,
+}
+
+impl<'a, F: NorFlash> KvStore<'a, F> {
+    const WORD: usize = {
+        let size = if F::WRITE_SIZE > F::READ_SIZE {
+            F::WRITE_SIZE
+        } else {
+            F::READ_SIZE
+        };
+        if size > 4 {
+            size
+        } else {
+            4
+        }
+    };
+    const SECTOR_HEADER: usize = round_up(HEADER_LEN, Self::WORD);
+
+    /// Create a store over the whole `flash`, which must be at least two erase sectors long.
+    ///
+    /// `buf` holds the entries while they're read or written, it must be a multiple of
+    /// `max(4, WRITE_SIZE, READ_SIZE)` bytes long, with room for 12 bytes of header and the largest
+    /// value. It must also satisfy the alignment requirements of `flash`.
+    pub fn new(flash: F, buf: &'a mut [u8]) -> Self {
+        assert!(flash.capacity() >= 2 * F::ERASE_SIZE);
+        assert!(buf.len() >= Self::SECTOR_HEADER && buf.len() % Self::WORD == 0);
+        Self {
+            flash,
+            buf,
+            mounted: false,
+            active: 0,
+            generation: 0,
+            write_offset: 0,
+            next_batch: 0,
+            dirty: false,
+        }
+    }
+
+    /// Read the journal from the flash, formatting it if no valid sector is found
+    pub async fn mount(&mut self) -> Result<(), Error<F::Error>> {
+        let mut latest = None;
+        for sector in 0..self.sector_count() {
+            if let Some(generation) = self.read_sector_header(sector).await? {
+                match latest {
+                    Some((_, latest)) if latest >= generation => {}
+                    _ => latest = Some((sector, generation)),
+                }
+            }
+        }
+
+        let Some((active, generation)) = latest else {
+            return self.format().await;
+        };
+        self.active = active;
+        self.generation = generation;
+
+        // Find the end of the journal.
+        let mut offset = Self::SECTOR_HEADER;
+        let mut last_batch = None;
+        while let Some(entry) = self.read_entry(active, offset).await? {
+            if entry.kind != KIND_COMMITTED_DATA {
+                last_batch = Some(entry.batch);
+            }
+            offset += self.entry_size(entry.len);
+        }
+        // A partially written entry must be compacted away before writing again.
+        self.dirty = offset + Self::SECTOR_HEADER <= F::ERASE_SIZE && !self.is_erased(active, offset).await?;
+        self.write_offset = offset;
+        self.next_batch = last_batch.map_or(0, |batch| batch.wrapping_add(1));
+        self.mounted = true;
+        Ok(())
+    }
+
+    /// Erase all the values
+    pub async fn format(&mut self) -> Result<(), Error<F::Error>> {
+        let sectors = self.sector_count() as u32;
+        self.flash
+            .erase(0, sectors * F::ERASE_SIZE as u32)
+            .await
+            .map_err(Error::Flash)?;
+        self.write_sector_header(0, 1).await?;
+        self.active = 0;
+        self.generation = 1;
+        self.write_offset = Self::SECTOR_HEADER;
+        self.next_batch = 0;
+        self.dirty = false;
+        self.mounted = true;
+        Ok(())
+    }
+
+    /// Get the committed value of a key
+    pub async fn get<V: Value>(&mut self, key: u16) -> Result<Option<V>, Error<F::Error>> {
+        self.ensure_mounted().await?;
+        match self.find(self.active, key).await? {
+            Some(entry) if entry.kind != KIND_TOMBSTONE => {
+                self.read_entry(self.active, entry.offset).await?;
+                let data = &self.buf[HEADER_LEN..HEADER_LEN + entry.len];
+                V::deserialize(data).map(Some).ok_or(Error::InvalidValue)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Set and commit the value of a key
+    pub async fn set<V: Value>(&mut self, key: u16, value: &V) -> Result<(), Error<F::Error>> {
+        let mut transaction = self.transaction().await?;
+        transaction.set(key, value).await?;
+        transaction.commit().await
+    }
+
+    /// Remove a key, and commit
+    pub async fn remove(&mut self, key: u16) -> Result<(), Error<F::Error>> {
+        let mut transaction = self.transaction().await?;
+        transaction.remove(key).await?;
+        transaction.commit().await
+    }
+
+    /// Start a transaction, to commit several changes atomically
+    pub async fn transaction(&mut self) -> Result<Transaction<'_, 'a, F>, Error<F::Error>> {
+        self.ensure_mounted().await?;
+        let batch = self.next_batch;
+        self.next_batch = batch.wrapping_add(1);
+        Ok(Transaction { store: self, batch })
+    }
+
+    async fn ensure_mounted(&mut self) -> Result<(), Error<F::Error>> {
+        if !self.mounted {
+            self.mount().await?;
+        }
+        Ok(())
+    }
+
+    fn sector_count(&self) -> usize {
+        self.flash.capacity() / F::ERASE_SIZE
+    }
+
+    fn entry_size(&self, len: usize) -> usize {
+        round_up(HEADER_LEN + len, Self::WORD)
+    }
+
+    /// Whether an entry of `len` bytes and a commit record fit in the active sector.
+    fn fits(&self, len: usize) -> bool {
+        !self.dirty && self.write_offset + self.entry_size(len) + self.entry_size(0) <= F::ERASE_SIZE
+    }
+
+    async fn read_sector_header(&mut self, sector: usize) -> Result<Option<u32>, Error<F::Error>> {
+        let header = &mut self.buf[..Self::SECTOR_HEADER];
+        self.flash
+            .read((sector * F::ERASE_SIZE) as u32, header)
+            .await
+            .map_err(Error::Flash)?;
+        let generation = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let valid =
+            header[..4] == SECTOR_MAGIC && header[8..12] == check(&header[..8], &[]).to_le_bytes() && generation != 0;
+        Ok(valid.then_some(generation))
+    }
+
+    async fn write_sector_header(&mut self, sector: usize, generation: u32) -> Result<(), Error<F::Error>> {
+        let header = &mut self.buf[..Self::SECTOR_HEADER];
+        header.fill(ERASE_VALUE);
+        header[..4].copy_from_slice(&SECTOR_MAGIC);
+        header[4..8].copy_from_slice(&generation.to_le_bytes());
+        let check = check(&header[..8], &[]);
+        header[8..12].copy_from_slice(&check.to_le_bytes());
+        self.flash
+            .write((sector * F::ERASE_SIZE) as u32, header)
+            .await
+            .map_err(Error::Flash)
+    }
+
+    async fn is_erased(&mut self, sector: usize, offset: usize) -> Result<bool, Error<F::Error>> {
+        let word = &mut self.buf[..Self::WORD];
+        self.flash
+            .read((sector * F::ERASE_SIZE + offset) as u32, word)
+            .await
+            .map_err(Error::Flash)?;
+        Ok(word.iter().all(|&b| b == ERASE_VALUE))
+    }
+
+    /// Read the entry at `offset` into the buffer, returning `None` at the end of the journal.
+    async fn read_entry(&mut self, sector: usize, offset: usize) -> Result<Option<Entry>, Error<F::Error>> {
+        if offset + Self::SECTOR_HEADER > F::ERASE_SIZE {
+            return Ok(None);
+        }
+        let base = (sector * F::ERASE_SIZE + offset) as u32;
+        let header = &mut self.buf[..Self::SECTOR_HEADER];
+        self.flash.read(base, header).await.map_err(Error::Flash)?;
+
+        let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let size = self.entry_size(len);
+        if size > self.buf.len() || offset + size > F::ERASE_SIZE {
+            return Ok(None);
+        }
+        self.flash
+            .read(base, &mut self.buf[..size])
+            .await
+            .map_err(Error::Flash)?;
+
+        let buf = &self.buf[..size];
+        let stored_check = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        if buf[7] != 0 || stored_check != check(&buf[..8], &buf[HEADER_LEN..HEADER_LEN + len]) {
+            return Ok(None);
+        }
+        Ok(Some(Entry {
+            offset,
+            key: u16::from_le_bytes([buf[0], buf[1]]),
+            len,
+            batch: u16::from_le_bytes([buf[4], buf[5]]),
+            kind: buf[6],
+        }))
+    }
+
+    /// Append the entry whose data is in the buffer.
+    async fn append(&mut self, kind: u8, key: u16, batch: u16, len: usize) -> Result<(), Error<F::Error>> {
+        let size = self.entry_size(len);
+        let buf = &mut self.buf[..size];
+        buf[0..2].copy_from_slice(&key.to_le_bytes());
+        buf[2..4].copy_from_slice(&(len as u16).to_le_bytes());
+        buf[4..6].copy_from_slice(&batch.to_le_bytes());
+        buf[6] = kind;
+        buf[7] = 0;
+        buf[HEADER_LEN + len..].fill(ERASE_VALUE);
+        let check = check(&buf[..8], &buf[HEADER_LEN..HEADER_LEN + len]);
+        buf[8..12].copy_from_slice(&check.to_le_bytes());
+
+        let offset = (self.active * F::ERASE_SIZE + self.write_offset) as u32;
+        self.write_offset += size;
+        let result = self.flash.write(offset, buf).await.map_err(Error::Flash);
+        // A failed write may leave a partial entry, hiding the entries after it.
+        self.dirty |= result.is_err();
+        result
+    }
+
+    /// Find the latest committed entry of a key.
+    async fn find(&mut self, sector: usize, key: u16) -> Result<Option<Entry>, Error<F::Error>> {
+        let mut found = None;
+        let mut pending = None;
+        let mut pending_batch = None;
+        let mut offset = Self::SECTOR_HEADER;
+        while let Some(entry) = self.read_entry(sector, offset).await? {
+            match entry.kind {
+                KIND_COMMITTED_DATA if entry.key == key => found = Some(entry),
+                KIND_DATA | KIND_TOMBSTONE => {
+                    // The entries of a batch are contiguous, followed by its commit record.
+                    if pending_batch != Some(entry.batch) {
+                        pending_batch = Some(entry.batch);
+                        pending = None;
+                    }
+                    if entry.key == key {
+                        pending = Some(entry);
+                    }
+                }
+                KIND_COMMIT if pending_batch == Some(entry.batch) => {
+                    if let Some(entry) = pending.take() {
+                        found = Some(entry);
+                    }
+                }
+                _ => {}
+            }
+            offset += self.entry_size(entry.len);
+        }
+        Ok(found)
+    }
+
+    /// Copy the live values, and the entries of the open batch, to the next sector.
+    async fn compact(&mut self, open_batch: u16) -> Result<(), Error<F::Error>> {
+        let old = self.active;
+        let new = (old + 1) % self.sector_count();
+        let new_base = (new * F::ERASE_SIZE) as u32;
+        self.flash
+            .erase(new_base, new_base + F::ERASE_SIZE as u32)
+            .await
+            .map_err(Error::Flash)?;
+
+        let mut dst = Self::SECTOR_HEADER;
+        for copy_open_batch in [false, true] {
+            let mut offset = Self::SECTOR_HEADER;
+            while let Some(entry) = self.read_entry(old, offset).await? {
+                let next = offset + self.entry_size(entry.len);
+                let copy = match entry.kind {
+                    KIND_DATA | KIND_TOMBSTONE if copy_open_batch => entry.batch == open_batch,
+                    KIND_DATA | KIND_COMMITTED_DATA if !copy_open_batch => self
+                        .find(old, entry.key)
+                        .await?
+                        .is_some_and(|live| live.offset == offset),
+                    _ => false,
+                };
+                if copy {
+                    self.read_entry(old, offset).await?;
+                    if !copy_open_batch {
+                        // Committed values don't need their commit record anymore.
+                        self.buf[6] = KIND_COMMITTED_DATA;
+                        let check = check(&self.buf[..8], &self.buf[HEADER_LEN..HEADER_LEN + entry.len]);
+                        self.buf[8..12].copy_from_slice(&check.to_le_bytes());
+                    }
+                    let size = self.entry_size(entry.len);
+                    if dst + size > F::ERASE_SIZE {
+                        return Err(Error::Full);
+                    }
+                    self.flash
+                        .write(new_base + dst as u32, &self.buf[..size])
+                        .await
+                        .map_err(Error::Flash)?;
+                    dst += size;
+                }
+                offset = next;
+            }
+        }
+
+        // The new sector becomes active once complete.
+        self.write_sector_header(new, self.generation.wrapping_add(1)).await?;
+        self.active = new;
+        self.generation = self.generation.wrapping_add(1);
+        self.write_offset = dst;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Changes to a [`KvStore`], committed atomically
+///
+/// The changes are not visible until [`commit`](Self::commit) is called, and are discarded if the
+/// transaction is dropped, or if power is lost, before.
+pub struct Transaction<'s, 'a, F: NorFlash> {
+    store: &'s mut KvStore<'a, F>,
+    batch: u16,
+}
+
+impl<'s, 'a, F: NorFlash> Transaction<'s, 'a, F> {
+    /// Set the value of a key
+    pub async fn set<V: Value>(&mut self, key: u16, value: &V) -> Result<(), Error<F::Error>> {
+        let len = self.serialize(value)?;
+        if !self.store.fits(len) {
+            self.store.compact(self.batch).await?;
+            if !self.store.fits(len) {
+                return Err(Error::Full);
+            }
+            // Compacting used the buffer.
+            self.serialize(value)?;
+        }
+        self.store.append(KIND_DATA, key, self.batch, len).await
+    }
+
+    /// Remove a key
+    pub async fn remove(&mut self, key: u16) -> Result<(), Error<F::Error>> {
+        self.append_empty(KIND_TOMBSTONE, key).await
+    }
+
+    /// Commit the changes
+    pub async fn commit(mut self) -> Result<(), Error<F::Error>> {
+        self.append_empty(KIND_COMMIT, 0).await
+    }
+
+    fn serialize<V: Value>(&mut self, value: &V) -> Result<usize, Error<F::Error>> {
+        let len = value
+            .serialize(&mut self.store.buf[HEADER_LEN..])
+            .ok_or(Error::BufferTooSmall)?;
+        if self.store.entry_size(len) > self.store.buf.len() || len > u16::MAX as usize {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(len)
+    }
+
+    async fn append_empty(&mut self, kind: u8, key: u16) -> Result<(), Error<F::Error>> {
+        // Commit records always fit, room for them is kept when appending.
+        if kind != KIND_COMMIT && !self.store.fits(0) || self.store.dirty {
+            self.store.compact(self.batch).await?;
+            if !self.store.fits(0) {
+                return Err(Error::Full);
+            }
+        }
+        self.store.append(kind, key, self.batch, 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::mem_flash::MemFlash;
+
+    type Flash = MemFlash<512, 256, 4>;
+
+    #[futures_test::test]
+    async fn values_survive_remount() {
+        let mut flash = Flash::default();
+        let mut buf = [0; 32];
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        assert_eq!(None, store.get::<u32>(1).await.unwrap());
+        store.set(1, &0x1234_5678u32).await.unwrap();
+        store.set(2, &true).await.unwrap();
+        store.set(3, b"hello").await.unwrap();
+        store.remove(2).await.unwrap();
+        store.set(1, &42u32).await.unwrap();
+
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        assert_eq!(Some(42u32), store.get(1).await.unwrap());
+        assert_eq!(None, store.get::<bool>(2).await.unwrap());
+        assert_eq!(Some(*b"hello"), store.get(3).await.unwrap());
+        assert_eq!(Err(Error::InvalidValue), store.get::<u8>(1).await);
+    }
+
+    #[futures_test::test]
+    async fn uncommitted_changes_are_discarded() {
+        let mut flash = Flash::default();
+        let mut buf = [0; 32];
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        store.set(1, &1u8).await.unwrap();
+
+        {
+            let mut transaction = store.transaction().await.unwrap();
+            transaction.set(1, &2u8).await.unwrap();
+            transaction.set(2, &2u8).await.unwrap();
+        }
+        assert_eq!(Some(1u8), store.get(1).await.unwrap());
+        assert_eq!(None, store.get::<u8>(2).await.unwrap());
+
+        let mut transaction = store.transaction().await.unwrap();
+        transaction.set(1, &3u8).await.unwrap();
+        transaction.set(2, &3u8).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        assert_eq!(Some(3u8), store.get(1).await.unwrap());
+        assert_eq!(Some(3u8), store.get(2).await.unwrap());
+    }
+
+    #[futures_test::test]
+    async fn partially_written_entry_is_ignored() {
+        let mut flash = Flash::default();
+        let mut buf = [0; 32];
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        store.set(1, &1u32).await.unwrap();
+        let end = store.write_offset;
+
+        // Power lost while writing the next value.
+        flash.mem[end..end + 4].copy_from_slice(&[1, 0, 4, 0]);
+
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        assert_eq!(Some(1u32), store.get(1).await.unwrap());
+        store.set(1, &2u32).await.unwrap();
+        assert_eq!(Some(2u32), store.get(1).await.unwrap());
+
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        assert_eq!(Some(2u32), store.get(1).await.unwrap());
+    }
+
+    #[futures_test::test]
+    async fn compaction_keeps_live_values() {
+        let mut flash = Flash::default();
+        let mut buf = [0; 32];
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        store.set(100, &7u64).await.unwrap();
+        for i in 0..100u32 {
+            let mut transaction = store.transaction().await.unwrap();
+            transaction.set(1, &i).await.unwrap();
+            transaction.set(2, &(i * 2)).await.unwrap();
+            transaction.commit().await.unwrap();
+        }
+        assert!(store.generation > 2);
+
+        let mut store = KvStore::new(&mut flash, &mut buf);
+        assert_eq!(Some(99u32), store.get(1).await.unwrap());
+        assert_eq!(Some(198u32), store.get(2).await.unwrap());
+        assert_eq!(Some(7u64), store.get(100).await.unwrap());
+    }
+}
@@ -1,8 +1,10 @@
 //! Utilities related to flash.
 
 mod concat_flash;
+pub mod kv_store;
 #[cfg(test)]
 pub(crate) mod mem_flash;
 pub mod partition;
+pub mod wear_leveling;
 
 pub use concat_flash::ConcatFlash;
@@ -0,0 +1,468 @@
+//! Wear-leveled flash
+//!
+//! [`WearLeveledFlash`] maps `LOGICAL` logical sectors onto `PHYSICAL` sectors of an underlying
+//! flash, with `PHYSICAL > LOGICAL`. Erasing a logical sector moves it to the least worn free
+//! physical sector, spreading the erases of frequently rewritten sectors over the whole pool.
+//! Sectors failing to erase or program are retired, and replaced by a spare sector.
+//!
+//! The mapping is stored in a header at the start of each physical sector, so the logical sectors
+//! are [`header_size`] bytes smaller than the physical ones. The header holds two records of
+//! `max(16, WRITE_SIZE, READ_SIZE)` bytes:
+//!
+//! | Record | Description                                                                 |
+//! |--------|-----------------------------------------------------------------------------|
+//! | 0      | Logical sector index, erase count and sequence number, written on allocation  |
+//! | 1      | Bad sector marker, written when the sector is retired                         |
+//!
+//! When a logical sector is erased, the new physical sector is written with a higher sequence
+//! number than the old one, so a power loss during the erase leaves the old data in place. The
+//! old physical sector is not erased until it is reused, so it keeps its erase count.
+
+use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+const RECORD_MAGIC: u8 = 0x57;
+const BAD_MAGIC: u8 = 0xBD;
+const RECORD_LEN: usize = 16;
+const MAX_RECORD_SIZE: usize = 32;
+const ERASE_VALUE: u8 = 0xFF;
+
+/// Wear-leveled flash error
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    /// The requested flash area is outside the logical flash
+    OutOfBounds,
+    /// No good physical sector is left to erase a logical sector
+    NoFreeSector,
+    /// Underlying flash error
+    Flash(T),
+}
+
+impl<T: NorFlashError> NorFlashError for Error<T> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::NoFreeSector => NorFlashErrorKind::Other,
+            Error::Flash(f) => f.kind(),
+        }
+    }
+}
+
+/// Wear statistics of a [`WearLeveledFlash`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WearStats {
+    /// Lowest erase count of the good physical sectors
+    pub min_erase_count: u32,
+    /// Highest erase count of the good physical sectors
+    pub max_erase_count: u32,
+    /// Number of retired physical sectors
+    pub bad_sectors: usize,
+}
+
+/// Size of the header of each physical sector, for a flash with the given write and read sizes.
+pub const fn header_size(write_size: usize, read_size: usize) -> usize {
+    2 * record_size(write_size, read_size)
+}
+
+const fn record_size(write_size: usize, read_size: usize) -> usize {
+    let size = if write_size > read_size { write_size } else { read_size };
+    let size = if size > RECORD_LEN { size } else { RECORD_LEN };
+    if size > MAX_RECORD_SIZE {
+        panic!("The write and read sizes of the wear-leveled flash must be at most 32 bytes");
+    }
+    size
+}
+
+/// Allocation record of a physical sector.
+struct Record {
+    logical: u16,
+    erase_count: u32,
+    sequence: u32,
+}
+
+impl Record {
+    fn encode(&self, buf: &mut [u8]) {
+        buf.fill(ERASE_VALUE);
+        buf[0] = RECORD_MAGIC;
+        buf[1] = 0;
+        buf[2..4].copy_from_slice(&self.logical.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.erase_count.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.check().to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let word = |offset: usize| u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]]);
+        let record = Self {
+            logical: u16::from_le_bytes([buf[2], buf[3]]),
+            erase_count: word(4),
+            sequence: word(8),
+        };
+        (buf[0] == RECORD_MAGIC && buf[1] == 0 && word(12) == record.check()).then_some(record)
+    }
+
+    fn check(&self) -> u32 {
+        !(self.logical as u32 ^ self.erase_count.rotate_left(8) ^ self.sequence.rotate_left(16))
+    }
+}
+
+/// A flash spreading erases over a pool of physical sectors
+///
+/// See the [module documentation](self). The mapping is read from the flash on the first
+/// operation, or with [`mount`](Self::mount).
+pub struct WearLeveledFlash<F: NorFlash, const LOGICAL: usize, const PHYSICAL: usize> {
+    flash: F,
+    mounted: bool,
+    map: [Option<u16>; LOGICAL],
+    erase_counts: [u32; PHYSICAL],
+    bad: [bool; PHYSICAL],
+    sequence: u32,
+    buf: [u8; MAX_RECORD_SIZE],
+}
+
+impl<F: NorFlash, const LOGICAL: usize, const PHYSICAL: usize> WearLeveledFlash<F, LOGICAL, PHYSICAL> {
+    const RECORD_SIZE: usize = record_size(F::WRITE_SIZE, F::READ_SIZE);
+    const HEADER_SIZE: usize = header_size(F::WRITE_SIZE, F::READ_SIZE);
+    const SECTOR_SIZE: usize = F::ERASE_SIZE - Self::HEADER_SIZE;
+
+    /// Create a new wear-leveled flash over the first `PHYSICAL` sectors of `flash`
+    pub fn new(flash: F) -> Self {
+        assert!(PHYSICAL > LOGICAL, "At least one spare physical sector is required");
+        assert!(PHYSICAL <= u16::MAX as usize);
+        assert!(PHYSICAL * F::ERASE_SIZE <= flash.capacity());
+        assert!(Self::HEADER_SIZE < F::ERASE_SIZE);
+        Self {
+            flash,
+            mounted: false,
+            map: [None; LOGICAL],
+            erase_counts: [0; PHYSICAL],
+            bad: [false; PHYSICAL],
+            sequence: 0,
+            buf: [0; MAX_RECORD_SIZE],
+        }
+    }
+
+    /// Read the mapping from the flash
+    pub async fn mount(&mut self) -> Result<(), Error<F::Error>> {
+        let mut sequences = [0u32; LOGICAL];
+        self.map = [None; LOGICAL];
+        self.sequence = 0;
+
+        for physical in 0..PHYSICAL {
+            let offset = (physical * F::ERASE_SIZE) as u32;
+            let record = &mut self.buf[..Self::RECORD_SIZE];
+            self.flash
+                .read(offset + Self::RECORD_SIZE as u32, record)
+                .await
+                .map_err(Error::Flash)?;
+            self.bad[physical] = record.iter().all(|&b| b == BAD_MAGIC);
+
+            self.flash.read(offset, record).await.map_err(Error::Flash)?;
+            let Some(record) = Record::decode(record) else {
+                // Never used, or the allocation was interrupted.
+                self.erase_counts[physical] = 0;
+                continue;
+            };
+            self.erase_counts[physical] = record.erase_count;
+            self.sequence = self.sequence.max(record.sequence);
+
+            let logical = record.logical as usize;
+            if !self.bad[physical]
+                && logical < LOGICAL
+                && (self.map[logical].is_none() || record.sequence > sequences[logical])
+            {
+                self.map[logical] = Some(physical as u16);
+                sequences[logical] = record.sequence;
+            }
+        }
+
+        self.mounted = true;
+        Ok(())
+    }
+
+    /// Get the wear statistics of the physical sectors
+    pub async fn stats(&mut self) -> Result<WearStats, Error<F::Error>> {
+        self.ensure_mounted().await?;
+        let mut counts = (0..PHYSICAL).filter(|&p| !self.bad[p]).map(|p| self.erase_counts[p]);
+        let first = counts.next().unwrap_or(0);
+        let (min_erase_count, max_erase_count) = counts.fold((first, first), |(min, max), c| (min.min(c), max.max(c)));
+        Ok(WearStats {
+            min_erase_count,
+            max_erase_count,
+            bad_sectors: self.bad.iter().filter(|&&bad| bad).count(),
+        })
+    }
+
+    async fn ensure_mounted(&mut self) -> Result<(), Error<F::Error>> {
+        if !self.mounted {
+            self.mount().await?;
+        }
+        Ok(())
+    }
+
+    /// Offset of a logical sector in the flash, after its header.
+    fn sector_offset(physical: u16) -> u32 {
+        (physical as usize * F::ERASE_SIZE + Self::HEADER_SIZE) as u32
+    }
+
+    /// Move a logical sector to the least worn free physical sector, leaving it erased.
+    async fn erase_sector(&mut self, logical: usize) -> Result<u16, Error<F::Error>> {
+        loop {
+            let free = (0..PHYSICAL)
+                .filter(|&p| !self.bad[p] && !self.map.contains(&Some(p as u16)))
+                .min_by_key(|&p| self.erase_counts[p])
+                .ok_or(Error::NoFreeSector)?;
+
+            let record = Record {
+                logical: logical as u16,
+                erase_count: self.erase_counts[free].wrapping_add(1),
+                sequence: self.sequence.wrapping_add(1),
+            };
+            if self.allocate(free, &record).await {
+                self.erase_counts[free] = record.erase_count;
+                self.sequence = record.sequence;
+                self.map[logical] = Some(free as u16);
+                return Ok(free as u16);
+            }
+
+            self.retire(free).await;
+        }
+    }
+
+    /// Erase a physical sector and write its allocation record, returning `false` if it failed.
+    async fn allocate(&mut self, physical: usize, record: &Record) -> bool {
+        let offset = (physical * F::ERASE_SIZE) as u32;
+        if self.flash.erase(offset, offset + F::ERASE_SIZE as u32).await.is_err() {
+            return false;
+        }
+
+        let buf = &mut self.buf[..Self::RECORD_SIZE];
+        record.encode(buf);
+        if self.flash.write(offset, buf).await.is_err() || self.flash.read(offset, buf).await.is_err() {
+            return false;
+        }
+        Record::decode(buf).is_some_and(|read| read.sequence == record.sequence)
+    }
+
+    /// Mark a physical sector bad, so it isn't used again.
+    async fn retire(&mut self, physical: usize) {
+        self.bad[physical] = true;
+        let buf = &mut self.buf[..Self::RECORD_SIZE];
+        buf.fill(BAD_MAGIC);
+        // Best effort, the sector is retired again on the next mount if this fails.
+        let offset = (physical * F::ERASE_SIZE + Self::RECORD_SIZE) as u32;
+        let _ = self.flash.write(offset, buf).await;
+    }
+
+    fn check_bounds(offset: u32, len: usize) -> Result<(), Error<F::Error>> {
+        if offset as usize + len > LOGICAL * Self::SECTOR_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+impl<F: NorFlash, const LOGICAL: usize, const PHYSICAL: usize> ErrorType for WearLeveledFlash<F, LOGICAL, PHYSICAL> {
+    type Error = Error<F::Error>;
+}
+
+impl<F: NorFlash, const LOGICAL: usize, const PHYSICAL: usize> ReadNorFlash for WearLeveledFlash<F, LOGICAL, PHYSICAL> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
+        Self::check_bounds(offset, bytes.len())?;
+        self.ensure_mounted().await?;
+
+        let mut offset = offset as usize;
+        while !bytes.is_empty() {
+            let logical = offset / Self::SECTOR_SIZE;
+            let in_sector = offset % Self::SECTOR_SIZE;
+            let len = bytes.len().min(Self::SECTOR_SIZE - in_sector);
+            let (chunk, rest) = bytes.split_at_mut(len);
+
+            match self.map[logical] {
+                Some(physical) => self
+                    .flash
+                    .read(Self::sector_offset(physical) + in_sector as u32, chunk)
+                    .await
+                    .map_err(Error::Flash)?,
+                // Sectors never written read as erased.
+                None => chunk.fill(ERASE_VALUE),
+            }
+
+            bytes = rest;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        LOGICAL * Self::SECTOR_SIZE
+    }
+}
+
+impl<F: NorFlash, const LOGICAL: usize, const PHYSICAL: usize> NorFlash for WearLeveledFlash<F, LOGICAL, PHYSICAL> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = Self::SECTOR_SIZE;
+
+    async fn write(&mut self, offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+        Self::check_bounds(offset, bytes.len())?;
+        self.ensure_mounted().await?;
+
+        let mut offset = offset as usize;
+        while !bytes.is_empty() {
+            let logical = offset / Self::SECTOR_SIZE;
+            let in_sector = offset % Self::SECTOR_SIZE;
+            let len = bytes.len().min(Self::SECTOR_SIZE - in_sector);
+            let (chunk, rest) = bytes.split_at(len);
+
+            let physical = match self.map[logical] {
+                Some(physical) => physical,
+                None => self.erase_sector(logical).await?,
+            };
+            self.flash
+                .write(Self::sector_offset(physical) + in_sector as u32, chunk)
+                .await
+                .map_err(Error::Flash)?;
+
+            bytes = rest;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to || from as usize % Self::SECTOR_SIZE != 0 || to as usize % Self::SECTOR_SIZE != 0 {
+            return Err(Error::OutOfBounds);
+        }
+        Self::check_bounds(from, (to - from) as usize)?;
+        self.ensure_mounted().await?;
+
+        for logical in from as usize / Self::SECTOR_SIZE..to as usize / Self::SECTOR_SIZE {
+            // Sectors never written are already erased.
+            if self.map[logical].is_some() {
+                self.erase_sector(logical).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::mem_flash::MemFlash;
+
+    type Flash = MemFlash<1024, 128, 4>;
+    const SECTOR_SIZE: usize = 128 - 32;
+
+    #[futures_test::test]
+    async fn data_survives_remount() {
+        let mut flash = Flash::default();
+        let mut wl = WearLeveledFlash::<_, 4, 8>::new(&mut flash);
+        assert_eq!(4 * SECTOR_SIZE, wl.capacity());
+
+        let mut read_buf = [0; 8];
+        wl.read(SECTOR_SIZE as u32 - 4, &mut read_buf).await.unwrap();
+        assert_eq!([0xFF; 8], read_buf);
+
+        // A write crossing two logical sectors.
+        wl.write(SECTOR_SIZE as u32 - 4, &[0xAA; 8]).await.unwrap();
+        wl.erase(0, SECTOR_SIZE as u32).await.unwrap();
+        wl.write(0, &[0x55; 4]).await.unwrap();
+
+        let mut wl = WearLeveledFlash::<_, 4, 8>::new(&mut flash);
+        wl.read(0, &mut read_buf[..4]).await.unwrap();
+        assert_eq!([0x55; 4], read_buf[..4]);
+        wl.read(SECTOR_SIZE as u32 - 4, &mut read_buf).await.unwrap();
+        assert_eq!([0xFF, 0xFF, 0xFF, 0xFF, 0xAA, 0xAA, 0xAA, 0xAA], read_buf);
+    }
+
+    #[futures_test::test]
+    async fn erases_are_spread() {
+        let mut flash = Flash::default();
+        let mut wl = WearLeveledFlash::<_, 2, 8>::new(&mut flash);
+        wl.write(SECTOR_SIZE as u32, &[0xAA; 4]).await.unwrap();
+        for _ in 0..70 {
+            wl.erase(0, SECTOR_SIZE as u32).await.unwrap();
+            wl.write(0, &[0x55; 4]).await.unwrap();
+        }
+
+        let stats = wl.stats().await.unwrap();
+        // The sector of logical sector 1 was erased once, and never moved.
+        assert_eq!(1, stats.min_erase_count);
+        assert_eq!(10, stats.max_erase_count);
+
+        let mut wl = WearLeveledFlash::<_, 2, 8>::new(&mut flash);
+        let mut read_buf = [0; 4];
+        wl.read(SECTOR_SIZE as u32, &mut read_buf).await.unwrap();
+        assert_eq!([0xAA; 4], read_buf);
+        assert_eq!(stats, wl.stats().await.unwrap());
+    }
+
+    /// Flash failing to erase its second sector.
+    struct BadSectorFlash(Flash);
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct EraseError;
+
+    impl NorFlashError for EraseError {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    impl ErrorType for BadSectorFlash {
+        type Error = EraseError;
+    }
+
+    impl ReadNorFlash for BadSectorFlash {
+        const READ_SIZE: usize = 1;
+
+        async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            self.0.read(offset, bytes).await.map_err(|_| EraseError)
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.capacity()
+        }
+    }
+
+    impl NorFlash for BadSectorFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 128;
+
+        async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.0.write(offset, bytes).await.map_err(|_| EraseError)
+        }
+
+        async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            if from == 128 {
+                return Err(EraseError);
+            }
+            self.0.erase(from, to).await.map_err(|_| EraseError)
+        }
+    }
+
+    #[futures_test::test]
+    async fn bad_sectors_are_retired() {
+        let mut wl = WearLeveledFlash::<_, 1, 3>::new(BadSectorFlash(Flash::default()));
+        for _ in 0..3 {
+            wl.erase(0, SECTOR_SIZE as u32).await.ok();
+            wl.write(0, &[0x55; 4]).await.unwrap();
+        }
+        assert_eq!(1, wl.stats().await.unwrap().bad_sectors);
+
+        let mut wl = WearLeveledFlash::<_, 1, 3>::new(wl.flash);
+        assert_eq!(1, wl.stats().await.unwrap().bad_sectors);
+        let mut read_buf = [0; 4];
+        wl.read(0, &mut read_buf).await.unwrap();
+        assert_eq!([0x55; 4], read_buf);
+
+        // With the spare sector gone, logical sector 0 can't move anymore.
+        let mut wl = WearLeveledFlash::<_, 1, 2>::new(wl.flash);
+        assert_eq!(Err(Error::NoFreeSector), wl.erase(0, SECTOR_SIZE as u32).await);
+    }
+}
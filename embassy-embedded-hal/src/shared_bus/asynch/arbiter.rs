@@ -0,0 +1,295 @@
+//! Bus arbiter granting access to a shared bus by priority
+//!
+//! [`BusArbiter`] is like an async [`Mutex`](embassy_sync::mutex::Mutex) around the bus, except
+//! that each lock has a [`Priority`]: when the bus is released, it goes to the highest priority
+//! device waiting for it, so a device polled at a high rate can't starve a device with tighter
+//! latency requirements. It is used by [`ArbitratedI2cDevice`](super::i2c::ArbitratedI2cDevice)
+//! and [`ArbitratedSpiDevice`](super::spi::ArbitratedSpiDevice).
+//!
+//! # Example (nrf52)
+//!
+//! ```rust,ignore
+//! use embassy_embedded_hal::shared_bus::asynch::arbiter::{BusArbiter, Priority};
+//! use embassy_embedded_hal::shared_bus::asynch::i2c::ArbitratedI2cDevice;
+//! use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+//!
+//! static I2C_BUS: StaticCell<BusArbiter<NoopRawMutex, Twim<TWISPI0>>> = StaticCell::new();
+//! let i2c = Twim::new(p.TWISPI0, Irqs, p.P0_03, p.P0_04, twim::Config::default());
+//! let i2c_bus = I2C_BUS.init(BusArbiter::new(i2c));
+//!
+//! // The IMU is read at a high rate, it must not delay the power monitor.
+//! let imu = ArbitratedI2cDevice::new(i2c_bus, Priority::Low).with_address(0x68);
+//! let power = ArbitratedI2cDevice::new(i2c_bus, Priority::High)
+//!     .with_address(0x40)
+//!     .with_timeout(Duration::from_millis(10));
+//! ```
+
+use core::cell::{RefCell, UnsafeCell};
+use core::future::{poll_fn, Future};
+use core::ops::{Deref, DerefMut};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::waitqueue::WakerRegistration;
+
+const PRIORITY_COUNT: usize = 3;
+
+/// Priority of a device acquiring a [`BusArbiter`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// Only acquire the bus when no other device waits for it.
+    Low,
+    /// Default priority.
+    #[default]
+    Normal,
+    /// Acquire the bus before the other devices.
+    High,
+}
+
+impl Priority {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+struct State {
+    locked: bool,
+    waiting: [usize; PRIORITY_COUNT],
+    wakers: [WakerRegistration; PRIORITY_COUNT],
+}
+
+impl State {
+    /// Whether a device of the given priority can acquire the bus.
+    fn can_lock(&self, priority: Priority) -> bool {
+        !self.locked && self.waiting[priority.index() + 1..].iter().all(|&n| n == 0)
+    }
+
+    /// Wake the highest priority device waiting for the bus.
+    fn wake_next(&mut self) {
+        if let Some(index) = (0..PRIORITY_COUNT).rev().find(|&i| self.waiting[i] > 0) {
+            self.wakers[index].wake();
+        }
+    }
+}
+
+/// Shared bus, granted to devices by priority.
+///
+/// See the [module documentation](self).
+pub struct BusArbiter<M: RawMutex, BUS> {
+    state: BlockingMutex<M, RefCell<State>>,
+    bus: UnsafeCell<BUS>,
+}
+
+unsafe impl<M: RawMutex + Send, BUS: Send> Send for BusArbiter<M, BUS> {}
+unsafe impl<M: RawMutex + Sync, BUS: Send> Sync for BusArbiter<M, BUS> {}
+
+impl<M: RawMutex, BUS> BusArbiter<M, BUS> {
+    /// Create a new `BusArbiter` for the bus.
+    pub const fn new(bus: BUS) -> Self {
+        Self {
+            state: BlockingMutex::new(RefCell::new(State {
+                locked: false,
+                waiting: [0; PRIORITY_COUNT],
+                wakers: [
+                    WakerRegistration::new(),
+                    WakerRegistration::new(),
+                    WakerRegistration::new(),
+                ],
+            })),
+            bus: UnsafeCell::new(bus),
+        }
+    }
+
+    /// Acquire the bus with the given priority.
+    ///
+    /// This waits until the bus is released, and no device with a higher priority waits for it.
+    /// Devices with the same priority may acquire the bus in any order.
+    pub async fn lock(&self, priority: Priority) -> BusGuard<'_, M, BUS> {
+        let mut waiter = Waiter {
+            state: &self.state,
+            priority,
+            queued: false,
+        };
+
+        poll_fn(|cx| {
+            let ready = self.state.lock(|s| {
+                let mut s = s.borrow_mut();
+                if s.can_lock(priority) {
+                    if waiter.queued {
+                        s.waiting[priority.index()] -= 1;
+                        waiter.queued = false;
+                    }
+                    s.locked = true;
+                    true
+                } else {
+                    if !waiter.queued {
+                        s.waiting[priority.index()] += 1;
+                        waiter.queued = true;
+                    }
+                    s.wakers[priority.index()].register(cx.waker());
+                    false
+                }
+            });
+
+            if ready {
+                Poll::Ready(BusGuard { arbiter: self })
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Attempt to immediately acquire the bus with the given priority.
+    ///
+    /// Returns `None` if the bus is in use, or if a device with a higher priority waits for it.
+    pub fn try_lock(&self, priority: Priority) -> Option<BusGuard<'_, M, BUS>> {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            if s.can_lock(priority) {
+                s.locked = true;
+                Some(BusGuard { arbiter: self })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Consumes this arbiter, returning the bus.
+    pub fn into_inner(self) -> BUS {
+        self.bus.into_inner()
+    }
+}
+
+/// A device waiting for the bus, removed from the queue if the lock is cancelled.
+struct Waiter<'a, M: RawMutex> {
+    state: &'a BlockingMutex<M, RefCell<State>>,
+    priority: Priority,
+    queued: bool,
+}
+
+impl<'a, M: RawMutex> Drop for Waiter<'a, M> {
+    fn drop(&mut self) {
+        if self.queued {
+            self.state.lock(|s| {
+                let mut s = s.borrow_mut();
+                s.waiting[self.priority.index()] -= 1;
+                // Lower priority devices may have waited for this one only.
+                if !s.locked {
+                    s.wake_next();
+                }
+            })
+        }
+    }
+}
+
+/// Access to the bus of a [`BusArbiter`].
+///
+/// Dropping it releases the bus.
+#[clippy::has_significant_drop]
+pub struct BusGuard<'a, M: RawMutex, BUS> {
+    arbiter: &'a BusArbiter<M, BUS>,
+}
+
+impl<'a, M: RawMutex, BUS> Drop for BusGuard<'a, M, BUS> {
+    fn drop(&mut self) {
+        self.arbiter.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.locked = false;
+            s.wake_next();
+        })
+    }
+}
+
+impl<'a, M: RawMutex, BUS> Deref for BusGuard<'a, M, BUS> {
+    type Target = BUS;
+    fn deref(&self) -> &Self::Target {
+        // Safety: the BusGuard represents exclusive access to the bus.
+        unsafe { &*(self.arbiter.bus.get() as *const BUS) }
+    }
+}
+
+impl<'a, M: RawMutex, BUS> DerefMut for BusGuard<'a, M, BUS> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: the BusGuard represents exclusive access to the bus.
+        unsafe { &mut *(self.arbiter.bus.get()) }
+    }
+}
+
+/// Deadline of a transaction on an arbitrated device.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct Deadline {
+    #[cfg(feature = "time")]
+    at: Option<embassy_time::Instant>,
+}
+
+impl Deadline {
+    #[cfg(feature = "time")]
+    pub(crate) fn after(timeout: Option<embassy_time::Duration>) -> Self {
+        Self {
+            at: timeout.map(|timeout| embassy_time::Instant::now() + timeout),
+        }
+    }
+
+    /// Run `fut`, returning `Err` if the deadline passes first.
+    pub(crate) async fn run<F: Future>(self, fut: F) -> Result<F::Output, ()> {
+        #[cfg(feature = "time")]
+        if let Some(at) = self.at {
+            return embassy_time::with_deadline(at, fut).await.map_err(|_| ());
+        }
+        Ok(fut.await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use futures_test::task::noop_context;
+
+    use super::*;
+
+    #[test]
+    fn high_priority_acquires_first() {
+        let arbiter = BusArbiter::<NoopRawMutex, u32>::new(0);
+        let mut cx = noop_context();
+
+        let guard = arbiter.try_lock(Priority::Normal).unwrap();
+        let mut low = pin!(arbiter.lock(Priority::Low));
+        let mut high = pin!(arbiter.lock(Priority::High));
+        assert!(low.as_mut().poll(&mut cx).is_pending());
+        assert!(high.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+
+        assert!(low.as_mut().poll(&mut cx).is_pending());
+        assert!(arbiter.try_lock(Priority::Normal).is_none());
+        let Poll::Ready(mut guard) = high.as_mut().poll(&mut cx) else {
+            panic!("high priority lock is pending");
+        };
+        *guard = 1;
+        drop(guard);
+
+        let Poll::Ready(guard) = low.as_mut().poll(&mut cx) else {
+            panic!("low priority lock is pending");
+        };
+        assert_eq!(1, *guard);
+    }
+
+    #[test]
+    fn cancelled_lock_leaves_queue() {
+        let arbiter = BusArbiter::<NoopRawMutex, u32>::new(0);
+        let mut cx = noop_context();
+
+        let guard = arbiter.try_lock(Priority::Normal).unwrap();
+        {
+            let mut high = pin!(arbiter.lock(Priority::High));
+            assert!(high.as_mut().poll(&mut cx).is_pending());
+        }
+        drop(guard);
+        assert!(arbiter.try_lock(Priority::Low).is_some());
+    }
+}
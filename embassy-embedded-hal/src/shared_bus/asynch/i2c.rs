@@ -26,6 +26,7 @@ use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::mutex::Mutex;
 use embedded_hal_async::i2c;
 
+use crate::shared_bus::asynch::arbiter::{BusArbiter, Deadline, Priority};
 use crate::shared_bus::I2cDeviceError;
 use crate::SetConfig;
 
@@ -164,3 +165,84 @@ where
         Ok(())
     }
 }
+
+/// I2C device on a bus shared through a [`BusArbiter`].
+///
+/// This is like [`I2cDevice`], except that the bus is acquired with the device's [`Priority`].
+/// The device can also be scoped to an address, and given a timeout for its transactions.
+pub struct ArbitratedI2cDevice<'a, M: RawMutex, BUS> {
+    bus: &'a BusArbiter<M, BUS>,
+    priority: Priority,
+    address: Option<u8>,
+    #[cfg(feature = "time")]
+    timeout: Option<embassy_time::Duration>,
+}
+
+impl<'a, M: RawMutex, BUS> ArbitratedI2cDevice<'a, M, BUS> {
+    /// Create a new `ArbitratedI2cDevice`, acquiring the bus with the given priority.
+    pub fn new(bus: &'a BusArbiter<M, BUS>, priority: Priority) -> Self {
+        Self {
+            bus,
+            priority,
+            address: None,
+            #[cfg(feature = "time")]
+            timeout: None,
+        }
+    }
+
+    /// Scope the device to an address.
+    ///
+    /// Transactions to other addresses fail with [`I2cDeviceError::AddressOutOfScope`], without
+    /// acquiring the bus. This catches drivers configured with the address of another device.
+    pub fn with_address(mut self, address: u8) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Fail transactions with [`I2cDeviceError::Timeout`] if they don't complete in time.
+    ///
+    /// The timeout includes the time waiting for the bus.
+    #[cfg(feature = "time")]
+    pub fn with_timeout(mut self, timeout: embassy_time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn deadline(&self) -> Deadline {
+        #[cfg(feature = "time")]
+        return Deadline::after(self.timeout);
+        #[cfg(not(feature = "time"))]
+        return Deadline::default();
+    }
+}
+
+impl<'a, M: RawMutex, BUS> i2c::ErrorType for ArbitratedI2cDevice<'a, M, BUS>
+where
+    BUS: i2c::ErrorType,
+{
+    type Error = I2cDeviceError<BUS::Error>;
+}
+
+impl<M, BUS> i2c::I2c for ArbitratedI2cDevice<'_, M, BUS>
+where
+    M: RawMutex,
+    BUS: i2c::I2c,
+{
+    async fn transaction(&mut self, address: u8, operations: &mut [i2c::Operation<'_>]) -> Result<(), Self::Error> {
+        if self.address.is_some_and(|scope| scope != address) {
+            return Err(I2cDeviceError::AddressOutOfScope);
+        }
+
+        let deadline = self.deadline();
+        let mut bus = deadline
+            .run(self.bus.lock(self.priority))
+            .await
+            .map_err(|_| I2cDeviceError::Timeout)?;
+        deadline
+            .run(bus.transaction(address, operations))
+            .await
+            .map_err(|_| I2cDeviceError::Timeout)?
+            .map_err(I2cDeviceError::I2c)?;
+        Ok(())
+    }
+}
@@ -31,6 +31,7 @@ use embedded_hal_1::digital::OutputPin;
 use embedded_hal_1::spi::Operation;
 use embedded_hal_async::spi;
 
+use crate::shared_bus::asynch::arbiter::{BusArbiter, Deadline, Priority};
 use crate::shared_bus::SpiDeviceError;
 use crate::SetConfig;
 
@@ -189,3 +190,137 @@ where
         Ok(op_res)
     }
 }
+
+/// SPI device on a bus shared through a [`BusArbiter`].
+///
+/// This is like [`SpiDevice`], except that the bus is acquired with the device's [`Priority`],
+/// and the device can be given a timeout for its transactions. CS is also deasserted if the
+/// transaction is cancelled, so that a dropped transaction doesn't leave the device selected
+/// while the bus is used by another one.
+pub struct ArbitratedSpiDevice<'a, M: RawMutex, BUS, CS: OutputPin> {
+    bus: &'a BusArbiter<M, BUS>,
+    cs: CS,
+    priority: Priority,
+    #[cfg(feature = "time")]
+    timeout: Option<embassy_time::Duration>,
+}
+
+impl<'a, M: RawMutex, BUS, CS: OutputPin> ArbitratedSpiDevice<'a, M, BUS, CS> {
+    /// Create a new `ArbitratedSpiDevice`, acquiring the bus with the given priority.
+    pub fn new(bus: &'a BusArbiter<M, BUS>, cs: CS, priority: Priority) -> Self {
+        Self {
+            bus,
+            cs,
+            priority,
+            #[cfg(feature = "time")]
+            timeout: None,
+        }
+    }
+
+    /// Fail transactions with [`SpiDeviceError::Timeout`] if they don't complete in time.
+    ///
+    /// The timeout includes the time waiting for the bus. On timeout, the bus is still flushed and
+    /// CS deasserted.
+    #[cfg(feature = "time")]
+    pub fn with_timeout(mut self, timeout: embassy_time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn deadline(&self) -> Deadline {
+        #[cfg(feature = "time")]
+        return Deadline::after(self.timeout);
+        #[cfg(not(feature = "time"))]
+        return Deadline::default();
+    }
+}
+
+impl<'a, M: RawMutex, BUS, CS> spi::ErrorType for ArbitratedSpiDevice<'a, M, BUS, CS>
+where
+    BUS: spi::ErrorType,
+    CS: OutputPin,
+{
+    type Error = SpiDeviceError<BUS::Error, CS::Error>;
+}
+
+impl<M, BUS, CS> spi::SpiDevice for ArbitratedSpiDevice<'_, M, BUS, CS>
+where
+    M: RawMutex,
+    BUS: spi::SpiBus,
+    CS: OutputPin,
+{
+    async fn transaction(&mut self, operations: &mut [spi::Operation<'_, u8>]) -> Result<(), Self::Error> {
+        if cfg!(not(feature = "time")) && operations.iter().any(|op| matches!(op, Operation::DelayNs(_))) {
+            return Err(SpiDeviceError::DelayNotSupported);
+        }
+
+        let deadline = self.deadline();
+        let mut bus = deadline
+            .run(self.bus.lock(self.priority))
+            .await
+            .map_err(|_| SpiDeviceError::Timeout)?;
+        let cs = ChipSelect::assert(&mut self.cs).map_err(SpiDeviceError::Cs)?;
+
+        let op_res = deadline.run(run_operations(&mut *bus, operations)).await;
+
+        // On failure, it's important to still flush and deassert CS.
+        let flush_res = bus.flush().await;
+        let cs_res = cs.deassert();
+
+        op_res
+            .map_err(|_| SpiDeviceError::Timeout)?
+            .map_err(SpiDeviceError::Spi)?;
+        flush_res.map_err(SpiDeviceError::Spi)?;
+        cs_res.map_err(SpiDeviceError::Cs)?;
+
+        Ok(())
+    }
+}
+
+/// CS asserted during a transaction, and deasserted if the transaction is dropped.
+struct ChipSelect<'a, CS: OutputPin> {
+    cs: &'a mut CS,
+    asserted: bool,
+}
+
+impl<'a, CS: OutputPin> ChipSelect<'a, CS> {
+    fn assert(cs: &'a mut CS) -> Result<Self, CS::Error> {
+        cs.set_low()?;
+        Ok(Self { cs, asserted: true })
+    }
+
+    fn deassert(mut self) -> Result<(), CS::Error> {
+        self.asserted = false;
+        self.cs.set_high()
+    }
+}
+
+impl<'a, CS: OutputPin> Drop for ChipSelect<'a, CS> {
+    fn drop(&mut self) {
+        if self.asserted {
+            let _ = self.cs.set_high();
+        }
+    }
+}
+
+async fn run_operations<BUS: spi::SpiBus>(
+    bus: &mut BUS,
+    operations: &mut [spi::Operation<'_, u8>],
+) -> Result<(), BUS::Error> {
+    for op in operations {
+        match op {
+            Operation::Read(buf) => bus.read(buf).await?,
+            Operation::Write(buf) => bus.write(buf).await?,
+            Operation::Transfer(read, write) => bus.transfer(read, write).await?,
+            Operation::TransferInPlace(buf) => bus.transfer_in_place(buf).await?,
+            #[cfg(not(feature = "time"))]
+            Operation::DelayNs(_) => unreachable!(),
+            #[cfg(feature = "time")]
+            Operation::DelayNs(ns) => {
+                bus.flush().await?;
+                embassy_time::Timer::after_nanos(*ns as _).await;
+            }
+        }
+    }
+    Ok(())
+}
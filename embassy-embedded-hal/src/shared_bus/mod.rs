@@ -14,6 +14,10 @@ pub enum I2cDeviceError<BUS> {
     I2c(BUS),
     /// Configuration of the inner I2C bus failed.
     Config,
+    /// The bus wasn't acquired, or the transaction didn't complete, before the timeout.
+    Timeout,
+    /// The transaction addressed another device than the one the device is scoped to.
+    AddressOutOfScope,
 }
 
 impl<BUS> i2c::Error for I2cDeviceError<BUS>
@@ -24,6 +28,8 @@ where
         match self {
             Self::I2c(e) => e.kind(),
             Self::Config => i2c::ErrorKind::Other,
+            Self::Timeout => i2c::ErrorKind::Other,
+            Self::AddressOutOfScope => i2c::ErrorKind::Other,
         }
     }
 }
@@ -41,6 +47,8 @@ pub enum SpiDeviceError<BUS, CS> {
     DelayNotSupported,
     /// The SPI bus could not be configured.
     Config,
+    /// The bus wasn't acquired, or the transaction didn't complete, before the timeout.
+    Timeout,
 }
 
 impl<BUS, CS> spi::Error for SpiDeviceError<BUS, CS>
@@ -54,6 +62,7 @@ where
             Self::Cs(_) => spi::ErrorKind::Other,
             Self::DelayNotSupported => spi::ErrorKind::Other,
             Self::Config => spi::ErrorKind::Other,
+            Self::Timeout => spi::ErrorKind::Other,
         }
     }
 }
@@ -25,7 +25,8 @@ impl Parse for Args {
 }
 
 /// Declares an async task that can be run by `embassy-executor`. The optional `pool_size` parameter can be used to specify how
-/// many concurrent tasks can be spawned (default is 1) for the function.
+/// many concurrent tasks can be spawned (default is 1) for the function. The optional `name` parameter sets the name
+/// reported by `embassy_executor::raw::Executor::current_task_name()` when the `task-names` feature is enabled (default is the function name).
 ///
 ///
 /// The following restrictions apply:
@@ -54,6 +55,15 @@ impl Parse for Args {
 ///     // Function body
 /// }
 /// ```
+///
+/// Declaring a task with a name:
+///
+/// ``` rust
+/// #[embassy_executor::task(name = "blinky")]
+/// async fn mytask() {
+///     // Function body
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn task(args: TokenStream, item: TokenStream) -> TokenStream {
     let args = syn::parse_macro_input!(args as Args);
@@ -2,7 +2,7 @@ use darling::export::NestedMeta;
 use darling::FromMeta;
 use proc_macro2::{Span, TokenStream};
 use quote::{format_ident, quote};
-use syn::{parse_quote, Expr, ExprLit, ItemFn, Lit, LitInt, ReturnType, Type};
+use syn::{parse_quote, Expr, ExprLit, ItemFn, Lit, LitInt, LitStr, ReturnType, Type};
 
 use crate::util::ctxt::Ctxt;
 
@@ -10,6 +10,9 @@ use crate::util::ctxt::Ctxt;
 struct Args {
     #[darling(default)]
     pool_size: Option<syn::Expr>,
+    /// Name of the task, defaults to the function name.
+    #[darling(default)]
+    name: Option<syn::LitStr>,
 }
 
 pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStream> {
@@ -20,6 +23,8 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
         lit: Lit::Int(LitInt::new("1", Span::call_site())),
     }));
 
+    let task_name = args.name;
+
     let ctxt = Ctxt::new();
 
     if f.sig.asyncness.is_none() {
@@ -73,6 +78,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
 
     let task_ident = f.sig.ident.clone();
     let task_inner_ident = format_ident!("__{}_task", task_ident);
+    let task_name = task_name.unwrap_or_else(|| LitStr::new(&task_ident.to_string(), task_ident.span()));
 
     let mut task_inner = f;
     let visibility = task_inner.vis.clone();
@@ -107,7 +113,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
 
             const POOL_SIZE: usize = #pool_size;
             static POOL: ::embassy_executor::raw::TaskPool<<() as _EmbassyInternalTaskTrait>::Fut, POOL_SIZE> = ::embassy_executor::raw::TaskPool::new();
-            unsafe { POOL._spawn_async_fn(move || <() as _EmbassyInternalTaskTrait>::construct(#(#full_args,)*)) }
+            unsafe { POOL._spawn_async_fn(move || <() as _EmbassyInternalTaskTrait>::construct(#(#full_args,)*)) }.__set_name(#task_name)
         }
     };
     #[cfg(not(feature = "nightly"))]
@@ -115,7 +121,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
         #visibility fn #task_ident(#fargs) -> ::embassy_executor::SpawnToken<impl Sized> {
             const POOL_SIZE: usize = #pool_size;
             static POOL: ::embassy_executor::_export::TaskPoolRef = ::embassy_executor::_export::TaskPoolRef::new();
-            unsafe { POOL.get::<_, POOL_SIZE>()._spawn_async_fn(move || #task_inner_ident(#(#full_args,)*)) }
+            unsafe { POOL.get::<_, POOL_SIZE>()._spawn_async_fn(move || #task_inner_ident(#(#full_args,)*)) }.__set_name(#task_name)
         }
     };
 
@@ -46,6 +46,8 @@ pub(crate) struct TaskHeader {
     pub(crate) run_queue_item: RunQueueItem,
     pub(crate) executor: SyncUnsafeCell<Option<&'static SyncExecutor>>,
     poll_fn: SyncUnsafeCell<Option<unsafe fn(TaskRef)>>,
+    #[cfg(feature = "task-names")]
+    pub(crate) name: SyncUnsafeCell<Option<&'static str>>,
 
     #[cfg(feature = "integrated-timers")]
     pub(crate) expires_at: SyncUnsafeCell<u64>,
@@ -84,6 +86,15 @@ impl TaskRef {
     pub(crate) fn as_ptr(self) -> *const TaskHeader {
         self.ptr.as_ptr()
     }
+
+    /// Name of the task, if it has one.
+    ///
+    /// Tasks spawned from a [`task`](embassy_executor_macros::task) function are named after
+    /// the function, or the `name` given to the macro.
+    #[cfg(feature = "task-names")]
+    pub fn name(self) -> Option<&'static str> {
+        unsafe { self.header().name.get() }
+    }
 }
 
 /// Raw storage in which a task can be spawned.
@@ -119,6 +130,8 @@ impl<F: Future + 'static> TaskStorage<F> {
                 executor: SyncUnsafeCell::new(None),
                 // Note: this is lazily initialized so that a static `TaskStorage` will go in `.bss`
                 poll_fn: SyncUnsafeCell::new(None),
+                #[cfg(feature = "task-names")]
+                name: SyncUnsafeCell::new(None),
 
                 #[cfg(feature = "integrated-timers")]
                 expires_at: SyncUnsafeCell::new(0),
@@ -316,6 +329,8 @@ impl Pender {
 pub(crate) struct SyncExecutor {
     run_queue: RunQueue,
     pender: Pender,
+    #[cfg(feature = "task-names")]
+    current_task: SyncUnsafeCell<Option<TaskRef>>,
 
     #[cfg(feature = "integrated-timers")]
     pub(crate) timer_queue: timer_queue::TimerQueue,
@@ -331,6 +346,8 @@ impl SyncExecutor {
         Self {
             run_queue: RunQueue::new(),
             pender,
+            #[cfg(feature = "task-names")]
+            current_task: SyncUnsafeCell::new(None),
 
             #[cfg(feature = "integrated-timers")]
             timer_queue: timer_queue::TimerQueue::new(),
@@ -401,9 +418,15 @@ impl SyncExecutor {
                 #[cfg(feature = "rtos-trace")]
                 trace::task_exec_begin(p.as_ptr() as u32);
 
+                #[cfg(feature = "task-names")]
+                self.current_task.set(Some(p));
+
                 // Run the task
                 task.poll_fn.get().unwrap_unchecked()(p);
 
+                #[cfg(feature = "task-names")]
+                self.current_task.set(None);
+
                 #[cfg(feature = "rtos-trace")]
                 trace::task_exec_end();
 
@@ -526,6 +549,19 @@ impl Executor {
         self.inner.poll()
     }
 
+    /// Get the name of the task this executor is polling, if any.
+    ///
+    /// Returns `None` if the executor isn't polling a task, or if the task has no name. Each executor records its own task, so this works with executors on
+    /// several cores, or nested in interrupts.
+    ///
+    /// This is meant to add context to logs, and to panic messages if the panic handler can reach
+    /// the executor. The higher level executors don't expose it, so their panics are not named.
+    #[cfg(feature = "task-names")]
+    pub fn current_task_name(&self) -> Option<&'static str> {
+        // Safety: the executor isn't `Sync`, so this runs on its thread, between or inside polls.
+        unsafe { self.inner.current_task.get() }.and_then(TaskRef::name)
+    }
+
     /// Get a spawner that spawns tasks in this executor.
     ///
     /// It is OK to call this method multiple times to obtain multiple
@@ -40,6 +40,20 @@ impl<S> SpawnToken<S> {
             phantom: PhantomData,
         }
     }
+
+    /// Set the name of the task. Used by the [`task`](embassy_executor_macros::task) macro.
+    ///
+    /// Not covered by semver guarantees. DO NOT call this directly.
+    #[doc(hidden)]
+    #[allow(unused_variables)]
+    pub fn __set_name(self, name: &'static str) -> Self {
+        #[cfg(feature = "task-names")]
+        if let Some(task) = self.raw_task {
+            // Safety: the task isn't spawned yet, nothing else accesses its header.
+            unsafe { task.header().name.set(Some(name)) }
+        }
+        self
+    }
 }
 
 impl<S> Drop for SpawnToken<S> {
@@ -149,3 +149,28 @@ fn executor_task_cfg_args() {
         let (_, _, _) = (a, b, c);
     }
 }
+
+#[cfg(feature = "task-names")]
+#[test]
+fn executor_task_name() {
+    #[task]
+    async fn task1(executor: &'static Executor, trace: Trace) {
+        trace.push(executor.current_task_name().unwrap())
+    }
+
+    #[task(name = "renamed")]
+    async fn task2(executor: &'static Executor, trace: Trace) {
+        trace.push(executor.current_task_name().unwrap())
+    }
+
+    let (executor, trace) = setup();
+    executor.spawner().spawn(task1(executor, trace.clone())).unwrap();
+    executor.spawner().spawn(task2(executor, trace.clone())).unwrap();
+    assert_eq!(executor.current_task_name(), None);
+
+    unsafe { executor.poll() };
+    assert_eq!(executor.current_task_name(), None);
+
+    // the run queue polls the most recently woken task first.
+    assert_eq!(trace.get(), &["pend", "renamed", "task1"])
+}
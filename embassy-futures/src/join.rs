@@ -320,3 +320,75 @@ pub fn join_array<Fut: Future, const N: usize>(futures: [Fut; N]) -> JoinArray<F
         futures: futures.map(MaybeDone::Future),
     }
 }
+
+// ====================================================================
+
+/// Future for the [`join_array_fair`] function.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct JoinArrayFair<Fut: Future, const N: usize> {
+    futures: [MaybeDone<Fut>; N],
+    next: usize,
+}
+
+impl<Fut: Future, const N: usize> fmt::Debug for JoinArrayFair<Fut, N>
+where
+    Fut: Future + fmt::Debug,
+    Fut::Output: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinArrayFair")
+            .field("futures", &self.futures)
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+impl<Fut: Future, const N: usize> Future for JoinArrayFair<Fut, N> {
+    type Output = [Fut::Output; N];
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let start = this.next;
+        this.next = if start + 1 >= N { 0 } else { start + 1 };
+
+        let mut all_done = true;
+        for k in 0..N {
+            let i = (start + k) % N;
+            all_done &= unsafe { Pin::new_unchecked(&mut this.futures[i]) }.poll(cx);
+        }
+
+        if all_done {
+            let mut array: [MaybeUninit<Fut::Output>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+            for (out, f) in array.iter_mut().zip(this.futures.iter_mut()) {
+                out.write(f.take_output());
+            }
+            Poll::Ready(unsafe { (&array as *const _ as *const [Fut::Output; N]).read() })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Joins the result of an array of futures, polling them in rotating order.
+///
+/// Same as [`join_array`], except that every poll starts at the future after the one
+/// that was polled first last time. This matters when polling the futures has side
+/// effects that favor whoever goes first, e.g. several futures competing for the same
+/// resource: with [`join_array`] the first future always gets the first try.
+///
+/// # Examples
+///
+/// ```
+/// # embassy_futures::block_on(async {
+///
+/// async fn foo(n: u32) -> u32 { n }
+/// let res = embassy_futures::join::join_array_fair([foo(1), foo(2), foo(3)]).await;
+///
+/// assert_eq!(res, [1, 2, 3]);
+/// # });
+/// ```
+pub fn join_array_fair<Fut: Future, const N: usize>(futures: [Fut; N]) -> JoinArrayFair<Fut, N> {
+    JoinArrayFair {
+        futures: futures.map(MaybeDone::Future),
+        next: 0,
+    }
+}
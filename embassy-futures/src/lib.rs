@@ -9,7 +9,11 @@ mod block_on;
 mod yield_now;
 
 pub mod join;
+#[cfg(feature = "embedded-hal-async")]
+pub mod retry;
 pub mod select;
+#[cfg(feature = "time")]
+pub mod timeout;
 
 pub use block_on::*;
 pub use yield_now::*;
@@ -0,0 +1,161 @@
+//! Retry fallible operations with exponential backoff.
+//!
+//! The delays between attempts are waited for with an [`embedded_hal_async::delay::DelayNs`], so
+//! this works with any time source, for example `embassy_time::Delay`.
+
+use core::future::Future;
+
+use embedded_hal_async::delay::DelayNs;
+
+/// How often, and how long apart, [`retry`] runs an operation.
+///
+/// The first retry happens `initial_delay_ms` after the first failure. Every following delay is
+/// `multiplier` times the previous one, up to `max_delay_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetryPolicy {
+    /// Number of times the operation is run, including the first one. At least 1.
+    pub max_attempts: u32,
+    /// Delay before the first retry, in milliseconds.
+    pub initial_delay_ms: u32,
+    /// Upper bound for the delay between two attempts, in milliseconds.
+    pub max_delay_ms: u32,
+    /// Factor the delay grows by after each retry. 1 retries at a fixed interval.
+    pub multiplier: u32,
+}
+
+impl RetryPolicy {
+    /// Run the operation up to `max_attempts` times, doubling the delay after each retry.
+    pub const fn exponential(max_attempts: u32, initial_delay_ms: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_delay_ms,
+            max_delay_ms: u32::MAX,
+            multiplier: 2,
+        }
+    }
+
+    /// Run the operation up to `max_attempts` times, `delay_ms` apart.
+    pub const fn fixed(max_attempts: u32, delay_ms: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_delay_ms: delay_ms,
+            max_delay_ms: delay_ms,
+            multiplier: 1,
+        }
+    }
+
+    /// Limit the delay between two attempts to `max_delay_ms`.
+    pub const fn with_max_delay(self, max_delay_ms: u32) -> Self {
+        Self { max_delay_ms, ..self }
+    }
+}
+
+/// Run a fallible operation until it succeeds, or the policy's attempts are exhausted.
+///
+/// `op` is called to create a new future for each attempt, and `delay` waits between attempts.
+/// Returns the output of the first successful attempt, or the error of the last one.
+///
+/// To limit the time an attempt can take, wrap the future in `timeout::with_timeout`, with the
+/// `time` feature, and map its error.
+pub async fn retry<D, F, Fut, T, E>(policy: RetryPolicy, delay: &mut D, mut op: F) -> Result<T, E>
+where
+    D: DelayNs,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    assert!(policy.max_attempts > 0);
+
+    let mut delay_ms = policy.initial_delay_ms.min(policy.max_delay_ms);
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt == policy.max_attempts => return Err(e),
+            Err(_) => {}
+        }
+
+        delay.delay_ms(delay_ms).await;
+        delay_ms = delay_ms.saturating_mul(policy.multiplier).min(policy.max_delay_ms);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::block_on;
+
+    /// Records the delays instead of waiting.
+    struct RecordDelay {
+        delays: [u32; 8],
+        len: usize,
+    }
+
+    impl RecordDelay {
+        fn new() -> Self {
+            Self { delays: [0; 8], len: 0 }
+        }
+
+        fn delays(&self) -> &[u32] {
+            &self.delays[..self.len]
+        }
+    }
+
+    impl DelayNs for RecordDelay {
+        async fn delay_ns(&mut self, _ns: u32) {
+            unreachable!()
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            self.delays[self.len] = ms;
+            self.len += 1;
+        }
+    }
+
+    #[test]
+    fn succeeds_after_failures() {
+        let attempts = Cell::new(0);
+        let mut delay = RecordDelay::new();
+        let res = block_on(retry(RetryPolicy::exponential(5, 10), &mut delay, || {
+            attempts.set(attempts.get() + 1);
+            let n = attempts.get();
+            async move {
+                if n == 3 {
+                    Ok(n)
+                } else {
+                    Err(n)
+                }
+            }
+        }));
+        assert_eq!(res, Ok(3));
+        assert_eq!(delay.delays(), [10, 20]);
+    }
+
+    #[test]
+    fn returns_last_error() {
+        let attempts = Cell::new(0);
+        let mut delay = RecordDelay::new();
+        let res: Result<(), u32> = block_on(retry(
+            RetryPolicy::exponential(4, 10).with_max_delay(25),
+            &mut delay,
+            || {
+                attempts.set(attempts.get() + 1);
+                let n = attempts.get();
+                async move { Err(n) }
+            },
+        ));
+        assert_eq!(res, Err(4));
+        assert_eq!(delay.delays(), [10, 20, 25]);
+    }
+
+    #[test]
+    fn fixed_delay() {
+        let mut delay = RecordDelay::new();
+        let res: Result<(), ()> = block_on(retry(RetryPolicy::fixed(3, 7), &mut delay, || async { Err(()) }));
+        assert_eq!(res, Err(()));
+        assert_eq!(delay.delays(), [7, 7]);
+    }
+}
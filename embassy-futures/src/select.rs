@@ -3,6 +3,7 @@
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
+use core::{fmt, mem};
 
 /// Result for [`select`].
 #[derive(Debug, Clone)]
@@ -19,6 +20,9 @@ pub enum Either<A, B> {
 /// This function returns a new future which polls all the futures.
 /// When one of them completes, it will complete with its result value.
 ///
+/// The futures are polled in argument order, so if both are ready at the same
+/// time `a` wins.
+///
 /// The other future is dropped.
 pub fn select<A, B>(a: A, b: B) -> Select<A, B>
 where
@@ -201,6 +205,10 @@ pub struct SelectArray<Fut, const N: usize> {
 /// completion the item resolved will be returned, along with the index of the
 /// future that was ready.
 ///
+/// The futures are always polled in order, so if several are ready at the same
+/// time the one with the lowest index wins ("biased" selection). Use
+/// [`RoundRobin::select_array`] if all futures should get a fair chance.
+///
 /// If the array is empty, the resulting future will be Pending forever.
 pub fn select_array<Fut: Future, const N: usize>(arr: [Fut; N]) -> SelectArray<Fut, N> {
     SelectArray { inner: arr }
@@ -246,6 +254,10 @@ pub struct SelectSlice<'a, Fut> {
 /// completion the item resolved will be returned, along with the index of the
 /// future that was ready.
 ///
+/// The futures are always polled in order, so if several are ready at the same
+/// time the one with the lowest index wins ("biased" selection). Use
+/// [`RoundRobin::select_slice`] if all futures should get a fair chance.
+///
 /// If the slice is empty, the resulting future will be Pending forever.
 pub fn select_slice<'a, Fut: Future>(slice: &'a mut [Fut]) -> SelectSlice<'a, Fut> {
     SelectSlice { inner: slice }
@@ -275,3 +287,328 @@ impl<'a, Fut: Future> Future for SelectSlice<'a, Fut> {
         }
     }
 }
+
+// ====================================================================
+
+/// Round-robin state for fair selection over a set of futures.
+///
+/// [`select_array`] and [`select_slice`] always poll their futures in order, which
+/// means a future that is ready all the time can starve the ones after it. `RoundRobin`
+/// remembers which index completed last and starts polling at the next one, so when
+/// selecting repeatedly over `N` uniform sources (e.g. `N` sockets in a loop), every
+/// source gets its turn.
+///
+/// # Examples
+///
+/// ```
+/// # embassy_futures::block_on(async {
+/// use embassy_futures::select::RoundRobin;
+///
+/// async fn ready(n: u32) -> u32 { n }
+///
+/// let mut rr = RoundRobin::new();
+/// assert_eq!(rr.select_array([ready(0), ready(1), ready(2)]).await, (0, 0));
+/// assert_eq!(rr.select_array([ready(0), ready(1), ready(2)]).await, (1, 1));
+/// assert_eq!(rr.select_array([ready(0), ready(1), ready(2)]).await, (2, 2));
+/// assert_eq!(rr.select_array([ready(0), ready(1), ready(2)]).await, (0, 0));
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl RoundRobin {
+    /// Create a new `RoundRobin`, starting at index 0.
+    pub const fn new() -> Self {
+        Self { next: 0 }
+    }
+
+    /// Select over an array of futures, starting at the index after the one that completed last.
+    ///
+    /// Behaves like [`select_array`] otherwise.
+    pub fn select_array<Fut: Future, const N: usize>(&mut self, arr: [Fut; N]) -> SelectArrayFair<'_, Fut, N> {
+        SelectArrayFair { inner: arr, rr: self }
+    }
+
+    /// Select over a slice of futures, starting at the index after the one that completed last.
+    ///
+    /// Behaves like [`select_slice`] otherwise.
+    pub fn select_slice<'a, Fut: Future>(&'a mut self, slice: &'a mut [Fut]) -> SelectSliceFair<'a, Fut> {
+        SelectSliceFair { inner: slice, rr: self }
+    }
+
+    /// Poll `futs` starting at `self.next`, wrapping around.
+    ///
+    /// Safety: the futures must be pinned.
+    unsafe fn poll<Fut: Future>(&mut self, futs: &mut [Fut], cx: &mut Context<'_>) -> Poll<(Fut::Output, usize)> {
+        let n = futs.len();
+        if n == 0 {
+            return Poll::Pending;
+        }
+        let start = self.next % n;
+        for k in 0..n {
+            let i = (start + k) % n;
+            if let Poll::Ready(res) = Pin::new_unchecked(&mut futs[i]).poll(cx) {
+                self.next = i + 1;
+                return Poll::Ready((res, i));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Future for the [`RoundRobin::select_array`] function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SelectArrayFair<'r, Fut, const N: usize> {
+    inner: [Fut; N],
+    rr: &'r mut RoundRobin,
+}
+
+impl<'r, Fut: Future, const N: usize> Future for SelectArrayFair<'r, Fut, N> {
+    type Output = (Fut::Output, usize);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: Since `self` is pinned, `inner` cannot move. Since `inner` cannot move,
+        // its elements also cannot move.
+        unsafe {
+            let this = self.get_unchecked_mut();
+            this.rr.poll(&mut this.inner, cx)
+        }
+    }
+}
+
+/// Future for the [`RoundRobin::select_slice`] function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SelectSliceFair<'a, Fut> {
+    inner: &'a mut [Fut],
+    rr: &'a mut RoundRobin,
+}
+
+impl<'a, Fut: Future> Future for SelectSliceFair<'a, Fut> {
+    type Output = (Fut::Output, usize);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: Since `self` is pinned, `inner` cannot move. Since `inner` cannot move,
+        // its elements also cannot move.
+        unsafe {
+            let this = self.get_unchecked_mut();
+            this.rr.poll(this.inner, cx)
+        }
+    }
+}
+
+// ====================================================================
+
+/// A future of [`race_ok`] or [`race_ok_array`], keeping its error once it failed.
+#[derive(Debug)]
+enum Attempt<Fut: Future> {
+    Running(Fut),
+    Failed(Fut::Output),
+    Gone,
+}
+
+impl<Fut: Future<Output = Result<T, E>>, T, E> Attempt<Fut> {
+    /// Poll the future if it's still running, and return its output if it succeeded.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Option<T> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Self::Running(fut) = this {
+            match unsafe { Pin::new_unchecked(fut) }.poll(cx) {
+                Poll::Ready(Ok(x)) => return Some(x),
+                Poll::Ready(Err(e)) => *this = Self::Failed(Err(e)),
+                Poll::Pending => {}
+            }
+        }
+        None
+    }
+
+    fn failed(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+
+    fn take_error(&mut self) -> E {
+        match mem::replace(self, Self::Gone) {
+            Self::Failed(Err(e)) => e,
+            _ => panic!("take_error when Attempt has not failed."),
+        }
+    }
+}
+
+impl<Fut: Future + Unpin> Unpin for Attempt<Fut> {}
+
+/// Wait for the first of two fallible futures to succeed.
+///
+/// This function returns a new future which polls both futures. When one of them completes
+/// with `Ok`, it completes with that value and drops the other one. A future that completes
+/// with `Err` is not polled again, and when both have failed, the future completes with both
+/// errors.
+///
+/// The futures are polled in argument order, so if both succeed at the same time `a` wins.
+pub fn race_ok<A, B, T, EA, EB>(a: A, b: B) -> RaceOk<A, B>
+where
+    A: Future<Output = Result<T, EA>>,
+    B: Future<Output = Result<T, EB>>,
+{
+    RaceOk {
+        a: Attempt::Running(a),
+        b: Attempt::Running(b),
+    }
+}
+
+/// Future for the [`race_ok`] function.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RaceOk<A: Future, B: Future> {
+    a: Attempt<A>,
+    b: Attempt<B>,
+}
+
+impl<A, B> fmt::Debug for RaceOk<A, B>
+where
+    A: Future + fmt::Debug,
+    A::Output: fmt::Debug,
+    B: Future + fmt::Debug,
+    B::Output: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RaceOk")
+            .field("a", &self.a)
+            .field("b", &self.b)
+            .finish()
+    }
+}
+
+impl<A, B, T, EA, EB> Future for RaceOk<A, B>
+where
+    A: Future<Output = Result<T, EA>>,
+    B: Future<Output = Result<T, EB>>,
+{
+    type Output = Result<T, (EA, EB)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Some(x) = unsafe { Pin::new_unchecked(&mut this.a) }.poll(cx) {
+            return Poll::Ready(Ok(x));
+        }
+        if let Some(x) = unsafe { Pin::new_unchecked(&mut this.b) }.poll(cx) {
+            return Poll::Ready(Ok(x));
+        }
+        if this.a.failed() && this.b.failed() {
+            return Poll::Ready(Err((this.a.take_error(), this.b.take_error())));
+        }
+        Poll::Pending
+    }
+}
+
+/// Wait for the first of an array of fallible futures to succeed.
+///
+/// Same as [`race_ok`], with the output of the successful future returned along with its
+/// index. When all futures have failed, the future completes with their errors, in order.
+///
+/// # Examples
+///
+/// ```
+/// # embassy_futures::block_on(async {
+/// use embassy_futures::select::race_ok_array;
+///
+/// async fn connect(n: u32) -> Result<u32, ()> {
+///     if n == 2 { Ok(n) } else { Err(()) }
+/// }
+///
+/// assert_eq!(race_ok_array([connect(1), connect(2), connect(3)]).await, Ok((2, 1)));
+/// assert_eq!(race_ok_array([connect(1), connect(3)]).await, Err([(), ()]));
+/// # });
+/// ```
+pub fn race_ok_array<Fut, T, E, const N: usize>(arr: [Fut; N]) -> RaceOkArray<Fut, N>
+where
+    Fut: Future<Output = Result<T, E>>,
+{
+    RaceOkArray {
+        inner: arr.map(Attempt::Running),
+    }
+}
+
+/// Future for the [`race_ok_array`] function.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RaceOkArray<Fut: Future, const N: usize> {
+    inner: [Attempt<Fut>; N],
+}
+
+impl<Fut, const N: usize> fmt::Debug for RaceOkArray<Fut, N>
+where
+    Fut: Future + fmt::Debug,
+    Fut::Output: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RaceOkArray").field("inner", &self.inner).finish()
+    }
+}
+
+impl<Fut, T, E, const N: usize> Future for RaceOkArray<Fut, N>
+where
+    Fut: Future<Output = Result<T, E>>,
+{
+    type Output = Result<(T, usize), [E; N]>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: Since `self` is pinned, `inner` cannot move. Since `inner` cannot move,
+        // its elements also cannot move. Therefore it is safe to access `inner` and pin
+        // references to the contained futures.
+        let this = unsafe { self.get_unchecked_mut() };
+        for (i, attempt) in this.inner.iter_mut().enumerate() {
+            if let Some(x) = unsafe { Pin::new_unchecked(attempt) }.poll(cx) {
+                return Poll::Ready(Ok((x, i)));
+            }
+        }
+        if this.inner.iter().all(Attempt::failed) {
+            return Poll::Ready(Err(core::array::from_fn(|i| this.inner[i].take_error())));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block_on, yield_now};
+
+    async fn attempt(yields: u32, res: Result<u32, u32>) -> Result<u32, u32> {
+        for _ in 0..yields {
+            yield_now().await;
+        }
+        res
+    }
+
+    #[test]
+    fn race_ok_both_fail() {
+        assert_eq!(block_on(race_ok(attempt(2, Err(1)), attempt(0, Err(2)))), Err((1, 2)));
+    }
+
+    #[test]
+    fn race_ok_first_success_after_failure() {
+        assert_eq!(block_on(race_ok(attempt(0, Err(1)), attempt(2, Ok(2)))), Ok(2));
+        assert_eq!(block_on(race_ok(attempt(2, Ok(1)), attempt(0, Err(2)))), Ok(1));
+    }
+
+    #[test]
+    fn race_ok_array_all_fail() {
+        let res = block_on(race_ok_array([
+            attempt(1, Err(1)),
+            attempt(0, Err(2)),
+            attempt(3, Err(3)),
+        ]));
+        assert_eq!(res, Err([1, 2, 3]));
+    }
+
+    #[test]
+    fn race_ok_array_success_after_failure() {
+        let res = block_on(race_ok_array([
+            attempt(0, Err(1)),
+            attempt(2, Ok(2)),
+            attempt(1, Err(3)),
+        ]));
+        assert_eq!(res, Ok((2, 1)));
+    }
+}
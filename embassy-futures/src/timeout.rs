@@ -0,0 +1,50 @@
+//! Limit the time a future can take.
+
+use core::future::Future;
+
+pub use embassy_time::TimeoutError;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::select::{select, Either};
+
+/// Run a future with a timeout.
+///
+/// If the future completes within `timeout`, its output is returned. Otherwise the future
+/// is dropped without being polled again, and `Err(TimeoutError)` is returned.
+///
+/// The future is polled before the timer, so if both are ready at the same time the
+/// future wins.
+pub async fn with_timeout<F: Future>(fut: F, timeout: Duration) -> Result<F::Output, TimeoutError> {
+    with_deadline(fut, Instant::now() + timeout).await
+}
+
+/// Run a future until a deadline.
+///
+/// Same as [`with_timeout`], with the time limit given as an [`Instant`].
+pub async fn with_deadline<F: Future>(fut: F, at: Instant) -> Result<F::Output, TimeoutError> {
+    match select(fut, Timer::at(at)).await {
+        Either::First(r) => Ok(r),
+        Either::Second(_) => Err(TimeoutError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::pending;
+
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn completes_in_time() {
+        assert_eq!(block_on(with_timeout(async { 5 }, Duration::from_secs(1))), Ok(5));
+    }
+
+    #[test]
+    fn times_out() {
+        assert_eq!(
+            block_on(with_deadline(pending::<()>(), Instant::from_ticks(0))),
+            Err(TimeoutError)
+        );
+    }
+}
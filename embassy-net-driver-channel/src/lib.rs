@@ -48,6 +48,7 @@ struct Shared {
     link_state: LinkState,
     waker: WakerRegistration,
     hardware_address: driver::HardwareAddress,
+    ieee802154_pan_id: Option<u16>,
 }
 
 /// Channel runner.
@@ -127,6 +128,13 @@ impl<'d, const MTU: usize> Runner<'d, MTU> {
         });
     }
 
+    /// Set the IEEE 802.15.4 PAN ID.
+    ///
+    /// `embassy-net` reads it when the stack is created, so it must be set before.
+    pub fn set_ieee802154_pan_id(&mut self, pan_id: Option<u16>) {
+        self.shared.lock(|s| s.borrow_mut().ieee802154_pan_id = pan_id);
+    }
+
     /// Wait until there is space for more inbound packets and return a slice they can be copied into.
     pub async fn rx_buf(&mut self) -> &mut [u8] {
         let p = self.rx_chan.send().await;
@@ -198,6 +206,13 @@ impl<'d> StateRunner<'d> {
             s.waker.wake();
         });
     }
+
+    /// Set the IEEE 802.15.4 PAN ID.
+    ///
+    /// `embassy-net` reads it when the stack is created, so it must be set before.
+    pub fn set_ieee802154_pan_id(&self, pan_id: Option<u16>) {
+        self.shared.lock(|s| s.borrow_mut().ieee802154_pan_id = pan_id);
+    }
 }
 
 impl<'d, const MTU: usize> RxRunner<'d, MTU> {
@@ -266,7 +281,25 @@ pub fn new<'d, const MTU: usize, const N_RX: usize, const N_TX: usize>(
     state: &'d mut State<MTU, N_RX, N_TX>,
     hardware_address: driver::HardwareAddress,
 ) -> (Runner<'d, MTU>, Device<'d, MTU>) {
-    let mut caps = Capabilities::default();
+    new_with_capabilities(state, hardware_address, Capabilities::default())
+}
+
+/// Create a channel, declaring the capabilities of the device.
+///
+/// Like [`new`], but lets the driver declare the work its hardware offloads from the stack.
+/// For example, a MAC that verifies checksums of received frames and inserts them in
+/// transmitted ones should set [`Checksum::None`](driver::Checksum::None) for the IPv4, TCP
+/// and UDP checksums, so `embassy-net` skips computing them in software.
+///
+/// `caps.max_transmission_unit` is ignored, it's always `MTU`.
+///
+/// Segmentation offload and scatter-gather transmission aren't supported yet: the stack builds
+/// each frame, at most `MTU` long, in a single packet buffer of the channel.
+pub fn new_with_capabilities<'d, const MTU: usize, const N_RX: usize, const N_TX: usize>(
+    state: &'d mut State<MTU, N_RX, N_TX>,
+    hardware_address: driver::HardwareAddress,
+    mut caps: Capabilities,
+) -> (Runner<'d, MTU>, Device<'d, MTU>) {
     caps.max_transmission_unit = MTU;
 
     // safety: this is a self-referential struct, however:
@@ -280,6 +313,7 @@ pub fn new<'d, const MTU: usize, const N_RX: usize, const N_TX: usize>(
         shared: Mutex::new(RefCell::new(Shared {
             link_state: LinkState::Down,
             hardware_address,
+            ieee802154_pan_id: None,
             waker: WakerRegistration::new(),
         })),
     });
@@ -355,6 +389,10 @@ impl<'d, const MTU: usize> embassy_net_driver::Driver for Device<'d, MTU> {
         self.shared.lock(|s| s.borrow().hardware_address)
     }
 
+    fn ieee802154_pan_id(&self) -> Option<u16> {
+        self.shared.lock(|s| s.borrow().ieee802154_pan_id)
+    }
+
     fn link_state(&mut self, cx: &mut Context) -> LinkState {
         self.shared.lock(|s| {
             let s = &mut *s.borrow_mut();
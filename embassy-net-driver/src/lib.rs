@@ -80,6 +80,39 @@ pub trait Driver {
     /// what kind of packet the sent/received bytes are, and determines some behaviors of
     /// the interface. For example, ARP/NDISC address resolution is only done for Ethernet mediums.
     fn hardware_address(&self) -> HardwareAddress;
+
+    /// Get the IEEE 802.15.4 PAN ID the device is part of.
+    ///
+    /// Only used for [`HardwareAddress::Ieee802154`] devices. The stack uses it as the destination
+    /// and source PAN ID of the frames it sends, and reads it once when it's created.
+    ///
+    /// The default implementation returns `None`, in which case frames are sent without PAN IDs.
+    fn ieee802154_pan_id(&self) -> Option<u16> {
+        None
+    }
+
+    /// Start accepting frames sent to the given multicast hardware address.
+    ///
+    /// This is called by the stack when it joins a multicast group, with the hardware address
+    /// the group maps to (for example `01:00:5e:xx:xx:xx` for IPv4 groups on Ethernet). Drivers
+    /// whose hardware filters out multicast frames must let frames to `addr` through until
+    /// [`Driver::remove_multicast_address`] is called for it.
+    ///
+    /// The default implementation does nothing, which is correct for drivers that receive all
+    /// multicast frames.
+    fn add_multicast_address(&mut self, addr: HardwareAddress) {
+        let _ = addr;
+    }
+
+    /// Stop accepting frames sent to the given multicast hardware address.
+    ///
+    /// This undoes a previous [`Driver::add_multicast_address`] call. It is only called once
+    /// the stack has left all groups mapping to `addr`.
+    ///
+    /// The default implementation does nothing.
+    fn remove_multicast_address(&mut self, addr: HardwareAddress) {
+        let _ = addr;
+    }
 }
 
 impl<T: ?Sized + Driver> Driver for &mut T {
@@ -105,6 +138,15 @@ impl<T: ?Sized + Driver> Driver for &mut T {
     fn hardware_address(&self) -> HardwareAddress {
         T::hardware_address(self)
     }
+    fn ieee802154_pan_id(&self) -> Option<u16> {
+        T::ieee802154_pan_id(self)
+    }
+    fn add_multicast_address(&mut self, addr: HardwareAddress) {
+        T::add_multicast_address(self, addr)
+    }
+    fn remove_multicast_address(&mut self, addr: HardwareAddress) {
+        T::remove_multicast_address(self, addr)
+    }
 }
 
 /// A token to receive a single network packet.
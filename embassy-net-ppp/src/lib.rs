@@ -11,7 +11,7 @@ use core::mem::MaybeUninit;
 use embassy_futures::select::{select, Either};
 use embassy_net_driver_channel as ch;
 use embassy_net_driver_channel::driver::LinkState;
-use embedded_io_async::{BufRead, Write};
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
 use ppproto::pppos::{BufferFullError, PPPoS, PPPoSAction};
 pub use ppproto::{Config, Ipv4Status};
 
@@ -157,6 +157,68 @@ pub fn new<'a, const N_RX: usize, const N_TX: usize>(state: &'a mut State<N_RX,
     (device, Runner { ch: runner })
 }
 
+/// Adds a read buffer to a serial port that only implements [`Read`].
+///
+/// [`Runner::run`] requires a [`BufRead`] serial port. UART drivers without an internal
+/// ring buffer (for example plain DMA-based ones) usually only implement [`Read`]. Wrap them
+/// in this adapter to run PPP over them. Writes are passed through unchanged.
+///
+/// [`Runner::run`] cancels pending reads when it has a packet to transmit, so the inner
+/// [`Read`] implementation should be cancel-safe, or bytes may be lost. PPP recovers from
+/// lost bytes, but it costs a retransmission.
+pub struct BufferedSerial<T, const N: usize> {
+    inner: T,
+    buf: [u8; N],
+    start: usize,
+    end: usize,
+}
+
+impl<T, const N: usize> BufferedSerial<T, N> {
+    /// Create a new `BufferedSerial` wrapping `inner`.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            buf: [0; N],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Get the wrapped serial port back. Buffered bytes not consumed yet are discarded.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: ErrorType, const N: usize> ErrorType for BufferedSerial<T, N> {
+    type Error = T::Error;
+}
+
+impl<T: Read, const N: usize> BufRead for BufferedSerial<T, N> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        if self.start == self.end {
+            let n = self.inner.read(&mut self.buf).await?;
+            self.start = 0;
+            self.end = n;
+        }
+        Ok(&self.buf[self.start..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.start = (self.start + amt).min(self.end);
+    }
+}
+
+impl<T: Write, const N: usize> Write for BufferedSerial<T, N> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.inner.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await
+    }
+}
+
 struct OnDrop<F: FnOnce()> {
     f: MaybeUninit<F>,
 }
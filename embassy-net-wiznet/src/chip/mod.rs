@@ -12,6 +12,9 @@ pub(crate) trait SealedChip {
     const COMMON_MAC: Self::Address;
     const COMMON_SOCKET_INTR: Self::Address;
     const COMMON_PHY_CFG: Self::Address;
+    const COMMON_GATEWAY: Self::Address;
+    const COMMON_SUBNET_MASK: Self::Address;
+    const COMMON_IP: Self::Address;
     const SOCKET_MODE: Self::Address;
     const SOCKET_COMMAND: Self::Address;
     const SOCKET_RXBUF_SIZE: Self::Address;
@@ -27,10 +30,15 @@ pub(crate) trait SealedChip {
 
     const BUF_SIZE: u16;
     const AUTO_WRAP: bool;
+    const SOCKETS: u8;
 
     fn rx_addr(addr: u16) -> Self::Address;
     fn tx_addr(addr: u16) -> Self::Address;
 
+    fn socket_reg(socket: u8, offset: u16) -> Self::Address;
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address;
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address;
+
     async fn bus_read<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &mut [u8])
         -> Result<(), SPI::Error>;
     async fn bus_write<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &[u8]) -> Result<(), SPI::Error>;
@@ -15,6 +15,9 @@ impl super::SealedChip for W5100S {
     const COMMON_MAC: Self::Address = 0x09;
     const COMMON_SOCKET_INTR: Self::Address = 0x16;
     const COMMON_PHY_CFG: Self::Address = 0x3c;
+    const COMMON_GATEWAY: Self::Address = 0x01;
+    const COMMON_SUBNET_MASK: Self::Address = 0x05;
+    const COMMON_IP: Self::Address = 0x0F;
 
     const SOCKET_MODE: Self::Address = SOCKET_BASE + 0x00;
     const SOCKET_COMMAND: Self::Address = SOCKET_BASE + 0x01;
@@ -31,6 +34,7 @@ impl super::SealedChip for W5100S {
 
     const BUF_SIZE: u16 = 0x2000;
     const AUTO_WRAP: bool = false;
+    const SOCKETS: u8 = 4;
 
     fn rx_addr(addr: u16) -> Self::Address {
         RX_BASE + addr
@@ -40,6 +44,18 @@ impl super::SealedChip for W5100S {
         TX_BASE + addr
     }
 
+    fn socket_reg(socket: u8, offset: u16) -> Self::Address {
+        SOCKET_BASE + socket as u16 * 0x100 + offset
+    }
+
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address {
+        RX_BASE + socket as u16 * (Self::BUF_SIZE / Self::SOCKETS as u16) + addr
+    }
+
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address {
+        TX_BASE + socket as u16 * (Self::BUF_SIZE / Self::SOCKETS as u16) + addr
+    }
+
     async fn bus_read<SPI: SpiDevice>(
         spi: &mut SPI,
         address: Self::Address,
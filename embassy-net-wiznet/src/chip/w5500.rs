@@ -1,11 +1,23 @@
 use embedded_hal_async::spi::{Operation, SpiDevice};
 
-#[repr(u8)]
-pub enum RegisterBlock {
-    Common = 0x00,
-    Socket0 = 0x01,
-    TxBuf = 0x02,
-    RxBuf = 0x03,
+/// Block select bits of the SPI control phase.
+#[derive(Clone, Copy)]
+pub struct RegisterBlock(u8);
+
+impl RegisterBlock {
+    const COMMON: Self = Self(0x00);
+
+    const fn socket(n: u8) -> Self {
+        Self(n << 2 | 0x01)
+    }
+
+    const fn tx_buf(n: u8) -> Self {
+        Self(n << 2 | 0x02)
+    }
+
+    const fn rx_buf(n: u8) -> Self {
+        Self(n << 2 | 0x03)
+    }
 }
 
 /// Wiznet W5500 chip.
@@ -15,33 +27,49 @@ impl super::Chip for W5500 {}
 impl super::SealedChip for W5500 {
     type Address = (RegisterBlock, u16);
 
-    const COMMON_MODE: Self::Address = (RegisterBlock::Common, 0x00);
-    const COMMON_MAC: Self::Address = (RegisterBlock::Common, 0x09);
-    const COMMON_SOCKET_INTR: Self::Address = (RegisterBlock::Common, 0x18);
-    const COMMON_PHY_CFG: Self::Address = (RegisterBlock::Common, 0x2E);
-
-    const SOCKET_MODE: Self::Address = (RegisterBlock::Socket0, 0x00);
-    const SOCKET_COMMAND: Self::Address = (RegisterBlock::Socket0, 0x01);
-    const SOCKET_RXBUF_SIZE: Self::Address = (RegisterBlock::Socket0, 0x1E);
-    const SOCKET_TXBUF_SIZE: Self::Address = (RegisterBlock::Socket0, 0x1F);
-    const SOCKET_TX_FREE_SIZE: Self::Address = (RegisterBlock::Socket0, 0x20);
-    const SOCKET_TX_DATA_WRITE_PTR: Self::Address = (RegisterBlock::Socket0, 0x24);
-    const SOCKET_RECVD_SIZE: Self::Address = (RegisterBlock::Socket0, 0x26);
-    const SOCKET_RX_DATA_READ_PTR: Self::Address = (RegisterBlock::Socket0, 0x28);
-    const SOCKET_INTR_MASK: Self::Address = (RegisterBlock::Socket0, 0x2C);
-    const SOCKET_INTR: Self::Address = (RegisterBlock::Socket0, 0x02);
+    const COMMON_MODE: Self::Address = (RegisterBlock::COMMON, 0x00);
+    const COMMON_MAC: Self::Address = (RegisterBlock::COMMON, 0x09);
+    const COMMON_SOCKET_INTR: Self::Address = (RegisterBlock::COMMON, 0x18);
+    const COMMON_PHY_CFG: Self::Address = (RegisterBlock::COMMON, 0x2E);
+    const COMMON_GATEWAY: Self::Address = (RegisterBlock::COMMON, 0x01);
+    const COMMON_SUBNET_MASK: Self::Address = (RegisterBlock::COMMON, 0x05);
+    const COMMON_IP: Self::Address = (RegisterBlock::COMMON, 0x0F);
+
+    const SOCKET_MODE: Self::Address = (RegisterBlock::socket(0), 0x00);
+    const SOCKET_COMMAND: Self::Address = (RegisterBlock::socket(0), 0x01);
+    const SOCKET_RXBUF_SIZE: Self::Address = (RegisterBlock::socket(0), 0x1E);
+    const SOCKET_TXBUF_SIZE: Self::Address = (RegisterBlock::socket(0), 0x1F);
+    const SOCKET_TX_FREE_SIZE: Self::Address = (RegisterBlock::socket(0), 0x20);
+    const SOCKET_TX_DATA_WRITE_PTR: Self::Address = (RegisterBlock::socket(0), 0x24);
+    const SOCKET_RECVD_SIZE: Self::Address = (RegisterBlock::socket(0), 0x26);
+    const SOCKET_RX_DATA_READ_PTR: Self::Address = (RegisterBlock::socket(0), 0x28);
+    const SOCKET_INTR_MASK: Self::Address = (RegisterBlock::socket(0), 0x2C);
+    const SOCKET_INTR: Self::Address = (RegisterBlock::socket(0), 0x02);
 
     const SOCKET_MODE_VALUE: u8 = (1 << 2) | (1 << 7);
 
     const BUF_SIZE: u16 = 0x4000;
     const AUTO_WRAP: bool = true;
+    const SOCKETS: u8 = 8;
 
     fn rx_addr(addr: u16) -> Self::Address {
-        (RegisterBlock::RxBuf, addr)
+        (RegisterBlock::rx_buf(0), addr)
     }
 
     fn tx_addr(addr: u16) -> Self::Address {
-        (RegisterBlock::TxBuf, addr)
+        (RegisterBlock::tx_buf(0), addr)
+    }
+
+    fn socket_reg(socket: u8, offset: u16) -> Self::Address {
+        (RegisterBlock::socket(socket), offset)
+    }
+
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address {
+        (RegisterBlock::rx_buf(socket), addr)
+    }
+
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address {
+        (RegisterBlock::tx_buf(socket), addr)
     }
 
     async fn bus_read<SPI: SpiDevice>(
@@ -49,8 +77,9 @@ impl super::SealedChip for W5500 {
         address: Self::Address,
         data: &mut [u8],
     ) -> Result<(), SPI::Error> {
-        let address_phase = address.1.to_be_bytes();
-        let control_phase = [(address.0 as u8) << 3];
+        let (RegisterBlock(block), offset) = address;
+        let address_phase = offset.to_be_bytes();
+        let control_phase = [block << 3];
         let operations = &mut [
             Operation::Write(&address_phase),
             Operation::Write(&control_phase),
@@ -60,8 +89,9 @@ impl super::SealedChip for W5500 {
     }
 
     async fn bus_write<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &[u8]) -> Result<(), SPI::Error> {
-        let address_phase = address.1.to_be_bytes();
-        let control_phase = [(address.0 as u8) << 3 | 0b0000_0100];
+        let (RegisterBlock(block), offset) = address;
+        let address_phase = offset.to_be_bytes();
+        let control_phase = [block << 3 | 0b0000_0100];
         let data_phase = data;
         let operations = &mut [
             Operation::Write(&address_phase[..]),
@@ -5,6 +5,7 @@
 
 pub mod chip;
 mod device;
+pub mod offload;
 
 use embassy_futures::select::{select3, Either3};
 use embassy_net_driver_channel as ch;
@@ -96,15 +97,7 @@ pub async fn new<'a, const N_RX: usize, const N_TX: usize, C: Chip, SPI: SpiDevi
     int: INT,
     mut reset: RST,
 ) -> (Device<'a>, Runner<'a, C, SPI, INT, RST>) {
-    // Reset the chip.
-    reset.set_low().ok();
-    // Ensure the reset is registered.
-    Timer::after_millis(1).await;
-    reset.set_high().ok();
-
-    // Wait for PLL lock. Some chips are slower than others.
-    // Slowest is w5100s which is 100ms, so let's just wait that.
-    Timer::after_millis(100).await;
+    reset_chip(&mut reset).await;
 
     let mac = WiznetDevice::new(spi_dev, mac_addr).await.unwrap();
 
@@ -119,3 +112,15 @@ pub async fn new<'a, const N_RX: usize, const N_TX: usize, C: Chip, SPI: SpiDevi
         },
     )
 }
+
+async fn reset_chip<RST: OutputPin>(reset: &mut RST) {
+    // Reset the chip.
+    reset.set_low().ok();
+    // Ensure the reset is registered.
+    Timer::after_millis(1).await;
+    reset.set_high().ok();
+
+    // Wait for PLL lock. Some chips are slower than others.
+    // Slowest is w5100s which is 100ms, so let's just wait that.
+    Timer::after_millis(100).await;
+}
@@ -0,0 +1,447 @@
+//! TCP offload mode.
+//!
+//! Instead of tunneling raw ethernet frames into `embassy-net`, this mode uses the TCP/IP stack
+//! built into the chip. Each [`TcpSocket`] maps to one of the chip's hardware sockets, and the
+//! chip's own buffer memory is used for the socket buffers, so no network stack and no
+//! packet buffers are needed on the MCU.
+//!
+//! The buffer memory is split evenly between the hardware sockets, 2kB of RX and 2kB of TX
+//! buffer each on both the W5500 (8 sockets) and the W5100S (4 sockets).
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::spi::SpiDevice;
+
+use crate::chip::Chip;
+
+// Socket register offsets, common to all supported chips.
+const SN_MODE: u16 = 0x00;
+const SN_COMMAND: u16 = 0x01;
+const SN_INTR: u16 = 0x02;
+const SN_STATUS: u16 = 0x03;
+const SN_PORT: u16 = 0x04;
+const SN_DEST_IP: u16 = 0x0C;
+const SN_DEST_PORT: u16 = 0x10;
+const SN_RXBUF_SIZE: u16 = 0x1E;
+const SN_TXBUF_SIZE: u16 = 0x1F;
+const SN_TX_FREE_SIZE: u16 = 0x20;
+const SN_TX_WRITE_PTR: u16 = 0x24;
+const SN_RECVD_SIZE: u16 = 0x26;
+const SN_RX_READ_PTR: u16 = 0x28;
+
+const MODE_TCP: u8 = 0x01;
+
+const INTR_TIMEOUT: u8 = 1 << 3;
+const INTR_SEND_OK: u8 = 1 << 4;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+#[repr(u8)]
+enum Command {
+    Open = 0x01,
+    Listen = 0x02,
+    Connect = 0x04,
+    Disconnect = 0x08,
+    Close = 0x10,
+    Send = 0x20,
+    Receive = 0x40,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Status {
+    Closed = 0x00,
+    Init = 0x13,
+    Listen = 0x14,
+    Established = 0x17,
+    CloseWait = 0x1C,
+}
+
+/// IP configuration of the chip's TCP/IP stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// IP address.
+    pub address: [u8; 4],
+    /// Subnet mask.
+    pub subnet_mask: [u8; 4],
+    /// Default gateway.
+    pub gateway: [u8; 4],
+}
+
+/// Error returned by offload socket operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// SPI bus error.
+    Spi(E),
+    /// The connection was reset by the remote end, or timed out.
+    ConnectionReset,
+    /// The socket is not connected.
+    NotConnected,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Self::Spi(e)
+    }
+}
+
+impl<E: core::fmt::Debug> embedded_io_async::Error for Error<E> {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Self::Spi(_) => embedded_io_async::ErrorKind::Other,
+            Self::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
+            Self::NotConnected => embedded_io_async::ErrorKind::NotConnected,
+        }
+    }
+}
+
+struct Shared<SPI> {
+    spi: Mutex<NoopRawMutex, SPI>,
+    used: Cell<u8>,
+    next_port: Cell<u16>,
+}
+
+/// Wiznet chip in TCP offload mode.
+pub struct Offload<C: Chip, SPI: SpiDevice, RST: OutputPin> {
+    shared: Shared<SPI>,
+    _reset: RST,
+    _phantom: PhantomData<C>,
+}
+
+impl<C: Chip, SPI: SpiDevice, RST: OutputPin> Offload<C, SPI, RST> {
+    /// Reset and initialize the chip in TCP offload mode.
+    pub async fn new(mac_addr: [u8; 6], config: Config, mut spi: SPI, mut reset: RST) -> Result<Self, SPI::Error> {
+        crate::reset_chip(&mut reset).await;
+
+        // Reset device
+        C::bus_write(&mut spi, C::COMMON_MODE, &[0x80]).await?;
+
+        C::bus_write(&mut spi, C::COMMON_MAC, &mac_addr).await?;
+        C::bus_write(&mut spi, C::COMMON_IP, &config.address).await?;
+        C::bus_write(&mut spi, C::COMMON_SUBNET_MASK, &config.subnet_mask).await?;
+        C::bus_write(&mut spi, C::COMMON_GATEWAY, &config.gateway).await?;
+
+        // Split the buffer memory evenly between all sockets.
+        let buf_kbs = (socket_buf_size::<C>() / 1024) as u8;
+        for socket in 0..C::SOCKETS {
+            C::bus_write(&mut spi, C::socket_reg(socket, SN_TXBUF_SIZE), &[buf_kbs]).await?;
+            C::bus_write(&mut spi, C::socket_reg(socket, SN_RXBUF_SIZE), &[buf_kbs]).await?;
+        }
+
+        Ok(Self {
+            shared: Shared {
+                spi: Mutex::new(spi),
+                used: Cell::new(0),
+                next_port: Cell::new(EPHEMERAL_PORT_START),
+            },
+            _reset: reset,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Allocate a TCP socket.
+    ///
+    /// Returns `None` if all hardware sockets are in use.
+    pub fn tcp_socket(&self) -> Option<TcpSocket<'_, C, SPI>> {
+        let used = self.shared.used.get();
+        let socket = (0..C::SOCKETS).find(|n| used & (1 << n) == 0)?;
+        self.shared.used.set(used | 1 << socket);
+
+        Some(TcpSocket {
+            shared: &self.shared,
+            socket,
+            send_pending: false,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Check if the ethernet link is up.
+    pub async fn is_link_up(&self) -> bool {
+        let mut spi = self.shared.spi.lock().await;
+        let mut link = [0];
+        C::bus_read(&mut *spi, C::COMMON_PHY_CFG, &mut link).await.ok();
+        link[0] & 1 == 1
+    }
+}
+
+fn socket_buf_size<C: Chip>() -> u16 {
+    C::BUF_SIZE / C::SOCKETS as u16
+}
+
+/// TCP socket using one of the chip's hardware sockets.
+///
+/// Dropping the socket frees the hardware socket, without closing the connection. Use
+/// [`close`](Self::close) or [`abort`](Self::abort) first.
+pub struct TcpSocket<'d, C: Chip, SPI: SpiDevice> {
+    shared: &'d Shared<SPI>,
+    socket: u8,
+    send_pending: bool,
+    _phantom: PhantomData<C>,
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> TcpSocket<'d, C, SPI> {
+    async fn read_reg(&self, offset: u16, data: &mut [u8]) -> Result<(), SPI::Error> {
+        let mut spi = self.shared.spi.lock().await;
+        C::bus_read(&mut *spi, C::socket_reg(self.socket, offset), data).await
+    }
+
+    async fn write_reg(&self, offset: u16, data: &[u8]) -> Result<(), SPI::Error> {
+        let mut spi = self.shared.spi.lock().await;
+        C::bus_write(&mut *spi, C::socket_reg(self.socket, offset), data).await
+    }
+
+    async fn read_reg_u16(&self, offset: u16) -> Result<u16, SPI::Error> {
+        loop {
+            // Wait until two sequential reads are equal
+            let mut res0 = [0u8; 2];
+            self.read_reg(offset, &mut res0).await?;
+            let mut res1 = [0u8; 2];
+            self.read_reg(offset, &mut res1).await?;
+            if res0 == res1 {
+                break Ok(u16::from_be_bytes(res0));
+            }
+        }
+    }
+
+    async fn command(&self, command: Command) -> Result<(), SPI::Error> {
+        self.write_reg(SN_COMMAND, &[command as u8]).await?;
+        // The command register clears itself once the command is accepted.
+        loop {
+            let mut data = [0];
+            self.read_reg(SN_COMMAND, &mut data).await?;
+            if data[0] == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn status(&self) -> Result<u8, SPI::Error> {
+        let mut data = [0];
+        self.read_reg(SN_STATUS, &mut data).await?;
+        Ok(data[0])
+    }
+
+    async fn open(&mut self, port: u16) -> Result<(), SPI::Error> {
+        self.command(Command::Close).await?;
+        self.write_reg(SN_INTR, &[0xff]).await?;
+        self.send_pending = false;
+
+        self.write_reg(SN_MODE, &[MODE_TCP]).await?;
+        self.write_reg(SN_PORT, &port.to_be_bytes()).await?;
+        self.command(Command::Open).await?;
+        while self.status().await? != Status::Init as u8 {}
+        Ok(())
+    }
+
+    async fn wait_status(&self, status: Status) -> Result<(), Error<SPI::Error>> {
+        loop {
+            let current = self.status().await?;
+            if current == status as u8 {
+                return Ok(());
+            }
+            if current == Status::Closed as u8 {
+                return Err(Error::ConnectionReset);
+            }
+            Timer::after(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Connect to a remote host, from an ephemeral local port.
+    pub async fn connect(&mut self, remote_address: [u8; 4], remote_port: u16) -> Result<(), Error<SPI::Error>> {
+        let port = self.shared.next_port.get();
+        self.shared
+            .next_port
+            .set(port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START));
+
+        self.open(port).await?;
+        self.write_reg(SN_DEST_IP, &remote_address).await?;
+        self.write_reg(SN_DEST_PORT, &remote_port.to_be_bytes()).await?;
+        self.command(Command::Connect).await?;
+        self.wait_status(Status::Established).await
+    }
+
+    /// Listen on a local port and wait for a remote host to connect.
+    pub async fn accept(&mut self, port: u16) -> Result<(), Error<SPI::Error>> {
+        self.open(port).await?;
+        self.command(Command::Listen).await?;
+        if self.status().await? != Status::Listen as u8 {
+            return Err(Error::ConnectionReset);
+        }
+        self.wait_status(Status::Established).await
+    }
+
+    /// Get the address and port of the remote host.
+    pub async fn remote_endpoint(&self) -> Result<([u8; 4], u16), Error<SPI::Error>> {
+        let mut address = [0; 4];
+        self.read_reg(SN_DEST_IP, &mut address).await?;
+        let port = self.read_reg_u16(SN_DEST_PORT).await?;
+        Ok((address, port))
+    }
+
+    /// Read data from the socket.
+    ///
+    /// Returns how many bytes were read, or 0 if the remote end has closed the connection
+    /// and all data has been read.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<SPI::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = loop {
+            let len = self.read_reg_u16(SN_RECVD_SIZE).await?;
+            if len != 0 {
+                break len as usize;
+            }
+            match self.status().await? {
+                s if s == Status::Established as u8 => {}
+                s if s == Status::CloseWait as u8 => return Ok(0),
+                _ => return Err(Error::ConnectionReset),
+            }
+            Timer::after(POLL_INTERVAL).await;
+        };
+        let len = len.min(buf.len());
+
+        let ptr = self.read_reg_u16(SN_RX_READ_PTR).await?;
+        {
+            let mut spi = self.shared.spi.lock().await;
+            if C::AUTO_WRAP {
+                C::bus_read(&mut *spi, C::socket_rx_addr(self.socket, ptr), &mut buf[..len]).await?;
+            } else {
+                let size = socket_buf_size::<C>();
+                let addr = ptr % size;
+                let n = ((size - addr) as usize).min(len);
+                C::bus_read(&mut *spi, C::socket_rx_addr(self.socket, addr), &mut buf[..n]).await?;
+                if n < len {
+                    C::bus_read(&mut *spi, C::socket_rx_addr(self.socket, 0), &mut buf[n..len]).await?;
+                }
+            }
+        }
+
+        self.write_reg(SN_RX_READ_PTR, &ptr.wrapping_add(len as u16).to_be_bytes())
+            .await?;
+        self.command(Command::Receive).await?;
+        Ok(len)
+    }
+
+    /// Write data to the socket.
+    ///
+    /// Returns how many bytes were written, which may be less than `buf.len()` if the
+    /// transmit buffer is full.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error<SPI::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Only one send can be in flight.
+        self.flush().await?;
+
+        let len = loop {
+            match self.status().await? {
+                s if s == Status::Established as u8 || s == Status::CloseWait as u8 => {}
+                _ => return Err(Error::NotConnected),
+            }
+            let free = self.read_reg_u16(SN_TX_FREE_SIZE).await?;
+            if free != 0 {
+                break (free as usize).min(buf.len());
+            }
+            Timer::after(POLL_INTERVAL).await;
+        };
+
+        let ptr = self.read_reg_u16(SN_TX_WRITE_PTR).await?;
+        {
+            let mut spi = self.shared.spi.lock().await;
+            if C::AUTO_WRAP {
+                C::bus_write(&mut *spi, C::socket_tx_addr(self.socket, ptr), &buf[..len]).await?;
+            } else {
+                let size = socket_buf_size::<C>();
+                let addr = ptr % size;
+                let n = ((size - addr) as usize).min(len);
+                C::bus_write(&mut *spi, C::socket_tx_addr(self.socket, addr), &buf[..n]).await?;
+                if n < len {
+                    C::bus_write(&mut *spi, C::socket_tx_addr(self.socket, 0), &buf[n..len]).await?;
+                }
+            }
+        }
+
+        self.write_reg(SN_TX_WRITE_PTR, &ptr.wrapping_add(len as u16).to_be_bytes())
+            .await?;
+        self.command(Command::Send).await?;
+        self.send_pending = true;
+        Ok(len)
+    }
+
+    /// Wait until the data handed to the chip has been sent and acknowledged.
+    pub async fn flush(&mut self) -> Result<(), Error<SPI::Error>> {
+        while self.send_pending {
+            let mut intr = [0];
+            self.read_reg(SN_INTR, &mut intr).await?;
+            if intr[0] & INTR_SEND_OK != 0 {
+                self.write_reg(SN_INTR, &[INTR_SEND_OK]).await?;
+                self.send_pending = false;
+            } else if intr[0] & INTR_TIMEOUT != 0 {
+                self.write_reg(SN_INTR, &[INTR_TIMEOUT]).await?;
+                self.send_pending = false;
+                return Err(Error::ConnectionReset);
+            } else {
+                Timer::after(POLL_INTERVAL).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gracefully close the connection, and wait for the remote end to close it too.
+    pub async fn close(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.status().await? != Status::Closed as u8 {
+            self.command(Command::Disconnect).await?;
+            match self.wait_status(Status::Closed).await {
+                Ok(()) | Err(Error::ConnectionReset) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.send_pending = false;
+        Ok(())
+    }
+
+    /// Immediately close the socket, without a graceful shutdown.
+    pub async fn abort(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.command(Command::Close).await?;
+        self.send_pending = false;
+        Ok(())
+    }
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> Drop for TcpSocket<'d, C, SPI> {
+    fn drop(&mut self) {
+        let used = self.shared.used.get();
+        self.shared.used.set(used & !(1 << self.socket));
+    }
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> embedded_io_async::ErrorType for TcpSocket<'d, C, SPI> {
+    type Error = Error<SPI::Error>;
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> embedded_io_async::Read for TcpSocket<'d, C, SPI> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        TcpSocket::read(self, buf).await
+    }
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> embedded_io_async::Write for TcpSocket<'d, C, SPI> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        TcpSocket::write(self, buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        TcpSocket::flush(self).await
+    }
+}
@@ -0,0 +1,1671 @@
+//! CoAP client and server (RFC 7252).
+//!
+//! Supported:
+//! - Confirmable requests, with retransmission and exponential back-off.
+//! - Piggybacked and separate responses, matched by token.
+//! - Block-wise transfers (RFC 7959): large request payloads are sent with Block1, large
+//!   responses are received with Block2 and reassembled in the user buffer.
+//! - Observe (RFC 7641), on both sides.
+//!
+//! The message layer ([`Message`], [`MessageBuilder`]) is public, for protocols built on top of
+//! CoAP like LwM2M.
+
+use embassy_time::{with_deadline, Duration, Instant};
+use heapless::{String, Vec};
+
+use crate::udp::{BindError, RecvError, SendError, UdpSocket};
+use crate::IpEndpoint;
+
+/// Default CoAP port.
+pub const COAP_PORT: u16 = 5683;
+
+/// Maximum length of a token.
+pub const MAX_TOKEN_LEN: usize = 8;
+
+/// Maximum length of a path, with its segments joined by `/`.
+pub const MAX_PATH_LEN: usize = 64;
+
+/// Maximum length of a query, with its parameters joined by `&`.
+pub const MAX_QUERY_LEN: usize = 64;
+
+/// Maximum number of observers registered on a [`CoapServer`].
+pub const MAX_OBSERVERS: usize = 4;
+
+/// Option numbers.
+pub mod option {
+    /// If-Match
+    pub const IF_MATCH: u16 = 1;
+    /// Uri-Host
+    pub const URI_HOST: u16 = 3;
+    /// ETag
+    pub const ETAG: u16 = 4;
+    /// If-None-Match
+    pub const IF_NONE_MATCH: u16 = 5;
+    /// Observe
+    pub const OBSERVE: u16 = 6;
+    /// Uri-Port
+    pub const URI_PORT: u16 = 7;
+    /// Location-Path
+    pub const LOCATION_PATH: u16 = 8;
+    /// Uri-Path
+    pub const URI_PATH: u16 = 11;
+    /// Content-Format
+    pub const CONTENT_FORMAT: u16 = 12;
+    /// Max-Age
+    pub const MAX_AGE: u16 = 14;
+    /// Uri-Query
+    pub const URI_QUERY: u16 = 15;
+    /// Accept
+    pub const ACCEPT: u16 = 17;
+    /// Location-Query
+    pub const LOCATION_QUERY: u16 = 20;
+    /// Block2
+    pub const BLOCK2: u16 = 23;
+    /// Block1
+    pub const BLOCK1: u16 = 27;
+    /// Size2
+    pub const SIZE2: u16 = 28;
+    /// Size1
+    pub const SIZE1: u16 = 60;
+}
+
+/// Content formats.
+pub mod content_format {
+    /// `text/plain; charset=utf-8`
+    pub const TEXT_PLAIN: u16 = 0;
+    /// `application/link-format`
+    pub const LINK_FORMAT: u16 = 40;
+    /// `application/octet-stream`
+    pub const OCTET_STREAM: u16 = 42;
+    /// `application/json`
+    pub const JSON: u16 = 50;
+    /// `application/cbor`
+    pub const CBOR: u16 = 60;
+}
+
+/// Errors returned by the CoAP client and server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The socket could not be bound.
+    Bind(BindError),
+    /// A message could not be sent.
+    Send(SendError),
+    /// A message could not be received.
+    Recv(RecvError),
+    /// No response was received.
+    Timeout,
+    /// The peer rejected the message with a reset.
+    Reset,
+    /// A received message is malformed.
+    Malformed,
+    /// Options were not added in increasing order.
+    OptionOrder,
+    /// A message or payload doesn't fit in the buffer.
+    BufferTooSmall,
+}
+
+/// Message type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    /// Confirmable.
+    Confirmable = 0,
+    /// Non-confirmable.
+    NonConfirmable = 1,
+    /// Acknowledgement.
+    Acknowledgement = 2,
+    /// Reset.
+    Reset = 3,
+}
+
+/// Request method or response code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Code(pub u8);
+
+impl Code {
+    /// Empty message.
+    pub const EMPTY: Code = Code::new(0, 0);
+    /// GET
+    pub const GET: Code = Code::new(0, 1);
+    /// POST
+    pub const POST: Code = Code::new(0, 2);
+    /// PUT
+    pub const PUT: Code = Code::new(0, 3);
+    /// DELETE
+    pub const DELETE: Code = Code::new(0, 4);
+    /// 2.01 Created
+    pub const CREATED: Code = Code::new(2, 1);
+    /// 2.02 Deleted
+    pub const DELETED: Code = Code::new(2, 2);
+    /// 2.03 Valid
+    pub const VALID: Code = Code::new(2, 3);
+    /// 2.04 Changed
+    pub const CHANGED: Code = Code::new(2, 4);
+    /// 2.05 Content
+    pub const CONTENT: Code = Code::new(2, 5);
+    /// 2.31 Continue
+    pub const CONTINUE: Code = Code::new(2, 31);
+    /// 4.00 Bad Request
+    pub const BAD_REQUEST: Code = Code::new(4, 0);
+    /// 4.01 Unauthorized
+    pub const UNAUTHORIZED: Code = Code::new(4, 1);
+    /// 4.02 Bad Option
+    pub const BAD_OPTION: Code = Code::new(4, 2);
+    /// 4.03 Forbidden
+    pub const FORBIDDEN: Code = Code::new(4, 3);
+    /// 4.04 Not Found
+    pub const NOT_FOUND: Code = Code::new(4, 4);
+    /// 4.05 Method Not Allowed
+    pub const METHOD_NOT_ALLOWED: Code = Code::new(4, 5);
+    /// 4.08 Request Entity Incomplete
+    pub const REQUEST_ENTITY_INCOMPLETE: Code = Code::new(4, 8);
+    /// 4.13 Request Entity Too Large
+    pub const REQUEST_ENTITY_TOO_LARGE: Code = Code::new(4, 13);
+    /// 4.15 Unsupported Content-Format
+    pub const UNSUPPORTED_CONTENT_FORMAT: Code = Code::new(4, 15);
+    /// 5.00 Internal Server Error
+    pub const INTERNAL_SERVER_ERROR: Code = Code::new(5, 0);
+    /// 5.01 Not Implemented
+    pub const NOT_IMPLEMENTED: Code = Code::new(5, 1);
+    /// 5.03 Service Unavailable
+    pub const SERVICE_UNAVAILABLE: Code = Code::new(5, 3);
+
+    /// Create a code from its class and detail, e.g. `Code::new(2, 5)` for 2.05.
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Self(class << 5 | detail)
+    }
+
+    /// Get the class of the code.
+    pub const fn class(&self) -> u8 {
+        self.0 >> 5
+    }
+
+    /// Get the detail of the code.
+    pub const fn detail(&self) -> u8 {
+        self.0 & 0x1f
+    }
+
+    /// Get whether this is a request method.
+    pub const fn is_request(&self) -> bool {
+        self.class() == 0 && self.0 != 0
+    }
+
+    /// Get whether this is a success response code.
+    pub const fn is_success(&self) -> bool {
+        self.class() == 2
+    }
+}
+
+/// Value of a Block1 or Block2 option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Block {
+    /// Block number.
+    pub num: u32,
+    /// Whether more blocks follow.
+    pub more: bool,
+    /// Size exponent: the block size is `16 << szx`.
+    pub szx: u8,
+}
+
+impl Block {
+    /// Get the size of the block.
+    pub const fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Get the offset of the block.
+    pub const fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    fn decode(value: u32) -> Option<Self> {
+        let szx = (value & 0x07) as u8;
+        if szx == 7 {
+            return None;
+        }
+        Some(Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    fn encode(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+}
+
+/// Get the size exponent of a block size, which must be a power of two from 16 to 1024.
+fn szx(block_size: usize) -> u8 {
+    (block_size.clamp(16, 1024).trailing_zeros() - 4) as u8
+}
+
+fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+}
+
+/// Option delta, value and encoded length of an option.
+type RawOption<'b> = (u16, &'b [u8], usize);
+
+/// Parse the option at the start of `data`.
+///
+/// Returns `None` at the end of the options.
+fn parse_option(data: &[u8]) -> Result<Option<RawOption<'_>>, Error> {
+    let Some(&first) = data.first() else {
+        return Ok(None);
+    };
+    if first == 0xff {
+        return Ok(None);
+    }
+
+    let mut pos = 1;
+    let mut extended = |nibble: u8| -> Result<u16, Error> {
+        match nibble {
+            0..=12 => Ok(nibble as u16),
+            13 => {
+                let v = *data.get(pos).ok_or(Error::Malformed)?;
+                pos += 1;
+                Ok(v as u16 + 13)
+            }
+            14 => {
+                let v = data.get(pos..pos + 2).ok_or(Error::Malformed)?;
+                pos += 2;
+                u16::from_be_bytes([v[0], v[1]])
+                    .checked_add(269)
+                    .ok_or(Error::Malformed)
+            }
+            _ => Err(Error::Malformed),
+        }
+    };
+    let delta = extended(first >> 4)?;
+    let len = extended(first & 0x0f)? as usize;
+
+    let value = data.get(pos..pos + len).ok_or(Error::Malformed)?;
+    Ok(Some((delta, value, pos + len)))
+}
+
+/// Iterator over the options of a message, yielding their numbers and values.
+#[derive(Clone)]
+pub struct Options<'b> {
+    data: &'b [u8],
+    number: u16,
+}
+
+impl<'b> Iterator for Options<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Options are validated when parsing the message.
+        let (delta, value, len) = parse_option(self.data).ok()??;
+        self.data = &self.data[len..];
+        self.number += delta;
+        Some((self.number, value))
+    }
+}
+
+/// A received CoAP message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message<'b> {
+    /// Message type.
+    pub ty: MessageType,
+    /// Request method or response code.
+    pub code: Code,
+    /// Message ID.
+    pub message_id: u16,
+    /// Token.
+    pub token: &'b [u8],
+    options: &'b [u8],
+    /// Payload.
+    pub payload: &'b [u8],
+}
+
+impl<'b> Message<'b> {
+    /// Parse a message.
+    pub fn parse(data: &'b [u8]) -> Result<Self, Error> {
+        if data.len() < 4 || data[0] >> 6 != 1 {
+            return Err(Error::Malformed);
+        }
+        let ty = match (data[0] >> 4) & 0x03 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_len = (data[0] & 0x0f) as usize;
+        if token_len > MAX_TOKEN_LEN {
+            return Err(Error::Malformed);
+        }
+        let token = data.get(4..4 + token_len).ok_or(Error::Malformed)?;
+
+        let rest = &data[4 + token_len..];
+        let mut pos = 0;
+        let mut number: u16 = 0;
+        while let Some((delta, _, len)) = parse_option(&rest[pos..])? {
+            number = number.checked_add(delta).ok_or(Error::Malformed)?;
+            pos += len;
+        }
+        let options = &rest[..pos];
+        let payload = match rest.get(pos) {
+            // A payload marker must be followed by a payload.
+            Some(_) if pos + 1 == rest.len() => return Err(Error::Malformed),
+            Some(_) => &rest[pos + 1..],
+            None => &[],
+        };
+
+        Ok(Self {
+            ty,
+            code: Code(data[1]),
+            message_id: u16::from_be_bytes([data[2], data[3]]),
+            token,
+            options,
+            payload,
+        })
+    }
+
+    /// Iterate over the options.
+    pub fn options(&self) -> Options<'b> {
+        Options {
+            data: self.options,
+            number: 0,
+        }
+    }
+
+    /// Get the value of the first option with the given number.
+    pub fn option(&self, number: u16) -> Option<&'b [u8]> {
+        self.options().find(|(n, _)| *n == number).map(|(_, v)| v)
+    }
+
+    /// Get the value of the first option with the given number, as an unsigned integer.
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(decode_uint)
+    }
+
+    /// Get the Content-Format option.
+    pub fn content_format(&self) -> Option<u16> {
+        self.uint_option(option::CONTENT_FORMAT).map(|v| v as u16)
+    }
+
+    /// Get the Observe option.
+    pub fn observe(&self) -> Option<u32> {
+        self.uint_option(option::OBSERVE)
+    }
+
+    /// Get the Block1 option.
+    pub fn block1(&self) -> Option<Block> {
+        self.uint_option(option::BLOCK1).and_then(Block::decode)
+    }
+
+    /// Get the Block2 option.
+    pub fn block2(&self) -> Option<Block> {
+        self.uint_option(option::BLOCK2).and_then(Block::decode)
+    }
+}
+
+/// Builder for a CoAP message.
+///
+/// Options must be added in increasing order of their numbers.
+pub struct MessageBuilder<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    number: u16,
+}
+
+impl<'b> MessageBuilder<'b> {
+    /// Start a message in `buf`.
+    pub fn new(buf: &'b mut [u8], ty: MessageType, code: Code, message_id: u16, token: &[u8]) -> Result<Self, Error> {
+        if token.len() > MAX_TOKEN_LEN || buf.len() < 4 + token.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        buf[0] = 0x40 | (ty as u8) << 4 | token.len() as u8;
+        buf[1] = code.0;
+        buf[2..4].copy_from_slice(&message_id.to_be_bytes());
+        buf[4..4 + token.len()].copy_from_slice(token);
+        Ok(Self {
+            len: 4 + token.len(),
+            buf,
+            number: 0,
+        })
+    }
+
+    fn push(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Add an option.
+    pub fn option(&mut self, number: u16, value: &[u8]) -> Result<(), Error> {
+        if number < self.number {
+            return Err(Error::OptionOrder);
+        }
+
+        fn split(v: usize) -> (u8, Vec<u8, 2>) {
+            let mut ext = Vec::new();
+            let nibble = match v {
+                0..=12 => v as u8,
+                13..=268 => {
+                    let _ = ext.push((v - 13) as u8);
+                    13
+                }
+                _ => {
+                    let _ = ext.extend_from_slice(&((v - 269) as u16).to_be_bytes());
+                    14
+                }
+            };
+            (nibble, ext)
+        }
+
+        let (delta, delta_ext) = split((number - self.number) as usize);
+        let (len, len_ext) = split(value.len());
+        self.push(&[delta << 4 | len])?;
+        self.push(&delta_ext)?;
+        self.push(&len_ext)?;
+        self.push(value)?;
+        self.number = number;
+        Ok(())
+    }
+
+    /// Add an option with an unsigned integer value, in its shortest form.
+    pub fn uint_option(&mut self, number: u16, value: u32) -> Result<(), Error> {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.option(number, &bytes[skip..])
+    }
+
+    /// Add one option per segment of a `/`-separated path, e.g. for [`option::URI_PATH`].
+    pub fn path_option(&mut self, number: u16, path: &str) -> Result<(), Error> {
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            self.option(number, segment.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Add one option per parameter of a `&`-separated query, e.g. for [`option::URI_QUERY`].
+    pub fn query_option(&mut self, number: u16, query: &str) -> Result<(), Error> {
+        for param in query.split('&').filter(|s| !s.is_empty()) {
+            self.option(number, param.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Add the payload, and return the length of the message.
+    pub fn payload(mut self, payload: &[u8]) -> Result<usize, Error> {
+        if !payload.is_empty() {
+            self.push(&[0xff])?;
+            self.push(payload)?;
+        }
+        Ok(self.len)
+    }
+
+    /// Finish a message without payload, and return its length.
+    pub fn finish(self) -> usize {
+        self.len
+    }
+}
+
+/// Send an empty acknowledgement or reset.
+async fn send_empty(socket: &UdpSocket<'_>, ty: MessageType, message_id: u16, remote: IpEndpoint) -> Result<(), Error> {
+    let id = message_id.to_be_bytes();
+    let msg = [0x40 | (ty as u8) << 4, 0, id[0], id[1]];
+    socket.send_to(&msg, remote).await.map_err(Error::Send)
+}
+
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self(if seed == 0 { 0x853c_49e6_748f_ea9b } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+/// CoAP client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct ClientConfig {
+    /// Initial retransmission timeout. The actual timeout is randomized up to 1.5 times this value.
+    pub ack_timeout: Duration,
+    /// Maximum number of retransmissions.
+    pub max_retransmit: u8,
+    /// Time to wait for a separate response, after the request was acknowledged.
+    pub response_timeout: Duration,
+    /// Block size for block-wise transfers, a power of two from 16 to 1024.
+    pub block_size: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(2),
+            max_retransmit: 4,
+            response_timeout: Duration::from_secs(30),
+            block_size: 512,
+        }
+    }
+}
+
+/// Request sent by a [`CoapClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Request<'a> {
+    /// Request method.
+    pub method: Code,
+    /// Path, with segments separated by `/`.
+    pub path: &'a str,
+    /// Query, with parameters separated by `&`.
+    pub query: Option<&'a str>,
+    /// Content format of the payload.
+    pub content_format: Option<u16>,
+    /// Accepted content format of the response.
+    pub accept: Option<u16>,
+    /// Payload. It is sent block-wise if larger than [`ClientConfig::block_size`].
+    pub payload: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Create a request without query, options or payload.
+    pub fn new(method: Code, path: &'a str) -> Self {
+        Self {
+            method,
+            path,
+            query: None,
+            content_format: None,
+            accept: None,
+            payload: &[],
+        }
+    }
+}
+
+/// Response received by a [`CoapClient`]. The payload is in the buffer given to the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response {
+    /// Response code.
+    pub code: Code,
+    /// Content format of the payload.
+    pub content_format: Option<u16>,
+    /// Observe option of the response. When registering an observation, `None` means the server
+    /// didn't register it.
+    pub observe: Option<u32>,
+    /// Length of the payload.
+    pub len: usize,
+}
+
+/// An observation registered with [`CoapClient::observe`].
+pub struct Observation {
+    remote: IpEndpoint,
+    token: [u8; MAX_TOKEN_LEN],
+    path: String<MAX_PATH_LEN>,
+    sequence: Option<(u32, Instant)>,
+}
+
+impl Observation {
+    /// Get whether notification `seq` received at `now` is newer than the last one (RFC 7641 §3.4).
+    fn is_fresh(&self, seq: u32, now: Instant) -> bool {
+        let Some((last, at)) = self.sequence else {
+            return true;
+        };
+        (last < seq && seq - last < 1 << 23)
+            || (last > seq && last - seq > 1 << 23)
+            || now > at + Duration::from_secs(128)
+    }
+}
+
+/// Buffers for a [`CoapClient`].
+///
+/// `N` must be able to hold a message with a full block, plus its options.
+pub struct ClientBuffers<const N: usize = 640> {
+    rx: [u8; N],
+    tx: [u8; N],
+}
+
+impl<const N: usize> ClientBuffers<N> {
+    /// Create new buffers.
+    pub const fn new() -> Self {
+        Self { rx: [0; N], tx: [0; N] }
+    }
+}
+
+impl<const N: usize> Default for ClientBuffers<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CoAP client.
+pub struct CoapClient<'a> {
+    socket: UdpSocket<'a>,
+    rx: &'a mut [u8],
+    tx: &'a mut [u8],
+    config: ClientConfig,
+    message_id: u16,
+    random: Random,
+}
+
+impl<'a> CoapClient<'a> {
+    /// Create a new client using the given UDP socket.
+    ///
+    /// The socket is bound to a dynamically allocated local port. `random_seed` is used to
+    /// generate message IDs and tokens.
+    pub fn new<const N: usize>(
+        mut socket: UdpSocket<'a>,
+        buffers: &'a mut ClientBuffers<N>,
+        config: ClientConfig,
+        random_seed: u64,
+    ) -> Result<Self, Error> {
+        socket.bind(0).map_err(Error::Bind)?;
+        let mut random = Random::new(random_seed);
+        Ok(Self {
+            socket,
+            rx: &mut buffers.rx,
+            tx: &mut buffers.tx,
+            config,
+            message_id: random.next() as u16,
+            random,
+        })
+    }
+
+    fn next_message_id(&mut self) -> u16 {
+        self.message_id = self.message_id.wrapping_add(1);
+        self.message_id
+    }
+
+    fn new_token(&mut self) -> [u8; MAX_TOKEN_LEN] {
+        self.random.next().to_ne_bytes()
+    }
+
+    /// Send a request and receive the response into `response`.
+    pub async fn request(
+        &mut self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        response: &mut [u8],
+    ) -> Result<Response, Error> {
+        let token = self.new_token();
+        let res = self.request_inner(remote, request, None, &token, 0, response).await?;
+        if res.len > response.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(res)
+    }
+
+    /// Register an observation of a resource.
+    ///
+    /// The current representation is received into `response`. Notifications are then received
+    /// with [`CoapClient::notification`].
+    pub async fn observe(
+        &mut self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        response: &mut [u8],
+    ) -> Result<(Observation, Response), Error> {
+        let mut path = String::new();
+        path.push_str(request.path).map_err(|_| Error::BufferTooSmall)?;
+        let token = self.new_token();
+        let res = self
+            .request_inner(remote, request, Some(0), &token, 0, response)
+            .await?;
+        if res.len > response.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        let observation = Observation {
+            remote,
+            token,
+            path,
+            sequence: res.observe.map(|seq| (seq, Instant::now())),
+        };
+        Ok((observation, res))
+    }
+
+    /// Wait for the next notification of an observation, and receive it into `response`.
+    ///
+    /// Notifications older than the last one received are skipped. Other messages received in
+    /// the meantime are rejected.
+    pub async fn notification(
+        &mut self,
+        observation: &mut Observation,
+        response: &mut [u8],
+    ) -> Result<Response, Error> {
+        loop {
+            let (n, remote) = self.socket.recv_from(self.rx).await.map_err(Error::Recv)?;
+            let Ok(msg) = Message::parse(&self.rx[..n]) else {
+                continue;
+            };
+            if remote != observation.remote || msg.token != observation.token || msg.code.is_request() {
+                if msg.ty == MessageType::Confirmable {
+                    send_empty(&self.socket, MessageType::Reset, msg.message_id, remote).await?;
+                }
+                continue;
+            }
+            if msg.ty == MessageType::Confirmable {
+                send_empty(&self.socket, MessageType::Acknowledgement, msg.message_id, remote).await?;
+            }
+
+            let now = Instant::now();
+            let seq = msg.observe();
+            if let Some(seq) = seq {
+                if !observation.is_fresh(seq, now) {
+                    debug!("coap: stale notification {}", seq);
+                    continue;
+                }
+                observation.sequence = Some((seq, now));
+            }
+
+            let first = msg.payload.len();
+            let len = first.min(response.len());
+            response[..len].copy_from_slice(&msg.payload[..len]);
+            let block = msg.block2().filter(|b| b.more);
+            let mut res = Response {
+                code: msg.code,
+                content_format: msg.content_format(),
+                observe: seq,
+                len: first,
+            };
+
+            // Fetch the rest of a large representation.
+            if let Some(block) = block {
+                let next = Block {
+                    num: block.num + 1,
+                    more: false,
+                    szx: block.szx,
+                };
+                let token = self.new_token();
+                let request = Request::new(Code::GET, observation.path.as_str());
+                let dst = response.get_mut(first..).unwrap_or(&mut []);
+                let rest = self
+                    .request_inner(remote, &request, None, &token, next.encode(), dst)
+                    .await?;
+                res.len = first + rest.len;
+            }
+
+            if res.len > response.len() {
+                return Err(Error::BufferTooSmall);
+            }
+            return Ok(res);
+        }
+    }
+
+    /// Cancel an observation.
+    pub async fn cancel(&mut self, observation: Observation) -> Result<(), Error> {
+        let request = Request::new(Code::GET, observation.path.as_str());
+        self.request_inner(observation.remote, &request, Some(1), &observation.token, 0, &mut [])
+            .await?;
+        Ok(())
+    }
+
+    /// Run a request, including block-wise transfers.
+    ///
+    /// `block2` is the Block2 option value to start from, or 0. The payload is copied to
+    /// `response` from the offset of that block, and the returned length can exceed the buffer.
+    async fn request_inner(
+        &mut self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        observe: Option<u32>,
+        token: &[u8],
+        block2: u32,
+        response: &mut [u8],
+    ) -> Result<Response, Error> {
+        let szx1 = szx(self.config.block_size);
+        let block_size = 16 << szx1;
+        let payload = request.payload;
+        let block1 = payload.len() > block_size;
+
+        let mut block1_num = 0;
+        let mut block2 = Block::decode(block2).filter(|b| b.num != 0);
+        let start = block2.map_or(0, |b| b.offset());
+        let mut observed = None;
+        loop {
+            let message_id = self.next_message_id();
+            let mut b = MessageBuilder::new(self.tx, MessageType::Confirmable, request.method, message_id, token)?;
+            if block2.is_none() {
+                if let Some(observe) = observe {
+                    b.uint_option(option::OBSERVE, observe)?;
+                }
+            }
+            b.path_option(option::URI_PATH, request.path)?;
+            if let Some(format) = request.content_format.filter(|_| !payload.is_empty()) {
+                b.uint_option(option::CONTENT_FORMAT, format as u32)?;
+            }
+            if let Some(query) = request.query {
+                b.query_option(option::URI_QUERY, query)?;
+            }
+            if let Some(accept) = request.accept {
+                b.uint_option(option::ACCEPT, accept as u32)?;
+            }
+            if let Some(block) = block2 {
+                b.uint_option(option::BLOCK2, block.encode())?;
+            }
+
+            let data = if block1 {
+                let start = block1_num as usize * block_size;
+                let end = (start + block_size).min(payload.len());
+                let block = Block {
+                    num: block1_num,
+                    more: end < payload.len(),
+                    szx: szx1,
+                };
+                b.uint_option(option::BLOCK1, block.encode())?;
+                if block1_num == 0 {
+                    b.uint_option(option::SIZE1, payload.len() as u32)?;
+                }
+                &payload[start..end]
+            } else if block2.is_none() {
+                payload
+            } else {
+                &[]
+            };
+            let more_block1 = block1 && (block1_num as usize + 1) * block_size < payload.len();
+            let len = b.payload(data)?;
+
+            let n = self.exchange(remote, len, message_id, token).await?;
+            let msg = Message::parse(&self.rx[..n])?;
+
+            if more_block1 && msg.code == Code::CONTINUE {
+                block1_num += 1;
+                continue;
+            }
+            if block2.is_none() {
+                observed = msg.observe();
+            }
+
+            let offset = block2.map_or(0, |b| b.offset()) - start;
+            if let Some(dst) = response.get_mut(offset..) {
+                let len = msg.payload.len().min(dst.len());
+                dst[..len].copy_from_slice(&msg.payload[..len]);
+            }
+
+            match msg.block2().filter(|b| b.more && msg.code.is_success()) {
+                Some(b) => {
+                    block2 = Some(Block {
+                        num: b.num + 1,
+                        more: false,
+                        szx: b.szx,
+                    });
+                }
+                None => {
+                    return Ok(Response {
+                        code: msg.code,
+                        content_format: msg.content_format(),
+                        observe: observed,
+                        len: offset + msg.payload.len(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Send the confirmable message in the transmit buffer and wait for the response.
+    ///
+    /// Returns the length of the response in the receive buffer.
+    async fn exchange(
+        &mut self,
+        remote: IpEndpoint,
+        len: usize,
+        message_id: u16,
+        token: &[u8],
+    ) -> Result<usize, Error> {
+        let jitter = self.random.next() % (self.config.ack_timeout.as_ticks() / 2 + 1);
+        let mut timeout = self.config.ack_timeout + Duration::from_ticks(jitter);
+        let mut retransmissions = 0;
+        let mut acked = false;
+
+        self.socket
+            .send_to(&self.tx[..len], remote)
+            .await
+            .map_err(Error::Send)?;
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let (n, from) = match with_deadline(deadline, self.socket.recv_from(self.rx)).await {
+                Ok(res) => res.map_err(Error::Recv)?,
+                Err(_) if acked || retransmissions == self.config.max_retransmit => return Err(Error::Timeout),
+                Err(_) => {
+                    retransmissions += 1;
+                    timeout *= 2;
+                    trace!("coap: retransmission {}", retransmissions);
+                    self.socket
+                        .send_to(&self.tx[..len], remote)
+                        .await
+                        .map_err(Error::Send)?;
+                    deadline = Instant::now() + timeout;
+                    continue;
+                }
+            };
+
+            let Ok(msg) = Message::parse(&self.rx[..n]) else {
+                continue;
+            };
+            if from != remote {
+                if msg.ty == MessageType::Confirmable {
+                    send_empty(&self.socket, MessageType::Reset, msg.message_id, from).await?;
+                }
+                continue;
+            }
+
+            match msg.ty {
+                MessageType::Reset if msg.message_id == message_id => return Err(Error::Reset),
+                MessageType::Acknowledgement if msg.message_id == message_id => {
+                    if msg.code == Code::EMPTY {
+                        // The response will be sent separately.
+                        acked = true;
+                        deadline = Instant::now() + self.config.response_timeout;
+                    } else if msg.token == token {
+                        return Ok(n);
+                    }
+                }
+                MessageType::Confirmable | MessageType::NonConfirmable if msg.token == token => {
+                    if msg.ty == MessageType::Confirmable {
+                        send_empty(&self.socket, MessageType::Acknowledgement, msg.message_id, from).await?;
+                    }
+                    return Ok(n);
+                }
+                MessageType::Confirmable => {
+                    send_empty(&self.socket, MessageType::Reset, msg.message_id, from).await?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Request received by a [`CoapServer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct IncomingRequest<'r> {
+    /// Request method.
+    pub method: Code,
+    /// Path, with segments joined by `/` and without a leading `/`.
+    pub path: &'r str,
+    /// Query, with parameters joined by `&`.
+    pub query: &'r str,
+    /// Content format of the payload.
+    pub content_format: Option<u16>,
+    /// Accepted content format of the response.
+    pub accept: Option<u16>,
+    /// Payload. Block-wise uploads are reassembled before the handler is called.
+    pub payload: &'r [u8],
+    /// Endpoint the request came from.
+    pub remote: IpEndpoint,
+}
+
+/// Response of a [`Handler`].
+///
+/// The code defaults to 4.04 Not Found, so that requests not handled get an error response.
+pub struct ResponseWriter<'r> {
+    code: Code,
+    content_format: Option<u16>,
+    max_age: Option<u32>,
+    observable: bool,
+    buffer: &'r mut [u8],
+    len: usize,
+}
+
+impl<'r> ResponseWriter<'r> {
+    fn new(buffer: &'r mut [u8]) -> Self {
+        Self {
+            code: Code::NOT_FOUND,
+            content_format: None,
+            max_age: None,
+            observable: false,
+            buffer,
+            len: 0,
+        }
+    }
+
+    /// Set the response code.
+    pub fn set_code(&mut self, code: Code) {
+        self.code = code;
+    }
+
+    /// Set the content format of the payload.
+    pub fn set_content_format(&mut self, format: u16) {
+        self.content_format = Some(format);
+    }
+
+    /// Set the Max-Age option.
+    pub fn set_max_age(&mut self, seconds: u32) {
+        self.max_age = Some(seconds);
+    }
+
+    /// Mark the resource as observable. Observation requests for it are then registered, and
+    /// observers are notified with [`CoapServer::notify`].
+    pub fn set_observable(&mut self) {
+        self.observable = true;
+    }
+
+    /// Append data to the payload. Large payloads are sent block-wise.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        self.buffer
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Get the payload written so far.
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+impl<'r> core::fmt::Write for ResponseWriter<'r> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// Request handler of a [`CoapServer`].
+pub trait Handler {
+    /// Answer a request.
+    ///
+    /// This is called again for each block of a block-wise response, and must produce the same
+    /// representation every time.
+    fn handle(&mut self, request: &IncomingRequest<'_>, response: &mut ResponseWriter<'_>);
+}
+
+struct Observer {
+    remote: IpEndpoint,
+    token: Vec<u8, MAX_TOKEN_LEN>,
+    path: String<MAX_PATH_LEN>,
+    /// Message ID of the last notification.
+    message_id: u16,
+    /// Whether the last confirmable notification was not acknowledged yet.
+    pending: bool,
+    /// Notifications sent since the last confirmable one.
+    count: u32,
+}
+
+struct Upload {
+    remote: IpEndpoint,
+    next: u32,
+    len: usize,
+}
+
+/// Buffers for a [`CoapServer`].
+///
+/// `MSG` must be able to hold a message with a full block, plus its options. `PAYLOAD` must be
+/// able to hold the largest representation, and the largest block-wise upload.
+pub struct ServerBuffers<const MSG: usize = 640, const PAYLOAD: usize = 1024> {
+    rx: [u8; MSG],
+    tx: [u8; MSG],
+    payload: [u8; PAYLOAD],
+    upload: [u8; PAYLOAD],
+}
+
+impl<const MSG: usize, const PAYLOAD: usize> ServerBuffers<MSG, PAYLOAD> {
+    /// Create new buffers.
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; MSG],
+            tx: [0; MSG],
+            payload: [0; PAYLOAD],
+            upload: [0; PAYLOAD],
+        }
+    }
+}
+
+impl<const MSG: usize, const PAYLOAD: usize> Default for ServerBuffers<MSG, PAYLOAD> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Send every 16th notification as confirmable, to detect observers which went away.
+const CONFIRMABLE_NOTIFICATION_INTERVAL: u32 = 16;
+
+/// CoAP server.
+///
+/// [`CoapServer::serve`] handles one incoming message. To send notifications to observers while
+/// serving requests, run it in a `select` with the event triggering [`CoapServer::notify`].
+pub struct CoapServer<'a> {
+    socket: UdpSocket<'a>,
+    rx: &'a mut [u8],
+    tx: &'a mut [u8],
+    payload: &'a mut [u8],
+    upload: &'a mut [u8],
+    upload_state: Option<Upload>,
+    observers: Vec<Observer, MAX_OBSERVERS>,
+    /// Last confirmable request answered, and the length of the response in the transmit buffer.
+    last: Option<(IpEndpoint, u16, usize)>,
+    block_size: usize,
+    message_id: u16,
+    sequence: u32,
+}
+
+impl<'a> CoapServer<'a> {
+    /// Create a new server using the given UDP socket, bound to `port`.
+    ///
+    /// Large responses are sent in blocks of `block_size` bytes, a power of two from 16 to 1024.
+    pub fn new<const MSG: usize, const PAYLOAD: usize>(
+        mut socket: UdpSocket<'a>,
+        port: u16,
+        buffers: &'a mut ServerBuffers<MSG, PAYLOAD>,
+        block_size: usize,
+        random_seed: u64,
+    ) -> Result<Self, Error> {
+        socket.bind(port).map_err(Error::Bind)?;
+        Ok(Self {
+            socket,
+            rx: &mut buffers.rx,
+            tx: &mut buffers.tx,
+            payload: &mut buffers.payload,
+            upload: &mut buffers.upload,
+            upload_state: None,
+            observers: Vec::new(),
+            last: None,
+            block_size: 16 << szx(block_size),
+            message_id: Random::new(random_seed).next() as u16,
+            sequence: 0,
+        })
+    }
+
+    fn next_message_id(&mut self) -> u16 {
+        self.message_id = self.message_id.wrapping_add(1);
+        self.message_id
+    }
+
+    /// Serve requests forever.
+    pub async fn run<H: Handler>(&mut self, handler: &mut H) -> ! {
+        loop {
+            if let Err(e) = self.serve(handler).await {
+                warn!("coap: error: {:?}", e);
+            }
+        }
+    }
+
+    /// Receive and handle one message.
+    pub async fn serve<H: Handler>(&mut self, handler: &mut H) -> Result<(), Error> {
+        let (n, remote) = self.socket.recv_from(self.rx).await.map_err(Error::Recv)?;
+        let msg = match Message::parse(&self.rx[..n]) {
+            Ok(msg) => msg,
+            Err(_) => {
+                // Reject malformed confirmable messages, if the header can be read.
+                if n >= 4 && self.rx[0] >> 4 == 0x04 {
+                    let message_id = u16::from_be_bytes([self.rx[2], self.rx[3]]);
+                    send_empty(&self.socket, MessageType::Reset, message_id, remote).await?;
+                }
+                return Ok(());
+            }
+        };
+
+        match msg.ty {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                let reset = msg.ty == MessageType::Reset;
+                let message_id = msg.message_id;
+                self.observers.retain_mut(|o| {
+                    if o.remote != remote || o.message_id != message_id {
+                        return true;
+                    }
+                    o.pending = false;
+                    !reset
+                });
+                return Ok(());
+            }
+            _ if !msg.code.is_request() => {
+                // Ping, or a response we didn't ask for.
+                if msg.ty == MessageType::Confirmable {
+                    send_empty(&self.socket, MessageType::Reset, msg.message_id, remote).await?;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let confirmable = msg.ty == MessageType::Confirmable;
+        if confirmable {
+            if let Some((last_remote, last_id, len)) = self.last {
+                if last_remote == remote && last_id == msg.message_id {
+                    // Retransmission of the last request: send the same response again.
+                    return self.socket.send_to(&self.tx[..len], remote).await.map_err(Error::Send);
+                }
+            }
+        }
+
+        let mut path: String<MAX_PATH_LEN> = String::new();
+        let mut query: String<MAX_QUERY_LEN> = String::new();
+        let mut valid = true;
+        for (number, value) in msg.options() {
+            let (s, sep) = match number {
+                option::URI_PATH => (&mut path, '/'),
+                option::URI_QUERY => (&mut query, '&'),
+                _ => continue,
+            };
+            let value = core::str::from_utf8(value).unwrap_or("");
+            if !s.is_empty() {
+                valid &= s.push(sep).is_ok();
+            }
+            valid &= s.push_str(value).is_ok();
+        }
+
+        let token: Vec<u8, MAX_TOKEN_LEN> = unwrap!(Vec::from_slice(msg.token));
+        let message_id = msg.message_id;
+        let observe = msg.observe();
+        let block2 = msg.block2();
+        let block1 = msg.block1();
+        let ty = match confirmable {
+            true => MessageType::Acknowledgement,
+            false => MessageType::NonConfirmable,
+        };
+
+        let mut response = ResponseWriter::new(self.payload);
+        let mut payload = msg.payload;
+        let mut block1_ack = None;
+        if !valid {
+            response.set_code(Code::BAD_REQUEST);
+        } else if let Some(block) = block1 {
+            // Reassemble the upload before calling the handler.
+            if block.num == 0 {
+                self.upload_state = Some(Upload {
+                    remote,
+                    next: 0,
+                    len: 0,
+                });
+            }
+            let offset = block.offset();
+            let expected = self
+                .upload_state
+                .as_ref()
+                .is_some_and(|s| s.remote == remote && s.next == block.num && s.len == offset);
+            let dst = self.upload.get_mut(offset..offset + msg.payload.len());
+            match (expected, dst, &mut self.upload_state) {
+                (true, Some(dst), Some(state)) => {
+                    dst.copy_from_slice(msg.payload);
+                    state.next += 1;
+                    state.len += msg.payload.len();
+                    block1_ack = Some(block);
+                    if block.more {
+                        response.set_code(Code::CONTINUE);
+                    } else {
+                        payload = &self.upload[..state.len];
+                        self.upload_state = None;
+                    }
+                }
+                (true, None, _) => {
+                    response.set_code(Code::REQUEST_ENTITY_TOO_LARGE);
+                    self.upload_state = None;
+                }
+                _ => {
+                    response.set_code(Code::REQUEST_ENTITY_INCOMPLETE);
+                    self.upload_state = None;
+                }
+            }
+        }
+
+        let request = IncomingRequest {
+            method: msg.code,
+            path: &path,
+            query: &query,
+            content_format: msg.content_format(),
+            accept: msg.uint_option(option::ACCEPT).map(|v| v as u16),
+            payload,
+            remote,
+        };
+        if response.code == Code::NOT_FOUND {
+            debug!("coap: {} /{}", request.method.0, request.path);
+            handler.handle(&request, &mut response);
+        }
+
+        let mut sequence = None;
+        if request.method == Code::GET {
+            let existing = self
+                .observers
+                .iter()
+                .position(|o| o.remote == remote && o.token == token);
+            match observe {
+                Some(0) if response.observable && response.code.is_success() => {
+                    self.sequence = self.sequence.wrapping_add(1) & 0xff_ffff;
+                    sequence = Some(self.sequence);
+                    let observer = Observer {
+                        remote,
+                        token: token.clone(),
+                        path: path.clone(),
+                        message_id,
+                        pending: false,
+                        count: 0,
+                    };
+                    match existing {
+                        Some(i) => self.observers[i] = observer,
+                        None => {
+                            if self.observers.push(observer).is_err() {
+                                // Serve the request without registering.
+                                sequence = None;
+                            }
+                        }
+                    }
+                }
+                Some(1) => {
+                    if let Some(i) = existing {
+                        self.observers.swap_remove(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let code = response.code;
+        let len = build_response(
+            self.tx,
+            ty,
+            message_id,
+            &token,
+            &response,
+            sequence,
+            block2,
+            block1_ack,
+            self.block_size,
+        )?;
+        let message_id = if confirmable {
+            message_id
+        } else {
+            self.next_message_id()
+        };
+        self.tx[2..4].copy_from_slice(&message_id.to_be_bytes());
+
+        self.socket
+            .send_to(&self.tx[..len], remote)
+            .await
+            .map_err(Error::Send)?;
+        trace!("coap: response {}", code.0);
+        if confirmable {
+            self.last = Some((remote, message_id, len));
+        }
+        Ok(())
+    }
+
+    /// Notify the observers of the resource at `path` (without leading `/`), calling `handler`
+    /// to get its representation.
+    ///
+    /// Observers are removed when the response is not successful, or when they don't
+    /// acknowledge confirmable notifications.
+    pub async fn notify<H: Handler>(&mut self, path: &str, handler: &mut H) -> Result<(), Error> {
+        let mut i = 0;
+        while i < self.observers.len() {
+            if self.observers[i].path != path {
+                i += 1;
+                continue;
+            }
+
+            let message_id = self.next_message_id();
+            let observer = &mut self.observers[i];
+            observer.count += 1;
+            let confirmable = observer.count == CONFIRMABLE_NOTIFICATION_INTERVAL;
+            if confirmable {
+                observer.count = 0;
+            }
+            if confirmable && observer.pending {
+                debug!("coap: removing unresponsive observer");
+                self.observers.swap_remove(i);
+                continue;
+            }
+            observer.pending |= confirmable;
+            observer.message_id = message_id;
+            let remote = observer.remote;
+            let token = observer.token.clone();
+
+            let request = IncomingRequest {
+                method: Code::GET,
+                path,
+                query: "",
+                content_format: None,
+                accept: None,
+                payload: &[],
+                remote,
+            };
+            let mut response = ResponseWriter::new(self.payload);
+            handler.handle(&request, &mut response);
+
+            self.sequence = self.sequence.wrapping_add(1) & 0xff_ffff;
+            let ty = match confirmable {
+                true => MessageType::Confirmable,
+                false => MessageType::NonConfirmable,
+            };
+            let success = response.code.is_success();
+            let len = build_response(
+                self.tx,
+                ty,
+                message_id,
+                &token,
+                &response,
+                success.then_some(self.sequence),
+                None,
+                None,
+                self.block_size,
+            )?;
+            self.socket
+                .send_to(&self.tx[..len], remote)
+                .await
+                .map_err(Error::Send)?;
+
+            // An error response ends the observation.
+            if success {
+                i += 1;
+            } else {
+                self.observers.swap_remove(i);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Build a response, sending the requested block of the payload if it is too large for one message.
+#[allow(clippy::too_many_arguments)]
+fn build_response(
+    tx: &mut [u8],
+    ty: MessageType,
+    message_id: u16,
+    token: &[u8],
+    response: &ResponseWriter<'_>,
+    observe: Option<u32>,
+    block2: Option<Block>,
+    block1: Option<Block>,
+    block_size: usize,
+) -> Result<usize, Error> {
+    let payload = response.payload();
+    let mut b = MessageBuilder::new(tx, ty, response.code, message_id, token)?;
+    if let Some(seq) = observe {
+        b.uint_option(option::OBSERVE, seq)?;
+    }
+    if let Some(format) = response.content_format {
+        b.uint_option(option::CONTENT_FORMAT, format as u32)?;
+    }
+    if let Some(max_age) = response.max_age {
+        b.uint_option(option::MAX_AGE, max_age)?;
+    }
+
+    // Use the block size requested by the client if it's smaller than ours.
+    let block = match block2 {
+        Some(block) if block.size() <= block_size => Some(block),
+        Some(block) => {
+            let szx = szx(block_size);
+            Some(Block {
+                num: (block.offset() / block_size) as u32,
+                more: false,
+                szx,
+            })
+        }
+        None if payload.len() > block_size => Some(Block {
+            num: 0,
+            more: false,
+            szx: szx(block_size),
+        }),
+        None => None,
+    };
+
+    let mut data = payload;
+    if let Some(mut block) = block {
+        let start = block.offset().min(payload.len());
+        let end = (start + block.size()).min(payload.len());
+        block.more = end < payload.len();
+        data = &payload[start..end];
+        b.uint_option(option::BLOCK2, block.encode())?;
+    }
+    if let Some(block) = block1 {
+        b.uint_option(option::BLOCK1, block.encode())?;
+    }
+    if block.is_some_and(|b| b.num == 0) {
+        b.uint_option(option::SIZE2, payload.len() as u32)?;
+    }
+    b.payload(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_message() {
+        let data = b"\x42\x01\x12\x34\xaa\xbb\xb4temp\x11\x32\xffhi";
+        let msg = Message::parse(data).unwrap();
+        assert_eq!(msg.ty, MessageType::Confirmable);
+        assert_eq!(msg.code, Code::GET);
+        assert_eq!(msg.message_id, 0x1234);
+        assert_eq!(msg.token, [0xaa, 0xbb]);
+        assert_eq!(msg.option(option::URI_PATH), Some(&b"temp"[..]));
+        assert_eq!(msg.content_format(), Some(content_format::JSON));
+        assert_eq!(msg.payload, b"hi");
+
+        let msg = Message::parse(b"\x60\x00\x00\x01").unwrap();
+        assert_eq!(msg.ty, MessageType::Acknowledgement);
+        assert_eq!(msg.code, Code::EMPTY);
+        assert_eq!(msg.options().count(), 0);
+        assert_eq!(msg.payload, b"");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // Too short, wrong version.
+        assert_eq!(Message::parse(b"\x40\x01\x00"), Err(Error::Malformed));
+        assert_eq!(Message::parse(b"\x80\x01\x00\x00"), Err(Error::Malformed));
+        // Token too long, truncated token.
+        assert_eq!(Message::parse(b"\x49\x01\x00\x00123456789"), Err(Error::Malformed));
+        assert_eq!(Message::parse(b"\x42\x01\x00\x00\xaa"), Err(Error::Malformed));
+        // Payload marker without payload.
+        assert_eq!(Message::parse(b"\x40\x01\x00\x00\xff"), Err(Error::Malformed));
+        // Reserved nibble, truncated extended delta and value.
+        assert_eq!(Message::parse(b"\x40\x01\x00\x00\xf1\x00"), Err(Error::Malformed));
+        assert_eq!(Message::parse(b"\x40\x01\x00\x00\xe0\x01"), Err(Error::Malformed));
+        assert_eq!(Message::parse(b"\x40\x01\x00\x00\x13ab"), Err(Error::Malformed));
+        // Option number overflow.
+        assert_eq!(
+            Message::parse(b"\x40\x01\x00\x00\xe0\xfe\xf2\xe0\x01\x00"),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn option_encoding() {
+        let cases: [(u16, usize, &[u8]); 6] = [
+            (12, 12, b"\xcc"),
+            (13, 13, b"\xdd\x00\x00"),
+            (268, 268, b"\xdd\xff\xff"),
+            (269, 269, b"\xee\x00\x00\x00\x00"),
+            (1000, 0, b"\xe0\x02\xdb"),
+            (0, 300, b"\x0e\x00\x1f"),
+        ];
+        for (number, len, header) in cases {
+            let mut buf = [0; 512];
+            let value = [0x5a; 300];
+            let mut builder = MessageBuilder::new(&mut buf, MessageType::Confirmable, Code::GET, 1, &[]).unwrap();
+            builder.option(number, &value[..len]).unwrap();
+            let n = builder.finish();
+            assert_eq!(&buf[4..4 + header.len()], header);
+            assert_eq!(n, 4 + header.len() + len);
+
+            let msg = Message::parse(&buf[..n]).unwrap();
+            let mut options = msg.options();
+            assert_eq!(options.next(), Some((number, &value[..len])));
+            assert_eq!(options.next(), None);
+        }
+    }
+
+    #[test]
+    fn build_message() {
+        let mut buf = [0; 64];
+        let mut builder =
+            MessageBuilder::new(&mut buf, MessageType::NonConfirmable, Code::POST, 0xbeef, b"tk").unwrap();
+        builder.path_option(option::URI_PATH, "/a/bc/").unwrap();
+        builder.uint_option(option::CONTENT_FORMAT, 0).unwrap();
+        builder.query_option(option::URI_QUERY, "x=1&y").unwrap();
+        builder
+            .uint_option(
+                option::BLOCK1,
+                Block {
+                    num: 20,
+                    more: true,
+                    szx: 2,
+                }
+                .encode(),
+            )
+            .unwrap();
+        assert_eq!(builder.option(option::URI_HOST, b"h"), Err(Error::OptionOrder));
+        let n = builder.payload(b"data").unwrap();
+
+        let msg = Message::parse(&buf[..n]).unwrap();
+        assert_eq!(msg.ty, MessageType::NonConfirmable);
+        assert_eq!(msg.code, Code::POST);
+        assert_eq!(msg.message_id, 0xbeef);
+        assert_eq!(msg.token, b"tk");
+        let mut options = msg.options();
+        assert_eq!(options.next(), Some((option::URI_PATH, &b"a"[..])));
+        assert_eq!(options.next(), Some((option::URI_PATH, &b"bc"[..])));
+        assert_eq!(options.next(), Some((option::CONTENT_FORMAT, &b""[..])));
+        assert_eq!(options.next(), Some((option::URI_QUERY, &b"x=1"[..])));
+        assert_eq!(options.next(), Some((option::URI_QUERY, &b"y"[..])));
+        assert_eq!(options.next(), Some((option::BLOCK1, &[0x01, 0x4a][..])));
+        assert_eq!(options.next(), None);
+        assert_eq!(msg.content_format(), Some(content_format::TEXT_PLAIN));
+        assert_eq!(
+            msg.block1(),
+            Some(Block {
+                num: 20,
+                more: true,
+                szx: 2
+            })
+        );
+        assert_eq!(msg.block2(), None);
+        assert_eq!(msg.payload, b"data");
+    }
+
+    #[test]
+    fn blocks() {
+        let block = Block::decode(0x2a).unwrap();
+        assert_eq!(
+            block,
+            Block {
+                num: 2,
+                more: true,
+                szx: 2
+            }
+        );
+        assert_eq!(block.size(), 64);
+        assert_eq!(block.offset(), 128);
+        assert_eq!(Block::decode(0x07), None);
+        assert_eq!(szx(1024), 6);
+        assert_eq!(szx(16), 0);
+    }
+}
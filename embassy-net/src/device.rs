@@ -1,9 +1,67 @@
+#[cfg(any(feature = "stats", feature = "frame-socket", feature = "firewall"))]
+use core::cell::RefCell;
+#[cfg(feature = "firewall")]
+use core::cell::RefMut;
 use core::task::Context;
 
 use embassy_net_driver::{Capabilities, Checksum, Driver, RxToken, TxToken};
 use smoltcp::phy::{self, Medium};
 use smoltcp::time::Instant;
 
+#[cfg(feature = "firewall")]
+use crate::firewall::{self, Direction, Firewall};
+#[cfg(feature = "frame-socket")]
+use crate::frame::FrameSockets;
+#[cfg(feature = "stats")]
+use crate::stats::Recorder;
+#[cfg(feature = "firewall")]
+use crate::time::instant_from_smoltcp;
+
+/// Observers and filters of the frames exchanged with the driver.
+pub(crate) struct Taps {
+    #[cfg(feature = "firewall")]
+    pub firewall: RefCell<Firewall>,
+    /// Received frames are copied here while the firewall is active, to be inspected before
+    /// smoltcp sees them.
+    #[cfg(feature = "firewall")]
+    firewall_rx: RefCell<[u8; firewall::MAX_FRAME_LEN]>,
+    #[cfg(feature = "stats")]
+    pub stats: RefCell<Recorder>,
+    #[cfg(feature = "frame-socket")]
+    pub frames: RefCell<FrameSockets>,
+}
+
+impl Taps {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "firewall")]
+            firewall: RefCell::new(Firewall::new()),
+            #[cfg(feature = "firewall")]
+            firewall_rx: RefCell::new([0; firewall::MAX_FRAME_LEN]),
+            #[cfg(feature = "stats")]
+            stats: RefCell::new(Recorder::new()),
+            #[cfg(feature = "frame-socket")]
+            frames: RefCell::new(FrameSockets::new()),
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn received(&self, medium: Medium, frame: &[u8]) {
+        #[cfg(feature = "stats")]
+        self.stats.borrow_mut().record_rx(medium, frame);
+        #[cfg(feature = "frame-socket")]
+        self.frames.borrow_mut().received(medium, frame);
+    }
+
+    #[allow(unused_variables)]
+    fn transmitted(&self, medium: Medium, frame: &[u8]) {
+        #[cfg(feature = "stats")]
+        self.stats.borrow_mut().record_tx(medium, frame);
+        #[cfg(feature = "frame-socket")]
+        self.frames.borrow_mut().transmitted(frame);
+    }
+}
+
 pub(crate) struct DriverAdapter<'d, 'c, T>
 where
     T: Driver,
@@ -12,24 +70,80 @@ where
     pub cx: Option<&'d mut Context<'c>>,
     pub inner: &'d mut T,
     pub medium: Medium,
+    pub taps: &'d Taps,
 }
 
 impl<'d, 'c, T> phy::Device for DriverAdapter<'d, 'c, T>
 where
     T: Driver,
 {
-    type RxToken<'a> = RxTokenAdapter<T::RxToken<'a>> where Self: 'a;
-    type TxToken<'a> = TxTokenAdapter<T::TxToken<'a>> where Self: 'a;
+    type RxToken<'a> = RxTokenAdapter<'a, T::RxToken<'a>> where Self: 'a;
+    type TxToken<'a> = TxTokenAdapter<'a, T::TxToken<'a>> where Self: 'a;
+
+    #[allow(unused_variables)]
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let cx = unwrap!(self.cx.as_deref_mut());
+        let (rx, tx) = self.inner.receive(cx)?;
+        let tx = TxTokenAdapter {
+            token: tx,
+            taps: self.taps,
+            medium: self.medium,
+            timestamp,
+        };
 
-    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        self.inner
-            .receive(unwrap!(self.cx.as_deref_mut()))
-            .map(|(rx, tx)| (RxTokenAdapter(rx), TxTokenAdapter(tx)))
+        // The frame is copied out of the driver and inspected before smoltcp sees it, so denied
+        // frames can be dropped along with their tokens.
+        #[cfg(feature = "firewall")]
+        if self.taps.firewall.borrow().is_active() {
+            let mut copy = self.taps.firewall_rx.borrow_mut();
+            let len = rx.consume(|buf| {
+                #[cfg(feature = "packet-trace")]
+                trace!("rx: {:?}", buf);
+                self.taps.received(self.medium, buf);
+                let fw = &mut *self.taps.firewall.borrow_mut();
+                let now = instant_from_smoltcp(timestamp);
+                // Frames too large to be copied can't be inspected.
+                match copy.get_mut(..buf.len()) {
+                    Some(copy) => {
+                        copy.copy_from_slice(buf);
+                        fw.allows_ingress(self.medium, copy, now).then_some(buf.len())
+                    }
+                    None => {
+                        fw.drop_oversized(Direction::Ingress);
+                        None
+                    }
+                }
+            });
+            let Some(len) = len else {
+                // Other frames may be pending in the driver: poll again.
+                cx.waker().wake_by_ref();
+                return None;
+            };
+            let rx = RxTokenAdapter {
+                frame: RxFrame::Copied(copy, len),
+                taps: self.taps,
+                medium: self.medium,
+            };
+            return Some((rx, tx));
+        }
+
+        let rx = RxTokenAdapter {
+            frame: RxFrame::Driver(rx),
+            taps: self.taps,
+            medium: self.medium,
+        };
+        Some((rx, tx))
     }
 
     /// Construct a transmit token.
-    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
-        self.inner.transmit(unwrap!(self.cx.as_deref_mut())).map(TxTokenAdapter)
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        let tx = self.inner.transmit(unwrap!(self.cx.as_deref_mut()))?;
+        Some(TxTokenAdapter {
+            token: tx,
+            taps: self.taps,
+            medium: self.medium,
+            timestamp,
+        })
     }
 
     /// Get a description of device capabilities.
@@ -46,6 +160,11 @@ where
         let mut smolcaps = phy::DeviceCapabilities::default();
 
         smolcaps.max_transmission_unit = caps.max_transmission_unit;
+        // While the firewall is active, outgoing frames must fit in its buffer.
+        #[cfg(feature = "firewall")]
+        if self.taps.firewall.borrow().is_active() {
+            smolcaps.max_transmission_unit = smolcaps.max_transmission_unit.min(firewall::MAX_FRAME_LEN);
+        }
         smolcaps.max_burst_size = caps.max_burst_size;
         smolcaps.medium = self.medium;
         smolcaps.checksum.ipv4 = convert(caps.checksum.ipv4);
@@ -64,11 +183,28 @@ where
     }
 }
 
-pub(crate) struct RxTokenAdapter<T>(T)
+/// Where a received frame is read from.
+enum RxFrame<'a, T> {
+    /// Still in the driver.
+    Driver(T),
+    /// Already copied by the firewall, with its length.
+    #[cfg(feature = "firewall")]
+    Copied(RefMut<'a, [u8; firewall::MAX_FRAME_LEN]>, usize),
+    #[cfg(not(feature = "firewall"))]
+    #[allow(unused)]
+    Never(core::marker::PhantomData<&'a ()>),
+}
+
+pub(crate) struct RxTokenAdapter<'a, T>
 where
-    T: RxToken;
+    T: RxToken,
+{
+    frame: RxFrame<'a, T>,
+    taps: &'a Taps,
+    medium: Medium,
+}
 
-impl<T> phy::RxToken for RxTokenAdapter<T>
+impl<'a, T> phy::RxToken for RxTokenAdapter<'a, T>
 where
     T: RxToken,
 {
@@ -76,19 +212,33 @@ where
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0.consume(|buf| {
-            #[cfg(feature = "packet-trace")]
-            trace!("rx: {:?}", buf);
-            f(buf)
-        })
+        match self.frame {
+            RxFrame::Driver(token) => token.consume(|buf| {
+                #[cfg(feature = "packet-trace")]
+                trace!("rx: {:?}", buf);
+                self.taps.received(self.medium, buf);
+                f(buf)
+            }),
+            #[cfg(feature = "firewall")]
+            RxFrame::Copied(mut buf, len) => f(&mut buf[..len]),
+            #[cfg(not(feature = "firewall"))]
+            RxFrame::Never(_) => unreachable!(),
+        }
     }
 }
 
-pub(crate) struct TxTokenAdapter<T>(T)
+pub(crate) struct TxTokenAdapter<'a, T>
 where
-    T: TxToken;
+    T: TxToken,
+{
+    token: T,
+    taps: &'a Taps,
+    medium: Medium,
+    #[allow(unused)]
+    timestamp: Instant,
+}
 
-impl<T> phy::TxToken for TxTokenAdapter<T>
+impl<'a, T> phy::TxToken for TxTokenAdapter<'a, T>
 where
     T: TxToken,
 {
@@ -96,10 +246,30 @@ where
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0.consume(len, |buf| {
+        #[cfg(feature = "firewall")]
+        {
+            // Build the frame aside, so it can be dropped. It fits, as the MTU is limited to the
+            // buffer's size while the firewall is active.
+            let fw = &mut *self.taps.firewall.borrow_mut();
+            if fw.is_active() {
+                let r = f(unwrap!(fw.buf.get_mut(..len)));
+                if fw.allows_egress(self.medium, len, instant_from_smoltcp(self.timestamp)) {
+                    self.token.consume(len, |buf| {
+                        buf.copy_from_slice(&fw.buf[..len]);
+                        #[cfg(feature = "packet-trace")]
+                        trace!("tx: {:?}", buf);
+                        self.taps.transmitted(self.medium, buf);
+                    });
+                }
+                return r;
+            }
+        }
+
+        self.token.consume(len, |buf| {
             let r = f(buf);
             #[cfg(feature = "packet-trace")]
             trace!("tx: {:?}", buf);
+            self.taps.transmitted(self.medium, buf);
             r
         })
     }
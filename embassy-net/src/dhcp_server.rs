@@ -0,0 +1,720 @@
+//! DHCPv4 server.
+//!
+//! Hands out IPv4 addresses from a fixed pool to clients on the local link, for devices acting as
+//! a WiFi access point or as the host end of a point-to-point Ethernet link.
+//!
+//! The stack must have a static IPv4 configuration (the server's own address) before the server is
+//! started. Replies to clients that don't have an address yet are broadcast, so no ARP entry is
+//! needed for them. Replies to requests forwarded by a relay agent are sent back to the relay.
+//!
+//! ```ignore
+//! let mut rx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut rx_buffer = [0; 1500];
+//! let mut tx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut tx_buffer = [0; 1500];
+//! let socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+//!
+//! let config = dhcp_server::Config::new(Ipv4Address::new(192, 168, 4, 1), Ipv4Address::new(192, 168, 4, 100));
+//! let mut server = DhcpServer::<'_, 8>::new(socket, config).unwrap();
+//! server.run().await;
+//! ```
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::wire::{
+    DhcpMessageType, DhcpPacket, DhcpRepr, EthernetAddress, Ipv4Address, Ipv4Cidr, DHCP_CLIENT_PORT,
+    DHCP_MAX_DNS_SERVER_COUNT, DHCP_SERVER_PORT,
+};
+
+use crate::udp::{self, UdpSocket};
+use crate::IpEndpoint;
+
+/// Maximum size of a DHCP message this server sends or accepts.
+const MAX_MESSAGE_LEN: usize = 576;
+
+/// How long an offered address is reserved for a client that hasn't requested it yet.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long an address declined by a client (because it is already in use) is kept out of the pool.
+const DECLINE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// DHCP server configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// Address of the server itself. Must be one of the stack's addresses.
+    pub server_address: Ipv4Address,
+    /// Subnet mask handed out to clients.
+    pub subnet_mask: Ipv4Address,
+    /// First address of the pool. The pool consists of `N` consecutive addresses, where `N`
+    /// is the lease table size of the [`DhcpServer`], in the server's subnet.
+    pub pool_start: Ipv4Address,
+    /// Default gateway handed out to clients. Usually the server address.
+    pub router: Option<Ipv4Address>,
+    /// DNS servers handed out to clients.
+    pub dns_servers: Vec<Ipv4Address, DHCP_MAX_DNS_SERVER_COUNT>,
+    /// Lease duration.
+    pub lease_duration: Duration,
+}
+
+impl Config {
+    /// Create a configuration with a /24 subnet, the server as the default gateway, no DNS servers
+    /// and a lease duration of one hour.
+    pub fn new(server_address: Ipv4Address, pool_start: Ipv4Address) -> Self {
+        Self {
+            server_address,
+            subnet_mask: Ipv4Address::new(255, 255, 255, 0),
+            pool_start,
+            router: Some(server_address),
+            dns_servers: Vec::new(),
+            lease_duration: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Error returned by [`DhcpServer::new`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The address pool is empty, would contain the server address, or isn't made of host addresses
+    /// of the server's subnet.
+    InvalidPool,
+    /// The UDP socket could not be bound to the DHCP server port.
+    Bind(udp::BindError),
+}
+
+/// State of an entry in the lease table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LeaseState {
+    /// The address was offered to the client, which has not requested it yet.
+    Offered,
+    /// The address is leased to the client.
+    Bound,
+    /// A client reported the address is already in use on the network.
+    Declined,
+}
+
+/// An entry in the lease table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Lease {
+    /// Hardware address of the client.
+    pub hardware_address: EthernetAddress,
+    /// Address assigned to the client.
+    pub address: Ipv4Address,
+    /// State of the lease.
+    pub state: LeaseState,
+    /// When the lease expires.
+    pub expires: Instant,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    hardware_address: EthernetAddress,
+    state: LeaseState,
+    expires: Instant,
+}
+
+/// A DHCPv4 server with a lease table of `N` entries.
+///
+/// The server hands out the `N` consecutive addresses starting at [`Config::pool_start`].
+pub struct DhcpServer<'a, const N: usize> {
+    socket: UdpSocket<'a>,
+    table: LeaseTable<N>,
+}
+
+/// The lease table, and the handling of the DHCP messages, apart from the socket.
+struct LeaseTable<const N: usize> {
+    config: Config,
+    slots: [Option<Slot>; N],
+}
+
+impl<'a, const N: usize> DhcpServer<'a, N> {
+    /// Create a new DHCP server using the given UDP socket.
+    ///
+    /// The socket is bound to the DHCP server port.
+    pub fn new(mut socket: UdpSocket<'a>, config: Config) -> Result<Self, Error> {
+        let table = LeaseTable::new(config)?;
+        socket.bind(DHCP_SERVER_PORT).map_err(Error::Bind)?;
+        Ok(Self { socket, table })
+    }
+
+    /// Get the server configuration.
+    pub fn config(&self) -> &Config {
+        &self.table.config
+    }
+
+    /// Iterate over the current, non-expired entries of the lease table.
+    pub fn leases(&self) -> impl Iterator<Item = Lease> + '_ {
+        let now = Instant::now();
+        self.table.slots.iter().enumerate().filter_map(move |(i, s)| match s {
+            Some(s) if s.expires > now => Some(Lease {
+                hardware_address: s.hardware_address,
+                address: self.table.pool_address(i),
+                state: s.state,
+                expires: s.expires,
+            }),
+            _ => None,
+        })
+    }
+
+    /// Release the lease of a client, making its address available to others.
+    ///
+    /// Returns whether a lease was found.
+    pub fn revoke(&mut self, hardware_address: EthernetAddress) -> bool {
+        match self.table.find(hardware_address) {
+            Some(i) => {
+                self.table.slots[i] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run the server.
+    ///
+    /// This processes DHCP requests forever.
+    pub async fn run(&mut self) -> ! {
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        loop {
+            let (n, _) = match self.socket.recv_from(&mut rx).await {
+                Ok(x) => x,
+                Err(udp::RecvError::Truncated) => {
+                    warn!("dhcp server: message too long");
+                    continue;
+                }
+            };
+
+            if let Some((len, dest)) = self.table.handle(&rx[..n], &mut tx) {
+                if let Err(e) = self.socket.send_to(&tx[..len], dest).await {
+                    warn!("dhcp server: failed to send reply: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> LeaseTable<N> {
+    fn new(config: Config) -> Result<Self, Error> {
+        let subnet = Ipv4Cidr::from_netmask(config.server_address, config.subnet_mask)
+            .map_err(|_| Error::InvalidPool)?
+            .network();
+        let start = u32::from_be_bytes(config.pool_start.0);
+        let server = u32::from_be_bytes(config.server_address.0);
+        let Some(end) = start.checked_add(N as u32).filter(|_| N > 0) else {
+            return Err(Error::InvalidPool);
+        };
+        // The network and broadcast addresses of the subnet can't be handed out.
+        let host = |addr: u32| {
+            let addr = Ipv4Address::from_bytes(&addr.to_be_bytes());
+            subnet.contains_addr(&addr) && addr != subnet.address() && Some(addr) != subnet.broadcast()
+        };
+        if (start..end).contains(&server) || !host(start) || !host(end - 1) {
+            return Err(Error::InvalidPool);
+        }
+
+        Ok(Self {
+            config,
+            slots: [None; N],
+        })
+    }
+
+    /// Handle a received message, and write the reply, if any, to `tx`.
+    ///
+    /// Returns the length of the reply and where to send it.
+    fn handle(&mut self, rx: &[u8], tx: &mut [u8]) -> Option<(usize, IpEndpoint)> {
+        let packet = DhcpPacket::new_checked(rx).ok()?;
+        let request = DhcpRepr::parse(&packet).ok()?;
+
+        let (reply, dest) = self.process(&request)?;
+        let len = reply.buffer_len();
+        let mut packet = DhcpPacket::new_unchecked(tx.get_mut(..len)?);
+        reply.emit(&mut packet).ok()?;
+        Some((len, dest))
+    }
+
+    fn process<'r>(&mut self, request: &DhcpRepr<'_>) -> Option<(DhcpRepr<'r>, IpEndpoint)> {
+        let now = Instant::now();
+        let mac = request.client_hardware_address;
+
+        // Messages with a server identifier are meant for a particular server.
+        let for_us =
+            request.server_identifier.is_none() || request.server_identifier == Some(self.config.server_address);
+
+        match request.message_type {
+            DhcpMessageType::Discover => {
+                let i = self.allocate(mac, request.requested_ip, now)?;
+                self.slots[i] = Some(Slot {
+                    hardware_address: mac,
+                    state: LeaseState::Offered,
+                    expires: now + OFFER_TIMEOUT,
+                });
+                debug!("dhcp server: offering {:?} to {:?}", self.pool_address(i), mac);
+                Some(self.reply(request, DhcpMessageType::Offer, self.pool_address(i)))
+            }
+            DhcpMessageType::Request => {
+                if !for_us {
+                    // The client selected another server, withdraw our offer.
+                    if let Some(i) = self.find(mac) {
+                        if matches!(
+                            self.slots[i],
+                            Some(Slot {
+                                state: LeaseState::Offered,
+                                ..
+                            })
+                        ) {
+                            self.slots[i] = None;
+                        }
+                    }
+                    return None;
+                }
+
+                let requested = match request.requested_ip {
+                    Some(ip) => ip,
+                    None => request.client_ip,
+                };
+                // A rebooting client checking an address it got before, from this or another
+                // server. Only the server that knows the client may answer (RFC 2131, 4.3.2).
+                let init_reboot = request.server_identifier.is_none() && request.client_ip.is_unspecified();
+                if init_reboot && self.find(mac).is_none() {
+                    debug!(
+                        "dhcp server: ignoring request for {:?} from unknown {:?}",
+                        requested, mac
+                    );
+                    return None;
+                }
+                match self.pool_index(requested) {
+                    Some(i) if self.available(i, mac, now) => {
+                        // Drop any other lease the client might still hold.
+                        if let Some(j) = self.find(mac) {
+                            if j != i {
+                                self.slots[j] = None;
+                            }
+                        }
+                        self.slots[i] = Some(Slot {
+                            hardware_address: mac,
+                            state: LeaseState::Bound,
+                            expires: now + self.config.lease_duration,
+                        });
+                        info!("dhcp server: leased {:?} to {:?}", requested, mac);
+                        Some(self.reply(request, DhcpMessageType::Ack, requested))
+                    }
+                    _ => {
+                        debug!("dhcp server: rejecting request for {:?} from {:?}", requested, mac);
+                        Some(self.reply(request, DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED))
+                    }
+                }
+            }
+            DhcpMessageType::Decline => {
+                if for_us {
+                    if let Some(i) = request.requested_ip.and_then(|ip| self.pool_index(ip)) {
+                        warn!(
+                            "dhcp server: {:?} declined by client, address in use",
+                            self.pool_address(i)
+                        );
+                        self.slots[i] = Some(Slot {
+                            hardware_address: EthernetAddress([0; 6]),
+                            state: LeaseState::Declined,
+                            expires: now + DECLINE_TIMEOUT,
+                        });
+                    }
+                }
+                None
+            }
+            DhcpMessageType::Release => {
+                if for_us {
+                    if let Some(i) = self.pool_index(request.client_ip) {
+                        if matches!(self.slots[i], Some(s) if s.hardware_address == mac) {
+                            debug!("dhcp server: {:?} released by {:?}", request.client_ip, mac);
+                            self.slots[i] = None;
+                        }
+                    }
+                }
+                None
+            }
+            DhcpMessageType::Inform => Some(self.reply(request, DhcpMessageType::Ack, Ipv4Address::UNSPECIFIED)),
+            _ => None,
+        }
+    }
+
+    fn reply<'r>(
+        &self,
+        request: &DhcpRepr<'_>,
+        message_type: DhcpMessageType,
+        your_ip: Ipv4Address,
+    ) -> (DhcpRepr<'r>, IpEndpoint) {
+        let nak = message_type == DhcpMessageType::Nak;
+        let inform = request.message_type == DhcpMessageType::Inform;
+        let relayed = !request.relay_agent_ip.is_unspecified();
+        let lease_secs = self.config.lease_duration.as_secs() as u32;
+
+        let reply = DhcpRepr {
+            message_type,
+            transaction_id: request.transaction_id,
+            secs: 0,
+            client_hardware_address: request.client_hardware_address,
+            client_ip: if inform {
+                request.client_ip
+            } else {
+                Ipv4Address::UNSPECIFIED
+            },
+            your_ip,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: if nak { None } else { self.config.router },
+            subnet_mask: if nak { None } else { Some(self.config.subnet_mask) },
+            relay_agent_ip: request.relay_agent_ip,
+            // A relay can't unicast a NAK to a client without an address.
+            broadcast: request.broadcast || (nak && relayed),
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(self.config.server_address),
+            parameter_request_list: None,
+            dns_servers: if nak || self.config.dns_servers.is_empty() {
+                None
+            } else {
+                Some(self.config.dns_servers.clone())
+            },
+            max_size: None,
+            lease_duration: if nak || inform { None } else { Some(lease_secs) },
+            renew_duration: if nak || inform { None } else { Some(lease_secs / 2) },
+            rebind_duration: if nak || inform { None } else { Some(lease_secs / 8 * 7) },
+            additional_options: &[],
+        };
+
+        // Replies to relayed requests go back to the relay, which forwards them. Clients that
+        // already have an address (renewing or informing) get a unicast reply, all others a
+        // broadcast since they can't answer ARP requests yet.
+        let dest = if relayed {
+            IpEndpoint::new(request.relay_agent_ip.into(), DHCP_SERVER_PORT)
+        } else if !nak && !request.client_ip.is_unspecified() {
+            IpEndpoint::new(request.client_ip.into(), DHCP_CLIENT_PORT)
+        } else {
+            IpEndpoint::new(Ipv4Address::BROADCAST.into(), DHCP_CLIENT_PORT)
+        };
+
+        (reply, dest)
+    }
+
+    fn pool_address(&self, i: usize) -> Ipv4Address {
+        let start = u32::from_be_bytes(self.config.pool_start.0);
+        Ipv4Address::from_bytes(&(start + i as u32).to_be_bytes())
+    }
+
+    fn pool_index(&self, addr: Ipv4Address) -> Option<usize> {
+        let start = u32::from_be_bytes(self.config.pool_start.0);
+        let i = u32::from_be_bytes(addr.0).checked_sub(start)? as usize;
+        (i < N).then_some(i)
+    }
+
+    fn find(&self, mac: EthernetAddress) -> Option<usize> {
+        self.slots.iter().position(|s| match s {
+            Some(s) => s.state != LeaseState::Declined && s.hardware_address == mac,
+            None => false,
+        })
+    }
+
+    /// Whether pool address `i` can be handed to `mac`.
+    fn available(&self, i: usize, mac: EthernetAddress, now: Instant) -> bool {
+        match &self.slots[i] {
+            None => true,
+            Some(s) if s.expires <= now => true,
+            Some(s) => s.state != LeaseState::Declined && s.hardware_address == mac,
+        }
+    }
+
+    /// Pick an address for `mac`: its current lease if any, else the requested address if it's free,
+    /// else the first free address in the pool.
+    fn allocate(&self, mac: EthernetAddress, requested: Option<Ipv4Address>, now: Instant) -> Option<usize> {
+        if let Some(i) = self.find(mac) {
+            return Some(i);
+        }
+        if let Some(i) = requested.and_then(|ip| self.pool_index(ip)) {
+            if self.available(i, mac, now) {
+                return Some(i);
+            }
+        }
+        let free = (0..N).find(|&i| self.available(i, mac, now));
+        if free.is_none() {
+            warn!("dhcp server: address pool exhausted");
+        }
+        free
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4Address = Ipv4Address([192, 168, 4, 1]);
+    const POOL: Ipv4Address = Ipv4Address([192, 168, 4, 100]);
+    const MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x01]);
+    const OTHER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x02]);
+
+    fn table() -> LeaseTable<2> {
+        LeaseTable::new(Config::new(SERVER, POOL)).unwrap()
+    }
+
+    fn request(message_type: DhcpMessageType, mac: EthernetAddress) -> DhcpRepr<'static> {
+        DhcpRepr {
+            message_type,
+            transaction_id: 0x1234_5678,
+            secs: 0,
+            client_hardware_address: mac,
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: None,
+            subnet_mask: None,
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: false,
+            requested_ip: None,
+            client_identifier: Some(mac),
+            server_identifier: None,
+            parameter_request_list: Some(&[1, 3, 6]),
+            dns_servers: None,
+            max_size: None,
+            lease_duration: None,
+            renew_duration: None,
+            rebind_duration: None,
+            additional_options: &[],
+        }
+    }
+
+    /// Send `request` through the wire format, and check the reply.
+    fn exchange<const N: usize>(
+        table: &mut LeaseTable<N>,
+        request: &DhcpRepr<'_>,
+        check: impl FnOnce(DhcpRepr<'_>, IpEndpoint),
+    ) -> bool {
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let len = request.buffer_len();
+        request.emit(&mut DhcpPacket::new_unchecked(&mut rx[..len])).unwrap();
+
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        let Some((len, dest)) = table.handle(&rx[..len], &mut tx) else {
+            return false;
+        };
+        let packet = DhcpPacket::new_checked(&tx[..len]).unwrap();
+        check(DhcpRepr::parse(&packet).unwrap(), dest);
+        true
+    }
+
+    #[test]
+    fn discover_and_request() {
+        let mut table = table();
+        let broadcast = IpEndpoint::new(Ipv4Address::BROADCAST.into(), DHCP_CLIENT_PORT);
+
+        assert!(exchange(
+            &mut table,
+            &request(DhcpMessageType::Discover, MAC),
+            |reply, dest| {
+                assert_eq!(reply.message_type, DhcpMessageType::Offer);
+                assert_eq!(reply.transaction_id, 0x1234_5678);
+                assert_eq!(reply.client_hardware_address, MAC);
+                assert_eq!(reply.your_ip, POOL);
+                assert_eq!(reply.server_identifier, Some(SERVER));
+                assert_eq!(reply.subnet_mask, Some(Ipv4Address::new(255, 255, 255, 0)));
+                assert_eq!(reply.router, Some(SERVER));
+                // smoltcp doesn't emit the renewal and rebinding times, which clients then derive from
+                // the lease duration, with the same values.
+                assert_eq!(reply.lease_duration, Some(3600));
+                assert_eq!(dest, broadcast);
+            }
+        ));
+        assert_eq!(table.slots[0].unwrap().state, LeaseState::Offered);
+
+        let mut req = request(DhcpMessageType::Request, MAC);
+        req.requested_ip = Some(POOL);
+        req.server_identifier = Some(SERVER);
+        assert!(exchange(&mut table, &req, |reply, dest| {
+            assert_eq!(reply.message_type, DhcpMessageType::Ack);
+            assert_eq!(reply.your_ip, POOL);
+            assert_eq!(dest, broadcast);
+        }));
+        assert_eq!(table.slots[0].unwrap().state, LeaseState::Bound);
+
+        // Renewal, answered by unicast.
+        let mut req = request(DhcpMessageType::Request, MAC);
+        req.client_ip = POOL;
+        assert!(exchange(&mut table, &req, |reply, dest| {
+            assert_eq!(reply.message_type, DhcpMessageType::Ack);
+            assert_eq!(dest, IpEndpoint::new(POOL.into(), DHCP_CLIENT_PORT));
+        }));
+
+        // Another client gets the next address, even when asking for the leased one.
+        let mut req = request(DhcpMessageType::Discover, OTHER_MAC);
+        req.requested_ip = Some(POOL);
+        assert!(exchange(&mut table, &req, |reply, _| {
+            assert_eq!(reply.your_ip, Ipv4Address::new(192, 168, 4, 101));
+        }));
+    }
+
+    #[test]
+    fn nak_and_other_server() {
+        let mut table = table();
+
+        let mut req = request(DhcpMessageType::Request, MAC);
+        req.requested_ip = Some(Ipv4Address::new(10, 0, 0, 5));
+        req.server_identifier = Some(SERVER);
+        assert!(exchange(&mut table, &req, |reply, _| {
+            assert_eq!(reply.message_type, DhcpMessageType::Nak);
+            assert_eq!(reply.your_ip, Ipv4Address::UNSPECIFIED);
+            assert_eq!(reply.subnet_mask, None);
+            assert_eq!(reply.router, None);
+            assert_eq!(reply.lease_duration, None);
+        }));
+
+        // A client selecting another server releases our offer.
+        assert!(exchange(
+            &mut table,
+            &request(DhcpMessageType::Discover, MAC),
+            |_, _| {}
+        ));
+        let mut req = request(DhcpMessageType::Request, MAC);
+        req.requested_ip = Some(Ipv4Address::new(192, 168, 4, 50));
+        req.server_identifier = Some(Ipv4Address::new(192, 168, 4, 2));
+        assert!(!exchange(&mut table, &req, |_, _| {}));
+        assert!(table.slots[0].is_none());
+    }
+
+    #[test]
+    fn init_reboot() {
+        let mut table = table();
+
+        // Unknown clients are ignored, another server may know them.
+        let mut req = request(DhcpMessageType::Request, MAC);
+        req.requested_ip = Some(Ipv4Address::new(10, 0, 0, 5));
+        assert!(!exchange(&mut table, &req, |_, _| {}));
+        req.requested_ip = Some(POOL);
+        assert!(!exchange(&mut table, &req, |_, _| {}));
+        assert!(table.slots[0].is_none());
+
+        // Known clients get their address confirmed, or a NAK if it's wrong.
+        let mut select = request(DhcpMessageType::Request, MAC);
+        select.requested_ip = Some(POOL);
+        select.server_identifier = Some(SERVER);
+        assert!(exchange(&mut table, &select, |reply, _| {
+            assert_eq!(reply.message_type, DhcpMessageType::Ack);
+        }));
+        assert!(exchange(&mut table, &req, |reply, _| {
+            assert_eq!(reply.message_type, DhcpMessageType::Ack);
+            assert_eq!(reply.your_ip, POOL);
+        }));
+        req.requested_ip = Some(Ipv4Address::new(10, 0, 0, 5));
+        assert!(exchange(&mut table, &req, |reply, _| {
+            assert_eq!(reply.message_type, DhcpMessageType::Nak);
+        }));
+    }
+
+    #[test]
+    fn relayed() {
+        let mut table = table();
+        let relay = Ipv4Address::new(192, 168, 4, 2);
+        let to_relay = IpEndpoint::new(relay.into(), DHCP_SERVER_PORT);
+
+        let mut req = request(DhcpMessageType::Discover, MAC);
+        req.relay_agent_ip = relay;
+        assert!(exchange(&mut table, &req, |reply, dest| {
+            assert_eq!(reply.message_type, DhcpMessageType::Offer);
+            assert_eq!(reply.relay_agent_ip, relay);
+            assert!(!reply.broadcast);
+            assert_eq!(dest, to_relay);
+        }));
+
+        // Even for clients with an address, and with the broadcast bit set in NAKs.
+        let mut req = request(DhcpMessageType::Request, MAC);
+        req.relay_agent_ip = relay;
+        req.client_ip = Ipv4Address::new(10, 0, 0, 5);
+        assert!(exchange(&mut table, &req, |reply, dest| {
+            assert_eq!(reply.message_type, DhcpMessageType::Nak);
+            assert!(reply.broadcast);
+            assert_eq!(dest, to_relay);
+        }));
+    }
+
+    #[test]
+    fn pool_validation() {
+        fn check<const N: usize>(pool_start: Ipv4Address, subnet_mask: Ipv4Address) -> Result<(), Error> {
+            let mut config = Config::new(SERVER, pool_start);
+            config.subnet_mask = subnet_mask;
+            LeaseTable::<N>::new(config).map(|_| ())
+        }
+        let mask_24 = Ipv4Address::new(255, 255, 255, 0);
+        let mask_28 = Ipv4Address::new(255, 255, 255, 240);
+
+        assert_eq!(check::<155>(POOL, mask_24), Ok(()));
+        assert_eq!(check::<13>(Ipv4Address::new(192, 168, 4, 2), mask_28), Ok(()));
+        // Empty, or containing the server.
+        assert_eq!(check::<0>(POOL, mask_24), Err(Error::InvalidPool));
+        assert_eq!(
+            check::<4>(Ipv4Address::new(192, 168, 4, 0), mask_24),
+            Err(Error::InvalidPool)
+        );
+        // Outside the subnet, or running into its broadcast address.
+        assert_eq!(
+            check::<4>(Ipv4Address::new(192, 168, 5, 100), mask_24),
+            Err(Error::InvalidPool)
+        );
+        assert_eq!(check::<156>(POOL, mask_24), Err(Error::InvalidPool));
+        assert_eq!(check::<4>(POOL, mask_28), Err(Error::InvalidPool));
+        // Invalid mask.
+        assert_eq!(
+            check::<4>(POOL, Ipv4Address::new(255, 0, 255, 0)),
+            Err(Error::InvalidPool)
+        );
+    }
+
+    #[test]
+    fn decline_and_release() {
+        let mut table = table();
+
+        let mut decline = request(DhcpMessageType::Decline, MAC);
+        decline.requested_ip = Some(POOL);
+        decline.server_identifier = Some(SERVER);
+        assert!(!exchange(&mut table, &decline, |_, _| {}));
+        assert_eq!(table.slots[0].unwrap().state, LeaseState::Declined);
+
+        assert!(exchange(
+            &mut table,
+            &request(DhcpMessageType::Discover, MAC),
+            |reply, _| {
+                assert_eq!(reply.your_ip, Ipv4Address::new(192, 168, 4, 101));
+            }
+        ));
+        // The pool is exhausted.
+        assert!(!exchange(
+            &mut table,
+            &request(DhcpMessageType::Discover, OTHER_MAC),
+            |_, _| {}
+        ));
+
+        let mut release = request(DhcpMessageType::Release, MAC);
+        release.client_ip = Ipv4Address::new(192, 168, 4, 101);
+        release.server_identifier = Some(SERVER);
+        assert!(!exchange(&mut table, &release, |_, _| {}));
+        assert!(table.slots[1].is_none());
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let mut table = table();
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let req = request(DhcpMessageType::Discover, MAC);
+        let len = req.buffer_len();
+        req.emit(&mut DhcpPacket::new_unchecked(&mut rx[..len])).unwrap();
+        let mut tx = [0; MAX_MESSAGE_LEN];
+
+        // Truncated header.
+        assert_eq!(table.handle(&rx[..100], &mut tx), None);
+        // Options without a message type.
+        let mut no_type = rx;
+        no_type[240..243].copy_from_slice(&[0, 0, 0]);
+        assert_eq!(table.handle(&no_type[..len], &mut tx), None);
+        // Reply that doesn't fit.
+        assert_eq!(table.handle(&rx[..len], &mut tx[..100]), None);
+        assert!(table.handle(&rx[..len], &mut tx).is_some());
+    }
+}
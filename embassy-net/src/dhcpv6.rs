@@ -0,0 +1,728 @@
+//! DHCPv6 client (RFC 8415), used for managed networks and stateless DNS configuration.
+//!
+//! The client is started by the Router Advertisement flags: "managed" requests an address
+//! (IA_NA), "other configuration" only requests DNS servers with an Information-request.
+//!
+//! Advertisements are collected until the first Solicit retransmission, and the one with the
+//! highest preference is requested. An advertisement with the maximum preference is requested
+//! immediately.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::udp;
+use smoltcp::wire::{IpEndpoint, Ipv6Address};
+
+use crate::SocketStack;
+
+const CLIENT_PORT: u16 = 546;
+const SERVER_PORT: u16 = 547;
+/// All_DHCP_Relay_Agents_and_Servers (ff02::1:2).
+const ALL_SERVERS: Ipv6Address = Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2]);
+
+const MSG_SOLICIT: u8 = 1;
+const MSG_ADVERTISE: u8 = 2;
+const MSG_REQUEST: u8 = 3;
+const MSG_RENEW: u8 = 5;
+const MSG_REBIND: u8 = 6;
+const MSG_REPLY: u8 = 7;
+const MSG_INFORMATION_REQUEST: u8 = 11;
+
+const OPT_CLIENTID: u16 = 1;
+const OPT_SERVERID: u16 = 2;
+const OPT_IA_NA: u16 = 3;
+const OPT_IAADDR: u16 = 5;
+const OPT_ORO: u16 = 6;
+const OPT_PREFERENCE: u16 = 7;
+const OPT_ELAPSED_TIME: u16 = 8;
+const OPT_STATUS_CODE: u16 = 13;
+const OPT_DNS_SERVERS: u16 = 23;
+const OPT_INFORMATION_REFRESH_TIME: u16 = 32;
+
+const STATUS_SUCCESS: u16 = 0;
+
+/// Preference of an advertisement to be requested without waiting for others (RFC 8415 section 18.2.1).
+const MAX_PREFERENCE: u8 = 255;
+
+const MAX_DUID_LEN: usize = 130;
+const MAX_DNS_SERVERS: usize = 3;
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// Default refresh time for stateless configuration (RFC 8415 `IRT_DEFAULT`).
+const DEFAULT_REFRESH: Duration = Duration::from_secs(86400);
+/// Maximum number of Request retransmissions before going back to soliciting (`REQ_MAX_RC`).
+const REQ_MAX_RC: u8 = 10;
+
+pub(crate) const RX_BUFFER_LEN: usize = 1024;
+pub(crate) const TX_BUFFER_LEN: usize = MAX_MESSAGE_LEN;
+
+/// Buffers for the DHCPv6 UDP socket, and for the received messages.
+pub(crate) struct Resources {
+    rx_meta: [udp::PacketMetadata; 2],
+    rx_buffer: [u8; RX_BUFFER_LEN],
+    tx_meta: [udp::PacketMetadata; 1],
+    tx_buffer: [u8; TX_BUFFER_LEN],
+    message: [u8; RX_BUFFER_LEN],
+}
+
+impl Resources {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_meta: [udp::PacketMetadata::EMPTY; 2],
+            rx_buffer: [0; RX_BUFFER_LEN],
+            tx_meta: [udp::PacketMetadata::EMPTY; 1],
+            tx_buffer: [0; TX_BUFFER_LEN],
+            message: [0; RX_BUFFER_LEN],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum State {
+    /// Waiting for a Router Advertisement asking for DHCPv6.
+    Idle,
+    Soliciting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+    InformationRequesting,
+    Informed,
+}
+
+impl State {
+    /// Initial and maximum retransmission timeouts.
+    fn timeouts(self) -> Option<(Duration, Duration)> {
+        match self {
+            State::Soliciting | State::InformationRequesting => {
+                Some((Duration::from_secs(1), Duration::from_secs(120)))
+            }
+            State::Requesting => Some((Duration::from_secs(1), Duration::from_secs(30))),
+            State::Renewing | State::Rebinding => Some((Duration::from_secs(10), Duration::from_secs(600))),
+            _ => None,
+        }
+    }
+}
+
+struct Lease {
+    address: Ipv6Address,
+    renew_at: Instant,
+    rebind_at: Instant,
+    expires: Instant,
+}
+
+/// Contents of an Advertise or Reply message.
+struct Message<'a> {
+    kind: u8,
+    server_id: Option<&'a [u8]>,
+    /// Address, T1, T2, valid lifetime.
+    address: Option<(Ipv6Address, u32, u32, u32)>,
+    dns_servers: Vec<Ipv6Address, MAX_DNS_SERVERS>,
+    refresh: Option<u32>,
+    preference: u8,
+    success: bool,
+}
+
+fn options(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    core::iter::from_fn(move || {
+        if data.len() < 4 {
+            return None;
+        }
+        let code = u16::from_be_bytes([data[0], data[1]]);
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if data.len() < 4 + len {
+            return None;
+        }
+        let value = &data[4..4 + len];
+        data = &data[4 + len..];
+        Some((code, value))
+    })
+}
+
+fn be32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn status_ok(value: &[u8]) -> bool {
+    value.len() >= 2 && u16::from_be_bytes([value[0], value[1]]) == STATUS_SUCCESS
+}
+
+impl<'a> Message<'a> {
+    fn parse(data: &'a [u8], xid: u32, duid: &[u8], iaid: u32) -> Option<Self> {
+        if data.len() < 4 || be32(data) & 0x00ff_ffff != xid {
+            return None;
+        }
+
+        let mut msg = Message {
+            kind: data[0],
+            server_id: None,
+            address: None,
+            dns_servers: Vec::new(),
+            refresh: None,
+            preference: 0,
+            success: true,
+        };
+        let mut client_id_ok = false;
+
+        for (code, value) in options(&data[4..]) {
+            match code {
+                OPT_CLIENTID => client_id_ok = value == duid,
+                OPT_SERVERID => msg.server_id = Some(value),
+                OPT_STATUS_CODE => msg.success = status_ok(value),
+                OPT_IA_NA if value.len() >= 12 && be32(value) == iaid => {
+                    let (t1, t2) = (be32(&value[4..]), be32(&value[8..]));
+                    for (code, value) in options(&value[12..]) {
+                        match code {
+                            OPT_STATUS_CODE => msg.success &= status_ok(value),
+                            OPT_IAADDR if value.len() >= 24 => {
+                                let valid = be32(&value[20..]);
+                                if valid != 0 && msg.address.is_none() {
+                                    let addr = Ipv6Address::from_bytes(&value[..16]);
+                                    msg.address = Some((addr, t1, t2, valid));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                OPT_DNS_SERVERS => {
+                    for addr in value.chunks_exact(16).take(MAX_DNS_SERVERS) {
+                        unwrap!(msg.dns_servers.push(Ipv6Address::from_bytes(addr)).ok());
+                    }
+                }
+                OPT_INFORMATION_REFRESH_TIME if value.len() == 4 => msg.refresh = Some(be32(value)),
+                OPT_PREFERENCE if value.len() == 1 => msg.preference = value[0],
+                _ => {}
+            }
+        }
+
+        (client_id_ok && msg.server_id.is_some()).then_some(msg)
+    }
+}
+
+struct Writer {
+    buf: Vec<u8, MAX_MESSAGE_LEN>,
+}
+
+impl Writer {
+    fn new(kind: u8, xid: u32) -> Self {
+        let mut buf = Vec::new();
+        let header = (kind as u32) << 24 | xid;
+        unwrap!(buf.extend_from_slice(&header.to_be_bytes()).ok());
+        Self { buf }
+    }
+
+    fn option(&mut self, code: u16, value: &[&[u8]]) {
+        let len: usize = value.iter().map(|v| v.len()).sum();
+        let res = self
+            .buf
+            .extend_from_slice(&code.to_be_bytes())
+            .and_then(|_| self.buf.extend_from_slice(&(len as u16).to_be_bytes()));
+        unwrap!(res.ok());
+        for v in value {
+            unwrap!(self.buf.extend_from_slice(v).ok());
+        }
+    }
+}
+
+/// DHCPv6 client state, owned by the autoconfiguration state.
+pub(crate) struct Client {
+    handle: SocketHandle,
+    /// Taken while the received messages are processed.
+    message: &'static mut [u8],
+    duid: Vec<u8, MAX_DUID_LEN>,
+    iaid: u32,
+    state: State,
+    xid: u32,
+    started: Instant,
+    next_transmit: Instant,
+    timeout: Duration,
+    retransmissions: u8,
+    server_id: Vec<u8, MAX_DUID_LEN>,
+    /// Best advertised address so far, and its preference.
+    offered: Option<(Ipv6Address, u8)>,
+    lease: Option<Lease>,
+    dns_servers: Vec<Ipv6Address, MAX_DNS_SERVERS>,
+    refresh_at: Option<Instant>,
+}
+
+impl Client {
+    pub(crate) fn new(
+        s: &mut SocketStack,
+        resources: &'static mut Resources,
+        hardware_addr: &[u8],
+        iid: &[u8; 8],
+    ) -> Self {
+        let Resources {
+            rx_meta,
+            rx_buffer,
+            tx_meta,
+            tx_buffer,
+            message,
+        } = resources;
+        let mut socket = udp::Socket::new(
+            udp::PacketBuffer::new(&mut rx_meta[..], &mut rx_buffer[..]),
+            udp::PacketBuffer::new(&mut tx_meta[..], &mut tx_buffer[..]),
+        );
+        unwrap!(socket.bind(CLIENT_PORT));
+        let handle = s.sockets.add(socket);
+
+        // DUID-LL (RFC 8415 section 11.4), with the Ethernet or EUI-64 hardware type.
+        let mut duid = Vec::new();
+        let (hw_type, hw_addr): (u16, &[u8]) = match hardware_addr.len() {
+            6 => (1, hardware_addr),
+            _ => (27, iid),
+        };
+        unwrap!(duid.extend_from_slice(&3u16.to_be_bytes()).ok());
+        unwrap!(duid.extend_from_slice(&hw_type.to_be_bytes()).ok());
+        unwrap!(duid.extend_from_slice(hw_addr).ok());
+
+        Self {
+            handle,
+            message,
+            duid,
+            iaid: be32(&iid[4..]),
+            state: State::Idle,
+            xid: 0,
+            started: Instant::now(),
+            next_transmit: Instant::now(),
+            timeout: Duration::from_secs(0),
+            retransmissions: 0,
+            server_id: Vec::new(),
+            offered: None,
+            lease: None,
+            dns_servers: Vec::new(),
+            refresh_at: None,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.state = State::Idle;
+        self.lease = None;
+        self.offered = None;
+        self.dns_servers.clear();
+        self.refresh_at = None;
+    }
+
+    /// Address leased from the server, if any.
+    pub(crate) fn address(&self) -> Option<Ipv6Address> {
+        self.lease.as_ref().map(|l| l.address)
+    }
+
+    pub(crate) fn dns_servers(&self) -> &[Ipv6Address] {
+        &self.dns_servers
+    }
+
+    /// Start DHCPv6 as requested by the flags of a Router Advertisement.
+    pub(crate) fn on_router_flags(&mut self, managed: bool, other: bool) {
+        match self.state {
+            State::Idle | State::InformationRequesting | State::Informed if managed => {
+                self.enter(State::Soliciting, Instant::now())
+            }
+            State::Idle if other => self.enter(State::InformationRequesting, Instant::now()),
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, state: State, now: Instant) {
+        debug!("DHCPv6: {:?} -> {:?}", self.state, state);
+        self.state = state;
+        self.xid = (self.xid.wrapping_add(0x9e37_79b9) ^ now.as_ticks() as u32) & 0x00ff_ffff;
+        self.started = now;
+        self.next_transmit = now;
+        self.retransmissions = 0;
+        if state == State::Soliciting {
+            self.offered = None;
+        }
+        if let Some((initial, _)) = state.timeouts() {
+            self.timeout = initial;
+        }
+    }
+
+    pub(crate) fn poll(&mut self, s: &mut SocketStack, now: Instant) {
+        let socket = s.sockets.get_mut::<udp::Socket>(self.handle);
+
+        let buf = core::mem::take(&mut self.message);
+        while let Ok((len, _)) = socket.recv_slice(buf) {
+            if let Some(msg) = Message::parse(&buf[..len], self.xid, &self.duid, self.iaid) {
+                self.process(msg, now);
+            }
+        }
+        self.message = buf;
+
+        if let Some(lease) = &self.lease {
+            if lease.expires <= now {
+                info!("DHCPv6: lease of {:?} expired", lease.address);
+                self.lease = None;
+                self.enter(State::Soliciting, now);
+            } else if self.state == State::Renewing && lease.rebind_at <= now {
+                self.enter(State::Rebinding, now);
+            } else if self.state == State::Bound && lease.renew_at <= now {
+                self.enter(State::Renewing, now);
+            }
+        }
+        if self.state == State::Informed && self.refresh_at.is_some_and(|t| t <= now) {
+            self.enter(State::InformationRequesting, now);
+        }
+
+        if let Some((_, max)) = self.state.timeouts() {
+            if self.next_transmit <= now {
+                if self.state == State::Requesting && self.retransmissions >= REQ_MAX_RC {
+                    self.enter(State::Soliciting, now);
+                } else if self.state == State::Soliciting && self.offered.is_some() {
+                    // End of the first retransmission period: request the best advertisement.
+                    self.enter(State::Requesting, now);
+                }
+                self.transmit(socket, now);
+                self.retransmissions = self.retransmissions.saturating_add(1);
+                self.next_transmit = now + self.timeout;
+                self.timeout = (self.timeout * 2).min(max);
+            }
+        }
+    }
+
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        if self.state.timeouts().is_some() {
+            return Some(self.next_transmit);
+        }
+        match self.state {
+            State::Bound => self.lease.as_ref().map(|l| l.renew_at),
+            State::Informed => self.refresh_at,
+            _ => None,
+        }
+    }
+
+    fn process(&mut self, msg: Message<'_>, now: Instant) {
+        // The transaction ID still matches once the transaction is done, so the DNS servers are only
+        // taken from a successful reply while waiting for one.
+        match (self.state, msg.kind) {
+            (State::Soliciting, MSG_ADVERTISE) => {
+                let (Some(server_id), Some((addr, ..)), true) = (msg.server_id, msg.address, msg.success) else {
+                    return;
+                };
+                if self.offered.is_some_and(|(_, preference)| preference >= msg.preference) {
+                    return;
+                }
+                self.server_id.clear();
+                if self.server_id.extend_from_slice(server_id).is_err() {
+                    return;
+                }
+                self.offered = Some((addr, msg.preference));
+                // Advertisements received after the first retransmission are not waited for.
+                if msg.preference == MAX_PREFERENCE || self.retransmissions > 1 {
+                    self.enter(State::Requesting, now);
+                }
+            }
+            (State::Requesting | State::Renewing | State::Rebinding, MSG_REPLY) => match msg.address {
+                Some((address, t1, t2, valid)) if msg.success => {
+                    if self.state == State::Rebinding {
+                        if let Some(server_id) = msg.server_id {
+                            self.server_id.clear();
+                            self.server_id.extend_from_slice(server_id).ok();
+                        }
+                    }
+                    let valid = Duration::from_secs(valid as u64);
+                    // RFC 8415 section 21.4: if the server leaves T1/T2 to the client, use 0.5 and 0.8.
+                    let t1 = if t1 == 0 {
+                        valid / 2
+                    } else {
+                        Duration::from_secs(t1 as u64)
+                    };
+                    let t2 = if t2 == 0 {
+                        valid * 4 / 5
+                    } else {
+                        Duration::from_secs(t2 as u64)
+                    };
+                    if self.lease.as_ref().map(|l| l.address) != Some(address) {
+                        info!("DHCPv6: leased {:?}", address);
+                    }
+                    self.lease = Some(Lease {
+                        address,
+                        renew_at: now + t1,
+                        rebind_at: now + t2,
+                        expires: now + valid,
+                    });
+                    if !msg.dns_servers.is_empty() {
+                        self.dns_servers = msg.dns_servers;
+                    }
+                    self.state = State::Bound;
+                }
+                // RFC 8415 section 18.2.10.1: the current address is used until it expires, and
+                // renewed again on the next retransmission.
+                _ if self.state != State::Requesting => debug!("DHCPv6: no address renewed"),
+                _ => {
+                    debug!("DHCPv6: request failed");
+                    self.lease = None;
+                    self.enter(State::Soliciting, now);
+                }
+            },
+            (State::InformationRequesting, MSG_REPLY) => {
+                if !msg.dns_servers.is_empty() {
+                    self.dns_servers = msg.dns_servers;
+                }
+                let refresh = msg
+                    .refresh
+                    .map_or(DEFAULT_REFRESH, |r| Duration::from_secs(r.max(600) as u64));
+                self.refresh_at = Some(now + refresh);
+                self.state = State::Informed;
+            }
+            _ => {}
+        }
+    }
+
+    fn transmit(&self, socket: &mut udp::Socket, now: Instant) {
+        let kind = match self.state {
+            State::Soliciting => MSG_SOLICIT,
+            State::Requesting => MSG_REQUEST,
+            State::Renewing => MSG_RENEW,
+            State::Rebinding => MSG_REBIND,
+            State::InformationRequesting => MSG_INFORMATION_REQUEST,
+            _ => return,
+        };
+
+        let mut w = Writer::new(kind, self.xid);
+        w.option(OPT_CLIENTID, &[&self.duid]);
+        if matches!(kind, MSG_REQUEST | MSG_RENEW) {
+            w.option(OPT_SERVERID, &[&self.server_id]);
+        }
+        let elapsed = (now.saturating_duration_since(self.started).as_millis() / 10).min(0xffff) as u16;
+        w.option(OPT_ELAPSED_TIME, &[&elapsed.to_be_bytes()]);
+
+        if kind == MSG_INFORMATION_REQUEST {
+            w.option(
+                OPT_ORO,
+                &[
+                    &OPT_DNS_SERVERS.to_be_bytes(),
+                    &OPT_INFORMATION_REFRESH_TIME.to_be_bytes(),
+                ],
+            );
+        } else {
+            w.option(OPT_ORO, &[&OPT_DNS_SERVERS.to_be_bytes()]);
+
+            let iaid = self.iaid.to_be_bytes();
+            let address = match kind {
+                MSG_SOLICIT => None,
+                MSG_REQUEST => self.offered.map(|(addr, _)| addr),
+                _ => self.address(),
+            };
+            match address {
+                Some(addr) => {
+                    // IAADDR option: address, preferred and valid lifetimes left to the server.
+                    let mut iaaddr = [0; 28];
+                    iaaddr[..2].copy_from_slice(&OPT_IAADDR.to_be_bytes());
+                    iaaddr[2..4].copy_from_slice(&24u16.to_be_bytes());
+                    iaaddr[4..20].copy_from_slice(addr.as_bytes());
+                    w.option(OPT_IA_NA, &[&iaid, &[0; 8], &iaaddr]);
+                }
+                None => w.option(OPT_IA_NA, &[&iaid, &[0; 8]]),
+            }
+        }
+
+        trace!("DHCPv6: sending message type {}", kind);
+        if socket
+            .send_slice(&w.buf, IpEndpoint::new(ALL_SERVERS.into(), SERVER_PORT))
+            .is_err()
+        {
+            warn!("DHCPv6: failed to send message");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUID: [u8; 10] = [0, 3, 0, 1, 0x02, 0, 0, 0, 0, 0x01];
+    const IAID: u32 = 0xfe00_0001;
+    const XID: u32 = 0x12_3456;
+    const ADDR: Ipv6Address = Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0x01]);
+    const DNS: Ipv6Address = Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x53]);
+
+    fn client() -> Client {
+        Client {
+            handle: SocketHandle::default(),
+            message: Default::default(),
+            duid: unwrap!(Vec::from_slice(&DUID).ok()),
+            iaid: IAID,
+            state: State::Idle,
+            xid: 0,
+            started: Instant::from_secs(0),
+            next_transmit: Instant::from_secs(0),
+            timeout: Duration::from_secs(0),
+            retransmissions: 0,
+            server_id: Vec::new(),
+            offered: None,
+            lease: None,
+            dns_servers: Vec::new(),
+            refresh_at: None,
+        }
+    }
+
+    /// IA_NA option value, with one address valid for an hour if `addr` is given.
+    fn ia_na(iaid: u32, addr: Option<Ipv6Address>) -> Vec<u8, 44> {
+        let mut ia = Vec::new();
+        unwrap!(ia.extend_from_slice(&iaid.to_be_bytes()).ok());
+        unwrap!(ia.extend_from_slice(&1800u32.to_be_bytes()).ok());
+        unwrap!(ia.extend_from_slice(&2880u32.to_be_bytes()).ok());
+        if let Some(addr) = addr {
+            unwrap!(ia.extend_from_slice(&OPT_IAADDR.to_be_bytes()).ok());
+            unwrap!(ia.extend_from_slice(&24u16.to_be_bytes()).ok());
+            unwrap!(ia.extend_from_slice(addr.as_bytes()).ok());
+            unwrap!(ia.extend_from_slice(&3000u32.to_be_bytes()).ok());
+            unwrap!(ia.extend_from_slice(&3600u32.to_be_bytes()).ok());
+        }
+        ia
+    }
+
+    /// Server message for the client, with an address and a DNS server.
+    fn server_message(kind: u8, xid: u32, server_id: u8, preference: Option<u8>, addr: Option<Ipv6Address>) -> Writer {
+        let mut w = Writer::new(kind, xid);
+        w.option(OPT_CLIENTID, &[&DUID]);
+        w.option(OPT_SERVERID, &[&[0, 3, 0, 1, 0x02, 0, 0, 0, 0, server_id]]);
+        w.option(OPT_IA_NA, &[&ia_na(IAID, addr)]);
+        w.option(OPT_DNS_SERVERS, &[DNS.as_bytes()]);
+        if let Some(preference) = preference {
+            w.option(OPT_PREFERENCE, &[&[preference]]);
+        }
+        w
+    }
+
+    fn parse(w: &Writer) -> Option<Message<'_>> {
+        Message::parse(&w.buf, XID, &DUID, IAID)
+    }
+
+    #[test]
+    fn parse_reply() {
+        let w = server_message(MSG_REPLY, XID, 1, Some(10), Some(ADDR));
+        let msg = unwrap!(parse(&w));
+        assert_eq!(msg.kind, MSG_REPLY);
+        assert_eq!(msg.server_id, Some(&[0, 3, 0, 1, 0x02, 0, 0, 0, 0, 1][..]));
+        assert_eq!(msg.address, Some((ADDR, 1800, 2880, 3600)));
+        assert_eq!(&msg.dns_servers[..], &[DNS]);
+        assert_eq!(msg.preference, 10);
+        assert!(msg.success);
+    }
+
+    #[test]
+    fn parse_rejected() {
+        // Other transaction.
+        assert!(parse(&server_message(MSG_REPLY, XID + 1, 1, None, Some(ADDR))).is_none());
+
+        // Other client.
+        let mut w = Writer::new(MSG_REPLY, XID);
+        w.option(OPT_CLIENTID, &[&[0, 3, 0, 1, 0x02, 0, 0, 0, 0, 0x02]]);
+        w.option(OPT_SERVERID, &[&[0, 1]]);
+        assert!(parse(&w).is_none());
+
+        // No server identifier.
+        let mut w = Writer::new(MSG_REPLY, XID);
+        w.option(OPT_CLIENTID, &[&DUID]);
+        assert!(parse(&w).is_none());
+    }
+
+    #[test]
+    fn parse_options() {
+        let mut w = Writer::new(MSG_REPLY, XID);
+        w.option(OPT_CLIENTID, &[&DUID]);
+        w.option(OPT_SERVERID, &[&[0, 1]]);
+        // Another IA, and an unknown option.
+        w.option(OPT_IA_NA, &[&ia_na(IAID + 1, Some(ADDR))]);
+        w.option(0x1234, &[&[1, 2, 3]]);
+        w.option(OPT_STATUS_CODE, &[&2u16.to_be_bytes(), b"no addrs"]);
+        let msg = unwrap!(parse(&w));
+        assert_eq!(msg.address, None);
+        assert!(!msg.success);
+
+        // A truncated option ends the message.
+        let mut w = Writer::new(MSG_REPLY, XID);
+        w.option(OPT_CLIENTID, &[&DUID]);
+        w.option(OPT_SERVERID, &[&[0, 1]]);
+        w.option(OPT_IA_NA, &[&ia_na(IAID, Some(ADDR))]);
+        w.buf.truncate(w.buf.len() - 1);
+        assert_eq!(unwrap!(parse(&w)).address, None);
+
+        let data = [0, 1, 0, 4, 1, 2, 3, 4, 0, 2, 0, 8, 1];
+        let mut options = options(&data);
+        assert_eq!(options.next(), Some((1, &[1, 2, 3, 4][..])));
+        assert_eq!(options.next(), None);
+    }
+
+    #[test]
+    fn advertise_preference() {
+        let mut c = client();
+        let now = Instant::from_secs(10);
+        c.enter(State::Soliciting, now);
+        c.retransmissions = 1;
+        let xid = c.xid;
+
+        let w = server_message(MSG_ADVERTISE, xid, 1, Some(10), Some(ADDR));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), now);
+        let other = Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0x02]);
+        let w = server_message(MSG_ADVERTISE, xid, 2, Some(20), Some(other));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), now);
+        let w = server_message(MSG_ADVERTISE, xid, 3, None, Some(ADDR));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), now);
+
+        // Still collecting advertisements, the best one so far is kept.
+        assert_eq!(c.state, State::Soliciting);
+        assert_eq!(c.offered, Some((other, 20)));
+        assert_eq!(c.server_id[..], [0, 3, 0, 1, 0x02, 0, 0, 0, 0, 2]);
+
+        // The maximum preference is requested immediately.
+        let w = server_message(MSG_ADVERTISE, xid, 4, Some(MAX_PREFERENCE), Some(ADDR));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), now);
+        assert_eq!(c.state, State::Requesting);
+        assert_eq!(c.offered, Some((ADDR, MAX_PREFERENCE)));
+        assert_eq!(c.server_id[..], [0, 3, 0, 1, 0x02, 0, 0, 0, 0, 4]);
+    }
+
+    #[test]
+    fn late_advertise() {
+        let mut c = client();
+        let now = Instant::from_secs(10);
+        c.enter(State::Soliciting, now);
+        // After the first retransmission, the first advertisement is taken.
+        c.retransmissions = 2;
+        let xid = c.xid;
+
+        let w = server_message(MSG_ADVERTISE, xid, 1, None, Some(ADDR));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), now);
+        assert_eq!(c.state, State::Requesting);
+        assert_eq!(c.offered, Some((ADDR, 0)));
+    }
+
+    #[test]
+    fn lease() {
+        let mut c = client();
+        let now = Instant::from_secs(10);
+        c.enter(State::Requesting, now);
+        let xid = c.xid;
+
+        let w = server_message(MSG_REPLY, xid, 1, None, Some(ADDR));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), now);
+        assert_eq!(c.state, State::Bound);
+        assert_eq!(c.address(), Some(ADDR));
+        assert_eq!(c.dns_servers(), &[DNS]);
+        assert_eq!(c.poll_at(), Some(now + Duration::from_secs(1800)));
+        let lease = unwrap!(c.lease.as_ref());
+        assert_eq!(lease.rebind_at, now + Duration::from_secs(2880));
+        assert_eq!(lease.expires, now + Duration::from_secs(3600));
+
+        // A renewal without address keeps the lease until it expires.
+        let later = now + Duration::from_secs(1800);
+        c.enter(State::Renewing, later);
+        let xid = c.xid;
+        let w = server_message(MSG_REPLY, xid, 1, None, None);
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), later);
+        assert_eq!(c.state, State::Renewing);
+        assert_eq!(c.address(), Some(ADDR));
+        assert_eq!(unwrap!(c.lease.as_ref()).expires, now + Duration::from_secs(3600));
+
+        // A successful renewal extends it.
+        let w = server_message(MSG_REPLY, xid, 1, None, Some(ADDR));
+        c.process(unwrap!(Message::parse(&w.buf, xid, &DUID, IAID)), later);
+        assert_eq!(c.state, State::Bound);
+        assert_eq!(unwrap!(c.lease.as_ref()).expires, later + Duration::from_secs(3600));
+    }
+}
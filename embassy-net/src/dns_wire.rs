@@ -0,0 +1,589 @@
+//! Minimal DNS message encoding and decoding.
+//!
+//! smoltcp's DNS support is limited to its own query socket, so protocols that need to build or
+//! inspect arbitrary DNS messages (mDNS, SRV/TXT lookups) use this instead. Names are never
+//! compressed when writing, but compressed names are followed when reading.
+#![allow(unused)]
+
+/// Header flag: message is a response.
+pub const FLAG_RESPONSE: u16 = 0x8000;
+/// Header flag: authoritative answer.
+pub const FLAG_AUTHORITATIVE: u16 = 0x0400;
+/// Header flag: message was truncated.
+pub const FLAG_TRUNCATED: u16 = 0x0200;
+/// Header flag: recursion desired.
+pub const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+/// Mask for the opcode in the header flags.
+pub const FLAG_OPCODE_MASK: u16 = 0x7800;
+/// Mask for the response code in the header flags.
+pub const FLAG_RCODE_MASK: u16 = 0x000f;
+
+pub const RCODE_NXDOMAIN: u16 = 3;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_ANY: u16 = 255;
+
+/// Maximum length of an encoded name.
+pub const MAX_NAME_LEN: usize = 255;
+
+const MAX_POINTER_HOPS: usize = 16;
+
+/// The message is malformed, or doesn't fit in the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Error;
+
+/// DNS message header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: u16,
+    pub answers: u16,
+    pub authorities: u16,
+    pub additionals: u16,
+}
+
+impl Header {
+    pub const LEN: usize = 12;
+
+    pub fn rcode(&self) -> u16 {
+        self.flags & FLAG_RCODE_MASK
+    }
+}
+
+/// Writes a DNS message into a buffer.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    header: Header,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8], id: u16, flags: u16) -> Result<Self, Error> {
+        if buf.len() < Header::LEN {
+            return Err(Error);
+        }
+        Ok(Self {
+            buf,
+            pos: Header::LEN,
+            header: Header {
+                id,
+                flags,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.pos + data.len();
+        if end > self.buf.len() {
+            return Err(Error);
+        }
+        self.buf[self.pos..end].copy_from_slice(data);
+        self.pos = end;
+        Ok(())
+    }
+
+    pub fn u8(&mut self, v: u8) -> Result<(), Error> {
+        self.bytes(&[v])
+    }
+
+    pub fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, v: u32) -> Result<(), Error> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    /// Write a name given as dot-separated labels, e.g. `"device.local"`.
+    pub fn name(&mut self, name: &str) -> Result<(), Error> {
+        self.labels(name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()))
+    }
+
+    /// Write a name given as a single label followed by a dot-separated suffix.
+    ///
+    /// Used for DNS-SD instance names, which may contain dots themselves.
+    pub fn name_with_label(&mut self, label: &str, suffix: &str) -> Result<(), Error> {
+        self.labels(core::iter::once(label).chain(suffix.trim_end_matches('.').split('.').filter(|l| !l.is_empty())))
+    }
+
+    fn labels<'l>(&mut self, labels: impl Iterator<Item = &'l str>) -> Result<(), Error> {
+        let start = self.pos;
+        for label in labels {
+            if label.len() > 63 {
+                return Err(Error);
+            }
+            self.u8(label.len() as u8)?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.u8(0)?;
+        if self.pos - start > MAX_NAME_LEN {
+            return Err(Error);
+        }
+        Ok(())
+    }
+
+    /// Write a TXT record's character-string.
+    pub fn character_string(&mut self, s: &[u8]) -> Result<(), Error> {
+        if s.len() > 255 {
+            return Err(Error);
+        }
+        self.u8(s.len() as u8)?;
+        self.bytes(s)
+    }
+
+    pub fn question(&mut self, name: &str, qtype: u16, qclass: u16) -> Result<(), Error> {
+        self.name(name)?;
+        self.u16(qtype)?;
+        self.u16(qclass)?;
+        self.header.questions += 1;
+        Ok(())
+    }
+
+    /// Write a resource record. The record's name must be written with [`name`](Self::name)
+    /// (or a variant) right before calling this.
+    pub fn record_data(
+        &mut self,
+        rtype: u16,
+        class: u16,
+        ttl: u32,
+        data: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.u16(rtype)?;
+        self.u16(class)?;
+        self.u32(ttl)?;
+        let len_pos = self.pos;
+        self.u16(0)?;
+        data(self)?;
+        let len = (self.pos - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+
+    pub fn answer_added(&mut self) {
+        self.header.answers += 1;
+    }
+
+    pub fn additional_added(&mut self) {
+        self.header.additionals += 1;
+    }
+
+    /// Current length of the message.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    /// Discard everything written after `len`. Used to drop a record that didn't fit.
+    pub fn truncate(&mut self, len: usize) {
+        self.pos = len.max(Header::LEN).min(self.pos);
+    }
+
+    /// Write the header and return the message length.
+    pub fn finish(self) -> usize {
+        let h = &self.header;
+        let b = &mut self.buf[..Header::LEN];
+        b[0..2].copy_from_slice(&h.id.to_be_bytes());
+        b[2..4].copy_from_slice(&h.flags.to_be_bytes());
+        b[4..6].copy_from_slice(&h.questions.to_be_bytes());
+        b[6..8].copy_from_slice(&h.answers.to_be_bytes());
+        b[8..10].copy_from_slice(&h.authorities.to_be_bytes());
+        b[10..12].copy_from_slice(&h.additionals.to_be_bytes());
+        self.pos
+    }
+}
+
+/// A possibly compressed name inside a message.
+#[derive(Clone, Copy)]
+pub struct Name<'a> {
+    msg: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Name<'a> {
+    /// Iterate over the labels of the name.
+    pub fn labels(&self) -> Labels<'a> {
+        Labels {
+            msg: self.msg,
+            pos: self.offset,
+            hops: 0,
+        }
+    }
+
+    /// Compare with a dot-separated name, ignoring ASCII case.
+    pub fn eq_str(&self, name: &str) -> bool {
+        let mut expected = name.trim_end_matches('.').split('.').filter(|l| !l.is_empty());
+        for label in self.labels() {
+            match (label, expected.next()) {
+                (Ok(l), Some(e)) if l.eq_ignore_ascii_case(e.as_bytes()) => {}
+                _ => return false,
+            }
+        }
+        expected.next().is_none()
+    }
+
+    /// Compare with a single label followed by a dot-separated suffix, ignoring ASCII case.
+    pub fn eq_label_suffix(&self, label: &str, suffix: &str) -> bool {
+        let mut labels = self.labels();
+        match labels.next() {
+            Some(Ok(l)) if l.eq_ignore_ascii_case(label.as_bytes()) => {}
+            _ => return false,
+        }
+        let rest = Name {
+            msg: self.msg,
+            offset: labels.pos,
+        };
+        // `labels.pos` may point at a compression pointer, which `labels()` follows.
+        rest.eq_str(suffix)
+    }
+
+    /// Whether the name ends with the given dot-separated suffix, ignoring ASCII case.
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        let n = self.labels().count();
+        let m = suffix
+            .trim_end_matches('.')
+            .split('.')
+            .filter(|l| !l.is_empty())
+            .count();
+        if m > n {
+            return false;
+        }
+        let mut labels = self.labels().skip(n - m);
+        let mut expected = suffix.trim_end_matches('.').split('.').filter(|l| !l.is_empty());
+        loop {
+            match (labels.next(), expected.next()) {
+                (None, None) => return true,
+                (Some(Ok(l)), Some(e)) if l.eq_ignore_ascii_case(e.as_bytes()) => {}
+                _ => return false,
+            }
+        }
+    }
+
+    /// Append the dot-separated form of the name to `out`.
+    pub fn write_to<const N: usize>(&self, out: &mut heapless::String<N>) -> Result<(), Error> {
+        for (i, label) in self.labels().enumerate() {
+            let label = core::str::from_utf8(label?).map_err(|_| Error)?;
+            if i != 0 {
+                out.push('.').map_err(|_| Error)?;
+            }
+            out.push_str(label).map_err(|_| Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the labels of a [`Name`].
+pub struct Labels<'a> {
+    msg: &'a [u8],
+    pos: usize,
+    hops: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = Result<&'a [u8], Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == usize::MAX {
+            return None;
+        }
+        loop {
+            let Some(&len) = self.msg.get(self.pos) else {
+                return self.fail();
+            };
+            let len = len as usize;
+            match len {
+                0 => return None,
+                0xc0..=0xff => {
+                    let Some(&lo) = self.msg.get(self.pos + 1) else {
+                        return self.fail();
+                    };
+                    self.hops += 1;
+                    if self.hops > MAX_POINTER_HOPS {
+                        return self.fail();
+                    }
+                    self.pos = ((len & 0x3f) << 8) | lo as usize;
+                }
+                1..=63 => {
+                    let Some(label) = self.msg.get(self.pos + 1..self.pos + 1 + len) else {
+                        return self.fail();
+                    };
+                    self.pos += 1 + len;
+                    return Some(Ok(label));
+                }
+                _ => return self.fail(),
+            }
+        }
+    }
+}
+
+impl<'a> Labels<'a> {
+    /// Return an error, and end the iteration.
+    fn fail(&mut self) -> Option<Result<&'a [u8], Error>> {
+        self.pos = usize::MAX;
+        Some(Err(Error))
+    }
+}
+
+/// A question entry.
+pub struct Question<'a> {
+    pub name: Name<'a>,
+    pub qtype: u16,
+    /// Class, with the top bit (mDNS "unicast response requested") masked off.
+    pub qclass: u16,
+    /// mDNS "QU" bit.
+    pub unicast_response: bool,
+}
+
+/// A resource record.
+pub struct Record<'a> {
+    pub name: Name<'a>,
+    pub rtype: u16,
+    /// Class, with the top bit (mDNS "cache flush") masked off.
+    pub class: u16,
+    /// Whether the mDNS "cache flush" bit is set.
+    pub cache_flush: bool,
+    pub ttl: u32,
+    pub data: &'a [u8],
+    /// Offset of `data` in the message, needed to decode compressed names inside it.
+    pub data_offset: usize,
+}
+
+impl<'a> Record<'a> {
+    /// Decode a name inside the record data, at offset `at` from the start of the data.
+    pub fn data_name(&self, msg: &'a [u8], at: usize) -> Name<'a> {
+        Name {
+            msg,
+            offset: self.data_offset + at,
+        }
+    }
+}
+
+/// Reads a DNS message.
+pub struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+    header: Header,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(msg: &'a [u8]) -> Result<Self, Error> {
+        if msg.len() < Header::LEN {
+            return Err(Error);
+        }
+        let u = |i: usize| u16::from_be_bytes([msg[i], msg[i + 1]]);
+        Ok(Self {
+            msg,
+            pos: Header::LEN,
+            header: Header {
+                id: u(0),
+                flags: u(2),
+                questions: u(4),
+                answers: u(6),
+                authorities: u(8),
+                additionals: u(10),
+            },
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn message(&self) -> &'a [u8] {
+        self.msg
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.msg.get(self.pos..self.pos + 2).ok_or(Error)?;
+        self.pos += 2;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.msg.get(self.pos..self.pos + 4).ok_or(Error)?;
+        self.pos += 4;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn name(&mut self) -> Result<Name<'a>, Error> {
+        let name = Name {
+            msg: self.msg,
+            offset: self.pos,
+        };
+        loop {
+            let len = *self.msg.get(self.pos).ok_or(Error)? as usize;
+            match len {
+                0 => {
+                    self.pos += 1;
+                    break;
+                }
+                0xc0..=0xff => {
+                    self.pos += 2;
+                    break;
+                }
+                1..=63 => self.pos += 1 + len,
+                _ => return Err(Error),
+            }
+        }
+        if self.pos > self.msg.len() {
+            return Err(Error);
+        }
+        Ok(name)
+    }
+
+    /// Read the next question. Must be called exactly `header().questions` times before reading records.
+    pub fn question(&mut self) -> Result<Question<'a>, Error> {
+        let name = self.name()?;
+        let qtype = self.u16()?;
+        let qclass = self.u16()?;
+        Ok(Question {
+            name,
+            qtype,
+            qclass: qclass & 0x7fff,
+            unicast_response: qclass & 0x8000 != 0,
+        })
+    }
+
+    /// Read the next resource record (answer, authority or additional).
+    pub fn record(&mut self) -> Result<Record<'a>, Error> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let data = self.msg.get(self.pos..self.pos + len).ok_or(Error)?;
+        let data_offset = self.pos;
+        self.pos += len;
+        Ok(Record {
+            name,
+            rtype,
+            class: class & 0x7fff,
+            cache_flush: class & 0x8000 != 0,
+            ttl,
+            data,
+            data_offset,
+        })
+    }
+
+    /// Skip all questions.
+    pub fn skip_questions(&mut self) -> Result<(), Error> {
+        for _ in 0..self.header.questions {
+            self.question()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Response with a question for `device.local`, and a PTR answer whose name and data are
+    /// compressed.
+    const MESSAGE: &[u8] = b"\x12\x34\x84\x00\x00\x01\x00\x01\x00\x00\x00\x00\
+        \x06device\x05local\x00\x00\x01\x80\x01\
+        \xc0\x0c\x00\x0c\x80\x01\x00\x00\x00\x78\x00\x06\x03abc\xc0\x13";
+
+    fn name(msg: &[u8], offset: usize) -> Name<'_> {
+        Name { msg, offset }
+    }
+
+    #[test]
+    fn read_compressed() {
+        let mut r = Reader::new(MESSAGE).unwrap();
+        assert_eq!(r.header().id, 0x1234);
+        assert_eq!(r.header().flags, FLAG_RESPONSE | FLAG_AUTHORITATIVE);
+        assert_eq!((r.header().questions, r.header().answers), (1, 1));
+
+        let q = r.question().unwrap();
+        assert!(q.name.eq_str("device.local"));
+        assert!(q.name.eq_str("DEVICE.Local."));
+        assert!(!q.name.eq_str("device"));
+        assert!(!q.name.eq_str("device.local.lan"));
+        assert_eq!((q.qtype, q.qclass, q.unicast_response), (TYPE_A, CLASS_IN, true));
+
+        let a = r.record().unwrap();
+        assert!(a.name.eq_str("device.local"));
+        assert_eq!((a.rtype, a.class, a.ttl), (TYPE_PTR, CLASS_IN, 120));
+        let target = a.data_name(MESSAGE, 0);
+        assert!(target.eq_str("abc.local"));
+        assert!(target.eq_label_suffix("abc", "local"));
+        assert!(target.ends_with("local"));
+        assert!(!target.ends_with("device.local"));
+
+        let mut out = heapless::String::<16>::new();
+        target.write_to(&mut out).unwrap();
+        assert_eq!(out, "abc.local");
+
+        assert_eq!(r.record().err(), Some(Error));
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let msg = b"\x03abc\xc0\x00";
+        assert!(!name(msg, 0).eq_str("abc"));
+        assert_eq!(name(msg, 0).labels().filter(Result::is_err).count(), 1);
+        let mut out = heapless::String::<128>::new();
+        assert_eq!(name(msg, 0).write_to(&mut out), Err(Error));
+    }
+
+    #[test]
+    fn truncated_names() {
+        let mut labels = name(b"\x03abc", 0).labels();
+        assert_eq!(labels.next(), Some(Ok(&b"abc"[..])));
+        assert_eq!(labels.next(), Some(Err(Error)));
+        assert_eq!(labels.next(), None);
+
+        let mut labels = name(b"\x03abc\xc0", 0).labels();
+        assert_eq!(labels.next(), Some(Ok(&b"abc"[..])));
+        assert_eq!(labels.next(), Some(Err(Error)));
+
+        assert_eq!(name(b"\x03ab", 0).labels().next(), Some(Err(Error)));
+        assert!(!name(b"\x03abc", 0).eq_str("abc"));
+        // Reserved label type.
+        assert_eq!(name(b"\x40", 0).labels().next(), Some(Err(Error)));
+
+        let mut msg = [0; 14];
+        msg[12] = 0x03;
+        let mut r = Reader::new(&msg).unwrap();
+        assert!(r.question().is_err());
+    }
+
+    #[test]
+    fn write() {
+        let mut buf = [0; 128];
+        let mut w = Writer::new(&mut buf, 7, FLAG_RECURSION_DESIRED).unwrap();
+        w.question("example.com.", TYPE_AAAA, CLASS_IN).unwrap();
+        w.name_with_label("My Printer.2", "_ipp._tcp.local").unwrap();
+        w.record_data(TYPE_TXT, CLASS_IN, 60, |w| w.character_string(b"a=b"))
+            .unwrap();
+        w.answer_added();
+        let len = w.finish();
+
+        let mut r = Reader::new(&buf[..len]).unwrap();
+        assert_eq!((r.header().id, r.header().flags), (7, FLAG_RECURSION_DESIRED));
+        let q = r.question().unwrap();
+        assert!(q.name.eq_str("example.com"));
+        assert_eq!(q.qtype, TYPE_AAAA);
+        let a = r.record().unwrap();
+        assert!(a.name.eq_label_suffix("My Printer.2", "_ipp._tcp.local"));
+        assert_eq!(a.data, b"\x03a=b");
+
+        let mut buf = [0; 300];
+        let mut w = Writer::new(&mut buf, 0, 0).unwrap();
+        assert_eq!(w.name(core::str::from_utf8(&[b'a'; 64]).unwrap()), Err(Error));
+        let mut small = [0; 16];
+        let mut w = Writer::new(&mut small, 0, 0).unwrap();
+        assert_eq!(w.name("example.com"), Err(Error));
+    }
+}
@@ -0,0 +1,709 @@
+//! Packet filter.
+//!
+//! The firewall inspects the IP packets exchanged with the driver, and drops the ones it denies
+//! before they reach smoltcp or the network. This allows basic hardening, such as only accepting
+//! connections from the local network, or limiting the rate of pings, without changing the stack.
+//!
+//! [`Stack::set_firewall`](crate::Stack::set_firewall) installs a list of [`Rule`]s: the first rule
+//! matching a packet decides, and the default action applies when none does. For cases rules can't
+//! express, a [`Hook`] set with [`Stack::set_firewall_hook`](crate::Stack::set_firewall_hook) sees the
+//! packets first.
+//!
+//! Only IPv4 and IPv6 packets are filtered, on the Ethernet and IP mediums, including VLAN-tagged
+//! Ethernet frames and IPv6 packets with extension headers. Other frames, such as ARP, are always
+//! allowed. IP packets that can't be parsed are always denied. With a default action of
+//! [`Action::Deny`], ICMPv6 must be allowed for IPv6 neighbor discovery to keep working.
+//!
+//! While rules, a hook or a default action of [`Action::Deny`] are set, frames are copied to buffers
+//! of the firewall to be inspected, and only handed to smoltcp or the driver if they are allowed.
+//! The MTU is then limited to [`MAX_FRAME_LEN`], and larger received frames are dropped.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::phy::Medium;
+use smoltcp::wire::IpProtocol;
+
+use crate::{IpAddress, IpCidr};
+
+/// Maximum number of rules.
+pub const MAX_RULES: usize = 16;
+
+/// Maximum length of the frames inspected, including the link-layer header.
+pub const MAX_FRAME_LEN: usize = 1536;
+
+/// Direction of a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Received from the network.
+    Ingress,
+    /// Sent to the network.
+    Egress,
+}
+
+/// Transport protocol of a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    /// TCP.
+    Tcp,
+    /// UDP.
+    Udp,
+    /// ICMP or ICMPv6.
+    Icmp,
+    /// Any other protocol, with its IP protocol number.
+    Other(u8),
+}
+
+impl From<IpProtocol> for Protocol {
+    fn from(p: IpProtocol) -> Self {
+        match p {
+            IpProtocol::Tcp => Protocol::Tcp,
+            IpProtocol::Udp => Protocol::Udp,
+            IpProtocol::Icmp | IpProtocol::Icmpv6 => Protocol::Icmp,
+            p => Protocol::Other(p.into()),
+        }
+    }
+}
+
+/// What to do with a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    /// Let the packet through.
+    Allow,
+    /// Drop the packet.
+    Deny,
+}
+
+/// A packet, as seen by the firewall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Packet {
+    /// Direction of the packet.
+    pub direction: Direction,
+    /// Transport protocol.
+    pub protocol: Protocol,
+    /// Address of this device.
+    pub local_addr: IpAddress,
+    /// Address of the peer.
+    pub remote_addr: IpAddress,
+    /// Local port, for TCP and UDP.
+    pub local_port: Option<u16>,
+    /// Port of the peer, for TCP and UDP.
+    pub remote_port: Option<u16>,
+    /// Length of the IP packet.
+    pub len: usize,
+}
+
+/// Custom filter, called for every packet before the rules.
+///
+/// Returning `None` leaves the decision to the rules.
+pub type Hook = fn(&Packet) -> Option<Action>;
+
+/// A firewall rule.
+///
+/// A rule matches all packets, and is narrowed down with its builder methods, for example
+/// `Rule::allow().direction(Direction::Ingress).protocol(Protocol::Tcp).local_port(80)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rule {
+    action: Action,
+    direction: Option<Direction>,
+    protocol: Option<Protocol>,
+    remote: Option<IpCidr>,
+    local_ports: Option<(u16, u16)>,
+    remote_ports: Option<(u16, u16)>,
+    rate_limit: Option<(u32, Duration)>,
+}
+
+impl Rule {
+    const fn new(action: Action) -> Self {
+        Self {
+            action,
+            direction: None,
+            protocol: None,
+            remote: None,
+            local_ports: None,
+            remote_ports: None,
+            rate_limit: None,
+        }
+    }
+
+    /// A rule allowing the packets it matches.
+    pub const fn allow() -> Self {
+        Self::new(Action::Allow)
+    }
+
+    /// A rule denying the packets it matches.
+    pub const fn deny() -> Self {
+        Self::new(Action::Deny)
+    }
+
+    /// Only match packets in `direction`.
+    pub const fn direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Only match packets of `protocol`.
+    pub const fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Only match packets exchanged with peers in `cidr`.
+    pub const fn remote(mut self, cidr: IpCidr) -> Self {
+        self.remote = Some(cidr);
+        self
+    }
+
+    /// Only match TCP and UDP packets with a local port in `first..=last`.
+    pub const fn local_ports(mut self, first: u16, last: u16) -> Self {
+        self.local_ports = Some((first, last));
+        self
+    }
+
+    /// Only match TCP and UDP packets with local port `port`.
+    pub const fn local_port(self, port: u16) -> Self {
+        self.local_ports(port, port)
+    }
+
+    /// Only match TCP and UDP packets with a peer port in `first..=last`.
+    pub const fn remote_ports(mut self, first: u16, last: u16) -> Self {
+        self.remote_ports = Some((first, last));
+        self
+    }
+
+    /// Only match TCP and UDP packets with peer port `port`.
+    pub const fn remote_port(self, port: u16) -> Self {
+        self.remote_ports(port, port)
+    }
+
+    /// Let at most `packets` packets match the rule in each `period`. The packets over the limit
+    /// are denied.
+    pub const fn rate_limit(mut self, packets: u32, period: Duration) -> Self {
+        self.rate_limit = Some((packets, period));
+        self
+    }
+
+    fn matches(&self, packet: &Packet) -> bool {
+        fn check<T>(condition: Option<T>, f: impl FnOnce(T) -> bool) -> bool {
+            match condition {
+                None => true,
+                Some(c) => f(c),
+            }
+        }
+        let in_range = |port: Option<u16>| move |(first, last)| port.is_some_and(|p| first <= p && p <= last);
+
+        check(self.direction, |d| d == packet.direction)
+            && check(self.protocol, |p| p == packet.protocol)
+            && check(self.remote, |c| c.contains_addr(&packet.remote_addr))
+            && check(self.local_ports, in_range(packet.local_port))
+            && check(self.remote_ports, in_range(packet.remote_port))
+    }
+}
+
+/// Firewall counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Counters {
+    /// Received packets dropped.
+    pub ingress_dropped: u32,
+    /// Packets dropped instead of being sent.
+    pub egress_dropped: u32,
+}
+
+struct RuleState {
+    rule: Rule,
+    window_start: Instant,
+    count: u32,
+}
+
+impl RuleState {
+    fn apply(&mut self, now: Instant) -> Action {
+        let Some((packets, period)) = self.rule.rate_limit else {
+            return self.rule.action;
+        };
+        if now >= self.window_start + period {
+            self.window_start = now;
+            self.count = 0;
+        }
+        if self.count >= packets {
+            return Action::Deny;
+        }
+        self.count += 1;
+        self.rule.action
+    }
+}
+
+pub(crate) struct Firewall {
+    rules: Vec<RuleState, MAX_RULES>,
+    default: Action,
+    hook: Option<Hook>,
+    counters: Counters,
+    /// Outgoing frames are built here, to be inspected before they are sent.
+    pub buf: [u8; MAX_FRAME_LEN],
+}
+
+impl Firewall {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            default: Action::Allow,
+            hook: None,
+            counters: Counters {
+                ingress_dropped: 0,
+                egress_dropped: 0,
+            },
+            buf: [0; MAX_FRAME_LEN],
+        }
+    }
+
+    pub(crate) fn set(&mut self, rules: &[Rule], default: Action) {
+        assert!(rules.len() <= MAX_RULES, "too many firewall rules");
+        self.rules.clear();
+        for rule in rules {
+            let state = RuleState {
+                rule: *rule,
+                window_start: Instant::from_ticks(0),
+                count: 0,
+            };
+            unwrap!(self.rules.push(state).ok());
+        }
+        self.default = default;
+    }
+
+    pub(crate) fn set_hook(&mut self, hook: Option<Hook>) {
+        self.hook = hook;
+    }
+
+    pub(crate) fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Whether packets can be denied at all. When not, frames aren't copied to be inspected.
+    pub(crate) fn is_active(&self) -> bool {
+        !self.rules.is_empty() || self.hook.is_some() || self.default == Action::Deny
+    }
+
+    /// Check a received frame.
+    pub(crate) fn allows_ingress(&mut self, medium: Medium, frame: &[u8], now: Instant) -> bool {
+        let parsed = parse(medium, Direction::Ingress, frame);
+        self.allows_parsed(Direction::Ingress, parsed, now)
+    }
+
+    /// Check the frame of length `len` built in `buf`.
+    pub(crate) fn allows_egress(&mut self, medium: Medium, len: usize, now: Instant) -> bool {
+        let parsed = parse(medium, Direction::Egress, &self.buf[..len]);
+        self.allows_parsed(Direction::Egress, parsed, now)
+    }
+
+    /// Count a frame dropped because it is too large to be inspected.
+    pub(crate) fn drop_oversized(&mut self, direction: Direction) {
+        trace!("firewall: dropping oversized frame");
+        self.dropped(direction);
+    }
+
+    fn allows_parsed(&mut self, direction: Direction, parsed: Result<Option<Packet>, Malformed>, now: Instant) -> bool {
+        match parsed {
+            Ok(Some(packet)) => self.allows(&packet, now),
+            Ok(None) => true,
+            Err(Malformed) => {
+                trace!("firewall: dropping malformed packet");
+                self.dropped(direction);
+                false
+            }
+        }
+    }
+
+    fn allows(&mut self, packet: &Packet, now: Instant) -> bool {
+        let action = match self.hook.and_then(|hook| hook(packet)) {
+            Some(action) => action,
+            None => match self.rules.iter_mut().find(|r| r.rule.matches(packet)) {
+                Some(rule) => rule.apply(now),
+                None => self.default,
+            },
+        };
+        if action == Action::Allow {
+            return true;
+        }
+
+        trace!("firewall: dropping packet {:?}", packet);
+        self.dropped(packet.direction);
+        false
+    }
+
+    fn dropped(&mut self, direction: Direction) {
+        let dropped = match direction {
+            Direction::Ingress => &mut self.counters.ingress_dropped,
+            Direction::Egress => &mut self.counters.egress_dropped,
+        };
+        *dropped = dropped.wrapping_add(1);
+    }
+}
+
+/// An IP packet the firewall can't parse.
+#[derive(Debug, PartialEq, Eq)]
+struct Malformed;
+
+/// Maximum number of VLAN tags skipped, for 802.1ad (QinQ) frames.
+#[cfg(feature = "medium-ethernet")]
+const MAX_VLAN_TAGS: usize = 2;
+
+/// Maximum number of IPv6 extension headers skipped before the transport header.
+#[cfg(feature = "proto-ipv6")]
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+/// Parse the IP packet in a frame. Returns `Ok(None)` for frames that aren't filtered.
+#[allow(unused_variables)]
+fn parse(medium: Medium, direction: Direction, frame: &[u8]) -> Result<Option<Packet>, Malformed> {
+    let packet = match medium {
+        #[cfg(feature = "medium-ethernet")]
+        Medium::Ethernet => {
+            let mut offset = 12;
+            let mut tags = 0;
+            loop {
+                match frame.get(offset..offset + 2).ok_or(Malformed)? {
+                    [0x08, 0x00] | [0x86, 0xdd] => break &frame[offset + 2..],
+                    // 802.1Q and 802.1ad tags are followed by the actual EtherType.
+                    [0x81, 0x00] | [0x88, 0xa8] if tags < MAX_VLAN_TAGS => {
+                        offset += 4;
+                        tags += 1;
+                    }
+                    [0x81, 0x00] | [0x88, 0xa8] => return Err(Malformed),
+                    _ => return Ok(None),
+                }
+            }
+        }
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => frame,
+        #[allow(unreachable_patterns)]
+        _ => return Ok(None),
+    };
+
+    let (src, dst, protocol, payload): (IpAddress, IpAddress, _, &[u8]) = match packet.first().ok_or(Malformed)? >> 4 {
+        #[cfg(feature = "proto-ipv4")]
+        4 => {
+            let ip = smoltcp::wire::Ipv4Packet::new_checked(packet).map_err(|_| Malformed)?;
+            // Only the first fragment has the transport header.
+            let payload = if ip.frag_offset() == 0 { ip.payload() } else { &[] };
+            (ip.src_addr().into(), ip.dst_addr().into(), ip.next_header(), payload)
+        }
+        #[cfg(feature = "proto-ipv6")]
+        6 => {
+            let ip = smoltcp::wire::Ipv6Packet::new_checked(packet).map_err(|_| Malformed)?;
+            let (protocol, payload) = skip_extension_headers(ip.next_header(), ip.payload())?;
+            (ip.src_addr().into(), ip.dst_addr().into(), protocol, payload)
+        }
+        _ => return Err(Malformed),
+    };
+
+    let protocol = Protocol::from(protocol);
+    let (src_port, dst_port) = match (protocol, payload) {
+        (Protocol::Tcp | Protocol::Udp, [a, b, c, d, ..]) => {
+            (Some(u16::from_be_bytes([*a, *b])), Some(u16::from_be_bytes([*c, *d])))
+        }
+        _ => (None, None),
+    };
+
+    let (local_addr, remote_addr, local_port, remote_port) = match direction {
+        Direction::Ingress => (dst, src, dst_port, src_port),
+        Direction::Egress => (src, dst, src_port, dst_port),
+    };
+    Ok(Some(Packet {
+        direction,
+        protocol,
+        local_addr,
+        remote_addr,
+        local_port,
+        remote_port,
+        len: packet.len(),
+    }))
+}
+
+/// Find the transport header after the IPv6 extension headers. The payload is empty for fragments
+/// other than the first one.
+#[cfg(feature = "proto-ipv6")]
+fn skip_extension_headers(mut next_header: IpProtocol, mut payload: &[u8]) -> Result<(IpProtocol, &[u8]), Malformed> {
+    for _ in 0..MAX_EXTENSION_HEADERS {
+        let len = match (next_header, payload) {
+            (IpProtocol::HopByHop | IpProtocol::Ipv6Route | IpProtocol::Ipv6Opts, [_, len, ..]) => {
+                (*len as usize + 1) * 8
+            }
+            (IpProtocol::Ipv6Frag, [_, _, offset_hi, offset_lo, ..]) => {
+                if u16::from_be_bytes([*offset_hi, *offset_lo]) >> 3 != 0 {
+                    return Ok((IpProtocol::from(payload[0]), &[]));
+                }
+                8
+            }
+            // The authentication header's length is in 4-octet units.
+            (IpProtocol::IpSecAh, [_, len, ..]) => (*len as usize + 2) * 4,
+            (IpProtocol::HopByHop | IpProtocol::Ipv6Route | IpProtocol::Ipv6Opts | IpProtocol::Ipv6Frag, _)
+            | (IpProtocol::IpSecAh, _) => return Err(Malformed),
+            _ => return Ok((next_header, payload)),
+        };
+        if payload.len() < len {
+            return Err(Malformed);
+        }
+        next_header = IpProtocol::from(payload[0]);
+        payload = &payload[len..];
+    }
+    Err(Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[cfg(feature = "proto-ipv4")]
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddress {
+        IpAddress::v4(a, b, c, d)
+    }
+
+    /// Ethernet frame with an IPv4 UDP packet from 192.168.1.10:12345 to 192.168.1.1:53.
+    #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+    const UDP_FRAME: [u8; 42] = [
+        // Ethernet header.
+        0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00, //
+        // IPv4 header.
+        0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 192, 168, 1, 10, 192, 168, 1, 1, //
+        // UDP header.
+        0x30, 0x39, 0x00, 0x35, 0, 8, 0, 0,
+    ];
+
+    #[cfg(feature = "proto-ipv4")]
+    fn tcp_packet(direction: Direction, remote_addr: IpAddress, local_port: u16) -> Packet {
+        Packet {
+            direction,
+            protocol: Protocol::Tcp,
+            local_addr: v4(192, 168, 1, 2),
+            remote_addr,
+            local_port: Some(local_port),
+            remote_port: Some(40000),
+            len: 40,
+        }
+    }
+
+    #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+    #[test]
+    fn parse_udp() {
+        let packet = parse(Medium::Ethernet, Direction::Ingress, &UDP_FRAME)
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.protocol, Protocol::Udp);
+        assert_eq!(packet.local_addr, v4(192, 168, 1, 1));
+        assert_eq!(packet.remote_addr, v4(192, 168, 1, 10));
+        assert_eq!(packet.local_port, Some(53));
+        assert_eq!(packet.remote_port, Some(12345));
+        assert_eq!(packet.len, 28);
+
+        let packet = parse(Medium::Ethernet, Direction::Egress, &UDP_FRAME).unwrap().unwrap();
+        assert_eq!(packet.local_addr, v4(192, 168, 1, 10));
+        assert_eq!(packet.remote_addr, v4(192, 168, 1, 1));
+        assert_eq!(packet.local_port, Some(12345));
+        assert_eq!(packet.remote_port, Some(53));
+    }
+
+    #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+    #[test]
+    fn parse_unfiltered_and_fragments() {
+        // ARP.
+        let mut frame = UDP_FRAME;
+        frame[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert_eq!(parse(Medium::Ethernet, Direction::Ingress, &frame), Ok(None));
+        // Truncated IP packet.
+        assert_eq!(
+            parse(Medium::Ethernet, Direction::Ingress, &UDP_FRAME[..30]),
+            Err(Malformed)
+        );
+
+        // Later fragments have no transport header.
+        let mut frame = UDP_FRAME;
+        frame[20..22].copy_from_slice(&[0x00, 0x01]);
+        let packet = parse(Medium::Ethernet, Direction::Ingress, &frame).unwrap().unwrap();
+        assert_eq!(packet.protocol, Protocol::Udp);
+        assert_eq!((packet.local_port, packet.remote_port), (None, None));
+    }
+
+    #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+    #[test]
+    fn parse_vlan() {
+        /// Insert a VLAN tag with `tpid` before the EtherType.
+        fn tag(frame: &[u8], tpid: [u8; 2]) -> std::vec::Vec<u8> {
+            let mut tagged = frame[..12].to_vec();
+            tagged.extend_from_slice(&tpid);
+            tagged.extend_from_slice(&[0x00, 0x05]);
+            tagged.extend_from_slice(&frame[12..]);
+            tagged
+        }
+
+        let vlan = tag(&UDP_FRAME, [0x81, 0x00]);
+        let packet = parse(Medium::Ethernet, Direction::Ingress, &vlan).unwrap().unwrap();
+        assert_eq!(packet.local_port, Some(53));
+        let qinq = tag(&vlan, [0x88, 0xa8]);
+        let packet = parse(Medium::Ethernet, Direction::Ingress, &qinq).unwrap().unwrap();
+        assert_eq!(packet.local_port, Some(53));
+
+        assert_eq!(
+            parse(Medium::Ethernet, Direction::Ingress, &tag(&qinq, [0x81, 0x00])),
+            Err(Malformed)
+        );
+        assert_eq!(parse(Medium::Ethernet, Direction::Ingress, &vlan[..16]), Err(Malformed));
+    }
+
+    #[cfg(all(feature = "medium-ip", feature = "proto-ipv6"))]
+    #[test]
+    fn parse_extension_headers() {
+        /// IPv6 packet with `headers` and a TCP header from port 40000 to port 22.
+        fn packet(next_header: u8, headers: &[u8]) -> std::vec::Vec<u8> {
+            let mut packet = std::vec![0x60, 0, 0, 0, 0, 0, next_header, 64];
+            packet.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+            packet.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+            packet.extend_from_slice(headers);
+            packet.extend_from_slice(&[0x9c, 0x40, 0x00, 0x16, 0, 0, 0, 0]);
+            let payload_len = (packet.len() - 40) as u16;
+            packet[4..6].copy_from_slice(&payload_len.to_be_bytes());
+            packet
+        }
+        let parse = |packet: &[u8]| parse(Medium::Ip, Direction::Ingress, packet);
+
+        // Hop-by-hop options, then a destination options header of 16 octets.
+        let mut headers = std::vec![60, 0, 1, 4, 0, 0, 0, 0];
+        headers.extend_from_slice(&[6, 1, 1, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let p = parse(&packet(0, &headers)).unwrap().unwrap();
+        assert_eq!(p.protocol, Protocol::Tcp);
+        assert_eq!((p.local_port, p.remote_port), (Some(22), Some(40000)));
+
+        // First fragment, and a later one without the transport header.
+        let p = parse(&packet(44, &[6, 0, 0, 0x01, 0, 0, 0, 1])).unwrap().unwrap();
+        assert_eq!((p.protocol, p.local_port), (Protocol::Tcp, Some(22)));
+        let p = parse(&packet(44, &[6, 0, 0, 0x08, 0, 0, 0, 1])).unwrap().unwrap();
+        assert_eq!((p.protocol, p.local_port), (Protocol::Tcp, None));
+
+        // Truncated extension header.
+        let mut truncated = packet(0, &[6, 1, 0, 0, 0, 0, 0, 0]);
+        truncated.truncate(48);
+        truncated[4..6].copy_from_slice(&8u16.to_be_bytes());
+        assert_eq!(parse(&truncated), Err(Malformed));
+    }
+
+    #[cfg(all(feature = "medium-ip", feature = "proto-ipv6"))]
+    #[test]
+    fn parse_icmpv6() {
+        let mut packet = [0; 48];
+        packet[..8].copy_from_slice(&[0x60, 0, 0, 0, 0, 8, 58, 255]);
+        packet[8..24].copy_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet[24..40].copy_from_slice(&[0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet[40] = 134;
+        let packet = parse(Medium::Ip, Direction::Ingress, &packet).unwrap().unwrap();
+        assert_eq!(packet.protocol, Protocol::Icmp);
+        assert_eq!(packet.remote_addr, IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!((packet.local_port, packet.remote_port), (None, None));
+        assert_eq!(packet.len, 48);
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    #[test]
+    fn rule_matches() {
+        let lan = IpCidr::new(v4(192, 168, 1, 0), 24);
+        let rule = Rule::allow()
+            .direction(Direction::Ingress)
+            .protocol(Protocol::Tcp)
+            .remote(lan)
+            .local_ports(80, 81);
+
+        assert!(Rule::deny().matches(&tcp_packet(Direction::Egress, v4(10, 0, 0, 1), 1)));
+        assert!(rule.matches(&tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 80)));
+        assert!(rule.matches(&tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 81)));
+        assert!(!rule.matches(&tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 82)));
+        assert!(!rule.matches(&tcp_packet(Direction::Egress, v4(192, 168, 1, 7), 80)));
+        assert!(!rule.matches(&tcp_packet(Direction::Ingress, v4(192, 168, 2, 7), 80)));
+
+        let udp = Packet {
+            protocol: Protocol::Udp,
+            ..tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 80)
+        };
+        assert!(!rule.matches(&udp));
+
+        // Port conditions never match packets without ports.
+        let icmp = Packet {
+            protocol: Protocol::Icmp,
+            local_port: None,
+            remote_port: None,
+            ..udp
+        };
+        assert!(!Rule::allow().remote_port(40000).matches(&icmp));
+        assert!(Rule::allow().protocol(Protocol::Icmp).matches(&icmp));
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    #[test]
+    fn first_rule_decides() {
+        let now = Instant::from_ticks(0);
+        let mut firewall = Firewall::new();
+        assert!(!firewall.is_active());
+        firewall.set(
+            &[
+                Rule::allow().local_port(22).remote(IpCidr::new(v4(10, 0, 0, 0), 8)),
+                Rule::deny().local_port(22),
+                Rule::allow().direction(Direction::Ingress),
+            ],
+            Action::Deny,
+        );
+        assert!(firewall.is_active());
+
+        assert!(firewall.allows(&tcp_packet(Direction::Ingress, v4(10, 1, 2, 3), 22), now));
+        assert!(!firewall.allows(&tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 22), now));
+        assert!(firewall.allows(&tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 80), now));
+        assert!(!firewall.allows(&tcp_packet(Direction::Egress, v4(192, 168, 1, 7), 80), now));
+        assert_eq!(
+            firewall.counters(),
+            Counters {
+                ingress_dropped: 1,
+                egress_dropped: 1,
+            }
+        );
+
+        // The hook decides first.
+        firewall.set_hook(Some(|p| (p.local_port == Some(80)).then_some(Action::Deny)));
+        assert!(!firewall.allows(&tcp_packet(Direction::Ingress, v4(192, 168, 1, 7), 80), now));
+        assert!(firewall.allows(&tcp_packet(Direction::Ingress, v4(10, 1, 2, 3), 22), now));
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    #[test]
+    fn rate_limit() {
+        let mut firewall = Firewall::new();
+        firewall.set(&[Rule::allow().rate_limit(2, Duration::from_secs(1))], Action::Allow);
+        let ssh = tcp_packet(Direction::Ingress, v4(10, 1, 2, 3), 22);
+
+        let start = Instant::from_secs(10);
+        assert!(firewall.allows(&ssh, start));
+        assert!(firewall.allows(&ssh, start));
+        assert!(!firewall.allows(&ssh, start));
+        assert!(!firewall.allows(&ssh, start + Duration::from_millis(999)));
+        assert!(firewall.allows(&ssh, start + Duration::from_secs(1)));
+        assert_eq!(firewall.counters().ingress_dropped, 2);
+    }
+
+    #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+    #[test]
+    fn malformed_denied() {
+        let mut firewall = Firewall::new();
+        firewall.set(&[], Action::Allow);
+        let now = Instant::from_ticks(0);
+
+        assert!(firewall.allows_ingress(Medium::Ethernet, &UDP_FRAME, now));
+        assert!(!firewall.allows_ingress(Medium::Ethernet, &UDP_FRAME[..30], now));
+        // Not an IP version.
+        let mut frame = UDP_FRAME;
+        frame[14] = 0x15;
+        assert!(!firewall.allows_ingress(Medium::Ethernet, &frame, now));
+        assert_eq!(firewall.counters().ingress_dropped, 2);
+    }
+}
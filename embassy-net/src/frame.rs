@@ -0,0 +1,493 @@
+//! Frame sockets, for sending and receiving raw link-layer frames.
+//!
+//! A [`FrameSocket`] sees the frames exchanged with the driver: whole Ethernet frames on the Ethernet
+//! medium, or IP packets on the IP medium. They can be used for protocols running directly on top of
+//! Ethernet, such as LLDP or PTP, or to capture traffic for debugging, for example by writing it out as
+//! pcap over RTT or USB.
+//!
+//! Received frames are copied to the matching sockets, the stack still processes them as usual.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::mem;
+use core::task::{Context, Poll};
+
+use embassy_net_driver::Driver;
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::Instant;
+use smoltcp::phy::{self, Medium};
+use smoltcp::storage::PacketBuffer;
+
+use crate::{SocketStack, Stack};
+
+/// Maximum number of frame sockets open at the same time.
+pub const MAX_FRAME_SOCKETS: usize = 2;
+
+/// Metadata storage for frame socket buffers.
+pub type PacketMetadata = smoltcp::storage::PacketMetadata<FrameMetadata>;
+
+/// Selects the frames a [`FrameSocket`] receives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    /// All frames, both received and transmitted by the stack. This is meant for packet capture.
+    All,
+    /// Received Ethernet frames with the given EtherType, for example `0x88cc` for LLDP.
+    ///
+    /// Never matches on mediums other than Ethernet.
+    EtherType(u16),
+}
+
+/// Direction of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// The frame was received from the driver.
+    Rx,
+    /// The frame was handed to the driver for transmission.
+    Tx,
+}
+
+/// Metadata of a frame received by a [`FrameSocket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameMetadata {
+    /// Whether the frame was received or transmitted.
+    pub direction: Direction,
+    /// When the frame went through the driver.
+    pub timestamp: Instant,
+}
+
+/// Error returned by [`FrameSocket::recv`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecvError {
+    /// Provided buffer was smaller than the received frame.
+    Truncated,
+}
+
+/// Error returned by [`FrameSocket::send`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendError {
+    /// The frame is larger than the device's MTU, or than the socket's transmit buffer.
+    TooLarge,
+}
+
+struct Slot {
+    filter: Filter,
+    rx: PacketBuffer<'static, FrameMetadata>,
+    tx: PacketBuffer<'static, FrameMetadata>,
+    rx_waker: WakerRegistration,
+    tx_waker: WakerRegistration,
+}
+
+pub(crate) struct FrameSockets {
+    slots: [Option<Slot>; MAX_FRAME_SOCKETS],
+}
+
+impl FrameSockets {
+    pub(crate) const fn new() -> Self {
+        const NONE: Option<Slot> = None;
+        Self {
+            slots: [NONE; MAX_FRAME_SOCKETS],
+        }
+    }
+
+    pub(crate) fn received(&mut self, medium: Medium, frame: &[u8]) {
+        let ethertype = match medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet if frame.len() >= 14 => Some(u16::from_be_bytes([frame[12], frame[13]])),
+            _ => None,
+        };
+        for slot in self.slots.iter_mut().flatten() {
+            let matches = match slot.filter {
+                Filter::All => true,
+                Filter::EtherType(t) => ethertype == Some(t),
+            };
+            if matches {
+                slot.push(frame, Direction::Rx);
+            }
+        }
+    }
+
+    pub(crate) fn transmitted(&mut self, frame: &[u8]) {
+        for slot in self.slots.iter_mut().flatten() {
+            if slot.filter == Filter::All {
+                slot.push(frame, Direction::Tx);
+            }
+        }
+    }
+
+    /// Length of the next frame queued for transmission by a socket.
+    fn next_tx_len(&mut self) -> Option<usize> {
+        self.slots
+            .iter_mut()
+            .flatten()
+            .find_map(|slot| slot.tx.peek().ok().map(|(_, frame)| frame.len()))
+    }
+
+    fn pop_tx(&mut self, buf: &mut [u8]) {
+        if let Some(slot) = self.slots.iter_mut().flatten().find(|slot| !slot.tx.is_empty()) {
+            if let Ok((_, frame)) = slot.tx.dequeue() {
+                buf.copy_from_slice(frame);
+            }
+            slot.tx_waker.wake();
+        }
+    }
+}
+
+impl Slot {
+    fn push(&mut self, frame: &[u8], direction: Direction) {
+        let meta = FrameMetadata {
+            direction,
+            timestamp: Instant::now(),
+        };
+        match self.rx.enqueue(frame.len(), meta) {
+            Ok(buf) => {
+                buf.copy_from_slice(frame);
+                self.rx_waker.wake();
+            }
+            Err(_) => trace!("frame socket rx buffer full, dropping frame"),
+        }
+    }
+}
+
+/// Transmit the frames queued by frame sockets.
+pub(crate) fn transmit<D: phy::Device>(
+    device: &mut D,
+    sockets: &RefCell<FrameSockets>,
+    timestamp: smoltcp::time::Instant,
+) {
+    use smoltcp::phy::TxToken;
+
+    loop {
+        let Some(len) = sockets.borrow_mut().next_tx_len() else {
+            break;
+        };
+        let Some(token) = device.transmit(timestamp) else {
+            break;
+        };
+        token.consume(len, |buf| sockets.borrow_mut().pop_tx(buf));
+    }
+}
+
+/// A frame socket.
+pub struct FrameSocket<'a> {
+    stack: &'a RefCell<SocketStack>,
+    index: usize,
+    /// Maximum frame size accepted by the device.
+    mtu: usize,
+}
+
+impl<'a> FrameSocket<'a> {
+    /// Create a new frame socket using the provided stack and buffers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`MAX_FRAME_SOCKETS`] frame sockets are already open.
+    pub fn new<D: Driver>(
+        stack: &'a Stack<D>,
+        filter: Filter,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        let s = &mut *stack.socket.borrow_mut();
+        let sockets = &mut *s.taps.frames.borrow_mut();
+
+        let rx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(rx_meta) };
+        let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+        let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
+        let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+
+        let index = unwrap!(sockets.slots.iter().position(|slot| slot.is_none()));
+        sockets.slots[index] = Some(Slot {
+            filter,
+            rx: PacketBuffer::new(rx_meta, rx_buffer),
+            tx: PacketBuffer::new(tx_meta, tx_buffer),
+            rx_waker: WakerRegistration::new(),
+            tx_waker: WakerRegistration::new(),
+        });
+
+        Self {
+            stack: &stack.socket,
+            index,
+            mtu: stack.inner.borrow().device.capabilities().max_transmission_unit,
+        }
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(&mut Slot) -> R) -> R {
+        let s = &mut *self.stack.borrow_mut();
+        let res = f(unwrap!(s.taps.frames.borrow_mut().slots[self.index].as_mut()));
+        s.waker.wake();
+        res
+    }
+
+    /// Receive a frame.
+    ///
+    /// This method will wait until a frame is received.
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<(usize, FrameMetadata), RecvError> {
+        poll_fn(move |cx| self.poll_recv(buf, cx)).await
+    }
+
+    /// Receive a frame.
+    ///
+    /// When no frame is available, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a frame is received.
+    pub fn poll_recv(&self, buf: &mut [u8], cx: &mut Context<'_>) -> Poll<Result<(usize, FrameMetadata), RecvError>> {
+        self.with_mut(|slot| match slot.rx.dequeue() {
+            Ok((meta, frame)) if frame.len() <= buf.len() => {
+                buf[..frame.len()].copy_from_slice(frame);
+                Poll::Ready(Ok((frame.len(), meta)))
+            }
+            Ok(_) => Poll::Ready(Err(RecvError::Truncated)),
+            Err(_) => {
+                slot.rx_waker.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Send a frame.
+    ///
+    /// The frame must be complete, including the link-layer header, and no larger than the device's
+    /// MTU. This method will wait until the frame has been queued for transmission.
+    pub async fn send(&self, frame: &[u8]) -> Result<(), SendError> {
+        poll_fn(move |cx| self.poll_send(frame, cx)).await
+    }
+
+    /// Send a frame.
+    ///
+    /// When the frame has been queued for transmission, this method will return `Poll::Ready(Ok(()))`.
+    ///
+    /// When the socket's send buffer is full, this method will return `Poll::Pending`
+    /// and register the current task to be notified when the buffer has space available.
+    pub fn poll_send(&self, frame: &[u8], cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        if frame.len() > self.mtu {
+            return Poll::Ready(Err(SendError::TooLarge));
+        }
+        self.with_mut(|slot| {
+            if frame.len() > slot.tx.payload_capacity() {
+                return Poll::Ready(Err(SendError::TooLarge));
+            }
+            let meta = FrameMetadata {
+                direction: Direction::Tx,
+                timestamp: Instant::now(),
+            };
+            match slot.tx.enqueue(frame.len(), meta) {
+                Ok(buf) => {
+                    buf.copy_from_slice(frame);
+                    Poll::Ready(Ok(()))
+                }
+                Err(_) => {
+                    slot.tx_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+impl Drop for FrameSocket<'_> {
+    fn drop(&mut self) {
+        self.stack.borrow_mut().taps.frames.borrow_mut().slots[self.index] = None;
+    }
+}
+
+#[cfg(all(test, feature = "medium-ethernet", feature = "medium-ip"))]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use futures::executor::block_on;
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+    use crate::testing::{stack, NoDevice, NoToken};
+    use crate::Config;
+
+    const MTU: usize = 100;
+
+    /// Buffers for a socket, with room for `frames` frames in each direction.
+    struct Buffers {
+        rx_meta: Vec<PacketMetadata>,
+        rx_buffer: Vec<u8>,
+        tx_meta: Vec<PacketMetadata>,
+        tx_buffer: Vec<u8>,
+    }
+
+    impl Buffers {
+        fn new(frames: usize, len: usize) -> Self {
+            Self {
+                rx_meta: vec![PacketMetadata::EMPTY; frames],
+                rx_buffer: vec![0; len],
+                tx_meta: vec![PacketMetadata::EMPTY; frames],
+                tx_buffer: vec![0; len],
+            }
+        }
+
+        fn socket<'a>(&'a mut self, stack: &'a Stack<NoDevice>, filter: Filter) -> FrameSocket<'a> {
+            FrameSocket::new(
+                stack,
+                filter,
+                &mut self.rx_meta,
+                &mut self.rx_buffer,
+                &mut self.tx_meta,
+                &mut self.tx_buffer,
+            )
+        }
+    }
+
+    /// smoltcp device recording the transmitted frames.
+    struct Capture {
+        frames: Vec<Vec<u8>>,
+        room: usize,
+    }
+
+    struct CaptureToken<'a>(&'a mut Capture);
+
+    impl phy::TxToken for CaptureToken<'_> {
+        fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+            let mut frame = vec![0; len];
+            let res = f(&mut frame);
+            self.0.frames.push(frame);
+            res
+        }
+    }
+
+    impl phy::RxToken for NoToken {
+        fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, _f: F) -> R {
+            match self {}
+        }
+    }
+
+    impl phy::Device for Capture {
+        type RxToken<'a> = NoToken;
+        type TxToken<'a> = CaptureToken<'a>;
+
+        fn receive(&mut self, _timestamp: smoltcp::time::Instant) -> Option<(NoToken, CaptureToken<'_>)> {
+            None
+        }
+
+        fn transmit(&mut self, _timestamp: smoltcp::time::Instant) -> Option<CaptureToken<'_>> {
+            self.room = self.room.checked_sub(1)?;
+            Some(CaptureToken(self))
+        }
+
+        fn capabilities(&self) -> phy::DeviceCapabilities {
+            phy::DeviceCapabilities::default()
+        }
+    }
+
+    fn ethernet_frame(ethertype: u16) -> [u8; 20] {
+        let mut frame = [0; 20];
+        frame[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        frame[19] = ethertype as u8;
+        frame
+    }
+
+    fn recv(socket: &FrameSocket<'_>) -> Option<(Vec<u8>, Direction)> {
+        let mut buf = [0; MTU];
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match socket.poll_recv(&mut buf, &mut cx) {
+            Poll::Ready(Ok((n, meta))) => Some((buf[..n].to_vec(), meta.direction)),
+            Poll::Ready(Err(e)) => panic!("{:?}", e),
+            Poll::Pending => None,
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let stack = stack(MTU, Config::default());
+        let (mut all, mut lldp) = (Buffers::new(4, 256), Buffers::new(4, 256));
+        let all = all.socket(stack, Filter::All);
+        let lldp = lldp.socket(stack, Filter::EtherType(0x88cc));
+
+        {
+            let s = stack.socket.borrow();
+            let mut frames = s.taps.frames.borrow_mut();
+            frames.received(Medium::Ethernet, &ethernet_frame(0x88cc));
+            frames.received(Medium::Ethernet, &ethernet_frame(0x0800));
+            // No EtherType on the IP medium.
+            frames.received(Medium::Ip, &ethernet_frame(0x88cc));
+            frames.transmitted(&ethernet_frame(0x88cc));
+        }
+
+        assert_eq!(recv(&all), Some((ethernet_frame(0x88cc).to_vec(), Direction::Rx)));
+        assert_eq!(recv(&all), Some((ethernet_frame(0x0800).to_vec(), Direction::Rx)));
+        assert_eq!(recv(&all), Some((ethernet_frame(0x88cc).to_vec(), Direction::Rx)));
+        assert_eq!(recv(&all), Some((ethernet_frame(0x88cc).to_vec(), Direction::Tx)));
+        assert_eq!(recv(&all), None);
+
+        assert_eq!(recv(&lldp), Some((ethernet_frame(0x88cc).to_vec(), Direction::Rx)));
+        assert_eq!(recv(&lldp), None);
+    }
+
+    #[test]
+    fn receive_overflow() {
+        let stack = stack(MTU, Config::default());
+        let mut buffers = Buffers::new(2, 64);
+        let socket = buffers.socket(stack, Filter::All);
+
+        for ethertype in 1..=3 {
+            let s = stack.socket.borrow();
+            s.taps
+                .frames
+                .borrow_mut()
+                .received(Medium::Ethernet, &ethernet_frame(ethertype));
+        }
+        // The third frame didn't fit.
+        assert_eq!(recv(&socket), Some((ethernet_frame(1).to_vec(), Direction::Rx)));
+
+        // A frame larger than the buffer is consumed.
+        let mut buf = [0; 10];
+        assert_eq!(block_on(socket.recv(&mut buf)), Err(RecvError::Truncated));
+        assert_eq!(recv(&socket), None);
+    }
+
+    #[test]
+    fn send() {
+        let stack = stack(MTU, Config::default());
+        let mut buffers = Buffers::new(1, 64);
+        let socket = buffers.socket(stack, Filter::EtherType(0x88cc));
+
+        // Larger than the MTU, or than the buffer.
+        assert_eq!(block_on(socket.send(&[0; MTU + 1])), Err(SendError::TooLarge));
+        assert_eq!(block_on(socket.send(&[0; 65])), Err(SendError::TooLarge));
+
+        assert_eq!(block_on(socket.send(&ethernet_frame(1))), Ok(()));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert_eq!(socket.poll_send(&ethernet_frame(2), &mut cx), Poll::Pending);
+
+        // The frame is sent when the device has room for it.
+        let mut device = Capture {
+            frames: Vec::new(),
+            room: 0,
+        };
+        let timestamp = smoltcp::time::Instant::from_millis(0);
+        transmit(&mut device, &stack.socket.borrow().taps.frames, timestamp);
+        assert!(device.frames.is_empty());
+        device.room = 2;
+        transmit(&mut device, &stack.socket.borrow().taps.frames, timestamp);
+        assert_eq!(device.frames, [ethernet_frame(1)]);
+        assert_eq!(device.room, 1);
+
+        assert_eq!(socket.poll_send(&ethernet_frame(2), &mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    fn slots_reused() {
+        let stack = stack(MTU, Config::default());
+        let mut buffers: Vec<_> = (0..MAX_FRAME_SOCKETS + 1).map(|_| Buffers::new(1, 64)).collect();
+        let mut buffers = buffers.iter_mut();
+        let sockets: Vec<_> = (0..MAX_FRAME_SOCKETS)
+            .map(|_| buffers.next().unwrap().socket(stack, Filter::All))
+            .collect();
+        drop(sockets);
+        let _socket = buffers.next().unwrap().socket(stack, Filter::All);
+    }
+}
@@ -0,0 +1,1290 @@
+//! HTTP/1.1 client and server.
+//!
+//! The client side has two levels:
+//! - [`Connection`] sends requests and receives responses over any `embedded-io-async` stream,
+//!   e.g. a [`TcpSocket`] or a [`TlsSocket`](crate::tls::TlsSocket), with support for keep-alive
+//!   and chunked request bodies.
+//! - [`HttpClient`] takes an `http://` URL, resolves the host, connects a [`TcpSocket`] and follows
+//!   redirects.
+//!
+//! The server side is meant for device configuration pages and REST endpoints: a [`Router`] maps
+//! requests to routes, and a [`Handler`] answers them. Each connection serves a single request.
+//!
+//! Nothing is allocated, headers are parsed in place in a user-provided buffer, and bodies are
+//! streamed through [`BodyReader`] and [`ChunkedWriter`].
+
+use core::fmt::Write as _;
+
+use embassy_net_driver::Driver;
+use embassy_time::Duration;
+use embedded_io_async::{ErrorKind, Read, Write};
+use heapless::{String, Vec};
+
+use crate::tcp::{self, TcpSocket};
+use crate::{IpAddress, Stack};
+
+/// Maximum number of headers in a request or response.
+pub const MAX_HEADERS: usize = 16;
+
+/// Maximum number of parameters captured by a route.
+pub const MAX_PARAMS: usize = 4;
+
+/// Maximum length of a URL followed by [`HttpClient`] after a redirect.
+pub const MAX_URL_LEN: usize = 256;
+
+/// Errors returned by the HTTP client and server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The transport returned an error.
+    Io(ErrorKind),
+    /// The connection was closed before the end of the message.
+    ConnectionClosed,
+    /// The TCP connection could not be established.
+    Connect(tcp::ConnectError),
+    /// The host name could not be resolved.
+    Dns,
+    /// The URL is invalid.
+    InvalidUrl,
+    /// The URL scheme is not `http`.
+    UnsupportedScheme,
+    /// Too many redirects were followed.
+    TooManyRedirects,
+    /// The message is malformed.
+    Malformed,
+    /// The message has more than [`MAX_HEADERS`] headers.
+    TooManyHeaders,
+    /// The message doesn't fit in the buffer.
+    BufferTooSmall,
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(kind) => *kind,
+            Error::ConnectionClosed => ErrorKind::ConnectionReset,
+            Error::Connect(_) => ErrorKind::ConnectionRefused,
+            Error::BufferTooSmall => ErrorKind::OutOfMemory,
+            Error::Malformed | Error::TooManyHeaders => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+pub(crate) fn io<E: embedded_io_async::Error>(e: E) -> Error {
+    Error::Io(e.kind())
+}
+
+/// Request method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    /// GET
+    Get,
+    /// HEAD
+    Head,
+    /// POST
+    Post,
+    /// PUT
+    Put,
+    /// DELETE
+    Delete,
+    /// PATCH
+    Patch,
+    /// OPTIONS
+    Options,
+}
+
+impl Method {
+    /// Get the method name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            _ => return None,
+        })
+    }
+}
+
+/// A header field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header<'a> {
+    /// Name of the header.
+    pub name: &'a str,
+    /// Value of the header.
+    pub value: &'a str,
+}
+
+impl<'a> Header<'a> {
+    /// Create a header.
+    pub const fn new(name: &'a str, value: &'a str) -> Self {
+        Self { name, value }
+    }
+}
+
+pub(crate) fn find_header<'a>(headers: &[Header<'a>], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value)
+}
+
+/// Get the reason phrase of a status code.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Read a message head. Returns the length of the head, including the final empty line, and the
+/// number of bytes read.
+pub(crate) async fn read_head<T: Read>(transport: &mut T, buffer: &mut [u8]) -> Result<(usize, usize), Error> {
+    let mut len: usize = 0;
+    loop {
+        // Only search the new bytes, plus 3 in case the end was split.
+        let from = len.saturating_sub(3);
+        let n = transport.read(&mut buffer[len..]).await.map_err(io)?;
+        if n == 0 {
+            return Err(Error::ConnectionClosed);
+        }
+        len += n;
+
+        if let Some(pos) = buffer[from..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok((from + pos + 4, len));
+        }
+        if len == buffer.len() {
+            return Err(Error::BufferTooSmall);
+        }
+    }
+}
+
+/// Split a message head into its start line and headers.
+pub(crate) fn parse_head(head: &[u8]) -> Result<(&str, Vec<Header<'_>, MAX_HEADERS>), Error> {
+    let head = core::str::from_utf8(head).map_err(|_| Error::Malformed)?;
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next().ok_or(Error::Malformed)?;
+
+    let mut headers = Vec::new();
+    for line in lines.filter(|l| !l.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(Error::Malformed)?;
+        headers
+            .push(Header::new(name.trim(), value.trim()))
+            .map_err(|_| Error::TooManyHeaders)?;
+    }
+    Ok((start_line, headers))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    /// Expecting a chunk size line.
+    Size,
+    /// In the middle of a chunk, with the given number of bytes left.
+    Data(usize),
+    /// Expecting the line break after a chunk.
+    End,
+    /// The last chunk was read.
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// Body with the given number of bytes left.
+    Length(usize),
+    /// Chunked transfer encoding.
+    Chunked(Chunk),
+    /// Body delimited by the end of the connection.
+    UntilClose,
+}
+
+/// Get the framing of a message body from its headers, if any.
+fn body_framing(headers: &[Header<'_>]) -> Result<Option<Framing>, Error> {
+    if let Some(encoding) = find_header(headers, "transfer-encoding") {
+        let last = encoding.rsplit(',').next().unwrap_or("").trim();
+        if last.eq_ignore_ascii_case("chunked") {
+            return Ok(Some(Framing::Chunked(Chunk::Size)));
+        }
+        return Ok(Some(Framing::UntilClose));
+    }
+    match find_header(headers, "content-length") {
+        Some(len) => Ok(Some(Framing::Length(len.parse().map_err(|_| Error::Malformed)?))),
+        None => Ok(None),
+    }
+}
+
+/// Reader for a message body.
+///
+/// Bytes received along with the message head are returned first, then the body is read from the
+/// transport. Chunked transfer encoding is decoded transparently.
+pub struct BodyReader<'b, T: Read> {
+    transport: T,
+    buffer: &'b mut [u8],
+    pos: usize,
+    len: usize,
+    framing: Framing,
+}
+
+impl<'b, T: Read> BodyReader<'b, T> {
+    fn new(transport: T, buffer: &'b mut [u8], len: usize, framing: Framing) -> Self {
+        Self {
+            transport,
+            buffer,
+            pos: 0,
+            len,
+            framing,
+        }
+    }
+
+    /// Read data from the buffer if there is any, or from the transport.
+    async fn read_raw(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.pos < self.len {
+            let n = buf.len().min(self.len - self.pos);
+            buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        } else {
+            self.transport.read(buf).await.map_err(io)
+        }
+    }
+
+    async fn read_byte(&mut self) -> Result<u8, Error> {
+        if self.pos == self.len && !self.buffer.is_empty() {
+            self.pos = 0;
+            self.len = self.transport.read(self.buffer).await.map_err(io)?;
+        }
+        let mut byte = [0];
+        match self.read_raw(&mut byte).await? {
+            0 => Err(Error::ConnectionClosed),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    /// Read a line, discarding its contents except for the leading hex digits.
+    async fn read_hex_line(&mut self) -> Result<Option<usize>, Error> {
+        let mut value: Option<usize> = None;
+        let mut digits = true;
+        loop {
+            match self.read_byte().await? {
+                b'\n' => return Ok(value),
+                b'\r' => digits = false,
+                c if digits && c.is_ascii_hexdigit() => {
+                    let digit = (c as char).to_digit(16).unwrap_or(0) as usize;
+                    let v = value.unwrap_or(0).checked_mul(16).ok_or(Error::Malformed)?;
+                    value = Some(v + digit);
+                }
+                _ => digits = false,
+            }
+        }
+    }
+
+    /// Read a trailer line. Returns `false` once the empty line ending the trailers is read.
+    async fn read_trailer_line(&mut self) -> Result<bool, Error> {
+        let mut empty = true;
+        loop {
+            match self.read_byte().await? {
+                b'\n' => return Ok(!empty),
+                b'\r' => {}
+                _ => empty = false,
+            }
+        }
+    }
+
+    /// Read the whole body into `buf`. Returns the length of the body.
+    pub async fn read_to_end(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        loop {
+            if len == buf.len() {
+                // Check whether there's anything left.
+                return match self.read(&mut [0]).await? {
+                    0 => Ok(len),
+                    _ => Err(Error::BufferTooSmall),
+                };
+            }
+            match self.read(&mut buf[len..]).await? {
+                0 => return Ok(len),
+                n => len += n,
+            }
+        }
+    }
+
+    /// Read and discard the rest of the body.
+    pub async fn discard(&mut self) -> Result<(), Error> {
+        let mut buf = [0; 64];
+        while self.read(&mut buf).await? != 0 {}
+        Ok(())
+    }
+
+    /// Get whether the whole body was read.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.framing, Framing::Length(0) | Framing::Chunked(Chunk::Done))
+    }
+
+    fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<'b, T: Read> embedded_io_async::ErrorType for BodyReader<'b, T> {
+    type Error = Error;
+}
+
+impl<'b, T: Read> Read for BodyReader<'b, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.framing {
+                Framing::Length(0) | Framing::Chunked(Chunk::Done) => return Ok(0),
+                Framing::Length(left) => {
+                    let n = buf.len().min(left);
+                    let n = self.read_raw(&mut buf[..n]).await?;
+                    if n == 0 {
+                        return Err(Error::ConnectionClosed);
+                    }
+                    self.framing = Framing::Length(left - n);
+                    return Ok(n);
+                }
+                Framing::UntilClose => return self.read_raw(buf).await,
+                Framing::Chunked(Chunk::Size) => {
+                    let size = self.read_hex_line().await?.ok_or(Error::Malformed)?;
+                    if size == 0 {
+                        // Skip the trailer fields.
+                        while self.read_trailer_line().await? {}
+                        self.framing = Framing::Chunked(Chunk::Done);
+                    } else {
+                        self.framing = Framing::Chunked(Chunk::Data(size));
+                    }
+                }
+                Framing::Chunked(Chunk::Data(left)) => {
+                    let n = buf.len().min(left);
+                    let n = self.read_raw(&mut buf[..n]).await?;
+                    if n == 0 {
+                        return Err(Error::ConnectionClosed);
+                    }
+                    self.framing = match left - n {
+                        0 => Framing::Chunked(Chunk::End),
+                        left => Framing::Chunked(Chunk::Data(left)),
+                    };
+                    return Ok(n);
+                }
+                Framing::Chunked(Chunk::End) => {
+                    self.read_hex_line().await?;
+                    self.framing = Framing::Chunked(Chunk::Size);
+                }
+            }
+        }
+    }
+}
+
+/// Writer for a body with chunked transfer encoding.
+///
+/// Each write is sent as one chunk. [`ChunkedWriter::finish`] must be called at the end of the body.
+pub struct ChunkedWriter<'w, T: Write> {
+    transport: &'w mut T,
+}
+
+impl<'w, T: Write> ChunkedWriter<'w, T> {
+    fn new(transport: &'w mut T) -> Self {
+        Self { transport }
+    }
+
+    /// Send the last chunk, ending the body.
+    pub async fn finish(self) -> Result<(), Error> {
+        self.transport.write_all(b"0\r\n\r\n").await.map_err(io)?;
+        self.transport.flush().await.map_err(io)
+    }
+}
+
+impl<'w, T: Write> embedded_io_async::ErrorType for ChunkedWriter<'w, T> {
+    type Error = Error;
+}
+
+impl<'w, T: Write> Write for ChunkedWriter<'w, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut size: String<20> = String::new();
+        let _ = write!(size, "{:x}\r\n", buf.len());
+        self.transport.write_all(size.as_bytes()).await.map_err(io)?;
+        self.transport.write_all(buf).await.map_err(io)?;
+        self.transport.write_all(b"\r\n").await.map_err(io)?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.transport.flush().await.map_err(io)
+    }
+}
+
+/// Write the head of a message, ending with the framing headers for `body`.
+///
+/// A `body` of `None` sends no framing headers, for requests without content.
+pub(crate) async fn write_head<T: Write>(
+    transport: &mut T,
+    start_line: &[&str],
+    headers: &[Header<'_>],
+    body: Option<&Body<'_>>,
+    close: bool,
+) -> Result<(), Error> {
+    for part in start_line {
+        transport.write_all(part.as_bytes()).await.map_err(io)?;
+    }
+    transport.write_all(b"\r\n").await.map_err(io)?;
+
+    for header in headers {
+        for part in [header.name, ": ", header.value, "\r\n"] {
+            transport.write_all(part.as_bytes()).await.map_err(io)?;
+        }
+    }
+
+    let mut framing: String<40> = String::new();
+    let _ = match body {
+        None => Ok(()),
+        Some(Body::Empty) => write!(framing, "Content-Length: 0\r\n"),
+        Some(Body::Bytes(data)) => write!(framing, "Content-Length: {}\r\n", data.len()),
+        Some(Body::Chunked) => write!(framing, "Transfer-Encoding: chunked\r\n"),
+    };
+    transport.write_all(framing.as_bytes()).await.map_err(io)?;
+    if close {
+        transport.write_all(b"Connection: close\r\n").await.map_err(io)?;
+    }
+    transport.write_all(b"\r\n").await.map_err(io)?;
+
+    if let Some(Body::Bytes(data)) = body {
+        transport.write_all(data).await.map_err(io)?;
+    }
+    Ok(())
+}
+
+/// Get the body framing to send with a request.
+fn request_body<'a>(method: Method, body: &'a Body<'a>) -> Option<&'a Body<'a>> {
+    match (method, body) {
+        (Method::Post | Method::Put | Method::Patch, _) | (_, Body::Bytes(_) | Body::Chunked) => Some(body),
+        _ => None,
+    }
+}
+
+/// Body of a request sent by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Body<'a> {
+    /// No body.
+    Empty,
+    /// Body sent in one piece.
+    Bytes(&'a [u8]),
+    /// Body streamed with chunked transfer encoding, using [`Connection::body_writer`].
+    Chunked,
+}
+
+/// HTTP request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Request<'a> {
+    /// Request method.
+    pub method: Method,
+    /// Value of the `Host` header.
+    pub host: &'a str,
+    /// Request target, i.e. the path and query.
+    pub path: &'a str,
+    /// Additional headers.
+    pub headers: &'a [Header<'a>],
+    /// Request body.
+    pub body: Body<'a>,
+}
+
+impl<'a> Request<'a> {
+    /// Create a request without additional headers or body.
+    pub fn new(method: Method, host: &'a str, path: &'a str) -> Self {
+        Self {
+            method,
+            host,
+            path,
+            headers: &[],
+            body: Body::Empty,
+        }
+    }
+}
+
+/// HTTP response received by the client.
+pub struct Response<'b, T: Read> {
+    /// Status code.
+    pub status: u16,
+    /// Reason phrase.
+    pub reason: &'b str,
+    headers: Vec<Header<'b>, MAX_HEADERS>,
+    body: BodyReader<'b, T>,
+}
+
+impl<'b, T: Read> Response<'b, T> {
+    fn parse(
+        transport: T,
+        buffer: &'b mut [u8],
+        head_len: usize,
+        len: usize,
+        head_request: bool,
+    ) -> Result<Self, Error> {
+        let (head, rest) = buffer.split_at_mut(head_len);
+        let (status_line, headers) = parse_head(head)?;
+
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+            return Err(Error::Malformed);
+        }
+        let status: u16 = parts.next().and_then(|s| s.parse().ok()).ok_or(Error::Malformed)?;
+        let reason = parts.next().unwrap_or("");
+
+        let framing = if head_request || status < 200 || status == 204 || status == 304 {
+            Framing::Length(0)
+        } else {
+            body_framing(&headers)?.unwrap_or(Framing::UntilClose)
+        };
+
+        Ok(Self {
+            status,
+            reason,
+            headers,
+            body: BodyReader::new(transport, rest, len - head_len, framing),
+        })
+    }
+
+    /// Get the headers.
+    pub fn headers(&self) -> &[Header<'b>] {
+        &self.headers
+    }
+
+    /// Get the value of a header, with a case-insensitive name.
+    pub fn header(&self, name: &str) -> Option<&'b str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Get the body reader.
+    pub fn body(&mut self) -> &mut BodyReader<'b, T> {
+        &mut self.body
+    }
+}
+
+/// HTTP client connection over an established stream.
+///
+/// The connection is kept alive between requests, so the body of a response must be read
+/// completely before sending the next request.
+pub struct Connection<'b, T: Read + Write> {
+    transport: T,
+    buffer: &'b mut [u8],
+    method: Method,
+}
+
+impl<'b, T: Read + Write> Connection<'b, T> {
+    /// Create a connection. `buffer` must be able to hold the head of the responses.
+    pub fn new(transport: T, buffer: &'b mut [u8]) -> Self {
+        Self {
+            transport,
+            buffer,
+            method: Method::Get,
+        }
+    }
+
+    /// Send a request.
+    ///
+    /// If the body is [`Body::Chunked`], it must then be written with [`Connection::body_writer`].
+    pub async fn send(&mut self, request: &Request<'_>) -> Result<(), Error> {
+        let start_line = [
+            request.method.as_str(),
+            " ",
+            request.path,
+            " HTTP/1.1\r\nHost: ",
+            request.host,
+        ];
+        let body = request_body(request.method, &request.body);
+        write_head(&mut self.transport, &start_line, request.headers, body, false).await?;
+        self.transport.flush().await.map_err(io)?;
+        self.method = request.method;
+        Ok(())
+    }
+
+    /// Get a writer for a chunked request body.
+    pub fn body_writer(&mut self) -> ChunkedWriter<'_, T> {
+        ChunkedWriter::new(&mut self.transport)
+    }
+
+    /// Receive the response to the last request.
+    pub async fn receive(&mut self) -> Result<Response<'_, &mut T>, Error> {
+        let (head_len, len) = read_head(&mut self.transport, self.buffer).await?;
+        Response::parse(
+            &mut self.transport,
+            self.buffer,
+            head_len,
+            len,
+            self.method == Method::Head,
+        )
+    }
+
+    /// Send a request and receive the response.
+    pub async fn request(&mut self, request: &Request<'_>) -> Result<Response<'_, &mut T>, Error> {
+        self.send(request).await?;
+        self.receive().await
+    }
+
+    /// Get the underlying transport back.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+struct Url<'a> {
+    authority: &'a str,
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+fn parse_url(url: &str) -> Result<Url<'_>, Error> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Err(match url.contains("://") {
+            true => Error::UnsupportedScheme,
+            false => Error::InvalidUrl,
+        });
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.strip_prefix('[') {
+        // IPv6 literal.
+        Some(v6) => {
+            let (host, port) = v6.split_once(']').ok_or(Error::InvalidUrl)?;
+            (host, port.strip_prefix(':'))
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| Error::InvalidUrl)?,
+        None => 80,
+    };
+    if host.is_empty() {
+        return Err(Error::InvalidUrl);
+    }
+
+    Ok(Url {
+        authority,
+        host,
+        port,
+        path,
+    })
+}
+
+/// Resolve the target of a redirect relative to the current URL.
+fn resolve_location(url: &Url<'_>, location: &str) -> Result<String<MAX_URL_LEN>, Error> {
+    let mut next = String::new();
+    let res = if location.contains("://") {
+        next.push_str(location).map_err(|_| ())
+    } else if location.starts_with('/') {
+        write!(next, "http://{}{}", url.authority, location).map_err(|_| ())
+    } else {
+        let path = url.path.split('?').next().unwrap_or("/");
+        let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        write!(next, "http://{}{}{}", url.authority, dir, location).map_err(|_| ())
+    };
+    res.map_err(|_| Error::BufferTooSmall)?;
+    Ok(next)
+}
+
+/// HTTP client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct ClientConfig {
+    /// Maximum number of redirects followed. Zero returns redirect responses as is.
+    pub max_redirects: u8,
+    /// Inactivity timeout of the connection.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            max_redirects: 5,
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Buffers for a request made with [`HttpClient`].
+///
+/// `RX` and `TX` are the TCP socket buffer sizes, `HEAD` must be able to hold the head of the
+/// response.
+pub struct ClientBuffers<const RX: usize = 1024, const TX: usize = 1024, const HEAD: usize = 1024> {
+    rx: [u8; RX],
+    tx: [u8; TX],
+    head: [u8; HEAD],
+}
+
+impl<const RX: usize, const TX: usize, const HEAD: usize> ClientBuffers<RX, TX, HEAD> {
+    /// Create new buffers.
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; RX],
+            tx: [0; TX],
+            head: [0; HEAD],
+        }
+    }
+}
+
+impl<const RX: usize, const TX: usize, const HEAD: usize> Default for ClientBuffers<RX, TX, HEAD> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// HTTP client making requests to `http://` URLs.
+///
+/// Each request uses a new connection, closed once the response is dropped.
+pub struct HttpClient<'d, D: Driver> {
+    stack: &'d Stack<D>,
+    config: ClientConfig,
+}
+
+impl<'d, D: Driver> HttpClient<'d, D> {
+    /// Create a new client.
+    pub fn new(stack: &'d Stack<D>, config: ClientConfig) -> Self {
+        Self { stack, config }
+    }
+
+    /// Make a request.
+    ///
+    /// Redirects are followed according to [`ClientConfig::max_redirects`]. After a `303 See
+    /// Other`, or a `301`/`302` answering a `POST`, the request is changed to a `GET` without body.
+    pub async fn request<'b, const RX: usize, const TX: usize, const HEAD: usize>(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &[Header<'_>],
+        body: &[u8],
+        buffers: &'b mut ClientBuffers<RX, TX, HEAD>,
+    ) -> Result<Response<'b, TcpSocket<'b>>, Error>
+    where
+        'd: 'b,
+    {
+        let mut socket = TcpSocket::new(self.stack, &mut buffers.rx, &mut buffers.tx);
+        socket.set_timeout(self.config.timeout);
+        let buffer = &mut buffers.head[..];
+
+        let mut method = method;
+        let mut body = body;
+        let mut location: String<MAX_URL_LEN> = String::new();
+        let mut redirects = 0;
+        loop {
+            let current = match location.is_empty() {
+                true => parse_url(url)?,
+                false => parse_url(&location)?,
+            };
+            let addr = self.resolve(current.host).await?;
+            socket.connect((addr, current.port)).await.map_err(Error::Connect)?;
+            debug!("http: {} {}", method.as_str(), current.path);
+
+            let start_line = [
+                method.as_str(),
+                " ",
+                current.path,
+                " HTTP/1.1\r\nHost: ",
+                current.authority,
+            ];
+            let content = match body.is_empty() {
+                true => Body::Empty,
+                false => Body::Bytes(body),
+            };
+            write_head(&mut socket, &start_line, headers, request_body(method, &content), true).await?;
+            socket.flush().await.map_err(io)?;
+
+            let (head_len, len) = read_head(&mut socket, buffer).await?;
+
+            let mut next = None;
+            if self.config.max_redirects != 0 {
+                let (status_line, headers) = parse_head(&buffer[..head_len])?;
+                let status = status_line.split(' ').nth(1).unwrap_or("");
+                if matches!(status, "301" | "302" | "303" | "307" | "308") {
+                    if let Some(target) = find_header(&headers, "location") {
+                        if status == "303" || (method == Method::Post && matches!(status, "301" | "302")) {
+                            method = Method::Get;
+                            body = &[];
+                        }
+                        next = Some(resolve_location(&current, target)?);
+                    }
+                }
+            }
+
+            match next {
+                None => return Response::parse(socket, buffer, head_len, len, method == Method::Head),
+                Some(next) => {
+                    if redirects == self.config.max_redirects {
+                        return Err(Error::TooManyRedirects);
+                    }
+                    redirects += 1;
+                    debug!("http: redirected to {}", next.as_str());
+                    socket.abort();
+                    let _ = socket.flush().await;
+                    location = next;
+                }
+            }
+        }
+    }
+
+    async fn resolve(&self, host: &str) -> Result<IpAddress, Error> {
+        if let Ok(addr) = host.parse() {
+            return Ok(addr);
+        }
+
+        #[cfg(feature = "dns")]
+        {
+            #[cfg(feature = "proto-ipv4")]
+            let qtype = crate::dns::DnsQueryType::A;
+            #[cfg(not(feature = "proto-ipv4"))]
+            let qtype = crate::dns::DnsQueryType::Aaaa;
+
+            let addrs = self.stack.dns_query(host, qtype).await.map_err(|_| Error::Dns)?;
+            addrs.first().copied().ok_or(Error::Dns)
+        }
+
+        #[cfg(not(feature = "dns"))]
+        Err(Error::Dns)
+    }
+}
+
+/// Parameters captured from the path by a route.
+pub type Params<'a> = Vec<(&'a str, &'a str), MAX_PARAMS>;
+
+/// Routing table mapping requests to routes.
+///
+/// A path pattern is made of `/`-separated segments. A segment starting with `:` captures the
+/// corresponding segment of the request path as a parameter, and a final `*` segment matches
+/// the rest of the path.
+///
+/// ```
+/// use embassy_net::http::{Method, Router};
+///
+/// #[derive(Clone, Copy)]
+/// enum Route {
+///     Index,
+///     GetLed,
+///     SetLed,
+/// }
+///
+/// static ROUTER: Router<'static, Route> = Router::new(&[
+///     (Method::Get, "/", Route::Index),
+///     (Method::Get, "/api/led/:id", Route::GetLed),
+///     (Method::Put, "/api/led/:id", Route::SetLed),
+/// ]);
+/// ```
+pub struct Router<'a, R: Copy> {
+    routes: &'a [(Method, &'a str, R)],
+}
+
+impl<'a, R: Copy> Router<'a, R> {
+    /// Create a router from a list of methods, path patterns and routes.
+    ///
+    /// The first matching route is used.
+    pub const fn new(routes: &'a [(Method, &'a str, R)]) -> Self {
+        Self { routes }
+    }
+
+    /// Find the route for a request.
+    ///
+    /// Returns the route and the captured parameters, or the status code to answer with.
+    pub fn find<'p>(&self, method: Method, path: &'p str) -> Result<(R, Params<'p>), u16>
+    where
+        'a: 'p,
+    {
+        let mut status = 404;
+        for (m, pattern, route) in self.routes {
+            if let Some(params) = match_path(pattern, path) {
+                if *m == method {
+                    return Ok((*route, params));
+                }
+                status = 405;
+            }
+        }
+        Err(status)
+    }
+}
+
+fn match_path<'p>(pattern: &'p str, path: &'p str) -> Option<Params<'p>> {
+    let mut params = Vec::new();
+    let mut pattern = pattern.split('/');
+    let mut path = path.split('/');
+    loop {
+        match (pattern.next(), path.next()) {
+            (Some("*"), _) => return Some(params),
+            (Some(p), Some(s)) if p.starts_with(':') => params.push((&p[1..], s)).ok()?,
+            (Some(p), Some(s)) if p == s => {}
+            (None, None) => return Some(params),
+            _ => return None,
+        }
+    }
+}
+
+/// Request received by the server.
+pub struct ServerRequest<'r, T: Read + Write> {
+    /// Request method.
+    pub method: Method,
+    /// Path, without the query.
+    pub path: &'r str,
+    /// Query, without the leading `?`.
+    pub query: Option<&'r str>,
+    headers: Vec<Header<'r>, MAX_HEADERS>,
+    params: Params<'r>,
+    body: BodyReader<'r, &'r mut T>,
+    responded: &'r mut bool,
+}
+
+impl<'r, T: Read + Write> ServerRequest<'r, T> {
+    /// Get the headers.
+    pub fn headers(&self) -> &[Header<'r>] {
+        &self.headers
+    }
+
+    /// Get the value of a header, with a case-insensitive name.
+    pub fn header(&self, name: &str) -> Option<&'r str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Get a parameter captured by the route.
+    pub fn param(&self, name: &str) -> Option<&'r str> {
+        self.params.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+    }
+
+    /// Get the body reader.
+    pub fn body(&mut self) -> &mut BodyReader<'r, &'r mut T> {
+        &mut self.body
+    }
+
+    /// Send a response with a body sent in one piece.
+    pub async fn respond(self, status: u16, headers: &[Header<'_>], body: &[u8]) -> Result<(), Error> {
+        let transport = self.start_response(status, headers, &Body::Bytes(body)).await?;
+        transport.flush().await.map_err(io)
+    }
+
+    /// Start a response with a body streamed with chunked transfer encoding.
+    pub async fn respond_chunked(self, status: u16, headers: &[Header<'_>]) -> Result<ChunkedWriter<'r, T>, Error> {
+        let transport = self.start_response(status, headers, &Body::Chunked).await?;
+        Ok(ChunkedWriter::new(transport))
+    }
+
+    /// Send a `101 Switching Protocols` response, and get the transport to continue with another
+    /// protocol, such as [WebSocket](crate::websocket).
+    pub async fn respond_upgrade(mut self, headers: &[Header<'_>]) -> Result<&'r mut T, Error> {
+        self.body.discard().await?;
+        *self.responded = true;
+
+        let transport = self.body.into_inner();
+        write_head(transport, &["HTTP/1.1 101 ", reason_phrase(101)], headers, None, false).await?;
+        transport.flush().await.map_err(io)?;
+        Ok(transport)
+    }
+
+    async fn start_response(
+        mut self,
+        status: u16,
+        headers: &[Header<'_>],
+        body: &Body<'_>,
+    ) -> Result<&'r mut T, Error> {
+        // Read the rest of the request first, so the connection closes cleanly.
+        self.body.discard().await?;
+        *self.responded = true;
+
+        let mut code: String<4> = String::new();
+        let _ = write!(code, "{}", status);
+        let transport = self.body.into_inner();
+        let start_line = ["HTTP/1.1 ", code.as_str(), " ", reason_phrase(status)];
+        write_head(transport, &start_line, headers, Some(body), true).await?;
+        Ok(transport)
+    }
+}
+
+/// Request handler of a [`Server`].
+pub trait Handler<R> {
+    /// Answer a request for `route`, using [`ServerRequest::respond`] or
+    /// [`ServerRequest::respond_chunked`].
+    ///
+    /// If the handler returns without responding, the server answers with `500 Internal Server Error`.
+    async fn handle<T: Read + Write>(&mut self, route: R, request: ServerRequest<'_, T>) -> Result<(), Error>;
+}
+
+/// HTTP server.
+pub struct Server<'a, R: Copy> {
+    router: Router<'a, R>,
+    timeout: Duration,
+}
+
+impl<'a, R: Copy> Server<'a, R> {
+    /// Create a server, closing idle connections after 10 seconds.
+    pub const fn new(router: Router<'a, R>) -> Self {
+        Self {
+            router,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Set the inactivity timeout of connections.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Accept connections on `port` and serve them forever, one at a time.
+    ///
+    /// `buffer` must be able to hold the head of the requests.
+    pub async fn run<H: Handler<R>>(
+        &self,
+        socket: &mut TcpSocket<'_>,
+        port: u16,
+        buffer: &mut [u8],
+        handler: &mut H,
+    ) -> ! {
+        loop {
+            if let Err(e) = socket.accept(port).await {
+                warn!("http: accept error: {:?}", e);
+                continue;
+            }
+            socket.set_timeout(Some(self.timeout));
+
+            if let Err(e) = self.serve(socket, buffer, handler).await {
+                warn!("http: error: {:?}", e);
+            }
+
+            socket.close();
+            let _ = socket.flush().await;
+            socket.abort();
+            let _ = socket.flush().await;
+        }
+    }
+
+    /// Serve a single request over an established stream.
+    pub async fn serve<T: Read + Write, H: Handler<R>>(
+        &self,
+        transport: &mut T,
+        buffer: &mut [u8],
+        handler: &mut H,
+    ) -> Result<(), Error> {
+        let (head_len, len) = match read_head(transport, buffer).await {
+            Ok(res) => res,
+            Err(Error::BufferTooSmall) => return error_response(transport, 431).await,
+            Err(e) => return Err(e),
+        };
+
+        let (head, rest) = buffer.split_at_mut(head_len);
+        let Ok((request_line, headers)) = parse_head(head) else {
+            return error_response(transport, 400).await;
+        };
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+            return error_response(transport, 400).await;
+        };
+        let Some(method) = Method::parse(method) else {
+            return error_response(transport, 501).await;
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+        let framing = match body_framing(&headers) {
+            Ok(framing) => framing.unwrap_or(Framing::Length(0)),
+            Err(_) => return error_response(transport, 400).await,
+        };
+
+        debug!("http: {} {}", method.as_str(), path);
+        let (route, params) = match self.router.find(method, path) {
+            Ok(found) => found,
+            Err(status) => return error_response(transport, status).await,
+        };
+
+        let mut responded = false;
+        let request = ServerRequest {
+            method,
+            path,
+            query,
+            headers,
+            params,
+            body: BodyReader::new(&mut *transport, rest, len - head_len, framing),
+            responded: &mut responded,
+        };
+        let res = handler.handle(route, request).await;
+        if !responded {
+            warn!("http: handler didn't respond");
+            return error_response(transport, 500).await;
+        }
+        res
+    }
+}
+
+pub(crate) async fn error_response<T: Write>(transport: &mut T, status: u16) -> Result<(), Error> {
+    let mut code: String<4> = String::new();
+    let _ = write!(code, "{}", status);
+    let start_line = ["HTTP/1.1 ", code.as_str(), " ", reason_phrase(status)];
+    write_head(transport, &start_line, &[], Some(&Body::Empty), true).await?;
+    transport.flush().await.map_err(io)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// Transport returning at most `step` bytes per read.
+    struct SlowReader {
+        data: Vec<u8>,
+        step: usize,
+    }
+
+    impl embedded_io_async::ErrorType for SlowReader {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for SlowReader {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.step).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    /// Read a body whose first `buffered` bytes were received with the head.
+    fn read_body(data: &[u8], buffered: usize, framing: Framing) -> (Result<usize, Error>, Vec<u8>, bool) {
+        let mut buffer = [0; 16];
+        buffer[..buffered].copy_from_slice(&data[..buffered]);
+        let transport = SlowReader {
+            data: data[buffered..].to_vec(),
+            step: 3,
+        };
+        let mut reader = BodyReader::new(transport, &mut buffer, buffered, framing);
+        let mut body = [0; 32];
+        let res = block_on(reader.read_to_end(&mut body));
+        let len = *res.as_ref().unwrap_or(&0);
+        (res, body[..len].to_vec(), reader.is_complete())
+    }
+
+    #[test]
+    fn head() {
+        let (start, headers) = parse_head(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A:b:c \r\n\r\n").unwrap();
+        assert_eq!(start, "HTTP/1.1 200 OK");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(find_header(&headers, "content-length"), Some("5"));
+        assert_eq!(find_header(&headers, "x-a"), Some("b:c"));
+        assert_eq!(find_header(&headers, "host"), None);
+    }
+
+    #[test]
+    fn invalid_head() {
+        assert_eq!(
+            parse_head(b"GET / HTTP/1.1\r\nHost\r\n\r\n").err(),
+            Some(Error::Malformed)
+        );
+        assert_eq!(parse_head(b"GET /\xff HTTP/1.1\r\n\r\n").err(), Some(Error::Malformed));
+
+        let mut head = Vec::from(&b"GET / HTTP/1.1\r\n"[..]);
+        for _ in 0..=MAX_HEADERS {
+            head.extend_from_slice(b"A: b\r\n");
+        }
+        head.extend_from_slice(b"\r\n");
+        assert_eq!(parse_head(&head).err(), Some(Error::TooManyHeaders));
+    }
+
+    #[test]
+    fn framing() {
+        let chunked = [Header::new("Transfer-Encoding", "gzip, chunked")];
+        assert_eq!(body_framing(&chunked), Ok(Some(Framing::Chunked(Chunk::Size))));
+        let gzip = [Header::new("Transfer-Encoding", "gzip")];
+        assert_eq!(body_framing(&gzip), Ok(Some(Framing::UntilClose)));
+        let length = [Header::new("Content-Length", "12")];
+        assert_eq!(body_framing(&length), Ok(Some(Framing::Length(12))));
+        let invalid = [Header::new("Content-Length", "twelve")];
+        assert_eq!(body_framing(&invalid), Err(Error::Malformed));
+        assert_eq!(body_framing(&[]), Ok(None));
+    }
+
+    #[test]
+    fn length_body() {
+        let (res, body, complete) = read_body(b"hello world", 4, Framing::Length(11));
+        assert_eq!(res, Ok(11));
+        assert_eq!(body, b"hello world");
+        assert!(complete);
+
+        let (res, _, complete) = read_body(b"hello", 2, Framing::Length(11));
+        assert_eq!(res, Err(Error::ConnectionClosed));
+        assert!(!complete);
+    }
+
+    #[test]
+    fn chunked_body() {
+        let data = b"4\r\nWiki\r\n5;ext=1\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nTrailer: x\r\n\r\n";
+        for buffered in [0, 5, 16] {
+            let (res, body, complete) = read_body(data, buffered, Framing::Chunked(Chunk::Size));
+            assert_eq!(res, Ok(23));
+            assert_eq!(body, b"Wikipedia in\r\n\r\nchunks.");
+            assert!(complete);
+        }
+    }
+
+    #[test]
+    fn invalid_chunked_body() {
+        let (res, _, _) = read_body(b"x\r\nabc\r\n", 0, Framing::Chunked(Chunk::Size));
+        assert_eq!(res, Err(Error::Malformed));
+
+        let (res, _, complete) = read_body(b"10\r\nabc", 0, Framing::Chunked(Chunk::Size));
+        assert_eq!(res, Err(Error::ConnectionClosed));
+        assert!(!complete);
+    }
+}
@@ -12,16 +12,48 @@ compile_error!("You must enable at least one of the following features: proto-ip
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(feature = "coap")]
+pub mod coap;
 mod device;
+#[cfg(feature = "dhcpv4-server")]
+pub mod dhcp_server;
+#[cfg(feature = "dhcpv6")]
+mod dhcpv6;
 #[cfg(feature = "dns")]
 pub mod dns;
+#[cfg(any(feature = "mdns", feature = "dns-resolver"))]
+mod dns_wire;
+#[cfg(feature = "firewall")]
+pub mod firewall;
+#[cfg(feature = "frame-socket")]
+pub mod frame;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 #[cfg(feature = "raw")]
 pub mod raw;
+#[cfg(feature = "dns-resolver")]
+pub mod resolver;
+#[cfg(feature = "slaac")]
+mod slaac;
+#[cfg(feature = "sntp")]
+pub mod sntp;
+#[cfg(feature = "stats")]
+pub mod stats;
 #[cfg(feature = "tcp")]
 pub mod tcp;
+#[cfg(all(test, feature = "medium-ip"))]
+mod testing;
 mod time;
+#[cfg(feature = "tls")]
+pub mod tls;
 #[cfg(feature = "udp")]
 pub mod udp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 use core::cell::RefCell;
 use core::future::{poll_fn, Future};
@@ -55,7 +87,7 @@ pub use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
 #[cfg(feature = "proto-ipv6")]
 pub use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
 
-use crate::device::DriverAdapter;
+use crate::device::{DriverAdapter, Taps};
 use crate::time::{instant_from_smoltcp, instant_to_smoltcp};
 
 const LOCAL_PORT_MIN: u16 = 1025;
@@ -72,6 +104,8 @@ pub struct StackResources<const SOCK: usize> {
     queries: [Option<dns::DnsQuery>; MAX_QUERIES],
     #[cfg(feature = "dhcpv4-hostname")]
     hostname: core::cell::UnsafeCell<HostnameResources>,
+    #[cfg(feature = "slaac")]
+    slaac: slaac::Resources,
 }
 
 #[cfg(feature = "dhcpv4-hostname")]
@@ -94,6 +128,8 @@ impl<const SOCK: usize> StackResources<SOCK> {
                 option: smoltcp::wire::DhcpOption { kind: 0, data: &[] },
                 data: [0; MAX_HOSTNAME_LEN],
             }),
+            #[cfg(feature = "slaac")]
+            slaac: slaac::Resources::new(),
         }
     }
 }
@@ -162,6 +198,39 @@ impl Default for DhcpConfig {
     }
 }
 
+/// IPv6 autoconfiguration.
+///
+/// The stack solicits Router Advertisements, forms an address from the advertised prefix (SLAAC)
+/// and uses the advertised default router and DNS servers (RDNSS). If the router asks for it, the
+/// address and DNS servers are requested with DHCPv6 instead.
+///
+/// A link-local address is added as well. When IPv4 is also in use, this needs a smoltcp
+/// interface with room for 3 addresses (the `iface-max-addr-count-3` smoltcp feature).
+#[cfg(feature = "slaac")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AutoConfigV6 {
+    /// Form an address from prefixes advertised with the "autonomous" flag.
+    pub slaac: bool,
+    /// Use DHCPv6 when the router sets the "managed" or "other configuration" flags.
+    #[cfg(feature = "dhcpv6")]
+    pub dhcpv6: bool,
+    /// Number of Router Solicitations sent after the link comes up.
+    pub max_router_solicitations: u8,
+}
+
+#[cfg(feature = "slaac")]
+impl Default for AutoConfigV6 {
+    fn default() -> Self {
+        Self {
+            slaac: true,
+            #[cfg(feature = "dhcpv6")]
+            dhcpv6: true,
+            max_router_solicitations: 3,
+        }
+    }
+}
+
 /// Network stack configuration.
 #[derive(Debug, Clone, Default)]
 #[non_exhaustive]
@@ -195,6 +264,22 @@ impl Config {
         }
     }
 
+    /// IPv6 configuration with autoconfiguration.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use embassy_net::Config;
+    /// let _cfg = Config::ipv6_auto(Default::default());
+    /// ```
+    #[cfg(feature = "slaac")]
+    pub fn ipv6_auto(config: AutoConfigV6) -> Self {
+        Self {
+            #[cfg(feature = "proto-ipv4")]
+            ipv4: ConfigV4::None,
+            ipv6: ConfigV6::Auto(config),
+        }
+    }
+
     /// IPv4 configuration with dynamic addressing.
     ///
     /// # Example
@@ -235,6 +320,9 @@ pub enum ConfigV6 {
     None,
     /// Use a static IPv6 address configuration.
     Static(StaticConfigV6),
+    /// Use router discovery, SLAAC and DHCPv6 to obtain an IP address configuration.
+    #[cfg(feature = "slaac")]
+    Auto(AutoConfigV6),
 }
 
 /// A network stack.
@@ -261,6 +349,16 @@ struct Inner<D: Driver> {
     dns_waker: WakerRegistration,
     #[cfg(feature = "dhcpv4-hostname")]
     hostname: &'static mut core::cell::UnsafeCell<HostnameResources>,
+    #[cfg(feature = "slaac")]
+    auto_v6: Option<slaac::AutoV6>,
+    /// Autoconfiguration state kept while disabled, with its sockets.
+    #[cfg(feature = "slaac")]
+    auto_v6_idle: Option<slaac::AutoV6>,
+    /// Buffers for the autoconfiguration sockets, until it is first enabled.
+    #[cfg(feature = "slaac")]
+    auto_v6_resources: Option<&'static mut slaac::Resources>,
+    #[cfg(feature = "slaac")]
+    random_seed: u64,
 }
 
 pub(crate) struct SocketStack {
@@ -268,6 +366,7 @@ pub(crate) struct SocketStack {
     pub(crate) iface: Interface,
     pub(crate) waker: WakerRegistration,
     next_local_port: u16,
+    pub(crate) taps: Taps,
 }
 
 fn to_smoltcp_hardware_address(addr: driver::HardwareAddress) -> (HardwareAddress, Medium) {
@@ -301,6 +400,10 @@ impl<D: Driver> Stack<D> {
         let (hardware_addr, medium) = to_smoltcp_hardware_address(device.hardware_address());
         let mut iface_cfg = smoltcp::iface::Config::new(hardware_addr);
         iface_cfg.random_seed = random_seed;
+        #[cfg(feature = "medium-ieee802154")]
+        {
+            iface_cfg.pan_id = device.ieee802154_pan_id().map(smoltcp::wire::Ieee802154Pan);
+        }
 
         let iface = Interface::new(
             iface_cfg,
@@ -308,6 +411,7 @@ impl<D: Driver> Stack<D> {
                 inner: &mut device,
                 cx: None,
                 medium,
+                taps: &Taps::new(),
             },
             instant_to_smoltcp(Instant::now()),
         );
@@ -322,6 +426,7 @@ impl<D: Driver> Stack<D> {
             iface,
             waker: WakerRegistration::new(),
             next_local_port,
+            taps: Taps::new(),
         };
 
         let mut inner = Inner {
@@ -343,6 +448,14 @@ impl<D: Driver> Stack<D> {
             dns_waker: WakerRegistration::new(),
             #[cfg(feature = "dhcpv4-hostname")]
             hostname: &mut resources.hostname,
+            #[cfg(feature = "slaac")]
+            auto_v6: None,
+            #[cfg(feature = "slaac")]
+            auto_v6_idle: None,
+            #[cfg(feature = "slaac")]
+            auto_v6_resources: Some(&mut resources.slaac),
+            #[cfg(feature = "slaac")]
+            random_seed,
         };
 
         #[cfg(feature = "proto-ipv4")]
@@ -375,6 +488,40 @@ impl<D: Driver> Stack<D> {
         self.with(|_s, i| i.link_up)
     }
 
+    /// Get a snapshot of the interface statistics.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> stats::Stats {
+        self.socket.borrow().taps.stats.borrow().stats()
+    }
+
+    /// Reset the interface statistics, and the retransmit counters of TCP connections, to zero.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.socket.borrow().taps.stats.borrow_mut().reset()
+    }
+
+    /// Set the firewall rules, and the action for packets matching none of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more than [`firewall::MAX_RULES`] rules.
+    #[cfg(feature = "firewall")]
+    pub fn set_firewall(&self, rules: &[firewall::Rule], default: firewall::Action) {
+        self.socket.borrow().taps.firewall.borrow_mut().set(rules, default)
+    }
+
+    /// Set a custom firewall filter, called for every packet before the rules.
+    #[cfg(feature = "firewall")]
+    pub fn set_firewall_hook(&self, hook: Option<firewall::Hook>) {
+        self.socket.borrow().taps.firewall.borrow_mut().set_hook(hook)
+    }
+
+    /// Get the number of packets dropped by the firewall.
+    #[cfg(feature = "firewall")]
+    pub fn firewall_counters(&self) -> firewall::Counters {
+        self.socket.borrow().taps.firewall.borrow().counters()
+    }
+
     /// Get whether the network stack has a valid IP configuration.
     /// This is true if the network stack has a static IP configuration or if DHCP has completed
     pub fn is_config_up(&self) -> bool {
@@ -602,9 +749,15 @@ impl<D: Driver> Stack<D> {
 #[cfg(feature = "igmp")]
 impl<D: Driver> Stack<D> {
     /// Join a multicast group.
+    ///
+    /// This sends an IGMPv2 membership report, answers membership queries for the group from then on,
+    /// and asks the driver to accept frames sent to the group's hardware address
+    /// (see [`Driver::add_multicast_address`](driver::Driver::add_multicast_address)).
+    ///
+    /// Only IPv4 groups can be joined, since smoltcp doesn't implement MLD yet.
     pub async fn join_multicast_group<T>(&self, addr: T) -> Result<bool, MulticastError>
     where
-        T: Into<IpAddress>,
+        T: Into<Ipv4Address>,
     {
         let addr = addr.into();
 
@@ -617,9 +770,9 @@ impl<D: Driver> Stack<D> {
     /// and register the current task to be notified when the queue has space available.
     pub fn poll_join_multicast_group<T>(&self, addr: T, cx: &mut Context<'_>) -> Poll<Result<bool, MulticastError>>
     where
-        T: Into<IpAddress>,
+        T: Into<Ipv4Address>,
     {
-        let addr = addr.into();
+        let addr = IpAddress::Ipv4(addr.into());
 
         self.with_mut(|s, i| {
             let (_hardware_addr, medium) = to_smoltcp_hardware_address(i.device.hardware_address());
@@ -627,12 +780,23 @@ impl<D: Driver> Stack<D> {
                 cx: Some(cx),
                 inner: &mut i.device,
                 medium,
+                taps: &s.taps,
             };
 
-            match s
+            let joined = s.iface.has_multicast_group(addr);
+            let res = s
                 .iface
-                .join_multicast_group(&mut smoldev, addr, instant_to_smoltcp(Instant::now()))
-            {
+                .join_multicast_group(&mut smoldev, addr, instant_to_smoltcp(Instant::now()));
+
+            // smoltcp adds the group before sending the report, so the filter must be
+            // updated even if sending failed.
+            if !joined && s.iface.has_multicast_group(addr) {
+                if let Some(hw) = multicast_hardware_address(i.device.hardware_address(), addr) {
+                    i.device.add_multicast_address(hw);
+                }
+            }
+
+            match res {
                 Ok(announce_sent) => Poll::Ready(Ok(announce_sent)),
                 Err(MulticastError::Exhausted) => Poll::Pending,
                 Err(other) => Poll::Ready(Err(other)),
@@ -641,9 +805,12 @@ impl<D: Driver> Stack<D> {
     }
 
     /// Leave a multicast group.
+    ///
+    /// This sends an IGMPv2 leave message, and removes the group's hardware address from the driver's
+    /// multicast filter unless another joined group maps to it.
     pub async fn leave_multicast_group<T>(&self, addr: T) -> Result<bool, MulticastError>
     where
-        T: Into<IpAddress>,
+        T: Into<Ipv4Address>,
     {
         let addr = addr.into();
 
@@ -656,9 +823,9 @@ impl<D: Driver> Stack<D> {
     /// and register the current task to be notified when the queue has space available.
     pub fn poll_leave_multicast_group<T>(&self, addr: T, cx: &mut Context<'_>) -> Poll<Result<bool, MulticastError>>
     where
-        T: Into<IpAddress>,
+        T: Into<Ipv4Address>,
     {
-        let addr = addr.into();
+        let addr = IpAddress::Ipv4(addr.into());
 
         self.with_mut(|s, i| {
             let (_hardware_addr, medium) = to_smoltcp_hardware_address(i.device.hardware_address());
@@ -666,12 +833,24 @@ impl<D: Driver> Stack<D> {
                 cx: Some(cx),
                 inner: &mut i.device,
                 medium,
+                taps: &s.taps,
             };
 
-            match s
+            let joined = s.iface.has_multicast_group(addr);
+            let res = s
                 .iface
-                .leave_multicast_group(&mut smoldev, addr, instant_to_smoltcp(Instant::now()))
-            {
+                .leave_multicast_group(&mut smoldev, addr, instant_to_smoltcp(Instant::now()));
+
+            if joined && !s.iface.has_multicast_group(addr) {
+                if let Some(hw) = multicast_hardware_address(i.device.hardware_address(), addr) {
+                    // Several groups map to the same hardware address, keep it if another one is still joined.
+                    if !multicast_hardware_address_in_use(&s.iface, hw) {
+                        i.device.remove_multicast_address(hw);
+                    }
+                }
+            }
+
+            match res {
                 Ok(leave_sent) => Poll::Ready(Ok(leave_sent)),
                 Err(MulticastError::Exhausted) => Poll::Pending,
                 Err(other) => Poll::Ready(Err(other)),
@@ -685,6 +864,46 @@ impl<D: Driver> Stack<D> {
     }
 }
 
+/// Get the hardware address frames to the multicast group `addr` are sent to, if the medium has one.
+#[cfg(feature = "igmp")]
+fn multicast_hardware_address(
+    hardware_addr: driver::HardwareAddress,
+    addr: IpAddress,
+) -> Option<driver::HardwareAddress> {
+    match (hardware_addr, addr) {
+        // RFC 1112: the low 23 bits of the group go into 01:00:5e:00:00:00.
+        (driver::HardwareAddress::Ethernet(_), IpAddress::Ipv4(addr)) => {
+            let b = addr.as_bytes();
+            Some(driver::HardwareAddress::Ethernet([
+                0x01,
+                0x00,
+                0x5e,
+                b[1] & 0x7f,
+                b[2],
+                b[3],
+            ]))
+        }
+        _ => None,
+    }
+}
+
+/// Get whether any joined multicast group maps to the hardware address `hw`.
+#[cfg(feature = "igmp")]
+fn multicast_hardware_address_in_use(iface: &Interface, hw: driver::HardwareAddress) -> bool {
+    match hw {
+        driver::HardwareAddress::Ethernet([0x01, 0x00, 0x5e, b1, b2, b3]) => {
+            // 32 IPv4 groups share each address: the top 4 bits of the first octet are
+            // fixed, the low 4 bits and the top bit of the second octet are lost.
+            (224..=239).any(|b0| {
+                [b1, b1 | 0x80]
+                    .iter()
+                    .any(|&b1| iface.has_multicast_group(smoltcp::wire::Ipv4Address::new(b0, b1, b2, b3)))
+            })
+        }
+        _ => false,
+    }
+}
+
 impl SocketStack {
     #[allow(clippy::absurd_extreme_comparisons, dead_code)]
     pub fn get_local_port(&mut self) -> u16 {
@@ -755,9 +974,39 @@ impl<D: Driver> Inner<D> {
 
     #[cfg(feature = "proto-ipv6")]
     pub fn set_config_v6(&mut self, _s: &mut SocketStack, config: ConfigV6) {
+        // Keep the autoconfiguration state aside if any, it is reused if enabled again.
+        #[cfg(feature = "slaac")]
+        if let Some(auto) = self.auto_v6.take() {
+            self.auto_v6_idle = Some(auto);
+        }
+
         self.static_v6 = match config {
             ConfigV6::None => None,
             ConfigV6::Static(c) => Some(c),
+            #[cfg(feature = "slaac")]
+            ConfigV6::Auto(c) => {
+                let auto = match self.auto_v6_idle.take() {
+                    Some(mut auto) => {
+                        auto.set_config(_s, c);
+                        auto
+                    }
+                    None => {
+                        let (hardware_addr, _) = to_smoltcp_hardware_address(self.device.hardware_address());
+                        let hardware_addr: &[u8] = match &hardware_addr {
+                            #[cfg(feature = "medium-ethernet")]
+                            HardwareAddress::Ethernet(addr) => addr.as_bytes(),
+                            #[cfg(feature = "medium-ieee802154")]
+                            HardwareAddress::Ieee802154(addr) => addr.as_bytes(),
+                            #[allow(unreachable_patterns)]
+                            _ => &[],
+                        };
+                        let resources = unwrap!(self.auto_v6_resources.take());
+                        slaac::AutoV6::new(_s, resources, c, hardware_addr, self.random_seed)
+                    }
+                };
+                self.auto_v6 = Some(auto);
+                None
+            }
         };
     }
 
@@ -804,6 +1053,15 @@ impl<D: Driver> Inner<D> {
             info!("IPv6: DOWN");
         }
 
+        #[cfg(feature = "slaac")]
+        if let Some(auto) = &self.auto_v6 {
+            let link_local = auto.link_local();
+            debug!("   Link-local:      {:?}", link_local);
+            if addrs.push(IpCidr::Ipv6(link_local)).is_err() {
+                warn!("No room for the IPv6 link-local address, increase smoltcp's iface-max-addr-count");
+            }
+        }
+
         // Apply addresses
         s.iface.update_ip_addrs(|a| *a = addrs);
 
@@ -855,8 +1113,11 @@ impl<D: Driver> Inner<D> {
             cx: Some(cx),
             inner: &mut self.device,
             medium,
+            taps: &s.taps,
         };
         s.iface.poll(timestamp, &mut smoldev, &mut s.sockets);
+        #[cfg(feature = "frame-socket")]
+        frame::transmit(&mut smoldev, &s.taps.frames, timestamp);
 
         // Update link up
         let old_link_up = self.link_up;
@@ -900,12 +1161,30 @@ impl<D: Driver> Inner<D> {
             }
         }
 
+        #[cfg(feature = "slaac")]
+        if let Some(auto) = &mut self.auto_v6 {
+            if old_link_up != self.link_up {
+                auto.reset();
+            }
+            if auto.poll(s, self.link_up) {
+                self.static_v6 = auto.config();
+                apply_config = true;
+            }
+        }
+
         if apply_config {
             self.apply_static_config(s);
         }
 
-        if let Some(poll_at) = s.iface.poll_at(timestamp, &mut s.sockets) {
-            let t = Timer::at(instant_from_smoltcp(poll_at));
+        #[allow(unused_mut)]
+        let mut poll_at = s.iface.poll_at(timestamp, &mut s.sockets).map(instant_from_smoltcp);
+        #[cfg(feature = "slaac")]
+        if let Some(at) = self.auto_v6.as_ref().and_then(|a| a.poll_at()) {
+            poll_at = Some(poll_at.map_or(at, |p| p.min(at)));
+        }
+
+        if let Some(poll_at) = poll_at {
+            let t = Timer::at(poll_at);
             pin_mut!(t);
             if t.poll(cx).is_ready() {
                 cx.waker().wake_by_ref();
@@ -0,0 +1,642 @@
+//! Multicast DNS (mDNS) responder and one-shot `.local` name resolution.
+//!
+//! The [`Responder`] answers queries for the device's hostname (`<hostname>.local`) and advertises
+//! DNS-SD services with their SRV and TXT records, so the device can be found on the local network
+//! without a static IP or a DNS server.
+//!
+//! [`resolve`] looks up the address of another host's `.local` name using a one-shot query.
+//!
+//! Only mDNS over IPv4 is supported, since it needs the stack's multicast group membership (IGMP).
+//! `AAAA` records for the device's IPv6 address are still answered if IPv6 is enabled.
+
+use embassy_net_driver::Driver;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::dns_wire::{self, Reader, Writer};
+use crate::udp::{self, UdpSocket};
+use crate::{IpAddress, IpEndpoint, Ipv4Address, MulticastError, Stack};
+
+/// UDP port used by mDNS.
+pub const MDNS_PORT: u16 = 5353;
+
+/// IPv4 multicast group used by mDNS.
+pub const MDNS_GROUP_V4: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+
+/// Maximum size of the mDNS messages sent and received.
+///
+/// Incoming messages larger than this are ignored.
+pub const MAX_MESSAGE_LEN: usize = 512;
+
+/// Maximum number of addresses returned by [`resolve`].
+pub const MAX_RESOLVE_RESULTS: usize = 4;
+
+const SERVICES_META_QUERY: &str = "_services._dns-sd._udp.local";
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// Errors returned by the mDNS responder and resolver.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A hostname, service or instance name is not a valid DNS name.
+    InvalidName,
+    /// The socket could not be bound.
+    Bind(udp::BindError),
+    /// A message could not be sent.
+    Send(udp::SendError),
+    /// The mDNS multicast group could not be joined.
+    Multicast(MulticastError),
+    /// No answer was received in time.
+    Timeout,
+}
+
+/// A DNS-SD service advertised by the [`Responder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Service<'a> {
+    /// Human-readable instance name, e.g. `"Kitchen sensor"`. Must be at most 63 bytes.
+    pub instance: &'a str,
+    /// Service type and protocol, e.g. `"_http._tcp"`.
+    pub service_type: &'a str,
+    /// Port the service listens on.
+    pub port: u16,
+    /// TXT record entries, e.g. `["path=/", "version=1"]`.
+    pub txt: &'a [&'a str],
+}
+
+/// mDNS responder configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Hostname, without the `.local` suffix.
+    pub hostname: &'a str,
+    /// Services to advertise.
+    pub services: &'a [Service<'a>],
+    /// Time-to-live of the records, in seconds.
+    pub ttl: u32,
+}
+
+impl<'a> Config<'a> {
+    /// Create a configuration advertising `hostname` and `services` with a TTL of 120 seconds.
+    pub fn new(hostname: &'a str, services: &'a [Service<'a>]) -> Self {
+        Self {
+            hostname,
+            services,
+            ttl: 120,
+        }
+    }
+}
+
+type NameBuf = String<{ dns_wire::MAX_NAME_LEN }>;
+
+fn join(a: &str, b: &str) -> Result<NameBuf, Error> {
+    let mut s = NameBuf::new();
+    s.push_str(a).map_err(|_| Error::InvalidName)?;
+    s.push('.').map_err(|_| Error::InvalidName)?;
+    s.push_str(b).map_err(|_| Error::InvalidName)?;
+    Ok(s)
+}
+
+/// mDNS responder.
+pub struct Responder<'a, 'c, D: Driver + 'static> {
+    stack: &'a Stack<D>,
+    socket: UdpSocket<'a>,
+    config: Config<'c>,
+    host: NameBuf,
+}
+
+impl<'a, 'c, D: Driver + 'static> Responder<'a, 'c, D> {
+    /// Create a new responder using the given UDP socket.
+    ///
+    /// The socket is bound to the mDNS port.
+    pub fn new(stack: &'a Stack<D>, mut socket: UdpSocket<'a>, config: Config<'c>) -> Result<Self, Error> {
+        let host = join(config.hostname, "local")?;
+        if config.hostname.is_empty() || config.hostname.len() > 63 || config.hostname.contains('.') {
+            return Err(Error::InvalidName);
+        }
+        for s in config.services {
+            if s.instance.is_empty() || s.instance.len() > 63 {
+                return Err(Error::InvalidName);
+            }
+            join(s.service_type, "local")?;
+        }
+
+        socket.bind(MDNS_PORT).map_err(Error::Bind)?;
+
+        Ok(Self {
+            stack,
+            socket,
+            config,
+            host,
+        })
+    }
+
+    /// Run the responder.
+    ///
+    /// Joins the mDNS multicast group, announces the configured records and then answers
+    /// queries forever. Only returns if joining the group or announcing fails.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        self.stack
+            .join_multicast_group(MDNS_GROUP_V4)
+            .await
+            .map_err(Error::Multicast)?;
+
+        // RFC 6762 section 8.3: send at least two unsolicited announcements, one second apart.
+        for i in 0..2 {
+            if i != 0 {
+                Timer::after(Duration::from_secs(1)).await;
+            }
+            self.announce().await?;
+        }
+
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        loop {
+            let (n, src) = match self.socket.recv_from(&mut rx).await {
+                Ok(x) => x,
+                Err(udp::RecvError::Truncated) => continue,
+            };
+            let Some((len, dest)) = self.respond(&rx[..n], src, &mut tx) else {
+                continue;
+            };
+            if let Err(e) = self.socket.send_to(&tx[..len], dest).await {
+                warn!("mdns: failed to send response: {:?}", e);
+            }
+        }
+    }
+
+    /// Send an unsolicited response with all records.
+    pub async fn announce(&mut self) -> Result<(), Error> {
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        let mut w = unwrap!(Writer::new(
+            &mut tx,
+            0,
+            dns_wire::FLAG_RESPONSE | dns_wire::FLAG_AUTHORITATIVE
+        ));
+        self.write_host(&mut w, dns_wire::TYPE_ANY, false);
+        for s in self.config.services {
+            self.write_service(&mut w, s, false);
+        }
+        let len = w.finish();
+        self.socket
+            .send_to(&tx[..len], IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT))
+            .await
+            .map_err(Error::Send)
+    }
+
+    /// Build a response to the query in `msg` into `tx`. Returns its length and destination.
+    fn respond(&self, msg: &[u8], src: IpEndpoint, tx: &mut [u8]) -> Option<(usize, IpEndpoint)> {
+        let mut r = Reader::new(msg).ok()?;
+        let header = *r.header();
+        if header.flags & (dns_wire::FLAG_RESPONSE | dns_wire::FLAG_OPCODE_MASK) != 0 {
+            return None;
+        }
+
+        // Queries not coming from port 5353 are "legacy unicast" queries (RFC 6762 section 6.7):
+        // they get a unicast reply with the query ID and question echoed back.
+        let legacy = src.port != MDNS_PORT;
+        let mut unicast = legacy;
+
+        let mut w = Writer::new(
+            tx,
+            if legacy { header.id } else { 0 },
+            dns_wire::FLAG_RESPONSE | dns_wire::FLAG_AUTHORITATIVE,
+        )
+        .ok()?;
+
+        if legacy {
+            // Echo the first question. It is re-read below to find the answers.
+            let mut r2 = Reader::new(msg).ok()?;
+            if header.questions > 0 {
+                let q = r2.question().ok()?;
+                let mut name = NameBuf::new();
+                q.name.write_to(&mut name).ok()?;
+                w.question(&name, q.qtype, q.qclass).ok()?;
+            }
+        }
+
+        let mut answered = false;
+        for _ in 0..header.questions {
+            let q = r.question().ok()?;
+            if q.qclass != dns_wire::CLASS_IN && q.qclass != dns_wire::CLASS_ANY {
+                continue;
+            }
+            unicast |= q.unicast_response;
+            answered |= self.answer(&mut w, &q, legacy);
+        }
+
+        if !answered {
+            return None;
+        }
+
+        let dest = if unicast {
+            src
+        } else {
+            IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT)
+        };
+        Some((w.finish(), dest))
+    }
+
+    /// Write the answers to one question. Returns whether anything was written.
+    fn answer(&self, w: &mut Writer, q: &dns_wire::Question, legacy: bool) -> bool {
+        let before = w.len();
+
+        if q.name.eq_str(&self.host) {
+            self.write_host(w, q.qtype, legacy);
+        } else if q.name.eq_str(SERVICES_META_QUERY) {
+            if matches!(q.qtype, dns_wire::TYPE_PTR | dns_wire::TYPE_ANY) {
+                for s in self.config.services {
+                    let Ok(service) = join(s.service_type, "local") else {
+                        continue;
+                    };
+                    self.record(w, |w| {
+                        w.name(SERVICES_META_QUERY)?;
+                        w.record_data(dns_wire::TYPE_PTR, dns_wire::CLASS_IN, self.config.ttl, |w| {
+                            w.name(&service)
+                        })
+                    });
+                }
+            }
+        } else {
+            for s in self.config.services {
+                let Ok(service) = join(s.service_type, "local") else {
+                    continue;
+                };
+                if q.name.eq_str(&service) && matches!(q.qtype, dns_wire::TYPE_PTR | dns_wire::TYPE_ANY) {
+                    self.write_service(w, s, legacy);
+                } else if q.name.eq_label_suffix(s.instance, &service) {
+                    if matches!(q.qtype, dns_wire::TYPE_SRV | dns_wire::TYPE_ANY) {
+                        self.write_srv(w, s, &service, legacy);
+                    }
+                    if matches!(q.qtype, dns_wire::TYPE_TXT | dns_wire::TYPE_ANY) {
+                        self.write_txt(w, s, &service, legacy);
+                    }
+                }
+            }
+        }
+
+        w.len() != before
+    }
+
+    /// Write one record as an answer. Records that don't fit are dropped.
+    fn record(&self, w: &mut Writer, f: impl FnOnce(&mut Writer) -> Result<(), dns_wire::Error>) {
+        let before = w.len();
+        match f(w) {
+            Ok(()) => w.answer_added(),
+            Err(_) => w.truncate(before),
+        }
+    }
+
+    fn class(&self, legacy: bool) -> u16 {
+        // The cache-flush bit must not be set in legacy unicast responses.
+        if legacy {
+            dns_wire::CLASS_IN
+        } else {
+            dns_wire::CLASS_IN | CACHE_FLUSH
+        }
+    }
+
+    fn write_host(&self, w: &mut Writer, qtype: u16, legacy: bool) {
+        #[cfg(feature = "proto-ipv4")]
+        if matches!(qtype, dns_wire::TYPE_A | dns_wire::TYPE_ANY) {
+            if let Some(config) = self.stack.config_v4() {
+                self.record(w, |w| {
+                    w.name(&self.host)?;
+                    w.record_data(dns_wire::TYPE_A, self.class(legacy), self.config.ttl, |w| {
+                        config.address.address().0.iter().try_for_each(|b| w.u8(*b))
+                    })
+                });
+            }
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if matches!(qtype, dns_wire::TYPE_AAAA | dns_wire::TYPE_ANY) {
+            if let Some(config) = self.stack.config_v6() {
+                self.record(w, |w| {
+                    w.name(&self.host)?;
+                    w.record_data(dns_wire::TYPE_AAAA, self.class(legacy), self.config.ttl, |w| {
+                        config.address.address().0.iter().try_for_each(|b| w.u8(*b))
+                    })
+                });
+            }
+        }
+    }
+
+    fn write_service(&self, w: &mut Writer, s: &Service, legacy: bool) {
+        let Ok(service) = join(s.service_type, "local") else {
+            return;
+        };
+        self.record(w, |w| {
+            w.name(&service)?;
+            w.record_data(dns_wire::TYPE_PTR, dns_wire::CLASS_IN, self.config.ttl, |w| {
+                w.name_with_label(s.instance, &service)
+            })
+        });
+        self.write_srv(w, s, &service, legacy);
+        self.write_txt(w, s, &service, legacy);
+    }
+
+    fn write_srv(&self, w: &mut Writer, s: &Service, service: &str, legacy: bool) {
+        self.record(w, |w| {
+            w.name_with_label(s.instance, service)?;
+            w.record_data(dns_wire::TYPE_SRV, self.class(legacy), self.config.ttl, |w| {
+                w.u16(0)?; // priority
+                w.u16(0)?; // weight
+                w.u16(s.port)?;
+                w.name(&self.host)
+            })
+        });
+    }
+
+    fn write_txt(&self, w: &mut Writer, s: &Service, service: &str, legacy: bool) {
+        self.record(w, |w| {
+            w.name_with_label(s.instance, service)?;
+            w.record_data(dns_wire::TYPE_TXT, self.class(legacy), self.config.ttl, |w| {
+                if s.txt.is_empty() {
+                    // A TXT record must contain at least one string.
+                    return w.character_string(&[]);
+                }
+                s.txt.iter().try_for_each(|t| w.character_string(t.as_bytes()))
+            })
+        });
+    }
+}
+
+/// Resolve a `.local` name using a one-shot mDNS query.
+///
+/// The query is sent from `socket`, which is bound to an ephemeral port if it isn't bound yet.
+/// Responders reply directly to that port, so this does not need the multicast group to be
+/// joined and can be used alongside a [`Responder`]. The query is repeated every second until an
+/// answer arrives or `timeout` expires.
+pub async fn resolve(
+    socket: &mut UdpSocket<'_>,
+    name: &str,
+    timeout: Duration,
+) -> Result<Vec<IpAddress, MAX_RESOLVE_RESULTS>, Error> {
+    if socket.endpoint().port == 0 {
+        socket.bind(0).map_err(Error::Bind)?;
+    }
+
+    let id = (Instant::now().as_ticks() & 0xffff) as u16;
+    let mut tx = [0; MAX_MESSAGE_LEN];
+    let mut w = Writer::new(&mut tx, id, 0).map_err(|_| Error::InvalidName)?;
+    #[cfg(feature = "proto-ipv4")]
+    w.question(name, dns_wire::TYPE_A, dns_wire::CLASS_IN)
+        .map_err(|_| Error::InvalidName)?;
+    #[cfg(feature = "proto-ipv6")]
+    w.question(name, dns_wire::TYPE_AAAA, dns_wire::CLASS_IN)
+        .map_err(|_| Error::InvalidName)?;
+    let len = w.finish();
+
+    let deadline = Instant::now() + timeout;
+    let mut rx = [0; MAX_MESSAGE_LEN];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::Timeout);
+        }
+        socket
+            .send_to(&tx[..len], IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT))
+            .await
+            .map_err(Error::Send)?;
+
+        let wait = (deadline - now).min(Duration::from_secs(1));
+        let res = with_timeout(wait, async {
+            loop {
+                let Ok((n, _)) = socket.recv_from(&mut rx).await else {
+                    continue;
+                };
+                let addrs = parse_answers(&rx[..n], id, name);
+                if !addrs.is_empty() {
+                    return addrs;
+                }
+            }
+        })
+        .await;
+
+        if let Ok(addrs) = res {
+            return Ok(addrs);
+        }
+    }
+}
+
+fn parse_answers(msg: &[u8], id: u16, name: &str) -> Vec<IpAddress, MAX_RESOLVE_RESULTS> {
+    let mut addrs = Vec::new();
+    let Ok(mut r) = Reader::new(msg) else {
+        return addrs;
+    };
+    let header = *r.header();
+    if header.flags & dns_wire::FLAG_RESPONSE == 0 || header.id != id || r.skip_questions().is_err() {
+        return addrs;
+    }
+
+    for _ in 0..(header.answers as usize + header.authorities as usize + header.additionals as usize) {
+        let Ok(rec) = r.record() else { break };
+        if rec.class != dns_wire::CLASS_IN || !rec.name.eq_str(name) {
+            continue;
+        }
+        let addr = match (rec.rtype, rec.data.len()) {
+            #[cfg(feature = "proto-ipv4")]
+            (dns_wire::TYPE_A, 4) => IpAddress::Ipv4(Ipv4Address::from_bytes(rec.data)),
+            #[cfg(feature = "proto-ipv6")]
+            (dns_wire::TYPE_AAAA, 16) => IpAddress::Ipv6(crate::Ipv6Address::from_bytes(rec.data)),
+            _ => continue,
+        };
+        if !addrs.contains(&addr) && addrs.push(addr).is_err() {
+            break;
+        }
+    }
+    addrs
+}
+
+#[cfg(all(test, feature = "medium-ip", feature = "proto-ipv4"))]
+mod tests {
+    extern crate std;
+    use std::vec::Vec as StdVec;
+
+    use super::*;
+    use crate::testing::{buffer, stack, NoDevice};
+    use crate::udp::PacketMetadata;
+    use crate::{Ipv4Cidr, StaticConfigV4};
+
+    const ADDR: Ipv4Address = Ipv4Address([192, 0, 2, 10]);
+    const PEER: Ipv4Address = Ipv4Address([192, 0, 2, 20]);
+    const SERVICES: &[Service] = &[Service {
+        instance: "Kitchen sensor",
+        service_type: "_http._tcp",
+        port: 80,
+        txt: &["path=/"],
+    }];
+    const FLUSH: u16 = dns_wire::CLASS_IN | CACHE_FLUSH;
+
+    fn responder(
+        hostname: &'static str,
+        services: &'static [Service<'static>],
+    ) -> Result<Responder<'static, 'static, NoDevice>, Error> {
+        let stack = stack(
+            1500,
+            crate::Config::ipv4_static(StaticConfigV4 {
+                address: Ipv4Cidr::new(ADDR, 24),
+                gateway: None,
+                dns_servers: Vec::new(),
+            }),
+        );
+        let socket = UdpSocket::new(
+            stack,
+            buffer(PacketMetadata::EMPTY, 1),
+            buffer(0, 64),
+            buffer(PacketMetadata::EMPTY, 1),
+            buffer(0, 64),
+        );
+        Responder::new(stack, socket, Config::new(hostname, services))
+    }
+
+    /// Query for `name`, with the unicast-response bit set if `qu`.
+    fn query(id: u16, name: &str, qtype: u16, qu: bool) -> StdVec<u8> {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let mut w = Writer::new(&mut buf, id, 0).unwrap();
+        // The unicast-response bit is the top bit of the class.
+        w.question(name, qtype, if qu { 0x8000 } else { 0 } | dns_wire::CLASS_IN)
+            .unwrap();
+        let len = w.finish();
+        buf[..len].to_vec()
+    }
+
+    struct Response {
+        id: u16,
+        questions: u16,
+        /// Type of the answers, and whether their cache-flush bit is set.
+        answers: StdVec<(u16, bool)>,
+        dest: IpEndpoint,
+    }
+
+    fn respond(r: &Responder<'_, '_, NoDevice>, msg: &[u8], src: IpEndpoint) -> Option<Response> {
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        let (len, dest) = r.respond(msg, src, &mut tx)?;
+        let mut reader = Reader::new(&tx[..len]).unwrap();
+        let header = *reader.header();
+        assert_eq!(header.flags, dns_wire::FLAG_RESPONSE | dns_wire::FLAG_AUTHORITATIVE);
+        reader.skip_questions().unwrap();
+        let answers = (0..header.answers)
+            .map(|_| {
+                let rec = reader.record().unwrap();
+                assert_eq!(rec.class, dns_wire::CLASS_IN);
+                (rec.rtype, rec.cache_flush)
+            })
+            .collect();
+        Some(Response {
+            id: header.id,
+            questions: header.questions,
+            answers,
+            dest,
+        })
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(responder("device", SERVICES).is_ok());
+        assert_eq!(responder("", &[]).err(), Some(Error::InvalidName));
+        assert_eq!(responder("my.device", &[]).err(), Some(Error::InvalidName));
+        let long = "a123456789b123456789c123456789d123456789e123456789f123456789g1234";
+        assert_eq!(responder(long, &[]).err(), Some(Error::InvalidName));
+        const UNNAMED: &[Service] = &[Service {
+            instance: "",
+            service_type: "_http._tcp",
+            port: 80,
+            txt: &[],
+        }];
+        assert_eq!(responder("device", UNNAMED).err(), Some(Error::InvalidName));
+    }
+
+    #[test]
+    fn host_query() {
+        let r = responder("device", SERVICES).unwrap();
+        let mdns = IpEndpoint::new(PEER.into(), MDNS_PORT);
+        let group = IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT);
+
+        // Multicast query, multicast answer.
+        let res = respond(&r, &query(0x1234, "Device.local", dns_wire::TYPE_A, false), mdns).unwrap();
+        assert_eq!((res.id, res.questions, res.dest), (0, 0, group));
+        assert_eq!(res.answers, [(dns_wire::TYPE_A, true)]);
+
+        // Unicast response requested.
+        let res = respond(&r, &query(0x1234, "device.local", dns_wire::TYPE_ANY, true), mdns).unwrap();
+        assert_eq!(res.dest, mdns);
+        assert_eq!(res.answers[0], (dns_wire::TYPE_A, true));
+
+        // Legacy unicast query: ID and question echoed, no cache-flush bit.
+        let legacy = IpEndpoint::new(PEER.into(), 40000);
+        let res = respond(&r, &query(0x1234, "device.local", dns_wire::TYPE_A, false), legacy).unwrap();
+        assert_eq!((res.id, res.questions, res.dest), (0x1234, 1, legacy));
+        assert_eq!(res.answers, [(dns_wire::TYPE_A, false)]);
+
+        // Other names and types, and responses, are not answered.
+        assert!(respond(&r, &query(0, "other.local", dns_wire::TYPE_A, false), mdns).is_none());
+        assert!(respond(&r, &query(0, "device.local", dns_wire::TYPE_TXT, false), mdns).is_none());
+        let mut response = query(0, "device.local", dns_wire::TYPE_A, false);
+        response[2] |= 0x80;
+        assert!(respond(&r, &response, mdns).is_none());
+    }
+
+    #[test]
+    fn service_query() {
+        let r = responder("device", SERVICES).unwrap();
+        let mdns = IpEndpoint::new(PEER.into(), MDNS_PORT);
+
+        // Shared PTR records don't have the cache-flush bit.
+        let res = respond(&r, &query(0, "_http._tcp.local", dns_wire::TYPE_PTR, false), mdns).unwrap();
+        assert_eq!(
+            res.answers,
+            [
+                (dns_wire::TYPE_PTR, false),
+                (dns_wire::TYPE_SRV, true),
+                (dns_wire::TYPE_TXT, true)
+            ]
+        );
+
+        let res = respond(&r, &query(0, SERVICES_META_QUERY, dns_wire::TYPE_PTR, false), mdns).unwrap();
+        assert_eq!(res.answers, [(dns_wire::TYPE_PTR, false)]);
+
+        let instance = "Kitchen sensor._http._tcp.local";
+        let res = respond(&r, &query(0, instance, dns_wire::TYPE_SRV, false), mdns).unwrap();
+        assert_eq!(res.answers, [(dns_wire::TYPE_SRV, true)]);
+        let res = respond(&r, &query(0, instance, dns_wire::TYPE_ANY, false), mdns).unwrap();
+        assert_eq!(res.answers, [(dns_wire::TYPE_SRV, true), (dns_wire::TYPE_TXT, true)]);
+
+        assert!(respond(&r, &query(0, "_ipp._tcp.local", dns_wire::TYPE_PTR, false), mdns).is_none());
+        assert!(respond(&r, &query(0, "Other._http._tcp.local", dns_wire::TYPE_SRV, false), mdns).is_none());
+    }
+
+    /// Response with the given records, as `(name, type, class, data)`.
+    fn response(id: u16, records: &[(&str, u16, u16, &[u8])]) -> StdVec<u8> {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let mut w = Writer::new(&mut buf, id, dns_wire::FLAG_RESPONSE).unwrap();
+        for &(name, rtype, class, data) in records {
+            w.name(name).unwrap();
+            w.record_data(rtype, class, 120, |w| data.iter().try_for_each(|b| w.u8(*b)))
+                .unwrap();
+            w.answer_added();
+        }
+        let len = w.finish();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn answers() {
+        let msg = response(
+            7,
+            &[
+                ("other.local", dns_wire::TYPE_A, FLUSH, &[192, 0, 2, 1]),
+                ("peer.local", dns_wire::TYPE_A, FLUSH, &PEER.0),
+                ("peer.local", dns_wire::TYPE_A, dns_wire::CLASS_IN, &PEER.0),
+                ("Peer.local", dns_wire::TYPE_A, dns_wire::CLASS_IN, &ADDR.0),
+                ("peer.local", dns_wire::TYPE_A, dns_wire::CLASS_IN, &[1, 2, 3]),
+                ("peer.local", dns_wire::TYPE_TXT, dns_wire::CLASS_IN, b"\x03a=b"),
+            ],
+        );
+        assert_eq!(&parse_answers(&msg, 7, "peer.local")[..], [PEER.into(), ADDR.into()]);
+        // Answer to another query.
+        assert!(parse_answers(&msg, 8, "peer.local").is_empty());
+        assert!(parse_answers(&msg[..20], 7, "peer.local").is_empty());
+    }
+}
@@ -0,0 +1,968 @@
+//! MQTT v3.1.1 and v5 client.
+//!
+//! The [`Client`] runs over any `embedded-io-async` stream, usually a [`TcpSocket`](crate::tcp::TcpSocket)
+//! or a [`TlsSocket`](crate::tls::TlsSocket) for MQTTS. It supports QoS 0, 1 and 2 in both
+//! directions, retained messages and will messages.
+//!
+//! The client doesn't spawn anything: [`Client::next_event`] must be called continuously, as it
+//! receives messages and acknowledgements, and also sends the keep-alive pings. Publishing and
+//! subscribing return a packet identifier immediately, the matching acknowledgement is reported
+//! later as an [`Event`].
+//!
+//! With MQTT v5, the only property sent is the session expiry interval set in the [`Config`], and
+//! received properties are ignored. Failure reason codes in publish acknowledgements are reported
+//! as [`Event::PublishRejected`].
+
+use core::ops::Range;
+
+use embassy_time::{with_deadline, with_timeout, Duration, Instant};
+use embedded_io_async::{ErrorKind, Read, Write};
+use heapless::Vec;
+
+/// Maximum number of incoming QoS 2 messages awaiting release.
+pub const MAX_INCOMING_QOS2: usize = 8;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Room reserved in front of each outgoing packet for its fixed header.
+const HEADER_ROOM: usize = 5;
+
+/// MQTT protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolVersion {
+    /// MQTT v3.1.1
+    V311,
+    /// MQTT v5
+    V5,
+}
+
+/// Quality of service of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QoS {
+    /// Delivered at most once, without acknowledgement.
+    AtMostOnce = 0,
+    /// Delivered at least once.
+    AtLeastOnce = 1,
+    /// Delivered exactly once.
+    ExactlyOnce = 2,
+}
+
+impl QoS {
+    fn from_bits(bits: u8) -> Result<Self, Error> {
+        match bits {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            _ => Err(Error::Protocol),
+        }
+    }
+}
+
+/// Errors returned by the MQTT client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The transport returned an error.
+    Io(ErrorKind),
+    /// The connection was closed.
+    ConnectionClosed,
+    /// The server refused the connection, with the given return or reason code.
+    ConnectionRefused(u8),
+    /// The server closed the session with a DISCONNECT packet (MQTT v5), with the given reason code.
+    Disconnected(u8),
+    /// The server sent an invalid packet.
+    Protocol,
+    /// A packet doesn't fit in the buffers.
+    BufferTooSmall,
+    /// The server didn't answer in time.
+    Timeout,
+    /// Too many incoming QoS 2 messages are awaiting release.
+    TooManyInflight,
+}
+
+impl<E: embedded_io_async::Error> From<E> for Error {
+    fn from(e: E) -> Self {
+        Error::Io(e.kind())
+    }
+}
+
+/// Will message, published by the server if the client disconnects unexpectedly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Will<'a> {
+    /// Topic of the message.
+    pub topic: &'a str,
+    /// Payload of the message.
+    pub payload: &'a [u8],
+    /// Quality of service of the message.
+    pub qos: QoS,
+    /// Whether the message is retained.
+    pub retain: bool,
+}
+
+/// MQTT client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Protocol version.
+    pub version: ProtocolVersion,
+    /// Client identifier. May be empty if the server assigns identifiers.
+    pub client_id: &'a str,
+    /// User name.
+    pub username: Option<&'a str>,
+    /// Password.
+    pub password: Option<&'a [u8]>,
+    /// Keep-alive interval. A ping is sent if nothing else was sent for this long. Zero disables it.
+    pub keep_alive: Duration,
+    /// Start a new session instead of resuming the existing one.
+    pub clean_session: bool,
+    /// Session expiry interval in seconds, after the connection closes (MQTT v5 only).
+    pub session_expiry: u32,
+    /// Will message.
+    pub will: Option<Will<'a>>,
+    /// Time to wait for the server to accept the connection.
+    pub connect_timeout: Duration,
+}
+
+impl<'a> Config<'a> {
+    /// Create an MQTT v3.1.1 configuration with a clean session and a keep-alive of 60 seconds.
+    pub fn new(client_id: &'a str) -> Self {
+        Self {
+            version: ProtocolVersion::V311,
+            client_id,
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(60),
+            clean_session: true,
+            session_expiry: 0,
+            will: None,
+            connect_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message<'a> {
+    /// Topic of the message.
+    pub topic: &'a str,
+    /// Payload of the message.
+    pub payload: &'a [u8],
+    /// Quality of service the message was delivered with.
+    pub qos: QoS,
+    /// Whether this is a retained message, sent because of a new subscription.
+    pub retain: bool,
+}
+
+impl<'a> Message<'a> {
+    /// Get whether the message topic matches a topic filter.
+    pub fn matches(&self, filter: &str) -> bool {
+        topic_matches(filter, self.topic)
+    }
+}
+
+/// Event returned by [`Client::next_event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event<'a> {
+    /// A message was received.
+    Message(Message<'a>),
+    /// A QoS 1 or 2 message was acknowledged by the server.
+    Published(u16),
+    /// A QoS 1 or 2 message was rejected by the server (MQTT v5).
+    PublishRejected {
+        /// Packet identifier returned by [`Client::publish`].
+        packet_id: u16,
+        /// Reason code, `0x80` or above.
+        reason_code: u8,
+    },
+    /// A subscription was acknowledged.
+    ///
+    /// `return_codes` has one entry per topic filter: the granted QoS (0 to 2), or a failure code
+    /// (`0x80` or above).
+    Subscribed {
+        /// Packet identifier returned by [`Client::subscribe`].
+        packet_id: u16,
+        /// Return codes, one per topic filter.
+        return_codes: &'a [u8],
+    },
+    /// An unsubscription was acknowledged.
+    Unsubscribed(u16),
+}
+
+/// Check whether `topic` matches the topic filter `filter`, which can contain `+` and `#` wildcards.
+///
+/// Topics starting with `$` are not matched by filters starting with a wildcard.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+struct Encoder<'b> {
+    buf: &'b mut [u8],
+    pos: usize,
+}
+
+impl<'b> Encoder<'b> {
+    fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, pos: HEADER_ROOM }
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.pos + data.len();
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, v: u8) -> Result<(), Error> {
+        self.bytes(&[v])
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Result<(), Error> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    /// Length-prefixed binary data or string.
+    fn data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let len = u16::try_from(data.len()).map_err(|_| Error::BufferTooSmall)?;
+        self.u16(len)?;
+        self.bytes(data)
+    }
+
+    /// Write the fixed header in front of the packet, and return the packet.
+    fn finish(self, header: u8) -> Result<&'b [u8], Error> {
+        let mut len = self.pos - HEADER_ROOM;
+        let mut varint = [0u8; 4];
+        let mut n = 0;
+        loop {
+            let mut byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            *varint.get_mut(n).ok_or(Error::BufferTooSmall)? = byte;
+            n += 1;
+            if len == 0 {
+                break;
+            }
+        }
+
+        let start = HEADER_ROOM - 1 - n;
+        self.buf[start] = header;
+        self.buf[start + 1..HEADER_ROOM].copy_from_slice(&varint[..n]);
+        Ok(&self.buf[start..self.pos])
+    }
+}
+
+struct Decoder<'b> {
+    buf: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Decoder<'b> {
+    fn new(buf: &'b [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'b [u8], Error> {
+        let data = self.buf.get(self.pos..self.pos + len).ok_or(Error::Protocol)?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut value = 0;
+        for i in 0..4 {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Protocol)
+    }
+
+    fn str(&mut self) -> Result<&'b str, Error> {
+        let len = self.u16()? as usize;
+        core::str::from_utf8(self.bytes(len)?).map_err(|_| Error::Protocol)
+    }
+
+    fn skip_properties(&mut self, version: ProtocolVersion) -> Result<(), Error> {
+        if version == ProtocolVersion::V5 {
+            let len = self.varint()?;
+            self.bytes(len)?;
+        }
+        Ok(())
+    }
+
+    /// Reason code of an acknowledgement, which is omitted for success in MQTT v5.
+    fn reason_code(&mut self, version: ProtocolVersion) -> u8 {
+        match version {
+            ProtocolVersion::V5 => self.u8().unwrap_or(0),
+            ProtocolVersion::V311 => 0,
+        }
+    }
+
+    fn rest(&mut self) -> &'b [u8] {
+        let data = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        data
+    }
+}
+
+/// Parse the fixed header at the start of `buf`.
+///
+/// Returns the header byte, the header length and the remaining length, or `None` if the header
+/// is not complete.
+fn parse_header(buf: &[u8]) -> Result<Option<(u8, usize, usize)>, Error> {
+    let mut len = 0;
+    for i in 0..4 {
+        let Some(&byte) = buf.get(1 + i) else {
+            return Ok(None);
+        };
+        len |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((buf[0], 2 + i, len)));
+        }
+    }
+    Err(Error::Protocol)
+}
+
+/// MQTT client.
+pub struct Client<'b, T: Read + Write> {
+    transport: T,
+    version: ProtocolVersion,
+    keep_alive: Duration,
+    rx_buffer: &'b mut [u8],
+    tx_buffer: &'b mut [u8],
+    rx_len: usize,
+    /// Length of the packet returned by the last call to `next_event`, removed on the next call.
+    consumed: usize,
+    next_packet_id: u16,
+    last_sent: Instant,
+    ping_sent: Option<Instant>,
+    incoming_qos2: Vec<u16, MAX_INCOMING_QOS2>,
+}
+
+impl<'b, T: Read + Write> Client<'b, T> {
+    /// Connect to the server over `transport`, which must be connected already.
+    ///
+    /// `rx_buffer` must be able to hold the largest packet received, `tx_buffer` the largest packet
+    /// sent. Returns the client, and whether the server resumed an existing session.
+    pub async fn connect(
+        transport: T,
+        config: &Config<'_>,
+        rx_buffer: &'b mut [u8],
+        tx_buffer: &'b mut [u8],
+    ) -> Result<(Self, bool), Error> {
+        let mut client = Self {
+            transport,
+            version: config.version,
+            keep_alive: config.keep_alive,
+            rx_buffer,
+            tx_buffer,
+            rx_len: 0,
+            consumed: 0,
+            next_packet_id: 1,
+            last_sent: Instant::now(),
+            ping_sent: None,
+            incoming_qos2: Vec::new(),
+        };
+
+        client.send_connect(config).await?;
+
+        let (header, body) = with_timeout(config.connect_timeout, client.read_packet())
+            .await
+            .map_err(|_| Error::Timeout)??;
+        if header >> 4 != CONNACK {
+            return Err(Error::Protocol);
+        }
+        let mut d = Decoder::new(&client.rx_buffer[body]);
+        let session_present = d.u8()? & 0x01 != 0;
+        let code = d.u8()?;
+        if code != 0 {
+            warn!("mqtt: connection refused: {}", code);
+            return Err(Error::ConnectionRefused(code));
+        }
+
+        debug!("mqtt: connected, session present: {}", session_present);
+        Ok((client, session_present))
+    }
+
+    async fn send_connect(&mut self, config: &Config<'_>) -> Result<(), Error> {
+        let mut e = Encoder::new(self.tx_buffer);
+        e.data(b"MQTT")?;
+        e.u8(match config.version {
+            ProtocolVersion::V311 => 4,
+            ProtocolVersion::V5 => 5,
+        })?;
+
+        let mut flags = 0;
+        if config.clean_session {
+            flags |= 0x02;
+        }
+        if let Some(will) = &config.will {
+            flags |= 0x04 | (will.qos as u8) << 3;
+            if will.retain {
+                flags |= 0x20;
+            }
+        }
+        if config.password.is_some() {
+            flags |= 0x40;
+        }
+        if config.username.is_some() {
+            flags |= 0x80;
+        }
+        e.u8(flags)?;
+        e.u16(config.keep_alive.as_secs().min(u16::MAX as u64) as u16)?;
+
+        if config.version == ProtocolVersion::V5 {
+            if config.session_expiry != 0 {
+                // Session Expiry Interval property.
+                e.u8(5)?;
+                e.u8(0x11)?;
+                e.u32(config.session_expiry)?;
+            } else {
+                e.u8(0)?;
+            }
+        }
+
+        e.data(config.client_id.as_bytes())?;
+        if let Some(will) = &config.will {
+            if config.version == ProtocolVersion::V5 {
+                e.u8(0)?;
+            }
+            e.data(will.topic.as_bytes())?;
+            e.data(will.payload)?;
+        }
+        if let Some(username) = config.username {
+            e.data(username.as_bytes())?;
+        }
+        if let Some(password) = config.password {
+            e.data(password)?;
+        }
+
+        let packet = e.finish(CONNECT << 4)?;
+        Self::send(&mut self.transport, &mut self.last_sent, packet).await
+    }
+
+    async fn send(transport: &mut T, last_sent: &mut Instant, packet: &[u8]) -> Result<(), Error> {
+        transport.write_all(packet).await?;
+        transport.flush().await?;
+        *last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    /// Publish a message.
+    ///
+    /// For QoS 1 and 2, returns the packet identifier, and [`Event::Published`] is returned by
+    /// [`Client::next_event`] once the server acknowledged the message, or
+    /// [`Event::PublishRejected`] if it refused it.
+    pub async fn publish(&mut self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<Option<u16>, Error> {
+        let packet_id = match qos {
+            QoS::AtMostOnce => None,
+            _ => Some(self.packet_id()),
+        };
+
+        let mut e = Encoder::new(self.tx_buffer);
+        e.data(topic.as_bytes())?;
+        if let Some(id) = packet_id {
+            e.u16(id)?;
+        }
+        if self.version == ProtocolVersion::V5 {
+            e.u8(0)?;
+        }
+        e.bytes(payload)?;
+
+        let header = PUBLISH << 4 | (qos as u8) << 1 | retain as u8;
+        let packet = e.finish(header)?;
+        Self::send(&mut self.transport, &mut self.last_sent, packet).await?;
+        Ok(packet_id)
+    }
+
+    /// Subscribe to topic filters, with the maximum QoS for each of them.
+    ///
+    /// Returns the packet identifier. [`Event::Subscribed`] is returned by [`Client::next_event`]
+    /// once the server acknowledged the subscription.
+    pub async fn subscribe(&mut self, filters: &[(&str, QoS)]) -> Result<u16, Error> {
+        let packet_id = self.packet_id();
+
+        let mut e = Encoder::new(self.tx_buffer);
+        e.u16(packet_id)?;
+        if self.version == ProtocolVersion::V5 {
+            e.u8(0)?;
+        }
+        for (filter, qos) in filters {
+            e.data(filter.as_bytes())?;
+            e.u8(*qos as u8)?;
+        }
+
+        let packet = e.finish(SUBSCRIBE << 4 | 0x02)?;
+        Self::send(&mut self.transport, &mut self.last_sent, packet).await?;
+        Ok(packet_id)
+    }
+
+    /// Unsubscribe from topic filters.
+    ///
+    /// Returns the packet identifier. [`Event::Unsubscribed`] is returned by [`Client::next_event`]
+    /// once the server acknowledged it.
+    pub async fn unsubscribe(&mut self, filters: &[&str]) -> Result<u16, Error> {
+        let packet_id = self.packet_id();
+
+        let mut e = Encoder::new(self.tx_buffer);
+        e.u16(packet_id)?;
+        if self.version == ProtocolVersion::V5 {
+            e.u8(0)?;
+        }
+        for filter in filters {
+            e.data(filter.as_bytes())?;
+        }
+
+        let packet = e.finish(UNSUBSCRIBE << 4 | 0x02)?;
+        Self::send(&mut self.transport, &mut self.last_sent, packet).await?;
+        Ok(packet_id)
+    }
+
+    /// Disconnect cleanly, and give the transport back.
+    ///
+    /// The will message is not published.
+    pub async fn disconnect(mut self) -> Result<T, Error> {
+        Self::send(&mut self.transport, &mut self.last_sent, &[DISCONNECT << 4, 0]).await?;
+        Ok(self.transport)
+    }
+
+    async fn send_ack(&mut self, kind: u8, packet_id: u16) -> Result<(), Error> {
+        // PUBREL has reserved flags set to 0b0010.
+        let flags = if kind == PUBREL { 0x02 } else { 0 };
+        let id = packet_id.to_be_bytes();
+        Self::send(
+            &mut self.transport,
+            &mut self.last_sent,
+            &[kind << 4 | flags, 2, id[0], id[1]],
+        )
+        .await
+    }
+
+    /// Read a complete packet into the start of the receive buffer. Returns the header byte and
+    /// the range of the body.
+    ///
+    /// This is cancel-safe: partially received packets are kept in the buffer.
+    async fn read_packet(&mut self) -> Result<(u8, Range<usize>), Error> {
+        if self.consumed != 0 {
+            self.rx_buffer.copy_within(self.consumed..self.rx_len, 0);
+            self.rx_len -= self.consumed;
+            self.consumed = 0;
+        }
+
+        loop {
+            if let Some((header, header_len, len)) = parse_header(&self.rx_buffer[..self.rx_len])? {
+                let total = header_len + len;
+                if total > self.rx_buffer.len() {
+                    return Err(Error::BufferTooSmall);
+                }
+                if self.rx_len >= total {
+                    self.consumed = total;
+                    return Ok((header, header_len..total));
+                }
+            }
+
+            let n = self.transport.read(&mut self.rx_buffer[self.rx_len..]).await?;
+            if n == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            self.rx_len += n;
+        }
+    }
+
+    /// Wait for the next event, and send keep-alive pings in the meantime.
+    ///
+    /// Incoming QoS 1 and 2 messages are acknowledged before they are returned.
+    pub async fn next_event(&mut self) -> Result<Event<'_>, Error> {
+        loop {
+            // Wait for a packet, or for the keep-alive deadline.
+            // A keep-alive of zero disables pings.
+            let deadline = match self.ping_sent {
+                _ if self.keep_alive == Duration::from_ticks(0) => Instant::MAX,
+                Some(sent) => sent + self.keep_alive,
+                None => self.last_sent + self.keep_alive,
+            };
+            let (header, body) = match with_deadline(deadline, self.read_packet()).await {
+                Ok(res) => res?,
+                Err(_) => {
+                    if self.ping_sent.is_some() {
+                        warn!("mqtt: no ping response");
+                        return Err(Error::Timeout);
+                    }
+                    trace!("mqtt: ping");
+                    Self::send(&mut self.transport, &mut self.last_sent, &[PINGREQ << 4, 0]).await?;
+                    self.ping_sent = Some(Instant::now());
+                    continue;
+                }
+            };
+
+            if self.handle(header, body.clone()).await? {
+                return self.event(header, body);
+            }
+        }
+    }
+
+    /// Process a received packet, sending the required acknowledgements.
+    ///
+    /// Returns whether the packet results in an event.
+    async fn handle(&mut self, header: u8, body: Range<usize>) -> Result<bool, Error> {
+        let kind = header >> 4;
+        match kind {
+            PINGRESP => {
+                self.ping_sent = None;
+                Ok(false)
+            }
+            PUBLISH => {
+                let qos = QoS::from_bits((header >> 1) & 0x03)?;
+                let mut d = Decoder::new(&self.rx_buffer[body]);
+                d.str()?;
+                let packet_id = match qos {
+                    QoS::AtMostOnce => None,
+                    _ => Some(d.u16()?),
+                };
+                match (qos, packet_id) {
+                    (QoS::AtLeastOnce, Some(id)) => {
+                        self.send_ack(PUBACK, id).await?;
+                        Ok(true)
+                    }
+                    (QoS::ExactlyOnce, Some(id)) => {
+                        // Deliver the message only the first time it's received. It's tracked before
+                        // being acknowledged, as the server doesn't send it again after PUBREC.
+                        let new = !self.incoming_qos2.contains(&id);
+                        if new {
+                            self.incoming_qos2.push(id).map_err(|_| Error::TooManyInflight)?;
+                        }
+                        if let Err(e) = self.send_ack(PUBREC, id).await {
+                            if new {
+                                self.incoming_qos2.pop();
+                            }
+                            return Err(e);
+                        }
+                        Ok(new)
+                    }
+                    _ => Ok(true),
+                }
+            }
+            PUBREC | PUBREL => {
+                let mut d = Decoder::new(&self.rx_buffer[body]);
+                let id = d.u16()?;
+                if kind == PUBREC {
+                    // A rejected message is not released, the exchange ends here.
+                    if d.reason_code(self.version) >= 0x80 {
+                        return Ok(true);
+                    }
+                    self.send_ack(PUBREL, id).await?;
+                } else {
+                    self.incoming_qos2.retain(|&i| i != id);
+                    self.send_ack(PUBCOMP, id).await?;
+                }
+                Ok(false)
+            }
+            PUBACK | PUBCOMP | SUBACK | UNSUBACK => Ok(true),
+            DISCONNECT => {
+                let code = Decoder::new(&self.rx_buffer[body]).u8().unwrap_or(0);
+                warn!("mqtt: disconnected by server: {}", code);
+                Err(Error::Disconnected(code))
+            }
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    fn event(&self, header: u8, body: Range<usize>) -> Result<Event<'_>, Error> {
+        let mut d = Decoder::new(&self.rx_buffer[body]);
+        match header >> 4 {
+            PUBLISH => {
+                let qos = QoS::from_bits((header >> 1) & 0x03)?;
+                let topic = d.str()?;
+                if qos != QoS::AtMostOnce {
+                    d.u16()?;
+                }
+                d.skip_properties(self.version)?;
+                Ok(Event::Message(Message {
+                    topic,
+                    payload: d.rest(),
+                    qos,
+                    retain: header & 0x01 != 0,
+                }))
+            }
+            PUBACK | PUBREC | PUBCOMP => {
+                let packet_id = d.u16()?;
+                match d.reason_code(self.version) {
+                    reason_code @ 0x80.. => Ok(Event::PublishRejected { packet_id, reason_code }),
+                    _ => Ok(Event::Published(packet_id)),
+                }
+            }
+            SUBACK => {
+                let packet_id = d.u16()?;
+                d.skip_properties(self.version)?;
+                Ok(Event::Subscribed {
+                    packet_id,
+                    return_codes: d.rest(),
+                })
+            }
+            UNSUBACK => Ok(Event::Unsubscribed(d.u16()?)),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// Transport returning the packets sent by the server, and recording the packets sent to it.
+    struct Script {
+        rx: Vec<u8>,
+        tx: Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for Script {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Script {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.rx.len());
+            buf[..n].copy_from_slice(&self.rx[..n]);
+            self.rx.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Script {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Connect, run `f` with the client, and return the packets it sent after CONNECT.
+    fn run(version: ProtocolVersion, server: &[u8], f: impl FnOnce(&mut Client<'_, Script>)) -> Vec<u8> {
+        let mut rx = match version {
+            ProtocolVersion::V311 => b"\x20\x02\x00\x00".to_vec(),
+            ProtocolVersion::V5 => b"\x20\x03\x00\x00\x00".to_vec(),
+        };
+        rx.extend_from_slice(server);
+        let transport = Script { rx, tx: Vec::new() };
+
+        let mut config = Config::new("test");
+        config.version = version;
+        let (mut rx_buffer, mut tx_buffer) = ([0; 64], [0; 64]);
+        let (mut client, _) = block_on(Client::connect(transport, &config, &mut rx_buffer, &mut tx_buffer)).unwrap();
+        let connect_len = client.transport().tx.len();
+        f(&mut client);
+        client.transport().tx.split_off(connect_len)
+    }
+
+    #[test]
+    fn topic_filters() {
+        assert!(topic_matches("a/b/c", "a/b/c"));
+        assert!(!topic_matches("a/b/c", "a/b"));
+        assert!(!topic_matches("a/b", "a/b/c"));
+        assert!(topic_matches("a/+/c", "a/b/c"));
+        assert!(topic_matches("a/+", "a/"));
+        assert!(!topic_matches("a/+", "a/b/c"));
+        assert!(topic_matches("a/#", "a/b/c"));
+        assert!(topic_matches("a/#", "a"));
+        assert!(topic_matches("#", "a/b"));
+        assert!(topic_matches("+/+", "/b"));
+        assert!(!topic_matches("#", "$SYS/uptime"));
+        assert!(!topic_matches("+/uptime", "$SYS/uptime"));
+        assert!(topic_matches("$SYS/#", "$SYS/uptime"));
+    }
+
+    #[test]
+    fn encode_packet() {
+        let mut buf = [0; 32];
+        let mut e = Encoder::new(&mut buf);
+        e.data(b"a/b").unwrap();
+        e.u16(0x1234).unwrap();
+        e.bytes(b"hi").unwrap();
+        let packet = e.finish(0x32).unwrap();
+        assert_eq!(packet, b"\x32\x09\x00\x03a/b\x12\x34hi");
+    }
+
+    #[test]
+    fn encode_long_packet() {
+        let mut buf = [0; 300];
+        let mut e = Encoder::new(&mut buf);
+        e.bytes(&[0xaa; 200]).unwrap();
+        let packet = e.finish(0x30).unwrap();
+        // 200 = 0x48 + 1 * 128.
+        assert_eq!(&packet[..3], [0x30, 0xc8, 0x01]);
+        assert_eq!(packet.len(), 203);
+    }
+
+    #[test]
+    fn encode_buffer_too_small() {
+        let mut buf = [0; HEADER_ROOM + 2];
+        let mut e = Encoder::new(&mut buf);
+        e.u16(1).unwrap();
+        assert_eq!(e.u8(2), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn decode_fields() {
+        let mut d = Decoder::new(b"\x07\x12\x34\x00\x03a/b\xc8\x01rest");
+        assert_eq!(d.u8(), Ok(7));
+        assert_eq!(d.u16(), Ok(0x1234));
+        assert_eq!(d.str(), Ok("a/b"));
+        assert_eq!(d.varint(), Ok(200));
+        assert_eq!(d.rest(), b"rest");
+        assert_eq!(d.u8(), Err(Error::Protocol));
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert_eq!(Decoder::new(b"\x00\x05abc").str(), Err(Error::Protocol));
+        assert_eq!(Decoder::new(b"\x00\x02\xff\xfe").str(), Err(Error::Protocol));
+        assert_eq!(Decoder::new(b"\xff\xff\xff\xff\x01").varint(), Err(Error::Protocol));
+    }
+
+    #[test]
+    fn decode_properties() {
+        let mut d = Decoder::new(b"\x02\x01\x01\x42");
+        d.skip_properties(ProtocolVersion::V5).unwrap();
+        assert_eq!(d.u8(), Ok(0x42));
+
+        let mut d = Decoder::new(b"\x42");
+        d.skip_properties(ProtocolVersion::V311).unwrap();
+        assert_eq!(d.u8(), Ok(0x42));
+    }
+
+    #[test]
+    fn fixed_header() {
+        assert_eq!(parse_header(b"\x30"), Ok(None));
+        assert_eq!(parse_header(b"\x30\xc8"), Ok(None));
+        assert_eq!(parse_header(b"\x30\xc8\x01"), Ok(Some((0x30, 3, 200))));
+        assert_eq!(parse_header(b"\xd0\x00"), Ok(Some((0xd0, 2, 0))));
+        assert_eq!(parse_header(b"\x30\xff\xff\xff\xff"), Err(Error::Protocol));
+    }
+
+    #[test]
+    fn publish_acknowledged() {
+        let server = b"\x40\x02\x00\x01\x50\x02\x00\x02\x70\x02\x00\x02";
+        let sent = run(ProtocolVersion::V311, server, |client| {
+            assert_eq!(block_on(client.next_event()), Ok(Event::Published(1)));
+            assert_eq!(block_on(client.next_event()), Ok(Event::Published(2)));
+        });
+        // PUBREL for the PUBREC.
+        assert_eq!(sent, b"\x62\x02\x00\x02");
+    }
+
+    #[test]
+    fn publish_rejected() {
+        // PUBACK with "not authorized", PUBACK with "no matching subscribers" then "success" without
+        // reason code, PUBREC with "quota exceeded".
+        let server = b"\x40\x03\x00\x01\x87\x40\x03\x00\x02\x10\x40\x02\x00\x03\x50\x04\x00\x04\x97\x00";
+        let sent = run(ProtocolVersion::V5, server, |client| {
+            let rejected = Event::PublishRejected {
+                packet_id: 1,
+                reason_code: 0x87,
+            };
+            assert_eq!(block_on(client.next_event()), Ok(rejected));
+            assert_eq!(block_on(client.next_event()), Ok(Event::Published(2)));
+            assert_eq!(block_on(client.next_event()), Ok(Event::Published(3)));
+            let rejected = Event::PublishRejected {
+                packet_id: 4,
+                reason_code: 0x97,
+            };
+            assert_eq!(block_on(client.next_event()), Ok(rejected));
+        });
+        // The rejected QoS 2 message is not released.
+        assert_eq!(sent, b"");
+    }
+
+    #[test]
+    fn incoming_qos2() {
+        let mut server = Vec::new();
+        for id in 1..=MAX_INCOMING_QOS2 as u8 {
+            server.extend_from_slice(&[0x34, 5, 0, 1, b'a', 0, id]);
+        }
+        // Duplicate of the first message, then one more message.
+        server.extend_from_slice(&[0x3c, 5, 0, 1, b'a', 0, 1]);
+        server.extend_from_slice(&[0x34, 5, 0, 1, b'a', 0, 0x42]);
+
+        let sent = run(ProtocolVersion::V311, &server, |client| {
+            for _ in 0..MAX_INCOMING_QOS2 {
+                assert!(matches!(block_on(client.next_event()), Ok(Event::Message(_))));
+            }
+            assert_eq!(block_on(client.next_event()), Err(Error::TooManyInflight));
+        });
+
+        // Every tracked message, and the duplicate, is acknowledged, but not the untracked one.
+        let mut expected = Vec::new();
+        for id in 1..=MAX_INCOMING_QOS2 as u8 {
+            expected.extend_from_slice(&[0x50, 2, 0, id]);
+        }
+        expected.extend_from_slice(&[0x50, 2, 0, 1]);
+        assert_eq!(sent, expected);
+    }
+}
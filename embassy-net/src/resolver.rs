@@ -0,0 +1,707 @@
+//! Caching DNS resolver, with failover across servers and SRV/TXT queries.
+//!
+//! [`Stack::dns_query`](crate::Stack::dns_query) is enough for one-off address lookups. The [`Resolver`]
+//! here adds what long-running devices need on top of it:
+//!
+//! - Answers are cached for their TTL, and failed lookups (nonexistent names, or names without records of
+//!   the requested type) for [`Config::negative_ttl`], so repeated lookups don't hit the network.
+//! - Queries go to the DNS servers of the stack configuration, or to the servers set with
+//!   [`Resolver::set_servers`]. When a server doesn't answer or fails, the next one is tried.
+//! - SRV and TXT records can be queried, as used by service discovery.
+//!
+//! Each query gets a random ID and is sent from a random source port, and responses are only accepted
+//! from the queried server, with the same ID and question, to make spoofing them harder.
+//!
+//! Only UDP is supported. Responses truncated by the server return [`Error::Truncated`].
+
+use embassy_net_driver::Driver;
+use embassy_time::{with_timeout, Duration, Instant};
+use heapless::{String, Vec};
+
+use crate::dns_wire::{self, Reader, Writer};
+use crate::udp::{self, UdpSocket};
+use crate::{IpAddress, IpEndpoint, IpListenEndpoint, Stack};
+
+/// DNS server port.
+pub const DNS_PORT: u16 = 53;
+/// Maximum length of DNS messages sent and received.
+pub const MAX_MESSAGE_LEN: usize = 512;
+/// Maximum number of addresses returned by [`Resolver::resolve`].
+pub const MAX_ADDRESSES: usize = 4;
+/// Maximum number of records returned by [`Resolver::query_srv`].
+pub const MAX_SRV_RECORDS: usize = 4;
+/// Maximum number of DNS servers.
+pub const MAX_SERVERS: usize = 6;
+/// Maximum length of names kept in the cache. Lookups for longer names are never cached.
+pub const MAX_CACHED_NAME_LEN: usize = 64;
+/// Maximum length of an SRV target name.
+pub const MAX_TARGET_LEN: usize = 64;
+
+const RCODE_SERVFAIL: u16 = 2;
+const SOURCE_PORT_MIN: u16 = 1025;
+
+/// Errors returned by the [`Resolver`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Binding the UDP socket failed.
+    Bind(udp::BindError),
+    /// Sending a query failed.
+    Send(udp::SendError),
+    /// The name is invalid, or too long.
+    InvalidName,
+    /// No DNS server is configured.
+    NoServers,
+    /// No server answered in time.
+    Timeout,
+    /// The name doesn't exist, or has no records of the requested type.
+    NotFound,
+    /// The last server queried failed with the given response code.
+    ServerFailure(u8),
+    /// The response was truncated by the server.
+    Truncated,
+    /// A response was malformed, or a record didn't fit in the provided buffer.
+    Malformed,
+}
+
+/// Address record type.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressType {
+    /// IPv4 addresses (`A` records).
+    #[cfg(feature = "proto-ipv4")]
+    A,
+    /// IPv6 addresses (`AAAA` records).
+    #[cfg(feature = "proto-ipv6")]
+    Aaaa,
+}
+
+impl AddressType {
+    fn record_type(self) -> u16 {
+        match self {
+            #[cfg(feature = "proto-ipv4")]
+            Self::A => dns_wire::TYPE_A,
+            #[cfg(feature = "proto-ipv6")]
+            Self::Aaaa => dns_wire::TYPE_AAAA,
+        }
+    }
+}
+
+/// Resolver configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// How long to wait for a server to answer before trying the next one.
+    pub timeout: Duration,
+    /// How many times each server is tried.
+    pub attempts: u8,
+    /// Whether to spread queries over all servers (round-robin), instead of sticking with the
+    /// server that answered last and only moving on when it fails.
+    pub rotate: bool,
+    /// How long failed lookups are cached.
+    pub negative_ttl: Duration,
+    /// Upper bound for the time answers are cached, whatever their TTL.
+    pub max_ttl: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+            attempts: 2,
+            rotate: false,
+            negative_ttl: Duration::from_secs(60),
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// A SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SrvRecord {
+    /// Priority of the target. Lower values must be tried first.
+    pub priority: u16,
+    /// Relative weight among targets with the same priority.
+    pub weight: u16,
+    /// Port of the service on the target.
+    pub port: u16,
+    /// Host name of the target.
+    pub target: String<MAX_TARGET_LEN>,
+}
+
+/// The strings of the TXT records returned by [`Resolver::query_txt`].
+#[derive(Debug, Clone)]
+pub struct TxtRecords<'b> {
+    data: &'b [u8],
+}
+
+impl<'b> Iterator for TxtRecords<'b> {
+    type Item = &'b [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&len, rest) = self.data.split_first()?;
+        let len = (len as usize).min(rest.len());
+        let (s, rest) = rest.split_at(len);
+        self.data = rest;
+        Some(s)
+    }
+}
+
+struct CacheEntry {
+    name: String<MAX_CACHED_NAME_LEN>,
+    rtype: u16,
+    /// `None` for a cached failure.
+    addrs: Option<Vec<IpAddress, MAX_ADDRESSES>>,
+    expires: Instant,
+}
+
+/// Results of address lookups, kept until they expire.
+struct Cache<const N: usize> {
+    entries: Vec<CacheEntry, N>,
+}
+
+impl<const N: usize> Cache<N> {
+    const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Get the cached result of a lookup, removing expired entries.
+    fn get(&mut self, name: &str, rtype: u16, now: Instant) -> Option<Result<Vec<IpAddress, MAX_ADDRESSES>, Error>> {
+        self.entries.retain(|e| e.expires > now);
+        let name = name.trim_end_matches('.');
+        let e = self
+            .entries
+            .iter()
+            .find(|e| e.rtype == rtype && e.name.as_str().eq_ignore_ascii_case(name))?;
+        Some(e.addrs.clone().ok_or(Error::NotFound))
+    }
+
+    /// Cache the result of a lookup: answers for their TTL, bounded by [`Config::max_ttl`], and
+    /// failures for [`Config::negative_ttl`].
+    fn insert(
+        &mut self,
+        name: &str,
+        rtype: u16,
+        res: &Result<(Vec<IpAddress, MAX_ADDRESSES>, u32), Error>,
+        now: Instant,
+        config: &Config,
+    ) {
+        let (addrs, ttl) = match res {
+            Ok((addrs, ttl)) => (
+                Some(addrs.clone()),
+                Duration::from_secs(*ttl as u64).min(config.max_ttl),
+            ),
+            Err(_) => (None, config.negative_ttl),
+        };
+        if ttl == Duration::from_ticks(0) {
+            return;
+        }
+
+        let mut key = String::new();
+        if key.push_str(name.trim_end_matches('.')).is_err() {
+            return;
+        }
+        let entry = CacheEntry {
+            name: key,
+            rtype,
+            addrs,
+            expires: now + ttl,
+        };
+        if let Err(entry) = self.entries.push(entry) {
+            // Full: replace the entry expiring first.
+            if let Some(oldest) = self.entries.iter_mut().min_by_key(|e| e.expires) {
+                *oldest = entry;
+            }
+        }
+    }
+}
+
+/// What to do with a response from a server, according to its header flags.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// The server answered, or the name doesn't exist: stop there.
+    Answered(Result<(), Error>),
+    /// The server failed, another one may do better.
+    TryNext(Error),
+    /// The query was refused by all servers.
+    Failed(Error),
+}
+
+impl Outcome {
+    /// `last` is whether the server is the last one of the current attempt.
+    fn of(flags: u16, last: bool) -> Self {
+        match flags & dns_wire::FLAG_RCODE_MASK {
+            0 if flags & dns_wire::FLAG_TRUNCATED != 0 => Self::Answered(Err(Error::Truncated)),
+            0 => Self::Answered(Ok(())),
+            dns_wire::RCODE_NXDOMAIN => Self::Answered(Err(Error::NotFound)),
+            // SERVFAIL may be temporary, and is retried on the next attempt. Other failures (REFUSED...)
+            // are not, once all servers have been tried.
+            rcode if rcode == RCODE_SERVFAIL || !last => Self::TryNext(Error::ServerFailure(rcode as u8)),
+            rcode => Self::Failed(Error::ServerFailure(rcode as u8)),
+        }
+    }
+}
+
+/// Indexes of the servers to query in turn: `attempts` times over all `count` servers, starting
+/// at `start`. Also returns whether each server is the last one of its attempt.
+fn server_order(start: usize, count: usize, attempts: u8) -> impl Iterator<Item = (usize, bool)> {
+    (0..attempts).flat_map(move |_| (0..count).map(move |k| ((start + k) % count, k + 1 == count)))
+}
+
+/// A caching DNS resolver.
+///
+/// `CACHE` is the number of lookups kept in the cache.
+pub struct Resolver<'a, D: Driver + 'static, const CACHE: usize = 8> {
+    stack: &'a Stack<D>,
+    socket: UdpSocket<'a>,
+    config: Config,
+    servers: Vec<IpAddress, MAX_SERVERS>,
+    next_server: usize,
+    random: Random,
+    cache: Cache<CACHE>,
+}
+
+impl<'a, D: Driver + 'static, const CACHE: usize> Resolver<'a, D, CACHE> {
+    /// Create a new resolver, sending queries from `socket`.
+    ///
+    /// The socket is bound again to a random port for each query, keeping the local address it is
+    /// bound to, if any. `random_seed` is used to generate the query IDs and source ports, it must
+    /// come from a proper random source, such as a hardware RNG.
+    pub fn new(stack: &'a Stack<D>, socket: UdpSocket<'a>, config: Config, random_seed: u64) -> Self {
+        Self {
+            stack,
+            socket,
+            config,
+            servers: Vec::new(),
+            next_server: 0,
+            random: Random::new(random_seed),
+            cache: Cache::new(),
+        }
+    }
+
+    /// Use the given DNS servers, instead of the ones from the stack configuration.
+    ///
+    /// Passing an empty slice goes back to the servers from the stack configuration. Servers beyond
+    /// [`MAX_SERVERS`] are ignored.
+    pub fn set_servers(&mut self, servers: &[IpAddress]) {
+        self.servers.clear();
+        for s in servers.iter().take(MAX_SERVERS) {
+            unwrap!(self.servers.push(*s).ok());
+        }
+        self.next_server = 0;
+    }
+
+    /// Remove all entries from the cache.
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Look up the addresses of `name`.
+    ///
+    /// Answers, and failures, are served from the cache while they are valid.
+    pub async fn resolve(
+        &mut self,
+        name: &str,
+        address_type: AddressType,
+    ) -> Result<Vec<IpAddress, MAX_ADDRESSES>, Error> {
+        let rtype = address_type.record_type();
+        let now = Instant::now();
+        if let Some(res) = self.cache.get(name, rtype, now) {
+            trace!("dns cache hit");
+            return res;
+        }
+
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let res = match self.exchange(name, rtype, &mut rx).await {
+            Ok(n) => parse_addresses(&rx[..n], rtype),
+            Err(Error::NotFound) => Err(Error::NotFound),
+            Err(e) => return Err(e),
+        };
+
+        self.cache.insert(name, rtype, &res, now, &self.config);
+        res.map(|(addrs, _)| addrs)
+    }
+
+    /// Query the SRV records of `name` (for example `_mqtt._tcp.example.com`).
+    ///
+    /// The records are sorted by priority, and by descending weight within the same priority.
+    /// SRV records are not cached.
+    pub async fn query_srv(&mut self, name: &str) -> Result<Vec<SrvRecord, MAX_SRV_RECORDS>, Error> {
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let n = self.exchange(name, dns_wire::TYPE_SRV, &mut rx).await?;
+        let msg = &rx[..n];
+
+        let mut r = Reader::new(msg).map_err(|_| Error::Malformed)?;
+        r.skip_questions().map_err(|_| Error::Malformed)?;
+        let mut out = Vec::<SrvRecord, MAX_SRV_RECORDS>::new();
+        for _ in 0..r.header().answers {
+            let rec = r.record().map_err(|_| Error::Malformed)?;
+            if rec.rtype != dns_wire::TYPE_SRV || rec.class != dns_wire::CLASS_IN || rec.data.len() < 7 {
+                continue;
+            }
+            let u = |i: usize| u16::from_be_bytes([rec.data[i], rec.data[i + 1]]);
+            let mut target = String::new();
+            rec.data_name(msg, 6)
+                .write_to(&mut target)
+                .map_err(|_| Error::Malformed)?;
+            let srv = SrvRecord {
+                priority: u(0),
+                weight: u(2),
+                port: u(4),
+                target,
+            };
+            if out.push(srv).is_err() {
+                warn!("dns: too many SRV records, ignoring the rest");
+                break;
+            }
+        }
+        if out.is_empty() {
+            return Err(Error::NotFound);
+        }
+        out.sort_unstable_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+        Ok(out)
+    }
+
+    /// Query the TXT records of `name`.
+    ///
+    /// The record data is copied to `buf`, and the returned iterator yields its strings, for all
+    /// records. TXT records are not cached.
+    pub async fn query_txt<'b>(&mut self, name: &str, buf: &'b mut [u8]) -> Result<TxtRecords<'b>, Error> {
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let n = self.exchange(name, dns_wire::TYPE_TXT, &mut rx).await?;
+
+        let mut r = Reader::new(&rx[..n]).map_err(|_| Error::Malformed)?;
+        r.skip_questions().map_err(|_| Error::Malformed)?;
+        let mut len = 0;
+        let mut found = false;
+        for _ in 0..r.header().answers {
+            let rec = r.record().map_err(|_| Error::Malformed)?;
+            if rec.rtype != dns_wire::TYPE_TXT || rec.class != dns_wire::CLASS_IN {
+                continue;
+            }
+            let dst = buf.get_mut(len..len + rec.data.len()).ok_or(Error::Malformed)?;
+            dst.copy_from_slice(rec.data);
+            len += rec.data.len();
+            found = true;
+        }
+        if !found {
+            return Err(Error::NotFound);
+        }
+        Ok(TxtRecords { data: &buf[..len] })
+    }
+
+    fn servers(&self) -> Vec<IpAddress, MAX_SERVERS> {
+        if !self.servers.is_empty() {
+            return self.servers.clone();
+        }
+        let mut servers = Vec::new();
+        #[cfg(feature = "proto-ipv4")]
+        if let Some(config) = self.stack.config_v4() {
+            for s in config.dns_servers {
+                let _ = servers.push(s.into());
+            }
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(config) = self.stack.config_v6() {
+            for s in config.dns_servers {
+                let _ = servers.push(s.into());
+            }
+        }
+        servers
+    }
+
+    /// Send a query to the servers in turn, until one answers. Returns the length of the response in `rx`.
+    async fn exchange(&mut self, name: &str, rtype: u16, rx: &mut [u8]) -> Result<usize, Error> {
+        let servers = self.servers();
+        if servers.is_empty() {
+            return Err(Error::NoServers);
+        }
+
+        let id = self.random.next() as u16;
+        let port = (self.random.next() % (u16::MAX - SOURCE_PORT_MIN + 1) as u64) as u16 + SOURCE_PORT_MIN;
+        let addr = self.socket.endpoint().addr;
+        self.socket.close();
+        self.socket.bind(IpListenEndpoint { addr, port }).map_err(Error::Bind)?;
+
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        let mut w = Writer::new(&mut tx, id, dns_wire::FLAG_RECURSION_DESIRED).map_err(|_| Error::InvalidName)?;
+        w.question(name, rtype, dns_wire::CLASS_IN)
+            .map_err(|_| Error::InvalidName)?;
+        let len = w.finish();
+
+        let start = self.next_server % servers.len();
+        let mut err = Error::Timeout;
+        for (i, last) in server_order(start, servers.len(), self.config.attempts) {
+            let server = IpEndpoint::new(servers[i], DNS_PORT);
+            self.socket.send_to(&tx[..len], server).await.map_err(Error::Send)?;
+
+            let res = with_timeout(self.config.timeout, async {
+                loop {
+                    let Ok((n, from)) = self.socket.recv_from(rx).await else {
+                        continue;
+                    };
+                    if from != server {
+                        continue;
+                    }
+                    if let Some(flags) = check_response(&rx[..n], id, name, rtype) {
+                        return (n, flags);
+                    }
+                }
+            })
+            .await;
+
+            let Ok((n, flags)) = res else {
+                debug!("dns: server {} timed out", server);
+                continue;
+            };
+            match Outcome::of(flags, last) {
+                Outcome::Answered(res) => {
+                    self.next_server = if self.config.rotate { i + 1 } else { i };
+                    return res.map(|_| n);
+                }
+                Outcome::TryNext(e) => {
+                    debug!("dns: server {} failed: {:?}", server, e);
+                    err = e;
+                }
+                Outcome::Failed(e) => return Err(e),
+            }
+        }
+        Err(err)
+    }
+}
+
+/// Check that `msg` is the response to our query, and return its header flags.
+fn check_response(msg: &[u8], id: u16, name: &str, rtype: u16) -> Option<u16> {
+    let mut r = Reader::new(msg).ok()?;
+    let header = *r.header();
+    if header.id != id || header.flags & dns_wire::FLAG_RESPONSE == 0 || header.questions != 1 {
+        return None;
+    }
+    let q = r.question().ok()?;
+    if !q.name.eq_str(name) || q.qtype != rtype || q.qclass != dns_wire::CLASS_IN {
+        return None;
+    }
+    Some(header.flags)
+}
+
+/// Extract the addresses of type `rtype`, and the lowest TTL among them.
+fn parse_addresses(msg: &[u8], rtype: u16) -> Result<(Vec<IpAddress, MAX_ADDRESSES>, u32), Error> {
+    let mut r = Reader::new(msg).map_err(|_| Error::Malformed)?;
+    r.skip_questions().map_err(|_| Error::Malformed)?;
+
+    let mut addrs = Vec::new();
+    let mut ttl = u32::MAX;
+    // The server answered our question, so records of the requested type are for the queried
+    // name or for the target of a CNAME in the answer.
+    for _ in 0..r.header().answers {
+        let rec = r.record().map_err(|_| Error::Malformed)?;
+        if rec.rtype != rtype || rec.class != dns_wire::CLASS_IN {
+            continue;
+        }
+        let addr: IpAddress = match rec.rtype {
+            #[cfg(feature = "proto-ipv4")]
+            dns_wire::TYPE_A if rec.data.len() == 4 => crate::Ipv4Address::from_bytes(rec.data).into(),
+            #[cfg(feature = "proto-ipv6")]
+            dns_wire::TYPE_AAAA if rec.data.len() == 16 => crate::Ipv6Address::from_bytes(rec.data).into(),
+            _ => continue,
+        };
+        ttl = ttl.min(rec.ttl);
+        if addrs.push(addr).is_err() {
+            break;
+        }
+    }
+    if addrs.is_empty() {
+        return Err(Error::NotFound);
+    }
+    Ok((addrs, ttl))
+}
+
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self(if seed == 0 { 0x853c_49e6_748f_ea9b } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4"))]
+mod tests {
+    use super::*;
+    use crate::Ipv4Address;
+
+    const ADDR: Ipv4Address = Ipv4Address([192, 0, 2, 1]);
+
+    fn addrs(addrs: &[Ipv4Address]) -> Vec<IpAddress, MAX_ADDRESSES> {
+        addrs.iter().map(|&a| a.into()).collect()
+    }
+
+    /// Response to an `A` query for `example.com` with ID 7.
+    fn response(buf: &mut [u8], flags: u16, answers: &[(u16, u32, &[u8])]) -> usize {
+        let mut w = Writer::new(buf, 7, dns_wire::FLAG_RESPONSE | flags).unwrap();
+        w.question("example.com", dns_wire::TYPE_A, dns_wire::CLASS_IN).unwrap();
+        for &(rtype, ttl, data) in answers {
+            w.name("example.com").unwrap();
+            w.record_data(rtype, dns_wire::CLASS_IN, ttl, |w| {
+                data.iter().try_for_each(|&b| w.u8(b))
+            })
+            .unwrap();
+            w.answer_added();
+        }
+        w.finish()
+    }
+
+    #[test]
+    fn cache_ttl() {
+        let config = Config {
+            max_ttl: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let mut cache = Cache::<4>::new();
+        let now = Instant::from_secs(100);
+        let a = dns_wire::TYPE_A;
+
+        cache.insert("example.com.", a, &Ok((addrs(&[ADDR]), 300)), now, &config);
+        assert_eq!(cache.get("EXAMPLE.com", a, now), Some(Ok(addrs(&[ADDR]))));
+        assert_eq!(cache.get("example.com", dns_wire::TYPE_AAAA, now), None);
+        assert_eq!(
+            cache.get("example.com", a, now + Duration::from_secs(299)),
+            Some(Ok(addrs(&[ADDR])))
+        );
+        assert_eq!(cache.get("example.com", a, now + Duration::from_secs(300)), None);
+        assert!(cache.entries.is_empty());
+
+        // The TTL is bounded, and failures are cached for the negative TTL.
+        cache.insert("long.example", a, &Ok((addrs(&[ADDR]), 86400)), now, &config);
+        cache.insert("missing.example", a, &Err(Error::NotFound), now, &config);
+        let later = now + Duration::from_secs(60);
+        assert_eq!(
+            cache.get("long.example", a, later - Duration::from_secs(1)),
+            Some(Ok(addrs(&[ADDR])))
+        );
+        assert_eq!(
+            cache.get("missing.example", a, later - Duration::from_secs(1)),
+            Some(Err(Error::NotFound))
+        );
+        assert_eq!(cache.get("missing.example", a, later), None);
+        assert!(cache.get("long.example", a, now + Duration::from_secs(3599)).is_some());
+        assert_eq!(cache.get("long.example", a, now + Duration::from_secs(3600)), None);
+
+        // Zero TTL, or a name too long for the cache.
+        cache.insert("zero.example", a, &Ok((addrs(&[ADDR]), 0)), now, &config);
+        let long = core::str::from_utf8(&[b'a'; MAX_CACHED_NAME_LEN + 1]).unwrap();
+        cache.insert(long, a, &Ok((addrs(&[ADDR]), 300)), now, &config);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn cache_full() {
+        let config = Config::default();
+        let mut cache = Cache::<2>::new();
+        let now = Instant::from_secs(100);
+        let a = dns_wire::TYPE_A;
+
+        cache.insert("a.example", a, &Ok((addrs(&[ADDR]), 300)), now, &config);
+        cache.insert("b.example", a, &Ok((addrs(&[ADDR]), 100)), now, &config);
+        cache.insert("c.example", a, &Ok((addrs(&[ADDR]), 200)), now, &config);
+        // The entry expiring first was replaced.
+        assert!(cache.get("a.example", a, now).is_some());
+        assert!(cache.get("b.example", a, now).is_none());
+        assert!(cache.get("c.example", a, now).is_some());
+
+        cache.clear();
+        assert!(cache.get("a.example", a, now).is_none());
+    }
+
+    #[test]
+    fn fallback_order() {
+        let order = |start, count, attempts| server_order(start, count, attempts).collect::<Vec<_, 16>>();
+        assert_eq!(order(0, 3, 1), [(0, false), (1, false), (2, true)]);
+        assert_eq!(
+            order(2, 3, 2),
+            [(2, false), (0, false), (1, true), (2, false), (0, false), (1, true)]
+        );
+        assert_eq!(order(0, 1, 2), [(0, true), (0, true)]);
+        assert_eq!(order(0, 2, 0), []);
+    }
+
+    #[test]
+    fn response_outcome() {
+        assert_eq!(Outcome::of(dns_wire::FLAG_RESPONSE, false), Outcome::Answered(Ok(())));
+        assert_eq!(
+            Outcome::of(dns_wire::FLAG_RESPONSE | dns_wire::FLAG_TRUNCATED, false),
+            Outcome::Answered(Err(Error::Truncated))
+        );
+        assert_eq!(
+            Outcome::of(dns_wire::RCODE_NXDOMAIN, false),
+            Outcome::Answered(Err(Error::NotFound))
+        );
+        // SERVFAIL is always retried, REFUSED only until all servers have been tried.
+        assert_eq!(Outcome::of(2, true), Outcome::TryNext(Error::ServerFailure(2)));
+        assert_eq!(Outcome::of(5, false), Outcome::TryNext(Error::ServerFailure(5)));
+        assert_eq!(Outcome::of(5, true), Outcome::Failed(Error::ServerFailure(5)));
+    }
+
+    #[test]
+    fn check_question() {
+        let mut buf = [0; 128];
+        let n = response(&mut buf, 0, &[]);
+        let msg = &buf[..n];
+        assert_eq!(
+            check_response(msg, 7, "example.com", dns_wire::TYPE_A),
+            Some(dns_wire::FLAG_RESPONSE)
+        );
+        assert_eq!(
+            check_response(msg, 7, "Example.COM.", dns_wire::TYPE_A),
+            Some(dns_wire::FLAG_RESPONSE)
+        );
+        assert_eq!(check_response(msg, 8, "example.com", dns_wire::TYPE_A), None);
+        assert_eq!(check_response(msg, 7, "example.org", dns_wire::TYPE_A), None);
+        assert_eq!(check_response(msg, 7, "example.com", dns_wire::TYPE_AAAA), None);
+
+        // A query, not a response.
+        let mut w = Writer::new(&mut buf, 7, 0).unwrap();
+        w.question("example.com", dns_wire::TYPE_A, dns_wire::CLASS_IN).unwrap();
+        let n = w.finish();
+        assert_eq!(check_response(&buf[..n], 7, "example.com", dns_wire::TYPE_A), None);
+    }
+
+    #[test]
+    fn addresses() {
+        let mut buf = [0; 256];
+        let other = Ipv4Address([192, 0, 2, 2]);
+        let n = response(
+            &mut buf,
+            0,
+            &[
+                (dns_wire::TYPE_CNAME, 10, b"\x03www\x00"),
+                (dns_wire::TYPE_A, 600, &ADDR.0),
+                (dns_wire::TYPE_A, 300, &other.0),
+                (dns_wire::TYPE_A, 60, &[1, 2, 3]),
+            ],
+        );
+        assert_eq!(
+            parse_addresses(&buf[..n], dns_wire::TYPE_A),
+            Ok((addrs(&[ADDR, other]), 300))
+        );
+
+        let n = response(&mut buf, 0, &[(dns_wire::TYPE_CNAME, 10, b"\x03www\x00")]);
+        assert_eq!(parse_addresses(&buf[..n], dns_wire::TYPE_A), Err(Error::NotFound));
+        assert_eq!(parse_addresses(&buf[..n - 1], dns_wire::TYPE_A), Err(Error::Malformed));
+    }
+}
@@ -0,0 +1,622 @@
+//! IPv6 autoconfiguration: router discovery, SLAAC and RDNSS.
+//!
+//! Router Advertisements are received through a raw ICMPv6 socket owned by the stack, since smoltcp
+//! does not process them itself. Duplicate address detection is not performed.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::raw;
+use smoltcp::wire::{Icmpv6Packet, IpAddress, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr, Ipv6Packet};
+
+#[cfg(feature = "dhcpv6")]
+use crate::dhcpv6;
+use crate::{AutoConfigV6, SocketStack, StaticConfigV6};
+
+const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+
+const NDP_OPT_SOURCE_LLADDR: u8 = 1;
+const NDP_OPT_PREFIX_INFO: u8 = 3;
+const NDP_OPT_RDNSS: u8 = 25;
+
+const RA_FLAG_MANAGED: u8 = 0x80;
+const RA_FLAG_OTHER: u8 = 0x40;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// Time between two Router Solicitations (RFC 4861 `RTR_SOLICITATION_INTERVAL`).
+const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+/// Lower bound for the valid lifetime of an existing address updated by an unauthenticated RA (RFC 4862).
+const MIN_VALID_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+const MAX_DNS_SERVERS: usize = 3;
+
+pub(crate) const RX_BUFFER_LEN: usize = 512;
+pub(crate) const TX_BUFFER_LEN: usize = 64;
+
+/// Buffers for the raw socket used for router discovery.
+pub(crate) struct Resources {
+    rx_meta: [raw::PacketMetadata; 2],
+    rx_buffer: [u8; RX_BUFFER_LEN],
+    tx_meta: [raw::PacketMetadata; 1],
+    tx_buffer: [u8; TX_BUFFER_LEN],
+    #[cfg(feature = "dhcpv6")]
+    dhcpv6: dhcpv6::Resources,
+}
+
+impl Resources {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_meta: [raw::PacketMetadata::EMPTY; 2],
+            rx_buffer: [0; RX_BUFFER_LEN],
+            tx_meta: [raw::PacketMetadata::EMPTY; 1],
+            tx_buffer: [0; TX_BUFFER_LEN],
+            #[cfg(feature = "dhcpv6")]
+            dhcpv6: dhcpv6::Resources::new(),
+        }
+    }
+}
+
+fn expiry(now: Instant, secs: u32) -> Option<Instant> {
+    match secs {
+        u32::MAX => None,
+        secs => Some(now + Duration::from_secs(secs as u64)),
+    }
+}
+
+/// New expiry of an existing address, for a prefix advertised with a `valid` lifetime.
+///
+/// RFC 4862 section 5.5.3 (e): extending the lifetime is always accepted, but it is only shortened
+/// below two hours if it's already shorter than that.
+fn updated_expiry(now: Instant, expires: Option<Instant>, valid: u32) -> Option<Instant> {
+    let new = expiry(now, valid);
+    match (new, expires) {
+        (None, _) => None,
+        (Some(_), _) if valid as u64 > MIN_VALID_LIFETIME.as_secs() => new,
+        (Some(n), Some(e)) if n > e => new,
+        (Some(_), Some(e)) if e.saturating_duration_since(now) <= MIN_VALID_LIFETIME => expires,
+        (Some(_), _) => Some(now + MIN_VALID_LIFETIME),
+    }
+}
+
+fn expired(now: Instant, expires: Option<Instant>) -> bool {
+    expires.is_some_and(|e| e <= now)
+}
+
+struct Router {
+    address: Ipv6Address,
+    expires: Instant,
+}
+
+struct Prefix {
+    cidr: Ipv6Cidr,
+    expires: Option<Instant>,
+}
+
+/// Autoconfiguration state, owned by the stack's `Inner`.
+pub(crate) struct AutoV6 {
+    config: AutoConfigV6,
+    handle: SocketHandle,
+    lladdr: Option<[u8; 6]>,
+    iid: [u8; 8],
+    solicitations: u8,
+    next_solicitation: Instant,
+    router: Option<Router>,
+    prefix: Option<Prefix>,
+    dns_servers: Vec<Ipv6Address, MAX_DNS_SERVERS>,
+    dns_expires: Option<Instant>,
+    #[cfg(feature = "dhcpv6")]
+    dhcp: Option<dhcpv6::Client>,
+    /// Buffers for the DHCPv6 client, until it is started.
+    #[cfg(feature = "dhcpv6")]
+    dhcp_resources: Option<&'static mut dhcpv6::Resources>,
+    current: Option<StaticConfigV6>,
+}
+
+impl AutoV6 {
+    /// Create the autoconfiguration state, adding its sockets to the stack.
+    ///
+    /// The interface identifier is derived from `hardware_addr`, or from `random_seed` on
+    /// mediums without link-layer addresses.
+    ///
+    /// The sockets can't be removed from the stack, as their buffers couldn't be reused: the state
+    /// is kept when autoconfiguration is disabled, and [reconfigured](AutoV6::set_config) to enable
+    /// it again.
+    pub(crate) fn new(
+        s: &mut SocketStack,
+        resources: &'static mut Resources,
+        config: AutoConfigV6,
+        hardware_addr: &[u8],
+        random_seed: u64,
+    ) -> Self {
+        let Resources {
+            rx_meta,
+            rx_buffer,
+            tx_meta,
+            tx_buffer,
+            #[cfg(feature = "dhcpv6")]
+            dhcpv6,
+        } = resources;
+        let socket = raw::Socket::new(
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            raw::PacketBuffer::new(&mut rx_meta[..], &mut rx_buffer[..]),
+            raw::PacketBuffer::new(&mut tx_meta[..], &mut tx_buffer[..]),
+        );
+        let handle = s.sockets.add(socket);
+
+        // Modified EUI-64 interface identifier (RFC 4291, appendix A).
+        let mut iid = [0; 8];
+        let mut lladdr = None;
+        match hardware_addr.len() {
+            6 => {
+                iid[..3].copy_from_slice(&hardware_addr[..3]);
+                iid[3] = 0xff;
+                iid[4] = 0xfe;
+                iid[5..].copy_from_slice(&hardware_addr[3..]);
+                lladdr = Some(unwrap!(hardware_addr.try_into().ok()));
+            }
+            8 => iid.copy_from_slice(hardware_addr),
+            _ => iid = random_seed.to_be_bytes(),
+        }
+        iid[0] ^= 0x02;
+
+        let mut this = Self {
+            config: config.clone(),
+            handle,
+            lladdr,
+            iid,
+            solicitations: 0,
+            next_solicitation: Instant::now(),
+            router: None,
+            prefix: None,
+            dns_servers: Vec::new(),
+            dns_expires: None,
+            #[cfg(feature = "dhcpv6")]
+            dhcp: None,
+            #[cfg(feature = "dhcpv6")]
+            dhcp_resources: Some(dhcpv6),
+            current: None,
+        };
+        this.set_config(s, config);
+        this
+    }
+
+    /// Change the configuration, and start again from scratch.
+    pub(crate) fn set_config(&mut self, _s: &mut SocketStack, config: AutoConfigV6) {
+        self.config = config;
+        self.reset();
+
+        #[cfg(feature = "dhcpv6")]
+        if self.config.dhcpv6 && self.dhcp.is_none() {
+            if let Some(resources) = self.dhcp_resources.take() {
+                let hardware_addr = self.lladdr.as_ref().map_or(&[][..], |a| &a[..]);
+                self.dhcp = Some(dhcpv6::Client::new(_s, resources, hardware_addr, &self.iid));
+            }
+        }
+    }
+
+    /// Link-local address of the interface.
+    pub(crate) fn link_local(&self) -> Ipv6Cidr {
+        let mut addr = [0; 16];
+        addr[0] = 0xfe;
+        addr[1] = 0x80;
+        addr[8..].copy_from_slice(&self.iid);
+        Ipv6Cidr::new(Ipv6Address(addr), 64)
+    }
+
+    /// Current configuration, if a global address is available.
+    pub(crate) fn config(&self) -> Option<StaticConfigV6> {
+        self.current.clone()
+    }
+
+    /// Forget everything learned from the network, and start soliciting routers again.
+    pub(crate) fn reset(&mut self) {
+        self.solicitations = 0;
+        self.next_solicitation = Instant::now();
+        self.router = None;
+        self.prefix = None;
+        self.dns_servers.clear();
+        self.dns_expires = None;
+        #[cfg(feature = "dhcpv6")]
+        if let Some(dhcp) = &mut self.dhcp {
+            dhcp.reset();
+        }
+    }
+
+    /// Process received packets and timers.
+    ///
+    /// Returns true if the configuration changed.
+    pub(crate) fn poll(&mut self, s: &mut SocketStack, link_up: bool) -> bool {
+        let now = Instant::now();
+
+        if link_up {
+            let socket = s.sockets.get_mut::<raw::Socket>(self.handle);
+            while let Ok(packet) = socket.recv() {
+                #[allow(unused_variables)]
+                if let Some((managed, other)) = self.process_packet(now, packet) {
+                    // The client stays idle after a reset until started here, so it does nothing
+                    // while DHCPv6 is disabled.
+                    #[cfg(feature = "dhcpv6")]
+                    if let Some(dhcp) = self.dhcp.as_mut().filter(|_| self.config.dhcpv6) {
+                        dhcp.on_router_flags(managed, other);
+                    }
+                }
+            }
+
+            if self.router.is_none()
+                && self.solicitations < self.config.max_router_solicitations
+                && self.next_solicitation <= now
+            {
+                self.solicitations += 1;
+                self.next_solicitation = now + RTR_SOLICITATION_INTERVAL;
+                self.send_solicitation(socket);
+            }
+
+            #[cfg(feature = "dhcpv6")]
+            if let Some(dhcp) = &mut self.dhcp {
+                dhcp.poll(s, now);
+            }
+        }
+
+        if self.router.as_ref().is_some_and(|r| r.expires <= now) {
+            debug!("IPv6: default router expired");
+            self.router = None;
+        }
+        if self.prefix.as_ref().is_some_and(|p| expired(now, p.expires)) {
+            debug!("IPv6: SLAAC address expired");
+            self.prefix = None;
+        }
+        if expired(now, self.dns_expires) {
+            self.dns_servers.clear();
+            self.dns_expires = None;
+        }
+
+        let config = self.compute_config();
+        if config != self.current {
+            self.current = config;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Earliest time at which [`AutoV6::poll`] has to be called again.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        let mut at = None;
+        let mut update = |t: Option<Instant>| {
+            if let Some(t) = t {
+                at = Some(at.map_or(t, |at: Instant| at.min(t)));
+            }
+        };
+
+        if self.router.is_none() && self.solicitations < self.config.max_router_solicitations {
+            update(Some(self.next_solicitation));
+        }
+        update(self.router.as_ref().map(|r| r.expires));
+        update(self.prefix.as_ref().and_then(|p| p.expires));
+        update(self.dns_expires);
+        #[cfg(feature = "dhcpv6")]
+        if let Some(dhcp) = &self.dhcp {
+            update(dhcp.poll_at());
+        }
+        at
+    }
+
+    fn compute_config(&self) -> Option<StaticConfigV6> {
+        #[allow(unused_mut)]
+        let mut address = self.prefix.as_ref().map(|p| p.cidr);
+        #[allow(unused_mut)]
+        let mut dns_servers = self.dns_servers.clone();
+
+        #[cfg(feature = "dhcpv6")]
+        if let Some(dhcp) = &self.dhcp {
+            // An address assigned by DHCPv6 takes precedence over SLAAC.
+            if let Some(addr) = dhcp.address() {
+                let on_link = self.prefix.as_ref().is_some_and(|p| p.cidr.contains_addr(&addr));
+                address = Some(Ipv6Cidr::new(addr, if on_link { 64 } else { 128 }));
+            }
+            for s in dhcp.dns_servers() {
+                if !dns_servers.contains(s) {
+                    dns_servers.push(*s).ok();
+                }
+            }
+        }
+
+        Some(StaticConfigV6 {
+            address: address?,
+            gateway: self.router.as_ref().map(|r| r.address),
+            dns_servers,
+        })
+    }
+
+    fn send_solicitation(&self, socket: &mut raw::Socket) {
+        let src = self.link_local().address();
+        let dst = Ipv6Address::LINK_LOCAL_ALL_ROUTERS;
+        let icmp_len = if self.lladdr.is_some() { 16 } else { 8 };
+
+        let mut buf = [0u8; 40 + 16];
+        let buf = &mut buf[..40 + icmp_len];
+
+        let mut packet = Ipv6Packet::new_unchecked(&mut *buf);
+        packet.set_version(6);
+        packet.set_traffic_class(0);
+        packet.set_flow_label(0);
+        packet.set_payload_len(icmp_len as u16);
+        packet.set_next_header(IpProtocol::Icmpv6);
+        packet.set_hop_limit(255);
+        packet.set_src_addr(src);
+        packet.set_dst_addr(dst);
+
+        let icmp = packet.payload_mut();
+        icmp[0] = ICMPV6_ROUTER_SOLICIT;
+        if let Some(lladdr) = self.lladdr {
+            icmp[8] = NDP_OPT_SOURCE_LLADDR;
+            icmp[9] = 1;
+            icmp[10..16].copy_from_slice(&lladdr);
+        }
+        Icmpv6Packet::new_unchecked(icmp).fill_checksum(&IpAddress::Ipv6(src), &IpAddress::Ipv6(dst));
+
+        trace!("IPv6: sending router solicitation");
+        if socket.send_slice(buf).is_err() {
+            warn!("IPv6: failed to send router solicitation");
+        }
+    }
+
+    /// Process a received ICMPv6 packet.
+    ///
+    /// Returns the "managed" and "other configuration" flags if it's a valid Router Advertisement.
+    fn process_packet(&mut self, now: Instant, packet: &[u8]) -> Option<(bool, bool)> {
+        let packet = Ipv6Packet::new_checked(packet).ok()?;
+        let src = packet.src_addr();
+        let dst = packet.dst_addr();
+        let icmp = packet.payload();
+
+        // RFC 4861 section 6.1.2: validation of Router Advertisements.
+        if packet.hop_limit() != 255 || !src.is_link_local() || icmp.len() < 16 {
+            return None;
+        }
+        if icmp[0] != ICMPV6_ROUTER_ADVERT || icmp[1] != 0 {
+            return None;
+        }
+        if !Icmpv6Packet::new_unchecked(icmp).verify_checksum(&IpAddress::Ipv6(src), &IpAddress::Ipv6(dst)) {
+            return None;
+        }
+
+        let flags = icmp[5];
+        let router_lifetime = u16::from_be_bytes([icmp[6], icmp[7]]);
+        trace!("IPv6: router advertisement from {:?}, flags {:02x}", src, flags);
+
+        if router_lifetime != 0 {
+            self.router = Some(Router {
+                address: src,
+                expires: now + Duration::from_secs(router_lifetime as u64),
+            });
+        } else if self.router.as_ref().is_some_and(|r| r.address == src) {
+            self.router = None;
+        }
+
+        let mut options = &icmp[16..];
+        while options.len() >= 8 {
+            let len = options[1] as usize * 8;
+            if len == 0 || len > options.len() {
+                break;
+            }
+            let (opt, rest) = options.split_at(len);
+            options = rest;
+
+            match opt[0] {
+                NDP_OPT_PREFIX_INFO if len == 32 && self.config.slaac => {
+                    let prefix_len = opt[2];
+                    let valid = u32::from_be_bytes([opt[4], opt[5], opt[6], opt[7]]);
+                    let preferred = u32::from_be_bytes([opt[8], opt[9], opt[10], opt[11]]);
+                    let net = Ipv6Address::from_bytes(&opt[16..32]);
+
+                    // RFC 4862 section 5.5.3.
+                    if opt[3] & PREFIX_FLAG_AUTONOMOUS == 0 || net.is_link_local() || preferred > valid {
+                        continue;
+                    }
+                    if prefix_len != 64 {
+                        debug!("IPv6: ignoring prefix {:?}/{} for SLAAC", net, prefix_len);
+                        continue;
+                    }
+
+                    let mut addr = net.0;
+                    addr[8..].copy_from_slice(&self.iid);
+                    let cidr = Ipv6Cidr::new(Ipv6Address(addr), 64);
+
+                    match &mut self.prefix {
+                        Some(p) if p.cidr == cidr => p.expires = updated_expiry(now, p.expires, valid),
+                        Some(_) => {}
+                        None if valid != 0 => {
+                            debug!("IPv6: SLAAC address {:?}", cidr);
+                            self.prefix = Some(Prefix {
+                                cidr,
+                                expires: expiry(now, valid),
+                            });
+                        }
+                        None => {}
+                    }
+                }
+                NDP_OPT_RDNSS if len >= 24 => {
+                    let lifetime = u32::from_be_bytes([opt[4], opt[5], opt[6], opt[7]]);
+                    self.dns_servers.clear();
+                    if lifetime == 0 {
+                        self.dns_expires = None;
+                        continue;
+                    }
+                    for addr in opt[8..].chunks_exact(16) {
+                        if self.dns_servers.push(Ipv6Address::from_bytes(addr)).is_err() {
+                            break;
+                        }
+                    }
+                    self.dns_expires = expiry(now, lifetime);
+                }
+                _ => {}
+            }
+        }
+
+        Some((flags & RA_FLAG_MANAGED != 0, flags & RA_FLAG_OTHER != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTER: Ipv6Address = Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    const PREFIX: Ipv6Address = Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    const DNS: Ipv6Address = Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x53]);
+
+    fn auto_v6() -> AutoV6 {
+        AutoV6 {
+            config: AutoConfigV6::default(),
+            handle: SocketHandle::default(),
+            lladdr: None,
+            iid: [0x02, 0, 0, 0xff, 0xfe, 0, 0, 0x01],
+            solicitations: 0,
+            next_solicitation: Instant::from_secs(0),
+            router: None,
+            prefix: None,
+            dns_servers: Vec::new(),
+            dns_expires: None,
+            #[cfg(feature = "dhcpv6")]
+            dhcp: None,
+            #[cfg(feature = "dhcpv6")]
+            dhcp_resources: None,
+            current: None,
+        }
+    }
+
+    /// Router Advertisement with a /64 prefix and a DNS server.
+    fn router_advert(flags: u8, router_lifetime: u16, valid: u32, preferred: u32) -> [u8; 40 + 16 + 32 + 24] {
+        let mut buf = [0; 40 + 16 + 32 + 24];
+        let mut packet = Ipv6Packet::new_unchecked(&mut buf[..]);
+        packet.set_version(6);
+        packet.set_payload_len(16 + 32 + 24);
+        packet.set_next_header(IpProtocol::Icmpv6);
+        packet.set_hop_limit(255);
+        packet.set_src_addr(ROUTER);
+        packet.set_dst_addr(Ipv6Address::LINK_LOCAL_ALL_NODES);
+
+        let icmp = packet.payload_mut();
+        icmp[0] = ICMPV6_ROUTER_ADVERT;
+        icmp[5] = flags;
+        icmp[6..8].copy_from_slice(&router_lifetime.to_be_bytes());
+
+        let prefix = &mut icmp[16..48];
+        prefix[..4].copy_from_slice(&[NDP_OPT_PREFIX_INFO, 4, 64, PREFIX_FLAG_AUTONOMOUS]);
+        prefix[4..8].copy_from_slice(&valid.to_be_bytes());
+        prefix[8..12].copy_from_slice(&preferred.to_be_bytes());
+        prefix[16..].copy_from_slice(PREFIX.as_bytes());
+
+        let rdnss = &mut icmp[48..];
+        rdnss[..2].copy_from_slice(&[NDP_OPT_RDNSS, 3]);
+        rdnss[4..8].copy_from_slice(&600u32.to_be_bytes());
+        rdnss[8..].copy_from_slice(DNS.as_bytes());
+
+        Icmpv6Packet::new_unchecked(icmp).fill_checksum(
+            &IpAddress::Ipv6(ROUTER),
+            &IpAddress::Ipv6(Ipv6Address::LINK_LOCAL_ALL_NODES),
+        );
+        buf
+    }
+
+    #[test]
+    fn slaac_address() {
+        let mut auto = auto_v6();
+        let now = Instant::from_secs(100);
+        let ra = router_advert(RA_FLAG_OTHER, 1800, 86400, 14400);
+        assert_eq!(auto.process_packet(now, &ra), Some((false, true)));
+
+        let config = unwrap!(auto.compute_config());
+        let mut addr = PREFIX.0;
+        addr[8..].copy_from_slice(&auto.iid);
+        assert_eq!(config.address, Ipv6Cidr::new(Ipv6Address(addr), 64));
+        assert_eq!(config.gateway, Some(ROUTER));
+        assert_eq!(&config.dns_servers[..], &[DNS]);
+        assert_eq!(
+            auto.prefix.as_ref().and_then(|p| p.expires),
+            Some(now + Duration::from_secs(86400))
+        );
+        assert_eq!(auto.dns_expires, Some(now + Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn invalid_router_advert() {
+        let now = Instant::from_secs(100);
+
+        // Forwarded by a router.
+        let mut ra = router_advert(0, 1800, 86400, 14400);
+        Ipv6Packet::new_unchecked(&mut ra[..]).set_hop_limit(64);
+        assert_eq!(auto_v6().process_packet(now, &ra), None);
+
+        // Bad checksum.
+        let mut ra = router_advert(0, 1800, 86400, 14400);
+        ra[40 + 2] ^= 0xff;
+        assert_eq!(auto_v6().process_packet(now, &ra), None);
+
+        // Preferred lifetime longer than the valid one: the prefix is ignored.
+        let mut auto = auto_v6();
+        let ra = router_advert(0, 1800, 3600, 7200);
+        assert_eq!(auto.process_packet(now, &ra), Some((false, false)));
+        assert!(auto.prefix.is_none());
+        assert!(auto.compute_config().is_none());
+    }
+
+    #[test]
+    fn prefix_lifetime() {
+        let now = Instant::from_secs(1000);
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+
+        // Longer lifetimes are always accepted.
+        assert_eq!(
+            updated_expiry(now, Some(now + hours(1)), 3 * 3600),
+            Some(now + hours(3))
+        );
+        assert_eq!(
+            updated_expiry(now, Some(now + hours(1)), 90 * 60),
+            Some(now + hours(1) + hours(1) / 2)
+        );
+        assert_eq!(updated_expiry(now, Some(now + hours(1)), u32::MAX), None);
+        // Above two hours, the advertised lifetime is taken.
+        assert_eq!(
+            updated_expiry(now, Some(now + hours(5)), 3 * 3600),
+            Some(now + hours(3))
+        );
+        assert_eq!(updated_expiry(now, None, 3 * 3600), Some(now + hours(3)));
+        // Shortening to less than two hours stops at two hours...
+        assert_eq!(updated_expiry(now, Some(now + hours(5)), 60), Some(now + hours(2)));
+        assert_eq!(updated_expiry(now, Some(now + hours(3)), 30 * 60), Some(now + hours(2)));
+        assert_eq!(updated_expiry(now, None, 0), Some(now + hours(2)));
+        // ...unless the remaining lifetime is already shorter.
+        assert_eq!(updated_expiry(now, Some(now + hours(1)), 60), Some(now + hours(1)));
+        assert_eq!(updated_expiry(now, Some(now + hours(1)), 0), Some(now + hours(1)));
+    }
+
+    #[test]
+    fn prefix_lifetime_from_router_advert() {
+        let mut auto = auto_v6();
+        let now = Instant::from_secs(100);
+        auto.process_packet(now, &router_advert(0, 1800, 3600, 1800));
+        assert_eq!(
+            auto.prefix.as_ref().and_then(|p| p.expires),
+            Some(now + Duration::from_secs(3600))
+        );
+
+        // A spoofed advertisement can't make the address expire early.
+        let later = now + Duration::from_secs(60);
+        auto.process_packet(later, &router_advert(0, 1800, 0, 0));
+        assert_eq!(
+            auto.prefix.as_ref().and_then(|p| p.expires),
+            Some(now + Duration::from_secs(3600))
+        );
+
+        // But it can be extended.
+        auto.process_packet(later, &router_advert(0, 1800, 7200, 3600));
+        assert_eq!(
+            auto.prefix.as_ref().and_then(|p| p.expires),
+            Some(later + Duration::from_secs(7200))
+        );
+    }
+}
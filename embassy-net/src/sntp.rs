@@ -0,0 +1,591 @@
+//! SNTP client keeping a wall clock on top of the `embassy-time` clock.
+//!
+//! The [`SntpClient`] periodically queries the configured NTP servers and updates a shared
+//! [`Clock`], which maps [`Instant`]s to Unix time. Any task can read the current wall-clock time
+//! from the [`Clock`] once the first synchronization completed.
+//!
+//! Each synchronization sends a short burst of requests and keeps the answer with the smallest
+//! round-trip delay, which filters out most of the network jitter. Between synchronizations, the
+//! clock also compensates for the measured frequency error of the local time base.
+//!
+//! Servers sending a Kiss-o'-Death `DENY` or `RSTR` are no longer queried, and `RATE` doubles the
+//! polling interval.
+
+use core::cell::Cell;
+
+use embassy_net_driver::Driver;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use crate::udp::{self, UdpSocket};
+use crate::{IpAddress, IpEndpoint, Stack};
+
+/// UDP port used by NTP servers.
+pub const NTP_PORT: u16 = 123;
+
+/// Maximum number of servers in [`Config::servers`]. Additional servers are ignored.
+pub const MAX_SERVERS: usize = 32;
+
+const PACKET_LEN: usize = 48;
+const NTP_VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Longest polling interval reached through `RATE` backoff (36 hours, as in NTP).
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(1 << 17);
+
+/// Maximum frequency correction applied to the local clock, in parts per billion.
+const MAX_FREQUENCY_PPB: i64 = 500_000;
+
+/// Minimum time between two synchronizations for estimating the frequency error.
+const MIN_FREQUENCY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Errors returned by the SNTP client.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The socket could not be bound.
+    Bind(udp::BindError),
+    /// A request could not be sent.
+    Send(udp::SendError),
+    /// A server name could not be resolved.
+    InvalidServer,
+    /// No usable server is left, every server sent a `DENY` or `RSTR` kiss code.
+    NoServers,
+    /// No valid answer was received from any server.
+    Timeout,
+}
+
+/// SNTP client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// NTP servers, queried in order until one answers.
+    ///
+    /// Entries can be IP addresses, or host names if the `dns` feature is enabled.
+    pub servers: &'a [&'a str],
+    /// Time between two successful synchronizations.
+    pub poll_interval: Duration,
+    /// Time before retrying after a failed synchronization.
+    pub retry_interval: Duration,
+    /// Time to wait for the answer to a single request.
+    pub timeout: Duration,
+    /// Number of requests sent per synchronization. The answer with the smallest round-trip
+    /// delay is used.
+    pub burst: u8,
+}
+
+impl<'a> Config<'a> {
+    /// Create a configuration querying `servers` every 1024 seconds, with bursts of 4 requests.
+    pub fn new(servers: &'a [&'a str]) -> Self {
+        Self {
+            servers,
+            poll_interval: Duration::from_secs(1024),
+            retry_interval: Duration::from_secs(16),
+            timeout: Duration::from_secs(2),
+            burst: 4,
+        }
+    }
+}
+
+/// Result of a successful synchronization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Synchronization {
+    /// Local time at which the answer was received.
+    pub instant: Instant,
+    /// Unix time at `instant`, in microseconds.
+    pub unix_micros: u64,
+    /// Correction applied to the clock, in microseconds. Zero for the first synchronization.
+    pub offset_micros: i64,
+    /// Round-trip delay of the selected answer.
+    pub delay: Duration,
+    /// Server which sent the selected answer.
+    pub server: IpAddress,
+    /// Stratum of that server.
+    pub stratum: u8,
+}
+
+#[derive(Clone, Copy)]
+struct ClockState {
+    instant: Instant,
+    unix_micros: u64,
+    frequency_ppb: i64,
+}
+
+impl ClockState {
+    fn unix_micros_at(&self, instant: Instant) -> u64 {
+        let elapsed = instant.as_micros() as i64 - self.instant.as_micros() as i64;
+        let correction = elapsed as i128 * self.frequency_ppb as i128 / 1_000_000_000;
+        (self.unix_micros as i64 + elapsed + correction as i64) as u64
+    }
+}
+
+/// Wall clock disciplined by an [`SntpClient`].
+///
+/// The clock can be shared between tasks, e.g. placed in a `static`.
+pub struct Clock<M: RawMutex> {
+    state: Mutex<M, Cell<Option<ClockState>>>,
+}
+
+impl<M: RawMutex> Clock<M> {
+    /// Create a new, not yet synchronized clock.
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Get whether the clock has been synchronized at least once.
+    pub fn is_synchronized(&self) -> bool {
+        self.state.lock(|s| s.get().is_some())
+    }
+
+    /// Get the local time of the last synchronization.
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.state.lock(|s| s.get().map(|s| s.instant))
+    }
+
+    /// Get the current Unix time in microseconds, or `None` if the clock is not synchronized.
+    pub fn now_unix_micros(&self) -> Option<u64> {
+        self.to_unix_micros(Instant::now())
+    }
+
+    /// Get the current Unix time in seconds, or `None` if the clock is not synchronized.
+    pub fn now_unix_secs(&self) -> Option<u64> {
+        self.now_unix_micros().map(|t| t / 1_000_000)
+    }
+
+    /// Convert a local [`Instant`] to Unix time in microseconds.
+    ///
+    /// Returns `None` if the clock is not synchronized.
+    pub fn to_unix_micros(&self, instant: Instant) -> Option<u64> {
+        self.state.lock(|s| s.get().map(|s| s.unix_micros_at(instant)))
+    }
+
+    fn update(&self, instant: Instant, unix_micros: u64) -> i64 {
+        self.state.lock(|s| {
+            let (offset, frequency_ppb) = match s.get() {
+                None => (0, 0),
+                Some(prev) => {
+                    let offset = unix_micros as i64 - prev.unix_micros_at(instant) as i64;
+                    let elapsed = instant.saturating_duration_since(prev.instant);
+                    let mut frequency_ppb = prev.frequency_ppb;
+                    // Offsets above a second are steps of the time itself, not drift.
+                    if elapsed >= MIN_FREQUENCY_INTERVAL && offset.abs() < 1_000_000 {
+                        frequency_ppb += offset * 1_000_000_000 / elapsed.as_micros() as i64;
+                        frequency_ppb = frequency_ppb.clamp(-MAX_FREQUENCY_PPB, MAX_FREQUENCY_PPB);
+                    }
+                    (offset, frequency_ppb)
+                }
+            };
+            s.set(Some(ClockState {
+                instant,
+                unix_micros,
+                frequency_ppb,
+            }));
+            offset
+        })
+    }
+}
+
+impl<M: RawMutex> Default for Clock<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Response {
+    Time {
+        unix_micros: u64,
+        delay: Duration,
+        stratum: u8,
+    },
+    Kiss([u8; 4]),
+}
+
+/// SNTP client.
+pub struct SntpClient<'a, 'c, D: Driver + 'static, M: RawMutex> {
+    stack: &'a Stack<D>,
+    socket: UdpSocket<'a>,
+    config: Config<'c>,
+    clock: &'a Clock<M>,
+    denied: u32,
+    poll_interval: Duration,
+}
+
+impl<'a, 'c, D: Driver + 'static, M: RawMutex> SntpClient<'a, 'c, D, M> {
+    /// Create a new SNTP client using the given UDP socket, updating `clock`.
+    ///
+    /// The socket is bound to a dynamically allocated local port.
+    pub fn new(
+        stack: &'a Stack<D>,
+        mut socket: UdpSocket<'a>,
+        clock: &'a Clock<M>,
+        config: Config<'c>,
+    ) -> Result<Self, Error> {
+        socket.bind(0).map_err(Error::Bind)?;
+        Ok(Self {
+            stack,
+            socket,
+            poll_interval: config.poll_interval,
+            config,
+            clock,
+            denied: 0,
+        })
+    }
+
+    /// Run the client forever.
+    pub async fn run(&mut self) -> ! {
+        self.run_with_callback(|_| {}).await
+    }
+
+    /// Run the client forever, calling `on_sync` after each successful synchronization.
+    ///
+    /// This can be used to set a hardware RTC from [`Synchronization::unix_micros`].
+    pub async fn run_with_callback(&mut self, mut on_sync: impl FnMut(&Synchronization)) -> ! {
+        loop {
+            self.stack.wait_config_up().await;
+            let wait = match self.sync().await {
+                Ok(sync) => {
+                    on_sync(&sync);
+                    self.poll_interval
+                }
+                Err(e) => {
+                    warn!("sntp: synchronization failed: {:?}", e);
+                    self.config.retry_interval
+                }
+            };
+            Timer::after(wait).await;
+        }
+    }
+
+    /// Synchronize the clock once.
+    ///
+    /// Servers are tried in order until one of them answers.
+    pub async fn sync(&mut self) -> Result<Synchronization, Error> {
+        let mut result = Err(Error::NoServers);
+        for (i, name) in self.config.servers.iter().enumerate().take(MAX_SERVERS) {
+            if self.denied & (1 << i) != 0 {
+                continue;
+            }
+            let server = match self.resolve(name).await {
+                Ok(server) => server,
+                Err(e) => {
+                    result = Err(e);
+                    continue;
+                }
+            };
+            match self.sync_with(i, server).await {
+                Ok(sync) => return Ok(sync),
+                Err(e) => result = Err(e),
+            }
+        }
+        result
+    }
+
+    async fn resolve(&self, name: &str) -> Result<IpAddress, Error> {
+        if let Ok(addr) = name.parse() {
+            return Ok(addr);
+        }
+
+        #[cfg(feature = "dns")]
+        {
+            #[cfg(feature = "proto-ipv4")]
+            let qtype = crate::dns::DnsQueryType::A;
+            #[cfg(not(feature = "proto-ipv4"))]
+            let qtype = crate::dns::DnsQueryType::Aaaa;
+
+            let addrs = self
+                .stack
+                .dns_query(name, qtype)
+                .await
+                .map_err(|_| Error::InvalidServer)?;
+            addrs.first().copied().ok_or(Error::InvalidServer)
+        }
+
+        #[cfg(not(feature = "dns"))]
+        Err(Error::InvalidServer)
+    }
+
+    async fn sync_with(&mut self, index: usize, server: IpAddress) -> Result<Synchronization, Error> {
+        let mut best: Option<(u64, Duration, u8, Instant)> = None;
+
+        for n in 0..self.config.burst.max(1) {
+            if n != 0 {
+                Timer::after_secs(2).await;
+            }
+
+            match self.request(server).await? {
+                Some((
+                    Response::Time {
+                        unix_micros,
+                        delay,
+                        stratum,
+                    },
+                    received,
+                )) if best.filter(|(_, d, _, _)| *d <= delay).is_none() => {
+                    best = Some((unix_micros, delay, stratum, received));
+                }
+                Some((Response::Kiss(code), _)) => {
+                    warn!("sntp: kiss code {:?} from {}", code, server);
+                    match &code {
+                        b"DENY" | b"RSTR" => {
+                            self.denied |= 1 << index;
+                            return Err(Error::NoServers);
+                        }
+                        b"RATE" => {
+                            self.poll_interval = (self.poll_interval * 2).min(MAX_POLL_INTERVAL);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (unix_micros, delay, stratum, instant) = best.ok_or(Error::Timeout)?;
+        let offset_micros = self.clock.update(instant, unix_micros);
+        debug!(
+            "sntp: synchronized with {}, offset {} us, delay {} us",
+            server,
+            offset_micros,
+            delay.as_micros()
+        );
+
+        Ok(Synchronization {
+            instant,
+            unix_micros,
+            offset_micros,
+            delay,
+            server,
+            stratum,
+        })
+    }
+
+    /// Send one request, and wait for its answer.
+    ///
+    /// Returns `Ok(None)` if no valid answer was received in time.
+    async fn request(&mut self, server: IpAddress) -> Result<Option<(Response, Instant)>, Error> {
+        // The transmit timestamp is only used to match the answer to the request,
+        // so the local tick count works even before the clock is synchronized.
+        let sent = Instant::now();
+        let transmit = sent.as_ticks().to_be_bytes();
+
+        let mut packet = [0u8; PACKET_LEN];
+        packet[0] = (NTP_VERSION << 3) | MODE_CLIENT;
+        packet[40..48].copy_from_slice(&transmit);
+
+        let endpoint = IpEndpoint::new(server, NTP_PORT);
+        self.socket.send_to(&packet, endpoint).await.map_err(Error::Send)?;
+
+        let res = with_timeout(self.config.timeout, async {
+            loop {
+                let mut buf = [0u8; PACKET_LEN];
+                let Ok((len, from)) = self.socket.recv_from(&mut buf).await else {
+                    continue;
+                };
+                let received = Instant::now();
+                if from != endpoint || len < PACKET_LEN || buf[24..32] != transmit {
+                    continue;
+                }
+                if let Some(response) = parse_response(&buf, sent, received) {
+                    return (response, received);
+                }
+            }
+        })
+        .await;
+
+        Ok(res.ok())
+    }
+}
+
+fn parse_response(buf: &[u8; PACKET_LEN], sent: Instant, received: Instant) -> Option<Response> {
+    let leap = buf[0] >> 6;
+    let mode = buf[0] & 0x07;
+    let stratum = buf[1];
+
+    if mode != MODE_SERVER {
+        return None;
+    }
+    if stratum == 0 {
+        let mut code = [0; 4];
+        code.copy_from_slice(&buf[12..16]);
+        return Some(Response::Kiss(code));
+    }
+    if leap == LEAP_UNSYNCHRONIZED || stratum > 15 {
+        return None;
+    }
+
+    let server_received = ntp_to_unix_micros(&buf[32..40])?;
+    let server_sent = ntp_to_unix_micros(&buf[40..48])?;
+
+    // Round-trip delay, excluding the server's processing time.
+    let round_trip = received.duration_since(sent).as_micros();
+    let processing = server_sent.checked_sub(server_received)?;
+    let delay = round_trip.checked_sub(processing)?;
+
+    Some(Response::Time {
+        unix_micros: server_sent + delay / 2,
+        delay: Duration::from_micros(delay),
+        stratum,
+    })
+}
+
+/// Convert an NTP timestamp to Unix time in microseconds.
+///
+/// Timestamps with the MSB of the seconds cleared are assumed to be in NTP era 1 (2036 onwards).
+/// Timestamps before the Unix epoch are rejected.
+fn ntp_to_unix_micros(ts: &[u8]) -> Option<u64> {
+    let secs = u32::from_be_bytes([ts[0], ts[1], ts[2], ts[3]]) as u64;
+    let frac = u32::from_be_bytes([ts[4], ts[5], ts[6], ts[7]]) as u64;
+    if secs == 0 && frac == 0 {
+        return None;
+    }
+    let secs = if secs & 0x8000_0000 == 0 {
+        secs + (1 << 32)
+    } else {
+        secs
+    };
+    let micros = (frac * 1_000_000) >> 32;
+    Some(secs.checked_sub(NTP_UNIX_OFFSET)? * 1_000_000 + micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z, in NTP and Unix seconds.
+    const NTP_2024: u32 = 0xE93C_7F00;
+    const UNIX_2024: u64 = 1_704_067_200;
+
+    fn timestamp(secs: u32, frac: u32) -> [u8; 8] {
+        let mut ts = [0; 8];
+        ts[..4].copy_from_slice(&secs.to_be_bytes());
+        ts[4..].copy_from_slice(&frac.to_be_bytes());
+        ts
+    }
+
+    fn server_packet(leap: u8, stratum: u8, received: [u8; 8], sent: [u8; 8]) -> [u8; PACKET_LEN] {
+        let mut buf = [0; PACKET_LEN];
+        buf[0] = (leap << 6) | (NTP_VERSION << 3) | MODE_SERVER;
+        buf[1] = stratum;
+        buf[32..40].copy_from_slice(&received);
+        buf[40..48].copy_from_slice(&sent);
+        buf
+    }
+
+    #[test]
+    fn ntp_timestamps() {
+        assert_eq!(ntp_to_unix_micros(&timestamp(NTP_2024, 0)), Some(UNIX_2024 * 1_000_000));
+        // The fraction is in units of 2^-32 seconds.
+        assert_eq!(
+            ntp_to_unix_micros(&timestamp(NTP_2024, 0x8000_0000)),
+            Some(UNIX_2024 * 1_000_000 + 500_000)
+        );
+        assert_eq!(
+            ntp_to_unix_micros(&timestamp(NTP_2024, 0x0000_10C7)),
+            Some(UNIX_2024 * 1_000_000 + 1)
+        );
+        // Era 1 starts in 2036, when the seconds wrap around.
+        assert_eq!(
+            ntp_to_unix_micros(&timestamp(1, 0)),
+            Some(((1u64 << 32) + 1 - NTP_UNIX_OFFSET) * 1_000_000)
+        );
+        // Unset timestamp, and 1968, before the Unix epoch.
+        assert_eq!(ntp_to_unix_micros(&timestamp(0, 0)), None);
+        assert_eq!(ntp_to_unix_micros(&timestamp(0x8000_0000, 0)), None);
+    }
+
+    #[test]
+    fn parse_time() {
+        // The server took 1 ms to answer, out of an 11 ms round trip.
+        let buf = server_packet(0, 2, timestamp(NTP_2024, 0), timestamp(NTP_2024, 0x0041_8938));
+        let response = parse_response(&buf, Instant::from_micros(1_000), Instant::from_micros(12_000));
+        assert_eq!(
+            response,
+            Some(Response::Time {
+                unix_micros: UNIX_2024 * 1_000_000 + 1_000 + 5_000,
+                delay: Duration::from_micros(10_000),
+                stratum: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let (sent, received) = (Instant::from_micros(0), Instant::from_micros(10_000));
+        let ts = timestamp(NTP_2024, 0);
+
+        // Kiss-o'-death.
+        let mut buf = server_packet(0, 0, ts, ts);
+        buf[12..16].copy_from_slice(b"RATE");
+        assert_eq!(parse_response(&buf, sent, received), Some(Response::Kiss(*b"RATE")));
+
+        // Unsynchronized server, invalid stratum, or not a server answer.
+        assert_eq!(parse_response(&server_packet(3, 2, ts, ts), sent, received), None);
+        assert_eq!(parse_response(&server_packet(0, 16, ts, ts), sent, received), None);
+        let mut buf = server_packet(0, 2, ts, ts);
+        buf[0] = (NTP_VERSION << 3) | MODE_CLIENT;
+        assert_eq!(parse_response(&buf, sent, received), None);
+
+        // Answer sent before the request was received by the server.
+        let buf = server_packet(0, 2, timestamp(NTP_2024, 0x8000_0000), ts);
+        assert_eq!(parse_response(&buf, sent, received), None);
+        // Server processing time longer than the round trip.
+        let buf = server_packet(0, 2, ts, timestamp(NTP_2024 + 1, 0));
+        assert_eq!(parse_response(&buf, sent, received), None);
+    }
+
+    #[test]
+    fn clock_update() {
+        let clock = Clock::<NoopRawMutex>::new();
+        let start = Instant::from_secs(10);
+        assert_eq!(clock.to_unix_micros(start), None);
+
+        // The first synchronization sets the time.
+        assert_eq!(clock.update(start, UNIX_2024 * 1_000_000), 0);
+        assert_eq!(clock.last_sync(), Some(start));
+        assert_eq!(
+            clock.to_unix_micros(start + Duration::from_secs(1)),
+            Some((UNIX_2024 + 1) * 1_000_000)
+        );
+
+        // The local clock is 10 ppm slow: 1 ms late after 100 s.
+        let next = start + Duration::from_secs(100);
+        assert_eq!(clock.update(next, (UNIX_2024 + 100) * 1_000_000 + 1_000), 1_000);
+        assert_eq!(
+            clock.to_unix_micros(next + Duration::from_secs(100)),
+            Some((UNIX_2024 + 200) * 1_000_000 + 1_000 + 1_000)
+        );
+
+        // Too soon to estimate the frequency: only the offset is corrected.
+        let soon = next + Duration::from_secs(10);
+        assert_eq!(clock.update(soon, (UNIX_2024 + 110) * 1_000_000 + 1_100 + 500), 500);
+        assert_eq!(
+            clock.to_unix_micros(soon + Duration::from_secs(100)),
+            Some((UNIX_2024 + 210) * 1_000_000 + 1_600 + 1_000)
+        );
+
+        // Steps of a second or more are not drift.
+        let later = soon + Duration::from_secs(100);
+        assert_eq!(
+            clock.update(later, (UNIX_2024 + 215) * 1_000_000 + 2_600),
+            5_000_000 - 1_000 + 1_000
+        );
+        assert_eq!(
+            clock.to_unix_micros(later + Duration::from_secs(100)),
+            Some((UNIX_2024 + 315) * 1_000_000 + 2_600 + 1_000)
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! Network statistics.
+//!
+//! smoltcp doesn't keep any counters, so they are gathered by inspecting the frames going through
+//! the driver. This has a small per-packet cost, which is why it's behind the `stats` feature.
+//!
+//! - [`Stack::stats()`](crate::Stack::stats) returns a [`Stats`] snapshot of the interface counters.
+//! - [`TcpSocket::stats()`](crate::tcp::TcpSocket::stats) returns the [`TcpStats`] of a connection.
+
+#[cfg(feature = "tcp")]
+use embassy_time::{Duration, Instant};
+#[cfg(feature = "tcp")]
+use heapless::Vec;
+use smoltcp::phy::Medium;
+#[allow(unused_imports)]
+use smoltcp::wire::IpProtocol;
+#[cfg(feature = "tcp")]
+use smoltcp::wire::{IpAddress, IpEndpoint, TcpPacket, TcpSeqNumber};
+
+/// Maximum number of TCP connections tracked at once.
+///
+/// When more connections are active, the least recently used one is forgotten.
+pub const MAX_TCP_CONNECTIONS: usize = 8;
+
+/// Interface statistics snapshot.
+///
+/// All counters start at zero when the stack is created or [reset](crate::Stack::reset_stats),
+/// and wrap around on overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Stats {
+    /// Frames received from the driver.
+    pub rx_packets: u32,
+    /// Bytes received from the driver, including link-layer headers.
+    pub rx_bytes: u64,
+    /// Frames handed to the driver for transmission.
+    pub tx_packets: u32,
+    /// Bytes handed to the driver for transmission, including link-layer headers.
+    pub tx_bytes: u64,
+    /// Received frames that were dropped because they were malformed or carried a protocol
+    /// the stack doesn't support.
+    pub rx_dropped: u32,
+    /// TCP segments sent again because they were not acknowledged in time (or lost, as signaled
+    /// by duplicate ACKs).
+    pub tcp_retransmits: u32,
+    /// Neighbor lookups sent: ARP requests and NDP neighbor solicitations.
+    ///
+    /// smoltcp doesn't expose its neighbor cache, but every cache miss causes a request. A steadily
+    /// growing gap between this and [`neighbor_replies`](Self::neighbor_replies) points to
+    /// unreachable hosts on the local network.
+    pub neighbor_requests: u32,
+    /// Neighbor lookup answers received: ARP replies and NDP neighbor advertisements.
+    pub neighbor_replies: u32,
+}
+
+/// Statistics of a single TCP connection.
+#[cfg(feature = "tcp")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct TcpStats {
+    /// Smoothed round-trip time estimate (RFC 6298), or `None` if no sample was taken yet.
+    pub rtt: Option<Duration>,
+    /// Segments of this connection that were retransmitted.
+    pub retransmits: u32,
+}
+
+#[cfg(feature = "tcp")]
+struct TcpConnection {
+    local: IpEndpoint,
+    remote: IpEndpoint,
+    /// Highest sequence number sent so far.
+    snd_max: Option<TcpSeqNumber>,
+    /// Segment being timed: the sequence number acknowledging it, and when it was sent.
+    sample: Option<(TcpSeqNumber, Instant)>,
+    srtt: Option<Duration>,
+    retransmits: u32,
+    last_used: Instant,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Rx,
+    Tx,
+}
+
+pub(crate) struct Recorder {
+    stats: Stats,
+    #[cfg(feature = "tcp")]
+    tcp: Vec<TcpConnection, MAX_TCP_CONNECTIONS>,
+}
+
+impl Recorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            stats: Stats {
+                rx_packets: 0,
+                rx_bytes: 0,
+                tx_packets: 0,
+                tx_bytes: 0,
+                rx_dropped: 0,
+                tcp_retransmits: 0,
+                neighbor_requests: 0,
+                neighbor_replies: 0,
+            },
+            #[cfg(feature = "tcp")]
+            tcp: Vec::new(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.stats = Stats::default();
+        #[cfg(feature = "tcp")]
+        for conn in self.tcp.iter_mut() {
+            conn.retransmits = 0;
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    pub(crate) fn tcp_stats(&self, local: IpEndpoint, remote: IpEndpoint) -> Option<TcpStats> {
+        self.tcp
+            .iter()
+            .find(|c| c.local == local && c.remote == remote)
+            .map(|c| TcpStats {
+                rtt: c.srtt,
+                retransmits: c.retransmits,
+            })
+    }
+
+    pub(crate) fn record_rx(&mut self, medium: Medium, frame: &[u8]) {
+        self.stats.rx_packets = self.stats.rx_packets.wrapping_add(1);
+        self.stats.rx_bytes = self.stats.rx_bytes.wrapping_add(frame.len() as u64);
+        if !self.inspect(medium, frame, Direction::Rx) {
+            self.stats.rx_dropped = self.stats.rx_dropped.wrapping_add(1);
+        }
+    }
+
+    pub(crate) fn record_tx(&mut self, medium: Medium, frame: &[u8]) {
+        self.stats.tx_packets = self.stats.tx_packets.wrapping_add(1);
+        self.stats.tx_bytes = self.stats.tx_bytes.wrapping_add(frame.len() as u64);
+        self.inspect(medium, frame, Direction::Tx);
+    }
+
+    /// Inspect a frame, returning `false` if the stack is going to drop it.
+    #[allow(unused_variables)]
+    fn inspect(&mut self, medium: Medium, frame: &[u8], dir: Direction) -> bool {
+        match medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet => {
+                use smoltcp::wire::{EthernetFrame, EthernetProtocol};
+
+                let Ok(eth) = EthernetFrame::new_checked(frame) else {
+                    return false;
+                };
+                match eth.ethertype() {
+                    #[cfg(feature = "proto-ipv4")]
+                    EthernetProtocol::Arp => self.inspect_arp(eth.payload(), dir),
+                    #[cfg(feature = "proto-ipv4")]
+                    EthernetProtocol::Ipv4 => self.inspect_ipv4(eth.payload(), dir),
+                    #[cfg(feature = "proto-ipv6")]
+                    EthernetProtocol::Ipv6 => self.inspect_ipv6(eth.payload(), dir),
+                    _ => false,
+                }
+            }
+            #[cfg(feature = "medium-ip")]
+            Medium::Ip => match frame.first().map(|b| b >> 4) {
+                #[cfg(feature = "proto-ipv4")]
+                Some(4) => self.inspect_ipv4(frame, dir),
+                #[cfg(feature = "proto-ipv6")]
+                Some(6) => self.inspect_ipv6(frame, dir),
+                _ => false,
+            },
+            // 6LoWPAN frames are only counted.
+            #[allow(unreachable_patterns)]
+            _ => true,
+        }
+    }
+
+    #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+    fn inspect_arp(&mut self, packet: &[u8], dir: Direction) -> bool {
+        use smoltcp::wire::{ArpOperation, ArpPacket};
+
+        let Ok(arp) = ArpPacket::new_checked(packet) else {
+            return false;
+        };
+        match (dir, arp.operation()) {
+            (Direction::Tx, ArpOperation::Request) => {
+                self.stats.neighbor_requests = self.stats.neighbor_requests.wrapping_add(1)
+            }
+            (Direction::Rx, ArpOperation::Reply) => {
+                self.stats.neighbor_replies = self.stats.neighbor_replies.wrapping_add(1)
+            }
+            _ => {}
+        }
+        true
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    #[allow(unused_variables)]
+    fn inspect_ipv4(&mut self, packet: &[u8], dir: Direction) -> bool {
+        use smoltcp::wire::Ipv4Packet;
+
+        let Ok(ip) = Ipv4Packet::new_checked(packet) else {
+            return false;
+        };
+        // Only the first fragment has the transport header.
+        #[cfg(feature = "tcp")]
+        if ip.next_header() == IpProtocol::Tcp && ip.frag_offset() == 0 {
+            self.inspect_tcp(
+                ip.src_addr().into(),
+                ip.dst_addr().into(),
+                ip.payload(),
+                dir,
+                Instant::now(),
+            );
+        }
+        true
+    }
+
+    #[cfg(feature = "proto-ipv6")]
+    fn inspect_ipv6(&mut self, packet: &[u8], dir: Direction) -> bool {
+        use smoltcp::wire::{Icmpv6Message, Icmpv6Packet, Ipv6Packet};
+
+        let Ok(ip) = Ipv6Packet::new_checked(packet) else {
+            return false;
+        };
+        match ip.next_header() {
+            #[cfg(feature = "tcp")]
+            IpProtocol::Tcp => {
+                let (src, dst) = (ip.src_addr().into(), ip.dst_addr().into());
+                self.inspect_tcp(src, dst, ip.payload(), dir, Instant::now())
+            }
+            IpProtocol::Icmpv6 => {
+                let Ok(icmp) = Icmpv6Packet::new_checked(ip.payload()) else {
+                    return false;
+                };
+                match (dir, icmp.msg_type()) {
+                    (Direction::Tx, Icmpv6Message::NeighborSolicit) => {
+                        self.stats.neighbor_requests = self.stats.neighbor_requests.wrapping_add(1)
+                    }
+                    (Direction::Rx, Icmpv6Message::NeighborAdvert) => {
+                        self.stats.neighbor_replies = self.stats.neighbor_replies.wrapping_add(1)
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    #[cfg(feature = "tcp")]
+    fn inspect_tcp(&mut self, src: IpAddress, dst: IpAddress, segment: &[u8], dir: Direction, now: Instant) {
+        let Ok(tcp) = TcpPacket::new_checked(segment) else {
+            return;
+        };
+        let src = IpEndpoint::new(src, tcp.src_port());
+        let dst = IpEndpoint::new(dst, tcp.dst_port());
+        let (local, remote) = match dir {
+            Direction::Tx => (src, dst),
+            Direction::Rx => (dst, src),
+        };
+
+        let conn = match self.tcp.iter().position(|c| c.local == local && c.remote == remote) {
+            Some(i) => &mut self.tcp[i],
+            // Only start tracking connections we send data on.
+            None if dir == Direction::Rx => return,
+            None => {
+                if self.tcp.is_full() {
+                    let (lru, _) = unwrap!(self.tcp.iter().enumerate().min_by_key(|(_, c)| c.last_used));
+                    self.tcp.swap_remove(lru);
+                }
+                let conn = TcpConnection {
+                    local,
+                    remote,
+                    snd_max: None,
+                    sample: None,
+                    srtt: None,
+                    retransmits: 0,
+                    last_used: now,
+                };
+                unwrap!(self.tcp.push(conn).ok());
+                unwrap!(self.tcp.last_mut())
+            }
+        };
+        conn.last_used = now;
+
+        match dir {
+            Direction::Tx => {
+                // A SYN without ACK opens a new connection on a reused port pair.
+                if tcp.syn() && !tcp.ack() && conn.snd_max.is_some_and(|max| tcp.seq_number() + 1 != max) {
+                    conn.snd_max = None;
+                    conn.sample = None;
+                    conn.srtt = None;
+                    conn.retransmits = 0;
+                }
+
+                let len = tcp.payload().len() + tcp.syn() as usize + tcp.fin() as usize;
+                if len == 0 {
+                    return;
+                }
+                let seq = tcp.seq_number();
+                let end = seq + len;
+                match conn.snd_max {
+                    Some(max) if seq < max => {
+                        conn.retransmits = conn.retransmits.wrapping_add(1);
+                        self.stats.tcp_retransmits = self.stats.tcp_retransmits.wrapping_add(1);
+                        // Karn's algorithm: ACKs for retransmitted data are ambiguous, don't time them.
+                        conn.sample = None;
+                        if end > max {
+                            conn.snd_max = Some(end);
+                        }
+                    }
+                    _ => {
+                        conn.snd_max = Some(end);
+                        if conn.sample.is_none() {
+                            conn.sample = Some((end, now));
+                        }
+                    }
+                }
+            }
+            Direction::Rx => {
+                if let Some((end, sent)) = conn.sample {
+                    if tcp.ack() && tcp.ack_number() >= end {
+                        let rtt = now - sent;
+                        conn.srtt = Some(match conn.srtt {
+                            // RFC 6298: SRTT <- 7/8 * SRTT + 1/8 * R'
+                            Some(srtt) => Duration::from_ticks((srtt.as_ticks() * 7 + rtt.as_ticks()) / 8),
+                            None => rtt,
+                        });
+                        conn.sample = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "medium-ethernet",
+    feature = "proto-ipv4",
+    feature = "proto-ipv6",
+    feature = "tcp"
+))]
+mod tests {
+    use smoltcp::wire::{
+        ArpOperation, ArpPacket, EthernetAddress, EthernetFrame, EthernetProtocol, Icmpv6Message, Icmpv6Packet,
+        Ipv4Address, Ipv6Address, Ipv6Packet,
+    };
+
+    use super::*;
+
+    const LOCAL: IpEndpoint = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address([192, 0, 2, 1])), 49152);
+    const REMOTE: IpEndpoint = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address([192, 0, 2, 2])), 80);
+
+    fn arp(operation: ArpOperation) -> [u8; 14 + 28] {
+        let mut buf = [0; 14 + 28];
+        let mut eth = EthernetFrame::new_unchecked(&mut buf[..]);
+        eth.set_dst_addr(EthernetAddress::BROADCAST);
+        eth.set_ethertype(EthernetProtocol::Arp);
+        let mut arp = ArpPacket::new_unchecked(eth.payload_mut());
+        arp.set_hardware_type(smoltcp::wire::ArpHardware::Ethernet);
+        arp.set_protocol_type(EthernetProtocol::Ipv4);
+        arp.set_hardware_len(6);
+        arp.set_protocol_len(4);
+        arp.set_operation(operation);
+        buf
+    }
+
+    fn neighbor_advert() -> [u8; 40 + 24] {
+        let mut buf = [0; 40 + 24];
+        let mut ip = Ipv6Packet::new_unchecked(&mut buf[..]);
+        ip.set_version(6);
+        ip.set_payload_len(24);
+        ip.set_next_header(IpProtocol::Icmpv6);
+        ip.set_hop_limit(255);
+        ip.set_dst_addr(Ipv6Address::LINK_LOCAL_ALL_NODES);
+        Icmpv6Packet::new_unchecked(ip.payload_mut()).set_msg_type(Icmpv6Message::NeighborAdvert);
+        buf
+    }
+
+    /// TCP segment from `src` to `dst`, with `len` bytes of payload, a multiple of 4 as the header
+    /// is padded with options.
+    fn segment(src: IpEndpoint, dst: IpEndpoint, flags: (bool, bool), seq: i32, ack: i32, len: usize) -> [u8; 40] {
+        let mut buf = [0; 40];
+        let mut tcp = TcpPacket::new_unchecked(&mut buf[..]);
+        tcp.set_src_port(src.port);
+        tcp.set_dst_port(dst.port);
+        tcp.set_header_len(40 - len as u8);
+        tcp.set_syn(flags.0);
+        tcp.set_ack(flags.1);
+        tcp.set_seq_number(TcpSeqNumber(seq));
+        tcp.set_ack_number(TcpSeqNumber(ack));
+        buf
+    }
+
+    fn send(r: &mut Recorder, flags: (bool, bool), seq: i32, len: usize, ms: u64) {
+        let s = segment(LOCAL, REMOTE, flags, seq, 0, len);
+        r.inspect_tcp(LOCAL.addr, REMOTE.addr, &s, Direction::Tx, Instant::from_millis(ms));
+    }
+
+    fn ack(r: &mut Recorder, ack: i32, ms: u64) {
+        let s = segment(REMOTE, LOCAL, (false, true), 0, ack, 0);
+        r.inspect_tcp(REMOTE.addr, LOCAL.addr, &s, Direction::Rx, Instant::from_millis(ms));
+    }
+
+    #[test]
+    fn counters() {
+        let mut r = Recorder::new();
+        r.record_tx(Medium::Ethernet, &arp(ArpOperation::Request));
+        r.record_rx(Medium::Ethernet, &arp(ArpOperation::Reply));
+        r.record_rx(Medium::Ethernet, &arp(ArpOperation::Request));
+        r.record_rx(Medium::Ip, &neighbor_advert());
+        r.record_rx(Medium::Ethernet, &[0; 10]);
+        r.record_rx(Medium::Ip, &[0x50; 20]);
+
+        let stats = r.stats();
+        assert_eq!((stats.tx_packets, stats.tx_bytes), (1, 42));
+        assert_eq!((stats.rx_packets, stats.rx_bytes), (5, 42 + 42 + 64 + 10 + 20));
+        assert_eq!(stats.rx_dropped, 2);
+        assert_eq!((stats.neighbor_requests, stats.neighbor_replies), (1, 2));
+
+        r.reset();
+        assert_eq!(r.stats(), Stats::default());
+    }
+
+    #[test]
+    fn tcp_rtt() {
+        let mut r = Recorder::new();
+        // Connections are only tracked once we send on them.
+        ack(&mut r, 1, 0);
+        assert_eq!(r.tcp_stats(LOCAL, REMOTE), None);
+
+        send(&mut r, (true, false), 0, 0, 0);
+        ack(&mut r, 1, 100);
+        assert_eq!(
+            r.tcp_stats(LOCAL, REMOTE).unwrap().rtt,
+            Some(Duration::from_millis(100))
+        );
+
+        // ACKs for retransmitted data are not timed.
+        send(&mut r, (false, true), 1, 8, 1000);
+        send(&mut r, (false, true), 1, 8, 1500);
+        ack(&mut r, 9, 1600);
+        let stats = r.tcp_stats(LOCAL, REMOTE).unwrap();
+        assert_eq!(stats.rtt, Some(Duration::from_millis(100)));
+        assert_eq!(stats.retransmits, 1);
+        assert_eq!(r.stats().tcp_retransmits, 1);
+
+        // Smoothed with the next sample, and partial ACKs don't end it.
+        send(&mut r, (false, true), 9, 8, 2000);
+        send(&mut r, (false, true), 17, 8, 2050);
+        ack(&mut r, 13, 2100);
+        ack(&mut r, 25, 2200);
+        assert_eq!(
+            r.tcp_stats(LOCAL, REMOTE).unwrap().rtt,
+            Some(Duration::from_micros(112_500))
+        );
+
+        // A new connection on the same port pair starts from scratch.
+        send(&mut r, (true, false), 5000, 0, 3000);
+        assert_eq!(
+            r.tcp_stats(LOCAL, REMOTE),
+            Some(TcpStats {
+                rtt: None,
+                retransmits: 0
+            })
+        );
+
+        // Retransmitted SYN. The count is reset with the interface counters.
+        send(&mut r, (true, false), 5000, 0, 4000);
+        assert_eq!(r.tcp_stats(LOCAL, REMOTE).unwrap().retransmits, 1);
+        r.reset();
+        assert_eq!(r.tcp_stats(LOCAL, REMOTE).unwrap().retransmits, 0);
+    }
+
+    #[test]
+    fn tcp_connections_evicted() {
+        let mut r = Recorder::new();
+        for i in 0..=MAX_TCP_CONNECTIONS as u16 {
+            let local = IpEndpoint::new(LOCAL.addr, 49152 + i);
+            let s = segment(local, REMOTE, (true, false), 0, 0, 0);
+            r.inspect_tcp(LOCAL.addr, REMOTE.addr, &s, Direction::Tx, Instant::from_secs(i as u64));
+        }
+        // The least recently used connection was forgotten.
+        assert_eq!(r.tcp_stats(LOCAL, REMOTE), None);
+        for i in 1..=MAX_TCP_CONNECTIONS as u16 {
+            assert!(r.tcp_stats(IpEndpoint::new(LOCAL.addr, 49152 + i), REMOTE).is_some());
+        }
+    }
+}
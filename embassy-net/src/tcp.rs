@@ -20,7 +20,7 @@ use smoltcp::socket::tcp;
 pub use smoltcp::socket::tcp::State;
 use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
 
-use crate::time::duration_to_smoltcp;
+use crate::time::{duration_from_smoltcp, duration_to_smoltcp};
 use crate::{SocketStack, Stack};
 
 /// Error returned by TcpSocket read/write functions.
@@ -128,6 +128,21 @@ impl<'a> TcpWriter<'a> {
         self.io.write_with(f).await
     }
 
+    /// Write data directly into the transmit buffer, without an intermediate copy.
+    ///
+    /// `f` is called with a free region of the transmit buffer and returns how many bytes it wrote
+    /// there, at most the length of the region. If `f` fills the region and the free space wraps
+    /// around the end of the ring buffer, it is called again with the rest of the free space.
+    ///
+    /// Returns the total number of bytes written. If the socket is not ready to accept data, it
+    /// waits until it is.
+    pub async fn send_zero_copy<F>(&mut self, f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&mut [u8]) -> usize,
+    {
+        self.io.send_zero_copy(f).await
+    }
+
     /// Return the maximum number of bytes inside the transmit buffer.
     pub fn send_capacity(&self) -> usize {
         self.io.send_capacity()
@@ -136,6 +151,10 @@ impl<'a> TcpWriter<'a> {
 
 impl<'a> TcpSocket<'a> {
     /// Create a new TCP socket on the given stack, with the given buffers.
+    ///
+    /// The window scale advertised to the remote host is derived from the size of `rx_buffer`:
+    /// receive buffers larger than 64 KiB enable window scaling, which is needed to reach high
+    /// throughput on links with a large bandwidth-delay product.
     pub fn new<D: Driver>(stack: &'a Stack<D>, rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Self {
         let s = &mut *stack.socket.borrow_mut();
         let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
@@ -174,6 +193,21 @@ impl<'a> TcpSocket<'a> {
         self.io.write_with(f).await
     }
 
+    /// Write data directly into the transmit buffer, without an intermediate copy.
+    ///
+    /// `f` is called with a free region of the transmit buffer and returns how many bytes it wrote
+    /// there, at most the length of the region. If `f` fills the region and the free space wraps
+    /// around the end of the ring buffer, it is called again with the rest of the free space.
+    ///
+    /// Returns the total number of bytes written. If the socket is not ready to accept data, it
+    /// waits until it is.
+    pub async fn send_zero_copy<F>(&mut self, f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&mut [u8]) -> usize,
+    {
+        self.io.send_zero_copy(f).await
+    }
+
     /// Call `f` with the largest contiguous slice of octets in the receive buffer,
     /// and dequeue the amount of elements returned by `f`.
     ///
@@ -278,6 +312,11 @@ impl<'a> TcpSocket<'a> {
             .with_mut(|s, _| s.set_timeout(duration.map(duration_to_smoltcp)))
     }
 
+    /// Get the timeout of the socket.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.io.with(|s, _| s.timeout().map(duration_from_smoltcp))
+    }
+
     /// Set the keep-alive interval for the socket.
     ///
     /// If the keep-alive interval is set, the socket will send keep-alive packets after
@@ -289,6 +328,40 @@ impl<'a> TcpSocket<'a> {
             .with_mut(|s, _| s.set_keep_alive(interval.map(duration_to_smoltcp)))
     }
 
+    /// Get the keep-alive interval of the socket.
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.io.with(|s, _| s.keep_alive().map(duration_from_smoltcp))
+    }
+
+    /// Enable or disable Nagle's algorithm.
+    ///
+    /// When enabled (the default), small writes are held back while previously sent data is
+    /// not acknowledged yet, so that they can be coalesced into full segments. Disabling it
+    /// lowers the latency of small writes, at the cost of sending more packets.
+    pub fn set_nagle_enabled(&mut self, enabled: bool) {
+        self.io.with_mut(|s, _| s.set_nagle_enabled(enabled))
+    }
+
+    /// Get whether Nagle's algorithm is enabled.
+    pub fn nagle_enabled(&self) -> bool {
+        self.io.with(|s, _| s.nagle_enabled())
+    }
+
+    /// Set the delayed ACK timeout.
+    ///
+    /// ACKs for received data are delayed by up to this duration, so they can be sent along
+    /// with response data. The default is 10 milliseconds. `None` sends ACKs immediately, which
+    /// helps bulk transfers to peers that wait for ACKs before sending more.
+    pub fn set_ack_delay(&mut self, duration: Option<Duration>) {
+        self.io
+            .with_mut(|s, _| s.set_ack_delay(duration.map(duration_to_smoltcp)))
+    }
+
+    /// Get the delayed ACK timeout.
+    pub fn ack_delay(&self) -> Option<Duration> {
+        self.io.with(|s, _| s.ack_delay().map(duration_from_smoltcp))
+    }
+
     /// Set the hop limit field in the IP header of sent packets.
     pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
         self.io.with_mut(|s, _| s.set_hop_limit(hop_limit))
@@ -308,6 +381,18 @@ impl<'a> TcpSocket<'a> {
         self.io.with(|s, _| s.remote_endpoint())
     }
 
+    /// Get the statistics of the current connection.
+    ///
+    /// Returns `None` if the socket isn't connected, or no segment was sent on this connection yet.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Option<crate::stats::TcpStats> {
+        let s = &*self.io.stack.borrow();
+        let socket = s.sockets.get::<tcp::Socket>(self.io.handle);
+        let (local, remote) = (socket.local_endpoint()?, socket.remote_endpoint()?);
+        let stats = s.taps.stats.borrow();
+        stats.tcp_stats(local, remote)
+    }
+
     /// Get the state of the socket.
     pub fn state(&self) -> State {
         self.io.with(|s, _| s.state())
@@ -458,6 +543,47 @@ impl<'d> TcpIo<'d> {
         .await
     }
 
+    async fn send_zero_copy<F>(&mut self, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&mut [u8]) -> usize,
+    {
+        poll_fn(move |cx| {
+            self.with_mut(|s, _| {
+                if !s.can_send() {
+                    if s.may_send() {
+                        // socket buffer is full wait until it has atleast one byte free
+                        s.register_send_waker(cx.waker());
+                        return Poll::Pending;
+                    } else {
+                        // if we can't transmit because the transmit half of the duplex connection is closed then return an error
+                        return Poll::Ready(Err(Error::ConnectionReset));
+                    }
+                }
+
+                // The free space can wrap around the end of the ring buffer, so fill it in up
+                // to two contiguous parts.
+                let mut total = 0;
+                for _ in 0..2 {
+                    match s.send(|buf| {
+                        let n = f(buf);
+                        (n, (n, buf.len()))
+                    }) {
+                        Ok((n, len)) => {
+                            total += n;
+                            if n < len || !s.can_send() {
+                                break;
+                            }
+                        }
+                        // Connection reset. TODO: this can also be timeouts etc, investigate.
+                        Err(tcp::SendError::InvalidState) => return Poll::Ready(Err(Error::ConnectionReset)),
+                    }
+                }
+                Poll::Ready(Ok(total))
+            })
+        })
+        .await
+    }
+
     async fn read_with<F, R>(&mut self, f: F) -> Result<R, Error>
     where
         F: FnOnce(&mut [u8]) -> (usize, R),
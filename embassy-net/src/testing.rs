@@ -0,0 +1,67 @@
+//! Helpers for the unit tests.
+
+extern crate std;
+
+use core::task::Context;
+use std::boxed::Box;
+
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState};
+
+use crate::{Config, Stack, StackResources};
+
+/// Driver which never sends or receives anything, on the IP medium.
+pub(crate) struct NoDevice {
+    pub mtu: usize,
+}
+
+pub(crate) enum NoToken {}
+
+impl embassy_net_driver::RxToken for NoToken {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, _f: F) -> R {
+        match self {}
+    }
+}
+
+impl embassy_net_driver::TxToken for NoToken {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, _len: usize, _f: F) -> R {
+        match self {}
+    }
+}
+
+impl Driver for NoDevice {
+    type RxToken<'a> = NoToken;
+    type TxToken<'a> = NoToken;
+
+    fn receive(&mut self, _cx: &mut Context) -> Option<(NoToken, NoToken)> {
+        None
+    }
+
+    fn transmit(&mut self, _cx: &mut Context) -> Option<NoToken> {
+        None
+    }
+
+    fn link_state(&mut self, _cx: &mut Context) -> LinkState {
+        LinkState::Down
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        caps.max_transmission_unit = self.mtu;
+        caps
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        HardwareAddress::Ip
+    }
+}
+
+/// Create a stack on a [`NoDevice`], which lives until the end of the tests.
+pub(crate) fn stack(mtu: usize, config: Config) -> &'static Stack<NoDevice> {
+    let resources = Box::leak(Box::new(StackResources::<4>::new()));
+    Box::leak(Box::new(Stack::new(NoDevice { mtu }, config, resources, 0)))
+}
+
+/// Leak a buffer filled with `value`, for sockets living until the end of the tests.
+pub(crate) fn buffer<T: Clone + 'static>(value: T, len: usize) -> &'static mut [T] {
+    std::vec![value; len].leak()
+}
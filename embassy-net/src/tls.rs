@@ -0,0 +1,429 @@
+//! TLS over TCP sockets.
+//!
+//! This module doesn't implement TLS itself. It provides the glue between a [`TcpSocket`] and a
+//! TLS implementation (such as `embedded-tls`, or `rustls` on `std` targets): a [`TlsConnector`]
+//! trait for the implementation to provide, buffer management with [`TlsBuffers`], and a
+//! [`TlsSocket`] that connects, performs the handshake and then behaves like any other
+//! `embedded-io-async` stream.
+//!
+//! With the `embedded-tls` feature, [`EmbeddedTlsConnector`] implements [`TlsConnector`] with the
+//! `embedded-tls` crate.
+//!
+//! Server name indication, ALPN and certificate verification are configured with [`TlsConfig`]
+//! and passed to the connector, so applications only deal with one configuration type whatever
+//! the TLS implementation is.
+
+use embassy_time::{with_timeout, Duration};
+use heapless::Vec;
+
+use crate::tcp::{self, TcpSocket};
+use crate::IpEndpoint;
+
+#[cfg(feature = "embedded-tls")]
+mod embedded_tls;
+#[cfg(feature = "embedded-tls")]
+pub use self::embedded_tls::EmbeddedTlsConnector;
+
+/// Maximum size of a TLS record, including the record header and encryption overhead.
+///
+/// A receive buffer of this size is needed unless the peer is known to send smaller records,
+/// e.g. through the max fragment length extension.
+pub const MAX_RECORD_LEN: usize = 16384 + 256;
+
+/// Maximum length of a negotiated ALPN protocol name.
+pub const MAX_ALPN_LEN: usize = 32;
+
+/// Errors returned by a [`CertificateVerifier`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CertificateError {
+    /// A certificate could not be parsed.
+    BadEncoding,
+    /// A certificate is expired or not yet valid.
+    Expired,
+    /// The chain doesn't lead to a trusted certificate.
+    UnknownIssuer,
+    /// The certificate isn't valid for the server name.
+    NameMismatch,
+    /// The certificate was rejected for another reason.
+    Rejected,
+}
+
+/// Hook verifying the certificate chain presented by the server.
+///
+/// The [`TlsConnector`] calls it during the handshake, after checking the signatures of the
+/// handshake itself.
+pub trait CertificateVerifier {
+    /// Verify a certificate chain.
+    ///
+    /// `chain` contains the DER-encoded certificates as sent by the server, starting with the
+    /// server's own certificate. `server_name` is [`TlsConfig::server_name`].
+    fn verify(&self, server_name: Option<&str>, chain: &[&[u8]]) -> Result<(), CertificateError>;
+}
+
+/// TLS configuration.
+#[derive(Clone, Copy)]
+#[non_exhaustive]
+pub struct TlsConfig<'a> {
+    /// Server name, sent with the server name indication (SNI) extension and used for
+    /// certificate verification.
+    pub server_name: Option<&'a str>,
+    /// Protocols offered with the ALPN extension, in order of preference, e.g. `[b"http/1.1"]`.
+    pub alpn: &'a [&'a [u8]],
+    /// Certificate verification hook.
+    ///
+    /// If `None`, the server certificate is not verified at all. This is only safe if the
+    /// connection is authenticated by other means, e.g. a pre-shared key.
+    pub verifier: Option<&'a dyn CertificateVerifier>,
+    /// Maximum duration of the handshake.
+    pub handshake_timeout: Option<Duration>,
+}
+
+impl<'a> TlsConfig<'a> {
+    /// Create a configuration for connecting to `server_name`, verifying its certificate with `verifier`.
+    pub fn new(server_name: &'a str, verifier: &'a dyn CertificateVerifier) -> Self {
+        Self {
+            server_name: Some(server_name),
+            alpn: &[],
+            verifier: Some(verifier),
+            handshake_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Create a configuration which doesn't verify the server certificate.
+    pub fn insecure() -> Self {
+        Self {
+            server_name: None,
+            alpn: &[],
+            verifier: None,
+            handshake_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Information about an established TLS session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SessionInfo {
+    /// Protocol selected by the server with ALPN, if any.
+    pub alpn_protocol: Option<Vec<u8, MAX_ALPN_LEN>>,
+}
+
+/// A TLS client implementation.
+///
+/// Implementations run the handshake over the provided socket, honoring the [`TlsConfig`]: send
+/// the server name and ALPN protocols, and call the [`CertificateVerifier`] if one is set.
+pub trait TlsConnector<'a> {
+    /// Error type of the implementation.
+    type Error: embedded_io_async::Error;
+    /// Established TLS connection.
+    type Connection: embedded_io_async::Read<Error = Self::Error> + embedded_io_async::Write<Error = Self::Error>;
+
+    /// Perform the handshake over a connected socket.
+    ///
+    /// `rx_buffer` and `tx_buffer` hold incoming and outgoing records.
+    async fn connect(
+        &mut self,
+        socket: TcpSocket<'a>,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+        config: &TlsConfig<'_>,
+    ) -> Result<(Self::Connection, SessionInfo), Self::Error>;
+
+    /// Send a `close_notify` alert, and give the socket back.
+    async fn close(&mut self, connection: Self::Connection) -> Result<TcpSocket<'a>, Self::Error>;
+}
+
+/// Record buffers for a [`TlsSocket`].
+///
+/// The default sizes allow receiving records of any size, and sending records of up to 4 KiB.
+pub struct TlsBuffers<const RX: usize = MAX_RECORD_LEN, const TX: usize = 4096> {
+    rx: [u8; RX],
+    tx: [u8; TX],
+}
+
+impl<const RX: usize, const TX: usize> TlsBuffers<RX, TX> {
+    /// Create new buffers.
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; RX],
+            tx: [0; TX],
+        }
+    }
+
+    fn split(&mut self) -> (&mut [u8], &mut [u8]) {
+        (&mut self.rx, &mut self.tx)
+    }
+}
+
+impl<const RX: usize, const TX: usize> Default for TlsBuffers<RX, TX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Errors returned when establishing a TLS connection.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The TCP connection could not be established.
+    Connect(tcp::ConnectError),
+    /// The handshake didn't complete in time.
+    Timeout,
+    /// The TLS implementation returned an error.
+    Tls(E),
+}
+
+/// A TLS connection over a [`TcpSocket`].
+pub struct TlsSocket<'a, C: TlsConnector<'a>> {
+    connection: C::Connection,
+    info: SessionInfo,
+}
+
+impl<'a, C: TlsConnector<'a>> TlsSocket<'a, C> {
+    /// Connect the socket to `remote_endpoint` and perform the handshake.
+    pub async fn connect<const RX: usize, const TX: usize, T>(
+        connector: &mut C,
+        mut socket: TcpSocket<'a>,
+        remote_endpoint: T,
+        buffers: &'a mut TlsBuffers<RX, TX>,
+        config: &TlsConfig<'_>,
+    ) -> Result<Self, Error<C::Error>>
+    where
+        T: Into<IpEndpoint>,
+    {
+        socket.connect(remote_endpoint).await.map_err(Error::Connect)?;
+        Self::handshake(connector, socket, buffers, config).await
+    }
+
+    /// Perform the handshake over an already connected socket.
+    pub async fn handshake<const RX: usize, const TX: usize>(
+        connector: &mut C,
+        socket: TcpSocket<'a>,
+        buffers: &'a mut TlsBuffers<RX, TX>,
+        config: &TlsConfig<'_>,
+    ) -> Result<Self, Error<C::Error>> {
+        let (rx, tx) = buffers.split();
+        let handshake = connector.connect(socket, rx, tx, config);
+        let res = match config.handshake_timeout {
+            Some(timeout) => with_timeout(timeout, handshake).await.map_err(|_| Error::Timeout)?,
+            None => handshake.await,
+        };
+
+        let (connection, info) = res.map_err(Error::Tls)?;
+        debug!("tls: connected, alpn: {:?}", info.alpn_protocol.as_deref());
+        Ok(Self { connection, info })
+    }
+
+    /// Get information about the session.
+    pub fn session_info(&self) -> &SessionInfo {
+        &self.info
+    }
+
+    /// Get the protocol selected with ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.info.alpn_protocol.as_deref()
+    }
+
+    /// Close the TLS session, and give the socket back.
+    ///
+    /// The TCP connection is still open, call [`TcpSocket::close`] to close it.
+    pub async fn close(self, connector: &mut C) -> Result<TcpSocket<'a>, C::Error> {
+        connector.close(self.connection).await
+    }
+}
+
+impl<'a, C: TlsConnector<'a>> embedded_io_async::ErrorType for TlsSocket<'a, C> {
+    type Error = C::Error;
+}
+
+impl<'a, C: TlsConnector<'a>> embedded_io_async::Read for TlsSocket<'a, C> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.connection.read(buf).await
+    }
+}
+
+impl<'a, C: TlsConnector<'a>> embedded_io_async::Write for TlsSocket<'a, C> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.connection.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.connection.flush().await
+    }
+}
+
+#[cfg(all(test, feature = "medium-ip"))]
+mod tests {
+    extern crate std;
+
+    use core::future::pending;
+    use std::vec::Vec as StdVec;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::testing::stack;
+    use crate::Config;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct MockError;
+
+    impl embedded_io_async::Error for MockError {
+        fn kind(&self) -> embedded_io_async::ErrorKind {
+            embedded_io_async::ErrorKind::Other
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    enum Handshake {
+        Complete,
+        Fail,
+        Hang,
+    }
+
+    /// Records the calls made by [`TlsSocket`], and echoes written data back.
+    struct MockConnector {
+        handshake: Handshake,
+        server_name: Option<StdVec<u8>>,
+        closed: bool,
+    }
+
+    impl MockConnector {
+        fn new(handshake: Handshake) -> Self {
+            Self {
+                handshake,
+                server_name: None,
+                closed: false,
+            }
+        }
+    }
+
+    struct MockConnection<'a> {
+        socket: TcpSocket<'a>,
+        data: StdVec<u8>,
+    }
+
+    impl<'a> embedded_io_async::ErrorType for MockConnection<'a> {
+        type Error = MockError;
+    }
+
+    impl<'a> embedded_io_async::Read for MockConnection<'a> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, MockError> {
+            let n = buf.len().min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl<'a> embedded_io_async::Write for MockConnection<'a> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, MockError> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    impl<'a> TlsConnector<'a> for MockConnector {
+        type Error = MockError;
+        type Connection = MockConnection<'a>;
+
+        async fn connect(
+            &mut self,
+            socket: TcpSocket<'a>,
+            _rx_buffer: &'a mut [u8],
+            _tx_buffer: &'a mut [u8],
+            config: &TlsConfig<'_>,
+        ) -> Result<(Self::Connection, SessionInfo), MockError> {
+            self.server_name = config.server_name.map(|n| n.as_bytes().to_vec());
+            match self.handshake {
+                Handshake::Complete => {}
+                Handshake::Fail => return Err(MockError),
+                Handshake::Hang => pending().await,
+            }
+
+            let info = SessionInfo {
+                alpn_protocol: config.alpn.first().map(|p| Vec::from_slice(p).unwrap()),
+            };
+            let connection = MockConnection {
+                socket,
+                data: StdVec::new(),
+            };
+            Ok((connection, info))
+        }
+
+        async fn close(&mut self, connection: Self::Connection) -> Result<TcpSocket<'a>, MockError> {
+            self.closed = true;
+            Ok(connection.socket)
+        }
+    }
+
+    struct AcceptAll;
+
+    impl CertificateVerifier for AcceptAll {
+        fn verify(&self, _server_name: Option<&str>, _chain: &[&[u8]]) -> Result<(), CertificateError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn handshake_then_close() {
+        use embedded_io_async::{Read, Write};
+
+        let stack = stack(1500, Config::default());
+        let (mut rx, mut tx) = ([0; 64], [0; 64]);
+        let socket = TcpSocket::new(stack, &mut rx, &mut tx);
+        let mut buffers = TlsBuffers::<64, 64>::new();
+        let mut connector = MockConnector::new(Handshake::Complete);
+        let mut config = TlsConfig::new("example.com", &AcceptAll);
+        config.alpn = &[b"mqtt", b"http/1.1"];
+
+        block_on(async {
+            let mut tls = TlsSocket::handshake(&mut connector, socket, &mut buffers, &config)
+                .await
+                .unwrap();
+            assert_eq!(tls.alpn_protocol(), Some(&b"mqtt"[..]));
+
+            tls.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            tls.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+
+            tls.close(&mut connector).await.unwrap();
+        });
+        assert_eq!(connector.server_name.as_deref(), Some(&b"example.com"[..]));
+        assert!(connector.closed);
+    }
+
+    #[test]
+    fn handshake_error() {
+        let stack = stack(1500, Config::default());
+        let (mut rx, mut tx) = ([0; 64], [0; 64]);
+        let socket = TcpSocket::new(stack, &mut rx, &mut tx);
+        let mut buffers = TlsBuffers::<64, 64>::new();
+        let mut connector = MockConnector::new(Handshake::Fail);
+
+        let res = block_on(TlsSocket::handshake(
+            &mut connector,
+            socket,
+            &mut buffers,
+            &TlsConfig::insecure(),
+        ));
+        assert!(matches!(res, Err(Error::Tls(MockError))));
+        assert!(!connector.closed);
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let stack = stack(1500, Config::default());
+        let (mut rx, mut tx) = ([0; 64], [0; 64]);
+        let socket = TcpSocket::new(stack, &mut rx, &mut tx);
+        let mut buffers = TlsBuffers::<64, 64>::new();
+        let mut connector = MockConnector::new(Handshake::Hang);
+        let mut config = TlsConfig::insecure();
+        config.handshake_timeout = Some(Duration::from_ticks(0));
+
+        let res = block_on(TlsSocket::handshake(&mut connector, socket, &mut buffers, &config));
+        assert!(matches!(res, Err(Error::Timeout)));
+    }
+}
@@ -0,0 +1,67 @@
+//! [`TlsConnector`] backed by the `embedded-tls` crate.
+
+use embedded_tls::{CryptoProvider, TlsConnection, TlsContext, TlsError};
+
+use super::{SessionInfo, TlsConfig, TlsConnector};
+use crate::tcp::TcpSocket;
+
+/// TLS 1.3 client using `embedded-tls`.
+///
+/// `new_provider` is called for each handshake and returns the `embedded-tls` crypto provider,
+/// which selects the cipher suite and verifies the server certificate. For example, to connect
+/// without verifying the certificate:
+///
+/// ```ignore
+/// let mut connector = EmbeddedTlsConnector::new(|| UnsecureProvider::new::<Aes128GcmSha256>(rng.clone()));
+/// ```
+///
+/// `embedded-tls` verifies certificates with the provider only, so [`TlsConfig::verifier`] is not
+/// supported: the handshake fails with [`TlsError::Unimplemented`] if it is set, rather than
+/// connecting without calling it. ALPN protocols are not offered, so
+/// [`SessionInfo::alpn_protocol`] is always `None`.
+pub struct EmbeddedTlsConnector<F> {
+    new_provider: F,
+}
+
+impl<F> EmbeddedTlsConnector<F> {
+    /// Create a new connector.
+    pub fn new(new_provider: F) -> Self {
+        Self { new_provider }
+    }
+}
+
+impl<'a, F, P> TlsConnector<'a> for EmbeddedTlsConnector<F>
+where
+    F: FnMut() -> P,
+    P: CryptoProvider,
+{
+    type Error = TlsError;
+    type Connection = TlsConnection<'a, TcpSocket<'a>, P::CipherSuite>;
+
+    async fn connect(
+        &mut self,
+        socket: TcpSocket<'a>,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+        config: &TlsConfig<'_>,
+    ) -> Result<(Self::Connection, SessionInfo), Self::Error> {
+        if config.verifier.is_some() {
+            return Err(TlsError::Unimplemented);
+        }
+
+        let mut tls_config = embedded_tls::TlsConfig::new();
+        if let Some(server_name) = config.server_name {
+            tls_config = tls_config.with_server_name(server_name);
+        }
+
+        let mut connection = TlsConnection::new(socket, rx_buffer, tx_buffer);
+        connection
+            .open(TlsContext::new(&tls_config, (self.new_provider)()))
+            .await?;
+        Ok((connection, SessionInfo::default()))
+    }
+
+    async fn close(&mut self, connection: Self::Connection) -> Result<TcpSocket<'a>, Self::Error> {
+        connection.close().await.map_err(|(_, e)| e)
+    }
+}
@@ -0,0 +1,835 @@
+//! WebSocket client and server (RFC 6455).
+//!
+//! A [`WebSocket`] runs over any `embedded-io-async` stream, usually a [`TcpSocket`](crate::tcp::TcpSocket)
+//! or a [`TlsSocket`](crate::tls::TlsSocket). The opening handshake is done by one of:
+//! - [`connect`], as a client, for example to stream telemetry to a cloud gateway.
+//! - [`upgrade`], as a server, from a route of the HTTP [`Server`](crate::http::Server), so the
+//!   WebSocket is served next to the other pages of the device.
+//! - [`accept`], as a server, directly on a TCP connection, for any request path.
+//!
+//! Fragmented messages are reassembled in the receive buffer, and pings are answered. Like the
+//! MQTT client, nothing runs in the background: [`WebSocket::recv`] must be called continuously,
+//! and it also sends keep-alive pings when the connection is idle.
+//!
+//! Extensions, such as compression, are not supported.
+
+use core::ops::Range;
+use core::str;
+
+use embassy_time::{with_deadline, Duration, Instant};
+use embedded_io_async::{ErrorKind, Read, Write};
+use heapless::{String, Vec};
+
+use crate::http::{self, Header, Method, ServerRequest, MAX_HEADERS};
+
+const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Maximum payload length of control frames.
+const MAX_CONTROL_LEN: usize = 125;
+
+/// Normal closure.
+pub const CLOSE_NORMAL: u16 = 1000;
+/// The endpoint is going away, for example a server shutting down.
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+/// The peer violated the protocol.
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// The close frame had no status code.
+pub const CLOSE_NO_STATUS: u16 = 1005;
+/// A text message wasn't valid UTF-8.
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+/// A message was too large to be processed.
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+/// Errors returned by the WebSocket client and server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The transport returned an error.
+    Io(ErrorKind),
+    /// The connection was closed.
+    ConnectionClosed,
+    /// The HTTP part of the handshake failed.
+    Http(http::Error),
+    /// The server answered the handshake with the given status, instead of switching protocols.
+    Rejected(u16),
+    /// The peer sent an invalid handshake or frame.
+    Protocol,
+    /// A received message doesn't fit in the buffer, or a control frame payload is too long.
+    MessageTooLarge,
+    /// The peer closed the WebSocket, with the given status code.
+    Closed(u16),
+    /// The peer didn't answer a keep-alive ping in time.
+    Timeout,
+}
+
+impl From<http::Error> for Error {
+    fn from(e: http::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+fn io<E: embedded_io_async::Error>(e: E) -> Error {
+    Error::Io(e.kind())
+}
+
+/// A WebSocket message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message<'a> {
+    /// Text message.
+    Text(&'a str),
+    /// Binary message.
+    Binary(&'a [u8]),
+}
+
+impl<'a> Message<'a> {
+    fn opcode(&self) -> u8 {
+        match self {
+            Message::Text(_) => OPCODE_TEXT,
+            Message::Binary(_) => OPCODE_BINARY,
+        }
+    }
+
+    fn data(&self) -> &'a [u8] {
+        match self {
+            Message::Text(text) => text.as_bytes(),
+            Message::Binary(data) => data,
+        }
+    }
+}
+
+/// WebSocket configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// Send a ping when nothing was received for this long. `None` disables keep-alive pings.
+    pub ping_interval: Option<Duration>,
+    /// How long to wait for the answer to a keep-alive ping before failing with [`Error::Timeout`].
+    pub pong_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ping_interval: Some(Duration::from_secs(30)),
+            pong_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self(if seed == 0 { 0x853c_49e6_748f_ea9b } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+/// Open a WebSocket as a client, over `transport`, which must be connected already.
+///
+/// `host` and `path` are the `Host` header and the request target. `headers` are added to the
+/// request, for example `Sec-WebSocket-Protocol` or `Authorization`. `buffer` must be able to hold
+/// the head of the response, and the largest message received. `random_seed` is used to generate the
+/// handshake key and the masking keys.
+pub async fn connect<'b, T: Read + Write>(
+    mut transport: T,
+    host: &str,
+    path: &str,
+    headers: &[Header<'_>],
+    buffer: &'b mut [u8],
+    config: Config,
+    random_seed: u64,
+) -> Result<WebSocket<'b, T>, Error> {
+    let mut random = Random::new(random_seed);
+    let mut nonce = [0; 16];
+    nonce[..8].copy_from_slice(&random.next().to_ne_bytes());
+    nonce[8..].copy_from_slice(&random.next().to_ne_bytes());
+    let key: String<24> = base64(&nonce);
+
+    let mut request_headers: Vec<Header<'_>, MAX_HEADERS> = Vec::new();
+    let handshake = [
+        Header::new("Upgrade", "websocket"),
+        Header::new("Connection", "Upgrade"),
+        Header::new("Sec-WebSocket-Key", &key),
+        Header::new("Sec-WebSocket-Version", "13"),
+    ];
+    for header in handshake.iter().chain(headers) {
+        request_headers
+            .push(*header)
+            .map_err(|_| Error::Http(http::Error::TooManyHeaders))?;
+    }
+    let start_line = ["GET ", path, " HTTP/1.1\r\nHost: ", host];
+    http::write_head(&mut transport, &start_line, &request_headers, None, false).await?;
+    transport.flush().await.map_err(io)?;
+
+    let (head_len, len) = http::read_head(&mut transport, buffer).await?;
+    check_response(&buffer[..head_len], &key)?;
+
+    debug!("websocket: connected");
+    Ok(WebSocket::new(transport, config, Some(random), buffer, head_len..len))
+}
+
+/// Check the head of the handshake response.
+fn check_response(head: &[u8], key: &str) -> Result<(), Error> {
+    let (status_line, headers) = http::parse_head(head)?;
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+        return Err(Error::Protocol);
+    }
+    let status: u16 = parts.next().and_then(|s| s.parse().ok()).ok_or(Error::Protocol)?;
+    if status != 101 {
+        warn!("websocket: handshake rejected: {}", status);
+        return Err(Error::Rejected(status));
+    }
+    let upgraded = http::find_header(&headers, "Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let accepted = http::find_header(&headers, "Sec-WebSocket-Accept") == Some(accept_key(key).as_str());
+    if !upgraded || !accepted {
+        return Err(Error::Protocol);
+    }
+    Ok(())
+}
+
+/// Accept a WebSocket as a server, over an established `transport`, whatever the request path.
+///
+/// Requests that aren't a valid WebSocket handshake are answered with `400 Bad Request`. `buffer` must
+/// be able to hold the head of the request, and the largest message received.
+pub async fn accept<'b, T: Read + Write>(
+    mut transport: T,
+    buffer: &'b mut [u8],
+    config: Config,
+) -> Result<WebSocket<'b, T>, Error> {
+    let (head_len, len) = http::read_head(&mut transport, buffer).await?;
+    let accept = match http::parse_head(&buffer[..head_len]) {
+        Ok((request_line, headers)) if request_line.starts_with("GET ") => handshake_accept(&headers),
+        _ => None,
+    };
+    let accept = match accept {
+        Some(accept) => accept,
+        None => {
+            http::error_response(&mut transport, 400).await?;
+            return Err(Error::Protocol);
+        }
+    };
+
+    let start_line = ["HTTP/1.1 101 ", http::reason_phrase(101)];
+    http::write_head(&mut transport, &start_line, &response_headers(&accept), None, false).await?;
+    transport.flush().await.map_err(io)?;
+
+    debug!("websocket: accepted");
+    Ok(WebSocket::new(transport, config, None, buffer, head_len..len))
+}
+
+/// Accept a WebSocket as a server, from a request received by the HTTP [`Server`](crate::http::Server).
+///
+/// Requests that aren't a valid WebSocket handshake are answered with `400 Bad Request`. `buffer` must
+/// be able to hold the largest message received.
+///
+/// The WebSocket must be served before the handler returns, as the server closes the connection
+/// afterwards. The inactivity timeout of the server still applies, so it must be longer than
+/// [`Config::ping_interval`].
+pub async fn upgrade<'b, 'r, T: Read + Write>(
+    request: ServerRequest<'r, T>,
+    buffer: &'b mut [u8],
+    config: Config,
+) -> Result<WebSocket<'b, &'r mut T>, Error> {
+    let accept = match handshake_accept(request.headers()) {
+        Some(accept) if request.method == Method::Get => accept,
+        _ => {
+            request.respond(400, &[], &[]).await?;
+            return Err(Error::Protocol);
+        }
+    };
+    let transport = request.respond_upgrade(&response_headers(&accept)).await?;
+
+    debug!("websocket: accepted");
+    Ok(WebSocket::new(transport, config, None, buffer, 0..0))
+}
+
+/// Check the headers of a handshake request, and get the `Sec-WebSocket-Accept` value to answer.
+fn handshake_accept(headers: &[Header<'_>]) -> Option<String<28>> {
+    let upgrade = http::find_header(headers, "Upgrade")?;
+    let connection = http::find_header(headers, "Connection")?;
+    let version = http::find_header(headers, "Sec-WebSocket-Version")?;
+    let key = http::find_header(headers, "Sec-WebSocket-Key")?;
+    let valid = upgrade.eq_ignore_ascii_case("websocket")
+        && connection.split(',').any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+        && version == "13";
+    valid.then(|| accept_key(key))
+}
+
+fn response_headers(accept: &str) -> [Header<'_>; 3] {
+    [
+        Header::new("Upgrade", "websocket"),
+        Header::new("Connection", "Upgrade"),
+        Header::new("Sec-WebSocket-Accept", accept),
+    ]
+}
+
+fn accept_key(key: &str) -> String<28> {
+    base64(&sha1(&[key.as_bytes(), GUID]))
+}
+
+fn base64<const N: usize>(data: &[u8]) -> String<N> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            let c = if i <= chunk.len() {
+                ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f]
+            } else {
+                b'='
+            };
+            let _ = out.push(c as char);
+        }
+    }
+    out
+}
+
+fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut block = [0; 64];
+    let mut len = 0;
+    for &b in parts.iter().flat_map(|p| p.iter()) {
+        block[len % 64] = b;
+        len += 1;
+        if len % 64 == 0 {
+            sha1_block(&mut state, &block);
+        }
+    }
+
+    let mut pos = len % 64;
+    block[pos] = 0x80;
+    pos += 1;
+    if pos > 56 {
+        block[pos..].fill(0);
+        sha1_block(&mut state, &block);
+        pos = 0;
+    }
+    block[pos..56].fill(0);
+    block[56..].copy_from_slice(&(len as u64 * 8).to_be_bytes());
+    sha1_block(&mut state, &block);
+
+    let mut digest = [0; 20];
+    for (out, word) in digest.chunks_exact_mut(4).zip(state) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn sha1_block(state: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for (w, b) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, &w) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+            20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+            _ => (b ^ c ^ d, 0xca62_c1d6),
+        };
+        let t = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = t;
+    }
+
+    for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+        *s = s.wrapping_add(v);
+    }
+}
+
+fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= key[i % 4];
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    header_len: usize,
+    len: usize,
+}
+
+/// Parse a frame header. Returns `None` if more bytes are needed.
+fn parse_frame(buf: &[u8]) -> Result<Option<Frame>, Error> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    if buf[0] & 0x70 != 0 {
+        // Reserved bits are only used by extensions.
+        return Err(Error::Protocol);
+    }
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut header_len) = match buf[1] & 0x7f {
+        126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() >= 10 => (u64::from_be_bytes(unwrap!(buf[2..10].try_into().ok())), 10),
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    let mask = if masked {
+        let Some(key) = buf.get(header_len..header_len + 4) else {
+            return Ok(None);
+        };
+        header_len += 4;
+        Some([key[0], key[1], key[2], key[3]])
+    } else {
+        None
+    };
+    Ok(Some(Frame {
+        fin: buf[0] & 0x80 != 0,
+        opcode: buf[0] & 0x0f,
+        mask,
+        header_len,
+        len: usize::try_from(len).map_err(|_| Error::MessageTooLarge)?,
+    }))
+}
+
+/// A WebSocket, after the opening handshake.
+pub struct WebSocket<'b, T: Read + Write> {
+    transport: T,
+    config: Config,
+    /// Source of masking keys. Only clients mask their frames.
+    random: Option<Random>,
+    /// Holds the message being reassembled, followed by the received bytes not processed yet.
+    buffer: &'b mut [u8],
+    msg_len: usize,
+    /// Opcode of the message being reassembled.
+    msg_opcode: Option<u8>,
+    /// Whether the message was returned by `recv`, it is removed on the next call.
+    msg_returned: bool,
+    rx_len: usize,
+    last_received: Instant,
+    ping_sent: Option<Instant>,
+    sending_fragments: bool,
+    close_sent: bool,
+}
+
+impl<'b, T: Read + Write> WebSocket<'b, T> {
+    fn new(transport: T, config: Config, random: Option<Random>, buffer: &'b mut [u8], pending: Range<usize>) -> Self {
+        // Frames sent by the peer right after the handshake may have been read with it.
+        let rx_len = pending.len();
+        buffer.copy_within(pending, 0);
+        Self {
+            transport,
+            config,
+            random,
+            buffer,
+            msg_len: 0,
+            msg_opcode: None,
+            msg_returned: false,
+            rx_len,
+            last_received: Instant::now(),
+            ping_sent: None,
+            sending_fragments: false,
+            close_sent: false,
+        }
+    }
+
+    /// Wait for the next message, answering pings and sending keep-alive pings in the meantime.
+    ///
+    /// When the peer closes the WebSocket, the close is acknowledged and [`Error::Closed`] is returned.
+    pub async fn recv(&mut self) -> Result<Message<'_>, Error> {
+        if self.msg_returned {
+            self.buffer.copy_within(self.msg_len..self.msg_len + self.rx_len, 0);
+            self.msg_len = 0;
+            self.msg_returned = false;
+        }
+
+        loop {
+            // Wait for a frame, or for the keep-alive deadline.
+            let deadline = match (self.config.ping_interval, self.ping_sent) {
+                (None, _) => Instant::MAX,
+                (Some(_), Some(sent)) => sent + self.config.pong_timeout,
+                (Some(interval), None) => self.last_received + interval,
+            };
+            let frame = match with_deadline(deadline, self.read_frame()).await {
+                Ok(Ok(frame)) => frame,
+                Ok(Err(e)) => return Err(self.fail(e).await),
+                Err(_) => {
+                    if self.ping_sent.is_some() {
+                        warn!("websocket: no pong");
+                        return Err(Error::Timeout);
+                    }
+                    trace!("websocket: ping");
+                    self.write_frame(OPCODE_PING, true, &[]).await?;
+                    self.ping_sent = Some(Instant::now());
+                    continue;
+                }
+            };
+            self.last_received = Instant::now();
+
+            let opcode = match self.handle(frame).await {
+                Ok(Some(opcode)) => opcode,
+                Ok(None) => continue,
+                Err(e) => return Err(self.fail(e).await),
+            };
+            self.msg_returned = true;
+
+            if opcode == OPCODE_TEXT && str::from_utf8(&self.buffer[..self.msg_len]).is_err() {
+                self.send_close(CLOSE_INVALID_DATA).await;
+                return Err(Error::Protocol);
+            }
+            let data = &self.buffer[..self.msg_len];
+            return Ok(match opcode {
+                // Validated just above.
+                OPCODE_TEXT => Message::Text(unsafe { str::from_utf8_unchecked(data) }),
+                _ => Message::Binary(data),
+            });
+        }
+    }
+
+    /// Read a complete frame after the message being reassembled.
+    ///
+    /// This is cancel-safe: partially received frames are kept in the buffer.
+    async fn read_frame(&mut self) -> Result<Frame, Error> {
+        loop {
+            let room = self.buffer.len() - self.msg_len;
+            if let Some(frame) = parse_frame(&self.buffer[self.msg_len..self.msg_len + self.rx_len])? {
+                let total = frame.header_len.checked_add(frame.len).ok_or(Error::MessageTooLarge)?;
+                if total > room {
+                    return Err(Error::MessageTooLarge);
+                }
+                if self.rx_len >= total {
+                    return Ok(frame);
+                }
+            } else if self.rx_len == room {
+                return Err(Error::MessageTooLarge);
+            }
+
+            let n = self
+                .transport
+                .read(&mut self.buffer[self.msg_len + self.rx_len..])
+                .await
+                .map_err(io)?;
+            if n == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            self.rx_len += n;
+        }
+    }
+
+    /// Process a received frame. Returns the opcode of the message once it is complete.
+    async fn handle(&mut self, frame: Frame) -> Result<Option<u8>, Error> {
+        // Clients mask all their frames, servers never do.
+        if frame.mask.is_some() == self.random.is_some() {
+            return Err(Error::Protocol);
+        }
+        let start = self.msg_len + frame.header_len;
+        let payload = start..start + frame.len;
+        let end = payload.end;
+        if let Some(key) = frame.mask {
+            apply_mask(&mut self.buffer[payload.clone()], key);
+        }
+
+        match frame.opcode {
+            OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG => {
+                if !frame.fin || frame.len > MAX_CONTROL_LEN {
+                    return Err(Error::Protocol);
+                }
+                let mut data = [0; MAX_CONTROL_LEN];
+                let data = &mut data[..frame.len];
+                data.copy_from_slice(&self.buffer[payload]);
+                self.buffer.copy_within(end..self.msg_len + self.rx_len, self.msg_len);
+                self.rx_len -= end - self.msg_len;
+
+                match frame.opcode {
+                    OPCODE_PING => self.write_frame(OPCODE_PONG, true, data).await?,
+                    OPCODE_PONG => self.ping_sent = None,
+                    _ => {
+                        let code = match data.len() {
+                            0 => CLOSE_NO_STATUS,
+                            1 => return Err(Error::Protocol),
+                            _ => u16::from_be_bytes([data[0], data[1]]),
+                        };
+                        debug!("websocket: closed by peer: {}", code);
+                        if !self.close_sent {
+                            self.close_sent = true;
+                            self.write_frame(OPCODE_CLOSE, true, &data[..data.len().min(2)]).await?;
+                        }
+                        return Err(Error::Closed(code));
+                    }
+                }
+                Ok(None)
+            }
+            OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                match (frame.opcode, self.msg_opcode) {
+                    (OPCODE_CONTINUATION, None) => return Err(Error::Protocol),
+                    (OPCODE_CONTINUATION, Some(_)) => {}
+                    (_, Some(_)) => return Err(Error::Protocol),
+                    (opcode, None) => self.msg_opcode = Some(opcode),
+                }
+
+                // Append the payload to the message, and move the following bytes after it.
+                self.buffer.copy_within(payload, self.msg_len);
+                let rest = end..self.msg_len + self.rx_len;
+                self.rx_len -= end - self.msg_len;
+                self.msg_len += frame.len;
+                self.buffer.copy_within(rest, self.msg_len);
+
+                Ok(if frame.fin { self.msg_opcode.take() } else { None })
+            }
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Close the WebSocket after a protocol error, if the error calls for it.
+    async fn fail(&mut self, error: Error) -> Error {
+        match error {
+            Error::Protocol => self.send_close(CLOSE_PROTOCOL_ERROR).await,
+            Error::MessageTooLarge => self.send_close(CLOSE_TOO_BIG).await,
+            _ => {}
+        }
+        error
+    }
+
+    async fn send_close(&mut self, code: u16) {
+        if !self.close_sent {
+            self.close_sent = true;
+            let _ = self.write_frame(OPCODE_CLOSE, true, &code.to_be_bytes()).await;
+        }
+    }
+
+    async fn write_frame(&mut self, opcode: u8, fin: bool, payload: &[u8]) -> Result<(), Error> {
+        let mut header = [0; 14];
+        header[0] = (fin as u8) << 7 | opcode;
+        let mut header_len = 2;
+        let len = payload.len();
+        if len < 126 {
+            header[1] = len as u8;
+        } else if len <= u16::MAX as usize {
+            header[1] = 126;
+            header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            header_len = 4;
+        } else {
+            header[1] = 127;
+            header[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+            header_len = 10;
+        }
+
+        match &mut self.random {
+            None => {
+                self.transport.write_all(&header[..header_len]).await.map_err(io)?;
+                self.transport.write_all(payload).await.map_err(io)?;
+            }
+            Some(random) => {
+                let key = (random.next() as u32).to_ne_bytes();
+                header[1] |= 0x80;
+                header[header_len..header_len + 4].copy_from_slice(&key);
+                header_len += 4;
+                self.transport.write_all(&header[..header_len]).await.map_err(io)?;
+
+                // Mask a copy, the chunk size keeps the key aligned.
+                let mut chunk = [0; 64];
+                for part in payload.chunks(chunk.len()) {
+                    let chunk = &mut chunk[..part.len()];
+                    chunk.copy_from_slice(part);
+                    apply_mask(chunk, key);
+                    self.transport.write_all(chunk).await.map_err(io)?;
+                }
+            }
+        }
+        self.transport.flush().await.map_err(io)
+    }
+
+    /// Send a message in a single frame.
+    ///
+    /// This must not be called while a fragmented message is being sent with [`WebSocket::send_fragment`].
+    pub async fn send(&mut self, message: Message<'_>) -> Result<(), Error> {
+        self.write_frame(message.opcode(), true, message.data()).await
+    }
+
+    /// Send a fragment of a message, `last` being set on the final fragment.
+    ///
+    /// The type of the message is taken from the first fragment. This allows sending messages
+    /// larger than any buffer, for example streaming a file.
+    pub async fn send_fragment(&mut self, message: Message<'_>, last: bool) -> Result<(), Error> {
+        let opcode = if self.sending_fragments {
+            OPCODE_CONTINUATION
+        } else {
+            message.opcode()
+        };
+        self.write_frame(opcode, last, message.data()).await?;
+        self.sending_fragments = !last;
+        Ok(())
+    }
+
+    /// Send a ping, with up to 125 bytes of payload.
+    ///
+    /// The pong is handled by [`WebSocket::recv`].
+    pub async fn ping(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > MAX_CONTROL_LEN {
+            return Err(Error::MessageTooLarge);
+        }
+        self.write_frame(OPCODE_PING, true, payload).await
+    }
+
+    /// Start the closing handshake, with a status code and a reason of up to 123 bytes.
+    ///
+    /// [`WebSocket::recv`] then returns [`Error::Closed`] once the peer acknowledged the close.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        if reason.len() > MAX_CONTROL_LEN - 2 {
+            return Err(Error::MessageTooLarge);
+        }
+        let mut data = [0; MAX_CONTROL_LEN];
+        data[..2].copy_from_slice(&code.to_be_bytes());
+        data[2..2 + reason.len()].copy_from_slice(reason.as_bytes());
+        self.close_sent = true;
+        self.write_frame(OPCODE_CLOSE, true, &data[..2 + reason.len()]).await
+    }
+
+    /// Get the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get the underlying transport back.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+    #[test]
+    fn accept_key_rfc6455() {
+        // RFC 6455, section 1.3.
+        assert_eq!(accept_key(KEY), ACCEPT);
+    }
+
+    #[test]
+    fn sha1_digest() {
+        assert_eq!(
+            sha1(&[b"abc"]),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50, 0xc2, 0x6c, 0x9c,
+                0xd0, 0xd8, 0x9d
+            ]
+        );
+        // Two blocks, split into several parts.
+        assert_eq!(
+            sha1(&[b"abcdbcdecdefdefgefghfghighij", b"hijkijkljklmklmnlmnomnopnopq"]),
+            [
+                0x84, 0x98, 0x3e, 0x44, 0x1c, 0x3b, 0xd2, 0x6e, 0xba, 0xae, 0x4a, 0xa1, 0xf9, 0x51, 0x29, 0xe5, 0xe5,
+                0x46, 0x70, 0xf1
+            ]
+        );
+    }
+
+    #[test]
+    fn base64_encoding() {
+        assert_eq!(base64::<8>(b""), "");
+        assert_eq!(base64::<8>(b"f"), "Zg==");
+        assert_eq!(base64::<8>(b"fo"), "Zm8=");
+        assert_eq!(base64::<8>(b"foo"), "Zm9v");
+        assert_eq!(base64::<8>(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn handshake() {
+        let mut headers = [
+            Header::new("Upgrade", "WebSocket"),
+            Header::new("Connection", "keep-alive, Upgrade"),
+            Header::new("Sec-WebSocket-Version", "13"),
+            Header::new("Sec-WebSocket-Key", KEY),
+        ];
+        assert_eq!(handshake_accept(&headers).as_deref(), Some(ACCEPT));
+        headers[2] = Header::new("Sec-WebSocket-Version", "8");
+        assert_eq!(handshake_accept(&headers), None);
+        assert_eq!(handshake_accept(&headers[..3]), None);
+
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert_eq!(check_response(response, KEY), Ok(()));
+        assert_eq!(
+            check_response(response, "x3JJHMbDL1EzLkh9GBhXDw=="),
+            Err(Error::Protocol)
+        );
+        assert_eq!(
+            check_response(b"HTTP/1.1 404 Not Found\r\n\r\n", KEY),
+            Err(Error::Rejected(404))
+        );
+    }
+
+    #[test]
+    fn frames() {
+        // RFC 6455, section 5.7.
+        let frame = parse_frame(b"\x81\x05Hello").unwrap().unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OPCODE_TEXT);
+        assert_eq!(frame.mask, None);
+        assert_eq!((frame.header_len, frame.len), (2, 5));
+
+        let mut data = *b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        let frame = parse_frame(&data).unwrap().unwrap();
+        assert_eq!((frame.header_len, frame.len), (6, 5));
+        apply_mask(&mut data[6..], frame.mask.unwrap());
+        assert_eq!(&data[6..], b"Hello");
+
+        let frame = parse_frame(b"\x01\x03Hel").unwrap().unwrap();
+        assert!(!frame.fin);
+        assert_eq!(frame.opcode, OPCODE_TEXT);
+        let frame = parse_frame(b"\x80\x02lo").unwrap().unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OPCODE_CONTINUATION);
+
+        let frame = parse_frame(b"\x82\x7e\x01\x00").unwrap().unwrap();
+        assert_eq!(frame.opcode, OPCODE_BINARY);
+        assert_eq!((frame.header_len, frame.len), (4, 256));
+        let frame = parse_frame(b"\x82\x7f\x00\x00\x00\x00\x00\x01\x00\x00")
+            .unwrap()
+            .unwrap();
+        assert_eq!((frame.header_len, frame.len), (10, 65536));
+    }
+
+    #[test]
+    fn incomplete_and_invalid_frames() {
+        assert!(parse_frame(b"\x81").unwrap().is_none());
+        assert!(parse_frame(b"\x82\x7e\x01").unwrap().is_none());
+        assert!(parse_frame(b"\x82\x7f\x00\x00\x00\x00").unwrap().is_none());
+        assert!(parse_frame(b"\x81\x85\x37\xfa").unwrap().is_none());
+        assert!(matches!(parse_frame(b"\xc1\x00"), Err(Error::Protocol)));
+    }
+}
@@ -41,6 +41,10 @@ embassy_hal_internal::peripherals! {
     TIMER1,
     TIMER2,
 
+    // EGU
+    EGU0,
+    EGU1,
+
     // GPIOTE
     GPIOTE_CH0,
     GPIOTE_CH1,
@@ -138,6 +142,9 @@ embassy_hal_internal::peripherals! {
 
     // Radio
     RADIO,
+
+    // AES CCM
+    CCM,
 }
 
 impl_uarte!(UARTE0, UARTE0, UARTE0_UART0);
@@ -162,6 +169,9 @@ impl_timer!(TIMER0, TIMER0, TIMER0);
 impl_timer!(TIMER1, TIMER1, TIMER1);
 impl_timer!(TIMER2, TIMER2, TIMER2);
 
+impl_egu!(EGU0, EGU0, SWI0_EGU0);
+impl_egu!(EGU1, EGU1, SWI1_EGU1);
+
 impl_pin!(P0_00, 0, 0);
 impl_pin!(P0_01, 0, 1);
 impl_pin!(P0_02, 0, 2);
@@ -39,6 +39,14 @@ embassy_hal_internal::peripherals! {
     TIMER2,
     TIMER3,
 
+    // EGU
+    EGU0,
+    EGU1,
+    EGU2,
+    EGU3,
+    EGU4,
+    EGU5,
+
     // GPIOTE
     GPIOTE_CH0,
     GPIOTE_CH1,
@@ -133,6 +141,9 @@ embassy_hal_internal::peripherals! {
 
     // Radio
     RADIO,
+
+    // AES CCM
+    CCM,
 }
 
 impl_usb!(USBD, USBD, USBD);
@@ -156,6 +167,13 @@ impl_timer!(TIMER1, TIMER1, TIMER1);
 impl_timer!(TIMER2, TIMER2, TIMER2);
 impl_timer!(TIMER3, TIMER3, TIMER3, extended);
 
+impl_egu!(EGU0, EGU0, SWI0_EGU0);
+impl_egu!(EGU1, EGU1, SWI1_EGU1);
+impl_egu!(EGU2, EGU2, SWI2_EGU2);
+impl_egu!(EGU3, EGU3, SWI3_EGU3);
+impl_egu!(EGU4, EGU4, SWI4_EGU4);
+impl_egu!(EGU5, EGU5, SWI5_EGU5);
+
 impl_qdec!(QDEC, QDEC, QDEC);
 
 impl_rng!(RNG, RNG, RNG);
@@ -53,6 +53,14 @@ embassy_hal_internal::peripherals! {
     TIMER3,
     TIMER4,
 
+    // EGU
+    EGU0,
+    EGU1,
+    EGU2,
+    EGU3,
+    EGU4,
+    EGU5,
+
     // GPIOTE
     GPIOTE_CH0,
     GPIOTE_CH1,
@@ -168,11 +176,22 @@ embassy_hal_internal::peripherals! {
     // PDM
     PDM,
 
+    // COMP / LPCOMP
+    COMP,
+    LPCOMP,
+
+    // NFC tag
+    #[cfg(not(feature = "nfc-pins-as-gpio"))]
+    NFCT,
+
     // I2S
     I2S,
 
     // Radio
     RADIO,
+
+    // AES CCM
+    CCM,
 }
 
 impl_usb!(USBD, USBD, USBD);
@@ -212,6 +231,13 @@ impl_timer!(TIMER2, TIMER2, TIMER2);
 impl_timer!(TIMER3, TIMER3, TIMER3, extended);
 impl_timer!(TIMER4, TIMER4, TIMER4, extended);
 
+impl_egu!(EGU0, EGU0, SWI0_EGU0);
+impl_egu!(EGU1, EGU1, SWI1_EGU1);
+impl_egu!(EGU2, EGU2, SWI2_EGU2);
+impl_egu!(EGU3, EGU3, SWI3_EGU3);
+impl_egu!(EGU4, EGU4, SWI4_EGU4);
+impl_egu!(EGU5, EGU5, SWI5_EGU5);
+
 impl_pin!(P0_00, 0, 0);
 impl_pin!(P0_01, 0, 1);
 impl_pin!(P0_02, 0, 2);
@@ -30,7 +30,7 @@ pub mod pac {
         ficr_s as ficr,
         fpu_ns as fpu,
         gpiote0_s as gpiote,
-        i2s0_ns as i2s0,
+        i2s0_ns as i2s,
         ipc_ns as ipc,
         kmu_ns as kmu,
         lpcomp_ns as lpcomp,
@@ -272,10 +272,32 @@ embassy_hal_internal::peripherals! {
     // PDM
     PDM0,
 
+    // COMP / LPCOMP
+    COMP,
+    LPCOMP,
+
+    // NFC tag
+    #[cfg(not(feature = "nfc-pins-as-gpio"))]
+    NFCT,
+
+    // I2S
+    I2S0,
+
     // QDEC
     QDEC0,
     QDEC1,
 
+    // EGU
+    EGU0,
+    EGU1,
+    EGU2,
+    EGU3,
+    EGU4,
+    EGU5,
+
+    // IPC
+    IPC,
+
     // GPIOTE
     GPIOTE_CH0,
     GPIOTE_CH1,
@@ -418,10 +440,19 @@ impl_timer!(TIMER0, TIMER0, TIMER0);
 impl_timer!(TIMER1, TIMER1, TIMER1);
 impl_timer!(TIMER2, TIMER2, TIMER2);
 
+impl_egu!(EGU0, EGU0, EGU0);
+impl_egu!(EGU1, EGU1, EGU1);
+impl_egu!(EGU2, EGU2, EGU2);
+impl_egu!(EGU3, EGU3, EGU3);
+impl_egu!(EGU4, EGU4, EGU4);
+impl_egu!(EGU5, EGU5, EGU5);
+
 impl_qspi!(QSPI, QSPI, QSPI);
 
 impl_pdm!(PDM0, PDM0, PDM0);
 
+impl_i2s!(I2S0, I2S0, I2S0);
+
 impl_qdec!(QDEC0, QDEC0, QDEC0);
 impl_qdec!(QDEC1, QDEC1, QDEC1);
 
@@ -146,6 +146,12 @@ embassy_hal_internal::peripherals! {
     TIMER1,
     TIMER2,
 
+    // EGU
+    EGU0,
+
+    // IPC
+    IPC,
+
     // GPIOTE
     GPIOTE_CH0,
     GPIOTE_CH1,
@@ -263,6 +269,8 @@ impl_timer!(TIMER0, TIMER0, TIMER0);
 impl_timer!(TIMER1, TIMER1, TIMER1);
 impl_timer!(TIMER2, TIMER2, TIMER2);
 
+impl_egu!(EGU0, EGU0, EGU0);
+
 impl_rng!(RNG, RNG, RNG);
 
 impl_pin!(P0_00, 0, 0);
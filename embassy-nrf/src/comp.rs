@@ -0,0 +1,328 @@
+//! Comparator (COMP) driver.
+//!
+//! The comparator compares an analog input against a reference voltage, or against a second
+//! analog input in differential mode. It keeps running while the CPU sleeps in System ON idle, so
+//! waiting for an [`Event`] lets the CPU sleep until the threshold is crossed.
+//!
+//! COMP and [LPCOMP](crate::lpcomp) share hardware, only one of them can be used at a time.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::peripherals::COMP;
+use crate::saadc::{AnyInput, Input};
+use crate::{interrupt, pac, Peripheral};
+
+/// Interrupt handler.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::COMP_LPCOMP> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        let r = unsafe { &*pac::COMP::ptr() };
+        r.intenclr.write(|w| w.up().clear().down().clear().cross().clear());
+        WAKER.wake();
+    }
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Comparator event.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// The input rose above the threshold.
+    Up,
+    /// The input fell below the threshold.
+    Down,
+    /// The input crossed the threshold in either direction.
+    Cross,
+}
+
+/// Reference voltage, for single-ended mode.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reference {
+    /// Internal 1.2 V reference.
+    Internal1V2,
+    /// Internal 1.8 V reference, VDD must be at least 2.7 V.
+    Internal1V8,
+    /// Internal 2.4 V reference, VDD must be at least 3.3 V.
+    Internal2V4,
+    /// VDD.
+    Vdd,
+}
+
+/// Speed and power mode.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// Low power, slow response.
+    LowPower,
+    /// Normal power and response time.
+    Normal,
+    /// High power, fast response.
+    HighSpeed,
+}
+
+/// COMP config
+#[non_exhaustive]
+pub struct Config {
+    /// Reference voltage, for single-ended mode with an internal reference.
+    pub reference: Reference,
+    /// Upper threshold in single-ended mode, in 1/64ths of the reference (0..=63).
+    ///
+    /// The [`Event::Up`] threshold is `(threshold_up + 1) / 64 * VREF`.
+    pub threshold_up: u8,
+    /// Lower threshold in single-ended mode, in 1/64ths of the reference (0..=63).
+    ///
+    /// The [`Event::Down`] threshold is `(threshold_down + 1) / 64 * VREF`. Setting it below
+    /// `threshold_up` adds hysteresis.
+    pub threshold_down: u8,
+    /// Enable 50 mV hysteresis in differential mode.
+    pub hysteresis: bool,
+    /// Speed and power mode.
+    pub speed: Speed,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            reference: Reference::Internal1V2,
+            threshold_up: 32,
+            threshold_down: 31,
+            hysteresis: false,
+            speed: Speed::Normal,
+        }
+    }
+}
+
+/// Comparator driver.
+pub struct Comp<'d> {
+    _peri: PeripheralRef<'d, COMP>,
+    _input: PeripheralRef<'d, AnyInput>,
+    _reference: Option<PeripheralRef<'d, AnyInput>>,
+}
+
+impl<'d> Comp<'d> {
+    /// Create a new comparator in single-ended mode, comparing `input` against an internal
+    /// reference.
+    pub fn new_single_ended(
+        comp: impl Peripheral<P = COMP> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::COMP_LPCOMP, InterruptHandler> + 'd,
+        input: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(comp, input);
+
+        let r = Self::regs();
+        r.psel.write(|w| unsafe { w.bits(analog_input(&*input) as u32) });
+        r.refsel.write(|w| match config.reference {
+            Reference::Internal1V2 => w.refsel().int1v2(),
+            Reference::Internal1V8 => w.refsel().int1v8(),
+            Reference::Internal2V4 => w.refsel().int2v4(),
+            Reference::Vdd => w.refsel().vdd(),
+        });
+        r.mode.write(|w| w.main().se());
+        Self::configure_thresholds(&config);
+
+        Self::new_inner(comp, input.map_into(), None, &config)
+    }
+
+    /// Create a new comparator in single-ended mode, comparing `input` against the voltage on
+    /// the `reference` analog input.
+    ///
+    /// `config.reference` is ignored.
+    pub fn new_single_ended_with_aref(
+        comp: impl Peripheral<P = COMP> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::COMP_LPCOMP, InterruptHandler> + 'd,
+        input: impl Peripheral<P = impl Input> + 'd,
+        reference: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(comp, input, reference);
+
+        let r = Self::regs();
+        r.psel.write(|w| unsafe { w.bits(analog_input(&*input) as u32) });
+        r.refsel.write(|w| w.refsel().aref());
+        r.extrefsel
+            .write(|w| unsafe { w.bits(analog_input(&*reference) as u32) });
+        r.mode.write(|w| w.main().se());
+        Self::configure_thresholds(&config);
+
+        Self::new_inner(comp, input.map_into(), Some(reference.map_into()), &config)
+    }
+
+    /// Create a new comparator in differential mode, comparing `positive` against `negative`.
+    ///
+    /// `config.reference` and the thresholds are ignored.
+    pub fn new_differential(
+        comp: impl Peripheral<P = COMP> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::COMP_LPCOMP, InterruptHandler> + 'd,
+        positive: impl Peripheral<P = impl Input> + 'd,
+        negative: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(comp, positive, negative);
+
+        let r = Self::regs();
+        r.psel.write(|w| unsafe { w.bits(analog_input(&*positive) as u32) });
+        r.extrefsel
+            .write(|w| unsafe { w.bits(analog_input(&*negative) as u32) });
+        r.mode.write(|w| w.main().diff());
+        r.hyst.write(|w| match config.hysteresis {
+            true => w.hyst().hyst50m_v(),
+            false => w.hyst().no_hyst(),
+        });
+
+        Self::new_inner(comp, positive.map_into(), Some(negative.map_into()), &config)
+    }
+
+    fn configure_thresholds(config: &Config) {
+        Self::regs().th.write(|w| unsafe {
+            w.thup().bits(config.threshold_up.min(63));
+            w.thdown().bits(config.threshold_down.min(63))
+        });
+    }
+
+    fn new_inner(
+        _peri: PeripheralRef<'d, COMP>,
+        _input: PeripheralRef<'d, AnyInput>,
+        _reference: Option<PeripheralRef<'d, AnyInput>>,
+        config: &Config,
+    ) -> Self {
+        let r = Self::regs();
+        r.mode.modify(|_, w| match config.speed {
+            Speed::LowPower => w.sp().low(),
+            Speed::Normal => w.sp().normal(),
+            Speed::HighSpeed => w.sp().high(),
+        });
+        r.intenclr.write(|w| unsafe { w.bits(0xf) });
+
+        interrupt::COMP_LPCOMP.unpend();
+        unsafe { interrupt::COMP_LPCOMP.enable() };
+
+        r.enable.write(|w| w.enable().enabled());
+
+        // The comparator is ready within a few microseconds, spinning is fine.
+        r.events_ready.reset();
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+        while r.events_ready.read().bits() == 0 {}
+        r.events_ready.reset();
+
+        Self {
+            _peri,
+            _input,
+            _reference,
+        }
+    }
+
+    /// Sample the comparator output.
+    ///
+    /// Returns `true` if the input is above the threshold.
+    pub fn sample(&mut self) -> bool {
+        let r = Self::regs();
+        r.tasks_sample.write(|w| unsafe { w.bits(1) });
+        r.result.read().bits() != 0
+    }
+
+    /// Wait for the comparator output to change.
+    ///
+    /// Only changes happening after this call are detected, use [`sample`](Self::sample) to get
+    /// the current state.
+    pub async fn wait_for(&mut self, event: Event) {
+        let r = Self::regs();
+
+        let on_drop = OnDrop::new(|| {
+            let r = Self::regs();
+            r.intenclr.write(|w| w.up().clear().down().clear().cross().clear());
+        });
+
+        Self::reset_event(event);
+        r.intenset.write(|w| match event {
+            Event::Up => w.up().set(),
+            Event::Down => w.down().set(),
+            Event::Cross => w.cross().set(),
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if Self::is_triggered(event) {
+                Self::reset_event(event);
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        drop(on_drop);
+    }
+
+    /// Wait for the input to rise above the threshold.
+    pub async fn wait_for_up(&mut self) {
+        self.wait_for(Event::Up).await
+    }
+
+    /// Wait for the input to fall below the threshold.
+    pub async fn wait_for_down(&mut self) {
+        self.wait_for(Event::Down).await
+    }
+
+    /// Wait for the input to cross the threshold in either direction.
+    ///
+    /// Returns `true` if the input is now above the threshold.
+    pub async fn wait_for_cross(&mut self) -> bool {
+        self.wait_for(Event::Cross).await;
+        self.sample()
+    }
+
+    fn is_triggered(event: Event) -> bool {
+        let r = Self::regs();
+        match event {
+            Event::Up => r.events_up.read().bits() != 0,
+            Event::Down => r.events_down.read().bits() != 0,
+            Event::Cross => r.events_cross.read().bits() != 0,
+        }
+    }
+
+    fn reset_event(event: Event) {
+        let r = Self::regs();
+        match event {
+            Event::Up => r.events_up.reset(),
+            Event::Down => r.events_down.reset(),
+            Event::Cross => r.events_cross.reset(),
+        }
+    }
+
+    fn regs() -> &'static pac::comp::RegisterBlock {
+        unsafe { &*pac::COMP::ptr() }
+    }
+}
+
+impl<'d> Drop for Comp<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xf) });
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.enable.write(|w| w.enable().disabled());
+    }
+}
+
+/// Index of the analog input `input` is connected to, for `PSEL` and `EXTREFSEL`.
+pub(crate) fn analog_input(input: &impl Input) -> u8 {
+    let channel: u8 = input.channel().into();
+    // SAADC numbers analog inputs from 1, 0 meaning not connected.
+    assert!(
+        (1..=8).contains(&channel),
+        "comparator inputs must be analog input pins"
+    );
+    channel - 1
+}
@@ -0,0 +1,110 @@
+//! Event Generator Unit (EGU) driver.
+//!
+//! The EGU provides 16 trigger channels. Triggering the task of a channel generates its event,
+//! which can in turn trigger tasks through (D)PPI. This lets software start hardware-chained
+//! operations, and lets one event fan out to more tasks than a single (D)PPI channel allows.
+
+#![macro_use]
+
+use core::marker::PhantomData;
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+
+use crate::ppi::{Event, Task};
+use crate::{interrupt, pac, Peripheral};
+
+/// Number of trigger channels of an EGU instance.
+pub const TRIGGERS: usize = 16;
+
+/// EGU driver.
+pub struct Egu<'d, T: Instance> {
+    _p: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Egu<'d, T> {
+    /// Create a new EGU instance.
+    pub fn new(egu: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(egu);
+
+        let r = T::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        Self { _p: egu }
+    }
+
+    /// Get a handle for the trigger channel `number`, in `0..16`.
+    pub fn trigger(&mut self, number: usize) -> Trigger<'d, T> {
+        assert!(number < TRIGGERS);
+        Trigger {
+            number,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Egu<'d, T> {
+    fn drop(&mut self) {
+        let r = T::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+    }
+}
+
+/// Trigger channel of an EGU.
+pub struct Trigger<'d, T: Instance> {
+    number: usize,
+    _p: PhantomData<&'d T>,
+}
+
+impl<'d, T: Instance> Trigger<'d, T> {
+    /// Generate the event of this channel from software.
+    pub fn pend(&mut self) {
+        T::regs().tasks_trigger[self.number].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Task generating the event of this channel.
+    pub fn task(&self) -> Task<'d> {
+        Task::from_reg(&T::regs().tasks_trigger[self.number])
+    }
+
+    /// Event generated when the task of this channel is triggered.
+    pub fn event(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_triggered[self.number])
+    }
+
+    /// Enable the interrupt for this channel.
+    ///
+    /// The interrupt is shared with the SWI of the same number, the application is responsible
+    /// for handling it and clearing the event.
+    pub fn enable_interrupt(&mut self) {
+        T::regs().intenset.write(|w| unsafe { w.bits(1 << self.number) });
+    }
+
+    /// Disable the interrupt for this channel.
+    pub fn disable_interrupt(&mut self) {
+        T::regs().intenclr.write(|w| unsafe { w.bits(1 << self.number) });
+    }
+}
+
+pub(crate) trait SealedInstance {
+    fn regs() -> &'static pac::egu0::RegisterBlock;
+}
+
+/// EGU peripheral instance.
+#[allow(private_bounds)]
+pub trait Instance: Peripheral<P = Self> + SealedInstance + 'static + Send {
+    /// Interrupt for this peripheral.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+macro_rules! impl_egu {
+    ($type:ident, $pac_type:ident, $irq:ident) => {
+        impl crate::egu::SealedInstance for peripherals::$type {
+            fn regs() -> &'static pac::egu0::RegisterBlock {
+                unsafe { &*(pac::$pac_type::ptr() as *const pac::egu0::RegisterBlock) }
+            }
+        }
+        impl crate::egu::Instance for peripherals::$type {
+            type Interrupt = crate::interrupt::typelevel::$irq;
+        }
+    };
+}
@@ -37,6 +37,14 @@ pub enum Error {
     BufferMisaligned,
     /// The buffer length is not a multiple of the alignment.
     BufferLengthMisaligned,
+    /// The next transmit buffer was not provided in time, so the previous one was sent again.
+    ///
+    /// The new buffer is still queued, the stream keeps running.
+    Underrun,
+    /// The next receive buffer was not provided in time, so the previous one was overwritten.
+    ///
+    /// The new buffer is still queued, the stream keeps running.
+    Overrun,
 }
 
 /// I2S configuration.
@@ -505,6 +513,7 @@ impl<'d, T: Instance> I2S<'d, T> {
         self.i2s
     }
 
+    #[allow(unused_unsafe)]
     fn apply_config(&self) {
         let c = &T::regs().config;
         match &self.master_clock {
@@ -616,6 +625,8 @@ impl<'d, T: Instance> I2S<'d, T> {
 
         compiler_fence(Ordering::SeqCst);
 
+        let underrun = Self::take_tx_ptr_update();
+
         let device = Device::<T>::new();
 
         device.update_tx(buffer_ptr)?;
@@ -624,7 +635,26 @@ impl<'d, T: Instance> I2S<'d, T> {
 
         compiler_fence(Ordering::SeqCst);
 
-        Ok(())
+        match underrun {
+            true => Err(Error::Underrun),
+            false => Ok(()),
+        }
+    }
+
+    /// Consume a pointer update that happened before the next buffer was provided.
+    ///
+    /// The peripheral latches the pointer once per buffer, if the event is already pending it
+    /// latched the previous pointer again.
+    fn take_tx_ptr_update() -> bool {
+        let device = Device::<T>::new();
+        if device.is_tx_ptr_updated() {
+            warn!("I2S TX underrun");
+            device.reset_tx_ptr_event();
+            device.enable_tx_ptr_interrupt();
+            true
+        } else {
+            false
+        }
     }
 
     async fn wait_tx_ptr_update() {
@@ -672,6 +702,8 @@ impl<'d, T: Instance> I2S<'d, T> {
 
         compiler_fence(Ordering::SeqCst);
 
+        let overrun = Self::take_rx_ptr_update();
+
         let device = Device::<T>::new();
 
         device.update_rx(buffer_ptr)?;
@@ -680,7 +712,23 @@ impl<'d, T: Instance> I2S<'d, T> {
 
         compiler_fence(Ordering::SeqCst);
 
-        Ok(())
+        match overrun {
+            true => Err(Error::Overrun),
+            false => Ok(()),
+        }
+    }
+
+    /// Consume a pointer update that happened before the next buffer was provided.
+    fn take_rx_ptr_update() -> bool {
+        let device = Device::<T>::new();
+        if device.is_rx_ptr_updated() {
+            warn!("I2S RX overrun");
+            device.reset_rx_ptr_event();
+            device.enable_rx_ptr_interrupt();
+            true
+        } else {
+            false
+        }
     }
 
     async fn wait_rx_ptr_update() {
@@ -764,6 +812,8 @@ impl<'d, T: Instance, S: Sample, const NB: usize, const NS: usize> OutputStream<
 
     /// Sends the current buffer for transmission in the DMA.
     /// Switches to use the next available buffer.
+    ///
+    /// Returns [`Error::Underrun`] if the previous buffer finished before this call.
     pub async fn send(&mut self) -> Result<(), Error>
     where
         S: Sample,
@@ -818,6 +868,8 @@ impl<'d, T: Instance, S: Sample, const NB: usize, const NS: usize> InputStream<'
 
     /// Sets the current buffer for reception from the DMA.
     /// Switches to use the next available buffer.
+    ///
+    /// Returns [`Error::Overrun`] if the previous buffer filled up before this call.
     #[allow(unused_mut)]
     pub async fn receive(&mut self) -> Result<(), Error>
     where
@@ -877,13 +929,44 @@ impl<'d, T: Instance, S: Sample, const NB: usize, const NS: usize> FullDuplexStr
 
     /// Sets the current buffers for output and input for transmission/reception from the DMA.
     /// Switch to use the next available buffers for output/input.
+    ///
+    /// Returns [`Error::Underrun`] or [`Error::Overrun`] if the previous buffers finished before
+    /// this call.
     pub async fn send_and_receive(&mut self) -> Result<(), Error>
     where
         S: Sample,
     {
-        I2S::<T>::send_from_ram(self.buffers_out.switch()).await?;
-        I2S::<T>::receive_from_ram(self.buffers_in.switch_mut()).await?;
-        Ok(())
+        let buffer_out = self.buffers_out.switch();
+        let buffer_in = self.buffers_in.switch_mut();
+        trace!(
+            "SEND AND RECEIVE: {} {}",
+            buffer_out as *const S as u32,
+            buffer_in as *const S as u32
+        );
+
+        slice_in_ram_or(buffer_out, Error::BufferNotInRAM)?;
+
+        // Both pointers are latched at the same time, check both before waiting for either.
+        let underrun = I2S::<T>::take_tx_ptr_update();
+        let overrun = I2S::<T>::take_rx_ptr_update();
+
+        compiler_fence(Ordering::SeqCst);
+
+        let device = Device::<T>::new();
+
+        device.update_tx(buffer_out)?;
+        device.update_rx(buffer_in)?;
+
+        I2S::<T>::wait_tx_ptr_update().await;
+        I2S::<T>::wait_rx_ptr_update().await;
+
+        compiler_fence(Ordering::SeqCst);
+
+        match (underrun, overrun) {
+            (true, _) => Err(Error::Underrun),
+            (_, true) => Err(Error::Overrun),
+            _ => Ok(()),
+        }
     }
 }
 
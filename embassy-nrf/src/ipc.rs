@@ -0,0 +1,188 @@
+//! Interprocessor Communication (IPC) driver.
+//!
+//! IPC signals events between the cores of the chip: the application and network cores of the
+//! nRF5340, or the application core and the modem of the nRF9160. A SEND task of one core
+//! triggers the RECEIVE events of the other cores subscribed to the same IPC channel. Data is
+//! exchanged through shared RAM, IPC only carries the notifications.
+//!
+//! [`Ipc::new`] maps task and event `n` to channel `n`, so [`Ipc::send`] on one core wakes
+//! [`Ipc::wait`] for the same number on the other core.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+#[cfg(all(feature = "_nrf5340-app", feature = "_s"))]
+use crate::gpio::Pin as GpioPin;
+use crate::interrupt::InterruptExt;
+use crate::peripherals::IPC;
+use crate::ppi::{Event, Task};
+use crate::{interrupt, pac, Peripheral};
+
+/// Number of IPC channels, tasks and events.
+#[cfg(feature = "_nrf5340")]
+pub const CHANNELS: usize = 16;
+/// Number of IPC channels, tasks and events.
+#[cfg(feature = "_nrf9160")]
+pub const CHANNELS: usize = 8;
+
+/// Interrupt handler.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::IPC> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        let r = regs();
+        let pending = r.intpend.read().bits();
+        r.intenclr.write(|w| unsafe { w.bits(pending) });
+
+        for (n, waker) in WAKERS.iter().enumerate() {
+            if pending & (1 << n) != 0 {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+static WAKERS: [AtomicWaker; CHANNELS] = [NEW_AW; CHANNELS];
+
+/// IPC driver.
+pub struct Ipc<'d> {
+    _peri: PeripheralRef<'d, IPC>,
+}
+
+impl<'d> Ipc<'d> {
+    /// Create a new IPC driver.
+    ///
+    /// Task and event `n` are mapped to channel `n`.
+    pub fn new(
+        ipc: impl Peripheral<P = IPC> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::IPC, InterruptHandler> + 'd,
+    ) -> Self {
+        into_ref!(ipc);
+
+        let r = regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        for n in 0..CHANNELS {
+            r.send_cnf[n].write(|w| unsafe { w.bits(1 << n) });
+            r.receive_cnf[n].write(|w| unsafe { w.bits(1 << n) });
+        }
+
+        interrupt::IPC.unpend();
+        unsafe { interrupt::IPC.enable() };
+
+        Self { _peri: ipc }
+    }
+
+    /// Set the channels the SEND task `task` signals, as a bit mask.
+    pub fn configure_send(&mut self, task: usize, channels: u32) {
+        assert!(task < CHANNELS);
+        regs().send_cnf[task].write(|w| unsafe { w.bits(channels) });
+    }
+
+    /// Set the channels the RECEIVE event `event` is generated for, as a bit mask.
+    pub fn configure_receive(&mut self, event: usize, channels: u32) {
+        assert!(event < CHANNELS);
+        regs().receive_cnf[event].write(|w| unsafe { w.bits(channels) });
+    }
+
+    /// Trigger the SEND task `task`, signalling the other cores.
+    pub fn send(&self, task: usize) {
+        assert!(task < CHANNELS);
+        regs().tasks_send[task].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Wait for the RECEIVE event `event`, and clear it.
+    ///
+    /// Returns immediately if the event was generated since it was last cleared, so no
+    /// notification is lost between two calls.
+    pub async fn wait(&self, event: usize) {
+        assert!(event < CHANNELS);
+        let r = regs();
+
+        poll_fn(|cx| {
+            WAKERS[event].register(cx.waker());
+
+            if r.events_receive[event].read().bits() != 0 {
+                r.events_receive[event].reset();
+                return Poll::Ready(());
+            }
+
+            r.intenset.write(|w| unsafe { w.bits(1 << event) });
+            Poll::Pending
+        })
+        .await;
+    }
+
+    /// Clear the RECEIVE event `event`, discarding any pending notification.
+    pub fn clear(&self, event: usize) {
+        assert!(event < CHANNELS);
+        regs().events_receive[event].reset();
+    }
+
+    /// Read the general purpose memory register `n`, shared with the other cores.
+    pub fn gpmem(&self, n: usize) -> u32 {
+        regs().gpmem[n].read().bits()
+    }
+
+    /// Write the general purpose memory register `n`, shared with the other cores.
+    pub fn set_gpmem(&mut self, n: usize, value: u32) {
+        regs().gpmem[n].write(|w| unsafe { w.bits(value) });
+    }
+
+    /// SEND task `task`, to signal the other cores from (D)PPI.
+    pub fn task_send(&self, task: usize) -> Task<'d> {
+        assert!(task < CHANNELS);
+        Task::from_reg(&regs().tasks_send[task])
+    }
+
+    /// RECEIVE event `event`, to trigger tasks through (D)PPI.
+    pub fn event_receive(&self, event: usize) -> Event<'d> {
+        assert!(event < CHANNELS);
+        Event::from_reg(&regs().events_receive[event])
+    }
+}
+
+impl<'d> Drop for Ipc<'d> {
+    fn drop(&mut self) {
+        let r = regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+    }
+}
+
+/// Release the network core from reset, so it boots from its own flash.
+///
+/// The network core image must have been programmed beforehand, e.g. with the debugger or by
+/// the network core bootloader. Pins used by the network core must be assigned to it before
+/// starting it, see [`assign_pin_to_network_core`] (secure mode only).
+#[cfg(feature = "_nrf5340-app")]
+pub fn start_network_core() {
+    let r = unsafe { &*pac::RESET::ptr() };
+    r.network.forceoff.write(|w| w.forceoff().release());
+}
+
+/// Give the control of `pin` to the network core.
+///
+/// The pin can then only be used by the network core and its peripherals, e.g. for the UART of
+/// an HCI transport or for radio front-end control.
+#[cfg(all(feature = "_nrf5340-app", feature = "_s"))]
+pub fn assign_pin_to_network_core(pin: impl Peripheral<P = impl GpioPin> + 'static) {
+    into_ref!(pin);
+    pin.conf().write(|w| w.mcusel().network_mcu());
+}
+
+/// Hold the network core in reset, stopping it.
+#[cfg(feature = "_nrf5340-app")]
+pub fn stop_network_core() {
+    let r = unsafe { &*pac::RESET::ptr() };
+    r.network.forceoff.write(|w| w.forceoff().hold());
+}
+
+fn regs() -> &'static pac::ipc::RegisterBlock {
+    unsafe { &*pac::IPC::ptr() }
+}
@@ -42,6 +42,15 @@ mod time_driver;
 
 #[cfg(not(feature = "nrf51"))]
 pub mod buffered_uarte;
+#[cfg(any(
+    feature = "nrf52832",
+    feature = "nrf52833",
+    feature = "nrf52840",
+    feature = "_nrf5340-app"
+))]
+pub mod comp;
+#[cfg(not(feature = "nrf51"))]
+pub mod egu;
 pub mod gpio;
 #[cfg(feature = "gpiote")]
 pub mod gpiote;
@@ -50,8 +59,32 @@ pub mod gpiote;
 #[cfg(not(any(feature = "_nrf9160", feature = "_nrf5340-app")))]
 pub mod radio;
 
-#[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+#[cfg(any(
+    feature = "nrf52832",
+    feature = "nrf52833",
+    feature = "nrf52840",
+    feature = "_nrf5340-app"
+))]
 pub mod i2s;
+#[cfg(any(feature = "_nrf5340", feature = "_nrf9160"))]
+pub mod ipc;
+#[cfg(any(
+    feature = "nrf52832",
+    feature = "nrf52833",
+    feature = "nrf52840",
+    feature = "_nrf5340-app"
+))]
+pub mod lpcomp;
+#[cfg(all(
+    any(
+        feature = "nrf52832",
+        feature = "nrf52833",
+        feature = "nrf52840",
+        feature = "_nrf5340-app"
+    ),
+    not(feature = "nfc-pins-as-gpio")
+))]
+pub mod nfct;
 pub mod nvmc;
 #[cfg(any(
     feature = "nrf52810",
@@ -0,0 +1,271 @@
+//! Low-power comparator (LPCOMP) driver.
+//!
+//! The low-power comparator compares an analog input against a fraction of VDD or an external
+//! reference. It keeps running while the CPU sleeps in System ON idle, so waiting for an [`Event`]
+//! lets the CPU sleep until the threshold is crossed, and it can wake the chip from System OFF.
+//!
+//! LPCOMP and [COMP](crate::comp) share hardware, only one of them can be used at a time.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::comp::analog_input;
+pub use crate::comp::Event;
+use crate::interrupt::InterruptExt;
+use crate::peripherals::LPCOMP;
+use crate::saadc::{AnyInput, Input};
+use crate::{interrupt, pac, Peripheral};
+
+/// Interrupt handler.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::COMP_LPCOMP> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        let r = unsafe { &*pac::LPCOMP::ptr() };
+        r.intenclr.write(|w| w.up().clear().down().clear().cross().clear());
+        WAKER.wake();
+    }
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Reference voltage, as a fraction of VDD.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(missing_docs)]
+pub enum Reference {
+    Vdd1_16,
+    Vdd1_8,
+    Vdd3_16,
+    Vdd2_8,
+    Vdd5_16,
+    Vdd3_8,
+    Vdd7_16,
+    Vdd4_8,
+    Vdd9_16,
+    Vdd5_8,
+    Vdd11_16,
+    Vdd6_8,
+    Vdd13_16,
+    Vdd7_8,
+    Vdd15_16,
+}
+
+/// LPCOMP config
+#[non_exhaustive]
+pub struct Config {
+    /// Reference voltage, when not using an external reference.
+    pub reference: Reference,
+    /// Enable 50 mV hysteresis.
+    pub hysteresis: bool,
+    /// Event waking the chip from System OFF.
+    ///
+    /// The driver must not be dropped before entering System OFF, as dropping it stops the
+    /// comparator.
+    pub wakeup: Event,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            reference: Reference::Vdd4_8,
+            hysteresis: false,
+            wakeup: Event::Cross,
+        }
+    }
+}
+
+/// Low-power comparator driver.
+pub struct Lpcomp<'d> {
+    _peri: PeripheralRef<'d, LPCOMP>,
+    _input: PeripheralRef<'d, AnyInput>,
+    _reference: Option<PeripheralRef<'d, AnyInput>>,
+}
+
+impl<'d> Lpcomp<'d> {
+    /// Create a new low-power comparator, comparing `input` against a fraction of VDD.
+    pub fn new(
+        lpcomp: impl Peripheral<P = LPCOMP> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::COMP_LPCOMP, InterruptHandler> + 'd,
+        input: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(lpcomp, input);
+
+        let r = Self::regs();
+        r.refsel.write(|w| match config.reference {
+            Reference::Vdd1_16 => w.refsel().ref1_16vdd(),
+            Reference::Vdd1_8 => w.refsel().ref1_8vdd(),
+            Reference::Vdd3_16 => w.refsel().ref3_16vdd(),
+            Reference::Vdd2_8 => w.refsel().ref2_8vdd(),
+            Reference::Vdd5_16 => w.refsel().ref5_16vdd(),
+            Reference::Vdd3_8 => w.refsel().ref3_8vdd(),
+            Reference::Vdd7_16 => w.refsel().ref7_16vdd(),
+            Reference::Vdd4_8 => w.refsel().ref4_8vdd(),
+            Reference::Vdd9_16 => w.refsel().ref9_16vdd(),
+            Reference::Vdd5_8 => w.refsel().ref5_8vdd(),
+            Reference::Vdd11_16 => w.refsel().ref11_16vdd(),
+            Reference::Vdd6_8 => w.refsel().ref6_8vdd(),
+            Reference::Vdd13_16 => w.refsel().ref13_16vdd(),
+            Reference::Vdd7_8 => w.refsel().ref7_8vdd(),
+            Reference::Vdd15_16 => w.refsel().ref15_16vdd(),
+        });
+
+        Self::new_inner(lpcomp, input.map_into(), None, &config)
+    }
+
+    /// Create a new low-power comparator, comparing `input` against the voltage on the
+    /// `reference` analog input, which must be AIN0 or AIN1.
+    ///
+    /// `config.reference` is ignored.
+    pub fn new_with_aref(
+        lpcomp: impl Peripheral<P = LPCOMP> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::COMP_LPCOMP, InterruptHandler> + 'd,
+        input: impl Peripheral<P = impl Input> + 'd,
+        reference: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(lpcomp, input, reference);
+
+        let extref = analog_input(&*reference);
+        assert!(extref < 2, "LPCOMP external reference must be AIN0 or AIN1");
+
+        let r = Self::regs();
+        r.refsel.write(|w| w.refsel().aref());
+        r.extrefsel.write(|w| unsafe { w.bits(extref as u32) });
+
+        Self::new_inner(lpcomp, input.map_into(), Some(reference.map_into()), &config)
+    }
+
+    fn new_inner(
+        _peri: PeripheralRef<'d, LPCOMP>,
+        input: PeripheralRef<'d, AnyInput>,
+        _reference: Option<PeripheralRef<'d, AnyInput>>,
+        config: &Config,
+    ) -> Self {
+        let r = Self::regs();
+        r.psel.write(|w| unsafe { w.bits(analog_input(&*input) as u32) });
+        r.hyst.write(|w| w.hyst().bit(config.hysteresis));
+        r.anadetect.write(|w| match config.wakeup {
+            Event::Up => w.anadetect().up(),
+            Event::Down => w.anadetect().down(),
+            Event::Cross => w.anadetect().cross(),
+        });
+        r.intenclr.write(|w| unsafe { w.bits(0xf) });
+
+        interrupt::COMP_LPCOMP.unpend();
+        unsafe { interrupt::COMP_LPCOMP.enable() };
+
+        r.enable.write(|w| w.enable().enabled());
+
+        // The comparator is ready within a few hundred microseconds, spinning is fine.
+        r.events_ready.reset();
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+        while r.events_ready.read().bits() == 0 {}
+        r.events_ready.reset();
+
+        Self {
+            _peri,
+            _input: input,
+            _reference,
+        }
+    }
+
+    /// Sample the comparator output.
+    ///
+    /// Returns `true` if the input is above the reference.
+    pub fn sample(&mut self) -> bool {
+        let r = Self::regs();
+        r.tasks_sample.write(|w| unsafe { w.bits(1) });
+        r.result.read().bits() != 0
+    }
+
+    /// Wait for the comparator output to change.
+    ///
+    /// Only changes happening after this call are detected, use [`sample`](Self::sample) to get
+    /// the current state.
+    pub async fn wait_for(&mut self, event: Event) {
+        let r = Self::regs();
+
+        let on_drop = OnDrop::new(|| {
+            let r = Self::regs();
+            r.intenclr.write(|w| w.up().clear().down().clear().cross().clear());
+        });
+
+        Self::reset_event(event);
+        r.intenset.write(|w| match event {
+            Event::Up => w.up().set(),
+            Event::Down => w.down().set(),
+            Event::Cross => w.cross().set(),
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if Self::is_triggered(event) {
+                Self::reset_event(event);
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        drop(on_drop);
+    }
+
+    /// Wait for the input to rise above the reference.
+    pub async fn wait_for_up(&mut self) {
+        self.wait_for(Event::Up).await
+    }
+
+    /// Wait for the input to fall below the reference.
+    pub async fn wait_for_down(&mut self) {
+        self.wait_for(Event::Down).await
+    }
+
+    /// Wait for the input to cross the reference in either direction.
+    ///
+    /// Returns `true` if the input is now above the reference.
+    pub async fn wait_for_cross(&mut self) -> bool {
+        self.wait_for(Event::Cross).await;
+        self.sample()
+    }
+
+    fn is_triggered(event: Event) -> bool {
+        let r = Self::regs();
+        match event {
+            Event::Up => r.events_up.read().bits() != 0,
+            Event::Down => r.events_down.read().bits() != 0,
+            Event::Cross => r.events_cross.read().bits() != 0,
+        }
+    }
+
+    fn reset_event(event: Event) {
+        let r = Self::regs();
+        match event {
+            Event::Up => r.events_up.reset(),
+            Event::Down => r.events_down.reset(),
+            Event::Cross => r.events_cross.reset(),
+        }
+    }
+
+    fn regs() -> &'static pac::lpcomp::RegisterBlock {
+        unsafe { &*pac::LPCOMP::ptr() }
+    }
+}
+
+impl<'d> Drop for Lpcomp<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xf) });
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.enable.write(|w| w.enable().disabled());
+    }
+}
@@ -0,0 +1,426 @@
+//! NFC-A tag (NFCT) driver.
+//!
+//! The NFCT peripheral implements the listen side of NFC-A (ISO 14443A): it detects the reader
+//! field, answers the anticollision and selection sequence in hardware, and then exchanges frames
+//! with the reader through EasyDMA. [`type2::Type2Tag`] and [`type4::Type4Tag`] build on it to
+//! emulate NDEF tags, for example for pairing or provisioning.
+//!
+//! The NFC pins must be left in NFC mode, so this driver is not available with the
+//! `nfc-pins-as-gpio` feature. The high-frequency crystal oscillator must be running while the
+//! tag is activated, select it with [`config::HfclkSource::ExternalXtal`](crate::config::HfclkSource).
+
+pub mod type2;
+pub mod type4;
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::peripherals::NFCT;
+use crate::util::slice_in_ram_or;
+use crate::{interrupt, pac, Peripheral};
+
+/// Largest frame the NFCT EasyDMA can transfer.
+pub const MAX_FRAME_LEN: usize = 257;
+
+/// NFCT error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The reader field was lost.
+    FieldLost,
+    /// A received frame had a CRC error.
+    Crc,
+    /// A received frame had a parity error.
+    Parity,
+    /// A received frame did not fit in the buffer.
+    Overrun,
+    /// The response was not sent within the frame delay window.
+    FrameDelayTimeout,
+    /// The buffer is longer than [`MAX_FRAME_LEN`].
+    BufferTooLong,
+    /// The buffer is not in data RAM. It's most likely in flash, and nRF's DMA cannot access flash.
+    BufferNotInRAM,
+}
+
+/// NFCID1, the tag identifier used during anticollision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NfcId {
+    /// 4-byte identifier.
+    SingleSize([u8; 4]),
+    /// 7-byte identifier.
+    DoubleSize([u8; 7]),
+    /// 10-byte identifier.
+    TripleSize([u8; 10]),
+}
+
+/// Protocol advertised to the reader in the SEL_RES response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    /// NFC Forum Type 2 tag.
+    Type2,
+    /// ISO-DEP (ISO 14443-4), used by NFC Forum Type 4A tags.
+    IsoDep,
+    /// NFC-DEP, for peer-to-peer.
+    NfcDep,
+    /// Both ISO-DEP and NFC-DEP.
+    IsoDepAndNfcDep,
+}
+
+/// Anticollision bit frame pattern advertised in the SENS_RES response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(missing_docs)]
+pub enum BitFrameSdd {
+    Sdd00000,
+    Sdd00001,
+    Sdd00010,
+    Sdd00100,
+    Sdd01000,
+    Sdd10000,
+}
+
+/// NFCT config
+#[non_exhaustive]
+pub struct Config {
+    /// Tag identifier.
+    pub nfcid1: NfcId,
+    /// Anticollision bit frame pattern.
+    pub bit_frame_sdd: BitFrameSdd,
+    /// Platform configuration bits of SENS_RES (0..=15), 0 for NFC Forum tags.
+    pub platform_config: u8,
+    /// Protocol advertised in SEL_RES.
+    pub protocol: Protocol,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nfcid1: NfcId::DoubleSize([0x5f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            bit_frame_sdd: BitFrameSdd::Sdd00001,
+            platform_config: 0,
+            protocol: Protocol::Type2,
+        }
+    }
+}
+
+/// Interrupt handler.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::NFCT> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        let r = regs();
+        // clear all interrupts
+        r.intenclr.write(|w| w.bits(0xffff_ffff));
+        WAKER.wake();
+    }
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// NFC-A tag driver.
+pub struct NfcT<'d> {
+    _p: PeripheralRef<'d, NFCT>,
+}
+
+impl<'d> NfcT<'d> {
+    /// Create a new NFCT driver and start sensing for a reader field.
+    pub fn new(
+        nfct: impl Peripheral<P = NFCT> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::NFCT, InterruptHandler> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(nfct);
+
+        let r = regs();
+
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        r.tasks_disable.write(|w| unsafe { w.bits(1) });
+
+        let (size, last, second_last, third_last) = match config.nfcid1 {
+            NfcId::SingleSize(id) => (0, u32::from_be_bytes(id), 0, 0),
+            NfcId::DoubleSize(id) => (
+                1,
+                u32::from_be_bytes([id[3], id[4], id[5], id[6]]),
+                u32::from_be_bytes([0, id[0], id[1], id[2]]),
+                0,
+            ),
+            NfcId::TripleSize(id) => (
+                2,
+                u32::from_be_bytes([id[6], id[7], id[8], id[9]]),
+                u32::from_be_bytes([0, id[3], id[4], id[5]]),
+                u32::from_be_bytes([0, id[0], id[1], id[2]]),
+            ),
+        };
+        r.nfcid1_last.write(|w| unsafe { w.bits(last) });
+        r.nfcid1_2nd_last.write(|w| unsafe { w.bits(second_last) });
+        r.nfcid1_3rd_last.write(|w| unsafe { w.bits(third_last) });
+
+        let sdd = match config.bit_frame_sdd {
+            BitFrameSdd::Sdd00000 => 0b00000,
+            BitFrameSdd::Sdd00001 => 0b00001,
+            BitFrameSdd::Sdd00010 => 0b00010,
+            BitFrameSdd::Sdd00100 => 0b00100,
+            BitFrameSdd::Sdd01000 => 0b01000,
+            BitFrameSdd::Sdd10000 => 0b10000,
+        };
+        r.sensres
+            .write(|w| unsafe { w.bits(sdd | size << 6 | ((config.platform_config as u32) & 0xf) << 8) });
+
+        let protocol = match config.protocol {
+            Protocol::Type2 => 0b00,
+            Protocol::IsoDep => 0b01,
+            Protocol::NfcDep => 0b10,
+            Protocol::IsoDepAndNfcDep => 0b11,
+        };
+        r.selres.write(|w| unsafe { w.protocol().bits(protocol) });
+
+        // Let the hardware answer anticollision, and respond within the ISO 14443A bit grid.
+        // Anticollision is always handled in hardware on the nRF52832.
+        #[cfg(not(feature = "nrf52832"))]
+        r.autocolresconfig.write(|w| w.mode().enabled());
+        r.framedelaymode.write(|w| w.framedelaymode().window_grid());
+
+        // Activate when a field is detected, go back to sensing when it is lost.
+        r.shorts
+            .write(|w| w.fielddetected_activate().enabled().fieldlost_sense().enabled());
+
+        interrupt::NFCT.unpend();
+        unsafe { interrupt::NFCT.enable() };
+
+        r.events_selected.reset();
+        r.events_fieldlost.reset();
+        r.tasks_sense.write(|w| unsafe { w.bits(1) });
+
+        Self { _p: nfct }
+    }
+
+    /// Wait until a reader selects the tag.
+    ///
+    /// Field detection, anticollision and selection are handled in hardware, this only returns
+    /// once the tag is ready to exchange frames.
+    pub async fn wait_for_selected(&mut self) {
+        let r = regs();
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_selected.read().bits() != 0 {
+                r.events_selected.reset();
+                r.events_fieldlost.reset();
+                r.framestatus.rx.write(|w| unsafe { w.bits(0xffff_ffff) });
+                return Poll::Ready(());
+            }
+
+            r.intenset.write(|w| w.selected().set());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Receive a frame from the reader, with parity and CRC checked.
+    ///
+    /// Returns the number of data bytes received, without the CRC.
+    pub async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() > MAX_FRAME_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        let r = regs();
+
+        r.rxd
+            .frameconfig
+            .write(|w| w.parity().parity().sof().so_f().crcmoderx().crc16rx());
+        r.packetptr.write(|w| unsafe { w.bits(buf.as_mut_ptr() as u32) });
+        r.maxlen.write(|w| unsafe { w.bits(buf.len() as u32) });
+
+        r.events_rxframeend.reset();
+        r.events_rxerror.reset();
+
+        let on_drop = OnDrop::new(|| {
+            let r = regs();
+            r.intenclr
+                .write(|w| w.rxframeend().clear().rxerror().clear().fieldlost().clear());
+            r.tasks_goidle.write(|w| unsafe { w.bits(1) });
+        });
+
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_enablerxdata.write(|w| unsafe { w.bits(1) });
+
+        let result = poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_fieldlost.read().bits() != 0 {
+                return Poll::Ready(Err(Error::FieldLost));
+            }
+            if r.events_rxframeend.read().bits() != 0 {
+                r.events_rxframeend.reset();
+                r.events_rxerror.reset();
+                return Poll::Ready(Ok(()));
+            }
+
+            r.intenset
+                .write(|w| w.rxframeend().set().rxerror().set().fieldlost().set());
+            Poll::Pending
+        })
+        .await;
+
+        compiler_fence(Ordering::SeqCst);
+        on_drop.defuse();
+        result?;
+
+        let status = r.framestatus.rx.read();
+        r.framestatus.rx.write(|w| unsafe { w.bits(status.bits()) });
+        if status.overrun().is_overrun() {
+            return Err(Error::Overrun);
+        }
+        if status.paritystatus().is_parity_error() {
+            return Err(Error::Parity);
+        }
+        if status.crcerror().is_crcerror() {
+            return Err(Error::Crc);
+        }
+
+        let len = r.rxd.amount.read().rxdatabytes().bits() as usize;
+        // The CRC is written to memory along with the data.
+        Ok(len.saturating_sub(2))
+    }
+
+    /// Send a frame to the reader, with parity and CRC added.
+    pub async fn transmit(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if buf.len() > MAX_FRAME_LEN {
+            return Err(Error::BufferTooLong);
+        }
+        slice_in_ram_or(buf, Error::BufferNotInRAM)?;
+
+        let r = regs();
+
+        r.txd.frameconfig.write(|w| {
+            w.parity()
+                .parity()
+                .discardmode()
+                .discard_start()
+                .sof()
+                .so_f()
+                .crcmodetx()
+                .crc16tx()
+        });
+        r.txd
+            .amount
+            .write(|w| unsafe { w.txdatabytes().bits(buf.len() as u16).txdatabits().bits(0) });
+        r.packetptr.write(|w| unsafe { w.bits(buf.as_ptr() as u32) });
+        r.maxlen.write(|w| unsafe { w.bits(buf.len() as u32) });
+
+        self.start_tx().await
+    }
+
+    /// Send a 4-bit ACK or NAK frame, without CRC, as used by NFC Forum Type 2 tags.
+    pub async fn transmit_nibble(&mut self, nibble: u8) -> Result<(), Error> {
+        let r = regs();
+
+        // The frame is read through EasyDMA, so it must be in RAM.
+        let buf = [nibble & 0x0f];
+
+        r.txd.frameconfig.write(|w| {
+            w.parity()
+                .parity()
+                .discardmode()
+                .discard_start()
+                .sof()
+                .so_f()
+                .crcmodetx()
+                .no_crctx()
+        });
+        r.txd
+            .amount
+            .write(|w| unsafe { w.txdatabytes().bits(0).txdatabits().bits(4) });
+        r.packetptr.write(|w| unsafe { w.bits(buf.as_ptr() as u32) });
+        r.maxlen.write(|w| unsafe { w.bits(1) });
+
+        self.start_tx().await
+    }
+
+    async fn start_tx(&mut self) -> Result<(), Error> {
+        let r = regs();
+
+        r.events_txframeend.reset();
+        r.events_error.reset();
+        r.errorstatus.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        let on_drop = OnDrop::new(|| {
+            let r = regs();
+            r.intenclr
+                .write(|w| w.txframeend().clear().error().clear().fieldlost().clear());
+            r.tasks_goidle.write(|w| unsafe { w.bits(1) });
+        });
+
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_starttx.write(|w| unsafe { w.bits(1) });
+
+        let result = poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_fieldlost.read().bits() != 0 {
+                return Poll::Ready(Err(Error::FieldLost));
+            }
+            if r.events_error.read().bits() != 0 {
+                r.events_error.reset();
+                r.errorstatus.write(|w| unsafe { w.bits(0xffff_ffff) });
+                return Poll::Ready(Err(Error::FrameDelayTimeout));
+            }
+            if r.events_txframeend.read().bits() != 0 {
+                r.events_txframeend.reset();
+                return Poll::Ready(Ok(()));
+            }
+
+            r.intenset
+                .write(|w| w.txframeend().set().error().set().fieldlost().set());
+            Poll::Pending
+        })
+        .await;
+
+        compiler_fence(Ordering::SeqCst);
+        on_drop.defuse();
+        result
+    }
+
+    /// Put the tag to sleep, after the reader sent HLTA.
+    ///
+    /// The tag answers anticollision again once woken up with WUPA.
+    pub fn sleep(&mut self) {
+        regs().tasks_gosleep.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Go back to idle, waiting for the next REQA or WUPA, e.g. after a protocol error.
+    pub fn idle(&mut self) {
+        regs().tasks_goidle.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Returns `true` if a reader field is present.
+    pub fn is_field_present(&self) -> bool {
+        regs().fieldpresent.read().fieldpresent().is_field_present()
+    }
+}
+
+impl<'d> Drop for NfcT<'d> {
+    fn drop(&mut self) {
+        let r = regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        r.shorts.reset();
+        r.tasks_disable.write(|w| unsafe { w.bits(1) });
+    }
+}
+
+fn regs() -> &'static pac::nfct::RegisterBlock {
+    unsafe { &*pac::NFCT::ptr() }
+}
@@ -0,0 +1,144 @@
+//! NFC Forum Type 2 tag emulation.
+//!
+//! The tag memory is organized in 4-byte pages. Pages 0 to 2 hold the identifier and lock bytes,
+//! page 3 the capability container, and the NDEF message is stored from page 4 in a TLV block.
+
+use super::{Error, NfcT};
+
+const CMD_READ: u8 = 0x30;
+const CMD_WRITE: u8 = 0xa2;
+const CMD_HALT: u8 = 0x50;
+
+const ACK: u8 = 0x0a;
+const NAK_INVALID_ARGUMENT: u8 = 0x00;
+
+const PAGE_SIZE: usize = 4;
+const DATA_START: usize = 4 * PAGE_SIZE;
+
+const TLV_NDEF: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xfe;
+
+/// Emulated Type 2 tag.
+pub struct Type2Tag<'a> {
+    memory: &'a mut [u8],
+    writable: bool,
+}
+
+impl<'a> Type2Tag<'a> {
+    /// Create a tag backed by `memory`, formatted for NDEF with no message.
+    ///
+    /// `uid` must be the identifier configured as [`NfcId::DoubleSize`](super::NfcId) in the
+    /// driver. `memory` must be a multiple of 8 bytes, at least 64 and at most 2048 bytes.
+    pub fn new(memory: &'a mut [u8], uid: [u8; 7], writable: bool) -> Self {
+        assert!(memory.len() >= 64 && memory.len() <= 2048 && memory.len() & 0x7 == 0);
+
+        memory.fill(0);
+        memory[0..3].copy_from_slice(&uid[0..3]);
+        memory[3] = 0x88 ^ uid[0] ^ uid[1] ^ uid[2];
+        memory[4..8].copy_from_slice(&uid[3..7]);
+        memory[8] = uid[3] ^ uid[4] ^ uid[5] ^ uid[6];
+
+        // Capability container: NDEF mapping version 1.0, data area size, access conditions.
+        memory[12] = 0xe1;
+        memory[13] = 0x10;
+        memory[14] = ((memory.len() - DATA_START) / 8) as u8;
+        memory[15] = if writable { 0x00 } else { 0x0f };
+
+        let mut tag = Self { memory, writable };
+        unwrap!(tag.set_message(&[]));
+        tag
+    }
+
+    /// Store an NDEF message in the tag.
+    pub fn set_message(&mut self, message: &[u8]) -> Result<(), Error> {
+        let data = &mut self.memory[DATA_START..];
+        let header = if message.len() < 0xff { 2 } else { 4 };
+        if header + message.len() + 1 > data.len() {
+            return Err(Error::BufferTooLong);
+        }
+
+        data[0] = TLV_NDEF;
+        if header == 2 {
+            data[1] = message.len() as u8;
+        } else {
+            data[1] = 0xff;
+            data[2..4].copy_from_slice(&(message.len() as u16).to_be_bytes());
+        }
+        data[header..header + message.len()].copy_from_slice(message);
+        data[header + message.len()] = TLV_TERMINATOR;
+        Ok(())
+    }
+
+    /// Get the NDEF message stored in the tag, if the NDEF TLV is valid.
+    ///
+    /// The reader may have changed it, if the tag is writable.
+    pub fn message(&self) -> Option<&[u8]> {
+        let data = &self.memory[DATA_START..];
+        if data[0] != TLV_NDEF {
+            return None;
+        }
+        let (header, len) = match data[1] {
+            0xff => (4, u16::from_be_bytes([data[2], data[3]]) as usize),
+            len => (2, len as usize),
+        };
+        data.get(header..header + len)
+    }
+
+    /// Get the raw tag memory.
+    pub fn memory(&self) -> &[u8] {
+        self.memory
+    }
+
+    /// Serve a reader until it halts the tag.
+    ///
+    /// Waits for the tag to be selected, then answers READ and WRITE commands. Returns `Ok` when
+    /// the reader sends HLTA, and [`Error::FieldLost`] when the field is removed.
+    pub async fn run(&mut self, nfct: &mut NfcT<'_>) -> Result<(), Error> {
+        let mut cmd = [0u8; 8];
+        let mut resp = [0u8; 16];
+
+        nfct.wait_for_selected().await;
+
+        loop {
+            let len = match nfct.receive(&mut cmd).await {
+                Ok(len) => len,
+                Err(Error::FieldLost) => return Err(Error::FieldLost),
+                // Ignore corrupted frames, the reader retries.
+                Err(_) => continue,
+            };
+
+            match (cmd[0], len) {
+                (CMD_READ, 2) => {
+                    // READ returns 4 pages, wrapping around the end of memory.
+                    let pages = self.memory.len() / PAGE_SIZE;
+                    let page = cmd[1] as usize;
+                    if page >= pages {
+                        nfct.transmit_nibble(NAK_INVALID_ARGUMENT).await?;
+                        continue;
+                    }
+                    for (i, chunk) in resp.chunks_mut(PAGE_SIZE).enumerate() {
+                        let start = (page + i) % pages * PAGE_SIZE;
+                        chunk.copy_from_slice(&self.memory[start..start + PAGE_SIZE]);
+                    }
+                    nfct.transmit(&resp).await?;
+                }
+                (CMD_WRITE, 6) => {
+                    let start = cmd[1] as usize * PAGE_SIZE;
+                    if !self.writable || start < DATA_START || start >= self.memory.len() {
+                        nfct.transmit_nibble(NAK_INVALID_ARGUMENT).await?;
+                        continue;
+                    }
+                    self.memory[start..start + PAGE_SIZE].copy_from_slice(&cmd[2..6]);
+                    nfct.transmit_nibble(ACK).await?;
+                }
+                (CMD_HALT, 2) if cmd[1] == 0x00 => {
+                    nfct.sleep();
+                    return Ok(());
+                }
+                _ => {
+                    nfct.transmit_nibble(NAK_INVALID_ARGUMENT).await?;
+                }
+            }
+        }
+    }
+}
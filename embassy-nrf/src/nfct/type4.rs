@@ -0,0 +1,257 @@
+//! NFC Forum Type 4 tag emulation.
+//!
+//! Implements enough of ISO-DEP (ISO 14443-4) and of the NDEF tag application for readers to
+//! read, and optionally write, a single NDEF file. Block chaining and CIDs are not supported,
+//! readers are told to keep frames within the driver buffers.
+//!
+//! The NFCT driver must be configured with [`Protocol::IsoDep`](super::Protocol).
+
+use super::{Error, NfcT};
+
+/// Frame size advertised in the ATS, FSCI 8 for 256 bytes.
+const FRAME_SIZE: usize = 256;
+/// Largest response payload, leaving room for the PCB, status word and CRC.
+const MAX_LE: usize = FRAME_SIZE - 5;
+/// Largest command payload, leaving room for the PCB, APDU header, Le and CRC.
+const MAX_LC: usize = FRAME_SIZE - 9;
+
+/// Answer to select: TL, T0 (TA, TB and TC present, FSCI 8), TA (106 kbit/s only),
+/// TB (FWI 8, SFGI 0), TC (no NAD, no CID).
+const ATS: [u8; 5] = [0x05, 0x78, 0x00, 0x80, 0x00];
+
+const RATS: u8 = 0xe0;
+const HLTA: u8 = 0x50;
+
+const NDEF_APPLICATION: [u8; 7] = [0xd2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+const CC_FILE: u16 = 0xe103;
+const NDEF_FILE: u16 = 0xe104;
+
+const SW_OK: [u8; 2] = [0x90, 0x00];
+const SW_WRONG_LENGTH: [u8; 2] = [0x67, 0x00];
+const SW_SECURITY: [u8; 2] = [0x69, 0x82];
+const SW_NOT_FOUND: [u8; 2] = [0x6a, 0x82];
+const SW_WRONG_PARAMS: [u8; 2] = [0x6b, 0x00];
+const SW_INS_NOT_SUPPORTED: [u8; 2] = [0x6d, 0x00];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum File {
+    None,
+    Cc,
+    Ndef,
+}
+
+/// Emulated Type 4 tag.
+pub struct Type4Tag<'a> {
+    ndef_file: &'a mut [u8],
+    writable: bool,
+    cc_file: [u8; 15],
+}
+
+impl<'a> Type4Tag<'a> {
+    /// Create a tag backed by `ndef_file`, with no message.
+    ///
+    /// The file holds a 2-byte length followed by the NDEF message, so it must be between 3 and
+    /// 32767 bytes long.
+    pub fn new(ndef_file: &'a mut [u8], writable: bool) -> Self {
+        assert!(ndef_file.len() >= 3 && ndef_file.len() <= 0x7fff);
+
+        let [max_le_hi, max_le_lo] = (MAX_LE as u16).to_be_bytes();
+        let [max_lc_hi, max_lc_lo] = (MAX_LC as u16).to_be_bytes();
+        let [size_hi, size_lo] = (ndef_file.len() as u16).to_be_bytes();
+        let [id_hi, id_lo] = NDEF_FILE.to_be_bytes();
+        let cc_file = [
+            0x00,
+            0x0f,
+            // Mapping version 2.0
+            0x20,
+            max_le_hi,
+            max_le_lo,
+            max_lc_hi,
+            max_lc_lo,
+            // NDEF file control TLV
+            0x04,
+            0x06,
+            id_hi,
+            id_lo,
+            size_hi,
+            size_lo,
+            0x00,
+            if writable { 0x00 } else { 0xff },
+        ];
+
+        ndef_file.fill(0);
+        Self {
+            ndef_file,
+            writable,
+            cc_file,
+        }
+    }
+
+    /// Store an NDEF message in the tag.
+    pub fn set_message(&mut self, message: &[u8]) -> Result<(), Error> {
+        if message.len() + 2 > self.ndef_file.len() {
+            return Err(Error::BufferTooLong);
+        }
+        self.ndef_file[..2].copy_from_slice(&(message.len() as u16).to_be_bytes());
+        self.ndef_file[2..2 + message.len()].copy_from_slice(message);
+        Ok(())
+    }
+
+    /// Get the NDEF message stored in the tag.
+    ///
+    /// The reader may have changed it, if the tag is writable.
+    pub fn message(&self) -> Option<&[u8]> {
+        let len = u16::from_be_bytes([self.ndef_file[0], self.ndef_file[1]]) as usize;
+        self.ndef_file.get(2..2 + len)
+    }
+
+    /// Serve a reader until it deselects or halts the tag.
+    ///
+    /// Returns `Ok` when the reader sends DESELECT or HLTA, and [`Error::FieldLost`] when the
+    /// field is removed.
+    pub async fn run(&mut self, nfct: &mut NfcT<'_>) -> Result<(), Error> {
+        let mut cmd = [0u8; FRAME_SIZE];
+        let mut resp = [0u8; FRAME_SIZE];
+        let mut resp_len = 0;
+        let mut file = File::None;
+        let mut active = false;
+
+        nfct.wait_for_selected().await;
+
+        loop {
+            let len = match nfct.receive(&mut cmd).await {
+                Ok(len) if len > 0 => len,
+                Err(Error::FieldLost) => return Err(Error::FieldLost),
+                // Ignore empty and corrupted frames, the reader retries.
+                _ => continue,
+            };
+            let pcb = cmd[0];
+
+            if !active {
+                match pcb {
+                    RATS => {
+                        active = true;
+                        nfct.transmit(&ATS).await?;
+                    }
+                    HLTA => {
+                        nfct.sleep();
+                        return Ok(());
+                    }
+                    // Type 2 commands and the like are not answered by ISO-DEP tags.
+                    _ => {}
+                }
+                continue;
+            }
+
+            match pcb & 0xe6 {
+                // I-block, without chaining: answer with the same block number.
+                0x02 if pcb & 0x10 == 0 => {
+                    resp[0] = pcb & 0x03;
+                    let apdu_len = self.process_apdu(&cmd[1..len], &mut resp[1..], &mut file);
+                    resp_len = 1 + apdu_len;
+                    nfct.transmit(&resp[..resp_len]).await?;
+                }
+                // R(NAK) for our last block: send it again.
+                0xa2 if pcb & 0x10 != 0 && resp_len > 0 && pcb & 0x01 == resp[0] & 0x01 => {
+                    nfct.transmit(&resp[..resp_len]).await?;
+                }
+                // Other R-blocks: acknowledge.
+                0xa2 => {
+                    let ack = [0xa2 | (pcb & 0x01)];
+                    nfct.transmit(&ack).await?;
+                }
+                // S(DESELECT)
+                0xc2 if pcb & 0x30 == 0 => {
+                    let deselect = [pcb];
+                    nfct.transmit(&deselect).await?;
+                    nfct.sleep();
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Handle a command APDU, returning the length of the response APDU.
+    fn process_apdu(&mut self, apdu: &[u8], resp: &mut [u8], file: &mut File) -> usize {
+        let status = |resp: &mut [u8], sw: [u8; 2]| {
+            resp[..2].copy_from_slice(&sw);
+            2
+        };
+
+        if apdu.len() < 4 || apdu[0] != 0x00 {
+            return status(resp, SW_INS_NOT_SUPPORTED);
+        }
+        let (ins, p1, p2) = (apdu[1], apdu[2], apdu[3]);
+        let body = &apdu[4..];
+
+        match ins {
+            // SELECT
+            0xa4 => {
+                let Some((&lc, data)) = body.split_first() else {
+                    return status(resp, SW_WRONG_LENGTH);
+                };
+                let Some(data) = data.get(..lc as usize) else {
+                    return status(resp, SW_WRONG_LENGTH);
+                };
+                match (p1, data) {
+                    (0x04, name) if name == NDEF_APPLICATION => {
+                        *file = File::None;
+                        status(resp, SW_OK)
+                    }
+                    (0x00, &[hi, lo]) if u16::from_be_bytes([hi, lo]) == CC_FILE => {
+                        *file = File::Cc;
+                        status(resp, SW_OK)
+                    }
+                    (0x00, &[hi, lo]) if u16::from_be_bytes([hi, lo]) == NDEF_FILE => {
+                        *file = File::Ndef;
+                        status(resp, SW_OK)
+                    }
+                    _ => status(resp, SW_NOT_FOUND),
+                }
+            }
+            // READ BINARY
+            0xb0 => {
+                let contents: &[u8] = match *file {
+                    File::Cc => &self.cc_file,
+                    File::Ndef => self.ndef_file,
+                    File::None => return status(resp, SW_NOT_FOUND),
+                };
+                let offset = u16::from_be_bytes([p1, p2]) as usize;
+                let le = match body.first() {
+                    Some(0) | None => MAX_LE,
+                    Some(&le) => le as usize,
+                };
+                if offset > contents.len() {
+                    return status(resp, SW_WRONG_PARAMS);
+                }
+                let n = le.min(MAX_LE).min(contents.len() - offset);
+                resp[..n].copy_from_slice(&contents[offset..offset + n]);
+                resp[n..n + 2].copy_from_slice(&SW_OK);
+                n + 2
+            }
+            // UPDATE BINARY
+            0xd6 => {
+                if *file != File::Ndef {
+                    return status(resp, SW_NOT_FOUND);
+                }
+                if !self.writable {
+                    return status(resp, SW_SECURITY);
+                }
+                let Some((&lc, data)) = body.split_first() else {
+                    return status(resp, SW_WRONG_LENGTH);
+                };
+                let Some(data) = data.get(..lc as usize) else {
+                    return status(resp, SW_WRONG_LENGTH);
+                };
+                let offset = u16::from_be_bytes([p1, p2]) as usize;
+                let Some(dest) = self.ndef_file.get_mut(offset..offset + data.len()) else {
+                    return status(resp, SW_WRONG_PARAMS);
+                };
+                dest.copy_from_slice(data);
+                status(resp, SW_OK)
+            }
+            _ => status(resp, SW_INS_NOT_SUPPORTED),
+        }
+    }
+}
@@ -20,6 +20,29 @@ pub const PAGE_SIZE: usize = 2048;
 /// Size of NVMC flash in bytes.
 pub const FLASH_SIZE: usize = crate::chip::FLASH_SIZE;
 
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52810",
+    feature = "nrf52811",
+    feature = "nrf52832"
+))]
+/// Granularity of flash protection in bytes, see [`Nvmc::protect`].
+pub const PROTECTION_BLOCK_SIZE: usize = 4096;
+#[cfg(any(
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840",
+    feature = "_nrf5340-net"
+))]
+/// Granularity of flash protection in bytes, see [`Nvmc::protect`].
+pub const PROTECTION_BLOCK_SIZE: usize = PAGE_SIZE;
+#[cfg(all(feature = "_nrf5340-app", feature = "_s"))]
+/// Granularity of flash protection in bytes, see [`Nvmc::protect`].
+pub const PROTECTION_BLOCK_SIZE: usize = 16 * 1024;
+#[cfg(all(feature = "_nrf9160", feature = "_s"))]
+/// Granularity of flash protection in bytes, see [`Nvmc::protect`].
+pub const PROTECTION_BLOCK_SIZE: usize = 32 * 1024;
+
 /// Error type for NVMC operations.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -28,6 +51,8 @@ pub enum Error {
     OutOfBounds,
     /// Unaligned operation or using unaligned buffers.
     Unaligned,
+    /// All the protection regions are already in use.
+    NoProtectionRegion,
 }
 
 impl NorFlashError for Error {
@@ -35,6 +60,7 @@ impl NorFlashError for Error {
         match self {
             Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
             Self::Unaligned => NorFlashErrorKind::NotAligned,
+            Self::NoProtectionRegion => NorFlashErrorKind::Other,
         }
     }
 }
@@ -55,6 +81,109 @@ impl<'d> Nvmc<'d> {
         unsafe { &*pac::NVMC::ptr() }
     }
 
+    /// Protect the flash from `from` to `to` against writes and erases, until the next reset.
+    ///
+    /// This is meant to be called early at startup, e.g. to lock a bootloader against accidental
+    /// writes from the application. `from` and `to` must be multiples of
+    /// [`PROTECTION_BLOCK_SIZE`]. The protection cannot be removed: writing or erasing a
+    /// protected block triggers a bus fault.
+    ///
+    /// It uses BPROT, ACL or SPU depending on the chip. ACL has 8 regions, each call uses one
+    /// of them. SPU is only accessible from the secure domain.
+    #[cfg(any(
+        feature = "nrf52805",
+        feature = "nrf52810",
+        feature = "nrf52811",
+        feature = "nrf52820",
+        feature = "nrf52832",
+        feature = "nrf52833",
+        feature = "nrf52840",
+        feature = "_nrf5340-net",
+        all(feature = "_nrf5340-app", feature = "_s"),
+        all(feature = "_nrf9160", feature = "_s"),
+    ))]
+    pub fn protect(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if to < from || to as usize > FLASH_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        if (from | to) as usize & (PROTECTION_BLOCK_SIZE - 1) != 0 {
+            return Err(Error::Unaligned);
+        }
+        if from == to {
+            return Ok(());
+        }
+
+        Self::protect_blocks(
+            from as usize / PROTECTION_BLOCK_SIZE,
+            to as usize / PROTECTION_BLOCK_SIZE,
+        )
+    }
+
+    #[cfg(any(
+        feature = "nrf52805",
+        feature = "nrf52810",
+        feature = "nrf52811",
+        feature = "nrf52832"
+    ))]
+    fn protect_blocks(first: usize, last: usize) -> Result<(), Error> {
+        let r = unsafe { &*pac::BPROT::ptr() };
+
+        // Each bit enables the protection of one block, writing 0 has no effect.
+        let mut config = [0u32; 4];
+        for block in first..last {
+            config[block / 32] |= 1 << (block % 32);
+        }
+
+        r.config0.write(|w| unsafe { w.bits(config[0]) });
+        r.config1.write(|w| unsafe { w.bits(config[1]) });
+        #[cfg(feature = "nrf52832")]
+        {
+            r.config2.write(|w| unsafe { w.bits(config[2]) });
+            r.config3.write(|w| unsafe { w.bits(config[3]) });
+        }
+        Ok(())
+    }
+
+    #[cfg(any(
+        feature = "nrf52820",
+        feature = "nrf52833",
+        feature = "nrf52840",
+        feature = "_nrf5340-net"
+    ))]
+    fn protect_blocks(first: usize, last: usize) -> Result<(), Error> {
+        let r = unsafe { &*pac::ACL::ptr() };
+
+        #[cfg(feature = "nrf52840")]
+        let regions = &r.acl;
+        #[cfg(not(feature = "nrf52840"))]
+        let regions = [&r.acl0, &r.acl1, &r.acl2, &r.acl3, &r.acl4, &r.acl5, &r.acl6, &r.acl7];
+
+        // A region can only be configured once, unused ones have a size of 0.
+        let region = regions
+            .iter()
+            .find(|region| region.size.read().size().bits() == 0)
+            .ok_or(Error::NoProtectionRegion)?;
+
+        region
+            .addr
+            .write(|w| unsafe { w.addr().bits((first * PROTECTION_BLOCK_SIZE) as u32) });
+        region.perm.write(|w| w.write().disable());
+        region
+            .size
+            .write(|w| unsafe { w.size().bits(((last - first) * PROTECTION_BLOCK_SIZE) as u32) });
+        Ok(())
+    }
+
+    #[cfg(all(any(feature = "_nrf5340-app", feature = "_nrf9160"), feature = "_s"))]
+    fn protect_blocks(first: usize, last: usize) -> Result<(), Error> {
+        let r = unsafe { &*pac::SPU::ptr() };
+
+        for region in &r.flashregion[first..last] {
+            region.perm.modify(|_, w| w.write().disable().lock().locked());
+        }
+        Ok(())
+    }
+
     fn wait_ready(&mut self) {
         let p = Self::regs();
         while p.ready.read().ready().is_busy() {}
@@ -4,7 +4,7 @@
 
 use core::future::poll_fn;
 use core::marker::PhantomData;
-use core::sync::atomic::{compiler_fence, Ordering};
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use core::task::Poll;
 
 use embassy_hal_internal::drop::OnDrop;
@@ -34,6 +34,32 @@ pub struct InterruptHandler<T: Instance> {
 impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
     unsafe fn on_interrupt() {
         let r = T::regs();
+        let s = T::state();
+
+        if s.streaming.load(Ordering::Relaxed) {
+            // Point the PDM at the other buffer as soon as it latched the current one, so frames
+            // always alternate between both buffers.
+            if r.events_started.read().bits() != 0 {
+                r.events_started.reset();
+                let started = s.frames_started.load(Ordering::Relaxed) + 1;
+                s.frames_started.store(started, Ordering::Relaxed);
+                let next = s.buffers[started % 2].load(Ordering::Relaxed);
+                r.sample.ptr.write(|w| w.sampleptr().bits(next));
+            }
+
+            if r.events_end.read().bits() != 0 {
+                r.events_end.reset();
+                let done = s.frames_done.load(Ordering::Relaxed) + 1;
+                s.frames_done.store(done, Ordering::Release);
+            }
+
+            if r.events_stopped.read().bits() != 0 {
+                r.intenclr.write(|w| w.stopped().clear());
+            }
+
+            s.waker.wake();
+            return;
+        }
 
         if r.events_end.read().bits() != 0 {
             r.intenclr.write(|w| w.end().clear());
@@ -69,6 +95,8 @@ pub enum Error {
     NotRunning,
     /// PDM is already running
     AlreadyRunning,
+    /// A frame was overwritten before it was read
+    Overrun,
 }
 
 static DUMMY_BUFFER: [i16; 1] = [0; 1];
@@ -361,6 +389,138 @@ impl<'d, T: Instance> Pdm<'d, T> {
     }
 }
 
+/// Continuous sampling into two buffers, delivering frames asynchronously.
+///
+/// Created with [`Pdm::stream`]. Frames are written alternately into both buffers: while one
+/// frame returned by [`read`](Self::read) is processed, the next one is being sampled into the
+/// other buffer.
+pub struct Stream<'s, 'd, T: Instance, const N: usize> {
+    _pdm: &'s mut Pdm<'d, T>,
+    buffers: &'s mut [[i16; N]; 2],
+    frames: usize,
+}
+
+impl<'d, T: Instance> Pdm<'d, T> {
+    /// Start continuous sampling into `buffers`.
+    ///
+    /// The PDM must not already be running, e.g. with [`start`](Self::start).
+    pub fn stream<'s, const N: usize>(
+        &'s mut self,
+        buffers: &'s mut [[i16; N]; 2],
+    ) -> Result<Stream<'s, 'd, T, N>, Error> {
+        if N == 0 {
+            return Err(Error::BufferZeroLength);
+        }
+        if N > EASY_DMA_SIZE {
+            return Err(Error::BufferTooLong);
+        }
+
+        let r = T::regs();
+        let s = T::state();
+
+        if r.events_started.read().bits() != 0 {
+            return Err(Error::AlreadyRunning);
+        }
+
+        s.buffers[0].store(buffers[0].as_mut_ptr() as u32, Ordering::Relaxed);
+        s.buffers[1].store(buffers[1].as_mut_ptr() as u32, Ordering::Relaxed);
+        s.frames_started.store(0, Ordering::Relaxed);
+        s.frames_done.store(0, Ordering::Relaxed);
+        s.streaming.store(true, Ordering::Relaxed);
+
+        r.sample
+            .ptr
+            .write(|w| unsafe { w.sampleptr().bits(buffers[0].as_mut_ptr() as u32) });
+        r.sample.maxcnt.write(|w| unsafe { w.buffsize().bits(N as _) });
+
+        r.events_end.reset();
+        r.events_started.reset();
+        r.events_stopped.reset();
+        r.intenset.write(|w| {
+            w.end().set();
+            w.started().set();
+            w.stopped().set();
+            w
+        });
+
+        compiler_fence(Ordering::SeqCst);
+
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        Ok(Stream {
+            _pdm: self,
+            buffers,
+            frames: 0,
+        })
+    }
+}
+
+impl<'s, 'd, T: Instance, const N: usize> Stream<'s, 'd, T, N> {
+    /// Wait for the next frame of samples.
+    ///
+    /// The frame must be processed before the next one is complete, otherwise it is overwritten
+    /// and [`Error::Overrun`] is returned. Reading continues with the newest frame after an overrun.
+    pub async fn read(&mut self) -> Result<&[i16; N], Error> {
+        let s = T::state();
+        let frames = self.frames;
+
+        let done = poll_fn(|cx| {
+            s.waker.register(cx.waker());
+            let done = s.frames_done.load(Ordering::Acquire);
+            if done > frames {
+                Poll::Ready(done)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        compiler_fence(Ordering::SeqCst);
+
+        if done - frames > 1 {
+            self.frames = done - 1;
+            return Err(Error::Overrun);
+        }
+
+        self.frames += 1;
+        Ok(&self.buffers[frames % 2])
+    }
+
+    /// Stop sampling and wait until the PDM has stopped.
+    pub async fn stop(self) {
+        let r = T::regs();
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            if r.events_stopped.read().bits() != 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+impl<'s, 'd, T: Instance, const N: usize> Drop for Stream<'s, 'd, T, N> {
+    fn drop(&mut self) {
+        let r = T::regs();
+
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        while r.events_stopped.read().bits() == 0 {}
+
+        r.intenclr.write(|w| {
+            w.end().clear();
+            w.started().clear();
+            w.stopped().clear();
+            w
+        });
+        T::state().streaming.store(false, Ordering::Relaxed);
+        r.events_started.reset();
+    }
+}
+
 /// PDM microphone driver Config
 pub struct Config {
     /// Use stero or mono operation
@@ -454,12 +614,20 @@ impl<'d, T: Instance> Drop for Pdm<'d, T> {
 /// Peripheral static state
 pub(crate) struct State {
     waker: AtomicWaker,
+    streaming: AtomicBool,
+    buffers: [AtomicU32; 2],
+    frames_started: AtomicUsize,
+    frames_done: AtomicUsize,
 }
 
 impl State {
     pub(crate) const fn new() -> Self {
         Self {
             waker: AtomicWaker::new(),
+            streaming: AtomicBool::new(false),
+            buffers: [AtomicU32::new(0), AtomicU32::new(0)],
+            frames_started: AtomicUsize::new(0),
+            frames_done: AtomicUsize::new(0),
         }
     }
 }
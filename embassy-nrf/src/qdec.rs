@@ -118,6 +118,19 @@ impl<'d, T: Instance> Qdec<'d, T> {
         r.ledpre
             .write(|w| unsafe { w.ledpre().bits(config.led_pre_usecs.min(511)) });
 
+        // Set number of samples per report
+        r.reportper.write(|w| match config.num_samples {
+            NumSamples::_10smpl => w.reportper()._10smpl(),
+            NumSamples::_40smpl => w.reportper()._40smpl(),
+            NumSamples::_80smpl => w.reportper()._80smpl(),
+            NumSamples::_120smpl => w.reportper()._120smpl(),
+            NumSamples::_160smpl => w.reportper()._160smpl(),
+            NumSamples::_200smpl => w.reportper()._200smpl(),
+            NumSamples::_240smpl => w.reportper()._240smpl(),
+            NumSamples::_280smpl => w.reportper()._280smpl(),
+            NumSamples::_1smpl => w.reportper()._1smpl(),
+        });
+
         // Set sample period
         r.sampleper.write(|w| match config.period {
             SamplePeriod::_128us => w.sampleper()._128us(),
@@ -184,6 +197,85 @@ impl<'d, T: Instance> Qdec<'d, T> {
         })
         .await
     }
+
+    /// Read and clear the accumulators without waiting for a report.
+    pub fn read_accumulator(&mut self) -> Report {
+        let r = T::regs();
+        r.tasks_readclracc.write(|w| unsafe { w.bits(1) });
+        Report::read::<T>()
+    }
+
+    /// Get a stream of reports, one for every `num_samples` samples with movement.
+    ///
+    /// The accumulators are latched and cleared by hardware when a report is ready, so no steps
+    /// are lost between reports as long as each one is read before the next is ready.
+    pub fn reports(&mut self) -> ReportStream<'_, 'd, T> {
+        let r = T::regs();
+        r.events_reportrdy.reset();
+        r.events_accof.reset();
+        r.shorts.modify(|_, w| w.reportrdy_readclracc().enabled());
+        ReportStream { _qdec: self }
+    }
+}
+
+/// Accumulated motion.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Report {
+    /// Number of steps, positive clockwise.
+    pub steps: i16,
+    /// Number of double transitions, where both inputs changed between two samples and the
+    /// direction could not be decoded.
+    pub double_transitions: u8,
+    /// The step accumulator overflowed, so steps were lost.
+    pub overflow: bool,
+}
+
+impl Report {
+    fn read<T: Instance>() -> Self {
+        let r = T::regs();
+        let overflow = r.events_accof.read().bits() != 0;
+        if overflow {
+            r.events_accof.reset();
+        }
+        Self {
+            steps: r.accread.read().bits() as i16,
+            double_transitions: r.accdblread.read().accdblread().bits(),
+            overflow,
+        }
+    }
+}
+
+/// Stream of QDEC reports.
+pub struct ReportStream<'a, 'd, T: Instance> {
+    _qdec: &'a mut Qdec<'d, T>,
+}
+
+impl<'a, 'd, T: Instance> ReportStream<'a, 'd, T> {
+    /// Wait for the next report.
+    pub async fn next(&mut self) -> Report {
+        let r = T::regs();
+        r.intenset.write(|w| w.reportrdy().set());
+
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            if r.events_reportrdy.read().bits() == 0 {
+                Poll::Pending
+            } else {
+                r.events_reportrdy.reset();
+                Poll::Ready(Report::read::<T>())
+            }
+        })
+        .await
+    }
+}
+
+impl<'a, 'd, T: Instance> Drop for ReportStream<'a, 'd, T> {
+    fn drop(&mut self) {
+        let r = T::regs();
+        r.shorts.modify(|_, w| w.reportrdy_readclracc().disabled());
+        r.intenclr.write(|w| w.reportrdy().clear());
+    }
 }
 
 /// Sample period
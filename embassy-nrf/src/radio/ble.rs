@@ -11,6 +11,7 @@ pub use pac::radio::mode::MODE_A as Mode;
 use pac::radio::pcnf0::PLEN_A as PreambleLength;
 
 use crate::interrupt::typelevel::Interrupt;
+use crate::ppi::{Event, Task};
 use crate::radio::*;
 pub use crate::radio::{Error, TxPower};
 use crate::util::slice_in_ram_or;
@@ -116,7 +117,7 @@ impl<'d, T: Instance> Radio<'d, T> {
         r.mode.write(|w| w.mode().variant(mode));
 
         #[cfg(not(feature = "nrf51"))]
-        r.pcnf0.write(|w| {
+        r.pcnf0.modify(|_, w| {
             w.plen().variant(match mode {
                 Mode::BLE_1MBIT => PreambleLength::_8BIT,
                 Mode::BLE_2MBIT => PreambleLength::_16BIT,
@@ -131,6 +132,46 @@ impl<'d, T: Instance> Radio<'d, T> {
                 _ => unimplemented!(),
             })
         });
+
+        // Coded PHY: 2-bit coding indicator and 3-bit TERM1 field
+        #[cfg(any(
+            feature = "nrf52811",
+            feature = "nrf52820",
+            feature = "nrf52833",
+            feature = "nrf52840",
+            feature = "_nrf5340-net"
+        ))]
+        {
+            let coded = matches!(mode, Mode::BLE_LR125KBIT | Mode::BLE_LR500KBIT);
+            r.pcnf0.modify(|_, w| unsafe {
+                w.cilen().bits(if coded { 2 } else { 0 });
+                w.termlen().bits(if coded { 3 } else { 0 })
+            });
+
+            // On the coded PHY, END is generated before TERM2 is sent, disabling on PHYEND
+            // avoids truncating the packet
+            r.shorts.write(|w| match coded {
+                true => w.ready_start().enabled().phyend_disable().enabled(),
+                false => w.ready_start().enabled().end_disable().enabled(),
+            });
+        }
+    }
+
+    /// Enable or disable fast ramp-up
+    ///
+    /// Fast ramp-up takes 40 us instead of 140 us, which changes the delay between TXEN/RXEN and
+    /// the start of the packet that link layer timings must account for.
+    ///
+    /// The radio must be disabled before calling this function
+    #[cfg(not(feature = "nrf51"))]
+    pub fn set_fast_ramp_up(&mut self, fast: bool) {
+        assert!(self.state() == RadioState::DISABLED);
+
+        let r = T::regs();
+        r.modecnf0.modify(|_, w| match fast {
+            true => w.ru().fast(),
+            false => w.ru().default(),
+        });
     }
 
     /// Set the header size changing the S1's len field
@@ -147,7 +188,7 @@ impl<'d, T: Instance> Radio<'d, T> {
             true => 8,
         };
 
-        r.pcnf0.write(|w| unsafe {
+        r.pcnf0.modify(|_, w| unsafe {
             w
                 // Configure S0 to 1 byte length, this will represent the Data/Adv header flags
                 .s0len()
@@ -334,6 +375,78 @@ impl<'d, T: Instance> Radio<'d, T> {
         Ok(())
     }
 
+    /// Send packet, at a time chosen by the hardware
+    ///
+    /// The radio is armed and waits for its TXEN task to be triggered, typically through PPI
+    /// from a TIMER compare event at the anchor point, see [`task_txen`](Self::task_txen).
+    /// If the length byte in the package is greater than the buffer length
+    /// the radio will read memory out of the buffer bounds
+    pub async fn transmit_scheduled(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        self.set_buffer(buffer)?;
+        self.trigger_and_wait_end(|| {}).await;
+
+        Ok(())
+    }
+
+    /// Receive packet, at a time chosen by the hardware
+    ///
+    /// The radio is armed and waits for its RXEN task to be triggered, typically through PPI
+    /// from a TIMER compare event, see [`task_rxen`](Self::task_rxen). To close the receive
+    /// window, trigger [`task_disable`](Self::task_disable) from another compare event; the
+    /// packet is then incomplete and [`is_crc_ok`](Self::is_crc_ok) returns `false`.
+    /// If the length byte in the received package is greater than the buffer length
+    /// the radio will write memory out of the buffer bounds
+    pub async fn receive_scheduled(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.set_buffer(buffer)?;
+        self.trigger_and_wait_end(|| {}).await;
+
+        Ok(())
+    }
+
+    /// Returns whether the CRC of the last received packet is valid
+    pub fn is_crc_ok(&self) -> bool {
+        T::regs().crcstatus.read().crcstatus().is_crcok()
+    }
+
+    /// Task to start the radio in transmit mode
+    pub fn task_txen(&self) -> Task<'d> {
+        Task::from_reg(&T::regs().tasks_txen)
+    }
+
+    /// Task to start the radio in receive mode
+    pub fn task_rxen(&self) -> Task<'d> {
+        Task::from_reg(&T::regs().tasks_rxen)
+    }
+
+    /// Task to disable the radio, e.g. to close a receive window
+    pub fn task_disable(&self) -> Task<'d> {
+        Task::from_reg(&T::regs().tasks_disable)
+    }
+
+    /// Event generated when the radio has ramped up
+    pub fn event_ready(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_ready)
+    }
+
+    /// Event generated when the access address has been sent or received
+    ///
+    /// Capture a TIMER on this event to timestamp the anchor point of a connection event.
+    pub fn event_address(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_address)
+    }
+
+    /// Event generated when the packet has been sent or received
+    pub fn event_end(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_end)
+    }
+
+    /// Event generated when the radio is disabled
+    ///
+    /// Start a TIMER on this event to schedule the response after the inter frame space.
+    pub fn event_disabled(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_disabled)
+    }
+
     async fn trigger_and_wait_end(&mut self, trigger: impl FnOnce()) {
         //self.trace_state();
 
@@ -346,13 +459,15 @@ impl<'d, T: Instance> Radio<'d, T> {
         let drop = OnDrop::new(|| {
             trace!("radio drop: stopping");
 
-            r.intenclr.write(|w| w.end().clear());
-            r.events_end.reset();
+            r.intenclr.write(|w| w.disabled().clear());
 
-            r.tasks_stop.write(|w| unsafe { w.bits(1) });
-
-            // The docs don't explicitly mention any event to acknowledge the stop task
-            while r.events_end.read().bits() == 0 {}
+            // The radio may still be waiting for a PPI trigger, in which case it is already disabled
+            if super::state(r) != RadioState::DISABLED {
+                r.events_disabled.reset();
+                r.tasks_disable.write(|w| unsafe { w.bits(1) });
+                while r.events_disabled.read().bits() == 0 {}
+            }
+            r.events_disabled.reset();
 
             trace!("radio drop: stopped");
         });
@@ -360,9 +475,11 @@ impl<'d, T: Instance> Radio<'d, T> {
         // trace!("radio:enable interrupt");
         // Clear some remnant side-effects (TODO: check if this is necessary)
         r.events_end.reset();
+        r.events_disabled.reset();
 
-        // Enable interrupt
-        r.intenset.write(|w| w.end().set());
+        // Wait for DISABLED rather than END: the shorts disable the radio after each packet,
+        // and a receive window closed with the DISABLE task has no END event
+        r.intenset.write(|w| w.disabled().set());
 
         compiler_fence(Ordering::SeqCst);
 
@@ -373,8 +490,8 @@ impl<'d, T: Instance> Radio<'d, T> {
         // On poll check if interrupt happen
         poll_fn(|cx| {
             s.event_waker.register(cx.waker());
-            if r.events_end.read().bits() == 1 {
-                // trace!("radio:end");
+            if r.events_disabled.read().bits() == 1 {
+                // trace!("radio:disabled");
                 return core::task::Poll::Ready(());
             }
             Poll::Pending
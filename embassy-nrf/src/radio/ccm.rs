@@ -0,0 +1,251 @@
+//! AES CCM encryption of Bluetooth Low Energy packets.
+//!
+//! The CCM block encrypts and authenticates link layer packets as specified for encrypted BLE
+//! connections, adding or checking the 4-byte MIC. Packets are processed from RAM to RAM, in the
+//! radio packet layout: S0 (header), LENGTH and S1 bytes followed by the payload. The radio must
+//! be configured with an 8-bit S1 field, see [`Radio::set_header_expansion`](super::ble::Radio::set_header_expansion).
+//!
+//! The CCM block shares its registers with the address resolver (AAR), only one of them can be
+//! used at a time.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::Error;
+use crate::interrupt::InterruptExt;
+use crate::peripherals::CCM;
+use crate::util::slice_in_ram_or;
+use crate::{interrupt, pac, Peripheral};
+
+/// Length of the packet header processed by the CCM block: S0, LENGTH and S1.
+const HEADER_LEN: usize = 3;
+/// Length of the message integrity check.
+pub const MIC_LEN: usize = 4;
+/// Largest payload with the default length field, larger payloads need the extended mode.
+const DEFAULT_MAX_PAYLOAD: usize = 27;
+/// Scratch area needed for the largest packet, 16 bytes plus MAXPACKETSIZE.
+const SCRATCH_LEN: usize = 16 + 251;
+
+/// Interrupt handler.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::CCM_AAR> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        let r = unsafe { &*pac::CCM::ptr() };
+        r.intenclr
+            .write(|w| w.endksgen().clear().endcrypt().clear().error().clear());
+        WAKER.wake();
+    }
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Packet direction.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Packets sent by the peripheral to the central.
+    PeripheralToCentral,
+    /// Packets sent by the central to the peripheral.
+    CentralToPeripheral,
+}
+
+/// Session parameters, laid out as the CCM data structure read by the peripheral.
+///
+/// It must be in RAM, as it is read with EasyDMA.
+#[derive(Clone)]
+#[repr(C)]
+pub struct CcmData {
+    key: [u8; 16],
+    counter: [u8; 8],
+    direction: u8,
+    iv: [u8; 8],
+}
+
+impl CcmData {
+    /// Create the session parameters from the session key and initialization vector.
+    ///
+    /// `key` is the session key, in the byte order of the CCM data structure.
+    pub fn new(key: [u8; 16], iv: [u8; 8]) -> Self {
+        Self {
+            key,
+            counter: [0; 8],
+            direction: 0,
+            iv,
+        }
+    }
+
+    /// Set the 39-bit packet counter.
+    pub fn set_counter(&mut self, counter: u64) {
+        self.counter = (counter & 0x7f_ffff_ffff).to_le_bytes();
+    }
+
+    /// Get the 39-bit packet counter.
+    pub fn counter(&self) -> u64 {
+        u64::from_le_bytes(self.counter)
+    }
+
+    /// Set the direction of the next packets.
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.direction = match direction {
+            Direction::PeripheralToCentral => 0,
+            Direction::CentralToPeripheral => 1,
+        };
+    }
+}
+
+/// CCM driver.
+pub struct Ccm<'d> {
+    _peri: PeripheralRef<'d, CCM>,
+}
+
+impl<'d> Ccm<'d> {
+    /// Create a new CCM driver.
+    pub fn new(
+        ccm: impl Peripheral<P = CCM> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::CCM_AAR, InterruptHandler> + 'd,
+    ) -> Self {
+        into_ref!(ccm);
+
+        let r = Self::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        interrupt::CCM_AAR.unpend();
+        unsafe { interrupt::CCM_AAR.enable() };
+
+        Self { _peri: ccm }
+    }
+
+    /// Encrypt the packet in `input` into `output`, appending the MIC.
+    ///
+    /// The LENGTH byte of `output` is the one of `input` plus [`MIC_LEN`]. `output` must be at
+    /// least [`MIC_LEN`] bytes longer than the packet. Packets with an empty payload are copied
+    /// without a MIC.
+    ///
+    /// The packet counter is not incremented, the caller must do it after each packet.
+    pub async fn encrypt(&mut self, data: &CcmData, input: &[u8], output: &mut [u8]) -> Result<(), Error> {
+        let len = Self::payload_len(input)?;
+        if output.len() < HEADER_LEN + len + MIC_LEN {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.crypt(true, data, input, output, len).await
+    }
+
+    /// Decrypt the packet in `input` into `output`, checking and removing the MIC.
+    ///
+    /// The LENGTH byte of `output` is the one of `input` minus [`MIC_LEN`]. Returns
+    /// [`Error::MicFailed`] if the packet is not authentic.
+    pub async fn decrypt(&mut self, data: &CcmData, input: &[u8], output: &mut [u8]) -> Result<(), Error> {
+        let len = Self::payload_len(input)?;
+        if len != 0 && len < MIC_LEN {
+            return Err(Error::BufferTooShort);
+        }
+        if output.len() < HEADER_LEN + len {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.crypt(false, data, input, output, len.saturating_sub(MIC_LEN))
+            .await?;
+
+        if len != 0 && Self::regs().micstatus.read().micstatus().is_check_failed() {
+            return Err(Error::MicFailed);
+        }
+        Ok(())
+    }
+
+    fn payload_len(packet: &[u8]) -> Result<usize, Error> {
+        let len = *packet.get(1).ok_or(Error::BufferTooShort)? as usize;
+        if packet.len() < HEADER_LEN + len {
+            return Err(Error::BufferTooShort);
+        }
+        Ok(len)
+    }
+
+    async fn crypt(
+        &mut self,
+        encrypt: bool,
+        data: &CcmData,
+        input: &[u8],
+        output: &mut [u8],
+        len: usize,
+    ) -> Result<(), Error> {
+        slice_in_ram_or(input, Error::BufferNotInRAM)?;
+        slice_in_ram_or(core::slice::from_ref(data), Error::BufferNotInRAM)?;
+
+        // Lives in the future, until the CCM block is stopped.
+        let mut scratch = [0u8; SCRATCH_LEN];
+        let r = Self::regs();
+
+        r.enable.write(|w| w.enable().enabled());
+        r.mode.write(|w| {
+            match encrypt {
+                true => w.mode().encryption(),
+                false => w.mode().decryption(),
+            };
+            match len > DEFAULT_MAX_PAYLOAD {
+                true => w.length().extended(),
+                false => w.length().default(),
+            }
+        });
+        r.cnfptr.write(|w| unsafe { w.bits(data as *const _ as u32) });
+        r.inptr.write(|w| unsafe { w.bits(input.as_ptr() as u32) });
+        r.outptr.write(|w| unsafe { w.bits(output.as_mut_ptr() as u32) });
+        r.scratchptr.write(|w| unsafe { w.bits(scratch.as_mut_ptr() as u32) });
+
+        r.events_endcrypt.reset();
+        r.events_error.reset();
+        r.shorts.write(|w| w.endksgen_crypt().enabled());
+        r.intenset.write(|w| w.endcrypt().set().error().set());
+
+        let on_drop = OnDrop::new(|| {
+            let r = Self::regs();
+            r.intenclr.write(|w| w.endcrypt().clear().error().clear());
+            r.tasks_stop.write(|w| unsafe { w.bits(1) });
+            r.enable.write(|w| w.enable().disabled());
+        });
+
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_ksgen.write(|w| unsafe { w.bits(1) });
+
+        let result = poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_error.read().bits() != 0 {
+                r.events_error.reset();
+                return Poll::Ready(Err(Error::BufferTooLong));
+            }
+            if r.events_endcrypt.read().bits() != 0 {
+                r.events_endcrypt.reset();
+                return Poll::Ready(Ok(()));
+            }
+            Poll::Pending
+        })
+        .await;
+
+        compiler_fence(Ordering::SeqCst);
+        drop(on_drop);
+
+        result
+    }
+
+    fn regs() -> &'static pac::ccm::RegisterBlock {
+        unsafe { &*pac::CCM::ptr() }
+    }
+}
+
+impl<'d> Drop for Ccm<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        r.shorts.reset();
+        r.enable.write(|w| w.enable().disabled());
+    }
+}
@@ -9,11 +9,42 @@ use embassy_hal_internal::{into_ref, PeripheralRef};
 use super::{state, Error, Instance, InterruptHandler, RadioState, TxPower};
 use crate::interrupt::typelevel::Interrupt;
 use crate::interrupt::{self};
+use crate::ppi::Event;
 use crate::Peripheral;
 
 /// Default (IEEE compliant) Start of Frame Delimiter
 pub const DEFAULT_SFD: u8 = 0xA7;
 
+/// Broadcast PAN ID and short address
+pub const BROADCAST: u16 = 0xFFFF;
+
+// Frame control field
+const FRAME_TYPE_MASK: u16 = 0b111;
+const FRAME_TYPE_ACK: u16 = 0b010;
+const FRAME_PENDING: u16 = 1 << 4;
+const ACK_REQUEST: u16 = 1 << 5;
+const SEQ_SUPPRESSION: u16 = 1 << 8;
+const DST_ADDR_MODE_SHIFT: u16 = 10;
+
+/// aUnitBackoffPeriod, 20 symbols of 16 us
+#[cfg(feature = "time")]
+const UNIT_BACKOFF_PERIOD_US: u64 = 320;
+/// macAckWaitDuration, 54 symbols of 16 us
+#[cfg(feature = "time")]
+const ACK_WAIT_DURATION_US: u64 = 864;
+/// macMinBE
+#[cfg(feature = "time")]
+const MIN_BE: u8 = 3;
+/// macMaxBE
+#[cfg(feature = "time")]
+const MAX_BE: u8 = 5;
+/// macMaxCSMABackoffs
+#[cfg(feature = "time")]
+const MAX_CSMA_BACKOFFS: u8 = 4;
+/// macMaxFrameRetries
+#[cfg(feature = "time")]
+const MAX_FRAME_RETRIES: u8 = 3;
+
 // TODO expose the other variants in `pac::CCAMODE_A`
 /// Clear Channel Assessment method
 pub enum Cca {
@@ -34,6 +65,9 @@ pub enum Cca {
 pub struct Radio<'d, T: Instance> {
     _p: PeripheralRef<'d, T>,
     needs_enable: bool,
+    pan_id: Option<u16>,
+    short_address: Option<u16>,
+    extended_address: Option<u64>,
 }
 
 impl<'d, T: Instance> Radio<'d, T> {
@@ -52,6 +86,8 @@ impl<'d, T: Instance> Radio<'d, T> {
 
         // Enable 802.15.4 mode
         r.mode.write(|w| w.mode().ieee802154_250kbit());
+        // Fast ramp-up, to answer within the 192 us turnaround time
+        r.modecnf0.modify(|_, w| w.ru().fast());
         // Configure CRC skip address
         r.crccnf.write(|w| w.len().two().skipaddr().ieee802154());
         unsafe {
@@ -107,6 +143,9 @@ impl<'d, T: Instance> Radio<'d, T> {
         let mut radio = Self {
             _p: radio,
             needs_enable: false,
+            pan_id: None,
+            short_address: None,
+            extended_address: None,
         };
 
         radio.set_sfd(DEFAULT_SFD);
@@ -150,6 +189,33 @@ impl<'d, T: Instance> Radio<'d, T> {
         r.sfd.write(|w| unsafe { w.sfd().bits(sfd) });
     }
 
+    /// Changes the PAN ID used to filter received frames
+    pub fn set_pan_id(&mut self, pan_id: Option<u16>) {
+        self.pan_id = pan_id;
+    }
+
+    /// Changes the short address used to filter and acknowledge received frames
+    pub fn set_short_address(&mut self, address: Option<u16>) {
+        self.short_address = address;
+    }
+
+    /// Changes the extended address used to filter and acknowledge received frames
+    pub fn set_extended_address(&mut self, address: Option<u64>) {
+        self.extended_address = address;
+    }
+
+    /// Event generated when the SFD of a frame has been sent or received
+    ///
+    /// Connect it to a TIMER capture task with PPI to timestamp frames.
+    pub fn event_framestart(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_framestart)
+    }
+
+    /// Event generated when the last bit of a frame has been sent or received
+    pub fn event_phyend(&self) -> Event<'d> {
+        Event::from_reg(&T::regs().events_phyend)
+    }
+
     /// Clear interrupts
     pub fn clear_all_interrupts(&mut self) {
         let r = T::regs();
@@ -441,6 +507,322 @@ impl<'d, T: Instance> Radio<'d, T> {
             TransmitResult::ChannelInUse => Err(Error::ChannelInUse),
         }
     }
+
+    /// Measures the energy on the current channel
+    ///
+    /// The measurement lasts `periods` periods of 128 us (8 symbols), and the highest level
+    /// measured is returned. See [`Cca::EnergyDetection`] for the range of the value.
+    pub async fn energy_detection(&mut self, periods: u32) -> u8 {
+        let s = T::state();
+        let r = T::regs();
+
+        self.receive_prepare();
+        r.events_edend.reset();
+
+        r.edcnt
+            .write(|w| unsafe { w.edcnt().bits(periods.clamp(1, 0x10_0000) - 1) });
+        r.shorts.write(|w| w.ready_edstart().enabled());
+
+        let dropper = OnDrop::new(|| {
+            let r = T::regs();
+            r.shorts.reset();
+            r.tasks_edstop.write(|w| w.tasks_edstop().set_bit());
+        });
+
+        match self.state() {
+            RadioState::RX_IDLE => r.tasks_edstart.write(|w| w.tasks_edstart().set_bit()),
+            _ => r.tasks_rxen.write(|w| w.tasks_rxen().set_bit()),
+        }
+
+        self.clear_all_interrupts();
+        core::future::poll_fn(|cx| {
+            s.event_waker.register(cx.waker());
+
+            if r.events_edend.read().events_edend().bit_is_set() {
+                r.events_edend.reset();
+                return Poll::Ready(());
+            }
+
+            r.intenset.write(|w| w.edend().set());
+            Poll::Pending
+        })
+        .await;
+
+        dropper.defuse();
+        r.shorts.reset();
+
+        r.edsample.read().edlvl().bits()
+    }
+
+    /// Performs a Clear Channel Assessment on the current channel, with the method set with
+    /// [`set_cca`](Self::set_cca)
+    ///
+    /// Returns [`Error::ChannelInUse`] if the channel is busy.
+    pub async fn clear_channel_assessment(&mut self) -> Result<(), Error> {
+        let s = T::state();
+        let r = T::regs();
+
+        self.receive_prepare();
+        r.events_ccaidle.reset();
+
+        r.shorts.write(|w| w.rxready_ccastart().enabled());
+
+        let dropper = OnDrop::new(|| {
+            let r = T::regs();
+            r.shorts.reset();
+            r.tasks_ccastop.write(|w| w.tasks_ccastop().set_bit());
+        });
+
+        match self.state() {
+            RadioState::RX_IDLE => r.tasks_ccastart.write(|w| w.tasks_ccastart().set_bit()),
+            _ => r.tasks_rxen.write(|w| w.tasks_rxen().set_bit()),
+        }
+
+        self.clear_all_interrupts();
+        let idle = core::future::poll_fn(|cx| {
+            s.event_waker.register(cx.waker());
+
+            if r.events_ccaidle.read().events_ccaidle().bit_is_set() {
+                r.events_ccaidle.reset();
+                return Poll::Ready(true);
+            } else if r.events_ccabusy.read().events_ccabusy().bit_is_set() {
+                r.events_ccabusy.reset();
+                return Poll::Ready(false);
+            }
+
+            r.intenset.write(|w| w.ccaidle().set().ccabusy().set());
+            Poll::Pending
+        })
+        .await;
+
+        dropper.defuse();
+        r.shorts.reset();
+
+        match idle {
+            true => Ok(()),
+            false => Err(Error::ChannelInUse),
+        }
+    }
+
+    /// Sends the given `packet` right away, without Clear Channel Assessment
+    ///
+    /// NOTE this method will *not* modify the `packet` argument. The mutable reference is used to
+    /// ensure the `packet` buffer is allocated in RAM, which is required by the RADIO peripheral
+    pub async fn send_now(&mut self, packet: &mut Packet) {
+        let s = T::state();
+        let r = T::regs();
+
+        // NOTE to avoid errata 204 (see rev1 v1.4) we go through DISABLED before TX
+        self.disable();
+        self.needs_enable = false;
+        r.events_phyend.reset();
+
+        r.shorts
+            .write(|w| w.txready_start().enabled().phyend_disable().enabled());
+
+        self.set_buffer(packet.buffer.as_mut());
+
+        let dropper = OnDrop::new(|| {
+            let r = T::regs();
+            r.shorts.reset();
+            r.tasks_disable.write(|w| w.tasks_disable().set_bit());
+        });
+
+        dma_start_fence();
+        r.tasks_txen.write(|w| w.tasks_txen().set_bit());
+
+        self.clear_all_interrupts();
+        core::future::poll_fn(|cx| {
+            s.event_waker.register(cx.waker());
+
+            if r.events_phyend.read().events_phyend().bit_is_set() {
+                r.events_phyend.reset();
+                trace!("TX done poll");
+                return Poll::Ready(());
+            }
+
+            r.intenset.write(|w| w.phyend().set());
+            Poll::Pending
+        })
+        .await;
+
+        dropper.defuse();
+    }
+
+    /// Receives one frame addressed to this device, and acknowledges it if requested
+    ///
+    /// Frames are filtered with the addresses set with [`set_pan_id`](Self::set_pan_id),
+    /// [`set_short_address`](Self::set_short_address) and
+    /// [`set_extended_address`](Self::set_extended_address); all frames are received if none is
+    /// set. Frames with an invalid CRC are dropped.
+    ///
+    /// `frame_pending` is called with the received frame to choose the frame pending bit of the
+    /// acknowledgment, e.g. when a child polls for indirect data. The acknowledgment is sent as
+    /// soon as the frame is received, so the turnaround time depends on the priority of the
+    /// executor running this future.
+    pub async fn receive_and_ack(
+        &mut self,
+        packet: &mut Packet,
+        mut frame_pending: impl FnMut(&[u8]) -> bool,
+    ) -> Result<(), Error> {
+        loop {
+            match self.receive(packet).await {
+                Ok(()) => {}
+                Err(Error::CrcFailed(_)) => continue,
+                Err(e) => return Err(e),
+            }
+
+            let Some(header) = Header::parse(packet) else {
+                continue;
+            };
+            let Some(unicast) = self.accepts(&header) else {
+                continue;
+            };
+
+            if unicast && header.ack_request() {
+                let mut ack = Packet::new_ack(header.seq, frame_pending(packet));
+                self.send_now(&mut ack).await;
+            }
+
+            return Ok(());
+        }
+    }
+
+    /// Sends the given `packet` with unslotted CSMA-CA, retrying until the acknowledgment is
+    /// received if the frame requests one
+    ///
+    /// `rng` is used for the random backoff. Returns the frame pending bit of the
+    /// acknowledgment, or `false` if the frame does not request an acknowledgment.
+    ///
+    /// NOTE this method will *not* modify the `packet` argument. The mutable reference is used to
+    /// ensure the `packet` buffer is allocated in RAM, which is required by the RADIO peripheral
+    #[cfg(feature = "time")]
+    pub async fn send(&mut self, packet: &mut Packet, rng: &mut impl rand_core::RngCore) -> Result<bool, Error> {
+        let seq = match Header::parse(packet) {
+            Some(header) if header.ack_request() && header.fcf & SEQ_SUPPRESSION == 0 => Some(header.seq),
+            _ => None,
+        };
+
+        let mut ack = Packet::new();
+        for _ in 0..=MAX_FRAME_RETRIES {
+            self.send_csma_ca(packet, rng).await?;
+
+            let Some(seq) = seq else {
+                return Ok(false);
+            };
+
+            let wait_ack = async {
+                loop {
+                    if self.receive(&mut ack).await.is_ok() {
+                        if let Some(pending) = ack.ack_for(seq) {
+                            return pending;
+                        }
+                    }
+                }
+            };
+            let timeout = embassy_time::Duration::from_micros(ACK_WAIT_DURATION_US);
+            if let Ok(pending) = embassy_time::with_timeout(timeout, wait_ack).await {
+                return Ok(pending);
+            }
+        }
+
+        Err(Error::NoAck)
+    }
+
+    /// Sends the given `packet` with unslotted CSMA-CA, without waiting for an acknowledgment
+    ///
+    /// `rng` is used for the random backoff.
+    #[cfg(feature = "time")]
+    pub async fn send_csma_ca(&mut self, packet: &mut Packet, rng: &mut impl rand_core::RngCore) -> Result<(), Error> {
+        let mut be = MIN_BE;
+        for _ in 0..=MAX_CSMA_BACKOFFS {
+            let periods = rng.next_u32() & ((1 << be) - 1);
+            embassy_time::Timer::after_micros(periods as u64 * UNIT_BACKOFF_PERIOD_US).await;
+
+            match self.try_send(packet).await {
+                Err(Error::ChannelInUse) => be = (be + 1).min(MAX_BE),
+                result => return result,
+            }
+        }
+
+        Err(Error::ChannelInUse)
+    }
+
+    /// Checks the destination of a received frame
+    ///
+    /// Returns `None` if the frame is not for this device, and whether it is unicast otherwise.
+    fn accepts(&self, header: &Header) -> Option<bool> {
+        if self.pan_id.is_none() && self.short_address.is_none() && self.extended_address.is_none() {
+            // Promiscuous mode
+            return Some(false);
+        }
+
+        if let Some(dst_pan) = header.dst_pan {
+            if dst_pan != BROADCAST && Some(dst_pan) != self.pan_id {
+                return None;
+            }
+        }
+
+        match header.dst {
+            Address::None => Some(false),
+            Address::Short(BROADCAST) => Some(false),
+            Address::Short(address) if Some(address) == self.short_address => Some(true),
+            Address::Extended(address) if Some(address) == self.extended_address => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Destination address of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Address {
+    None,
+    Short(u16),
+    Extended(u64),
+}
+
+/// The fields of a MAC header needed for filtering and acknowledgment
+struct Header {
+    fcf: u16,
+    seq: u8,
+    dst_pan: Option<u16>,
+    dst: Address,
+}
+
+impl Header {
+    fn parse(frame: &[u8]) -> Option<Self> {
+        let fcf = u16::from_le_bytes([*frame.first()?, *frame.get(1)?]);
+        let mut pos = 2;
+
+        let seq = match fcf & SEQ_SUPPRESSION {
+            0 => {
+                pos += 1;
+                *frame.get(2)?
+            }
+            _ => 0,
+        };
+
+        let dst_mode = (fcf >> DST_ADDR_MODE_SHIFT) & 0b11;
+        let dst_pan = match dst_mode {
+            0 => None,
+            _ => {
+                let pan = u16::from_le_bytes([*frame.get(pos)?, *frame.get(pos + 1)?]);
+                pos += 2;
+                Some(pan)
+            }
+        };
+        let dst = match dst_mode {
+            0b10 => Address::Short(u16::from_le_bytes([*frame.get(pos)?, *frame.get(pos + 1)?])),
+            0b11 => Address::Extended(u64::from_le_bytes(frame.get(pos..pos + 8)?.try_into().ok()?)),
+            _ => Address::None,
+        };
+
+        Some(Self { fcf, seq, dst_pan, dst })
+    }
+
+    fn ack_request(&self) -> bool {
+        self.fcf & FRAME_TYPE_MASK != FRAME_TYPE_ACK && self.fcf & ACK_REQUEST != 0
+    }
 }
 
 /// An IEEE 802.15.4 packet
@@ -506,6 +888,31 @@ impl Packet {
         self.buffer[Self::PHY_HDR] = len + Self::CRC;
     }
 
+    /// Returns an immediate acknowledgment for the frame with sequence number `seq`
+    fn new_ack(seq: u8, frame_pending: bool) -> Self {
+        let mut fcf = FRAME_TYPE_ACK;
+        if frame_pending {
+            fcf |= FRAME_PENDING;
+        }
+        let [fcf0, fcf1] = fcf.to_le_bytes();
+        let mut packet = Self::new();
+        packet.copy_from_slice(&[fcf0, fcf1, seq]);
+        packet
+    }
+
+    /// If this packet is an immediate acknowledgment for sequence number `seq`, returns its
+    /// frame pending bit
+    fn ack_for(&self, seq: u8) -> Option<bool> {
+        if self.len() != 3 {
+            return None;
+        }
+        let fcf = u16::from_le_bytes([self[0], self[1]]);
+        if fcf & FRAME_TYPE_MASK != FRAME_TYPE_ACK || self[2] != seq {
+            return None;
+        }
+        Some(fcf & FRAME_PENDING != 0)
+    }
+
     /// Returns the LQI (Link Quality Indicator) of the received packet
     ///
     /// Note that the LQI is stored in the `Packet`'s internal buffer by the hardware so the value
@@ -7,6 +7,16 @@
 
 /// Bluetooth Low Energy Radio driver.
 pub mod ble;
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52810",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52832",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+pub mod ccm;
 #[cfg(any(
     feature = "nrf52811",
     feature = "nrf52820",
@@ -40,6 +50,10 @@ pub enum Error {
     ChannelInUse,
     /// CRC check failed
     CrcFailed(u16),
+    /// No acknowledgment was received after the last retry
+    NoAck,
+    /// Message integrity check failed
+    MicFailed,
 }
 
 /// Interrupt handler
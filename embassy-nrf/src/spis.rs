@@ -15,6 +15,7 @@ pub use pac::spis0::config::ORDER_A as BitOrder;
 use crate::chip::{EASY_DMA_SIZE, FORCE_COPY_BUFFER_SIZE};
 use crate::gpio::{self, AnyPin, Pin as GpioPin, SealedPin as _};
 use crate::interrupt::typelevel::Interrupt;
+use crate::ppi::Event;
 use crate::util::{slice_in_ram_or, slice_ptr_parts, slice_ptr_parts_mut};
 use crate::{interrupt, pac, Peripheral};
 
@@ -225,6 +226,9 @@ impl<'d, T: Instance> Spis<'d, T> {
 
         let r = T::regs();
 
+        // Clear status register, so it only reports the coming transaction.
+        r.status.write(|w| w.overflow().clear().overread().clear());
+
         // Set up the DMA write.
         let (ptr, len) = slice_ptr_parts(tx);
         if len > EASY_DMA_SIZE {
@@ -292,9 +296,6 @@ impl<'d, T: Instance> Spis<'d, T> {
         let r = T::regs();
         let s = T::state();
 
-        // Clear status register.
-        r.status.write(|w| w.overflow().clear().overread().clear());
-
         // Acquire semaphore.
         if r.semstat.read().bits() != 1 {
             // Reset and enable the acquire event.
@@ -437,6 +438,24 @@ impl<'d, T: Instance> Spis<'d, T> {
     pub fn is_overflow(&mut self) -> bool {
         T::regs().status.read().overflow().is_present()
     }
+
+    /// Returns reference to `End` event endpoint for PPI.
+    ///
+    /// Generated when the transaction ends, i.e. when CSN is released by the master.
+    #[inline(always)]
+    pub fn event_end(&self) -> Event<'d> {
+        let r = T::regs();
+
+        Event::from_reg(&r.events_end)
+    }
+
+    /// Returns reference to `Acquired` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_acquired(&self) -> Event<'d> {
+        let r = T::regs();
+
+        Event::from_reg(&r.events_acquired)
+    }
 }
 
 impl<'d, T: Instance> Drop for Spis<'d, T> {
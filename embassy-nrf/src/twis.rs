@@ -16,6 +16,7 @@ use embassy_time::{Duration, Instant};
 use crate::chip::{EASY_DMA_SIZE, FORCE_COPY_BUFFER_SIZE};
 use crate::gpio::Pin as GpioPin;
 use crate::interrupt::typelevel::Interrupt;
+use crate::ppi::Event;
 use crate::util::slice_in_ram_or;
 use crate::{gpio, interrupt, pac, Peripheral};
 
@@ -291,6 +292,34 @@ impl<'d, T: Instance> Twis<'d, T> {
         T::regs().match_.read().bits() as _
     }
 
+    /// Returns reference to `Write` event endpoint for PPI.
+    ///
+    /// Generated when a write command matching one of the addresses is received.
+    #[inline(always)]
+    pub fn event_write(&self) -> Event<'d> {
+        let r = T::regs();
+
+        Event::from_reg(&r.events_write)
+    }
+
+    /// Returns reference to `Read` event endpoint for PPI.
+    ///
+    /// Generated when a read command matching one of the addresses is received.
+    #[inline(always)]
+    pub fn event_read(&self) -> Event<'d> {
+        let r = T::regs();
+
+        Event::from_reg(&r.events_read)
+    }
+
+    /// Returns reference to `Stopped` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_stopped(&self) -> Event<'d> {
+        let r = T::regs();
+
+        Event::from_reg(&r.events_stopped)
+    }
+
     /// Wait for read, write, stop or error
     fn blocking_listen_wait(&mut self) -> Result<Status, Error> {
         let r = T::regs();
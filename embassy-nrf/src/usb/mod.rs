@@ -25,9 +25,15 @@ use crate::{interrupt, pac, Peripheral};
 const NEW_AW: AtomicWaker = AtomicWaker::new();
 static BUS_WAKER: AtomicWaker = NEW_AW;
 static EP0_WAKER: AtomicWaker = NEW_AW;
+static SOF_WAKER: AtomicWaker = NEW_AW;
 static EP_IN_WAKERS: [AtomicWaker; 8] = [NEW_AW; 8];
 static EP_OUT_WAKERS: [AtomicWaker; 8] = [NEW_AW; 8];
 static READY_ENDPOINTS: AtomicU32 = AtomicU32::new(0);
+/// Number of SOF events seen by the interrupt handler, wrapping.
+static SOF_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Index of the isochronous endpoints.
+const ISO_INDEX: usize = 8;
 
 /// Interrupt handler.
 pub struct InterruptHandler<T: Instance> {
@@ -68,6 +74,20 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
             BUS_WAKER.wake();
         }
 
+        // Isochronous transfers are scheduled by SOF: data received during the previous frame can
+        // be read, and data for the next IN token can be written. The interrupt is only enabled
+        // while someone waits for it, as it fires every millisecond.
+        if regs.events_sof.read().bits() != 0 {
+            regs.events_sof.reset();
+            regs.intenclr.write(|w| w.sof().clear());
+
+            READY_ENDPOINTS.fetch_or(In::mask(ISO_INDEX) | Out::mask(ISO_INDEX), Ordering::AcqRel);
+            SOF_COUNT.fetch_add(1, Ordering::AcqRel);
+            In::waker(ISO_INDEX).wake();
+            Out::waker(ISO_INDEX).wake();
+            SOF_WAKER.wake();
+        }
+
         if regs.events_epdata.read().bits() != 0 {
             regs.events_epdata.reset();
 
@@ -113,6 +133,13 @@ impl<'d, T: Instance, V: VbusDetect> Driver<'d, T, V> {
             vbus_detect,
         }
     }
+
+    /// Get a handle to wait for start-of-frame (SOF) packets.
+    ///
+    /// Only one task at a time should wait for SOF.
+    pub fn sof(&mut self) -> Sof<'d, T> {
+        Sof { _phantom: PhantomData }
+    }
 }
 
 impl<'d, T: Instance, V: VbusDetect + 'd> driver::Driver<'d> for Driver<'d, T, V> {
@@ -159,6 +186,7 @@ impl<'d, T: Instance, V: VbusDetect + 'd> driver::Driver<'d> for Driver<'d, T, V
                 _p: unsafe { self._p.clone_unchecked() },
                 power_available: false,
                 vbus_detect: self.vbus_detect,
+                iso_split: self.alloc_in.is_used(ISO_INDEX) && self.alloc_out.is_used(ISO_INDEX),
             },
             ControlPipe {
                 _p: self._p,
@@ -173,6 +201,7 @@ pub struct Bus<'d, T: Instance, V: VbusDetect> {
     _p: PeripheralRef<'d, T>,
     power_available: bool,
     vbus_detect: V,
+    iso_split: bool,
 }
 
 impl<'d, T: Instance, V: VbusDetect> driver::Bus for Bus<'d, T, V> {
@@ -198,6 +227,14 @@ impl<'d, T: Instance, V: VbusDetect> driver::Bus for Bus<'d, T, V> {
 
         errata::post_enable();
 
+        // The isochronous buffer is shared between IN and OUT when both are used.
+        regs.isosplit.write(|w| match self.iso_split {
+            true => w.split().half_in(),
+            false => w.split().one_dir(),
+        });
+        // Answer IN tokens with a zero-length packet when no data was written for the frame.
+        regs.isoinconfig.write(|w| w.response().zero_data());
+
         unsafe { NVIC::unmask(pac::Interrupt::USBD) };
 
         regs.intenset.write(|w| {
@@ -234,7 +271,7 @@ impl<'d, T: Instance, V: VbusDetect> driver::Bus for Bus<'d, T, V> {
                 regs.epinen.write(|w| unsafe { w.bits(0x01) });
                 regs.epouten.write(|w| unsafe { w.bits(0x01) });
                 READY_ENDPOINTS.store(In::mask(0), Ordering::Release);
-                for i in 1..=7 {
+                for i in 1..=ISO_INDEX {
                     In::waker(i).wake();
                     Out::waker(i).wake();
                 }
@@ -356,7 +393,9 @@ impl<'d, T: Instance, V: VbusDetect> driver::Bus for Bus<'d, T, V> {
                     // peripheral will NAK all incoming packets) until we write a zero to the SIZE
                     // register (see figure 203 of the 52840 manual). To avoid that we write a 0 to the
                     // SIZE register
-                    regs.size.epout[i].reset();
+                    if i != ISO_INDEX {
+                        regs.size.epout[i].reset();
+                    }
                 } else {
                     READY_ENDPOINTS.fetch_and(!ready_mask, Ordering::AcqRel);
                 }
@@ -501,6 +540,9 @@ impl<'d, T: Instance, Dir> Endpoint<'d, T, Dir> {
             } else if r & Dir::mask(i) != 0 {
                 Poll::Ready(Ok(()))
             } else {
+                if i == ISO_INDEX {
+                    T::regs().intenset.write(|w| w.sof().set());
+                }
                 Poll::Pending
             }
         })
@@ -586,30 +628,148 @@ unsafe fn write_dma<T: Instance>(i: usize, buf: &[u8]) {
     dma_end();
 }
 
+unsafe fn read_iso_dma<T: Instance>(buf: &mut [u8]) -> Result<usize, EndpointError> {
+    let regs = T::regs();
+
+    // Data received during the previous frame, if any
+    let r = regs.size.isoout.read();
+    let size = if r.zero().is_zero_data() {
+        0
+    } else {
+        r.size().bits() as usize
+    };
+    if size > buf.len() {
+        return Err(EndpointError::BufferOverflow);
+    }
+    if size == 0 {
+        return Ok(0);
+    }
+
+    regs.isoout.ptr.write(|w| w.bits(buf.as_ptr() as u32));
+    regs.isoout.maxcnt.write(|w| w.bits(size as u32));
+
+    dma_start();
+    regs.events_endisoout.reset();
+    regs.tasks_startisoout.write(|w| w.bits(1));
+    while regs.events_endisoout.read().bits() == 0 {}
+    regs.events_endisoout.reset();
+    dma_end();
+
+    Ok(size)
+}
+
+unsafe fn write_iso_dma<T: Instance>(buf: &[u8]) {
+    if slice_in_ram(buf) {
+        write_iso_dma_ram::<T>(buf)
+    } else {
+        write_iso_dma_bounce::<T>(buf)
+    }
+}
+
+/// EasyDMA can't read FLASH, so we copy through RAM. This is kept out of line so that the 1023
+/// byte bounce buffer is only on the stack when it is needed.
+#[inline(never)]
+unsafe fn write_iso_dma_bounce<T: Instance>(buf: &[u8]) {
+    assert!(buf.len() <= 1023);
+    let mut ram_buf: MaybeUninit<[u8; 1023]> = MaybeUninit::uninit();
+    let ptr = ram_buf.as_mut_ptr() as *mut u8;
+    core::ptr::copy_nonoverlapping(buf.as_ptr(), ptr, buf.len());
+    write_iso_dma_ram::<T>(core::slice::from_raw_parts(ptr, buf.len()))
+}
+
+unsafe fn write_iso_dma_ram<T: Instance>(buf: &[u8]) {
+    let regs = T::regs();
+
+    regs.isoin.ptr.write(|w| w.bits(buf.as_ptr() as u32));
+    regs.isoin.maxcnt.write(|w| w.bits(buf.len() as u32));
+
+    regs.events_endisoin.reset();
+
+    dma_start();
+    regs.tasks_startisoin.write(|w| w.bits(1));
+    while regs.events_endisoin.read().bits() == 0 {}
+    regs.events_endisoin.reset();
+    dma_end();
+}
+
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
+    /// Read a packet.
+    ///
+    /// For the isochronous endpoint, this waits for the next start of frame and returns the data
+    /// received during the previous frame, which can be empty.
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
         let i = self.info.addr.index();
         assert!(i != 0);
 
         self.wait_data_ready().await.map_err(|_| EndpointError::Disabled)?;
 
+        if i == ISO_INDEX {
+            return unsafe { read_iso_dma::<T>(buf) };
+        }
+
         unsafe { read_dma::<T>(i, buf) }
     }
 }
 
 impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
+    /// Write a packet.
+    ///
+    /// For the isochronous endpoint, this waits for the next start of frame and queues the data
+    /// for the IN token of that frame.
     async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
         let i = self.info.addr.index();
         assert!(i != 0);
 
+        if i == ISO_INDEX && buf.len() > self.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+
         self.wait_data_ready().await.map_err(|_| EndpointError::Disabled)?;
 
-        unsafe { write_dma::<T>(i, buf) }
+        if i == ISO_INDEX {
+            unsafe { write_iso_dma::<T>(buf) }
+        } else {
+            unsafe { write_dma::<T>(i, buf) }
+        }
 
         Ok(())
     }
 }
 
+/// Start-of-frame notifications.
+pub struct Sof<'d, T: Instance> {
+    _phantom: PhantomData<&'d T>,
+}
+
+impl<'d, T: Instance> Sof<'d, T> {
+    /// Wait for the next start-of-frame packet, and return its frame number.
+    pub async fn wait(&mut self) -> u16 {
+        let regs = T::regs();
+
+        // Only a SOF counted by the interrupt handler after this point completes the wait.
+        let count = SOF_COUNT.load(Ordering::Acquire);
+        poll_fn(|cx| {
+            SOF_WAKER.register(cx.waker());
+            if SOF_COUNT.load(Ordering::Acquire) != count {
+                Poll::Ready(())
+            } else {
+                // The handler disables the interrupt on each SOF, e.g. one the isochronous
+                // endpoints waited for before ours, so enable it again on every poll.
+                regs.intenset.write(|w| w.sof().set());
+                Poll::Pending
+            }
+        })
+        .await;
+
+        self.frame_number()
+    }
+
+    /// Returns the frame number of the last start-of-frame packet.
+    pub fn frame_number(&self) -> u16 {
+        T::regs().framecntr.read().framecntr().bits()
+    }
+}
+
 /// USB control pipe.
 pub struct ControlPipe<'d, T: Instance> {
     _p: PeripheralRef<'d, T>,
@@ -756,6 +916,10 @@ impl Allocator {
         Self { used: 0 }
     }
 
+    fn is_used(&self, index: usize) -> bool {
+        self.used & (1 << index) != 0
+    }
+
     fn allocate(&mut self, ep_type: EndpointType) -> Result<usize, driver::EndpointAllocError> {
         // Endpoint addresses are fixed in hardware:
         // - 0x80 / 0x00 - Control        EP0
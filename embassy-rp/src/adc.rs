@@ -8,6 +8,7 @@ use core::task::Poll;
 use embassy_hal_internal::{into_ref, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 
+use crate::dma::Channel as _;
 use crate::gpio::{self, AnyPin, Pull, SealedPin as GpioPin};
 use crate::interrupt::typelevel::Binding;
 use crate::interrupt::InterruptExt;
@@ -106,6 +107,8 @@ impl Sample {
 pub enum Error {
     /// Error converting value.
     ConversionFailed,
+    /// Invalid channel selection or buffer for the requested operation.
+    InvalidArgument,
 }
 
 /// ADC mode.
@@ -219,18 +222,26 @@ impl<'d> Adc<'d, Async> {
         }
     }
 
-    async fn read_many_inner<W: dma::Word>(
-        &mut self,
-        ch: &mut Channel<'_>,
-        buf: &mut [W],
-        fcs_err: bool,
-        div: u16,
-        dma: impl Peripheral<P = impl dma::Channel>,
-    ) -> Result<(), Error> {
+    /// Select the channels to convert and set up the FIFO for DMA.
+    ///
+    /// With several channels, the ADC converts them round-robin in ascending channel order.
+    fn setup_many<W: dma::Word>(chs: &[Channel<'_>], fcs_err: bool) -> Result<(), Error> {
+        let mut mask = 0u8;
+        for ch in chs {
+            if mask & (1 << ch.channel()) != 0 {
+                return Err(Error::InvalidArgument);
+            }
+            mask |= 1 << ch.channel();
+        }
+        if mask == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
         let r = Self::regs();
-        // clear previous errors and set channel
+        // clear previous errors and set channels
         r.cs().modify(|w| {
-            w.set_ainsel(ch.channel());
+            w.set_ainsel(mask.trailing_zeros() as u8);
+            w.set_rrobin(if chs.len() > 1 { mask } else { 0 });
             w.set_err_sticky(true); // clear previous errors
             w.set_start_many(false);
         });
@@ -248,22 +259,26 @@ impl<'d> Adc<'d, Async> {
             w.set_shift(mem::size_of::<W>() == 1);
             w.set_en(true);
             w.set_err(fcs_err);
+            // clear overflow/underflow flags
+            w.set_over(true);
+            w.set_under(true);
         });
+        Ok(())
+    }
+
+    async fn read_many_inner<W: dma::Word>(
+        &mut self,
+        chs: &mut [Channel<'_>],
+        buf: &mut [W],
+        fcs_err: bool,
+        div: u16,
+        dma: impl Peripheral<P = impl dma::Channel>,
+    ) -> Result<(), Error> {
+        let r = Self::regs();
+        Self::setup_many::<W>(chs, fcs_err)?;
 
         // reset dma config on drop, regardless of whether it was a future being cancelled
         // or the method returning normally.
-        struct ResetDmaConfig;
-        impl Drop for ResetDmaConfig {
-            fn drop(&mut self) {
-                pac::ADC.cs().write_clear(|w| w.set_start_many(true));
-                while !pac::ADC.cs().read().ready() {}
-                pac::ADC.fcs().write_clear(|w| {
-                    w.set_dreq_en(true);
-                    w.set_shift(true);
-                    w.set_en(true);
-                });
-            }
-        }
         let auto_reset = ResetDmaConfig;
 
         let dma = unsafe { dma::read(dma, r.fifo().as_ptr() as *const W, buf as *mut [W], 36) };
@@ -292,7 +307,95 @@ impl<'d> Adc<'d, Async> {
         div: u16,
         dma: impl Peripheral<P = impl dma::Channel>,
     ) -> Result<(), Error> {
-        self.read_many_inner(ch, buf, false, div, dma).await
+        self.read_many_inner(core::slice::from_mut(ch), buf, false, div, dma)
+            .await
+    }
+
+    /// Sample multiple channels round-robin using DMA.
+    ///
+    /// Samples are interleaved in ascending channel order, regardless of the order of `chs`:
+    /// with channels 0 and 2, `buf` receives `[ch0, ch2, ch0, ch2, ...]`.
+    #[inline]
+    pub async fn read_many_multichannel<S: AdcSample>(
+        &mut self,
+        chs: &mut [Channel<'_>],
+        buf: &mut [S],
+        div: u16,
+        dma: impl Peripheral<P = impl dma::Channel>,
+    ) -> Result<(), Error> {
+        self.read_many_inner(chs, buf, false, div, dma).await
+    }
+
+    /// Start converting continuously into `buf` using two chained DMA channels.
+    ///
+    /// `buf` is filled one half at a time, without stopping: each half is returned by
+    /// [`ContinuousCapture::next`] while the DMA writes the other half, and must be processed
+    /// before that one is full. Samples of several channels are interleaved in ascending channel
+    /// order, as for [`read_many_multichannel`](Self::read_many_multichannel).
+    ///
+    /// The length of `buf` must be twice a power of two samples, and each half must fit in
+    /// 32 KiB. `buf` must be aligned to the size of a half in bytes, e.g. by wrapping it in a
+    /// `#[repr(align(N))]` struct, as the halves are DMA address rings.
+    pub fn start_continuous<'a, S: AdcSample>(
+        &'a mut self,
+        chs: &'a mut [Channel<'_>],
+        buf: &'a mut [S],
+        div: u16,
+        dma_a: impl Peripheral<P = impl dma::Channel> + 'a,
+        dma_b: impl Peripheral<P = impl dma::Channel> + 'a,
+    ) -> Result<ContinuousCapture<'a, S>, Error> {
+        into_ref!(dma_a, dma_b);
+
+        let half = buf.len() / 2;
+        let half_bytes = half * mem::size_of::<S>();
+        // a ring size of 0 disables wrapping, so halves must be at least 2 bytes.
+        if half_bytes < 2
+            || buf.len() != 2 * half
+            || !half_bytes.is_power_of_two()
+            || half_bytes > 1 << 15
+            || (buf.as_ptr() as usize) & (half_bytes - 1) != 0
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let r = Self::regs();
+        Self::setup_many::<S>(chs, false)?;
+
+        let dma_a = dma_a.map_into();
+        let dma_b = dma_b.map_into();
+        let ring_size = half_bytes.trailing_zeros() as u8;
+        for (ch, chain_to, offset) in [(&dma_a, dma_b.number(), 0), (&dma_b, dma_a.number(), half)] {
+            let p = ch.regs();
+            p.read_addr().write_value(r.fifo().as_ptr() as u32);
+            p.write_addr().write_value(buf[offset..].as_ptr() as u32);
+            p.trans_count().write_value(half as u32);
+            let mut w = pac::dma::regs::CtrlTrig(0);
+            w.set_treq_sel(pac::dma::vals::TreqSel(36));
+            w.set_data_size(S::size());
+            w.set_incr_read(false);
+            w.set_incr_write(true);
+            // wrap the write address within the half, so the channel needs no re-arming
+            w.set_ring_sel(true);
+            w.set_ring_size(ring_size);
+            w.set_chain_to(chain_to);
+            w.set_en(true);
+            // don't trigger the channels yet
+            p.al1_ctrl().write_value(w.0);
+        }
+
+        compiler_fence(Ordering::SeqCst);
+        // channel B is triggered by A, and A by B afterwards.
+        dma_a.regs().ctrl_trig().modify(|_| {});
+        r.div().write_set(|w| w.set_int(div));
+        r.cs().write_set(|w| w.set_start_many(true));
+
+        Ok(ContinuousCapture {
+            _phantom: PhantomData,
+            buf: buf.as_mut_ptr(),
+            half,
+            dma: [dma_a, dma_b],
+            next: 0,
+        })
     }
 
     /// Sample multiple values from a channel using DMA with errors inlined in samples.
@@ -306,11 +409,98 @@ impl<'d> Adc<'d, Async> {
     ) {
         // errors are reported in individual samples
         let _ = self
-            .read_many_inner(ch, unsafe { mem::transmute::<_, &mut [u16]>(buf) }, true, div, dma)
+            .read_many_inner(
+                core::slice::from_mut(ch),
+                unsafe { mem::transmute::<_, &mut [u16]>(buf) },
+                true,
+                div,
+                dma,
+            )
             .await;
     }
 }
 
+// reset dma config on drop, regardless of whether it was a future being cancelled
+// or the method returning normally.
+struct ResetDmaConfig;
+
+impl Drop for ResetDmaConfig {
+    fn drop(&mut self) {
+        pac::ADC.cs().write_clear(|w| {
+            w.set_start_many(true);
+            w.set_rrobin(0x1f);
+        });
+        while !pac::ADC.cs().read().ready() {}
+        pac::ADC.fcs().write_clear(|w| {
+            w.set_dreq_en(true);
+            w.set_shift(true);
+            w.set_en(true);
+        });
+    }
+}
+
+/// Continuous ADC capture, started by [`Adc::start_continuous`].
+///
+/// Conversions stop when it is dropped.
+pub struct ContinuousCapture<'a, S: AdcSample> {
+    _phantom: PhantomData<(&'a mut Adc<'a, Async>, &'a mut [S])>,
+    buf: *mut S,
+    half: usize,
+    dma: [PeripheralRef<'a, dma::AnyChannel>; 2],
+    next: usize,
+}
+
+impl<'a, S: AdcSample> ContinuousCapture<'a, S> {
+    /// Wait for the next half of the buffer to be filled, and return it.
+    ///
+    /// The returned samples are overwritten once the other half is full, so they must be
+    /// processed in time. Data is lost silently if a whole half is missed.
+    pub async fn next(&mut self) -> Result<&[S], Error> {
+        let index = self.next;
+        poll_fn(|cx| {
+            let this = &self.dma[index];
+            let other = &self.dma[1 - index];
+            dma::CHANNEL_WAKERS[this.number() as usize].register(cx.waker());
+            dma::CHANNEL_WAKERS[other.number() as usize].register(cx.waker());
+
+            // the half is full once its channel stopped, or once the other channel it
+            // triggered is running.
+            if !this.regs().ctrl_trig().read().busy() || other.regs().ctrl_trig().read().busy() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        compiler_fence(Ordering::SeqCst);
+        self.next = 1 - index;
+
+        let r = pac::ADC;
+        if r.cs().read().err_sticky() {
+            r.cs().write_set(|w| w.set_err_sticky(true));
+            return Err(Error::ConversionFailed);
+        }
+
+        Ok(unsafe { core::slice::from_raw_parts(self.buf.add(index * self.half), self.half) })
+    }
+}
+
+impl<'a, S: AdcSample> Drop for ContinuousCapture<'a, S> {
+    fn drop(&mut self) {
+        // stop the chain before aborting, so an aborted channel doesn't trigger the other one.
+        for ch in &self.dma {
+            ch.regs().al1_ctrl().write_value(0);
+        }
+        pac::DMA.chan_abort().write(|w| {
+            w.set_chan_abort((1 << self.dma[0].number()) | (1 << self.dma[1].number()));
+        });
+        for ch in &self.dma {
+            while ch.regs().ctrl_trig().read().busy() {}
+        }
+        drop(ResetDmaConfig);
+    }
+}
+
 impl<'d> Adc<'d, Blocking> {
     /// Create ADC driver in blocking mode.
     pub fn new_blocking(_inner: impl Peripheral<P = ADC> + 'd, _config: Config) -> Self {
@@ -0,0 +1,28 @@
+//! Hardware divider
+//!
+//! Each core has its own 32-bit hardware divider in SIO, which computes a quotient and
+//! remainder in 8 cycles. With the `intrinsics` feature, the compiler's division routines
+//! already use it, so this module is only needed to get both results from a single division.
+//!
+//! The divider state is saved and restored by any code that interrupts a division in progress,
+//! so these functions can be used from any task or interrupt handler.
+
+pub use crate::intrinsics::DivResult;
+
+/// Unsigned division, returning both quotient and remainder.
+///
+/// Dividing by zero returns a quotient of `0xFFFF_FFFF` and the dividend as the remainder.
+#[inline]
+pub fn div_u32(dividend: u32, divisor: u32) -> DivResult<u32> {
+    crate::intrinsics::divider_unsigned(dividend, divisor)
+}
+
+/// Signed division, returning both quotient and remainder.
+///
+/// The quotient is rounded towards zero and the remainder has the sign of the dividend, like
+/// the `/` and `%` operators. Dividing by zero returns a quotient of `-1` for positive
+/// dividends and `1` for negative ones, and the dividend as the remainder.
+#[inline]
+pub fn div_i32(dividend: i32, divisor: i32) -> DivResult<i32> {
+    crate::intrinsics::divider_signed(dividend, divisor)
+}
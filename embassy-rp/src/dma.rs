@@ -85,6 +85,12 @@ pub unsafe fn write<'a, C: Channel, W: Word>(
 // static mut so that this is allocated in RAM.
 static mut DUMMY: u32 = 0;
 
+/// Address of a word in RAM, to read dummy data from or discard data into in repeated transfers.
+#[allow(unused_unsafe)]
+pub(crate) fn dummy_ptr() -> *mut u32 {
+    unsafe { core::ptr::addr_of_mut!(DUMMY) }
+}
+
 /// DMA repeated write.
 ///
 /// SAFETY: Slice must point to a valid location reachable by DMA.
@@ -206,7 +212,7 @@ impl<'a, C: Channel> Future for Transfer<'a, C> {
 
 pub(crate) const CHANNEL_COUNT: usize = 12;
 const NEW_AW: AtomicWaker = AtomicWaker::new();
-static CHANNEL_WAKERS: [AtomicWaker; CHANNEL_COUNT] = [NEW_AW; CHANNEL_COUNT];
+pub(crate) static CHANNEL_WAKERS: [AtomicWaker; CHANNEL_COUNT] = [NEW_AW; CHANNEL_COUNT];
 
 trait SealedChannel {}
 trait SealedWord {}
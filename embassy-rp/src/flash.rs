@@ -240,6 +240,52 @@ impl<'d, T: Instance, M: Mode, const FLASH_SIZE: usize> Flash<'d, T, M, FLASH_SI
         };
         Ok(jedec.unwrap())
     }
+
+    /// Blocking copy of `len` bytes from `src` to `dst` within the flash.
+    ///
+    /// The data goes through a RAM buffer one page at a time, so it's fine to copy from
+    /// memory-mapped flash. `dst` and `len` must be multiples of [`ERASE_SIZE`], the destination
+    /// sectors are erased before being programmed. The ranges must not overlap.
+    ///
+    /// This is useful for dual-bank style updates, e.g. backing up the active image into the
+    /// inactive bank before overwriting it.
+    ///
+    /// NOTE: `src` and `dst` are offsets from the flash start, NOT absolute addresses.
+    pub fn blocking_copy(&mut self, src: u32, dst: u32, len: u32) -> Result<(), Error> {
+        check_read(self, src, len as usize)?;
+        check_erase(self, dst, dst + len)?;
+        if src < dst + len && dst < src + len {
+            return Err(Error::OutOfBounds);
+        }
+
+        trace!(
+            "Copying {:?} bytes from 0x{:x} to 0x{:x}",
+            len,
+            FLASH_BASE as u32 + src,
+            FLASH_BASE as u32 + dst
+        );
+
+        let mut writer = FlashWriter::new(self, dst, dst + len)?;
+        let mut buf = [0_u8; PAGE_SIZE];
+        let mut offset = 0;
+        while offset < len {
+            writer.flash.blocking_read(src + offset, &mut buf)?;
+            writer.write(&buf)?;
+            offset += PAGE_SIZE as u32;
+        }
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Start a streaming write to the flash region `from..to`.
+    ///
+    /// See [`FlashWriter`] for details.
+    ///
+    /// NOTE: `from` and `to` are offsets from the flash start, NOT absolute addresses.
+    pub fn writer(&mut self, from: u32, to: u32) -> Result<FlashWriter<'_, 'd, T, M, FLASH_SIZE>, Error> {
+        FlashWriter::new(self, from, to)
+    }
 }
 
 impl<'d, T: Instance, const FLASH_SIZE: usize> Flash<'d, T, Blocking, FLASH_SIZE> {
@@ -418,6 +464,100 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> embedded_storage_async::nor_flash
     }
 }
 
+/// Streaming writer for a flash region.
+///
+/// This is meant for receiving data of unknown length in small pieces, like a firmware
+/// update image written to the inactive bank of a dual-bank layout. Incoming data is collected
+/// in a page-sized RAM buffer, and each sector is erased right before its first page is
+/// programmed, so nothing outside of the written range is touched.
+///
+/// Like all other write operations, programming runs from RAM with interrupts disabled and
+/// CORE1 paused, so it's safe to keep executing from flash while writing. The region must not
+/// contain the running program, though.
+pub struct FlashWriter<'a, 'd, T: Instance, M: Mode, const FLASH_SIZE: usize> {
+    flash: &'a mut Flash<'d, T, M, FLASH_SIZE>,
+    offset: u32,
+    end: u32,
+    buf: [u8; PAGE_SIZE],
+    buf_len: usize,
+    written: usize,
+}
+
+impl<'a, 'd, T: Instance, M: Mode, const FLASH_SIZE: usize> FlashWriter<'a, 'd, T, M, FLASH_SIZE> {
+    fn new(flash: &'a mut Flash<'d, T, M, FLASH_SIZE>, from: u32, to: u32) -> Result<Self, Error> {
+        check_erase(flash, from, to)?;
+        Ok(Self {
+            flash,
+            offset: from,
+            end: to,
+            buf: [0xFF; PAGE_SIZE],
+            buf_len: 0,
+            written: 0,
+        })
+    }
+
+    /// Number of bytes written so far, including bytes still buffered in RAM.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Append data to the region.
+    ///
+    /// Returns [`Error::OutOfBounds`] if the data doesn't fit in the region, in which
+    /// case nothing is written.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        let remaining = (self.end - self.offset) as usize - self.buf_len;
+        if data.len() > remaining {
+            return Err(Error::OutOfBounds);
+        }
+
+        while !data.is_empty() {
+            let n = data.len().min(PAGE_SIZE - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            self.written += n;
+            data = &data[n..];
+
+            if self.buf_len == PAGE_SIZE {
+                self.flush_page()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write out the last partial page, padded with `0xFF`, and return the total number of
+    /// bytes written.
+    pub fn finish(mut self) -> Result<usize, Error> {
+        if self.buf_len > 0 {
+            self.flush_page()?;
+        }
+        Ok(self.written)
+    }
+
+    fn flush_page(&mut self) -> Result<(), Error> {
+        let offset = self.offset;
+        let erase = offset as usize % ERASE_SIZE == 0;
+        let page = &self.buf;
+
+        trace!("Programming page at 0x{:x}", FLASH_BASE as u32 + offset);
+
+        unsafe {
+            in_ram(|| {
+                if erase {
+                    ram_helpers::flash_range_erase(offset, ERASE_SIZE as u32);
+                }
+                ram_helpers::flash_range_program(offset, page);
+            })?
+        };
+
+        self.offset += PAGE_SIZE as u32;
+        self.buf = [0xFF; PAGE_SIZE];
+        self.buf_len = 0;
+        Ok(())
+    }
+}
+
 #[allow(dead_code)]
 mod ram_helpers {
     use super::*;
@@ -68,6 +68,10 @@ pub struct Config {
     pub addr: u16,
     /// Control if the peripheral should ack to and report general calls.
     pub general_call: bool,
+    /// Stretch the clock when the RX FIFO is full, until the application drains it.
+    ///
+    /// If disabled, bytes received while the RX FIFO is full are NACKed and dropped.
+    pub stretch_on_rx_full: bool,
 }
 
 impl Default for Config {
@@ -75,6 +79,7 @@ impl Default for Config {
         Self {
             addr: 0x55,
             general_call: true,
+            stretch_on_rx_full: true,
         }
     }
 }
@@ -115,6 +120,18 @@ impl<'d, T: Instance> I2cSlave<'d, T> {
         ret
     }
 
+    /// Change the configuration, e.g. to respond to a different address.
+    ///
+    /// This resets the peripheral, so it should only be called between transactions.
+    pub fn set_config(&mut self, config: Config) {
+        assert!(!i2c_reserved_addr(config.addr));
+        assert!(config.addr != 0);
+
+        self.config = config;
+        self.pending_byte = None;
+        self.reset();
+    }
+
     /// Reset the i2c peripheral. If you cancel a respond_to_read, you may stall the bus.
     /// You can recover the bus by calling this function, but doing so will almost certainly cause
     /// an i/o error in the master.
@@ -132,7 +149,7 @@ impl<'d, T: Instance> I2cSlave<'d, T> {
             w.set_master_mode(false);
             w.set_ic_slave_disable(false);
             w.set_tx_empty_ctrl(true);
-            w.set_rx_fifo_full_hld_ctrl(true);
+            w.set_rx_fifo_full_hld_ctrl(self.config.stretch_on_rx_full);
 
             // This typically makes no sense for a slave, but it is used to
             // tune spike suppression, according to the datasheet.
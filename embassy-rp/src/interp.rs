@@ -0,0 +1,284 @@
+//! Interpolators
+//!
+//! Each core has two interpolators in SIO, `INTERP0` and `INTERP1`, which can do a shift,
+//! mask and add on two accumulators in a single cycle. They are useful for inner loops like
+//! texture mapping, table lookups or audio resampling.
+//!
+//! The interpolator registers are per core: a driver accesses the interpolator of the core
+//! it runs on. If an interpolator is shared between tasks, or used from an interrupt handler,
+//! use [`Interp::save`] and [`Interp::restore`] around the code using it.
+
+use core::marker::PhantomData;
+
+use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+
+use crate::peripherals;
+
+const SIO_BASE: usize = 0xd000_0000;
+
+// Register offsets within an interpolator block.
+const ACCUM0: usize = 0x00;
+const BASE0: usize = 0x08;
+const POP_LANE0: usize = 0x14;
+const POP_FULL: usize = 0x1c;
+const PEEK_LANE0: usize = 0x20;
+const PEEK_FULL: usize = 0x28;
+const CTRL_LANE0: usize = 0x2c;
+const ACCUM0_ADD: usize = 0x34;
+const BASE_1AND0: usize = 0x3c;
+
+const CTRL_BLEND: u32 = 1 << 21;
+const CTRL_CLAMP: u32 = 1 << 22;
+const CTRL_OVERF: u32 = 1 << 25;
+
+/// Interpolator lane.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Lane {
+    /// Lane 0.
+    Lane0,
+    /// Lane 1.
+    Lane1,
+}
+
+/// Lane configuration.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LaneConfig {
+    /// Right rotation applied to the accumulator, 0 to 31.
+    pub shift: u8,
+    /// Least significant bit of the mask applied after shifting, 0 to 31.
+    pub mask_lsb: u8,
+    /// Most significant bit of the mask applied after shifting, 0 to 31.
+    pub mask_msb: u8,
+    /// Sign-extend the masked result from `mask_msb` before adding it to the base.
+    pub signed: bool,
+    /// Feed the accumulator of the other lane into this lane's shift and mask.
+    pub cross_input: bool,
+    /// Feed the result of the other lane back into this lane's accumulator on pop.
+    pub cross_result: bool,
+    /// Add the raw accumulator to the base, instead of the shifted and masked value.
+    /// The shifted and masked value is still used for the full result.
+    pub add_raw: bool,
+    /// ORed into bits 29:28 of the lane result, e.g. to point at a memory region.
+    pub force_msb: u8,
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        Self {
+            shift: 0,
+            mask_lsb: 0,
+            mask_msb: 31,
+            signed: false,
+            cross_input: false,
+            cross_result: false,
+            add_raw: false,
+            force_msb: 0,
+        }
+    }
+}
+
+impl LaneConfig {
+    fn bits(&self) -> u32 {
+        assert!(self.shift < 32 && self.mask_lsb < 32 && self.mask_msb < 32 && self.force_msb < 4);
+
+        (self.shift as u32)
+            | (self.mask_lsb as u32) << 5
+            | (self.mask_msb as u32) << 10
+            | (self.signed as u32) << 15
+            | (self.cross_input as u32) << 16
+            | (self.cross_result as u32) << 17
+            | (self.add_raw as u32) << 18
+            | (self.force_msb as u32) << 19
+    }
+}
+
+/// Saved interpolator state, see [`Interp::save`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct State {
+    accum: [u32; 2],
+    base: [u32; 3],
+    ctrl: [u32; 2],
+}
+
+/// Interpolator driver.
+///
+/// The driver is not `Send`, so it can't be moved to the other core by accident.
+pub struct Interp<'d, T: Instance> {
+    _inner: PeripheralRef<'d, T>,
+    phantom: PhantomData<*mut ()>,
+}
+
+impl<'d, T: Instance> Interp<'d, T> {
+    /// Create a new interpolator driver, with both lanes in their default configuration.
+    pub fn new(inner: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(inner);
+
+        let mut interp = Self {
+            _inner: inner,
+            phantom: PhantomData,
+        };
+        interp.set_lane_config(Lane::Lane0, &LaneConfig::default());
+        interp.set_lane_config(Lane::Lane1, &LaneConfig::default());
+        interp
+    }
+
+    #[inline(always)]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (SIO_BASE + T::OFFSET + offset) as *mut u32
+    }
+
+    #[inline(always)]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    #[inline(always)]
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { self.reg(offset).write_volatile(value) }
+    }
+
+    /// Configure a lane.
+    ///
+    /// This keeps the blend and clamp modes, which are configured through lane 0.
+    pub fn set_lane_config(&mut self, lane: Lane, config: &LaneConfig) {
+        let offset = CTRL_LANE0 + 4 * lane as usize;
+        let keep = match lane {
+            Lane::Lane0 => self.read(offset) & (CTRL_BLEND | CTRL_CLAMP),
+            Lane::Lane1 => 0,
+        };
+        self.write(offset, config.bits() | keep);
+    }
+
+    /// Set the accumulator of a lane.
+    #[inline]
+    pub fn set_accum(&mut self, lane: Lane, value: u32) {
+        self.write(ACCUM0 + 4 * lane as usize, value)
+    }
+
+    /// Read the accumulator of a lane.
+    #[inline]
+    pub fn accum(&self, lane: Lane) -> u32 {
+        self.read(ACCUM0 + 4 * lane as usize)
+    }
+
+    /// Atomically add `value` to the accumulator of a lane.
+    #[inline]
+    pub fn add_accum(&mut self, lane: Lane, value: u32) {
+        self.write(ACCUM0_ADD + 4 * lane as usize, value)
+    }
+
+    /// Set base register 0, 1 or 2. Base 2 is only used for the full result.
+    #[inline]
+    pub fn set_base(&mut self, index: usize, value: u32) {
+        assert!(index < 3);
+        self.write(BASE0 + 4 * index, value)
+    }
+
+    /// Read base register 0, 1 or 2.
+    #[inline]
+    pub fn base(&self, index: usize) -> u32 {
+        assert!(index < 3);
+        self.read(BASE0 + 4 * index)
+    }
+
+    /// Set the low half of `value` to base 0 and the high half to base 1, in a single write.
+    ///
+    /// Each half is sign-extended if the respective lane is configured as signed.
+    #[inline]
+    pub fn set_base_1and0(&mut self, value: u32) {
+        self.write(BASE_1AND0, value)
+    }
+
+    /// Read the result of a lane, and write the lane results to both accumulators.
+    #[inline]
+    pub fn pop(&mut self, lane: Lane) -> u32 {
+        self.read(POP_LANE0 + 4 * lane as usize)
+    }
+
+    /// Read the result of a lane, without updating the accumulators.
+    #[inline]
+    pub fn peek(&self, lane: Lane) -> u32 {
+        self.read(PEEK_LANE0 + 4 * lane as usize)
+    }
+
+    /// Read the full result, and write the lane results to both accumulators.
+    #[inline]
+    pub fn pop_full(&mut self) -> u32 {
+        self.read(POP_FULL)
+    }
+
+    /// Read the full result, without updating the accumulators.
+    #[inline]
+    pub fn peek_full(&self) -> u32 {
+        self.read(PEEK_FULL)
+    }
+
+    /// Check if any lane has masked off set bits in its last result.
+    #[inline]
+    pub fn overflowed(&self) -> bool {
+        self.read(CTRL_LANE0) & CTRL_OVERF != 0
+    }
+
+    /// Save the accumulators, bases and lane configuration.
+    pub fn save(&self) -> State {
+        State {
+            accum: [self.read(ACCUM0), self.read(ACCUM0 + 4)],
+            base: [self.read(BASE0), self.read(BASE0 + 4), self.read(BASE0 + 8)],
+            ctrl: [self.read(CTRL_LANE0), self.read(CTRL_LANE0 + 4)],
+        }
+    }
+
+    /// Restore a state saved with [`save`](Self::save).
+    pub fn restore(&mut self, state: &State) {
+        self.write(ACCUM0, state.accum[0]);
+        self.write(ACCUM0 + 4, state.accum[1]);
+        self.write(BASE0, state.base[0]);
+        self.write(BASE0 + 4, state.base[1]);
+        self.write(BASE0 + 8, state.base[2]);
+        self.write(CTRL_LANE0, state.ctrl[0]);
+        self.write(CTRL_LANE0 + 4, state.ctrl[1]);
+    }
+}
+
+impl<'d> Interp<'d, peripherals::INTERP0> {
+    /// Enable blend mode.
+    ///
+    /// In blend mode, the full result linearly interpolates between base 0 and base 1, using
+    /// the lowest 8 bits of the lane 1 shift and mask as the fraction.
+    pub fn set_blend(&mut self, enabled: bool) {
+        let ctrl = self.read(CTRL_LANE0) & !CTRL_BLEND;
+        self.write(CTRL_LANE0, ctrl | if enabled { CTRL_BLEND } else { 0 });
+    }
+}
+
+impl<'d> Interp<'d, peripherals::INTERP1> {
+    /// Enable clamp mode.
+    ///
+    /// In clamp mode, the lane 0 result is clamped between base 0 and base 1.
+    pub fn set_clamp(&mut self, enabled: bool) {
+        let ctrl = self.read(CTRL_LANE0) & !CTRL_CLAMP;
+        self.write(CTRL_LANE0, ctrl | if enabled { CTRL_CLAMP } else { 0 });
+    }
+}
+
+trait SealedInstance {
+    const OFFSET: usize;
+}
+
+/// Interpolator instance.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + Sized + 'static {}
+
+impl SealedInstance for peripherals::INTERP0 {
+    const OFFSET: usize = 0x80;
+}
+impl Instance for peripherals::INTERP0 {}
+
+impl SealedInstance for peripherals::INTERP1 {
+    const OFFSET: usize = 0xc0;
+}
+impl Instance for peripherals::INTERP1 {}
@@ -17,12 +17,14 @@ mod intrinsics;
 pub mod adc;
 pub mod bootsel;
 pub mod clocks;
+pub mod divider;
 pub mod dma;
 pub mod flash;
 mod float;
 pub mod gpio;
 pub mod i2c;
 pub mod i2c_slave;
+pub mod interp;
 pub mod multicore;
 pub mod pwm;
 mod reset;
@@ -33,10 +35,12 @@ pub mod spi;
 pub mod time_driver;
 pub mod uart;
 pub mod usb;
+pub mod usb_host;
 pub mod watchdog;
 
 // PIO
 pub mod pio;
+pub mod pio_programs;
 pub(crate) mod relocate;
 
 // Reexports
@@ -203,6 +207,9 @@ embassy_hal_internal::peripherals! {
 
     CORE1,
 
+    INTERP0,
+    INTERP1,
+
     PIO0,
     PIO1,
 
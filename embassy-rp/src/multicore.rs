@@ -8,6 +8,15 @@
 //! Enable the `critical-section-impl` feature in embassy-rp when sharing data across cores using
 //! the `embassy-sync` primitives and `CriticalSectionRawMutex`.
 //!
+//! Single words can also be passed between cores through the SIO FIFO with [`fifo_send`] and
+//! [`fifo_receive`]. The words are buffered in a mailbox protected by a `CriticalSectionRawMutex`,
+//! so a `critical-section` implementation is required. As the mailbox of a core is only accessed
+//! from that core, a single-core implementation that only masks interrupts is enough, the
+//! `critical-section-impl` feature is not required for this.
+//!
+//! With the `executor` feature, [`spawn_core1_executor`] starts an executor on core1 and returns a
+//! `SendSpawner` for it, so that tasks can be spawned on core1 from core0.
+//!
 //! # Usage
 //!
 //! ```no_run
@@ -49,6 +58,12 @@
 use core::mem::ManuallyDrop;
 use core::sync::atomic::{compiler_fence, AtomicBool, Ordering};
 
+#[cfg(feature = "executor")]
+use embassy_executor::SendSpawner;
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+
 use crate::interrupt::InterruptExt;
 use crate::peripherals::CORE1;
 use crate::{gpio, install_stack_guard, interrupt, pac};
@@ -56,6 +71,9 @@ use crate::{gpio, install_stack_guard, interrupt, pac};
 const PAUSE_TOKEN: u32 = 0xDEADBEEF;
 const RESUME_TOKEN: u32 = !0xDEADBEEF;
 static IS_CORE1_INIT: AtomicBool = AtomicBool::new(false);
+// Set by CORE1 while it is paused. CORE1 acknowledges the pause and resume tokens through this
+// flag rather than the FIFO, so the words it sends can't be mistaken for acknowledgements.
+static CORE1_PAUSED: AtomicBool = AtomicBool::new(false);
 
 #[inline(always)]
 fn core1_setup(stack_bottom: *mut usize) {
@@ -84,31 +102,60 @@ impl<const SIZE: usize> Stack<SIZE> {
     }
 }
 
-#[cfg(feature = "rt")]
-#[interrupt]
-#[link_section = ".data.ram_func"]
-unsafe fn SIO_IRQ_PROC1() {
+/// Number of words buffered per core by [`fifo_receive`], on top of the 8-word hardware FIFO.
+const MAILBOX_DEPTH: usize = 8;
+
+// Words received from the other core, filled by the `SIO_IRQ_PROCx` handler of each core.
+static MAILBOX: [Channel<CriticalSectionRawMutex, u32, MAILBOX_DEPTH>; 2] = [Channel::new(), Channel::new()];
+// Set when a word was dropped because the mailbox of the core was full.
+static MAILBOX_OVERFLOW: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+// Move words from the inter-core FIFO to the mailbox of the current core.
+//
+// The FIFO is always drained, so that the pause and resume tokens are handled even when the
+// mailbox is full. Words that don't fit in the mailbox are dropped, see `fifo_overflowed`.
+#[inline(always)]
+fn fifo_irq(core: usize) {
     let sio = pac::SIO;
     // Clear IRQ
     sio.fifo().st().write(|w| w.set_wof(false));
 
     while sio.fifo().st().read().vld() {
+        let value = sio.fifo().rd().read();
         // Pause CORE1 execution and disable interrupts
-        if fifo_read_wfe() == PAUSE_TOKEN {
+        if core == 1 && value == PAUSE_TOKEN {
             cortex_m::interrupt::disable();
             // Signal to CORE0 that execution is paused
-            fifo_write(PAUSE_TOKEN);
+            CORE1_PAUSED.store(true, Ordering::Release);
+            cortex_m::asm::sev();
             // Wait for `resume` signal from CORE0
             while fifo_read_wfe() != RESUME_TOKEN {
                 cortex_m::asm::nop();
             }
             cortex_m::interrupt::enable();
             // Signal to CORE0 that execution is resumed
-            fifo_write(RESUME_TOKEN);
+            CORE1_PAUSED.store(false, Ordering::Release);
+            cortex_m::asm::sev();
+        } else if MAILBOX[core].try_send(value).is_err() {
+            MAILBOX_OVERFLOW[core].store(true, Ordering::Relaxed);
         }
     }
 }
 
+#[cfg(feature = "rt")]
+#[interrupt]
+#[link_section = ".data.ram_func"]
+unsafe fn SIO_IRQ_PROC0() {
+    fifo_irq(0);
+}
+
+#[cfg(feature = "rt")]
+#[interrupt]
+#[link_section = ".data.ram_func"]
+unsafe fn SIO_IRQ_PROC1() {
+    fifo_irq(1);
+}
+
 /// Spawn a function on this core
 pub fn spawn_core1<F, const SIZE: usize>(_core1: CORE1, stack: &'static mut Stack<SIZE>, entry: F)
 where
@@ -195,42 +242,98 @@ where
         core1_startup::<F> as usize,
     ];
 
-    let mut seq = 0;
-    let mut fails = 0;
-    loop {
-        let cmd = cmd_seq[seq] as u32;
-        if cmd == 0 {
-            fifo_drain();
-            cortex_m::asm::sev();
-        }
-        fifo_write(cmd);
-
-        let response = fifo_read();
-        if cmd == response {
-            seq += 1;
-        } else {
-            seq = 0;
-            fails += 1;
-            if fails > 16 {
-                // The second core isn't responding, and isn't going to take the entrypoint
-                panic!("CORE1 not responding");
+    with_core0_fifo_irq_masked(|| {
+        let mut seq = 0;
+        let mut fails = 0;
+        loop {
+            let cmd = cmd_seq[seq] as u32;
+            if cmd == 0 {
+                fifo_drain();
+                cortex_m::asm::sev();
+            }
+            fifo_write(cmd);
+
+            let response = fifo_read();
+            if cmd == response {
+                seq += 1;
+            } else {
+                seq = 0;
+                fails += 1;
+                if fails > 16 {
+                    // The second core isn't responding, and isn't going to take the entrypoint
+                    panic!("CORE1 not responding");
+                }
+            }
+            if seq >= cmd_seq.len() {
+                break;
             }
         }
-        if seq >= cmd_seq.len() {
-            break;
-        }
+
+        // Wait until the other core has copied `entry` before returning.
+        fifo_read();
+    });
+}
+
+/// Spawn an executor on CORE1, and return a spawner for it.
+///
+/// The executor lives on `stack`, and runs forever. `init` is called on CORE1
+/// with the executor's [`Spawner`](embassy_executor::Spawner), like [`Executor::run`](embassy_executor::Executor::run).
+///
+/// The returned [`SendSpawner`] can be used from CORE0 to spawn more tasks on CORE1. Note that
+/// tasks spawned that way still run on CORE1, so they must not use peripherals that are
+/// bound to CORE0 interrupts.
+#[cfg(feature = "executor")]
+pub fn spawn_core1_executor<F, const SIZE: usize>(core1: CORE1, stack: &'static mut Stack<SIZE>, init: F) -> SendSpawner
+where
+    F: FnOnce(embassy_executor::Spawner) + Send + 'static,
+{
+    spawn_core1(core1, stack, move || {
+        let mut executor = embassy_executor::Executor::new();
+        // Safety: this closure never returns, so the executor lives forever.
+        let executor: &'static mut embassy_executor::Executor = unsafe { &mut *(&mut executor as *mut _) };
+        executor.run(|spawner| {
+            unsafe { CORE1_SPAWNER.get().write(spawner.make_send()) };
+            CORE1_SPAWNER_READY.store(true, Ordering::Release);
+            cortex_m::asm::sev();
+            init(spawner)
+        })
+    });
+
+    // Wait until the executor on CORE1 is up.
+    while !CORE1_SPAWNER_READY.load(Ordering::Acquire) {
+        cortex_m::asm::wfe();
     }
+    unsafe { CORE1_SPAWNER.get().read() }
+}
 
-    // Wait until the other core has copied `entry` before returning.
-    fifo_read();
+#[cfg(feature = "executor")]
+struct SpawnerSlot(core::cell::UnsafeCell<core::mem::MaybeUninit<SendSpawner>>);
+
+#[cfg(feature = "executor")]
+impl SpawnerSlot {
+    fn get(&self) -> *mut SendSpawner {
+        self.0.get().cast()
+    }
 }
 
+// Safety: only written once by CORE1 before setting `CORE1_SPAWNER_READY`, and only read by
+// CORE0 after seeing it set.
+#[cfg(feature = "executor")]
+unsafe impl Sync for SpawnerSlot {}
+
+#[cfg(feature = "executor")]
+static CORE1_SPAWNER: SpawnerSlot = SpawnerSlot(core::cell::UnsafeCell::new(core::mem::MaybeUninit::uninit()));
+#[cfg(feature = "executor")]
+static CORE1_SPAWNER_READY: AtomicBool = AtomicBool::new(false);
+
 /// Pause execution on CORE1.
 pub fn pause_core1() {
     if IS_CORE1_INIT.load(Ordering::Acquire) {
         fifo_write(PAUSE_TOKEN);
         // Wait for CORE1 to signal it has paused execution.
-        while fifo_read() != PAUSE_TOKEN {}
+        while !CORE1_PAUSED.load(Ordering::Acquire) {
+            cortex_m::asm::nop();
+        }
     }
 }
 
@@ -239,10 +342,54 @@ pub fn resume_core1() {
     if IS_CORE1_INIT.load(Ordering::Acquire) {
         fifo_write(RESUME_TOKEN);
         // Wait for CORE1 to signal it has resumed execution.
-        while fifo_read() != RESUME_TOKEN {}
+        while CORE1_PAUSED.load(Ordering::Acquire) {
+            cortex_m::asm::nop();
+        }
     }
 }
 
+/// Send a word to the other core through the inter-core FIFO.
+///
+/// Waits until there is space in the hardware FIFO. The word is delivered to
+/// [`fifo_receive`] on the other core.
+///
+/// The values `0xDEADBEEF` and `!0xDEADBEEF` are reserved for pausing CORE1 during
+/// flash operations and must not be sent from CORE0. CORE1 can send any value.
+pub async fn fifo_send(value: u32) {
+    let sio = pac::SIO;
+    while !sio.fifo().st().read().rdy() {
+        yield_now().await;
+    }
+    sio.fifo().wr().write_value(value);
+    // Wake up the other core in case it's waiting for an event.
+    cortex_m::asm::sev();
+}
+
+/// Receive a word sent by the other core with [`fifo_send`].
+///
+/// On CORE0 this enables the `SIO_IRQ_PROC0` interrupt on first use. Words are
+/// buffered until received, so they can be sent before the receiver is waiting.
+/// Words sent while the buffer is full are dropped, see [`fifo_overflowed`].
+pub async fn fifo_receive() -> u32 {
+    let core = pac::SIO.cpuid().read() as usize;
+    if core == 0 {
+        unsafe { interrupt::SIO_IRQ_PROC0.enable() };
+    }
+    MAILBOX[core].receive().await
+}
+
+/// Returns whether words sent to the current core were dropped because they were not received
+/// with [`fifo_receive`] in time, and clears the flag.
+pub fn fifo_overflowed() -> bool {
+    let core = pac::SIO.cpuid().read() as usize;
+    // No atomic swap on the Cortex-M0+, the flag is only set from this core's interrupt handler.
+    critical_section::with(|_| {
+        let overflowed = MAILBOX_OVERFLOW[core].load(Ordering::Relaxed);
+        MAILBOX_OVERFLOW[core].store(false, Ordering::Relaxed);
+        overflowed
+    })
+}
+
 // Push a value to the inter-core FIFO, block until space is available
 #[inline(always)]
 fn fifo_write(value: u32) {
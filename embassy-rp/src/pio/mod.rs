@@ -1,4 +1,13 @@
 //! PIO driver.
+//!
+//! Programs are assembled at compile time with `pio_proc::pio_asm!`, loaded with
+//! [`Common::load_program`] and applied to a state machine with [`Config::use_program`].
+//!
+//! Not provided yet:
+//! - async waiting on IRQ flags 4 to 7: they can't raise a system interrupt, poll them with
+//!   [`IrqFlags`] instead. Flags 0 to 3 are awaited with [`Irq::wait`].
+//! - DMA channel chaining helpers, e.g. for ping-pong buffers. Each `dma_*` transfer uses a
+//!   single channel.
 use core::future::Future;
 use core::marker::PhantomData;
 use core::pin::Pin as FuturePin;
@@ -336,6 +345,22 @@ impl<'d, PIO: Instance, const SM: usize> StateMachineRx<'d, PIO, SM> {
         Some(self.pull())
     }
 
+    /// Pull a byte, halfword or word from RX FIFO.
+    ///
+    /// Narrow reads return the least significant bits of the FIFO entry, so the program should
+    /// shift data in to the left, or push it with a threshold of the word size.
+    pub fn pull_word<W: Word>(&mut self) -> W {
+        unsafe { core::ptr::read_volatile(PIO::PIO.rxf(SM).as_ptr() as *const W) }
+    }
+
+    /// Attempt pulling a byte, halfword or word from RX FIFO.
+    pub fn try_pull_word<W: Word>(&mut self) -> Option<W> {
+        if self.empty() {
+            return None;
+        }
+        Some(self.pull_word())
+    }
+
     /// Wait for RX FIFO readable.
     pub fn wait_pull<'a>(&'a mut self) -> FifoInFuture<'a, 'd, PIO, SM> {
         FifoInFuture::new(self)
@@ -365,6 +390,34 @@ impl<'d, PIO: Instance, const SM: usize> StateMachineRx<'d, PIO, SM> {
         compiler_fence(Ordering::SeqCst);
         Transfer::new(ch)
     }
+
+    /// Prepare a repeated DMA transfer from RX FIFO, discarding `len` words.
+    ///
+    /// Useful to drain data the program produces as a side effect, e.g. the RX half of a
+    /// write-only SPI transfer.
+    pub fn dma_pull_repeated<'a, C: Channel, W: Word>(
+        &'a mut self,
+        ch: PeripheralRef<'a, C>,
+        len: usize,
+    ) -> Transfer<'a, C> {
+        let pio_no = PIO::PIO_NO;
+        let p = ch.regs();
+        p.write_addr().write_value(crate::dma::dummy_ptr() as u32);
+        p.read_addr().write_value(PIO::PIO.rxf(SM).as_ptr() as u32);
+        p.trans_count().write_value(len as u32);
+        compiler_fence(Ordering::SeqCst);
+        p.ctrl_trig().write(|w| {
+            // Set RX DREQ for this statemachine
+            w.set_treq_sel(TreqSel(pio_no * 8 + SM as u8 + 4));
+            w.set_data_size(W::size());
+            w.set_chain_to(ch.number());
+            w.set_incr_read(false);
+            w.set_incr_write(false);
+            w.set_en(true);
+        });
+        compiler_fence(Ordering::SeqCst);
+        Transfer::new(ch)
+    }
 }
 
 /// Type representing a state machine TX FIFO.
@@ -422,6 +475,23 @@ impl<'d, PIO: Instance, const SM: usize> StateMachineTx<'d, PIO, SM> {
         true
     }
 
+    /// Push a byte, halfword or word to TX FIFO.
+    ///
+    /// Narrow writes are replicated to all byte lanes of the FIFO entry, so the program can shift
+    /// data out in either direction, with a threshold of the word size.
+    pub fn push_word<W: Word>(&mut self, v: W) {
+        unsafe { core::ptr::write_volatile(PIO::PIO.txf(SM).as_ptr() as *mut W, v) }
+    }
+
+    /// Attempt to push a byte, halfword or word to TX FIFO.
+    pub fn try_push_word<W: Word>(&mut self, v: W) -> bool {
+        if self.full() {
+            return false;
+        }
+        self.push_word(v);
+        true
+    }
+
     /// Wait until FIFO is ready for writing.
     pub fn wait_push<'a>(&'a mut self, value: u32) -> FifoOutFuture<'a, 'd, PIO, SM> {
         FifoOutFuture::new(self, value)
@@ -447,6 +517,34 @@ impl<'d, PIO: Instance, const SM: usize> StateMachineTx<'d, PIO, SM> {
         compiler_fence(Ordering::SeqCst);
         Transfer::new(ch)
     }
+
+    /// Prepare a repeated DMA transfer to TX FIFO, pushing `len` dummy words.
+    ///
+    /// Useful to clock programs that only need a word count from the FIFO, e.g. the TX half of
+    /// a read-only SPI transfer.
+    pub fn dma_push_repeated<'a, C: Channel, W: Word>(
+        &'a mut self,
+        ch: PeripheralRef<'a, C>,
+        len: usize,
+    ) -> Transfer<'a, C> {
+        let pio_no = PIO::PIO_NO;
+        let p = ch.regs();
+        p.read_addr().write_value(crate::dma::dummy_ptr() as u32);
+        p.write_addr().write_value(PIO::PIO.txf(SM).as_ptr() as u32);
+        p.trans_count().write_value(len as u32);
+        compiler_fence(Ordering::SeqCst);
+        p.ctrl_trig().write(|w| {
+            // Set TX DREQ for this statemachine
+            w.set_treq_sel(TreqSel(pio_no * 8 + SM as u8));
+            w.set_data_size(W::size());
+            w.set_chain_to(ch.number());
+            w.set_incr_read(false);
+            w.set_incr_write(false);
+            w.set_en(true);
+        });
+        compiler_fence(Ordering::SeqCst);
+        Transfer::new(ch)
+    }
 }
 
 /// A type representing a single PIO state machine.
@@ -499,6 +597,18 @@ pub struct ShiftConfig {
     pub auto_fill: bool,
 }
 
+impl ShiftConfig {
+    /// Shift in `direction`, pulling or pushing automatically every `threshold` bits.
+    pub fn auto(threshold: u8, direction: ShiftDirection) -> Self {
+        assert!((1..=32).contains(&threshold), "threshold must be between 1 and 32");
+        Self {
+            threshold,
+            direction,
+            auto_fill: true,
+        }
+    }
+}
+
 /// PIO pin config.
 #[derive(Clone, Copy, Default, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -0,0 +1,8 @@
+//! Pre-built PIO programs for common interfaces.
+//!
+//! Each driver comes with a program type, loaded once into a PIO instance with
+//! [`Common`](crate::pio::Common), so several state machines can share it.
+
+pub mod rotary_encoder;
+pub mod uart;
+pub mod ws2812;
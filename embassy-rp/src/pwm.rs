@@ -1,11 +1,13 @@
 //! Pulse Width Modulation (PWM)
 
 use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+use embassy_time::{Duration, Instant, Timer};
 use fixed::traits::ToFixed;
 use fixed::FixedU16;
 use pac::pwm::regs::{ChDiv, Intr};
 use pac::pwm::vals::Divmode;
 
+use crate::clocks::clk_sys_freq;
 use crate::gpio::{AnyPin, Pin as GpioPin, Pull, SealedPin as _};
 use crate::{pac, peripherals, RegExt};
 
@@ -80,6 +82,28 @@ impl From<InputMode> for Divmode {
     }
 }
 
+/// PWM input measurement error.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MeasureError {
+    /// The driver was created without a 'b' pin.
+    NoInputPin,
+    /// The counter wrapped during the gate interval, use a shorter gate.
+    Overflow,
+    /// The gate interval is too long to count the high time, even with the largest divider.
+    GateTooLong,
+}
+
+/// PWM input measurement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Measurement {
+    /// Frequency of the input signal in Hz, from the number of rising edges in the gate interval.
+    pub frequency: u32,
+    /// Fraction of the gate interval the input signal was high, from 0.0 to 1.0.
+    pub duty_cycle: f32,
+}
+
 /// PWM driver.
 pub struct Pwm<'d, T: Slice> {
     inner: PeripheralRef<'d, T>,
@@ -279,6 +303,92 @@ impl<'d, T: Slice> Pwm<'d, T> {
         pac::PWM.intr().write_value(Intr(self.bit() as _));
     }
 
+    /// Measure the frequency of the signal on the 'b' pin, in Hz.
+    ///
+    /// This counts the rising edges over the `gate` interval, so the resolution is `1 / gate`.
+    /// The slice is temporarily switched to rising edge mode, and its configuration is restored
+    /// afterwards.
+    pub async fn measure_frequency(&mut self, gate: Duration) -> Result<u32, MeasureError> {
+        let (edges, elapsed) = self.count_input(gate, Divmode::RISE, 1).await?;
+        Ok((edges as u64 * 1_000_000 / elapsed.as_micros().max(1)) as u32)
+    }
+
+    /// Measure the duty cycle of the signal on the 'b' pin, from 0.0 to 1.0.
+    ///
+    /// This counts `clk_sys` cycles while the input is high over the `gate` interval, using the
+    /// smallest clock divider that doesn't overflow the counter. At 125 MHz, the gate can be up
+    /// to about 130 ms long. The slice is temporarily switched to level mode, and its
+    /// configuration is restored afterwards.
+    pub async fn measure_duty_cycle(&mut self, gate: Duration) -> Result<f32, MeasureError> {
+        let clk = clk_sys_freq() as u64;
+        let cycles = clk * gate.as_micros() / 1_000_000;
+        let divider = cycles.div_ceil(0xffff).max(1);
+        if divider > 0xff {
+            return Err(MeasureError::GateTooLong);
+        }
+
+        let (high, elapsed) = self.count_input(gate, Divmode::LEVEL, divider as u8).await?;
+        let total = clk * elapsed.as_micros() / 1_000_000;
+        Ok(((high as u64 * divider) as f32 / total.max(1) as f32).min(1.0))
+    }
+
+    /// Measure frequency and duty cycle of the signal on the 'b' pin.
+    ///
+    /// The frequency and the duty cycle are measured one after the other, each over a
+    /// `gate` interval. See [`measure_frequency`](Self::measure_frequency) and
+    /// [`measure_duty_cycle`](Self::measure_duty_cycle).
+    pub async fn measure(&mut self, gate: Duration) -> Result<Measurement, MeasureError> {
+        let frequency = self.measure_frequency(gate).await?;
+        let duty_cycle = self.measure_duty_cycle(gate).await?;
+        Ok(Measurement { frequency, duty_cycle })
+    }
+
+    async fn count_input(
+        &mut self,
+        gate: Duration,
+        divmode: Divmode,
+        divider: u8,
+    ) -> Result<(u16, Duration), MeasureError> {
+        if self.pin_b.is_none() {
+            return Err(MeasureError::NoInputPin);
+        }
+
+        let p = self.inner.regs();
+        let csr = p.csr().read();
+        let div = p.div().read();
+        let top = p.top().read();
+
+        p.csr().modify(|w| {
+            w.set_en(false);
+            w.set_divmode(divmode);
+            w.set_ph_correct(false);
+        });
+        p.div().write_value(ChDiv((divider as u32) << 4));
+        p.top().write(|w| w.set_top(0xffff));
+        p.ctr().write(|w| w.0 = 0);
+        self.clear_wrapped();
+
+        let start = Instant::now();
+        p.csr().modify(|w| w.set_en(true));
+        Timer::after(gate).await;
+        p.csr().modify(|w| w.set_en(false));
+        let elapsed = start.elapsed();
+
+        let count = p.ctr().read().ctr();
+        let wrapped = self.wrapped();
+        self.clear_wrapped();
+
+        p.div().write_value(div);
+        p.top().write_value(top);
+        p.ctr().write(|w| w.0 = 0);
+        p.csr().write_value(csr);
+
+        if wrapped {
+            return Err(MeasureError::Overflow);
+        }
+        Ok((count, elapsed))
+    }
+
     #[inline]
     fn bit(&self) -> u32 {
         1 << self.inner.number() as usize
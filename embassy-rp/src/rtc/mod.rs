@@ -1,7 +1,12 @@
 //! RTC driver.
 mod filter;
 
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
 use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
 
 pub use self::filter::DateTimeFilter;
 
@@ -11,6 +16,10 @@ mod datetime;
 
 pub use self::datetime::{DateTime, DayOfWeek, Error as DateTimeError};
 use crate::clocks::clk_rtc_freq;
+use crate::interrupt::typelevel::{Binding, Interrupt};
+use crate::{interrupt, pac};
+
+static ALARM_WAKER: AtomicWaker = AtomicWaker::new();
 
 /// A reference to the real time clock of the system
 pub struct Rtc<'d, T: Instance> {
@@ -32,6 +41,21 @@ impl<'d, T: Instance> Rtc<'d, T> {
         Self { inner }
     }
 
+    /// Create a new instance of the real time clock, with support for [`wait_for_alarm`].
+    ///
+    /// [`wait_for_alarm`]: #method.wait_for_alarm
+    pub fn new_async(
+        inner: impl Peripheral<P = T> + 'd,
+        _irq: impl Binding<T::Interrupt, InterruptHandler<T>>,
+    ) -> Self {
+        let rtc = Self::new(inner);
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        rtc
+    }
+
     /// Enable or disable the leap year check. The rp2040 chip will always add a Feb 29th on every year that is divisable by 4, but this may be incorrect (e.g. on century years). This function allows you to disable this check.
     ///
     /// Leap year checking is enabled by default.
@@ -169,6 +193,37 @@ impl<'d, T: Instance> Rtc<'d, T> {
         }
     }
 
+    /// Wait for the alarm scheduled with [`schedule_alarm`] to fire.
+    ///
+    /// The alarm is disabled when this returns, so it has to be scheduled again to wait for
+    /// the next match. This requires the driver to be created with [`new_async`].
+    ///
+    /// The alarm also wakes the chip from `WFI`/`WFE` sleep, and from [`dormant_sleep`] if
+    /// `clk_rtc` is clocked from a GPIN pin.
+    ///
+    /// [`schedule_alarm`]: #method.schedule_alarm
+    /// [`new_async`]: #method.new_async
+    /// [`dormant_sleep`]: crate::clocks::dormant_sleep
+    pub async fn wait_for_alarm(&mut self) {
+        assert!(
+            T::Interrupt::is_enabled(),
+            "RTC interrupt not enabled, create the driver with `Rtc::new_async`"
+        );
+
+        poll_fn(|cx| {
+            ALARM_WAKER.register(cx.waker());
+
+            if self.inner.regs().intr().read().rtc() {
+                self.disable_alarm();
+                return Poll::Ready(());
+            }
+
+            self.inner.regs().inte().modify(|w| w.set_rtc(true));
+            Poll::Pending
+        })
+        .await
+    }
+
     /// Clear the interrupt. This should be called every time the `RTC_IRQ` interrupt is triggered,
     /// or the next [`schedule_alarm`] will never fire.
     ///
@@ -178,6 +233,20 @@ impl<'d, T: Instance> Rtc<'d, T> {
     }
 }
 
+/// RTC alarm interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _rtc: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    // Mask the alarm interrupt and wake the task waiting for it. The match stays active until
+    // the waiting task disables the alarm.
+    unsafe fn on_interrupt() {
+        pac::RTC.inte().modify(|w| w.set_rtc(false));
+        ALARM_WAKER.wake();
+    }
+}
+
 /// Errors that can occur on methods on [Rtc]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum RtcError {
@@ -194,11 +263,16 @@ trait SealedInstance {
 
 /// RTC peripheral instance.
 #[allow(private_bounds)]
-pub trait Instance: SealedInstance {}
+pub trait Instance: SealedInstance {
+    /// Interrupt for this peripheral.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
 
 impl SealedInstance for crate::peripherals::RTC {
     fn regs(&self) -> crate::pac::rtc::Rtc {
         crate::pac::RTC
     }
 }
-impl Instance for crate::peripherals::RTC {}
+impl Instance for crate::peripherals::RTC {
+    type Interrupt = crate::interrupt::typelevel::RTC_IRQ;
+}
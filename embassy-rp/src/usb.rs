@@ -14,7 +14,7 @@ use embassy_usb_driver::{
 use crate::interrupt::typelevel::{Binding, Interrupt};
 use crate::{interrupt, pac, peripherals, Peripheral, RegExt};
 
-trait SealedInstance {
+pub(crate) trait SealedInstance {
     fn regs() -> crate::pac::usb::Usb;
     fn dpram() -> crate::pac::usb_dpram::UsbDpram;
 }
@@ -672,16 +672,21 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
         });
 
         trace!("control: data_out len={} first={} last={}", buf.len(), first, last);
+        T::regs().inte().write_set(|w| w.set_setup_req(true));
         let val = poll_fn(|cx| {
             EP_OUT_WAKERS[0].register(cx.waker());
+            if T::regs().sie_status().read().setup_rec() {
+                trace!("aborted control data_out: received another SETUP");
+                return Poll::Ready(Err(EndpointError::Disabled));
+            }
             let val = T::dpram().ep_out_buffer_control(0).read();
             if val.available(0) {
                 Poll::Pending
             } else {
-                Poll::Ready(val)
+                Poll::Ready(Ok(val))
             }
         })
-        .await;
+        .await?;
 
         let rx_len = val.length(0) as _;
         trace!("control data_out DONE, rx_len = {}", rx_len);
@@ -717,16 +722,22 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             w.set_available(0, true);
         });
 
+        T::regs().inte().write_set(|w| w.set_setup_req(true));
         poll_fn(|cx| {
             EP_IN_WAKERS[0].register(cx.waker());
+            EP_OUT_WAKERS[0].register(cx.waker());
+            if T::regs().sie_status().read().setup_rec() {
+                trace!("aborted control data_in: received another SETUP");
+                return Poll::Ready(Err(EndpointError::Disabled));
+            }
             let bufcontrol = T::dpram().ep_in_buffer_control(0);
             if bufcontrol.read().available(0) {
                 Poll::Pending
             } else {
-                Poll::Ready(())
+                Poll::Ready(Ok(()))
             }
         })
-        .await;
+        .await?;
         trace!("control: data_in DONE");
 
         if last {
@@ -766,9 +777,15 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
 
         // wait for completion before returning, needed so
         // set_address() doesn't happen early.
+        // The host may also give up on the request and send another SETUP.
+        T::regs().inte().write_set(|w| w.set_setup_req(true));
         poll_fn(|cx| {
             EP_IN_WAKERS[0].register(cx.waker());
-            if bufcontrol.read().available(0) {
+            EP_OUT_WAKERS[0].register(cx.waker());
+            if T::regs().sie_status().read().setup_rec() {
+                trace!("aborted control accept: received another SETUP");
+                Poll::Ready(())
+            } else if bufcontrol.read().available(0) {
                 Poll::Pending
             } else {
                 Poll::Ready(())
@@ -0,0 +1,582 @@
+//! USB host driver.
+//!
+//! Control and bulk transfers use the single "EPX" endpoint of the controller, one packet at a
+//! time. NAKed packets are retried by the hardware until the device answers. Interrupt transfers
+//! use an interrupt endpoint of the controller instead, which polls the device at the interval of
+//! the endpoint. Hubs are not supported, devices must be attached directly to the port.
+//!
+//! Enumeration is done by [`embassy_usb::host`](https://docs.embassy.dev/embassy-usb), on top of
+//! this driver.
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::slice;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::Timer;
+use embassy_usb_driver::host::{Channel, DeviceEvent, HostDriver, HostError, Speed};
+use embassy_usb_driver::{Direction, EndpointType};
+
+use crate::interrupt::typelevel::{Binding, Interrupt};
+use crate::pac::usb::regs::{SieCtrl, SieStatus};
+use crate::pac::usb_dpram::regs::{EpBufferControl, EpControl};
+use crate::pac::usb_dpram::vals::EpControlEndpointType;
+use crate::usb::Instance;
+use crate::{interrupt, pac, Peripheral, RegExt};
+
+const EP_MEMORY: *mut u8 = pac::USBCTRL_DPRAM.as_ptr() as *mut u8;
+/// Offset of the EPX endpoint control register in DPRAM.
+const EPX_CONTROL: usize = 0x100;
+/// Offset of the EPX data buffer in DPRAM.
+const EPX_BUFFER: usize = 0x180;
+/// Size of the EPX data buffer, the largest full speed packet.
+const EPX_BUFFER_LEN: usize = 64;
+/// Offset of the data buffer of interrupt endpoint 1 in DPRAM, right after the EPX buffer.
+const INT_EP_BUFFER: usize = EPX_BUFFER + EPX_BUFFER_LEN;
+/// BUFF_STATUS bits of interrupt endpoint 1.
+const INT_EP_BUFF_STATUS: u32 = 0b11 << 2;
+
+static BUS_WAKER: AtomicWaker = AtomicWaker::new();
+static TRANSFER_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// USB host interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let regs = T::regs();
+        let ints = regs.ints().read();
+
+        if ints.host_conn_dis() {
+            regs.inte().write_clear(|w| w.set_host_conn_dis(true));
+            BUS_WAKER.wake();
+        }
+        if ints.trans_complete()
+            || ints.stall()
+            || ints.error_rx_timeout()
+            || ints.error_data_seq()
+            || ints.error_crc()
+            || ints.error_bit_stuff()
+            || ints.error_rx_overflow()
+        {
+            regs.inte().write_clear(|w| {
+                w.set_trans_complete(true);
+                w.set_stall(true);
+                w.set_error_rx_timeout(true);
+                w.set_error_data_seq(true);
+                w.set_error_crc(true);
+                w.set_error_bit_stuff(true);
+                w.set_error_rx_overflow(true);
+            });
+            TRANSFER_WAKER.wake();
+        }
+        if ints.buff_status() {
+            regs.inte().write_clear(|w| w.set_buff_status(true));
+            TRANSFER_WAKER.wake();
+        }
+    }
+}
+
+/// Kind of a single packet transaction.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Transaction {
+    Setup,
+    In,
+    Out,
+}
+
+/// RP2040 USB host driver.
+///
+/// Transfers fail with [`HostError::Disconnected`] until [`HostDriver::wait_for_device_event`]
+/// reported a connected device.
+pub struct UsbHost<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    connected: Option<Speed>,
+}
+
+impl<'d, T: Instance> UsbHost<'d, T> {
+    /// Create a new USB host driver.
+    ///
+    /// The board must supply VBUS to the port, the controller only drives the data lines.
+    pub fn new(_usb: impl Peripheral<P = T> + 'd, _irq: impl Binding<T::Interrupt, InterruptHandler<T>>) -> Self {
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        let regs = T::regs();
+        unsafe {
+            // zero fill regs
+            let p = regs.as_ptr() as *mut u32;
+            for i in 0..0x9c / 4 {
+                p.add(i).write_volatile(0)
+            }
+
+            // zero fill epmem
+            let p = EP_MEMORY as *mut u32;
+            for i in 0..0x180 / 4 {
+                p.add(i).write_volatile(0)
+            }
+        }
+
+        regs.usb_muxing().write(|w| {
+            w.set_to_phy(true);
+            w.set_softcon(true);
+        });
+        regs.usb_pwr().write(|w| {
+            w.set_vbus_detect(true);
+            w.set_vbus_detect_override_en(true);
+        });
+        regs.main_ctrl().write(|w| {
+            w.set_controller_en(true);
+            w.set_host_ndevice(true);
+        });
+        regs.sie_ctrl().write_value(Self::sie_ctrl_base());
+        regs.inte().write_set(|w| w.set_host_conn_dis(true));
+
+        Self {
+            phantom: PhantomData,
+            connected: None,
+        }
+    }
+
+    /// SIE_CTRL bits kept set between transactions.
+    fn sie_ctrl_base() -> SieCtrl {
+        let mut w = SieCtrl(0);
+        w.set_sof_en(true);
+        w.set_keep_alive_en(true);
+        w.set_pulldown_en(true);
+        w.set_ep0_int_1buf(true);
+        w
+    }
+
+    fn speed() -> Option<Speed> {
+        match T::regs().sie_status().read().speed() {
+            0b01 => Some(Speed::Low),
+            0b10 => Some(Speed::Full),
+            _ => None,
+        }
+    }
+
+    fn epx_control() -> pac::common::Reg<EpControl, pac::common::RW> {
+        unsafe { pac::common::Reg::from_ptr(EP_MEMORY.add(EPX_CONTROL) as _) }
+    }
+
+    fn epx_buffer_control() -> pac::common::Reg<EpBufferControl, pac::common::RW> {
+        T::dpram().ep_in_buffer_control(0)
+    }
+
+    /// In host mode, interrupt endpoint `n` uses the control registers of IN endpoint `n` in device
+    /// mode.
+    fn int_ep_buffer_control() -> pac::common::Reg<EpBufferControl, pac::common::RW> {
+        T::dpram().ep_in_buffer_control(1)
+    }
+
+    /// Error reported by the SIE for the current transaction, if any.
+    fn status_error(status: SieStatus) -> Option<HostError> {
+        if status.stall_rec() {
+            Some(HostError::Stall)
+        } else if status.rx_timeout() {
+            Some(HostError::Timeout)
+        } else if status.data_seq_error() {
+            Some(HostError::DataSequence)
+        } else if status.crc_error() || status.bit_stuff_error() || status.rx_overflow() {
+            Some(HostError::Protocol)
+        } else {
+            None
+        }
+    }
+
+    fn clear_status() {
+        T::regs().sie_status().write(|w| {
+            w.set_trans_complete(true);
+            w.set_stall_rec(true);
+            w.set_rx_timeout(true);
+            w.set_data_seq_error(true);
+            w.set_crc_error(true);
+            w.set_bit_stuff_error(true);
+            w.set_rx_overflow(true);
+        });
+    }
+
+    fn enable_error_interrupts(w: &mut pac::usb::regs::Int) {
+        w.set_stall(true);
+        w.set_error_rx_timeout(true);
+        w.set_error_data_seq(true);
+        w.set_error_crc(true);
+        w.set_error_bit_stuff(true);
+        w.set_error_rx_overflow(true);
+    }
+
+    /// Write the buffer control register of a transaction. For IN transactions, the buffer
+    /// accepts up to `len` bytes.
+    fn start_buffer(bufcontrol: pac::common::Reg<EpBufferControl, pac::common::RW>, len: usize, pid: bool, full: bool) {
+        // The AVAILABLE bit must be set separately, after the rest of the register has been
+        // written, as the controller may see it before the other fields otherwise.
+        bufcontrol.write(|w| {
+            w.set_length(0, len as u16);
+            w.set_pid(0, pid);
+            w.set_full(0, full);
+            w.set_last(0, true);
+        });
+        cortex_m::asm::delay(12);
+        bufcontrol.write(|w| {
+            w.set_length(0, len as u16);
+            w.set_pid(0, pid);
+            w.set_full(0, full);
+            w.set_last(0, true);
+            w.set_available(0, true);
+        });
+    }
+
+    /// Copy a received packet of `rx_len` bytes from DPRAM at `offset` to `buf`.
+    ///
+    /// The packet is received even if it doesn't fit, so this returns
+    /// [`HostError::BufferOverflow`] after the device moved on to its next data toggle.
+    fn read_packet(offset: usize, rx_len: usize, buf: &mut [u8]) -> Result<usize, HostError> {
+        if rx_len > buf.len() {
+            return Err(HostError::BufferOverflow);
+        }
+        compiler_fence(Ordering::SeqCst);
+        let mem = unsafe { slice::from_raw_parts(EP_MEMORY.add(offset), rx_len) };
+        buf[..rx_len].copy_from_slice(mem);
+        compiler_fence(Ordering::SeqCst);
+        Ok(rx_len)
+    }
+
+    /// Send or receive a single packet, on an interrupt endpoint for interrupt channels, and on
+    /// EPX otherwise.
+    async fn packet(
+        &mut self,
+        channel: &Channel,
+        kind: Transaction,
+        pid: bool,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        match channel.endpoint.ep_type {
+            EndpointType::Interrupt => self.interrupt_transaction(channel, pid, buf).await,
+            _ => self.transaction(channel, kind, pid, buf).await,
+        }
+    }
+
+    /// Perform a single packet transaction on interrupt endpoint 1 of the controller, which polls
+    /// the device every `interval_ms` frames until it answers.
+    ///
+    /// Transfers are done one at a time, so a single interrupt endpoint is enough. For IN
+    /// transactions, returns the number of bytes received into `buf`.
+    async fn interrupt_transaction(
+        &mut self,
+        channel: &Channel,
+        pid: bool,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        if self.connected.is_none() || Self::speed().is_none() {
+            return Err(HostError::Disconnected);
+        }
+
+        let regs = T::regs();
+        let direction = channel.endpoint.addr.direction();
+        let max_packet_size = channel.endpoint.max_packet_size as usize;
+        assert!(max_packet_size <= EPX_BUFFER_LEN);
+
+        regs.addr_endp_x(0).write(|w| {
+            w.set_address(channel.device_address);
+            w.set_endpoint(channel.endpoint.addr.index() as u8);
+            w.set_intep_dir(direction == Direction::Out);
+        });
+        T::dpram().ep_in_control(0).write(|w| {
+            w.set_enable(true);
+            w.set_interrupt_per_buff(true);
+            w.set_endpoint_type(EpControlEndpointType::INTERRUPT);
+            w.set_buffer_address(INT_EP_BUFFER as u16);
+            // The device is polled every `host_poll_interval + 1` frames.
+            w.set_host_poll_interval(channel.endpoint.interval_ms.max(1) as u16 - 1);
+        });
+
+        let len = match direction {
+            Direction::Out => {
+                let len = buf.len().min(max_packet_size);
+                compiler_fence(Ordering::SeqCst);
+                let mem = unsafe { slice::from_raw_parts_mut(EP_MEMORY.add(INT_EP_BUFFER), len) };
+                mem.copy_from_slice(&buf[..len]);
+                compiler_fence(Ordering::SeqCst);
+                len
+            }
+            Direction::In => max_packet_size,
+        };
+        Self::start_buffer(Self::int_ep_buffer_control(), len, pid, direction == Direction::Out);
+
+        Self::clear_status();
+        regs.buff_status().write(|w| w.0 = 0xFFFF_FFFF);
+        regs.int_ep_ctrl().write(|w| w.set_int_ep_active(1 << 1));
+
+        // Stop polling if the transfer is dropped.
+        let _stop = OnDrop::new(|| {
+            T::regs().int_ep_ctrl().write(|w| w.set_int_ep_active(0));
+            T::dpram().ep_in_control(0).write(|w| w.set_enable(false));
+        });
+
+        poll_fn(|cx| {
+            TRANSFER_WAKER.register(cx.waker());
+
+            let status = regs.sie_status().read();
+            if regs.buff_status().read().0 & INT_EP_BUFF_STATUS != 0 {
+                Poll::Ready(Ok(()))
+            } else if let Some(e) = Self::status_error(status) {
+                Poll::Ready(Err(e))
+            } else if status.speed() == 0 {
+                Poll::Ready(Err(HostError::Disconnected))
+            } else {
+                regs.inte().write_set(|w| {
+                    w.set_buff_status(true);
+                    Self::enable_error_interrupts(w);
+                });
+                Poll::Pending
+            }
+        })
+        .await?;
+
+        match direction {
+            Direction::Out => Ok(len),
+            Direction::In => {
+                let rx_len = Self::int_ep_buffer_control().read().length(0) as usize;
+                Self::read_packet(INT_EP_BUFFER, rx_len, buf)
+            }
+        }
+    }
+
+    /// Perform a single packet transaction on EPX.
+    ///
+    /// For IN transactions, returns the number of bytes received into `buf`.
+    async fn transaction(
+        &mut self,
+        channel: &Channel,
+        kind: Transaction,
+        pid: bool,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        if self.connected.is_none() || Self::speed().is_none() {
+            return Err(HostError::Disconnected);
+        }
+
+        let regs = T::regs();
+        let len = buf.len().min(channel.endpoint.max_packet_size as usize);
+        assert!(len <= EPX_BUFFER_LEN);
+
+        regs.addr_endp().write(|w| {
+            w.set_address(channel.device_address);
+            w.set_endpoint(channel.endpoint.addr.index() as u8);
+        });
+
+        let ep_type = match channel.endpoint.ep_type {
+            EndpointType::Control => EpControlEndpointType::CONTROL,
+            EndpointType::Bulk => EpControlEndpointType::BULK,
+            EndpointType::Interrupt => EpControlEndpointType::INTERRUPT,
+            EndpointType::Isochronous => EpControlEndpointType::ISOCHRONOUS,
+        };
+        Self::epx_control().write(|w| {
+            w.set_enable(true);
+            w.set_interrupt_per_buff(true);
+            w.set_endpoint_type(ep_type);
+            w.set_buffer_address(EPX_BUFFER as u16);
+        });
+
+        match kind {
+            Transaction::Setup => {
+                assert!(buf.len() == 8);
+                compiler_fence(Ordering::SeqCst);
+                let mem = unsafe { slice::from_raw_parts_mut(EP_MEMORY, 8) };
+                mem.copy_from_slice(buf);
+                compiler_fence(Ordering::SeqCst);
+            }
+            Transaction::In | Transaction::Out => {
+                if kind == Transaction::Out {
+                    compiler_fence(Ordering::SeqCst);
+                    let mem = unsafe { slice::from_raw_parts_mut(EP_MEMORY.add(EPX_BUFFER), len) };
+                    mem.copy_from_slice(&buf[..len]);
+                    compiler_fence(Ordering::SeqCst);
+                }
+
+                let length = match kind {
+                    Transaction::Out => len,
+                    _ => channel.endpoint.max_packet_size as usize,
+                };
+                Self::start_buffer(Self::epx_buffer_control(), length, pid, kind == Transaction::Out);
+            }
+        }
+
+        // Clear the status of the previous transaction.
+        Self::clear_status();
+
+        // Like the AVAILABLE bit, START_TRANS must be written after the other bits.
+        let mut sie_ctrl = Self::sie_ctrl_base();
+        match kind {
+            Transaction::Setup => sie_ctrl.set_send_setup(true),
+            Transaction::In => sie_ctrl.set_receive_data(true),
+            Transaction::Out => sie_ctrl.set_send_data(true),
+        }
+        regs.sie_ctrl().write_value(sie_ctrl);
+        cortex_m::asm::delay(12);
+        sie_ctrl.set_start_trans(true);
+        regs.sie_ctrl().write_value(sie_ctrl);
+
+        let result = poll_fn(|cx| {
+            TRANSFER_WAKER.register(cx.waker());
+
+            let status = regs.sie_status().read();
+            if let Some(e) = Self::status_error(status) {
+                Poll::Ready(Err(e))
+            } else if status.trans_complete() {
+                Poll::Ready(Ok(()))
+            } else if status.speed() == 0 {
+                Poll::Ready(Err(HostError::Disconnected))
+            } else {
+                regs.inte().write_set(|w| {
+                    w.set_trans_complete(true);
+                    Self::enable_error_interrupts(w);
+                });
+                Poll::Pending
+            }
+        })
+        .await;
+
+        if result.is_err() {
+            regs.sie_ctrl().write_value({
+                let mut w = Self::sie_ctrl_base();
+                w.set_stop_trans(true);
+                w
+            });
+        }
+        Self::epx_control().write(|w| w.set_enable(false));
+        result?;
+
+        if kind != Transaction::In {
+            return Ok(len);
+        }
+
+        let rx_len = Self::epx_buffer_control().read().length(0) as usize;
+        Self::read_packet(EPX_BUFFER, rx_len, buf)
+    }
+
+    /// Receive packets until `buf` is full or a short packet is received, toggling the data PID.
+    async fn receive(&mut self, channel: &mut Channel, buf: &mut [u8]) -> Result<usize, HostError> {
+        let max_packet_size = channel.endpoint.max_packet_size as usize;
+        let mut n = 0;
+        loop {
+            let end = (n + max_packet_size).min(buf.len());
+            let pid = channel.data_toggle();
+            let res = self.packet(channel, Transaction::In, pid, &mut buf[n..end]).await;
+            // A packet that didn't fit was still acknowledged, the device expects the next toggle.
+            if let Ok(_) | Err(HostError::BufferOverflow) = res {
+                channel.set_data_toggle(!pid);
+            }
+            let rx_len = res?;
+            n += rx_len;
+            if rx_len < max_packet_size || n == buf.len() {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Send `buf` as packets, toggling the data PID.
+    async fn send(&mut self, channel: &mut Channel, buf: &[u8], zero_length_packet: bool) -> Result<(), HostError> {
+        let max_packet_size = channel.endpoint.max_packet_size as usize;
+        let mut packet = [0; EPX_BUFFER_LEN];
+        let mut last_len = 0;
+        for chunk in buf.chunks(max_packet_size) {
+            let packet = &mut packet[..chunk.len()];
+            packet.copy_from_slice(chunk);
+            let pid = channel.data_toggle();
+            self.packet(channel, Transaction::Out, pid, packet).await?;
+            channel.set_data_toggle(!pid);
+            last_len = chunk.len();
+        }
+        if zero_length_packet && (buf.is_empty() || last_len == max_packet_size) {
+            let pid = channel.data_toggle();
+            self.packet(channel, Transaction::Out, pid, &mut []).await?;
+            channel.set_data_toggle(!pid);
+        }
+        Ok(())
+    }
+
+    async fn setup(&mut self, channel: &mut Channel, setup: &[u8; 8]) -> Result<(), HostError> {
+        let mut packet = *setup;
+        self.transaction(channel, Transaction::Setup, false, &mut packet)
+            .await?;
+        // The data stage starts with DATA1.
+        channel.set_data_toggle(true);
+        Ok(())
+    }
+
+    async fn status(&mut self, channel: &Channel, direction: Direction) -> Result<(), HostError> {
+        let kind = match direction {
+            Direction::In => Transaction::In,
+            Direction::Out => Transaction::Out,
+        };
+        self.transaction(channel, kind, true, &mut []).await?;
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance> HostDriver for UsbHost<'d, T> {
+    async fn wait_for_device_event(&mut self) -> DeviceEvent {
+        let regs = T::regs();
+        poll_fn(|cx| {
+            BUS_WAKER.register(cx.waker());
+
+            // Acknowledge the connection change before reading the speed, so a change after the
+            // read triggers the interrupt again.
+            regs.sie_status().write(|w| w.set_speed(0b11));
+            let speed = Self::speed();
+            if speed != self.connected {
+                self.connected = speed;
+                return Poll::Ready(match speed {
+                    Some(speed) => DeviceEvent::Connected(speed),
+                    None => DeviceEvent::Disconnected,
+                });
+            }
+
+            regs.inte().write_set(|w| w.set_host_conn_dis(true));
+            Poll::Pending
+        })
+        .await
+    }
+
+    async fn bus_reset(&mut self) {
+        let regs = T::regs();
+        let mut sie_ctrl = Self::sie_ctrl_base();
+        sie_ctrl.set_reset_bus(true);
+        regs.sie_ctrl().write_value(sie_ctrl);
+
+        // Reset recovery time, before the device must answer on address 0.
+        Timer::after_millis(50).await;
+    }
+
+    async fn control_in(&mut self, channel: &mut Channel, setup: &[u8; 8], buf: &mut [u8]) -> Result<usize, HostError> {
+        self.setup(channel, setup).await?;
+        let n = match buf.is_empty() {
+            true => 0,
+            false => self.receive(channel, buf).await?,
+        };
+        self.status(channel, Direction::Out).await?;
+        Ok(n)
+    }
+
+    async fn control_out(&mut self, channel: &mut Channel, setup: &[u8; 8], buf: &[u8]) -> Result<(), HostError> {
+        self.setup(channel, setup).await?;
+        if !buf.is_empty() {
+            self.send(channel, buf, false).await?;
+        }
+        self.status(channel, Direction::In).await
+    }
+
+    async fn data_in(&mut self, channel: &mut Channel, buf: &mut [u8]) -> Result<usize, HostError> {
+        self.receive(channel, buf).await
+    }
+
+    async fn data_out(&mut self, channel: &mut Channel, buf: &[u8]) -> Result<(), HostError> {
+        self.send(channel, buf, true).await
+    }
+}
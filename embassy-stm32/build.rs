@@ -497,6 +497,43 @@ fn main() {
                 }
             }
         }
+
+        /// Whether the selected clock keeps running in Stop mode: LSE, or HSI which is started
+        /// on request of the peripheral.
+        fn gen_mux_runs_in_stop(&self, mux: &PeripheralRccRegister) -> TokenStream {
+            let ir = &self.rcc_registers.ir;
+            let fieldset_name = mux.register.to_ascii_lowercase();
+            let fieldset = ir
+                .fieldsets
+                .iter()
+                .find(|i| i.name.eq_ignore_ascii_case(&fieldset_name))
+                .unwrap();
+            let field_name = mux.field.to_ascii_lowercase();
+            let field = fieldset.fields.iter().find(|i| i.name == field_name).unwrap();
+            let enum_name = field.enumm.unwrap();
+            let enumm = ir.enums.iter().find(|i| i.name == enum_name).unwrap();
+
+            let variants: Vec<_> = enumm
+                .variants
+                .iter()
+                .filter(|v| v.name == "LSE" || v.name == "HSI")
+                .map(|v| format_ident!("{}", v.name))
+                .collect();
+            if variants.is_empty() {
+                return quote!(false);
+            }
+
+            let fieldset_name = format_ident!("{}", fieldset_name);
+            let field_name = format_ident!("{}", field_name);
+            let enum_name = format_ident!("{}", enum_name);
+
+            quote! {
+                matches!(
+                    crate::pac::RCC.#fieldset_name().read().#field_name(),
+                    #(crate::pac::rcc::vals::#enum_name::#variants)|*
+                )
+            }
+        }
     }
 
     for p in METADATA.peripherals {
@@ -558,6 +595,10 @@ fn main() {
                 PeripheralRccKernelClock::Mux(mux) => clock_gen.gen_mux(mux),
                 PeripheralRccKernelClock::Clock(clock) => clock_gen.gen_clock(clock),
             };
+            let clock_runs_in_stop = match &rcc.kernel_clock {
+                PeripheralRccKernelClock::Mux(mux) => clock_gen.gen_mux_runs_in_stop(mux),
+                PeripheralRccKernelClock::Clock(_) => quote!(false),
+            };
 
             // A refcount leak can result if the same field is shared by peripherals with different stop modes
             // This condition should be checked in stm32-data
@@ -606,11 +647,32 @@ fn main() {
 
                         #end_rst
                     }
+                    fn enable_with_cs(_cs: critical_section::CriticalSection) {
+                        #before_enable
+                        #incr_stop_refcount
+
+                        crate::pac::RCC.#en_reg().modify(|w| w.#set_en_field(true));
+
+                        // dummy read (like in the ST HALs)
+                        let _ = crate::pac::RCC.#en_reg().read();
+
+                        // DSB for good measure
+                        cortex_m::asm::dsb();
+                    }
                     fn disable_with_cs(_cs: critical_section::CriticalSection) {
                         #before_disable
                         crate::pac::RCC.#en_reg().modify(|w| w.#set_en_field(false));
                         #decr_stop_refcount
                     }
+                    fn kernel_clock_runs_in_stop() -> bool {
+                        #clock_runs_in_stop
+                    }
+                    fn allow_stop_with_cs(_cs: critical_section::CriticalSection) {
+                        #decr_stop_refcount
+                    }
+                    fn block_stop_with_cs(_cs: critical_section::CriticalSection) {
+                        #incr_stop_refcount
+                    }
                 }
 
                 impl crate::rcc::RccPeripheral for peripherals::#pname {}
@@ -794,6 +856,9 @@ fn main() {
         (("sai", "MCLK_A"), quote!(crate::sai::MclkPin<A>)),
         (("sai", "MCLK_B"), quote!(crate::sai::MclkPin<B>)),
         (("sai", "WS"), quote!(crate::sai::WsPin)),
+        (("sai", "CK1"), quote!(crate::sai::Ck1Pin)),
+        (("sai", "D1"), quote!(crate::sai::D1Pin)),
+        (("sai", "D2"), quote!(crate::sai::D2Pin)),
         (("spi", "SCK"), quote!(crate::spi::SckPin)),
         (("spi", "MOSI"), quote!(crate::spi::MosiPin)),
         (("spi", "MISO"), quote!(crate::spi::MisoPin)),
@@ -823,6 +888,43 @@ fn main() {
         (("dcmi", "HSYNC"), quote!(crate::dcmi::HSyncPin)),
         (("dcmi", "VSYNC"), quote!(crate::dcmi::VSyncPin)),
         (("dcmi", "PIXCLK"), quote!(crate::dcmi::PixClkPin)),
+        (("dsihost", "TE"), quote!(crate::dsihost::TePin)),
+        (("pssi", "D0"), quote!(crate::pssi::D0Pin)),
+        (("pssi", "D1"), quote!(crate::pssi::D1Pin)),
+        (("pssi", "D2"), quote!(crate::pssi::D2Pin)),
+        (("pssi", "D3"), quote!(crate::pssi::D3Pin)),
+        (("pssi", "D4"), quote!(crate::pssi::D4Pin)),
+        (("pssi", "D5"), quote!(crate::pssi::D5Pin)),
+        (("pssi", "D6"), quote!(crate::pssi::D6Pin)),
+        (("pssi", "D7"), quote!(crate::pssi::D7Pin)),
+        (("pssi", "D8"), quote!(crate::pssi::D8Pin)),
+        (("pssi", "D9"), quote!(crate::pssi::D9Pin)),
+        (("pssi", "D10"), quote!(crate::pssi::D10Pin)),
+        (("pssi", "D11"), quote!(crate::pssi::D11Pin)),
+        (("pssi", "D12"), quote!(crate::pssi::D12Pin)),
+        (("pssi", "D13"), quote!(crate::pssi::D13Pin)),
+        (("pssi", "D14"), quote!(crate::pssi::D14Pin)),
+        (("pssi", "D15"), quote!(crate::pssi::D15Pin)),
+        (("pssi", "PDCK"), quote!(crate::pssi::PdckPin)),
+        (("pssi", "DE"), quote!(crate::pssi::DePin)),
+        (("pssi", "RDY"), quote!(crate::pssi::RdyPin)),
+        (("dfsdm", "CKOUT"), quote!(crate::dfsdm::CkoutPin)),
+        (("dfsdm", "DATIN0"), quote!(crate::dfsdm::DatinPin<Ch0>)),
+        (("dfsdm", "DATIN1"), quote!(crate::dfsdm::DatinPin<Ch1>)),
+        (("dfsdm", "DATIN2"), quote!(crate::dfsdm::DatinPin<Ch2>)),
+        (("dfsdm", "DATIN3"), quote!(crate::dfsdm::DatinPin<Ch3>)),
+        (("dfsdm", "DATIN4"), quote!(crate::dfsdm::DatinPin<Ch4>)),
+        (("dfsdm", "DATIN5"), quote!(crate::dfsdm::DatinPin<Ch5>)),
+        (("dfsdm", "DATIN6"), quote!(crate::dfsdm::DatinPin<Ch6>)),
+        (("dfsdm", "DATIN7"), quote!(crate::dfsdm::DatinPin<Ch7>)),
+        (("dfsdm", "CKIN0"), quote!(crate::dfsdm::CkinPin<Ch0>)),
+        (("dfsdm", "CKIN1"), quote!(crate::dfsdm::CkinPin<Ch1>)),
+        (("dfsdm", "CKIN2"), quote!(crate::dfsdm::CkinPin<Ch2>)),
+        (("dfsdm", "CKIN3"), quote!(crate::dfsdm::CkinPin<Ch3>)),
+        (("dfsdm", "CKIN4"), quote!(crate::dfsdm::CkinPin<Ch4>)),
+        (("dfsdm", "CKIN5"), quote!(crate::dfsdm::CkinPin<Ch5>)),
+        (("dfsdm", "CKIN6"), quote!(crate::dfsdm::CkinPin<Ch6>)),
+        (("dfsdm", "CKIN7"), quote!(crate::dfsdm::CkinPin<Ch7>)),
         (("usb", "DP"), quote!(crate::usb::DpPin)),
         (("usb", "DM"), quote!(crate::usb::DmPin)),
         (("otg", "DP"), quote!(crate::usb::DpPin)),
@@ -857,6 +959,8 @@ fn main() {
         (("eth", "TXD2"), quote!(crate::eth::TXD2Pin)),
         (("eth", "TXD3"), quote!(crate::eth::TXD3Pin)),
         (("eth", "TX_EN"), quote!(crate::eth::TXEnPin)),
+        (("mdios", "MDIO"), quote!(crate::mdios::MdioPin)),
+        (("mdios", "MDC"), quote!(crate::mdios::MdcPin)),
         (("fmc", "A0"), quote!(crate::fmc::A0Pin)),
         (("fmc", "A1"), quote!(crate::fmc::A1Pin)),
         (("fmc", "A2"), quote!(crate::fmc::A2Pin)),
@@ -1104,6 +1208,22 @@ fn main() {
                     }
                 }
 
+                if regs.kind == "comp" && chip_name.starts_with("stm32g4") {
+                    let peri = format_ident!("{}", p.name);
+                    let pin_name = format_ident!("{}", pin.pin);
+                    if let Some(ch) = pin.signal.strip_prefix("INP") {
+                        let ch: u8 = ch.parse().unwrap_or(0);
+                        g.extend(quote! {
+                            impl_comp_inp_pin!( #peri, #pin_name, #ch);
+                        })
+                    } else if let Some(ch) = pin.signal.strip_prefix("INM") {
+                        let ch: u8 = ch.parse().unwrap_or(0);
+                        g.extend(quote! {
+                            impl_comp_inm_pin!( #peri, #pin_name, #ch);
+                        })
+                    }
+                }
+
                 // DAC is special
                 if regs.kind == "dac" {
                     let peri = format_ident!("{}", p.name);
@@ -1137,6 +1257,8 @@ fn main() {
         (("i2c", "TX"), quote!(crate::i2c::TxDma)),
         (("dcmi", "DCMI"), quote!(crate::dcmi::FrameDma)),
         (("dcmi", "PSSI"), quote!(crate::dcmi::FrameDma)),
+        (("pssi", "PSSI"), quote!(crate::pssi::PssiDma)),
+        (("pssi", "DCMI"), quote!(crate::pssi::PssiDma)),
         // SDMMCv1 uses the same channel for both directions, so just implement for RX
         (("sdmmc", "RX"), quote!(crate::sdmmc::SdmmcDma)),
         (("quadspi", "QUADSPI"), quote!(crate::qspi::QuadDma)),
@@ -1147,12 +1269,20 @@ fn main() {
         (("hash", "IN"), quote!(crate::hash::Dma)),
         (("cryp", "IN"), quote!(crate::cryp::DmaIn)),
         (("cryp", "OUT"), quote!(crate::cryp::DmaOut)),
+        (("jpeg", "IN"), quote!(crate::jpeg::DmaIn)),
+        (("jpeg", "OUT"), quote!(crate::jpeg::DmaOut)),
+        (("dfsdm", "FLT0"), quote!(crate::dfsdm::Dma<Flt0>)),
+        (("dfsdm", "FLT1"), quote!(crate::dfsdm::Dma<Flt1>)),
+        (("dfsdm", "FLT2"), quote!(crate::dfsdm::Dma<Flt2>)),
+        (("dfsdm", "FLT3"), quote!(crate::dfsdm::Dma<Flt3>)),
         (("timer", "CH1"), quote!(crate::timer::Ch1Dma)),
         (("timer", "CH2"), quote!(crate::timer::Ch2Dma)),
         (("timer", "CH3"), quote!(crate::timer::Ch3Dma)),
         (("timer", "CH4"), quote!(crate::timer::Ch4Dma)),
         (("cordic", "WRITE"), quote!(crate::cordic::WriteDma)), // FIXME: stm32u5a crash on Cordic driver
         (("cordic", "READ"), quote!(crate::cordic::ReadDma)),   // FIXME: stm32u5a crash on Cordic driver
+        (("fmac", "WRITE"), quote!(crate::fmac::WriteDma)),
+        (("fmac", "READ"), quote!(crate::fmac::ReadDma)),
     ]
     .into();
 
@@ -1553,7 +1683,7 @@ fn main() {
     let mut s = chip_name.split('_');
     let mut chip_name: String = s.next().unwrap().to_string();
     let core_name = if let Some(c) = s.next() {
-        if !c.starts_with("CM") {
+        if !c.starts_with("cm") {
             chip_name.push('_');
             chip_name.push_str(c);
             None
@@ -1566,6 +1696,7 @@ fn main() {
 
     if let Some(core) = core_name {
         println!("cargo:rustc-cfg={}_{}", &chip_name[..chip_name.len() - 2], core);
+        println!("cargo:rustc-cfg=core_{}", core);
     }
 
     // =======
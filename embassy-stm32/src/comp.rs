@@ -0,0 +1,327 @@
+//! Comparator (COMP)
+//!
+//! Only the STM32G4 comparators are supported.
+//!
+//! Besides GPIO inputs, the comparators are wired internally to the rest of the analog
+//! peripherals. The interconnections available on a chip are checked at compile time:
+//!
+//! - a [`DacChannel`](crate::dac::DacChannel) can drive the inverting input, see [`InvertingInput`].
+//! - a timer output compare channel can blank the output, see [`BlankingSource`].
+//! - an [`OpAmp`](crate::opamp::OpAmp) output can be sampled by the ADC, see
+//!   [`OpAmpInternalOutput`](crate::opamp::OpAmpInternalOutput).
+#![macro_use]
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+
+use crate::dac::DacChannel;
+use crate::Peripheral;
+
+/// Hysteresis.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Hysteresis {
+    None,
+    Mv10,
+    Mv20,
+    Mv30,
+    Mv40,
+    Mv50,
+    Mv60,
+    Mv70,
+}
+
+/// Fraction of the internal voltage reference, for the inverting input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Vrefint {
+    /// 1/4 VREFINT.
+    Quarter,
+    /// 1/2 VREFINT.
+    Half,
+    /// 3/4 VREFINT.
+    ThreeQuarters,
+    /// VREFINT.
+    Full,
+}
+
+/// Comparator configuration.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Invert the output.
+    pub invert: bool,
+    /// Hysteresis.
+    pub hysteresis: Hysteresis,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            invert: false,
+            hysteresis: Hysteresis::None,
+        }
+    }
+}
+
+/// Comparator driver.
+pub struct Comp<'d, T: Instance> {
+    _inner: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Comp<'d, T> {
+    /// Create a new comparator driver, and enable the comparator.
+    ///
+    /// A DAC channel used as the inverting input is only borrowed to check the interconnection,
+    /// so its value can still be updated afterwards. It must stay enabled while the
+    /// comparator is in use.
+    pub fn new(
+        inner: impl Peripheral<P = T> + 'd,
+        inp: impl Peripheral<P = impl NonInvertingPin<T> + crate::gpio::Pin> + 'd,
+        inm: impl InvertingInput<T>,
+        config: Config,
+    ) -> Self {
+        into_ref!(inner, inp);
+        inp.set_as_analog();
+        inm.setup();
+
+        let (scalen, brgen) = inm.uses_vrefint();
+        T::regs().csr().modify(|w| {
+            w.set_inpsel(inp.channel());
+            w.set_inmsel(inm.inmsel());
+            w.set_scalen(scalen);
+            w.set_brgen(brgen);
+            w.set_pol(config.invert);
+            w.set_hyst(config.hysteresis as u8);
+            w.set_en(true);
+        });
+
+        Self { _inner: inner }
+    }
+
+    /// Blank the output with an output compare channel of timer `B`.
+    ///
+    /// The output is forced low while the timer channel is active. The channel is configured by
+    /// the timer driver, e.g. to mask switching noise in a PWM cycle.
+    pub fn set_blanking<B: BlankingSource<T>>(&mut self) {
+        T::regs().csr().modify(|w| w.set_blanksel(B::BLANKSEL));
+    }
+
+    /// Disable output blanking.
+    pub fn disable_blanking(&mut self) {
+        T::regs().csr().modify(|w| w.set_blanksel(0));
+    }
+
+    /// Get the output level.
+    ///
+    /// Returns `true` when the non-inverting input is above the inverting input, or the other
+    /// way around with [`Config::invert`].
+    pub fn output_level(&self) -> bool {
+        T::regs().csr().read().value()
+    }
+}
+
+impl<'d, T: Instance> Drop for Comp<'d, T> {
+    fn drop(&mut self) {
+        T::regs().csr().modify(|w| {
+            w.set_en(false);
+            w.set_scalen(false);
+            w.set_brgen(false);
+        });
+    }
+}
+
+pub(crate) trait SealedInstance {
+    fn regs() -> crate::pac::comp::Comp;
+}
+
+pub(crate) trait SealedNonInvertingPin<T: Instance> {
+    fn channel(&self) -> bool;
+}
+
+pub(crate) trait SealedInvertingInput<T: Instance> {
+    fn inmsel(&self) -> u8;
+
+    /// Whether the VREFINT scaler and its resistor bridge are needed, as `(scalen, brgen)`.
+    fn uses_vrefint(&self) -> (bool, bool) {
+        (false, false)
+    }
+
+    fn setup(&self) {}
+}
+
+pub(crate) trait SealedBlankingSource<T: Instance> {
+    const BLANKSEL: u8;
+}
+
+/// Comparator instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + 'static {}
+
+/// Non-inverting input pin trait.
+#[allow(private_bounds)]
+pub trait NonInvertingPin<T: Instance>: SealedNonInvertingPin<T> {}
+
+/// Inverting input trait.
+///
+/// Implemented for the inverting input pins, [`Vrefint`] fractions, and the DAC channels
+/// wired to the comparator.
+#[allow(private_bounds)]
+pub trait InvertingInput<T: Instance>: SealedInvertingInput<T> {}
+
+/// Output blanking source trait, implemented for the timers that can blank the comparator.
+#[allow(private_bounds)]
+pub trait BlankingSource<T: Instance>: SealedBlankingSource<T> {}
+
+impl<T: Instance> SealedInvertingInput<T> for Vrefint {
+    fn inmsel(&self) -> u8 {
+        *self as u8
+    }
+
+    fn uses_vrefint(&self) -> (bool, bool) {
+        match self {
+            Vrefint::Full => (true, false),
+            _ => (true, true),
+        }
+    }
+}
+impl<T: Instance> InvertingInput<T> for Vrefint {}
+
+impl<T: Instance, P: InvertingPin<T> + crate::gpio::Pin> SealedInvertingInput<T> for P {
+    fn inmsel(&self) -> u8 {
+        SealedInvertingPin::<T>::inmsel(self)
+    }
+
+    fn setup(&self) {
+        self.set_as_analog();
+    }
+}
+impl<T: Instance, P: InvertingPin<T> + crate::gpio::Pin> InvertingInput<T> for P {}
+
+pub(crate) trait SealedInvertingPin<T: Instance> {
+    fn inmsel(&self) -> u8;
+}
+
+/// Inverting input pin trait.
+#[allow(private_bounds)]
+pub trait InvertingPin<T: Instance>: SealedInvertingPin<T> {}
+
+foreach_peripheral! {
+    (comp, $inst:ident) => {
+        impl SealedInstance for crate::peripherals::$inst {
+            fn regs() -> crate::pac::comp::Comp {
+                crate::pac::$inst
+            }
+        }
+
+        impl Instance for crate::peripherals::$inst {}
+    };
+}
+
+macro_rules! impl_comp_dac {
+    ($inst:ident, $dac:ident, $ch:expr, $inmsel:expr) => {
+        foreach_peripheral!(
+            (dac, $dac) => {
+                impl<'a, 'd, DMA> SealedInvertingInput<crate::peripherals::$inst>
+                    for &'a DacChannel<'d, crate::peripherals::$dac, $ch, DMA>
+                {
+                    fn inmsel(&self) -> u8 {
+                        $inmsel
+                    }
+                }
+
+                impl<'a, 'd, DMA> InvertingInput<crate::peripherals::$inst>
+                    for &'a DacChannel<'d, crate::peripherals::$dac, $ch, DMA>
+                {
+                }
+            };
+        );
+    };
+}
+
+macro_rules! impl_comp_blanking {
+    ($inst:ident, $tim:ident, $blanksel:expr) => {
+        foreach_peripheral!(
+            (timer, $tim) => {
+                impl SealedBlankingSource<crate::peripherals::$inst> for crate::peripherals::$tim {
+                    const BLANKSEL: u8 = $blanksel;
+                }
+
+                impl BlankingSource<crate::peripherals::$inst> for crate::peripherals::$tim {}
+            };
+        );
+    };
+}
+
+// Internal interconnections, RM0440 "COMP inverting input selection" and "COMP blanking sources".
+foreach_peripheral!(
+    (comp, COMP1) => {
+        impl_comp_dac!(COMP1, DAC3, 1, 0b100);
+        impl_comp_dac!(COMP1, DAC1, 1, 0b101);
+        impl_comp_blanking!(COMP1, TIM1, 1); // TIM1_OC5
+        impl_comp_blanking!(COMP1, TIM2, 2); // TIM2_OC3
+        impl_comp_blanking!(COMP1, TIM3, 3); // TIM3_OC3
+        impl_comp_blanking!(COMP1, TIM8, 4); // TIM8_OC5
+        impl_comp_blanking!(COMP1, TIM20, 5); // TIM20_OC5
+        impl_comp_blanking!(COMP1, TIM15, 6); // TIM15_OC1
+        impl_comp_blanking!(COMP1, TIM4, 7); // TIM4_OC3
+    };
+    (comp, COMP2) => {
+        impl_comp_dac!(COMP2, DAC3, 2, 0b100);
+        impl_comp_dac!(COMP2, DAC1, 2, 0b101);
+        impl_comp_blanking!(COMP2, TIM1, 1); // TIM1_OC5
+        impl_comp_blanking!(COMP2, TIM2, 2); // TIM2_OC3
+        impl_comp_blanking!(COMP2, TIM3, 3); // TIM3_OC3
+        impl_comp_blanking!(COMP2, TIM8, 4); // TIM8_OC5
+        impl_comp_blanking!(COMP2, TIM20, 5); // TIM20_OC5
+        impl_comp_blanking!(COMP2, TIM15, 6); // TIM15_OC1
+        impl_comp_blanking!(COMP2, TIM4, 7); // TIM4_OC3
+    };
+    (comp, COMP3) => {
+        impl_comp_dac!(COMP3, DAC3, 1, 0b100);
+        impl_comp_dac!(COMP3, DAC1, 1, 0b101);
+    };
+    (comp, COMP4) => {
+        impl_comp_dac!(COMP4, DAC3, 2, 0b100);
+        impl_comp_dac!(COMP4, DAC1, 1, 0b101);
+    };
+    // COMP5 to COMP7 only in STM32G4 Cat 3/4 devices
+    (comp, COMP5) => {
+        impl_comp_dac!(COMP5, DAC4, 1, 0b100);
+        impl_comp_dac!(COMP5, DAC1, 2, 0b101);
+    };
+    (comp, COMP6) => {
+        impl_comp_dac!(COMP6, DAC4, 2, 0b100);
+        impl_comp_dac!(COMP6, DAC2, 1, 0b101);
+    };
+    (comp, COMP7) => {
+        impl_comp_dac!(COMP7, DAC4, 1, 0b100);
+        impl_comp_dac!(COMP7, DAC2, 1, 0b101);
+    };
+);
+
+#[allow(unused_macros)]
+macro_rules! impl_comp_inp_pin {
+    ($inst:ident, $pin:ident, $ch:expr) => {
+        impl crate::comp::NonInvertingPin<peripherals::$inst> for crate::peripherals::$pin {}
+        impl crate::comp::SealedNonInvertingPin<peripherals::$inst> for crate::peripherals::$pin {
+            fn channel(&self) -> bool {
+                $ch != 0
+            }
+        }
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! impl_comp_inm_pin {
+    ($inst:ident, $pin:ident, $ch:expr) => {
+        impl crate::comp::InvertingPin<peripherals::$inst> for crate::peripherals::$pin {}
+        impl crate::comp::SealedInvertingPin<peripherals::$inst> for crate::peripherals::$pin {
+            fn inmsel(&self) -> u8 {
+                // INM pins follow the 4 VREFINT fractions and the 2 DAC channels.
+                0b110 + $ch
+            }
+        }
+    };
+}
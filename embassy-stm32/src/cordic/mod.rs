@@ -578,6 +578,99 @@ impl<'d, T: Instance> Cordic<'d, T> {
     }
 }
 
+// scalar related
+impl<'d, T: Instance> Cordic<'d, T> {
+    /// Compute sine and cosine of a single angle in q1.31 format, returns `(sin, cos)`
+    ///
+    /// `angle` is expressed in units of π, so `-1.0..1.0` maps to `-π..π`.
+    ///
+    /// Notice:
+    /// Scalar functions switch FUNCTION and SCALE on demand and keep the new values in the driver config,
+    /// ARG2 is left at the last value written. Consider run [.reconfigure()](Self::reconfigure) or
+    /// [.set_config()](Self::set_config) before going back to buffer calculation with `arg1_only`.
+    pub fn sin_cos(&mut self, angle: u32) -> (u32, u32) {
+        let (cos, sin) = self.calc_scalar(Function::Cos, Scale::Arg1Res1, angle, 0x7FFF_FFFF);
+        (sin, cos)
+    }
+
+    /// Rotate vector `(modulus, 0)` by `angle` in q1.31 format, returns `(modulus * cos(angle), modulus * sin(angle))`
+    ///
+    /// `angle` is expressed in units of π, `modulus` should be in `0.0..=1.0`.
+    /// This is the building block of park and inverse park transforms.
+    pub fn rotate(&mut self, angle: u32, modulus: u32) -> (u32, u32) {
+        self.calc_scalar(Function::Cos, Scale::Arg1Res1, angle, modulus)
+    }
+
+    /// Compute the angle of vector `(x, y)` in q1.31 format, expressed in units of π
+    pub fn atan2(&mut self, y: u32, x: u32) -> u32 {
+        self.calc_scalar(Function::Phase, Scale::Arg1Res1, x, y).0
+    }
+
+    /// Compute the magnitude of vector `(x, y)` in q1.31 format
+    ///
+    /// Notice:
+    /// Result will saturate if magnitude is above `1.0`.
+    pub fn magnitude(&mut self, x: u32, y: u32) -> u32 {
+        self.calc_scalar(Function::Modulus, Scale::Arg1Res1, x, y).0
+    }
+
+    /// Convert vector `(x, y)` to polar coordinates in q1.31 format, returns `(angle, magnitude)`
+    ///
+    /// `angle` is expressed in units of π.
+    pub fn phase_modulus(&mut self, x: u32, y: u32) -> (u32, u32) {
+        self.calc_scalar(Function::Phase, Scale::Arg1Res1, x, y)
+    }
+
+    /// Compute square root of a single value in q1.31 format
+    ///
+    /// SCALE is chosen according to `value`, values below 0.027 lose precision.
+    pub fn sqrt(&mut self, value: u32) -> Result<u32, CordicError> {
+        if (value as i32) < 0 {
+            return Err(ArgError {
+                func: Function::Sqrt,
+                scale: None,
+                arg_range: [0.0, 1.0],
+                inclusive_upper_bound: false,
+                arg_type: ArgType::Arg1,
+            }
+            .into());
+        }
+
+        // above 0.75, divide input by 2 (SCALE = 1) and multiply result by 2
+        if value < 0x6000_0000 {
+            Ok(self.calc_scalar(Function::Sqrt, Scale::Arg1Res1, value, 0).0)
+        } else {
+            let res = self.calc_scalar(Function::Sqrt, Scale::Arg1o2Res2, value >> 1, 0).0;
+            Ok((res as i32).saturating_mul(2) as u32)
+        }
+    }
+
+    fn calc_scalar(&mut self, function: Function, scale: Scale, arg1: u32, arg2: u32) -> (u32, u32) {
+        if self.config.function as u8 != function as u8 {
+            self.config.function = function;
+            self.peri.set_func(function);
+        }
+
+        if self.config.scale != scale {
+            self.config.scale = scale;
+            self.peri.set_scale(scale);
+        }
+
+        self.peri.set_argument_count(AccessCount::Two);
+        self.peri.set_result_count(AccessCount::Two);
+        self.peri.set_data_width(Width::Bits32, Width::Bits32);
+
+        // writing ARG2 starts the calculation, reading RDATA stalls the bus until result is ready
+        self.peri.write_argument(arg1);
+        self.peri.write_argument(arg2);
+
+        let res1 = self.peri.read_result();
+        let res2 = self.peri.read_result();
+
+        (res1, res2)
+    }
+}
+
 macro_rules! check_arg_value {
     ($func_arg1_name:ident, $func_arg2_name:ident, $float_type:ty) => {
         impl<'d, T: Instance> Cordic<'d, T> {
@@ -60,3 +60,13 @@ floating_fixed_convert!(
     15,
     0x3800_0000u32 // binary form of 1f32^(-15)
 );
+
+floating_fixed_convert!(
+    f32_to_q1_31,
+    q1_31_to_f32,
+    u32,
+    i32,
+    f32,
+    31,
+    0x3000_0000u32 // binary form of 1f32^(-31)
+);
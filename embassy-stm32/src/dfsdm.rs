@@ -0,0 +1,482 @@
+//! Digital Filter for Sigma-Delta Modulators (DFSDM)
+//!
+//! The channels receive the serial bitstreams of sigma-delta modulators, such as PDM microphones
+//! or metering frontends, and the filters decimate them into samples streamed by DMA.
+#![macro_use]
+
+use core::marker::PhantomData;
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+
+use crate::dma::{ringbuffer, ReadableRingBuffer, TransferOptions};
+use crate::gpio::{AFType, AnyPin, SealedPin as _, Speed};
+use crate::{pac, peripherals, Peripheral};
+
+/// DFSDM error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The samples weren't read fast enough, and were overwritten.
+    Overrun,
+}
+
+impl From<ringbuffer::OverrunError> for Error {
+    fn from(_: ringbuffer::OverrunError) -> Self {
+        Self::Overrun
+    }
+}
+
+/// Source of the output clock.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockSource {
+    /// The DFSDM kernel clock.
+    System,
+    /// The audio clock.
+    Audio,
+}
+
+/// DFSDM configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Source of the output clock.
+    pub clock_source: ClockSource,
+    /// Division of the source giving the CKOUT clock of the modulators, in `2..=256`.
+    pub clock_divider: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            clock_source: ClockSource::System,
+            clock_divider: 32,
+        }
+    }
+}
+
+/// Serial interface type of a channel.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SerialInterface {
+    /// SPI, sampling the data on the rising clock edges.
+    SpiRisingEdge,
+    /// SPI, sampling the data on the falling clock edges.
+    SpiFallingEdge,
+    /// Manchester coded input, a rising edge being a 0.
+    ManchesterRisingZero,
+    /// Manchester coded input, a rising edge being a 1.
+    ManchesterRisingOne,
+}
+
+/// Clock of a channel in SPI mode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpiClock {
+    /// The CKIN pin of the channel.
+    External,
+    /// The CKOUT clock.
+    Internal,
+    /// The CKOUT clock, sampling on every other falling edge, e.g. for a modulator sending a bit
+    /// every two clock cycles.
+    InternalDivFalling,
+    /// The CKOUT clock, sampling on every other rising edge.
+    InternalDivRising,
+}
+
+/// Channel configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct ChannelConfig {
+    /// Serial interface type.
+    pub interface: SerialInterface,
+    /// Clock in SPI mode.
+    pub clock: SpiClock,
+    /// Calibration offset, subtracted from the samples (24-bit signed).
+    pub offset: i32,
+    /// Right shift of the filter output, in `0..=31`, fitting the samples in 24 bits.
+    pub right_shift: u8,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            interface: SerialInterface::SpiRisingEdge,
+            clock: SpiClock::Internal,
+            offset: 0,
+            right_shift: 0,
+        }
+    }
+}
+
+/// Order of the sinc filter.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FilterOrder {
+    /// FastSinc filter.
+    FastSinc,
+    /// Sinc1 filter.
+    Sinc1,
+    /// Sinc2 filter.
+    Sinc2,
+    /// Sinc3 filter.
+    Sinc3,
+    /// Sinc4 filter.
+    Sinc4,
+    /// Sinc5 filter.
+    Sinc5,
+}
+
+/// Filter configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct FilterConfig {
+    /// Order of the sinc filter.
+    pub order: FilterOrder,
+    /// Sinc filter oversampling (decimation) ratio, in `1..=1024`.
+    pub oversampling: u16,
+    /// Integrator oversampling ratio, averaging that many sinc filter outputs, in `1..=256`.
+    pub integrator_oversampling: u16,
+    /// Start the conversions together with filter 0, e.g. for the other microphones of an array.
+    pub sync_with_filter0: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        // 16 kHz samples from the 2.048 MHz bitstream of a PDM microphone.
+        Self {
+            order: FilterOrder::Sinc4,
+            oversampling: 128,
+            integrator_oversampling: 1,
+            sync_with_filter0: false,
+        }
+    }
+}
+
+/// DFSDM driver, owning the channels.
+///
+/// Configure the channels with [`configure_channel`](Self::configure_channel) and its variants,
+/// then create a [`Filter`] for each stream of samples.
+pub struct Dfsdm<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    ckout: Option<PeripheralRef<'d, AnyPin>>,
+    pins: [Option<PeripheralRef<'d, AnyPin>>; 16],
+}
+
+impl<'d, T: Instance> Dfsdm<'d, T> {
+    /// Create a new DFSDM driver, clocking the modulators from the CKOUT pin.
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        ckout: impl Peripheral<P = impl CkoutPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        assert!((2..=256).contains(&config.clock_divider));
+        Self::new_inner(peri, new_pin!(ckout, AFType::OutputPushPull, Speed::VeryHigh), config)
+    }
+
+    /// Create a new DFSDM driver, with modulators clocked externally.
+    ///
+    /// The channels must use [`SpiClock::External`] or Manchester coding.
+    pub fn new_without_clock_output(peri: impl Peripheral<P = T> + 'd) -> Self {
+        Self::new_inner(
+            peri,
+            None,
+            Config {
+                clock_divider: 1,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn new_inner(peri: impl Peripheral<P = T> + 'd, ckout: Option<PeripheralRef<'d, AnyPin>>, config: Config) -> Self {
+        into_ref!(peri);
+
+        T::enable_and_reset();
+
+        T::regs().ch(0).cfgr1().modify(|w| {
+            w.set_ckoutsrc(config.clock_source == ClockSource::Audio);
+            // A divider of 0 disables the output clock.
+            w.set_ckoutdiv((config.clock_divider - 1) as u8);
+            w.set_dfsdmen(true);
+        });
+
+        Self {
+            _peri: peri,
+            ckout,
+            pins: Default::default(),
+        }
+    }
+
+    /// Configure a channel reading its DATIN pin.
+    pub fn configure_channel<C: ChannelInstance>(
+        &mut self,
+        datin: impl Peripheral<P = impl DatinPin<T, C>> + 'd,
+        config: &ChannelConfig,
+    ) {
+        self.pins[C::INDEX * 2] = new_pin!(datin, AFType::Input, Speed::VeryHigh);
+        Self::configure_channel_inner(C::INDEX, false, config);
+    }
+
+    /// Configure a channel reading its DATIN pin, clocked from its CKIN pin.
+    pub fn configure_channel_with_clock_input<C: ChannelInstance>(
+        &mut self,
+        datin: impl Peripheral<P = impl DatinPin<T, C>> + 'd,
+        ckin: impl Peripheral<P = impl CkinPin<T, C>> + 'd,
+        config: &ChannelConfig,
+    ) {
+        assert_eq!(config.clock, SpiClock::External);
+        self.pins[C::INDEX * 2] = new_pin!(datin, AFType::Input, Speed::VeryHigh);
+        self.pins[C::INDEX * 2 + 1] = new_pin!(ckin, AFType::Input, Speed::VeryHigh);
+        Self::configure_channel_inner(C::INDEX, false, config);
+    }
+
+    /// Configure a channel reading the pins of the following channel, e.g. for the second PDM
+    /// microphone sharing a data line, sampled on the other clock edge.
+    ///
+    /// The following channel, or channel 0 for channel 7, must be configured with its pins.
+    pub fn configure_channel_from_next<C: ChannelInstance>(&mut self, config: &ChannelConfig) {
+        Self::configure_channel_inner(C::INDEX, true, config);
+    }
+
+    fn configure_channel_inner(index: usize, from_next: bool, config: &ChannelConfig) {
+        assert!(config.right_shift < 32);
+        let ch = T::regs().ch(index);
+
+        ch.cfgr1().modify(|w| w.set_chen(false));
+        ch.cfgr2().write(|w| {
+            w.set_offset(config.offset as u32 & 0xFF_FFFF);
+            w.set_dtrbs(config.right_shift);
+        });
+        ch.cfgr1().modify(|w| {
+            w.set_sitp(match config.interface {
+                SerialInterface::SpiRisingEdge => 0b00,
+                SerialInterface::SpiFallingEdge => 0b01,
+                SerialInterface::ManchesterRisingZero => 0b10,
+                SerialInterface::ManchesterRisingOne => 0b11,
+            });
+            w.set_spicksel(match config.clock {
+                SpiClock::External => 0b00,
+                SpiClock::Internal => 0b01,
+                SpiClock::InternalDivFalling => 0b10,
+                SpiClock::InternalDivRising => 0b11,
+            });
+            w.set_chinsel(from_next);
+            // Serial input, one sample per word.
+            w.set_datmpx(0);
+            w.set_datpack(0);
+            w.set_chen(true);
+        });
+    }
+}
+
+impl<'d, T: Instance> Drop for Dfsdm<'d, T> {
+    fn drop(&mut self) {
+        let regs = T::regs();
+        for index in 0..8 {
+            regs.ch(index).cfgr1().modify(|w| w.set_chen(false));
+        }
+        regs.ch(0).cfgr1().modify(|w| w.set_dfsdmen(false));
+
+        self.ckout.as_ref().map(|x| x.set_as_disconnected());
+        for pin in self.pins.iter().flatten() {
+            pin.set_as_disconnected();
+        }
+
+        T::disable();
+    }
+}
+
+fn ring_buffer_options() -> TransferOptions {
+    TransferOptions {
+        half_transfer_ir: true,
+        //the ring buffers always use circular mode
+        ..Default::default()
+    }
+}
+
+/// DFSDM filter, converting a channel continuously.
+///
+/// Once [`start`](Self::start)ed, the samples go to a DMA ring buffer in the background, and
+/// [`read`](Self::read) pops them.
+pub struct Filter<'d, T: Instance, F: FilterInstance> {
+    _phantom: PhantomData<(&'d Dfsdm<'d, T>, F)>,
+    ring_buffer: ReadableRingBuffer<'d, u32>,
+}
+
+impl<'d, T: Instance, F: FilterInstance> Filter<'d, T, F> {
+    /// Create a new filter, converting channel `C`.
+    ///
+    /// The channel must be configured beforehand with the [`Dfsdm`] driver.
+    pub fn new<C: ChannelInstance>(
+        _dfsdm: &'d Dfsdm<'_, T>,
+        dma: impl Peripheral<P = impl Dma<T, F>> + 'd,
+        dma_buf: &'d mut [u32],
+        config: FilterConfig,
+    ) -> Self {
+        assert!((1..=1024).contains(&config.oversampling));
+        assert!((1..=256).contains(&config.integrator_oversampling));
+        assert!(!config.sync_with_filter0 || F::INDEX != 0);
+        into_ref!(dma);
+
+        let flt = T::regs().flt(F::INDEX);
+        flt.cr1().modify(|w| w.set_dfen(false));
+        flt.fcr().write(|w| {
+            w.set_ford(match config.order {
+                FilterOrder::FastSinc => 0,
+                FilterOrder::Sinc1 => 1,
+                FilterOrder::Sinc2 => 2,
+                FilterOrder::Sinc3 => 3,
+                FilterOrder::Sinc4 => 4,
+                FilterOrder::Sinc5 => 5,
+            });
+            w.set_fosr(config.oversampling - 1);
+            w.set_iosr((config.integrator_oversampling - 1) as u8);
+        });
+        flt.cr1().modify(|w| {
+            w.set_rch(C::INDEX as u8);
+            w.set_rcont(true);
+            w.set_rsync(config.sync_with_filter0);
+            w.set_rdmaen(true);
+            w.set_fast(true);
+        });
+
+        let request = dma.request();
+        let ring_buffer = unsafe {
+            ReadableRingBuffer::new(
+                dma,
+                request,
+                flt.rdatar().as_ptr() as *mut u32,
+                dma_buf,
+                ring_buffer_options(),
+            )
+        };
+
+        Self {
+            _phantom: PhantomData,
+            ring_buffer,
+        }
+    }
+
+    /// Start the conversions.
+    ///
+    /// Filters synchronized with filter 0 start once it's started.
+    pub fn start(&mut self) {
+        self.ring_buffer.start();
+        let flt = T::regs().flt(F::INDEX);
+        flt.cr1().modify(|w| w.set_dfen(true));
+        if !flt.cr1().read().rsync() {
+            flt.cr1().modify(|w| w.set_rswstart(true));
+        }
+    }
+
+    /// Stop the conversions.
+    ///
+    /// The samples left in the ring buffer are discarded, the filter can be started again with
+    /// [`start`](Self::start).
+    pub fn stop(&mut self) {
+        T::regs().flt(F::INDEX).cr1().modify(|w| w.set_dfen(false));
+        self.ring_buffer.request_stop();
+        while self.ring_buffer.is_running() {}
+        self.ring_buffer.clear();
+    }
+
+    /// Read samples from the ring buffer, as 24-bit signed values.
+    ///
+    /// If there are less than `data.len()` samples in the buffer, this waits until there are.
+    pub async fn read(&mut self, data: &mut [i32]) -> Result<(), Error> {
+        let words = unsafe { core::slice::from_raw_parts_mut(data.as_mut_ptr().cast::<u32>(), data.len()) };
+        self.ring_buffer.read_exact(words).await?;
+        for sample in data.iter_mut() {
+            // The sample is in the 24 MSBs, the channel number in the LSBs.
+            *sample >>= 8;
+        }
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance, F: FilterInstance> Drop for Filter<'d, T, F> {
+    fn drop(&mut self) {
+        T::regs().flt(F::INDEX).cr1().modify(|w| {
+            w.set_dfen(false);
+            w.set_rdmaen(false);
+        });
+    }
+}
+
+trait SealedChannel {
+    const INDEX: usize;
+}
+
+/// Channel instance trait.
+#[allow(private_bounds)]
+pub trait ChannelInstance: SealedChannel {}
+
+trait SealedFilter {
+    const INDEX: usize;
+}
+
+/// Filter instance trait.
+#[allow(private_bounds)]
+pub trait FilterInstance: SealedFilter {}
+
+macro_rules! impl_channel {
+    ($name:ident, $index:expr) => {
+        #[doc = concat!("Channel ", stringify!($index), ".")]
+        pub enum $name {}
+        impl SealedChannel for $name {
+            const INDEX: usize = $index;
+        }
+        impl ChannelInstance for $name {}
+    };
+}
+
+macro_rules! impl_filter {
+    ($name:ident, $index:expr) => {
+        #[doc = concat!("Filter ", stringify!($index), ".")]
+        pub enum $name {}
+        impl SealedFilter for $name {
+            const INDEX: usize = $index;
+        }
+        impl FilterInstance for $name {}
+    };
+}
+
+impl_channel!(Ch0, 0);
+impl_channel!(Ch1, 1);
+impl_channel!(Ch2, 2);
+impl_channel!(Ch3, 3);
+impl_channel!(Ch4, 4);
+impl_channel!(Ch5, 5);
+impl_channel!(Ch6, 6);
+impl_channel!(Ch7, 7);
+
+impl_filter!(Flt0, 0);
+impl_filter!(Flt1, 1);
+impl_filter!(Flt2, 2);
+impl_filter!(Flt3, 3);
+
+trait SealedInstance {
+    fn regs() -> pac::dfsdm::Dfsdm;
+}
+
+/// DFSDM instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + crate::rcc::RccPeripheral + 'static + Send {}
+
+pin_trait!(CkoutPin, Instance);
+pin_trait!(DatinPin, Instance, ChannelInstance);
+pin_trait!(CkinPin, Instance, ChannelInstance);
+
+dma_trait!(Dma, Instance, FilterInstance);
+
+foreach_peripheral!(
+    (dfsdm, $inst:ident) => {
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> crate::pac::dfsdm::Dfsdm {
+                crate::pac::$inst
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+);
@@ -0,0 +1,98 @@
+//! Data cache maintenance for DMA buffers
+//!
+//! On cores with a data cache, memory written by the CPU must be cleaned before DMA reads it, and
+//! memory written by DMA must be invalidated before the CPU reads it. Maintenance works on whole
+//! 32-byte cache lines: lines only partially covered by a receive buffer are cleaned and
+//! invalidated rather than invalidated, so the data around the buffer is never discarded. That
+//! data must still not be written by the CPU while the transfer is running, or the write-back
+//! overwrites the received bytes sharing its line. Aligning receive buffers to 32 bytes, or
+//! placing them in a non-cacheable region, avoids this.
+//!
+//! These are no-ops on chips without a data cache, or when the cache is disabled.
+#![allow(unused)]
+
+#[cfg(any(stm32f7, stm32h7))]
+mod imp {
+    use cortex_m::peripheral::{CBP, SCB};
+
+    const LINE_SIZE: usize = 32;
+
+    fn for_each_line(addr: usize, len: usize, mut f: impl FnMut(u32)) {
+        // The Cortex-M4 core of dual-core H7 chips has no cache, and reads CCR.DC as zero.
+        if len == 0 || !SCB::dcache_enabled() {
+            return;
+        }
+
+        let end = addr + len;
+        let mut line = addr & !(LINE_SIZE - 1);
+        while line < end {
+            f(line as u32);
+            line += LINE_SIZE;
+        }
+
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+
+    pub(crate) fn clean(addr: usize, len: usize) {
+        for_each_line(addr, len, |line| unsafe { (*CBP::PTR).dccmvac.write(line) });
+    }
+
+    pub(crate) fn invalidate(addr: usize, len: usize) {
+        for_each_line(addr, len, |line| unsafe { (*CBP::PTR).dcimvac.write(line) });
+    }
+
+    pub(crate) fn clean_invalidate(addr: usize, len: usize) {
+        for_each_line(addr, len, |line| unsafe { (*CBP::PTR).dccimvac.write(line) });
+    }
+
+    /// Make a buffer written by DMA visible to the CPU.
+    ///
+    /// Lines may have been fetched again by speculative reads while the transfer was running.
+    pub(crate) fn finish_read(addr: usize, len: usize) {
+        let end = addr + len;
+        let head = (addr + LINE_SIZE - 1) & !(LINE_SIZE - 1);
+        let tail = end & !(LINE_SIZE - 1);
+        if head >= tail {
+            clean_invalidate(addr, len);
+            return;
+        }
+
+        // Lines only partially covered by the buffer are shared with other data, which must not
+        // be discarded.
+        if addr < head {
+            clean_invalidate(addr, head - addr);
+        }
+        invalidate(head, tail - head);
+        if tail < end {
+            clean_invalidate(tail, end - tail);
+        }
+    }
+}
+
+#[cfg(not(any(stm32f7, stm32h7)))]
+mod imp {
+    pub(crate) fn clean(_addr: usize, _len: usize) {}
+
+    pub(crate) fn invalidate(_addr: usize, _len: usize) {}
+
+    pub(crate) fn clean_invalidate(_addr: usize, _len: usize) {}
+
+    pub(crate) fn finish_read(_addr: usize, _len: usize) {}
+}
+
+pub(crate) use imp::*;
+
+/// Prepare a buffer about to be read by DMA.
+pub(crate) fn prepare_write<W>(buf: *const [W]) {
+    let (ptr, len) = super::slice_ptr_parts(buf);
+    clean(ptr, len * core::mem::size_of::<W>());
+}
+
+/// Prepare a buffer about to be written by DMA.
+///
+/// Dirty lines are written back first, so data sharing a cache line with the buffer is not lost.
+pub(crate) fn prepare_read<W>(buf: *mut [W]) {
+    let (ptr, len) = super::slice_ptr_parts_mut(buf);
+    clean_invalidate(ptr, len * core::mem::size_of::<W>());
+}
@@ -8,7 +8,7 @@ use embassy_sync::waitqueue::AtomicWaker;
 
 use super::ringbuffer::{DmaCtrl, OverrunError, ReadableDmaRingBuffer, WritableDmaRingBuffer};
 use super::word::{Word, WordSize};
-use super::{AnyChannel, Channel, Dir, Request, STATE};
+use super::{AnyChannel, Channel, Dir, Priority, Request, STATE};
 use crate::interrupt::typelevel::Interrupt;
 use crate::{interrupt, pac};
 
@@ -77,20 +77,6 @@ impl Default for TransferOptions {
     }
 }
 
-/// DMA request priority
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub enum Priority {
-    /// Low Priority
-    Low,
-    /// Medium Priority
-    Medium,
-    /// High Priority
-    High,
-    /// Very High Priority
-    VeryHigh,
-}
-
 #[cfg(dma)]
 impl From<Priority> for pac::dma::vals::Pl {
     fn from(value: Priority) -> Self {
@@ -541,6 +527,9 @@ impl AnyChannel {
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct Transfer<'a> {
     channel: PeripheralRef<'a, AnyChannel>,
+    /// Buffer written by DMA, as address and length in bytes, to invalidate from the data cache
+    /// once the transfer is done.
+    invalidate: Option<(usize, usize)>,
 }
 
 impl<'a> Transfer<'a> {
@@ -568,7 +557,9 @@ impl<'a> Transfer<'a> {
         let (ptr, len) = super::slice_ptr_parts_mut(buf);
         assert!(len > 0 && len <= 0xFFFF);
 
-        Self::new_inner(
+        super::cache::prepare_read(buf);
+
+        let mut this = Self::new_inner(
             channel.map_into(),
             request,
             Dir::PeripheralToMemory,
@@ -578,7 +569,9 @@ impl<'a> Transfer<'a> {
             true,
             W::size(),
             options,
-        )
+        );
+        this.invalidate = Some((ptr, len * W::size().bytes()));
+        this
     }
 
     /// Create a new write DMA transfer (memory to peripheral).
@@ -605,6 +598,8 @@ impl<'a> Transfer<'a> {
         let (ptr, len) = super::slice_ptr_parts(buf);
         assert!(len > 0 && len <= 0xFFFF);
 
+        super::cache::prepare_write(buf);
+
         Self::new_inner(
             channel.map_into(),
             request,
@@ -629,6 +624,8 @@ impl<'a> Transfer<'a> {
     ) -> Self {
         into_ref!(channel);
 
+        super::cache::prepare_write(core::slice::from_ref(repeated));
+
         Self::new_inner(
             channel.map_into(),
             request,
@@ -658,7 +655,10 @@ impl<'a> Transfer<'a> {
         );
         channel.start();
 
-        Self { channel }
+        Self {
+            channel,
+            invalidate: None,
+        }
     }
 
     /// Request the transfer to stop.
@@ -689,8 +689,15 @@ impl<'a> Transfer<'a> {
         // "Subsequent reads and writes cannot be moved ahead of preceding reads."
         fence(Ordering::SeqCst);
 
+        self.finish();
         core::mem::forget(self);
     }
+
+    fn finish(&mut self) {
+        if let Some((addr, len)) = self.invalidate.take() {
+            super::cache::finish_read(addr, len);
+        }
+    }
 }
 
 impl<'a> Drop for Transfer<'a> {
@@ -700,6 +707,8 @@ impl<'a> Drop for Transfer<'a> {
 
         // "Subsequent reads and writes cannot be moved ahead of preceding reads."
         fence(Ordering::SeqCst);
+
+        self.finish();
     }
 }
 
@@ -714,6 +723,8 @@ impl<'a> Future for Transfer<'a> {
         if self.is_running() {
             Poll::Pending
         } else {
+            fence(Ordering::SeqCst);
+            self.finish();
             Poll::Ready(())
         }
     }
@@ -767,6 +778,8 @@ impl<'a, W: Word> ReadableRingBuffer<'a, W> {
         into_ref!(channel);
         let channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
 
+        super::cache::prepare_read(&mut *buffer);
+
         let buffer_ptr = buffer.as_mut_ptr();
         let len = buffer.len();
         let dir = Dir::PeripheralToMemory;
@@ -903,6 +916,8 @@ impl<'a, W: Word> WritableRingBuffer<'a, W> {
         into_ref!(channel);
         let channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
 
+        super::cache::prepare_write(&*buffer);
+
         let len = buffer.len();
         let dir = Dir::MemoryToPeripheral;
         let data_size = W::size();
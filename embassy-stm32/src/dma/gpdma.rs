@@ -9,9 +9,8 @@ use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 
 use super::word::{Word, WordSize};
-use super::{AnyChannel, Channel, Dir, Request, STATE};
+use super::{AnyChannel, Channel, Dir, Priority, Request, STATE};
 use crate::interrupt::typelevel::Interrupt;
-use crate::interrupt::Priority;
 use crate::pac;
 use crate::pac::gpdma::vals;
 
@@ -24,11 +23,86 @@ pub(crate) struct ChannelInfo {
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
-pub struct TransferOptions {}
+pub struct TransferOptions {
+    /// Request priority level
+    pub priority: Priority,
+    /// Hardware trigger gating the transfer, see [`ChainedTransfer`].
+    pub trigger: Option<Trigger>,
+}
 
 impl Default for TransferOptions {
     fn default() -> Self {
-        Self {}
+        Self {
+            priority: Priority::Low,
+            trigger: None,
+        }
+    }
+}
+
+/// GPDMA trigger.
+///
+/// With a trigger, the channel still waits for its request, but also waits for the trigger
+/// event before transferring, as set by the [`TriggerMode`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Trigger {
+    /// Trigger input, `TRIGSEL` in the "GPDMA trigger selection" table of the reference manual.
+    ///
+    /// The inputs include EXTI lines, timers, and the `gpdmaX_chY_tc` transfer complete events
+    /// of the other channels.
+    pub source: u8,
+    /// Edge of the trigger input that fires the trigger.
+    pub polarity: TriggerPolarity,
+    /// What a trigger event lets the channel transfer.
+    pub mode: TriggerMode,
+}
+
+/// GPDMA trigger polarity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerPolarity {
+    /// Trigger on the rising edge.
+    Rising,
+    /// Trigger on the falling edge.
+    Falling,
+}
+
+/// GPDMA trigger mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerMode {
+    /// A trigger event lets the channel transfer a whole block.
+    Block,
+    /// A trigger event lets the channel transfer a single burst.
+    Burst,
+}
+
+impl From<TriggerPolarity> for vals::ChTr2Trigpol {
+    fn from(value: TriggerPolarity) -> Self {
+        match value {
+            TriggerPolarity::Rising => vals::ChTr2Trigpol::from_bits(1),
+            TriggerPolarity::Falling => vals::ChTr2Trigpol::from_bits(2),
+        }
+    }
+}
+
+impl From<TriggerMode> for vals::ChTr2Trigm {
+    fn from(value: TriggerMode) -> Self {
+        match value {
+            TriggerMode::Block => vals::ChTr2Trigm::from_bits(0),
+            TriggerMode::Burst => vals::ChTr2Trigm::from_bits(3),
+        }
+    }
+}
+
+impl From<Priority> for vals::Prio {
+    fn from(value: Priority) -> Self {
+        match value {
+            Priority::Low => vals::Prio::from_bits(0),
+            Priority::Medium => vals::Prio::from_bits(1),
+            Priority::High => vals::Prio::from_bits(2),
+            Priority::VeryHigh => vals::Prio::from_bits(3),
+        }
     }
 }
 
@@ -53,7 +127,7 @@ impl ChannelState {
 }
 
 /// safety: must be called only once
-pub(crate) unsafe fn init(cs: critical_section::CriticalSection, irq_priority: Priority) {
+pub(crate) unsafe fn init(cs: critical_section::CriticalSection, irq_priority: crate::interrupt::Priority) {
     foreach_interrupt! {
         ($peri:ident, gpdma, $block:ident, $signal_name:ident, $irq:ident) => {
             crate::interrupt::typelevel::$irq::set_priority_with_cs(cs, irq_priority);
@@ -101,6 +175,9 @@ impl AnyChannel {
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct Transfer<'a> {
     channel: PeripheralRef<'a, AnyChannel>,
+    /// Buffer written by DMA, as address and length in bytes, to invalidate from the data cache
+    /// once the transfer is done.
+    invalidate: Option<(usize, usize)>,
 }
 
 impl<'a> Transfer<'a> {
@@ -128,7 +205,9 @@ impl<'a> Transfer<'a> {
         let (ptr, len) = super::slice_ptr_parts_mut(buf);
         assert!(len > 0 && len <= 0xFFFF);
 
-        Self::new_inner(
+        super::cache::prepare_read(buf);
+
+        let mut this = Self::new_inner(
             channel.map_into(),
             request,
             Dir::PeripheralToMemory,
@@ -138,7 +217,9 @@ impl<'a> Transfer<'a> {
             true,
             W::size(),
             options,
-        )
+        );
+        this.invalidate = Some((ptr, len * W::size().bytes()));
+        this
     }
 
     /// Create a new write DMA transfer (memory to peripheral).
@@ -165,6 +246,8 @@ impl<'a> Transfer<'a> {
         let (ptr, len) = super::slice_ptr_parts(buf);
         assert!(len > 0 && len <= 0xFFFF);
 
+        super::cache::prepare_write(buf);
+
         Self::new_inner(
             channel.map_into(),
             request,
@@ -189,6 +272,8 @@ impl<'a> Transfer<'a> {
     ) -> Self {
         into_ref!(channel);
 
+        super::cache::prepare_write(core::slice::from_ref(repeated));
+
         Self::new_inner(
             channel.map_into(),
             request,
@@ -211,7 +296,7 @@ impl<'a> Transfer<'a> {
         mem_len: usize,
         incr_mem: bool,
         data_size: WordSize,
-        _options: TransferOptions,
+        options: TransferOptions,
     ) -> Self {
         let info = channel.info();
         let ch = info.dma.ch(info.num);
@@ -219,7 +304,10 @@ impl<'a> Transfer<'a> {
         // "Preceding reads and writes cannot be moved past subsequent writes."
         fence(Ordering::SeqCst);
 
-        let this = Self { channel };
+        let this = Self {
+            channel,
+            invalidate: None,
+        };
 
         #[cfg(dmamux)]
         super::dmamux::configure_dmamux(&*this.channel, request);
@@ -239,6 +327,11 @@ impl<'a> Transfer<'a> {
                 Dir::PeripheralToMemory => vals::ChTr2Dreq::SOURCEPERIPHERAL,
             });
             w.set_reqsel(request);
+            if let Some(trigger) = options.trigger {
+                w.set_trigsel(trigger.source);
+                w.set_trigpol(trigger.polarity.into());
+                w.set_trigm(trigger.mode.into());
+            }
         });
         ch.br1().write(|w| {
             // BNDT is specified as bytes, not as number of transfers.
@@ -257,6 +350,8 @@ impl<'a> Transfer<'a> {
         }
 
         ch.cr().write(|w| {
+            w.set_prio(options.priority.into());
+
             // Enable interrupts
             w.set_tcie(true);
             w.set_useie(true);
@@ -308,8 +403,20 @@ impl<'a> Transfer<'a> {
         // "Subsequent reads and writes cannot be moved ahead of preceding reads."
         fence(Ordering::SeqCst);
 
+        self.finish();
         core::mem::forget(self);
     }
+
+    fn finish(&mut self) {
+        if let Some((addr, len)) = self.invalidate.take() {
+            super::cache::finish_read(addr, len);
+        }
+    }
+
+    fn has_trigger(&self) -> bool {
+        let info = self.channel.info();
+        info.dma.ch(info.num).tr2().read().trigpol().to_bits() != 0
+    }
 }
 
 impl<'a> Drop for Transfer<'a> {
@@ -319,6 +426,8 @@ impl<'a> Drop for Transfer<'a> {
 
         // "Subsequent reads and writes cannot be moved ahead of preceding reads."
         fence(Ordering::SeqCst);
+
+        self.finish();
     }
 }
 
@@ -332,7 +441,77 @@ impl<'a> Future for Transfer<'a> {
         if self.is_running() {
             Poll::Pending
         } else {
+            fence(Ordering::SeqCst);
+            self.finish();
             Poll::Ready(())
         }
     }
 }
+
+/// Two DMA transfers on different channels, the second one started in hardware by the
+/// completion of the first one.
+///
+/// This runs sequences like a command followed by data on SPI without the CPU in between.
+/// Create the second transfer first, with a [`Trigger`] on the `gpdmaX_chY_tc` event of the
+/// first channel in its [`TransferOptions`], so it's armed and waits for the trigger. Then
+/// create the first transfer, which starts right away.
+///
+/// Dropping the chained transfer stops both transfers.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ChainedTransfer<'a> {
+    first: Transfer<'a>,
+    second: Transfer<'a>,
+}
+
+impl<'a> ChainedTransfer<'a> {
+    /// Chain two transfers.
+    ///
+    /// Panics if `second` has no trigger.
+    pub fn new(first: Transfer<'a>, second: Transfer<'a>) -> Self {
+        assert!(
+            second.has_trigger(),
+            "the second transfer of a chain must have a trigger"
+        );
+        Self { first, second }
+    }
+
+    /// Request both transfers to stop.
+    ///
+    /// This doesn't immediately stop the transfers, you have to wait until [`is_running`](Self::is_running) returns false.
+    pub fn request_stop(&mut self) {
+        self.first.request_stop();
+        self.second.request_stop();
+    }
+
+    /// Return whether either transfer is still running, or the second one is still waiting for its trigger.
+    pub fn is_running(&mut self) -> bool {
+        self.first.is_running() || self.second.is_running()
+    }
+
+    /// Blocking wait until both transfers finish.
+    pub fn blocking_wait(self) {
+        self.first.blocking_wait();
+        self.second.blocking_wait();
+    }
+
+    /// Split into the first and second transfer, e.g. to check their remaining transfers.
+    pub fn split(self) -> (Transfer<'a>, Transfer<'a>) {
+        (self.first, self.second)
+    }
+}
+
+impl<'a> Unpin for ChainedTransfer<'a> {}
+impl<'a> Future for ChainedTransfer<'a> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // The first transfer completes before the second one, poll it so its cache maintenance is done.
+        let first = Pin::new(&mut this.first).poll(cx);
+        let second = Pin::new(&mut this.second).poll(cx);
+        if first.is_ready() && second.is_ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -19,6 +19,7 @@ pub use dmamux::*;
 mod util;
 pub(crate) use util::*;
 
+pub(crate) mod cache;
 pub(crate) mod ringbuffer;
 pub mod word;
 
@@ -35,6 +36,35 @@ enum Dir {
     PeripheralToMemory,
 }
 
+/// DMA request priority, used to arbitrate between channels requesting at the same time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// Low Priority
+    Low,
+    /// Medium Priority
+    Medium,
+    /// High Priority
+    High,
+    /// Very High Priority
+    ///
+    /// On GPDMA, this is the high priority class, meant for latency-critical channels. The other
+    /// levels are low priority, arbitrated with increasing weights.
+    VeryHigh,
+}
+
+impl Default for Priority {
+    /// The priority used by the transfers of the drivers: very high on DMA and BDMA, and low on
+    /// GPDMA, where very high is a separate class.
+    fn default() -> Self {
+        if cfg!(gpdma) {
+            Priority::Low
+        } else {
+            Priority::VeryHigh
+        }
+    }
+}
+
 /// DMA request type alias. (also known as DMA channel number in some chips)
 #[cfg(any(dma_v2, bdma_v2, gpdma, dmamux))]
 pub type Request = u8;
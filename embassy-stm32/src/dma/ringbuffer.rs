@@ -229,6 +229,11 @@ impl<'a, W: Word> ReadableDmaRingBuffer<'a, W> {
         unsafe {
             let dma_buf = self.dma_buf.as_ptr();
 
+            super::cache::finish_read(
+                dma_buf.add(data_range.start) as usize,
+                length * core::mem::size_of::<W>(),
+            );
+
             for i in 0..length {
                 buf[i] = core::ptr::read_volatile(dma_buf.offset((data_range.start + i) as isize));
             }
@@ -373,6 +378,11 @@ impl<'a, W: Word> WritableDmaRingBuffer<'a, W> {
             for i in 0..length {
                 core::ptr::write_volatile(dma_buf.offset((data_range.start + i) as isize), buf[i]);
             }
+
+            super::cache::clean(
+                dma_buf.add(data_range.start) as usize,
+                length * core::mem::size_of::<W>(),
+            );
         }
 
         length
@@ -0,0 +1,594 @@
+//! DSI Host (DSIHOST)
+//!
+//! Drives MIPI-DSI displays from the pixels streamed by the LTDC, either continuously (video mode)
+//! or on request (adapted command mode), and sends generic and DCS packets to configure the
+//! display.
+#![macro_use]
+
+use core::marker::PhantomData;
+
+use embassy_hal_internal::PeripheralRef;
+
+use crate::gpio::{AFType, AnyPin, SealedPin as _, Speed};
+use crate::pac::dsihost::regs::Gpdr;
+use crate::{pac, peripherals, Peripheral};
+
+/// Number of polls before giving up on a FIFO, PLL or read operation.
+const TIMEOUT_POLLS: u32 = 1_000_000;
+
+/// DSI Host error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The regulator or the PLL didn't become ready in time.
+    NotReady,
+    /// The command or payload FIFOs didn't drain in time.
+    FifoTimeout,
+    /// The display didn't answer a read in time.
+    ReadTimeout,
+    /// The LTDC didn't finish a refresh in time.
+    RefreshTimeout,
+}
+
+/// Number of data lanes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Lanes {
+    /// Data lane 0 only.
+    One,
+    /// Data lanes 0 and 1.
+    Two,
+}
+
+/// PLL output divider.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PllOutputDiv {
+    /// Divide by 1.
+    Div1,
+    /// Divide by 2.
+    Div2,
+    /// Divide by 4.
+    Div4,
+    /// Divide by 8.
+    Div8,
+}
+
+/// DSI PLL configuration.
+///
+/// The lane bit rate is `HSE * ndiv / (idf * 2^odf)`, and the lane byte clock an eighth of it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PllConfig {
+    /// Loop division factor, in `10..=125`.
+    pub ndiv: u8,
+    /// Input division factor, in `1..=7`.
+    pub idf: u8,
+    /// Output division factor.
+    pub odf: PllOutputDiv,
+}
+
+/// Color coding of the pixels sent by the LTDC.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ColorCoding {
+    /// 16-bit RGB565, packed.
+    Rgb565,
+    /// 18-bit RGB666, packed.
+    Rgb666Packed,
+    /// 18-bit RGB666, each component in its own byte.
+    Rgb666Loose,
+    /// 24-bit RGB888.
+    Rgb888,
+}
+
+impl ColorCoding {
+    fn colc(self) -> u8 {
+        match self {
+            ColorCoding::Rgb565 => 0b000,
+            ColorCoding::Rgb666Packed => 0b011,
+            ColorCoding::Rgb666Loose => 0b100,
+            ColorCoding::Rgb888 => 0b101,
+        }
+    }
+}
+
+/// DSI Host configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// DSI PLL configuration.
+    pub pll: PllConfig,
+    /// Number of data lanes.
+    pub lanes: Lanes,
+    /// Unit interval (bit period) in quarters of a nanosecond, e.g. 8 for 500 Mbit/s lanes.
+    pub unit_interval_x4: u8,
+    /// Lane byte clock division giving the TX escape clock, at most 20 MHz.
+    pub tx_escape_clock_div: u8,
+    /// Virtual channel of the display.
+    pub virtual_channel: u8,
+    /// Color coding of the pixels sent by the LTDC.
+    pub color_coding: ColorCoding,
+    /// Stop the clock lane between high-speed transmissions.
+    pub automatic_clock_lane_control: bool,
+    /// Minimum wait period, in lane byte clock cycles, before a high-speed transmission request
+    /// after the stop state.
+    pub stop_wait_time: u8,
+    /// Clock lane transition time from low-power to high-speed, in lane byte clock cycles.
+    pub clock_lp_to_hs_time: u16,
+    /// Clock lane transition time from high-speed to low-power, in lane byte clock cycles.
+    pub clock_hs_to_lp_time: u16,
+    /// Data lanes transition time from low-power to high-speed, in lane byte clock cycles.
+    pub data_lp_to_hs_time: u8,
+    /// Data lanes transition time from high-speed to low-power, in lane byte clock cycles.
+    pub data_hs_to_lp_time: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // 500 Mbit/s on two lanes from a 25 MHz HSE.
+        Self {
+            pll: PllConfig {
+                ndiv: 100,
+                idf: 5,
+                odf: PllOutputDiv::Div1,
+            },
+            lanes: Lanes::Two,
+            unit_interval_x4: 8,
+            tx_escape_clock_div: 4,
+            virtual_channel: 0,
+            color_coding: ColorCoding::Rgb888,
+            automatic_clock_lane_control: false,
+            stop_wait_time: 10,
+            clock_lp_to_hs_time: 35,
+            clock_hs_to_lp_time: 35,
+            data_lp_to_hs_time: 35,
+            data_hs_to_lp_time: 35,
+        }
+    }
+}
+
+/// Video mode transmission type.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VideoMode {
+    /// Non-burst transmission with sync pulses.
+    NonBurstSyncPulses,
+    /// Non-burst transmission with sync events.
+    NonBurstSyncEvents,
+    /// Burst transmission.
+    Burst,
+}
+
+/// Active level of a control signal.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Polarity {
+    /// Active high.
+    ActiveHigh,
+    /// Active low.
+    ActiveLow,
+}
+
+/// Video mode configuration.
+///
+/// The horizontal timings are in lane byte clock cycles, the vertical ones in lines. They must
+/// match the timings the LTDC is configured with.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VideoConfig {
+    /// Transmission type.
+    pub mode: VideoMode,
+    /// Number of pixels in a single video packet; the whole line in burst mode.
+    pub packet_size: u16,
+    /// Number of chunks per line, 0 for no chunks.
+    pub chunks: u16,
+    /// Size of the null packets inserted between chunks, 0 for none.
+    pub null_packet_size: u16,
+    /// Polarity of the LTDC horizontal sync.
+    pub hsync_polarity: Polarity,
+    /// Polarity of the LTDC vertical sync.
+    pub vsync_polarity: Polarity,
+    /// Polarity of the LTDC data enable.
+    pub data_enable_polarity: Polarity,
+    /// Horizontal sync active duration.
+    pub horizontal_sync_active: u16,
+    /// Horizontal back porch duration.
+    pub horizontal_back_porch: u16,
+    /// Total horizontal line duration.
+    pub horizontal_line: u16,
+    /// Vertical sync active duration.
+    pub vertical_sync_active: u16,
+    /// Vertical back porch duration.
+    pub vertical_back_porch: u16,
+    /// Vertical front porch duration.
+    pub vertical_front_porch: u16,
+    /// Vertical active duration.
+    pub vertical_active: u16,
+    /// Send commands in low-power mode during the blanking periods.
+    pub low_power_commands: bool,
+    /// Largest low-power command size, in bytes, fitting the vertical sync, back porch and front
+    /// porch lines.
+    pub low_power_largest_packet_size: u8,
+    /// Largest low-power command size, in bytes, fitting the vertical active lines.
+    pub low_power_vact_largest_packet_size: u8,
+}
+
+/// Source of the tearing effect signal in adapted command mode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TearingEffectSource {
+    /// Tearing effect reported by the display on the DSI link.
+    Link,
+    /// Tearing effect signal on the TE pin.
+    ExternalPin,
+}
+
+/// Adapted command mode configuration.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommandConfig {
+    /// Largest number of pixels in a memory write command.
+    pub command_size: u16,
+    /// Source of the tearing effect signal.
+    pub tearing_effect_source: TearingEffectSource,
+    /// Polarity of the tearing effect signal on the TE pin.
+    pub tearing_effect_polarity: Polarity,
+    /// Polarity of the LTDC vertical sync stopping the refresh.
+    pub vsync_polarity: Polarity,
+    /// Refresh the display automatically on each tearing effect, instead of on [`DsiHost::refresh`].
+    pub automatic_refresh: bool,
+    /// Request a tearing effect acknowledge from the display.
+    pub tearing_effect_acknowledge: bool,
+}
+
+/// Data type of a DSI packet.
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum DataType {
+    GenericShortWrite0 = 0x03,
+    GenericShortWrite1 = 0x13,
+    GenericShortWrite2 = 0x23,
+    GenericRead0 = 0x04,
+    GenericRead1 = 0x14,
+    GenericRead2 = 0x24,
+    DcsShortWrite0 = 0x05,
+    DcsShortWrite1 = 0x15,
+    DcsRead = 0x06,
+    SetMaximumReturnPacketSize = 0x37,
+    GenericLongWrite = 0x29,
+    DcsLongWrite = 0x39,
+}
+
+/// DSI Host driver.
+pub struct DsiHost<'d, T: Instance> {
+    _peri: PhantomData<&'d mut T>,
+    te: Option<PeripheralRef<'d, AnyPin>>,
+    virtual_channel: u8,
+}
+
+impl<'d, T: Instance> DsiHost<'d, T> {
+    /// Create a new DSI Host driver, taking the tearing effect from the DSI link.
+    ///
+    /// This starts the regulator, the PLL and the D-PHY. Configure the transmission with
+    /// [`configure_video_mode`](Self::configure_video_mode) or
+    /// [`configure_command_mode`](Self::configure_command_mode), then call
+    /// [`start`](Self::start).
+    pub fn new(peri: impl Peripheral<P = T> + 'd, config: Config) -> Result<Self, Error> {
+        Self::new_inner(peri, None, config)
+    }
+
+    /// Create a new DSI Host driver, with a TE pin.
+    pub fn new_with_te(
+        peri: impl Peripheral<P = T> + 'd,
+        te: impl Peripheral<P = impl TePin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        Self::new_inner(peri, new_pin!(te, AFType::Input, Speed::VeryHigh), config)
+    }
+
+    fn new_inner(
+        _peri: impl Peripheral<P = T> + 'd,
+        te: Option<PeripheralRef<'d, AnyPin>>,
+        config: Config,
+    ) -> Result<Self, Error> {
+        assert!((10..=125).contains(&config.pll.ndiv));
+        assert!((1..=7).contains(&config.pll.idf));
+        assert!(config.virtual_channel < 4);
+
+        T::enable_and_reset();
+
+        let regs = T::regs();
+
+        regs.wrpcr().modify(|w| w.set_regen(true));
+        poll(|| regs.wisr().read().rrs()).map_err(|_| Error::NotReady)?;
+
+        regs.wrpcr().modify(|w| {
+            w.set_ndiv(config.pll.ndiv);
+            w.set_idf(config.pll.idf);
+            w.set_odf(config.pll.odf as u8);
+        });
+        regs.wrpcr().modify(|w| w.set_pllen(true));
+        poll(|| regs.wisr().read().pllls()).map_err(|_| Error::NotReady)?;
+
+        regs.pctlr().modify(|w| {
+            w.set_cke(true);
+            w.set_den(true);
+        });
+        regs.clcr().modify(|w| {
+            w.set_dpcc(true);
+            w.set_acr(config.automatic_clock_lane_control);
+        });
+        regs.pconfr().modify(|w| {
+            w.set_nl(match config.lanes {
+                Lanes::One => 0,
+                Lanes::Two => 1,
+            });
+            w.set_sw_time(config.stop_wait_time);
+        });
+        regs.ccr().modify(|w| w.set_txeckdiv(config.tx_escape_clock_div));
+        regs.wpcr0().modify(|w| w.set_uix4(config.unit_interval_x4));
+
+        regs.cltcr().write(|w| {
+            w.set_lp2hs_time(config.clock_lp_to_hs_time);
+            w.set_hs2lp_time(config.clock_hs_to_lp_time);
+        });
+        regs.dltcr().modify(|w| {
+            w.set_lp2hs_time(config.data_lp_to_hs_time);
+            w.set_hs2lp_time(config.data_hs_to_lp_time);
+        });
+
+        regs.lvcidr().write(|w| w.set_vcid(config.virtual_channel));
+        regs.gvcidr().write(|w| w.set_vcid(config.virtual_channel));
+        regs.lcolcr().modify(|w| w.set_colc(config.color_coding.colc()));
+        regs.wcfgr().modify(|w| w.set_colmux(config.color_coding.colc()));
+
+        regs.ier0().write(|_| {});
+        regs.ier1().write(|_| {});
+
+        Ok(Self {
+            _peri: PhantomData,
+            te,
+            virtual_channel: config.virtual_channel,
+        })
+    }
+
+    /// Configure the transmission in video mode: the LTDC pixels are streamed continuously.
+    pub fn configure_video_mode(&mut self, config: &VideoConfig) {
+        let regs = T::regs();
+
+        regs.mcr().modify(|w| w.set_cmdm(false));
+        regs.wcfgr().modify(|w| w.set_dsim(false));
+
+        regs.vmcr().modify(|w| {
+            w.set_vmt(match config.mode {
+                VideoMode::NonBurstSyncPulses => 0b00,
+                VideoMode::NonBurstSyncEvents => 0b01,
+                VideoMode::Burst => 0b10,
+            });
+            // Return to low-power during all the blanking periods.
+            w.set_lpvsae(true);
+            w.set_lpvbpe(true);
+            w.set_lpvfpe(true);
+            w.set_lpvae(true);
+            w.set_lphbpe(true);
+            w.set_lphfpe(true);
+            w.set_lpce(config.low_power_commands);
+        });
+        regs.vpcr().write(|w| w.set_vpsize(config.packet_size));
+        regs.vccr().write(|w| w.set_numc(config.chunks));
+        regs.vnpcr().write(|w| w.set_npsize(config.null_packet_size));
+
+        regs.lpcr().write(|w| {
+            w.set_hsp(config.hsync_polarity == Polarity::ActiveLow);
+            w.set_vsp(config.vsync_polarity == Polarity::ActiveLow);
+            w.set_dep(config.data_enable_polarity == Polarity::ActiveLow);
+        });
+
+        regs.vhsacr().write(|w| w.set_hsa(config.horizontal_sync_active));
+        regs.vhbpcr().write(|w| w.set_hbp(config.horizontal_back_porch));
+        regs.vlcr().write(|w| w.set_hline(config.horizontal_line));
+        regs.vvsacr().write(|w| w.set_vsa(config.vertical_sync_active));
+        regs.vvbpcr().write(|w| w.set_vbp(config.vertical_back_porch));
+        regs.vvfpcr().write(|w| w.set_vfp(config.vertical_front_porch));
+        regs.vvacr().write(|w| w.set_va(config.vertical_active));
+
+        regs.lpmcr().write(|w| {
+            w.set_lpsize(config.low_power_largest_packet_size);
+            w.set_vlpsize(config.low_power_vact_largest_packet_size);
+        });
+    }
+
+    /// Configure the transmission in adapted command mode: the LTDC pixels are sent with memory
+    /// write commands on each [`refresh`](Self::refresh), or on each tearing effect.
+    pub fn configure_command_mode(&mut self, config: &CommandConfig) {
+        let regs = T::regs();
+
+        regs.mcr().modify(|w| w.set_cmdm(true));
+        regs.wcfgr().modify(|w| {
+            w.set_dsim(true);
+            w.set_tesrc(config.tearing_effect_source == TearingEffectSource::ExternalPin);
+            w.set_tepol(config.tearing_effect_polarity == Polarity::ActiveLow);
+            w.set_ar(config.automatic_refresh);
+            w.set_vspol(config.vsync_polarity == Polarity::ActiveHigh);
+        });
+        regs.lccr().write(|w| w.set_cmdsize(config.command_size));
+        regs.cmcr().modify(|w| w.set_teare(config.tearing_effect_acknowledge));
+    }
+
+    /// Send the commands in low-power mode, e.g. while configuring the display.
+    pub fn set_low_power_commands(&mut self, low_power: bool) {
+        T::regs().cmcr().modify(|w| {
+            w.set_gsw0tx(low_power);
+            w.set_gsw1tx(low_power);
+            w.set_gsw2tx(low_power);
+            w.set_gsr0tx(low_power);
+            w.set_gsr1tx(low_power);
+            w.set_gsr2tx(low_power);
+            w.set_glwtx(low_power);
+            w.set_dsw0tx(low_power);
+            w.set_dsw1tx(low_power);
+            w.set_dsr0tx(low_power);
+            w.set_dlwtx(low_power);
+            w.set_mrdps(low_power);
+        });
+    }
+
+    /// Start the DSI Host and its wrapper.
+    ///
+    /// In video mode, the LTDC pixels are streamed from now on.
+    pub fn start(&mut self) {
+        T::regs().cr().modify(|w| w.set_en(true));
+        T::regs().wcr().modify(|w| w.set_dsien(true));
+    }
+
+    /// Stop the DSI Host and its wrapper.
+    pub fn stop(&mut self) {
+        T::regs().wcr().modify(|w| w.set_dsien(false));
+        T::regs().cr().modify(|w| w.set_en(false));
+    }
+
+    /// Send a frame from the LTDC to the display in adapted command mode, blocking until done.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let regs = T::regs();
+        regs.wcr().modify(|w| w.set_ltdcen(true));
+        poll(|| !regs.wisr().read().busy()).map_err(|_| Error::RefreshTimeout)
+    }
+
+    /// Send a DCS command with its parameters.
+    pub fn write_dcs(&mut self, command: u8, params: &[u8]) -> Result<(), Error> {
+        match params {
+            [] => self.short_write(DataType::DcsShortWrite0, command, 0),
+            [param] => self.short_write(DataType::DcsShortWrite1, command, *param),
+            _ => self.long_write(DataType::DcsLongWrite, Some(command), params),
+        }
+    }
+
+    /// Send a generic write packet.
+    pub fn write_generic(&mut self, data: &[u8]) -> Result<(), Error> {
+        match data {
+            [] => self.short_write(DataType::GenericShortWrite0, 0, 0),
+            [p0] => self.short_write(DataType::GenericShortWrite1, *p0, 0),
+            [p0, p1] => self.short_write(DataType::GenericShortWrite2, *p0, *p1),
+            _ => self.long_write(DataType::GenericLongWrite, None, data),
+        }
+    }
+
+    /// Send a DCS read command, and read its answer into `buf`.
+    pub fn read_dcs(&mut self, command: u8, buf: &mut [u8]) -> Result<(), Error> {
+        self.read(DataType::DcsRead, command, 0, buf)
+    }
+
+    /// Send a generic read packet with up to two parameters, and read its answer into `buf`.
+    pub fn read_generic(&mut self, params: &[u8], buf: &mut [u8]) -> Result<(), Error> {
+        match params {
+            [] => self.read(DataType::GenericRead0, 0, 0, buf),
+            [p0] => self.read(DataType::GenericRead1, *p0, 0, buf),
+            [p0, p1] => self.read(DataType::GenericRead2, *p0, *p1, buf),
+            _ => panic!("generic reads have at most two parameters"),
+        }
+    }
+
+    fn read(&mut self, data_type: DataType, p0: u8, p1: u8, buf: &mut [u8]) -> Result<(), Error> {
+        assert!(buf.len() <= u16::MAX as usize);
+
+        if buf.len() > 2 {
+            let len = (buf.len() as u16).to_le_bytes();
+            self.short_write(DataType::SetMaximumReturnPacketSize, len[0], len[1])?;
+        }
+        self.short_write(data_type, p0, p1)?;
+
+        let regs = T::regs();
+        for chunk in buf.chunks_mut(4) {
+            poll(|| !regs.gpsr().read().prdfe()).map_err(|_| Error::ReadTimeout)?;
+            let word = regs.gpdr().read().0.to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn short_write(&mut self, data_type: DataType, p0: u8, p1: u8) -> Result<(), Error> {
+        let regs = T::regs();
+        poll(|| !regs.gpsr().read().cmdff()).map_err(|_| Error::FifoTimeout)?;
+        self.write_header(data_type, p0, p1);
+        Ok(())
+    }
+
+    fn long_write(&mut self, data_type: DataType, command: Option<u8>, data: &[u8]) -> Result<(), Error> {
+        let len = data.len() + command.is_some() as usize;
+        assert!(len <= u16::MAX as usize);
+
+        let regs = T::regs();
+        poll(|| regs.gpsr().read().cmdfe()).map_err(|_| Error::FifoTimeout)?;
+
+        let mut bytes = command.iter().chain(data.iter()).copied();
+        for _ in 0..len.div_ceil(4) {
+            let mut word = [0; 4];
+            for (byte, value) in word.iter_mut().zip(&mut bytes) {
+                *byte = value;
+            }
+            poll(|| !regs.gpsr().read().pwrff()).map_err(|_| Error::FifoTimeout)?;
+            regs.gpdr().write_value(Gpdr(u32::from_le_bytes(word)));
+        }
+
+        let len = (len as u16).to_le_bytes();
+        self.write_header(data_type, len[0], len[1]);
+        Ok(())
+    }
+
+    fn write_header(&mut self, data_type: DataType, wclsb: u8, wcmsb: u8) {
+        T::regs().ghcr().write(|w| {
+            w.set_dt(data_type as u8);
+            w.set_vcid(self.virtual_channel);
+            w.set_wclsb(wclsb);
+            w.set_wcmsb(wcmsb);
+        });
+    }
+}
+
+impl<'d, T: Instance> Drop for DsiHost<'d, T> {
+    fn drop(&mut self) {
+        self.stop();
+        let regs = T::regs();
+        regs.pctlr().modify(|w| {
+            w.set_cke(false);
+            w.set_den(false);
+        });
+        regs.wrpcr().modify(|w| w.set_pllen(false));
+        regs.wrpcr().modify(|w| w.set_regen(false));
+        self.te.as_ref().map(|x| x.set_as_disconnected());
+
+        T::disable();
+    }
+}
+
+fn poll(mut ready: impl FnMut() -> bool) -> Result<(), ()> {
+    for _ in 0..TIMEOUT_POLLS {
+        if ready() {
+            return Ok(());
+        }
+    }
+    Err(())
+}
+
+trait SealedInstance {
+    fn regs() -> pac::dsihost::Dsihost;
+}
+
+/// DSI Host instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + crate::rcc::RccPeripheral + 'static + Send {}
+
+pin_trait!(TePin, Instance);
+
+foreach_peripheral!(
+    (dsihost, $inst:ident) => {
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> crate::pac::dsihost::Dsihost {
+                crate::pac::$inst
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+);
@@ -32,6 +32,9 @@ pub(crate) struct Packet<const N: usize>([u8; N]);
 /// queue. A bigger queue allows the hardware to receive more packets while the
 /// CPU is busy doing other things, which may increase performance (especially for RX)
 /// at the cost of more RAM usage.
+///
+/// On chips with a data cache, packet buffers are cleaned and invalidated by the driver, but the
+/// descriptors share cache lines and must be placed in non-cacheable memory.
 pub struct PacketQueue<const TX: usize, const RX: usize> {
     tx_desc: [TDes; TX],
     rx_desc: [RDes; RX],
@@ -73,6 +76,56 @@ impl<const TX: usize, const RX: usize> PacketQueue<TX, RX> {
 
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+/// Reference counts of the bins of the 64-bin multicast hash filter of the MAC.
+///
+/// Several addresses can share a bin, so a bin stays enabled until all of them are removed.
+pub(crate) struct MulticastFilter {
+    refs: [u8; 64],
+}
+
+impl MulticastFilter {
+    pub(crate) const fn new() -> Self {
+        Self { refs: [0; 64] }
+    }
+
+    /// Bin of `addr`: the upper 6 bits of the bit-reversed CRC-32 of the address.
+    fn bin(addr: &[u8; 6]) -> usize {
+        let mut crc = 0xFFFF_FFFFu32;
+        for &byte in addr {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        ((!crc).reverse_bits() >> 26) as usize
+    }
+
+    /// Add `addr`, and return the new hash table.
+    pub(crate) fn add(&mut self, addr: &[u8; 6]) -> u64 {
+        let bin = Self::bin(addr);
+        self.refs[bin] = self.refs[bin].saturating_add(1);
+        self.table()
+    }
+
+    /// Remove `addr`, and return the new hash table.
+    pub(crate) fn remove(&mut self, addr: &[u8; 6]) -> u64 {
+        let bin = Self::bin(addr);
+        self.refs[bin] = self.refs[bin].saturating_sub(1);
+        self.table()
+    }
+
+    fn table(&self) -> u64 {
+        self.refs
+            .iter()
+            .enumerate()
+            .fold(0, |table, (bin, &refs)| table | ((refs > 0) as u64) << bin)
+    }
+}
+
 impl<'d, T: Instance, P: PHY> embassy_net_driver::Driver for Ethernet<'d, T, P> {
     type RxToken<'a> = RxToken<'a, 'd> where Self: 'a;
     type TxToken<'a> = TxToken<'a, 'd> where Self: 'a;
@@ -113,6 +166,20 @@ impl<'d, T: Instance, P: PHY> embassy_net_driver::Driver for Ethernet<'d, T, P>
     fn hardware_address(&self) -> HardwareAddress {
         HardwareAddress::Ethernet(self.mac_addr)
     }
+
+    fn add_multicast_address(&mut self, addr: HardwareAddress) {
+        if let HardwareAddress::Ethernet(addr) = addr {
+            let table = self.multicast.add(&addr);
+            self.set_multicast_hash_table(table);
+        }
+    }
+
+    fn remove_multicast_address(&mut self, addr: HardwareAddress) {
+        if let HardwareAddress::Ethernet(addr) = addr {
+            let table = self.multicast.remove(&addr);
+            self.set_multicast_hash_table(table);
+        }
+    }
 }
 
 /// `embassy-net` RX token.
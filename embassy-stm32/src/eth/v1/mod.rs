@@ -53,6 +53,7 @@ pub struct Ethernet<'d, T: Instance, P: PHY> {
     pub(crate) phy: P,
     pub(crate) station_management: EthernetStationManagement<T>,
     pub(crate) mac_addr: [u8; 6],
+    pub(crate) multicast: MulticastFilter,
 }
 
 #[cfg(eth_v1a)]
@@ -176,6 +177,9 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
             )
         });
 
+        // Filter multicast frames with the hash table, initially empty, see `add_multicast_address`.
+        mac.macffr().modify(|w| w.set_hm(true));
+
         // pause time
         mac.macfcr().modify(|w| w.set_pt(0x100));
 
@@ -228,6 +232,7 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
                 clock_range: clock_range,
             },
             mac_addr,
+            multicast: MulticastFilter::new(),
             tx: TDesRing::new(&mut queue.tx_desc, &mut queue.tx_buf),
             rx: RDesRing::new(&mut queue.rx_desc, &mut queue.rx_buf),
         };
@@ -264,6 +269,12 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
 
         this
     }
+
+    pub(crate) fn set_multicast_hash_table(&mut self, table: u64) {
+        let mac = T::regs().ethernet_mac();
+        mac.machthr().write(|w| w.set_hth((table >> 32) as u32));
+        mac.machtlr().write(|w| w.set_htl(table as u32));
+    }
 }
 
 /// Ethernet station management interface.
@@ -211,7 +211,9 @@ impl<'a> RDesRing<'a> {
 
         let descriptor = &mut self.descriptors[self.index];
         let len = descriptor.packet_len();
-        return Some(&mut self.buffers[self.index].0[..len]);
+        let buffer = &mut self.buffers[self.index].0[..len];
+        crate::dma::cache::finish_read(buffer.as_ptr() as usize, len);
+        return Some(buffer);
     }
 
     /// Pop the packet previously returned by `available`.
@@ -219,6 +221,7 @@ impl<'a> RDesRing<'a> {
         let descriptor = &mut self.descriptors[self.index];
         assert!(descriptor.available());
 
+        crate::dma::cache::prepare_read(&mut self.buffers[self.index].0[..]);
         self.descriptors[self.index].set_ready(self.buffers[self.index].0.as_mut_ptr());
 
         self.demand_poll();
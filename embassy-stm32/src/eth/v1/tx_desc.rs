@@ -150,6 +150,8 @@ impl<'a> TDesRing<'a> {
         let descriptor = &mut self.descriptors[self.index];
         assert!(descriptor.available());
 
+        crate::dma::cache::clean(self.buffers[self.index].0.as_ptr() as usize, len);
+
         descriptor.set_buffer1(self.buffers[self.index].0.as_ptr());
         descriptor.set_buffer1_len(len);
 
@@ -105,6 +105,8 @@ impl<'a> TDesRing<'a> {
         assert!(td.available());
         assert!(len as u32 <= EMAC_TDES2_B1L);
 
+        crate::dma::cache::clean(self.buffers[self.index].0.as_ptr() as usize, len);
+
         // Read format
         td.tdes0.set(self.buffers[self.index].0.as_ptr() as u32);
         td.tdes2.set(len as u32 & EMAC_TDES2_B1L | EMAC_TDES2_IOC);
@@ -230,7 +232,9 @@ impl<'a> RDesRing<'a> {
 
         let descriptor = &mut self.descriptors[self.index];
         let len = (descriptor.rdes3.get() & EMAC_RDES3_PKTLEN) as usize;
-        return Some(&mut self.buffers[self.index].0[..len]);
+        let buffer = &mut self.buffers[self.index].0[..len];
+        crate::dma::cache::finish_read(buffer.as_ptr() as usize, len);
+        return Some(buffer);
     }
 
     /// Pop the packet previously returned by `available`.
@@ -238,6 +242,7 @@ impl<'a> RDesRing<'a> {
         let rd = &mut self.descriptors[self.index];
         assert!(rd.available());
 
+        crate::dma::cache::prepare_read(&mut self.buffers[self.index].0[..]);
         rd.set_ready(self.buffers[self.index].0.as_mut_ptr());
 
         // "Preceding reads and writes cannot be moved past subsequent writes."
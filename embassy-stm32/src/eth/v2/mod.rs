@@ -43,6 +43,7 @@ pub struct Ethernet<'d, T: Instance, P: PHY> {
     pub(crate) phy: P,
     pub(crate) station_management: EthernetStationManagement<T>,
     pub(crate) mac_addr: [u8; 6],
+    pub(crate) multicast: MulticastFilter,
 }
 
 /// Pins of ethernet driver.
@@ -235,6 +236,9 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
             )
         });
 
+        // Filter multicast frames with the hash table, initially empty, see `add_multicast_address`.
+        mac.macpfr().modify(|w| w.set_hmc(true));
+
         mac.macqtx_fcr().modify(|w| w.set_pt(0x100));
 
         // disable all MMC RX interrupts
@@ -291,6 +295,7 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
                 clock_range: clock_range,
             },
             mac_addr,
+            multicast: MulticastFilter::new(),
         };
 
         fence(Ordering::SeqCst);
@@ -323,6 +328,12 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
 
         this
     }
+
+    pub(crate) fn set_multicast_hash_table(&mut self, table: u64) {
+        let mac = T::regs().ethernet_mac();
+        mac.macht1r().write(|w| w.set_ht63t32((table >> 32) as u32));
+        mac.macht0r().write(|w| w.set_ht31t0(table as u32));
+    }
 }
 
 /// Ethernet SMI driver.
@@ -0,0 +1,322 @@
+//! Filter Math Accelerator (FMAC)
+//!
+//! The FMAC runs FIR and IIR filters on q1.15 samples out of its own 256-word local memory. Input
+//! samples are pushed into the circular X1 buffer and results are popped from the circular Y
+//! buffer, either by the CPU or by DMA. The filter state is kept between calls, so a signal can be
+//! streamed through [`Fmac::filter`] block by block.
+#![macro_use]
+
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+
+use crate::pac::fmac::vals;
+use crate::{dma, pac, peripherals, Peripheral};
+
+/// Size of the FMAC local memory, in 16-bit words.
+pub const MEMORY_SIZE: usize = 256;
+
+/// FMAC error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// An input sample was written while the X1 buffer was full.
+    Overflow,
+    /// A result was read while the Y buffer was empty.
+    Underflow,
+    /// The accumulator saturated while computing a result.
+    Saturation,
+}
+
+/// Filter to run.
+///
+/// Coefficients are in q1.15 format. For IIR filters, the feedback coefficients are applied with a
+/// positive sign, i.e. `y[n] = sum(b[k] * x[n - k]) + sum(a[k] * y[n - k])` where `a` starts at
+/// `a[1]`.
+#[derive(Clone, Copy)]
+pub enum Filter<'a> {
+    /// Finite impulse response filter, `2..=127` coefficients.
+    Fir {
+        /// Coefficients `b[0]..b[N-1]`.
+        coefficients: &'a [u16],
+    },
+    /// Infinite impulse response filter, `2..=64` feedforward and `1..=63` feedback coefficients.
+    Iir {
+        /// Feedforward coefficients `b[0]..b[P-1]`.
+        feedforward: &'a [u16],
+        /// Feedback coefficients `a[1]..a[Q]`.
+        feedback: &'a [u16],
+    },
+}
+
+/// FMAC configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Results are shifted left by `gain` bits, in `0..=7`.
+    pub gain: u8,
+    /// Clip results to the q1.15 range instead of wrapping around.
+    pub clipping: bool,
+    /// Extra space in the X1 buffer, so input can be written ahead of the filter.
+    pub input_headroom: u8,
+    /// Extra space in the Y buffer, so results can be read behind the filter.
+    pub output_headroom: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gain: 0,
+            clipping: true,
+            input_headroom: 4,
+            output_headroom: 4,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum Function {
+    LoadX1 = 1,
+    LoadX2 = 2,
+    LoadY = 3,
+    Convolution = 8,
+    IirDirectForm1 = 9,
+}
+
+/// FMAC driver.
+pub struct Fmac<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Fmac<'d, T> {
+    /// Create a new FMAC driver.
+    pub fn new(peri: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(peri);
+
+        T::enable_and_reset();
+
+        Self { _peri: peri }
+    }
+
+    /// Load a filter and start it.
+    ///
+    /// The filter history is cleared: the filter behaves as if all previous input samples and
+    /// results were zero. Call this again to recover after an [`Error`].
+    pub fn configure(&mut self, filter: Filter, config: &Config) {
+        assert!(config.gain <= 7);
+
+        let (feedforward, feedback, function) = match filter {
+            Filter::Fir { coefficients } => {
+                assert!((2..=127).contains(&coefficients.len()));
+                (coefficients, &[][..], Function::Convolution)
+            }
+            Filter::Iir { feedforward, feedback } => {
+                assert!((2..=64).contains(&feedforward.len()));
+                assert!((1..=63).contains(&feedback.len()));
+                (feedforward, feedback, Function::IirDirectForm1)
+            }
+        };
+
+        let x1_size = feedforward.len() + config.input_headroom as usize;
+        let x2_size = feedforward.len() + feedback.len();
+        let y_size = feedback.len() + (config.output_headroom as usize).max(1);
+        assert!(x1_size + x2_size + y_size <= MEMORY_SIZE);
+
+        self.stop();
+
+        let regs = T::regs();
+        regs.x1bufcfg().write(|w| {
+            w.set_x1_base(0);
+            w.set_x1_buf_size(x1_size as u8);
+        });
+        regs.x2bufcfg().write(|w| {
+            w.set_x2_base(x1_size as u8);
+            w.set_x2_buf_size(x2_size as u8);
+        });
+        regs.ybufcfg().write(|w| {
+            w.set_y_base((x1_size + x2_size) as u8);
+            w.set_y_buf_size(y_size as u8);
+        });
+
+        // X2 holds the feedforward coefficients followed by the feedback coefficients.
+        self.load(
+            Function::LoadX2,
+            feedforward.len() as u8,
+            feedback.len() as u8,
+            feedforward.iter().chain(feedback).copied(),
+        );
+
+        // Zero the history, so the first input sample produces the first result.
+        let history = feedforward.len() as u8 - 1;
+        self.load(
+            Function::LoadX1,
+            history,
+            0,
+            core::iter::repeat(0).take(history as usize),
+        );
+        if !feedback.is_empty() {
+            let history = feedback.len() as u8;
+            self.load(
+                Function::LoadY,
+                history,
+                0,
+                core::iter::repeat(0).take(history as usize),
+            );
+        }
+
+        regs.cr().write(|w| w.set_clipen(config.clipping));
+        regs.param().write(|w| {
+            w.set_p(feedforward.len() as u8);
+            w.set_q(feedback.len() as u8);
+            w.set_r(config.gain);
+            w.set_func(vals::Func::from_bits(function as u8));
+            w.set_start(true);
+        });
+    }
+
+    /// Stop the filter and reset its buffers.
+    pub fn stop(&mut self) {
+        let regs = T::regs();
+        regs.param().modify(|w| w.set_start(false));
+        regs.cr().modify(|w| w.set_reset(true));
+        while regs.cr().read().reset() {}
+    }
+
+    fn load(&mut self, function: Function, p: u8, q: u8, values: impl Iterator<Item = u16>) {
+        if p == 0 {
+            return;
+        }
+
+        let regs = T::regs();
+        regs.param().write(|w| {
+            w.set_p(p);
+            w.set_q(q);
+            w.set_func(vals::Func::from_bits(function as u8));
+            w.set_start(true);
+        });
+        for value in values {
+            regs.wdata().write(|w| w.set_wdata(value));
+        }
+        // START is cleared by hardware once all values have been loaded.
+        while regs.param().read().start() {}
+    }
+
+    /// Run `input` through the filter, blocking.
+    ///
+    /// Each input sample produces one result, so `output` must have the same length as `input`.
+    pub fn blocking_filter(&mut self, input: &[u16], output: &mut [u16]) -> Result<(), Error> {
+        assert_eq!(input.len(), output.len());
+
+        let regs = T::regs();
+        for (x, y) in input.iter().zip(output.iter_mut()) {
+            while regs.sr().read().x1full() {}
+            regs.wdata().write(|w| w.set_wdata(*x));
+            while regs.sr().read().yempty() {}
+            *y = regs.rdata().read().rdata();
+        }
+
+        Self::check_error()
+    }
+
+    /// Run `input` through the filter, using DMA to feed input samples and fetch results.
+    ///
+    /// Each input sample produces one result, so `output` must have the same length as `input`.
+    /// The filter state is kept between calls, so a continuous signal can be processed block by
+    /// block.
+    pub async fn filter(
+        &mut self,
+        write_dma: impl Peripheral<P = impl WriteDma<T>>,
+        read_dma: impl Peripheral<P = impl ReadDma<T>>,
+        input: &[u16],
+        output: &mut [u16],
+    ) -> Result<(), Error> {
+        assert_eq!(input.len(), output.len());
+
+        if input.is_empty() {
+            return Ok(());
+        }
+
+        into_ref!(write_dma, read_dma);
+
+        let regs = T::regs();
+        let write_req = write_dma.request();
+        let read_req = read_dma.request();
+
+        regs.cr().modify(|w| {
+            w.set_dmawen(true);
+            w.set_dmaren(true);
+        });
+
+        let _on_drop = OnDrop::new(|| {
+            T::regs().cr().modify(|w| {
+                w.set_dmawen(false);
+                w.set_dmaren(false);
+            });
+        });
+
+        unsafe {
+            let write_transfer = dma::Transfer::new_write(
+                &mut write_dma,
+                write_req,
+                input,
+                regs.wdata().as_ptr() as *mut u16,
+                Default::default(),
+            );
+
+            let read_transfer = dma::Transfer::new_read(
+                &mut read_dma,
+                read_req,
+                regs.rdata().as_ptr() as *mut u16,
+                output,
+                Default::default(),
+            );
+
+            embassy_futures::join::join(write_transfer, read_transfer).await;
+        }
+
+        Self::check_error()
+    }
+
+    fn check_error() -> Result<(), Error> {
+        let sr = T::regs().sr().read();
+        if sr.ovfl() {
+            Err(Error::Overflow)
+        } else if sr.unfl() {
+            Err(Error::Underflow)
+        } else if sr.sat() {
+            Err(Error::Saturation)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Fmac<'d, T> {
+    fn drop(&mut self) {
+        self.stop();
+        T::disable();
+    }
+}
+
+trait SealedInstance {
+    fn regs() -> pac::fmac::Fmac;
+}
+
+/// FMAC instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + crate::rcc::RccPeripheral + 'static + Send {}
+
+foreach_peripheral!(
+    (fmac, $inst:ident) => {
+        impl Instance for peripherals::$inst {}
+
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> crate::pac::fmac::Fmac {
+                crate::pac::$inst
+            }
+        }
+    };
+);
+
+dma_trait!(WriteDma, Instance);
+dma_trait!(ReadDma, Instance);
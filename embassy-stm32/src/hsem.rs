@@ -0,0 +1,377 @@
+//! Hardware Semaphore (HSEM)
+//!
+//! The HSEM provides 32 semaphores shared between the cores of dual-core chips. A semaphore is
+//! owned by a core and, within that core, by a process ID, so it can be used to arbitrate access to
+//! shared peripherals and RAM. [`HsemRawMutex`] plugs a semaphore into `embassy-sync` mutexes.
+#![macro_use]
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::typelevel::Interrupt;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+/// Number of semaphores.
+pub const SEMAPHORE_COUNT: usize = 32;
+
+const WAKER: AtomicWaker = AtomicWaker::new();
+static WAKERS: [AtomicWaker; SEMAPHORE_COUNT] = [WAKER; SEMAPHORE_COUNT];
+
+/// Core owning a semaphore.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum CoreId {
+    /// Cortex-M7 core.
+    #[cfg(stm32h7)]
+    Core0 = 0x3,
+    /// Cortex-M4 core.
+    #[cfg(stm32h7)]
+    Core1 = 0x1,
+    /// Cortex-M4 core.
+    #[cfg(not(stm32h7))]
+    Core0 = 0x4,
+    /// Cortex-M0+ core.
+    #[cfg(not(stm32h7))]
+    Core1 = 0x8,
+}
+
+impl CoreId {
+    /// Get the ID of the core running this code.
+    pub fn current() -> Self {
+        #[cfg(not(stm32h7))]
+        const CORTEX_M0P: u32 = 0xC60;
+        const CORTEX_M4: u32 = 0xC24;
+        #[cfg(stm32h7)]
+        const CORTEX_M7: u32 = 0xC27;
+
+        let partno = (unsafe { (*cortex_m::peripheral::CPUID::PTR).base.read() } >> 4) & 0xFFF;
+        match partno {
+            #[cfg(stm32h7)]
+            CORTEX_M7 => CoreId::Core0,
+            #[cfg(stm32h7)]
+            CORTEX_M4 => CoreId::Core1,
+            #[cfg(not(stm32h7))]
+            CORTEX_M4 => CoreId::Core0,
+            #[cfg(not(stm32h7))]
+            CORTEX_M0P => CoreId::Core1,
+            _ => panic!("unknown core"),
+        }
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        [CoreId::Core0, CoreId::Core1].into_iter().find(|c| *c as u8 == bits)
+    }
+
+    /// Index of the interrupt registers of this core.
+    fn index(self) -> usize {
+        match self {
+            CoreId::Core0 => 0,
+            CoreId::Core1 => 1,
+        }
+    }
+}
+
+/// Owner of a locked semaphore.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Owner {
+    /// Core holding the semaphore.
+    pub core: CoreId,
+    /// Process ID the semaphore was locked with, `0` for one-step locks.
+    pub process_id: u8,
+}
+
+/// HSEM interrupt handler.
+///
+/// Wakes tasks waiting in [`HardwareSemaphore::lock`] when a semaphore is released.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let regs = T::regs();
+        let core = CoreId::current().index();
+
+        let misr = regs.misr(core).read().0;
+        regs.ier(core).modify(|w| w.0 &= !misr);
+        regs.icr(core).write(|w| w.0 = misr);
+
+        for (n, waker) in WAKERS.iter().enumerate() {
+            if misr & (1 << n) != 0 {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// HSEM driver.
+pub struct HardwareSemaphore<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    core: CoreId,
+}
+
+impl<'d, T: Instance> HardwareSemaphore<'d, T> {
+    /// Create a new HSEM driver.
+    ///
+    /// The peripheral is not reset, so semaphores locked by the other core are kept.
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+    ) -> Self {
+        into_ref!(peri);
+
+        T::enable();
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Self {
+            _peri: peri,
+            core: CoreId::current(),
+        }
+    }
+
+    /// ID of the core this driver runs on.
+    pub fn core_id(&self) -> CoreId {
+        self.core
+    }
+
+    /// Try to lock semaphore `sem` for `process_id`, with the two-step procedure.
+    ///
+    /// Returns a guard that releases the semaphore when dropped, or `None` if the semaphore is
+    /// held by another core or process.
+    pub fn try_lock(&self, sem: usize, process_id: u8) -> Option<SemaphoreGuard<'_, 'd, T>> {
+        assert!(sem < SEMAPHORE_COUNT);
+
+        let regs = T::regs();
+        regs.r(sem).write(|w| {
+            w.set_procid(process_id);
+            w.set_coreid(self.core as u8);
+            w.set_lock(true);
+        });
+
+        let r = regs.r(sem).read();
+        if r.lock() && r.coreid() == self.core as u8 && r.procid() == process_id {
+            compiler_fence(Ordering::SeqCst);
+            Some(SemaphoreGuard {
+                hsem: self,
+                sem,
+                process_id,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Lock semaphore `sem` for `process_id`, waiting until it is released if it is held.
+    pub async fn lock(&self, sem: usize, process_id: u8) -> SemaphoreGuard<'_, 'd, T> {
+        let regs = T::regs();
+        let core = self.core.index();
+
+        poll_fn(|cx| {
+            WAKERS[sem].register(cx.waker());
+
+            if let Some(guard) = self.try_lock(sem, process_id) {
+                return Poll::Ready(guard);
+            }
+
+            // Ask for an interrupt on release, then check again in case it was released meanwhile.
+            critical_section::with(|_| regs.ier(core).modify(|w| w.0 |= 1 << sem));
+            match self.try_lock(sem, process_id) {
+                Some(guard) => {
+                    critical_section::with(|_| regs.ier(core).modify(|w| w.0 &= !(1 << sem)));
+                    regs.icr(core).write(|w| w.0 = 1 << sem);
+                    Poll::Ready(guard)
+                }
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Lock semaphore `sem` with the one-step procedure, using process ID `0`.
+    ///
+    /// Returns `false` if the semaphore is held by another core or process.
+    pub fn try_lock_one_step(&self, sem: usize) -> bool {
+        assert!(sem < SEMAPHORE_COUNT);
+
+        let r = T::regs().rlr(sem).read();
+        let locked = r.lock() && r.coreid() == self.core as u8 && r.procid() == 0;
+        compiler_fence(Ordering::SeqCst);
+        locked
+    }
+
+    /// Release semaphore `sem` held by this core with `process_id`.
+    ///
+    /// Prefer dropping the [`SemaphoreGuard`]; this is meant for semaphores locked with
+    /// [`try_lock_one_step`](Self::try_lock_one_step) or handed over with [`core::mem::forget`].
+    pub fn unlock(&self, sem: usize, process_id: u8) {
+        assert!(sem < SEMAPHORE_COUNT);
+
+        compiler_fence(Ordering::SeqCst);
+        T::regs().r(sem).write(|w| {
+            w.set_procid(process_id);
+            w.set_coreid(self.core as u8);
+            w.set_lock(false);
+        });
+    }
+
+    /// Release all semaphores held by `core`.
+    ///
+    /// `key` must match the key set with [`set_clear_key`](Self::set_clear_key).
+    pub fn unlock_all(&self, core: CoreId, key: u16) {
+        T::regs().cr().write(|w| {
+            w.set_key(key);
+            w.set_coreid(core as u8);
+        });
+    }
+
+    /// Set the key required by [`unlock_all`](Self::unlock_all).
+    pub fn set_clear_key(&self, key: u16) {
+        T::regs().keyr().write(|w| w.set_key(key));
+    }
+
+    /// Get the current owner of semaphore `sem`, or `None` if it is free.
+    pub fn owner(&self, sem: usize) -> Option<Owner> {
+        assert!(sem < SEMAPHORE_COUNT);
+
+        let r = T::regs().r(sem).read();
+        if !r.lock() {
+            return None;
+        }
+
+        Some(Owner {
+            core: unwrap!(CoreId::from_bits(r.coreid())),
+            process_id: r.procid(),
+        })
+    }
+
+    /// Check whether semaphore `sem` is locked, by any core.
+    pub fn is_locked(&self, sem: usize) -> bool {
+        self.owner(sem).is_some()
+    }
+
+    /// Signal the other core by locking and releasing semaphore `sem`.
+    ///
+    /// Cores listening for the release of `sem` get an interrupt. Returns `false` if the semaphore
+    /// is held, in which case no release happens.
+    pub fn notify(&self, sem: usize) -> bool {
+        self.try_lock(sem, 0).is_some()
+    }
+
+    /// Register `waker` to be woken on the next release of semaphore `sem`, by any core.
+    pub(crate) fn listen(&self, sem: usize, waker: &core::task::Waker) {
+        WAKERS[sem].register(waker);
+        let core = self.core.index();
+        critical_section::with(|_| T::regs().ier(core).modify(|w| w.0 |= 1 << sem));
+    }
+}
+
+impl<'d, T: Instance> Drop for HardwareSemaphore<'d, T> {
+    fn drop(&mut self) {
+        let core = self.core.index();
+        critical_section::with(|_| T::regs().ier(core).write(|w| w.0 = 0));
+        T::disable();
+    }
+}
+
+/// Guard for a locked semaphore, released on drop.
+pub struct SemaphoreGuard<'a, 'd, T: Instance> {
+    hsem: &'a HardwareSemaphore<'d, T>,
+    sem: usize,
+    process_id: u8,
+}
+
+impl<'a, 'd, T: Instance> SemaphoreGuard<'a, 'd, T> {
+    /// Index of the locked semaphore.
+    pub fn semaphore(&self) -> usize {
+        self.sem
+    }
+}
+
+impl<'a, 'd, T: Instance> Drop for SemaphoreGuard<'a, 'd, T> {
+    fn drop(&mut self) {
+        self.hsem.unlock(self.sem, self.process_id);
+    }
+}
+
+/// `embassy-sync` raw mutex backed by hardware semaphore `SEM`.
+///
+/// Locking disables interrupts on the current core, then spins on the semaphore with the one-step
+/// procedure, so a `Mutex<HsemRawMutex<N>, T>` placed in shared RAM can be used from both cores.
+/// The HSEM clock must have been enabled with [`HardwareSemaphore::new`] on each core using it.
+pub struct HsemRawMutex<const SEM: usize> {
+    _phantom: PhantomData<()>,
+}
+
+unsafe impl<const SEM: usize> Send for HsemRawMutex<SEM> {}
+unsafe impl<const SEM: usize> Sync for HsemRawMutex<SEM> {}
+
+impl<const SEM: usize> HsemRawMutex<SEM> {
+    /// Create a new `HsemRawMutex`.
+    pub const fn new() -> Self {
+        Self { _phantom: PhantomData }
+    }
+}
+
+unsafe impl<const SEM: usize> RawMutex for HsemRawMutex<SEM> {
+    const INIT: Self = Self::new();
+
+    fn lock<R>(&self, f: impl FnOnce() -> R) -> R {
+        let regs = pac::HSEM;
+        let core = CoreId::current() as u8;
+
+        critical_section::with(|_| {
+            loop {
+                let r = regs.rlr(SEM).read();
+                if r.lock() && r.coreid() == core && r.procid() == 0 {
+                    break;
+                }
+            }
+            compiler_fence(Ordering::SeqCst);
+
+            let ret = f();
+
+            compiler_fence(Ordering::SeqCst);
+            regs.r(SEM).write(|w| {
+                w.set_coreid(core);
+                w.set_lock(false);
+            });
+
+            ret
+        })
+    }
+}
+
+trait SealedInstance {
+    fn regs() -> pac::hsem::Hsem;
+}
+
+/// HSEM instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + crate::rcc::RccPeripheral + 'static + Send {
+    /// Interrupt for this HSEM instance, on the current core.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+foreach_interrupt!(
+    ($inst:ident, hsem, HSEM, GLOBAL, $irq:ident) => {
+        impl Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::typelevel::$irq;
+        }
+
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> crate::pac::hsem::Hsem {
+                crate::pac::$inst
+            }
+        }
+    };
+);
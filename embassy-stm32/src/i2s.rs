@@ -1,12 +1,32 @@
 //! Inter-IC Sound (I2S)
-use embassy_hal_internal::into_ref;
-
-use crate::gpio::{AFType, AnyPin, SealedPin};
-use crate::mode::Async;
-use crate::pac::spi::vals;
-use crate::spi::{Config as SpiConfig, *};
+use embassy_hal_internal::{into_ref, PeripheralRef};
+
+use crate::dma::{ringbuffer, word, ReadableRingBuffer, TransferOptions, WritableRingBuffer};
+use crate::gpio::{AFType, AnyPin, SealedPin as _};
+use crate::pac::spi::{vals, Spi as Regs};
+#[cfg(spi_v3)]
+use crate::spi::MisoPin;
+use crate::spi::{CkPin, Instance, MckPin, MosiPin, RxDma, TxDma, WsPin};
 use crate::time::Hertz;
-use crate::{Peripheral, PeripheralRef};
+use crate::Peripheral;
+
+/// I2S error
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// `write` called on an I2S in receive mode.
+    NotATransmitter,
+    /// `read` called on an I2S in transmit mode.
+    NotAReceiver,
+    /// Overrun
+    Overrun,
+}
+
+impl From<ringbuffer::OverrunError> for Error {
+    fn from(_: ringbuffer::OverrunError) -> Self {
+        Self::Overrun
+    }
+}
 
 /// I2S mode
 #[derive(Copy, Clone)]
@@ -19,11 +39,14 @@ pub enum Mode {
 
 /// I2S function
 #[derive(Copy, Clone)]
-pub enum Function {
+enum Function {
     /// Transmit audio data
     Transmit,
     /// Receive audio data
     Receive,
+    /// Transmit and receive audio data
+    #[cfg(spi_v3)]
+    FullDuplex,
 }
 
 /// I2C standard
@@ -42,7 +65,6 @@ pub enum Standard {
 }
 
 impl Standard {
-    #[cfg(any(spi_v1, spi_f1))]
     const fn i2sstd(&self) -> vals::I2sstd {
         match self {
             Standard::Philips => vals::I2sstd::PHILIPS,
@@ -53,7 +75,6 @@ impl Standard {
         }
     }
 
-    #[cfg(any(spi_v1, spi_f1))]
     const fn pcmsync(&self) -> vals::Pcmsync {
         match self {
             Standard::PcmLongSync => vals::Pcmsync::LONG,
@@ -76,7 +97,6 @@ pub enum Format {
 }
 
 impl Format {
-    #[cfg(any(spi_v1, spi_f1))]
     const fn datlen(&self) -> vals::Datlen {
         match self {
             Format::Data16Channel16 => vals::Datlen::BITS16,
@@ -86,7 +106,6 @@ impl Format {
         }
     }
 
-    #[cfg(any(spi_v1, spi_f1))]
     const fn chlen(&self) -> vals::Chlen {
         match self {
             Format::Data16Channel16 => vals::Chlen::BITS16,
@@ -114,12 +133,21 @@ impl ClockPolarity {
             ClockPolarity::IdleLow => vals::Ckpol::IDLELOW,
         }
     }
+
+    // The data is sampled on the rising edge of an idle low clock, and on the falling edge of an
+    // idle high one.
+    #[cfg(spi_v3)]
+    const fn ckpol(&self) -> vals::Ckpol {
+        match self {
+            ClockPolarity::IdleHigh => vals::Ckpol::SAMPLEONFALLING,
+            ClockPolarity::IdleLow => vals::Ckpol::SAMPLEONRISING,
+        }
+    }
 }
 
 /// [`I2S`] configuration.
 ///
 ///  - `MS`: `Master` or `Slave`
-///  - `TR`: `Transmit` or `Receive`
 ///  - `STD`: I2S standard, eg `Philips`
 ///  - `FMT`: Frame Format marker, eg `Data16Channel16`
 #[non_exhaustive]
@@ -127,8 +155,6 @@ impl ClockPolarity {
 pub struct Config {
     /// Mode
     pub mode: Mode,
-    /// Function (transmit, receive)
-    pub function: Function,
     /// Which I2S standard to use.
     pub standard: Standard,
     /// Data format.
@@ -143,7 +169,6 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             mode: Mode::Master,
-            function: Function::Transmit,
             standard: Standard::Philips,
             format: Format::Data16Channel16,
             clock_polarity: ClockPolarity::IdleLow,
@@ -152,45 +177,207 @@ impl Default for Config {
     }
 }
 
+fn tx_ptr<W: word::Word>(regs: Regs) -> *mut W {
+    #[cfg(any(spi_v1, spi_f1))]
+    let dr = regs.dr();
+    #[cfg(spi_v3)]
+    let dr = regs.txdr32();
+    dr.as_ptr() as *mut W
+}
+
+fn rx_ptr<W: word::Word>(regs: Regs) -> *mut W {
+    #[cfg(any(spi_v1, spi_f1))]
+    let dr = regs.dr();
+    #[cfg(spi_v3)]
+    let dr = regs.rxdr32();
+    dr.as_ptr() as *mut W
+}
+
+// return the type for (sd, clocks)
+fn get_af_types(mode: Mode, function: Function) -> (AFType, AFType) {
+    (
+        //sd is defined by the function
+        match function {
+            Function::Receive => AFType::Input,
+            _ => AFType::OutputPushPull,
+        },
+        //clocks (ck and ws) are defined by master/slave
+        match mode {
+            Mode::Master => AFType::OutputPushPull,
+            Mode::Slave => AFType::Input,
+        },
+    )
+}
+
+fn ring_buffer_options() -> TransferOptions {
+    TransferOptions {
+        half_transfer_ir: true,
+        //the ring buffers always use circular mode
+        ..Default::default()
+    }
+}
+
 /// I2S driver.
-pub struct I2S<'d, T: Instance> {
-    _peri: Spi<'d, T, Async>,
-    sd: Option<PeripheralRef<'d, AnyPin>>,
+///
+/// Audio data goes through DMA ring buffers: once [`start`](Self::start)ed, the peripheral
+/// transmits or receives continuously, and [`write`](Self::write) and [`read`](Self::read) push to
+/// and pop from the buffers in the background.
+pub struct I2S<'d, T: Instance, W: word::Word> {
+    _peri: PeripheralRef<'d, T>,
+    txsd: Option<PeripheralRef<'d, AnyPin>>,
+    rxsd: Option<PeripheralRef<'d, AnyPin>>,
     ws: Option<PeripheralRef<'d, AnyPin>>,
     ck: Option<PeripheralRef<'d, AnyPin>>,
     mck: Option<PeripheralRef<'d, AnyPin>>,
+    tx_ring_buffer: Option<WritableRingBuffer<'d, W>>,
+    rx_ring_buffer: Option<ReadableRingBuffer<'d, W>>,
 }
 
-impl<'d, T: Instance> I2S<'d, T> {
-    /// Note: Full-Duplex modes are not supported at this time
-    pub fn new(
+impl<'d, T: Instance, W: word::Word> I2S<'d, T, W> {
+    /// Create a transmitter driver.
+    pub fn new_txonly(
         peri: impl Peripheral<P = T> + 'd,
         sd: impl Peripheral<P = impl MosiPin<T>> + 'd,
         ws: impl Peripheral<P = impl WsPin<T>> + 'd,
         ck: impl Peripheral<P = impl CkPin<T>> + 'd,
         mck: impl Peripheral<P = impl MckPin<T>> + 'd,
         txdma: impl Peripheral<P = impl TxDma<T>> + 'd,
-        rxdma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        txdma_buf: &'d mut [W],
         freq: Hertz,
         config: Config,
     ) -> Self {
-        into_ref!(sd, ws, ck, mck);
+        into_ref!(sd, txdma);
+        let (sd_af_type, _) = get_af_types(config.mode, Function::Transmit);
+        sd.set_as_af(sd.af_num(), sd_af_type);
+        sd.set_speed(crate::gpio::Speed::VeryHigh);
+
+        let request = txdma.request();
+        let tx_ring_buffer =
+            unsafe { WritableRingBuffer::new(txdma, request, tx_ptr(T::REGS), txdma_buf, ring_buffer_options()) };
+
+        Self::new_inner(
+            peri,
+            Some(sd.map_into()),
+            None,
+            ws,
+            ck,
+            mck,
+            Some(tx_ring_buffer),
+            None,
+            freq,
+            config,
+            Function::Transmit,
+        )
+    }
 
-        sd.set_as_af(sd.af_num(), AFType::OutputPushPull);
+    /// Create a receiver driver.
+    pub fn new_rxonly(
+        peri: impl Peripheral<P = T> + 'd,
+        #[cfg(any(spi_v1, spi_f1))] sd: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        #[cfg(spi_v3)] sd: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        ws: impl Peripheral<P = impl WsPin<T>> + 'd,
+        ck: impl Peripheral<P = impl CkPin<T>> + 'd,
+        mck: impl Peripheral<P = impl MckPin<T>> + 'd,
+        rxdma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        rxdma_buf: &'d mut [W],
+        freq: Hertz,
+        config: Config,
+    ) -> Self {
+        into_ref!(sd, rxdma);
+        let (sd_af_type, _) = get_af_types(config.mode, Function::Receive);
+        sd.set_as_af(sd.af_num(), sd_af_type);
         sd.set_speed(crate::gpio::Speed::VeryHigh);
 
-        ws.set_as_af(ws.af_num(), AFType::OutputPushPull);
+        let request = rxdma.request();
+        let rx_ring_buffer =
+            unsafe { ReadableRingBuffer::new(rxdma, request, rx_ptr(T::REGS), rxdma_buf, ring_buffer_options()) };
+
+        Self::new_inner(
+            peri,
+            None,
+            Some(sd.map_into()),
+            ws,
+            ck,
+            mck,
+            None,
+            Some(rx_ring_buffer),
+            freq,
+            config,
+            Function::Receive,
+        )
+    }
+
+    /// Create a full duplex driver, transmitting on `txsd` and receiving on `rxsd`.
+    #[cfg(spi_v3)]
+    pub fn new_full_duplex(
+        peri: impl Peripheral<P = T> + 'd,
+        txsd: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        rxsd: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        ws: impl Peripheral<P = impl WsPin<T>> + 'd,
+        ck: impl Peripheral<P = impl CkPin<T>> + 'd,
+        mck: impl Peripheral<P = impl MckPin<T>> + 'd,
+        txdma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        txdma_buf: &'d mut [W],
+        rxdma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        rxdma_buf: &'d mut [W],
+        freq: Hertz,
+        config: Config,
+    ) -> Self {
+        into_ref!(txsd, rxsd, txdma, rxdma);
+        txsd.set_as_af(txsd.af_num(), AFType::OutputPushPull);
+        txsd.set_speed(crate::gpio::Speed::VeryHigh);
+        rxsd.set_as_af(rxsd.af_num(), AFType::Input);
+        rxsd.set_speed(crate::gpio::Speed::VeryHigh);
+
+        let request = txdma.request();
+        let tx_ring_buffer =
+            unsafe { WritableRingBuffer::new(txdma, request, tx_ptr(T::REGS), txdma_buf, ring_buffer_options()) };
+        let request = rxdma.request();
+        let rx_ring_buffer =
+            unsafe { ReadableRingBuffer::new(rxdma, request, rx_ptr(T::REGS), rxdma_buf, ring_buffer_options()) };
+
+        Self::new_inner(
+            peri,
+            Some(txsd.map_into()),
+            Some(rxsd.map_into()),
+            ws,
+            ck,
+            mck,
+            Some(tx_ring_buffer),
+            Some(rx_ring_buffer),
+            freq,
+            config,
+            Function::FullDuplex,
+        )
+    }
+
+    fn new_inner(
+        peri: impl Peripheral<P = T> + 'd,
+        txsd: Option<PeripheralRef<'d, AnyPin>>,
+        rxsd: Option<PeripheralRef<'d, AnyPin>>,
+        ws: impl Peripheral<P = impl WsPin<T>> + 'd,
+        ck: impl Peripheral<P = impl CkPin<T>> + 'd,
+        mck: impl Peripheral<P = impl MckPin<T>> + 'd,
+        tx_ring_buffer: Option<WritableRingBuffer<'d, W>>,
+        rx_ring_buffer: Option<ReadableRingBuffer<'d, W>>,
+        freq: Hertz,
+        config: Config,
+        function: Function,
+    ) -> Self {
+        into_ref!(peri, ws, ck, mck);
+
+        let (_, ck_af_type) = get_af_types(config.mode, function);
+        ws.set_as_af(ws.af_num(), ck_af_type);
         ws.set_speed(crate::gpio::Speed::VeryHigh);
 
-        ck.set_as_af(ck.af_num(), AFType::OutputPushPull);
+        ck.set_as_af(ck.af_num(), ck_af_type);
         ck.set_speed(crate::gpio::Speed::VeryHigh);
 
+        // The master clock is always an output.
         mck.set_as_af(mck.af_num(), AFType::OutputPushPull);
         mck.set_speed(crate::gpio::Speed::VeryHigh);
 
-        let mut spi_cfg = SpiConfig::default();
-        spi_cfg.frequency = freq;
-        let spi = Spi::new_internal(peri, txdma, rxdma, spi_cfg);
+        T::enable_and_reset();
 
         // TODO move i2s to the new mux infra.
         //#[cfg(all(rcc_f4, not(stm32f410)))]
@@ -199,16 +386,17 @@ impl<'d, T: Instance> I2S<'d, T> {
         let pclk = T::frequency();
 
         let (odd, div) = compute_baud_rate(pclk, freq, config.master_clock, config.format);
+        let regs = T::REGS;
 
         #[cfg(any(spi_v1, spi_f1))]
         {
-            use stm32_metapac::spi::vals::{I2scfg, Odd};
+            use vals::{I2scfg, Odd};
 
             // 1. Select the I2SDIV[7:0] bits in the SPI_I2SPR register to define the serial clock baud
             // rate to reach the proper audio sample frequency. The ODD bit in the SPI_I2SPR
             // register also has to be defined.
 
-            T::REGS.i2spr().modify(|w| {
+            regs.i2spr().modify(|w| {
                 w.set_i2sdiv(div);
                 w.set_odd(match odd {
                     true => Odd::ODD,
@@ -230,55 +418,195 @@ impl<'d, T: Instance> I2S<'d, T> {
             // Select also the I2S master mode and direction (Transmitter or Receiver) through the
             // I2SCFG[1:0] bits in the SPI_I2SCFGR register.
 
+            regs.i2scfgr().modify(|w| {
+                w.set_ckpol(config.clock_polarity.ckpol());
+
+                w.set_i2smod(true);
+                w.set_i2sstd(config.standard.i2sstd());
+                w.set_pcmsync(config.standard.pcmsync());
+
+                w.set_datlen(config.format.datlen());
+                w.set_chlen(config.format.chlen());
+
+                w.set_i2scfg(match (config.mode, function) {
+                    (Mode::Master, Function::Transmit) => I2scfg::MASTERTX,
+                    (Mode::Master, Function::Receive) => I2scfg::MASTERRX,
+                    (Mode::Slave, Function::Transmit) => I2scfg::SLAVETX,
+                    (Mode::Slave, Function::Receive) => I2scfg::SLAVERX,
+                });
+            });
+
             // 4. If needed, select all the potential interruption sources and the DMA capabilities by
             // writing the SPI_CR2 register.
 
-            // 5. The I2SE bit in SPI_I2SCFGR register must be set.
+            regs.cr2().modify(|w| {
+                w.set_txdmaen(tx_ring_buffer.is_some());
+                w.set_rxdmaen(rx_ring_buffer.is_some());
+            });
 
-            T::REGS.i2scfgr().modify(|w| {
-                w.set_ckpol(config.clock_polarity.ckpol());
+            // 5. The I2SE bit in SPI_I2SCFGR register must be set, this is done by `start`.
+        }
 
+        #[cfg(spi_v3)]
+        {
+            use vals::{I2scfg, Odd};
+
+            // The configuration can only be changed while the peripheral is disabled.
+            regs.cr1().modify(|w| w.set_spe(false));
+
+            regs.i2scfgr().modify(|w| {
                 w.set_i2smod(true);
+
+                w.set_i2sdiv(div);
+                w.set_odd(match odd {
+                    true => Odd::ODD,
+                    false => Odd::EVEN,
+                });
+                w.set_mckoe(config.master_clock);
+
+                w.set_ckpol(config.clock_polarity.ckpol());
                 w.set_i2sstd(config.standard.i2sstd());
                 w.set_pcmsync(config.standard.pcmsync());
 
                 w.set_datlen(config.format.datlen());
                 w.set_chlen(config.format.chlen());
 
-                w.set_i2scfg(match (config.mode, config.function) {
+                w.set_i2scfg(match (config.mode, function) {
                     (Mode::Master, Function::Transmit) => I2scfg::MASTERTX,
                     (Mode::Master, Function::Receive) => I2scfg::MASTERRX,
+                    (Mode::Master, Function::FullDuplex) => I2scfg::MASTERFULLDUPLEX,
                     (Mode::Slave, Function::Transmit) => I2scfg::SLAVETX,
                     (Mode::Slave, Function::Receive) => I2scfg::SLAVERX,
+                    (Mode::Slave, Function::FullDuplex) => I2scfg::SLAVEFULLDUPLEX,
                 });
+            });
 
-                w.set_i2se(true)
+            regs.cfg1().modify(|w| {
+                w.set_txdmaen(tx_ring_buffer.is_some());
+                w.set_rxdmaen(rx_ring_buffer.is_some());
             });
         }
 
         Self {
-            _peri: spi,
-            sd: Some(sd.map_into()),
+            _peri: peri,
+            txsd,
+            rxsd,
             ws: Some(ws.map_into()),
             ck: Some(ck.map_into()),
             mck: Some(mck.map_into()),
+            tx_ring_buffer,
+            rx_ring_buffer,
         }
     }
 
-    /// Write audio data.
-    pub async fn write<W: Word>(&mut self, data: &[W]) -> Result<(), Error> {
-        self._peri.write(data).await
+    /// Start the I2S driver.
+    ///
+    /// The transmit ring buffer should be filled with [`write_immediate`](Self::write_immediate)
+    /// before, so the transmission doesn't start with an underrun.
+    pub fn start(&mut self) {
+        if let Some(rb) = &mut self.tx_ring_buffer {
+            rb.start();
+        }
+        if let Some(rb) = &mut self.rx_ring_buffer {
+            rb.start();
+        }
+
+        #[cfg(any(spi_v1, spi_f1))]
+        T::REGS.i2scfgr().modify(|w| w.set_i2se(true));
+
+        #[cfg(spi_v3)]
+        {
+            T::REGS.cr1().modify(|w| w.set_spe(true));
+            T::REGS.cr1().modify(|w| w.set_cstart(true));
+        }
     }
 
-    /// Read audio data.
-    pub async fn read<W: Word>(&mut self, data: &mut [W]) -> Result<(), Error> {
-        self._peri.read(data).await
+    /// Stop the I2S driver.
+    ///
+    /// The data left in the ring buffers is discarded, it can be started again with
+    /// [`start`](Self::start).
+    pub fn stop(&mut self) {
+        #[cfg(any(spi_v1, spi_f1))]
+        {
+            // Let the last frame complete before disabling the peripheral.
+            if self.tx_ring_buffer.is_some() {
+                while !T::REGS.sr().read().txe() {}
+            }
+            while T::REGS.sr().read().bsy() {}
+            T::REGS.i2scfgr().modify(|w| w.set_i2se(false));
+        }
+
+        #[cfg(spi_v3)]
+        {
+            T::REGS.cr1().modify(|w| w.set_csusp(true));
+            while T::REGS.cr1().read().cstart() {}
+            T::REGS.cr1().modify(|w| w.set_spe(false));
+        }
+
+        if let Some(rb) = &mut self.tx_ring_buffer {
+            rb.request_stop();
+            while rb.is_running() {}
+            rb.clear();
+        }
+        if let Some(rb) = &mut self.rx_ring_buffer {
+            rb.request_stop();
+            while rb.is_running() {}
+            rb.clear();
+        }
+    }
+
+    /// Write data to the I2S ringbuffer.
+    ///
+    /// This appends the data to the buffer and returns immediately. The
+    /// data will be transmitted in the background.
+    ///
+    /// If there's no space in the buffer, this waits until there is.
+    pub async fn write(&mut self, data: &[W]) -> Result<(), Error> {
+        match &mut self.tx_ring_buffer {
+            Some(buffer) => {
+                buffer.write_exact(data).await?;
+                Ok(())
+            }
+            None => Err(Error::NotATransmitter),
+        }
+    }
+
+    /// Write data to the I2S ringbuffer without waiting, typically before starting the driver.
+    ///
+    /// Returns the number of words written, and the space left in the buffer.
+    pub fn write_immediate(&mut self, data: &[W]) -> Result<(usize, usize), Error> {
+        match &mut self.tx_ring_buffer {
+            Some(buffer) => Ok(buffer.write_immediate(data)?),
+            None => Err(Error::NotATransmitter),
+        }
+    }
+
+    /// Read data from the I2S ringbuffer.
+    ///
+    /// Once started, I2S is always receiving data in the background. This function pops
+    /// already-received data from the buffer.
+    ///
+    /// If there's less than `data.len()` data in the buffer, this waits until there is.
+    pub async fn read(&mut self, data: &mut [W]) -> Result<(), Error> {
+        match &mut self.rx_ring_buffer {
+            Some(buffer) => {
+                buffer.read_exact(data).await?;
+                Ok(())
+            }
+            None => Err(Error::NotAReceiver),
+        }
     }
 }
 
-impl<'d, T: Instance> Drop for I2S<'d, T> {
+impl<'d, T: Instance, W: word::Word> Drop for I2S<'d, T, W> {
     fn drop(&mut self) {
-        self.sd.as_ref().map(|x| x.set_as_disconnected());
+        #[cfg(any(spi_v1, spi_f1))]
+        T::REGS.i2scfgr().modify(|w| w.set_i2se(false));
+        #[cfg(spi_v3)]
+        T::REGS.cr1().modify(|w| w.set_spe(false));
+
+        self.txsd.as_ref().map(|x| x.set_as_disconnected());
+        self.rxsd.as_ref().map(|x| x.set_as_disconnected());
         self.ws.as_ref().map(|x| x.set_as_disconnected());
         self.ck.as_ref().map(|x| x.set_as_disconnected());
         self.mck.as_ref().map(|x| x.set_as_disconnected());
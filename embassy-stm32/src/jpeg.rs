@@ -0,0 +1,786 @@
+//! JPEG codec (JPEG)
+//!
+//! Baseline JPEG decoding, with header parsing, and encoding with the standard quantization and
+//! Huffman tables scaled to a quality factor.
+//!
+//! The codec works on MCU-ordered data: the decoder outputs, and the encoder expects, the 8x8
+//! blocks of each MCU in turn (all the Y blocks, then the Cb and Cr blocks), not RGB pixels.
+//!
+//! The async operations stream the data with DMA, on chips where the codec has DMA requests. On
+//! H7, whose codec is served by the MDMA, use the blocking operations.
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::ptr;
+use core::task::Poll;
+
+use embassy_futures::join::join;
+use embassy_futures::select::{select, Either};
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::dma::{NoDma, Priority, Transfer, TransferOptions};
+use crate::interrupt::typelevel::Interrupt;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+static JPEG_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Number of polls of an idle codec before giving up on a blocking operation.
+const IDLE_POLLS: u32 = 1_000_000;
+
+/// Largest DMA transfer, in words.
+const MAX_DMA_WORDS: usize = u16::MAX as usize;
+
+// Offsets of the codec memories from the register block.
+const QMEM0: usize = 0x50;
+const QMEM1: usize = 0x90;
+const HUFFENC_AC0: usize = 0x500;
+const HUFFENC_AC1: usize = 0x660;
+const HUFFENC_DC0: usize = 0x7C0;
+const HUFFENC_DC1: usize = 0x7E0;
+
+/// JPEG interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let sr = T::regs().sr().read();
+        if sr.eocf() || sr.hpdf() {
+            T::regs().cr().modify(|w| {
+                w.set_eocie(false);
+                w.set_hpdie(false);
+            });
+            JPEG_WAKER.wake();
+        }
+    }
+}
+
+/// JPEG error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The output buffer is too small for the result.
+    BufferTooSmall,
+    /// The input ended before the end of the image.
+    IncompleteInput,
+}
+
+/// Color space of an image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ColorSpace {
+    /// One luminance component.
+    Grayscale,
+    /// Luminance and two chrominance components.
+    YCbCr,
+    /// Four components, decoding only.
+    Cmyk,
+}
+
+/// Chrominance subsampling of a [`ColorSpace::YCbCr`] image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChromaSubsampling {
+    /// No subsampling: 8x8 pixel MCUs, with one block per component.
+    Yuv444,
+    /// Horizontal subsampling: 16x8 pixel MCUs, with two Y blocks.
+    Yuv422,
+    /// Horizontal and vertical subsampling: 16x16 pixel MCUs, with four Y blocks.
+    Yuv420,
+}
+
+impl ChromaSubsampling {
+    /// Horizontal and vertical sampling factors of the luminance.
+    fn factors(self) -> (u8, u8) {
+        match self {
+            ChromaSubsampling::Yuv444 => (1, 1),
+            ChromaSubsampling::Yuv422 => (2, 1),
+            ChromaSubsampling::Yuv420 => (2, 2),
+        }
+    }
+}
+
+/// Image properties, parsed from the header when decoding.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageInfo {
+    /// Width in pixels.
+    pub width: u16,
+    /// Height in pixels.
+    pub height: u16,
+    /// Color space.
+    pub color_space: ColorSpace,
+    /// Chrominance subsampling, [`ChromaSubsampling::Yuv444`] for other color spaces.
+    pub chroma_subsampling: ChromaSubsampling,
+}
+
+impl ImageInfo {
+    /// Number of MCUs in the image.
+    pub fn mcu_count(&self) -> usize {
+        let (h, v) = self.chroma_subsampling.factors();
+        let mcu_width = 8 * h as usize;
+        let mcu_height = 8 * v as usize;
+        (self.width as usize).div_ceil(mcu_width) * (self.height as usize).div_ceil(mcu_height)
+    }
+
+    /// Size of the MCU-ordered data of the image, in bytes.
+    pub fn mcu_data_len(&self) -> usize {
+        let (h, v) = self.chroma_subsampling.factors();
+        let blocks = match self.color_space {
+            ColorSpace::Grayscale => 1,
+            ColorSpace::YCbCr => (h * v) as usize + 2,
+            ColorSpace::Cmyk => 4,
+        };
+        self.mcu_count() * blocks * 64
+    }
+}
+
+/// Result of a decoding.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Decoded {
+    /// Properties of the decoded image.
+    pub info: ImageInfo,
+    /// Number of bytes of MCU-ordered data written to the output.
+    pub len: usize,
+}
+
+/// Encoding configuration.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncodeConfig {
+    /// Width in pixels.
+    pub width: u16,
+    /// Height in pixels.
+    pub height: u16,
+    /// Color space, [`ColorSpace::Grayscale`] or [`ColorSpace::YCbCr`].
+    pub color_space: ColorSpace,
+    /// Chrominance subsampling, ignored for [`ColorSpace::Grayscale`].
+    pub chroma_subsampling: ChromaSubsampling,
+    /// Quality factor, in `1..=100`.
+    pub quality: u8,
+}
+
+impl EncodeConfig {
+    fn info(&self) -> ImageInfo {
+        ImageInfo {
+            width: self.width,
+            height: self.height,
+            color_space: self.color_space,
+            chroma_subsampling: match self.color_space {
+                ColorSpace::YCbCr => self.chroma_subsampling,
+                _ => ChromaSubsampling::Yuv444,
+            },
+        }
+    }
+}
+
+/// JPEG driver.
+pub struct Jpeg<'d, T: Instance, DmaIn = NoDma, DmaOut = NoDma> {
+    _peri: PeripheralRef<'d, T>,
+    #[allow(dead_code)]
+    indma: PeripheralRef<'d, DmaIn>,
+    #[allow(dead_code)]
+    outdma: PeripheralRef<'d, DmaOut>,
+}
+
+impl<'d, T: Instance, DmaIn, DmaOut> Jpeg<'d, T, DmaIn, DmaOut> {
+    /// Create a new JPEG driver.
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        indma: impl Peripheral<P = DmaIn> + 'd,
+        outdma: impl Peripheral<P = DmaOut> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+    ) -> Self {
+        T::enable_and_reset();
+        into_ref!(peri, indma, outdma);
+
+        T::regs().cr().write(|w| w.set_jcen(true));
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Self {
+            _peri: peri,
+            indma,
+            outdma,
+        }
+    }
+
+    /// Decode a JPEG image into MCU-ordered data, blocking.
+    pub fn blocking_decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<Decoded, Error> {
+        self.start_decode();
+        let len = self.blocking_process(input, output)?;
+        Ok(Decoded {
+            info: Self::image_info(),
+            len,
+        })
+    }
+
+    /// Encode MCU-ordered data into a JPEG image, blocking.
+    ///
+    /// Returns the size of the JPEG image written to `output`.
+    pub fn blocking_encode(&mut self, config: &EncodeConfig, input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        assert_eq!(input.len(), config.info().mcu_data_len());
+        let header_len = write_header(config, output)?;
+        self.start_encode(config);
+        let len = self.blocking_process(input, &mut output[header_len..])?;
+        finish_image(output, header_len + len)
+    }
+
+    /// Decode a JPEG image into MCU-ordered data.
+    ///
+    /// `input` and `output` must be word-aligned.
+    pub async fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<Decoded, Error>
+    where
+        DmaIn: crate::jpeg::DmaIn<T>,
+        DmaOut: crate::jpeg::DmaOut<T>,
+    {
+        self.start_decode();
+        let len = self.process(input, output).await?;
+        Ok(Decoded {
+            info: Self::image_info(),
+            len,
+        })
+    }
+
+    /// Encode MCU-ordered data into a JPEG image.
+    ///
+    /// `input` and `output` must be word-aligned. Returns the size of the JPEG image written to
+    /// `output`.
+    pub async fn encode(&mut self, config: &EncodeConfig, input: &[u8], output: &mut [u8]) -> Result<usize, Error>
+    where
+        DmaIn: crate::jpeg::DmaIn<T>,
+        DmaOut: crate::jpeg::DmaOut<T>,
+    {
+        assert_eq!(input.len(), config.info().mcu_data_len());
+        let header_len = write_header(config, output)?;
+        self.start_encode(config);
+        let len = self.process(input, &mut output[header_len..]).await?;
+        finish_image(output, header_len + len)
+    }
+
+    fn start_decode(&mut self) {
+        let regs = T::regs();
+        regs.confr1().write(|w| {
+            w.set_de(true);
+            w.set_hdr(true);
+        });
+        Self::start();
+    }
+
+    fn start_encode(&mut self, config: &EncodeConfig) {
+        assert!((1..=100).contains(&config.quality));
+        let info = config.info();
+        let regs = T::regs();
+
+        write_quantization_table::<T>(QMEM0, &LUMA_QUANTIZATION, config.quality);
+        write_quantization_table::<T>(QMEM1, &CHROMA_QUANTIZATION, config.quality);
+        write_huffman_dc_table::<T>(HUFFENC_DC0, &LUMA_DC_BITS, &LUMA_DC_VALUES);
+        write_huffman_dc_table::<T>(HUFFENC_DC1, &CHROMA_DC_BITS, &CHROMA_DC_VALUES);
+        write_huffman_ac_table::<T>(HUFFENC_AC0, &LUMA_AC_BITS, &LUMA_AC_VALUES);
+        write_huffman_ac_table::<T>(HUFFENC_AC1, &CHROMA_AC_BITS, &CHROMA_AC_VALUES);
+
+        let components = match info.color_space {
+            ColorSpace::Grayscale => 1,
+            ColorSpace::YCbCr => 3,
+            ColorSpace::Cmyk => panic!("CMYK encoding is not supported"),
+        };
+        regs.confr1().write(|w| {
+            w.set_de(false);
+            w.set_hdr(false);
+            w.set_nf(components - 1);
+            w.set_ns(components - 1);
+            w.set_colspace(if components == 1 { 0 } else { 1 });
+            w.set_ysize(info.height);
+        });
+        regs.confr2().write(|w| w.set_nmcu(info.mcu_count() as u32 - 1));
+        regs.confr3().write(|w| w.set_xsize(info.width));
+
+        let (h, v) = info.chroma_subsampling.factors();
+        regs.confr4().write(|w| {
+            w.set_nb(h * v - 1);
+            w.set_hsf(h);
+            w.set_vsf(v);
+        });
+        for confr in [regs.confr5(), regs.confr6()] {
+            confr.write(|w| {
+                w.set_nb(0);
+                w.set_hsf(1);
+                w.set_vsf(1);
+                w.set_qt(1);
+                w.set_ha(true);
+                w.set_hd(true);
+            });
+        }
+
+        Self::start();
+    }
+
+    fn start() {
+        let regs = T::regs();
+        regs.cr().modify(|w| {
+            w.set_iff(true);
+            w.set_off(true);
+        });
+        regs.cfr().write(|w| {
+            w.set_ceocf(true);
+            w.set_chpdf(true);
+        });
+        regs.confr0().write(|w| w.set_start(true));
+    }
+
+    fn stop() {
+        let regs = T::regs();
+        regs.confr0().write(|w| w.set_start(false));
+        regs.cr().modify(|w| {
+            w.set_idmaen(false);
+            w.set_odmaen(false);
+            w.set_iff(true);
+            w.set_off(true);
+        });
+    }
+
+    fn image_info() -> ImageInfo {
+        let regs = T::regs();
+        let confr1 = regs.confr1().read();
+        let confr4 = regs.confr4().read();
+        let color_space = match (confr1.nf(), confr1.colspace()) {
+            (0, _) => ColorSpace::Grayscale,
+            (3, _) | (_, 3) => ColorSpace::Cmyk,
+            _ => ColorSpace::YCbCr,
+        };
+        let chroma_subsampling = match (color_space, confr4.hsf(), confr4.vsf()) {
+            (ColorSpace::YCbCr, 2, 2) => ChromaSubsampling::Yuv420,
+            (ColorSpace::YCbCr, 2, 1) => ChromaSubsampling::Yuv422,
+            _ => ChromaSubsampling::Yuv444,
+        };
+        ImageInfo {
+            width: regs.confr3().read().xsize(),
+            height: confr1.ysize(),
+            color_space,
+            chroma_subsampling,
+        }
+    }
+
+    fn blocking_process(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        let regs = T::regs();
+        let mut input = input.chunks(4);
+        let mut written = 0;
+        let mut idle = 0;
+
+        let result = loop {
+            let sr = regs.sr().read();
+            if sr.ofnef() {
+                if let Err(e) = store_word(output, &mut written, regs.dor().read()) {
+                    break Err(e);
+                }
+                idle = 0;
+            } else if sr.eocf() {
+                break Ok(written);
+            } else if sr.ifnff() && input.len() > 0 {
+                regs.dir().write_value(load_word(input.next().unwrap()));
+                idle = 0;
+            } else if input.len() == 0 {
+                idle += 1;
+                if idle > IDLE_POLLS {
+                    break Err(Error::IncompleteInput);
+                }
+            }
+        };
+
+        Self::stop();
+        result
+    }
+
+    async fn process(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize, Error>
+    where
+        DmaIn: crate::jpeg::DmaIn<T>,
+        DmaOut: crate::jpeg::DmaOut<T>,
+    {
+        assert_eq!(input.as_ptr() as usize % 4, 0);
+        assert_eq!(output.as_ptr() as usize % 4, 0);
+
+        let regs = T::regs();
+        let (input_words, input_tail) = input.split_at(input.len() / 4 * 4);
+        let input_words: &[u32] =
+            unsafe { core::slice::from_raw_parts(input_words.as_ptr().cast(), input_words.len() / 4) };
+        let output_words = output.len() / 4;
+
+        let indma = &mut self.indma;
+        let outdma = &mut self.outdma;
+
+        let write = async {
+            for chunk in input_words.chunks(MAX_DMA_WORDS) {
+                let request = indma.request();
+                let options = TransferOptions {
+                    priority: Priority::High,
+                    ..Default::default()
+                };
+                let transfer =
+                    unsafe { Transfer::new_write(&mut *indma, request, chunk, regs.dir().as_ptr(), options) };
+                regs.cr().modify(|w| w.set_idmaen(true));
+                transfer.await;
+            }
+            regs.cr().modify(|w| w.set_idmaen(false));
+            if !input_tail.is_empty() {
+                while !regs.sr().read().ifnff() {}
+                regs.dir().write_value(load_word(input_tail));
+            }
+        };
+
+        let read = async {
+            let mut written = 0;
+            while written < output_words {
+                let len = (output_words - written).min(MAX_DMA_WORDS);
+                let chunk = ptr::slice_from_raw_parts_mut(output[written * 4..].as_mut_ptr().cast(), len);
+                let request = outdma.request();
+                let options = TransferOptions {
+                    priority: Priority::VeryHigh,
+                    ..Default::default()
+                };
+                let mut transfer =
+                    unsafe { Transfer::new_read_raw(&mut *outdma, request, regs.dor().as_ptr(), chunk, options) };
+                regs.cr().modify(|w| w.set_odmaen(true));
+
+                match select(&mut transfer, wait_end_of_conversion::<T>()).await {
+                    Either::First(()) => written += len,
+                    Either::Second(()) => {
+                        regs.cr().modify(|w| w.set_odmaen(false));
+                        transfer.request_stop();
+                        while transfer.is_running() {}
+                        written += len - transfer.get_remaining_transfers() as usize;
+                        break;
+                    }
+                }
+            }
+            regs.cr().modify(|w| w.set_odmaen(false));
+
+            // Drain what the DMA left in the FIFO, or wait for the end of the conversion if the
+            // output is full.
+            let mut written = written * 4;
+            loop {
+                let sr = regs.sr().read();
+                if sr.ofnef() {
+                    store_word(output, &mut written, regs.dor().read())?;
+                } else if sr.eocf() {
+                    break Ok(written);
+                } else {
+                    wait_end_of_conversion::<T>().await;
+                }
+            }
+        };
+
+        let (_, result) = join(write, read).await;
+        Self::stop();
+        result
+    }
+}
+
+impl<'d, T: Instance, DmaIn, DmaOut> Drop for Jpeg<'d, T, DmaIn, DmaOut> {
+    fn drop(&mut self) {
+        T::regs().cr().write(|w| w.set_jcen(false));
+        T::disable();
+    }
+}
+
+async fn wait_end_of_conversion<T: Instance>() {
+    poll_fn(|cx| {
+        JPEG_WAKER.register(cx.waker());
+        if T::regs().sr().read().eocf() {
+            return Poll::Ready(());
+        }
+        T::regs().cr().modify(|w| w.set_eocie(true));
+        Poll::Pending
+    })
+    .await
+}
+
+fn load_word(bytes: &[u8]) -> u32 {
+    let mut word = [0; 4];
+    word[..bytes.len()].copy_from_slice(bytes);
+    u32::from_le_bytes(word)
+}
+
+fn store_word(output: &mut [u8], written: &mut usize, word: u32) -> Result<(), Error> {
+    let len = (output.len() - *written).min(4);
+    if len == 0 {
+        return Err(Error::BufferTooSmall);
+    }
+    output[*written..*written + len].copy_from_slice(&word.to_le_bytes()[..len]);
+    *written += len;
+    Ok(())
+}
+
+/// Trim the codec output after the end of image marker, adding it if missing.
+fn finish_image(output: &mut [u8], len: usize) -> Result<usize, Error> {
+    let start = len.saturating_sub(5);
+    if let Some(pos) = output[start..len].windows(2).rposition(|w| w == [0xFF, 0xD9]) {
+        return Ok(start + pos + 2);
+    }
+    if output.len() < len + 2 {
+        return Err(Error::BufferTooSmall);
+    }
+    output[len..len + 2].copy_from_slice(&[0xFF, 0xD9]);
+    Ok(len + 2)
+}
+
+fn write_memory<T: Instance>(offset: usize, index: usize, value: u32) {
+    unsafe {
+        (T::regs().as_ptr() as *mut u32)
+            .add(offset / 4 + index)
+            .write_volatile(value)
+    }
+}
+
+/// Scale a quantization table to a quality factor, in zigzag order.
+fn scaled_quantization(table: &[u8; 64], quality: u8) -> [u8; 64] {
+    let scale = if quality < 50 {
+        5000 / quality as u32
+    } else {
+        200 - 2 * quality as u32
+    };
+    let mut scaled = [0; 64];
+    for (value, index) in scaled.iter_mut().zip(ZIGZAG) {
+        *value = ((table[index as usize] as u32 * scale + 50) / 100).clamp(1, 255) as u8;
+    }
+    scaled
+}
+
+fn write_quantization_table<T: Instance>(offset: usize, table: &[u8; 64], quality: u8) {
+    let scaled = scaled_quantization(table, quality);
+    for (i, word) in scaled.chunks(4).enumerate() {
+        write_memory::<T>(offset, i, load_word(word));
+    }
+}
+
+/// Generate the Huffman codes of a table, in the order of its values (JPEG Annex C).
+fn huffman_codes(bits: &[u8; 16], mut f: impl FnMut(usize, u8, u16)) {
+    let mut code = 0u16;
+    let mut k = 0;
+    for (i, count) in bits.iter().enumerate() {
+        for _ in 0..*count {
+            f(k, i as u8 + 1, code);
+            code += 1;
+            k += 1;
+        }
+        code <<= 1;
+    }
+}
+
+/// Encoder memory entry: code length minus one, and the code LSBs.
+fn huffman_entry(len: u8, code: u16) -> u32 {
+    ((len as u32 - 1) & 0xF) << 8 | (code as u32 & 0xFF)
+}
+
+fn write_huffman_entries<T: Instance>(offset: usize, entries: &[u32]) {
+    for (i, pair) in entries.chunks(2).enumerate() {
+        write_memory::<T>(offset, i, pair[0] | pair[1] << 16);
+    }
+}
+
+fn write_huffman_dc_table<T: Instance>(offset: usize, bits: &[u8; 16], values: &[u8; 12]) {
+    let mut entries = [0; 16];
+    // Entries 12 to 15 are used internally by the codec.
+    entries[12..].fill(0xFFF);
+    huffman_codes(bits, |k, len, code| {
+        entries[values[k] as usize] = huffman_entry(len, code);
+    });
+    write_huffman_entries::<T>(offset, &entries);
+}
+
+fn write_huffman_ac_table<T: Instance>(offset: usize, bits: &[u8; 16], values: &[u8; 162]) {
+    let mut entries = [0; 176];
+    // Entries 162 to 175 are used internally by the codec.
+    entries[162..168].fill(0xFFF);
+    for (i, entry) in entries[168..].iter_mut().enumerate() {
+        *entry = 0xFD0 + i as u32;
+    }
+    huffman_codes(bits, |k, len, code| {
+        let index = match values[k] {
+            0x00 => 160,
+            0xF0 => 161,
+            value => (value >> 4) as usize * 10 + (value & 0xF) as usize - 1,
+        };
+        entries[index] = huffman_entry(len, code);
+    });
+    write_huffman_entries::<T>(offset, &entries);
+}
+
+/// Minimal writer for the JPEG header.
+struct HeaderWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> HeaderWriter<'a> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn marker(&mut self, marker: u8, len: usize) -> Result<(), Error> {
+        self.bytes(&[0xFF, marker])?;
+        self.bytes(&(len as u16 + 2).to_be_bytes())
+    }
+
+    fn quantization_table(&mut self, id: u8, table: &[u8; 64], quality: u8) -> Result<(), Error> {
+        self.marker(0xDB, 65)?;
+        self.bytes(&[id])?;
+        self.bytes(&scaled_quantization(table, quality))
+    }
+
+    fn huffman_table(&mut self, class_id: u8, bits: &[u8; 16], values: &[u8]) -> Result<(), Error> {
+        self.marker(0xC4, 17 + values.len())?;
+        self.bytes(&[class_id])?;
+        self.bytes(bits)?;
+        self.bytes(values)
+    }
+}
+
+/// Write the JPEG header preceding the entropy-coded data, returning its word-aligned length.
+fn write_header(config: &EncodeConfig, buf: &mut [u8]) -> Result<usize, Error> {
+    let info = config.info();
+    let ycbcr = info.color_space == ColorSpace::YCbCr;
+    let components: usize = if ycbcr { 3 } else { 1 };
+    let (h, v) = info.chroma_subsampling.factors();
+    let mut w = HeaderWriter { buf, len: 0 };
+
+    // Start of image.
+    w.bytes(&[0xFF, 0xD8])?;
+
+    w.quantization_table(0, &LUMA_QUANTIZATION, config.quality)?;
+    if ycbcr {
+        w.quantization_table(1, &CHROMA_QUANTIZATION, config.quality)?;
+    }
+
+    // Baseline start of frame.
+    w.marker(0xC0, 6 + 3 * components)?;
+    w.bytes(&[8])?;
+    w.bytes(&info.height.to_be_bytes())?;
+    w.bytes(&info.width.to_be_bytes())?;
+    w.bytes(&[components as u8, 1, h << 4 | v, 0])?;
+    if ycbcr {
+        w.bytes(&[2, 0x11, 1, 3, 0x11, 1])?;
+    }
+
+    w.huffman_table(0x00, &LUMA_DC_BITS, &LUMA_DC_VALUES)?;
+    w.huffman_table(0x10, &LUMA_AC_BITS, &LUMA_AC_VALUES)?;
+    if ycbcr {
+        w.huffman_table(0x01, &CHROMA_DC_BITS, &CHROMA_DC_VALUES)?;
+        w.huffman_table(0x11, &CHROMA_AC_BITS, &CHROMA_AC_VALUES)?;
+    }
+
+    // Markers can be preceded by fill bytes, keeping the codec output word-aligned.
+    let sos_len = 2 + 2 + 1 + 2 * components + 3;
+    while (w.len + sos_len) % 4 != 0 {
+        w.bytes(&[0xFF])?;
+    }
+
+    // Start of scan.
+    w.marker(0xDA, 4 + 2 * components)?;
+    w.bytes(&[components as u8, 1, 0x00])?;
+    if ycbcr {
+        w.bytes(&[2, 0x11, 3, 0x11])?;
+    }
+    w.bytes(&[0, 63, 0])?;
+
+    Ok(w.len)
+}
+
+/// Natural order index of each zigzag order coefficient.
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21,
+    28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54,
+    47, 55, 62, 63,
+];
+
+// Standard tables from JPEG Annex K, quantization tables in natural order.
+
+const LUMA_QUANTIZATION: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, //
+    12, 12, 14, 19, 26, 58, 60, 55, //
+    14, 13, 16, 24, 40, 57, 69, 56, //
+    14, 17, 22, 29, 51, 87, 80, 62, //
+    18, 22, 37, 56, 68, 109, 103, 77, //
+    24, 35, 55, 64, 81, 104, 113, 92, //
+    49, 64, 78, 87, 103, 121, 120, 101, //
+    72, 92, 95, 98, 112, 100, 103, 99, //
+];
+
+const CHROMA_QUANTIZATION: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, //
+    18, 21, 26, 66, 99, 99, 99, 99, //
+    24, 26, 56, 99, 99, 99, 99, 99, //
+    47, 66, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+];
+
+const LUMA_DC_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const LUMA_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const CHROMA_DC_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const CHROMA_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const LUMA_AC_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07, 0x22, 0x71, 0x14,
+    0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09,
+    0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a,
+    0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65,
+    0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88,
+    0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9,
+    0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca,
+    0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea,
+    0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+];
+
+const CHROMA_AC_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71, 0x13, 0x22, 0x32,
+    0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16,
+    0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39,
+    0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64,
+    0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86,
+    0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8,
+    0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9,
+    0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+];
+
+trait SealedInstance {
+    fn regs() -> pac::jpeg::Jpeg;
+}
+
+/// JPEG instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + crate::rcc::RccPeripheral + 'static + Send {
+    /// Interrupt for this JPEG instance.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+foreach_interrupt!(
+    ($inst:ident, jpeg, JPEG, GLOBAL, $irq:ident) => {
+        impl Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::typelevel::$irq;
+        }
+
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> crate::pac::jpeg::Jpeg {
+                crate::pac::$inst
+            }
+        }
+    };
+);
+
+dma_trait!(DmaIn, Instance);
+dma_trait!(DmaOut, Instance);
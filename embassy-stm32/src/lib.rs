@@ -46,7 +46,7 @@ pub mod dma;
 pub mod gpio;
 pub mod rcc;
 #[cfg(feature = "_time-driver")]
-mod time_driver;
+pub mod time_driver;
 pub mod timer;
 
 // Sometimes-present hardware
@@ -62,33 +62,53 @@ pub mod cordic;
 pub mod crc;
 #[cfg(cryp)]
 pub mod cryp;
+#[cfg(all(comp, stm32g4))]
+pub mod comp;
 #[cfg(dac)]
 pub mod dac;
 #[cfg(dcmi)]
 pub mod dcmi;
+#[cfg(dfsdm)]
+pub mod dfsdm;
+#[cfg(dsihost)]
+pub mod dsihost;
 #[cfg(eth)]
 pub mod eth;
 #[cfg(feature = "exti")]
 pub mod exti;
 pub mod flash;
+#[cfg(fmac)]
+pub mod fmac;
 #[cfg(fmc)]
 pub mod fmc;
 #[cfg(hash)]
 pub mod hash;
 #[cfg(hrtim)]
 pub mod hrtim;
+#[cfg(hsem)]
+pub mod hsem;
 #[cfg(i2c)]
 pub mod i2c;
-#[cfg(all(spi_v1, rcc_f4))]
+#[cfg(any(all(spi_v1, rcc_f4), spi_v3))]
 pub mod i2s;
 #[cfg(stm32wb)]
 pub mod ipcc;
+#[cfg(jpeg)]
+pub mod jpeg;
 #[cfg(feature = "low-power")]
 pub mod low_power;
+#[cfg(mdios)]
+pub mod mdios;
+#[cfg(any(stm32f7, stm32h7))]
+pub mod mpu;
+#[cfg(all(stm32h7, hsem, any(core_cm7, core_cm4)))]
+pub mod multicore;
 #[cfg(opamp)]
 pub mod opamp;
 #[cfg(octospi)]
 pub mod ospi;
+#[cfg(pssi)]
+pub mod pssi;
 #[cfg(quadspi)]
 pub mod qspi;
 #[cfg(rng)]
@@ -11,6 +11,8 @@
 //!
 //!  * `GPIO`
 //!  * `RCC`
+//!  * `LPUART` receivers waiting for a [wakeup event](crate::usart::Config::wakeup), if their
+//!    kernel clock is LSE or HSI
 //!
 //! Since entering and leaving low-power modes typically incurs a significant latency, the
 //! low-power executor will only attempt to enter when the next timer event is at least
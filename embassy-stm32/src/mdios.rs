@@ -0,0 +1,246 @@
+//! Management Data Input/Output Slave (MDIOS)
+//!
+//! The MDIOS lets the chip act as a device on an MDIO bus, e.g. to emulate a PHY or to expose
+//! registers to a switch or host. The bus master reads and writes 32 16-bit registers, which the
+//! driver exposes with [`Mdios::set_register`] and [`Mdios::register`].
+#![macro_use]
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::gpio::{AFType, AnyPin, SealedPin as _, Speed};
+use crate::interrupt::typelevel::Interrupt;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+static MDIOS_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Number of registers exposed to the MDIO master.
+pub const REGISTER_COUNT: usize = 32;
+
+/// MDIOS error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A frame addressed to this port was received without a valid preamble.
+    Preamble,
+    /// A frame addressed to this port was received with an invalid start bit sequence.
+    Start,
+    /// A write frame addressed to this port was received with an invalid turnaround sequence.
+    Turnaround,
+}
+
+/// MDIOS configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Port address the MDIOS responds to, in `0..32`.
+    pub port_address: u8,
+    /// Accept frames without a preamble.
+    pub disable_preamble_check: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port_address: 0,
+            disable_preamble_check: false,
+        }
+    }
+}
+
+/// MDIOS interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let regs = T::regs();
+        let sr = regs.sr().read();
+        if regs.wrfr().read().wrf() != 0 || regs.rdfr().read().rdf() != 0 || sr.perf() || sr.serf() || sr.terf() {
+            regs.cr().modify(|w| {
+                w.set_wrie(false);
+                w.set_rdie(false);
+                w.set_eie(false);
+            });
+            MDIOS_WAKER.wake();
+        }
+    }
+}
+
+/// MDIOS driver.
+pub struct Mdios<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    mdio: Option<PeripheralRef<'d, AnyPin>>,
+    mdc: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d, T: Instance> Mdios<'d, T> {
+    /// Create a new MDIOS driver.
+    ///
+    /// All registers read by the master are zero until set with [`set_register`](Self::set_register).
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        mdio: impl Peripheral<P = impl MdioPin<T>> + 'd,
+        mdc: impl Peripheral<P = impl MdcPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        assert!(config.port_address < 32);
+        into_ref!(peri);
+
+        T::enable_and_reset();
+
+        let regs = T::regs();
+        for reg in 0..REGISTER_COUNT {
+            regs.doutr(reg).write(|w| w.set_dout(0));
+        }
+        regs.cwrfr().write(|w| w.set_cwrf(u32::MAX));
+        regs.crdfr().write(|w| w.set_crdf(u32::MAX));
+        regs.clrfr().write(|w| {
+            w.set_cperf(true);
+            w.set_cserf(true);
+            w.set_cterf(true);
+        });
+        regs.cr().write(|w| {
+            w.set_port_addr(config.port_address);
+            w.set_dpc(config.disable_preamble_check);
+            w.set_en(true);
+        });
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Self {
+            _peri: peri,
+            mdio: new_pin!(mdio, AFType::OutputPushPull, Speed::VeryHigh),
+            mdc: new_pin!(mdc, AFType::Input, Speed::VeryHigh),
+        }
+    }
+
+    /// Set the value returned to the master when it reads register `reg`.
+    pub fn set_register(&mut self, reg: usize, value: u16) {
+        assert!(reg < REGISTER_COUNT);
+        T::regs().doutr(reg).write(|w| w.set_dout(value));
+    }
+
+    /// Get the value last written by the master to register `reg`.
+    pub fn register(&self, reg: usize) -> u16 {
+        assert!(reg < REGISTER_COUNT);
+        T::regs().dinr(reg).read().din()
+    }
+
+    /// Wait until the master writes registers.
+    ///
+    /// Returns a mask of the registers written since the last call, bit `n` being set when register
+    /// `n` was written. The written values can be read with [`register`](Self::register).
+    pub async fn wait_for_write(&mut self) -> Result<u32, Error> {
+        self.wait_for_flags(
+            || T::regs().wrfr().read().wrf(),
+            |mask| T::regs().cwrfr().write(|w| w.set_cwrf(mask)),
+            |w| w.set_wrie(true),
+        )
+        .await
+    }
+
+    /// Wait until the master reads registers.
+    ///
+    /// Returns a mask of the registers read since the last call, bit `n` being set when register `n`
+    /// was read.
+    pub async fn wait_for_read(&mut self) -> Result<u32, Error> {
+        self.wait_for_flags(
+            || T::regs().rdfr().read().rdf(),
+            |mask| T::regs().crdfr().write(|w| w.set_crdf(mask)),
+            |w| w.set_rdie(true),
+        )
+        .await
+    }
+
+    async fn wait_for_flags(
+        &mut self,
+        flags: impl Fn() -> u32,
+        clear: impl Fn(u32),
+        enable: impl Fn(&mut pac::mdios::regs::Cr),
+    ) -> Result<u32, Error> {
+        poll_fn(|cx| {
+            MDIOS_WAKER.register(cx.waker());
+
+            let regs = T::regs();
+            let sr = regs.sr().read();
+            if sr.perf() || sr.serf() || sr.terf() {
+                regs.clrfr().write(|w| {
+                    w.set_cperf(true);
+                    w.set_cserf(true);
+                    w.set_cterf(true);
+                });
+                return Poll::Ready(Err(if sr.perf() {
+                    Error::Preamble
+                } else if sr.serf() {
+                    Error::Start
+                } else {
+                    Error::Turnaround
+                }));
+            }
+
+            let mask = flags();
+            if mask != 0 {
+                clear(mask);
+                return Poll::Ready(Ok(mask));
+            }
+
+            regs.cr().modify(|w| {
+                enable(w);
+                w.set_eie(true);
+            });
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> Drop for Mdios<'d, T> {
+    fn drop(&mut self) {
+        T::regs().cr().modify(|w| {
+            w.set_en(false);
+            w.set_wrie(false);
+            w.set_rdie(false);
+            w.set_eie(false);
+        });
+        self.mdio.as_ref().map(|x| x.set_as_disconnected());
+        self.mdc.as_ref().map(|x| x.set_as_disconnected());
+
+        T::disable();
+    }
+}
+
+trait SealedInstance {
+    fn regs() -> pac::mdios::Mdios;
+}
+
+/// MDIOS instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + crate::rcc::RccPeripheral + 'static + Send {
+    /// Interrupt for this MDIOS instance.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+pin_trait!(MdioPin, Instance);
+pin_trait!(MdcPin, Instance);
+
+foreach_interrupt!(
+    ($inst:ident, mdios, MDIOS, GLOBAL, $irq:ident) => {
+        impl Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::typelevel::$irq;
+        }
+
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> crate::pac::mdios::Mdios {
+                crate::pac::$inst
+            }
+        }
+    };
+);
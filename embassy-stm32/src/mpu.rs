@@ -0,0 +1,257 @@
+//! Memory Protection Unit (MPU) configuration for DMA buffers
+//!
+//! On chips with a data cache, the CPU may see stale data in a buffer written by DMA, and DMA may
+//! read stale data from a buffer the CPU has only written to the cache. Placing DMA buffers in a
+//! region marked as non-cacheable (or write-through, for buffers only read by DMA such as
+//! framebuffers) avoids these coherency issues without disabling the cache globally.
+//!
+//! Buffers can be grouped in a dedicated linker section with [`dma_buffer!`](crate::dma_buffer),
+//! which the linker script should place in a `NOLOAD` output section, e.g. in `memory.x`:
+//!
+//! ```text
+//! SECTIONS
+//! {
+//!     .dma_buffer (NOLOAD) : ALIGN(32)
+//!     {
+//!         *(.dma_buffer .dma_buffer.*);
+//!     } > RAM_D2
+//! }
+//! ```
+//!
+//! The section is then covered by a non-cacheable [`Region`] configured with [`Mpu`]. MPU regions
+//! must be a power of two in size and aligned to their size.
+
+use cortex_m::peripheral::MPU;
+
+/// MPU error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The region number is not implemented by this MPU.
+    InvalidRegion,
+    /// The region size is not a power of two of at least 32 bytes.
+    InvalidSize,
+    /// The region base address is not aligned to the region size.
+    UnalignedBase,
+}
+
+/// Memory type and cache policy of a region.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemoryType {
+    /// Strongly-ordered memory, accesses are never buffered nor cached.
+    StronglyOrdered,
+    /// Shareable device memory, for peripheral registers.
+    Device,
+    /// Normal memory, not cached. Suited for buffers written and read by DMA.
+    NonCacheable,
+    /// Normal memory, cached with write-through and no write-allocate. Writes reach memory
+    /// immediately, suited for buffers only read by DMA, such as framebuffers.
+    WriteThrough,
+    /// Normal memory, cached with write-back and write-allocate. This is the default for SRAM.
+    WriteBack,
+}
+
+impl MemoryType {
+    /// TEX, C and B bits of RASR.
+    fn attributes(self) -> u32 {
+        let (tex, c, b) = match self {
+            MemoryType::StronglyOrdered => (0b000, 0, 0),
+            MemoryType::Device => (0b000, 0, 1),
+            MemoryType::NonCacheable => (0b001, 0, 0),
+            MemoryType::WriteThrough => (0b000, 1, 0),
+            MemoryType::WriteBack => (0b001, 1, 1),
+        };
+        tex << 19 | c << 17 | b << 16
+    }
+}
+
+/// Access permissions of a region.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Access {
+    /// No access.
+    None,
+    /// Read-only access.
+    ReadOnly,
+    /// Read and write access.
+    ReadWrite,
+}
+
+/// MPU region
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Region {
+    /// Base address, aligned to `size`.
+    pub base: u32,
+    /// Size in bytes, a power of two of at least 32.
+    pub size: u32,
+    /// Memory type and cache policy.
+    pub memory_type: MemoryType,
+    /// Access permissions, for both privileged and unprivileged code.
+    pub access: Access,
+    /// Allow instruction fetches.
+    pub executable: bool,
+    /// Mark the region as shareable between bus masters.
+    pub shareable: bool,
+}
+
+impl Region {
+    /// Non-cacheable read-write region, for buffers written and read by DMA.
+    pub const fn non_cacheable(base: u32, size: u32) -> Self {
+        Self {
+            base,
+            size,
+            memory_type: MemoryType::NonCacheable,
+            access: Access::ReadWrite,
+            executable: false,
+            shareable: true,
+        }
+    }
+
+    /// Write-through read-write region, for buffers only read by DMA.
+    pub const fn write_through(base: u32, size: u32) -> Self {
+        Self {
+            base,
+            size,
+            memory_type: MemoryType::WriteThrough,
+            access: Access::ReadWrite,
+            executable: false,
+            shareable: false,
+        }
+    }
+
+    fn rasr(&self) -> Result<u32, Error> {
+        if self.size < 32 || !self.size.is_power_of_two() {
+            return Err(Error::InvalidSize);
+        }
+        if self.base & (self.size - 1) != 0 {
+            return Err(Error::UnalignedBase);
+        }
+
+        let ap = match self.access {
+            Access::None => 0b000,
+            Access::ReadOnly => 0b110,
+            Access::ReadWrite => 0b011,
+        };
+        let size = self.size.trailing_zeros() - 1;
+
+        Ok((!self.executable as u32) << 28
+            | ap << 24
+            | self.memory_type.attributes()
+            | (self.shareable as u32) << 18
+            | size << 1
+            | 1)
+    }
+}
+
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+/// MPU driver.
+///
+/// Regions are numbered by priority: where regions overlap, the attributes of the highest numbered
+/// region apply.
+pub struct Mpu {
+    mpu: MPU,
+}
+
+impl Mpu {
+    /// Create a new MPU driver, taking ownership of the core MPU peripheral.
+    ///
+    /// The MPU is left enabled or disabled as it was. Enable it with [`enable`](Self::enable) once
+    /// regions are configured.
+    pub fn new(mpu: MPU) -> Self {
+        Self { mpu }
+    }
+
+    /// Number of regions implemented by the MPU.
+    pub fn region_count(&self) -> u8 {
+        (self.mpu._type.read() >> 8) as u8
+    }
+
+    /// Configure and enable region `number`.
+    ///
+    /// If the region holds data in the cache, clean it before changing its memory type.
+    pub fn set_region(&mut self, number: u8, region: &Region) -> Result<(), Error> {
+        if number >= self.region_count() {
+            return Err(Error::InvalidRegion);
+        }
+        let rasr = region.rasr()?;
+
+        self.update(|mpu| unsafe {
+            mpu.rnr.write(number as u32);
+            mpu.rbar.write(region.base);
+            mpu.rasr.write(rasr);
+        });
+        Ok(())
+    }
+
+    /// Disable region `number`.
+    pub fn clear_region(&mut self, number: u8) -> Result<(), Error> {
+        if number >= self.region_count() {
+            return Err(Error::InvalidRegion);
+        }
+
+        self.update(|mpu| unsafe {
+            mpu.rnr.write(number as u32);
+            mpu.rasr.write(0);
+        });
+        Ok(())
+    }
+
+    /// Enable the MPU.
+    ///
+    /// Memory not covered by a region keeps the default memory map attributes.
+    pub fn enable(&mut self) {
+        cortex_m::asm::dsb();
+        unsafe { self.mpu.ctrl.write(CTRL_PRIVDEFENA | CTRL_ENABLE) };
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+
+    /// Disable the MPU.
+    pub fn disable(&mut self) {
+        cortex_m::asm::dsb();
+        unsafe { self.mpu.ctrl.write(0) };
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+
+    /// Release the core MPU peripheral.
+    pub fn free(self) -> MPU {
+        self.mpu
+    }
+
+    /// Run `f` with the MPU disabled, so no access hits a half-configured region.
+    fn update(&mut self, f: impl FnOnce(&mut MPU)) {
+        critical_section::with(|_| {
+            let ctrl = self.mpu.ctrl.read();
+            self.disable();
+            f(&mut self.mpu);
+            if ctrl & CTRL_ENABLE != 0 {
+                self.enable();
+            }
+        })
+    }
+}
+
+/// Declare a DMA buffer in the `.dma_buffer` linker section.
+///
+/// The section is not initialized at startup, so the buffer is declared as
+/// [`MaybeUninit`](core::mem::MaybeUninit). See the [module documentation](crate::mpu) for the
+/// linker script and MPU setup.
+///
+/// ```ignore
+/// embassy_stm32::dma_buffer! {
+///     static mut RX_BUF: [u8; 1024];
+/// }
+/// ```
+#[macro_export]
+macro_rules! dma_buffer {
+    ($(#[$attr:meta])* $vis:vis static mut $name:ident: $ty:ty;) => {
+        $(#[$attr])*
+        #[link_section = ".dma_buffer"]
+        $vis static mut $name: ::core::mem::MaybeUninit<$ty> = ::core::mem::MaybeUninit::uninit();
+    };
+}
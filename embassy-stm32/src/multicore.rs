@@ -0,0 +1,216 @@
+//! Dual-core support for STM32H745/H747/H755/H757
+//!
+//! The Cortex-M7 core controls the start of the Cortex-M4 core with [`start_cm4`]. Both cores can
+//! then exchange messages over a [`Channel`] placed in SRAM shared by both cores, using HSEM
+//! semaphores to wake the other side when the channel changes.
+//!
+//! Both firmware images must place the channel at the same address, e.g. with a
+//! `#[link_section]` pointing to a `NOLOAD` section in SRAM3, and the Cortex-M7 core must call
+//! [`Channel::init`] before starting the Cortex-M4 core. The shared region must not be cached by
+//! the Cortex-M7 core, either by leaving its data cache disabled or by marking the region as
+//! non-cacheable with the MPU.
+
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Poll;
+
+use embassy_sync::channel::{TryReceiveError, TrySendError};
+
+use crate::hsem::{HardwareSemaphore, Instance};
+
+/// Start the Cortex-M4 core.
+///
+/// Only needed when the `BCM4` option bit is cleared, otherwise the Cortex-M4 core starts along
+/// with the Cortex-M7 core.
+#[cfg(core_cm7)]
+pub fn start_cm4() {
+    crate::pac::RCC.gcr().modify(|w| w.set_boot_c2(true));
+}
+
+/// Single-producer, single-consumer channel in memory shared between both cores.
+///
+/// One core sends with [`Channel::sender`] while the other receives with [`Channel::receiver`].
+/// Only one of each can exist at a time. The channel holds up to `N - 1` messages.
+#[repr(C)]
+pub struct Channel<T: Copy, const N: usize> {
+    read: AtomicUsize,
+    write: AtomicUsize,
+    sender_taken: AtomicBool,
+    receiver_taken: AtomicBool,
+    buf: UnsafeCell<[MaybeUninit<T>; N]>,
+}
+
+unsafe impl<T: Copy + Send, const N: usize> Sync for Channel<T, N> {}
+
+impl<T: Copy, const N: usize> Channel<T, N> {
+    /// Create a new channel.
+    pub const fn new() -> Self {
+        assert!(N >= 2);
+        Self {
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            sender_taken: AtomicBool::new(false),
+            receiver_taken: AtomicBool::new(false),
+            buf: UnsafeCell::new([MaybeUninit::uninit(); N]),
+        }
+    }
+
+    /// Empty the channel.
+    ///
+    /// Memory in a `NOLOAD` section is not initialized at startup, so this must be called once by
+    /// the Cortex-M7 core before starting the Cortex-M4 core.
+    pub fn init(&self) {
+        self.sender_taken.store(false, Ordering::Relaxed);
+        self.receiver_taken.store(false, Ordering::Relaxed);
+        self.read.store(0, Ordering::Relaxed);
+        self.write.store(0, Ordering::Release);
+    }
+
+    /// Get the sending side of the channel.
+    ///
+    /// Semaphore `data_sem` is released by the sender when a message is sent, `space_sem` by the
+    /// receiver when a message is received. Both semaphores must only be used by this channel.
+    ///
+    /// Panics if a [`Sender`] already exists.
+    pub fn sender<'a, 'd, H: Instance>(
+        &'a self,
+        hsem: &'a HardwareSemaphore<'d, H>,
+        data_sem: usize,
+        space_sem: usize,
+    ) -> Sender<'a, 'd, H, T, N> {
+        // Only the sending core takes the sender, so this doesn't rely on exclusive accesses
+        // working across cores.
+        assert!(
+            !self.sender_taken.swap(true, Ordering::Acquire),
+            "channel sender already taken"
+        );
+        Sender {
+            channel: self,
+            hsem,
+            data_sem,
+            space_sem,
+        }
+    }
+
+    /// Get the receiving side of the channel.
+    ///
+    /// Must be created with the same semaphores as the [`Sender`] on the other core.
+    ///
+    /// Panics if a [`Receiver`] already exists.
+    pub fn receiver<'a, 'd, H: Instance>(
+        &'a self,
+        hsem: &'a HardwareSemaphore<'d, H>,
+        data_sem: usize,
+        space_sem: usize,
+    ) -> Receiver<'a, 'd, H, T, N> {
+        assert!(
+            !self.receiver_taken.swap(true, Ordering::Acquire),
+            "channel receiver already taken"
+        );
+        Receiver {
+            channel: self,
+            hsem,
+            data_sem,
+            space_sem,
+        }
+    }
+
+    fn try_push(&self, message: T) -> Result<(), T> {
+        let write = self.write.load(Ordering::Relaxed);
+        let next = (write + 1) % N;
+        if next == self.read.load(Ordering::Acquire) {
+            return Err(message);
+        }
+
+        unsafe { (*self.buf.get())[write] = MaybeUninit::new(message) };
+        self.write.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let read = self.read.load(Ordering::Relaxed);
+        if read == self.write.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let message = unsafe { (*self.buf.get())[read].assume_init() };
+        self.read.store((read + 1) % N, Ordering::Release);
+        Some(message)
+    }
+}
+
+/// Sending side of a [`Channel`].
+pub struct Sender<'a, 'd, H: Instance, T: Copy, const N: usize> {
+    channel: &'a Channel<T, N>,
+    hsem: &'a HardwareSemaphore<'d, H>,
+    data_sem: usize,
+    space_sem: usize,
+}
+
+impl<'a, 'd, H: Instance, T: Copy, const N: usize> Sender<'a, 'd, H, T, N> {
+    /// Attempt to send a message immediately.
+    pub fn try_send(&mut self, message: T) -> Result<(), TrySendError<T>> {
+        self.channel.try_push(message).map_err(TrySendError::Full)?;
+        self.hsem.notify(self.data_sem);
+        Ok(())
+    }
+
+    /// Send a message, waiting until there is space in the channel.
+    pub async fn send(&mut self, message: T) {
+        let mut message = Some(message);
+        poll_fn(|cx| {
+            self.hsem.listen(self.space_sem, cx.waker());
+            match self.try_send(unwrap!(message.take())) {
+                Ok(()) => Poll::Ready(()),
+                Err(TrySendError::Full(m)) => {
+                    message = Some(m);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl<'a, 'd, H: Instance, T: Copy, const N: usize> Drop for Sender<'a, 'd, H, T, N> {
+    fn drop(&mut self) {
+        self.channel.sender_taken.store(false, Ordering::Release);
+    }
+}
+
+/// Receiving side of a [`Channel`].
+pub struct Receiver<'a, 'd, H: Instance, T: Copy, const N: usize> {
+    channel: &'a Channel<T, N>,
+    hsem: &'a HardwareSemaphore<'d, H>,
+    data_sem: usize,
+    space_sem: usize,
+}
+
+impl<'a, 'd, H: Instance, T: Copy, const N: usize> Receiver<'a, 'd, H, T, N> {
+    /// Attempt to receive a message immediately.
+    pub fn try_receive(&mut self) -> Result<T, TryReceiveError> {
+        let message = self.channel.try_pop().ok_or(TryReceiveError::Empty)?;
+        self.hsem.notify(self.space_sem);
+        Ok(message)
+    }
+
+    /// Receive a message, waiting until one is sent.
+    pub async fn receive(&mut self) -> T {
+        poll_fn(|cx| {
+            self.hsem.listen(self.data_sem, cx.waker());
+            match self.try_receive() {
+                Ok(message) => Poll::Ready(message),
+                Err(TryReceiveError::Empty) => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+impl<'a, 'd, H: Instance, T: Copy, const N: usize> Drop for Receiver<'a, 'd, H, T, N> {
+    fn drop(&mut self) {
+        self.channel.receiver_taken.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,383 @@
+//! Parallel Synchronous Slave Interface (PSSI)
+//!
+//! Exchanges 8 or 16-bit data with an FPGA or ASIC, on a parallel bus clocked by the device, with
+//! optional data enable (DE) and ready (RDY) flow control.
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_futures::select::{select, Either};
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::dma::Transfer;
+use crate::gpio::{AFType, Speed};
+use crate::interrupt::typelevel::Interrupt;
+use crate::{interrupt, Peripheral};
+
+static PSSI_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        if T::regs().mis().read().ovr_mis() {
+            T::regs().ier().modify(|w| w.set_ovr_ie(false));
+            PSSI_WAKER.wake();
+        }
+    }
+}
+
+/// PSSI error.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// Overrun when receiving, or underrun when transmitting: the DMA couldn't keep up with the
+    /// bus.
+    Overrun,
+}
+
+/// Clock edge on which the data is captured, by the PSSI when receiving, or by the device when
+/// transmitting.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum ClockPolarity {
+    FallingEdge,
+    RisingEdge,
+}
+
+/// Active level of the DE and RDY signals.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Polarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// Flow control signals in use.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlowControl {
+    /// No flow control: data is exchanged on every clock cycle.
+    None,
+    /// Data enable only: the transmitter flags the valid data.
+    DataEnable,
+    /// Ready only: the receiver flags when it can accept data.
+    Ready,
+    /// Both data enable and ready.
+    DataEnableAndReady,
+}
+
+/// PSSI configuration.
+#[non_exhaustive]
+pub struct Config {
+    /// PDCK polarity.
+    pub clock_polarity: ClockPolarity,
+    /// DE polarity.
+    pub data_enable_polarity: Polarity,
+    /// RDY polarity.
+    pub ready_polarity: Polarity,
+    /// Flow control signals in use, with the DE and RDY pins given to the constructor.
+    pub flow_control: FlowControl,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            clock_polarity: ClockPolarity::RisingEdge,
+            data_enable_polarity: Polarity::ActiveHigh,
+            ready_polarity: Polarity::ActiveHigh,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+macro_rules! config_pins {
+    ($aftype:expr, $($pin:ident),*) => {
+        into_ref!($($pin),*);
+        critical_section::with(|_| {
+            $(
+                $pin.set_as_af($pin.af_num(), $aftype);
+                $pin.set_speed(Speed::VeryHigh);
+            )*
+        })
+    };
+}
+
+/// PSSI driver.
+///
+/// The data goes through the DMA in 32-bit words, packing four bytes, or two half-words, in
+/// little-endian order.
+pub struct Pssi<'d, T: Instance, Dma: PssiDma<T>> {
+    _peri: PeripheralRef<'d, T>,
+    dma: PeripheralRef<'d, Dma>,
+}
+
+impl<'d, T, Dma> Pssi<'d, T, Dma>
+where
+    T: Instance,
+    Dma: PssiDma<T>,
+{
+    /// Create a new PSSI driver with 8 data bits, without flow control.
+    pub fn new_8bit(
+        peri: impl Peripheral<P = T> + 'd,
+        dma: impl Peripheral<P = Dma> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        d4: impl Peripheral<P = impl D4Pin<T>> + 'd,
+        d5: impl Peripheral<P = impl D5Pin<T>> + 'd,
+        d6: impl Peripheral<P = impl D6Pin<T>> + 'd,
+        d7: impl Peripheral<P = impl D7Pin<T>> + 'd,
+        pdck: impl Peripheral<P = impl PdckPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        assert!(config.flow_control == FlowControl::None);
+        into_ref!(peri, dma);
+        config_pins!(AFType::OutputPushPull, d0, d1, d2, d3, d4, d5, d6, d7);
+        config_pins!(AFType::Input, pdck);
+
+        Self::new_inner(peri, dma, config, 0b00)
+    }
+
+    /// Create a new PSSI driver with 8 data bits and flow control.
+    pub fn new_8bit_with_flow_control(
+        peri: impl Peripheral<P = T> + 'd,
+        dma: impl Peripheral<P = Dma> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        d4: impl Peripheral<P = impl D4Pin<T>> + 'd,
+        d5: impl Peripheral<P = impl D5Pin<T>> + 'd,
+        d6: impl Peripheral<P = impl D6Pin<T>> + 'd,
+        d7: impl Peripheral<P = impl D7Pin<T>> + 'd,
+        pdck: impl Peripheral<P = impl PdckPin<T>> + 'd,
+        de: impl Peripheral<P = impl DePin<T>> + 'd,
+        rdy: impl Peripheral<P = impl RdyPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, dma);
+        config_pins!(AFType::OutputPushPull, d0, d1, d2, d3, d4, d5, d6, d7);
+        config_pins!(AFType::OutputPushPull, de, rdy);
+        config_pins!(AFType::Input, pdck);
+
+        Self::new_inner(peri, dma, config, 0b00)
+    }
+
+    /// Create a new PSSI driver with 16 data bits, without flow control.
+    pub fn new_16bit(
+        peri: impl Peripheral<P = T> + 'd,
+        dma: impl Peripheral<P = Dma> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        d4: impl Peripheral<P = impl D4Pin<T>> + 'd,
+        d5: impl Peripheral<P = impl D5Pin<T>> + 'd,
+        d6: impl Peripheral<P = impl D6Pin<T>> + 'd,
+        d7: impl Peripheral<P = impl D7Pin<T>> + 'd,
+        d8: impl Peripheral<P = impl D8Pin<T>> + 'd,
+        d9: impl Peripheral<P = impl D9Pin<T>> + 'd,
+        d10: impl Peripheral<P = impl D10Pin<T>> + 'd,
+        d11: impl Peripheral<P = impl D11Pin<T>> + 'd,
+        d12: impl Peripheral<P = impl D12Pin<T>> + 'd,
+        d13: impl Peripheral<P = impl D13Pin<T>> + 'd,
+        d14: impl Peripheral<P = impl D14Pin<T>> + 'd,
+        d15: impl Peripheral<P = impl D15Pin<T>> + 'd,
+        pdck: impl Peripheral<P = impl PdckPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        assert!(config.flow_control == FlowControl::None);
+        into_ref!(peri, dma);
+        config_pins!(AFType::OutputPushPull, d0, d1, d2, d3, d4, d5, d6, d7);
+        config_pins!(AFType::OutputPushPull, d8, d9, d10, d11, d12, d13, d14, d15);
+        config_pins!(AFType::Input, pdck);
+
+        Self::new_inner(peri, dma, config, 0b11)
+    }
+
+    /// Create a new PSSI driver with 16 data bits and flow control.
+    pub fn new_16bit_with_flow_control(
+        peri: impl Peripheral<P = T> + 'd,
+        dma: impl Peripheral<P = Dma> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        d4: impl Peripheral<P = impl D4Pin<T>> + 'd,
+        d5: impl Peripheral<P = impl D5Pin<T>> + 'd,
+        d6: impl Peripheral<P = impl D6Pin<T>> + 'd,
+        d7: impl Peripheral<P = impl D7Pin<T>> + 'd,
+        d8: impl Peripheral<P = impl D8Pin<T>> + 'd,
+        d9: impl Peripheral<P = impl D9Pin<T>> + 'd,
+        d10: impl Peripheral<P = impl D10Pin<T>> + 'd,
+        d11: impl Peripheral<P = impl D11Pin<T>> + 'd,
+        d12: impl Peripheral<P = impl D12Pin<T>> + 'd,
+        d13: impl Peripheral<P = impl D13Pin<T>> + 'd,
+        d14: impl Peripheral<P = impl D14Pin<T>> + 'd,
+        d15: impl Peripheral<P = impl D15Pin<T>> + 'd,
+        pdck: impl Peripheral<P = impl PdckPin<T>> + 'd,
+        de: impl Peripheral<P = impl DePin<T>> + 'd,
+        rdy: impl Peripheral<P = impl RdyPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, dma);
+        config_pins!(AFType::OutputPushPull, d0, d1, d2, d3, d4, d5, d6, d7);
+        config_pins!(AFType::OutputPushPull, d8, d9, d10, d11, d12, d13, d14, d15);
+        config_pins!(AFType::OutputPushPull, de, rdy);
+        config_pins!(AFType::Input, pdck);
+
+        Self::new_inner(peri, dma, config, 0b11)
+    }
+
+    fn new_inner(peri: PeripheralRef<'d, T>, dma: PeripheralRef<'d, Dma>, config: Config, edm: u8) -> Self {
+        T::enable_and_reset();
+
+        T::regs().cr().write(|w| {
+            w.set_ckpol(config.clock_polarity == ClockPolarity::RisingEdge);
+            w.set_depol(config.data_enable_polarity == Polarity::ActiveHigh);
+            w.set_rdypol(config.ready_polarity == Polarity::ActiveHigh);
+            w.set_derdycfg(match config.flow_control {
+                FlowControl::None => 0b000,
+                FlowControl::DataEnableAndReady => 0b001,
+                FlowControl::Ready => 0b010,
+                FlowControl::DataEnable => 0b011,
+            });
+            w.set_edm(edm);
+            w.set_dmaen(true);
+        });
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Self { _peri: peri, dma }
+    }
+
+    /// Receive data from the device, until `buffer` is full.
+    pub async fn read(&mut self, buffer: &mut [u32]) -> Result<(), Error> {
+        let regs = T::regs();
+        regs.cr().modify(|w| w.set_enable(false));
+        regs.cr().modify(|w| w.set_outen(false));
+
+        let request = self.dma.request();
+        let transfer = unsafe {
+            Transfer::new_read(
+                &mut self.dma,
+                request,
+                regs.dr().as_ptr() as *mut u32,
+                buffer,
+                Default::default(),
+            )
+        };
+        let result = Self::run(transfer).await;
+        regs.cr().modify(|w| w.set_enable(false));
+        result
+    }
+
+    /// Transmit data to the device.
+    ///
+    /// The PSSI stays enabled afterwards, so the device can clock out the data left in the FIFO.
+    pub async fn write(&mut self, buffer: &[u32]) -> Result<(), Error> {
+        let regs = T::regs();
+        regs.cr().modify(|w| w.set_enable(false));
+        regs.cr().modify(|w| w.set_outen(true));
+
+        let request = self.dma.request();
+        let transfer = unsafe {
+            Transfer::new_write(
+                &mut self.dma,
+                request,
+                buffer,
+                regs.dr().as_ptr() as *mut u32,
+                Default::default(),
+            )
+        };
+        let result = Self::run(transfer).await;
+        if result.is_err() {
+            regs.cr().modify(|w| w.set_enable(false));
+        }
+        result
+    }
+
+    async fn run(transfer: Transfer<'_>) -> Result<(), Error> {
+        let regs = T::regs();
+        regs.icr().write(|w| w.set_ovr_isc(true));
+        regs.ier().modify(|w| w.set_ovr_ie(true));
+        regs.cr().modify(|w| w.set_enable(true));
+
+        let overrun = poll_fn(|cx| {
+            PSSI_WAKER.register(cx.waker());
+            if regs.ris().read().ovr_ris() {
+                regs.icr().write(|w| w.set_ovr_isc(true));
+                Poll::Ready(())
+            } else {
+                regs.ier().modify(|w| w.set_ovr_ie(true));
+                Poll::Pending
+            }
+        });
+
+        let result = match select(transfer, overrun).await {
+            Either::First(()) => Ok(()),
+            Either::Second(()) => Err(Error::Overrun),
+        };
+
+        regs.ier().modify(|w| w.set_ovr_ie(false));
+        result
+    }
+}
+
+trait SealedInstance: crate::rcc::RccPeripheral {
+    fn regs() -> crate::pac::pssi::Pssi;
+}
+
+/// PSSI instance.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + Peripheral<P = Self> + 'static {
+    /// Interrupt for this instance.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+pin_trait!(D0Pin, Instance);
+pin_trait!(D1Pin, Instance);
+pin_trait!(D2Pin, Instance);
+pin_trait!(D3Pin, Instance);
+pin_trait!(D4Pin, Instance);
+pin_trait!(D5Pin, Instance);
+pin_trait!(D6Pin, Instance);
+pin_trait!(D7Pin, Instance);
+pin_trait!(D8Pin, Instance);
+pin_trait!(D9Pin, Instance);
+pin_trait!(D10Pin, Instance);
+pin_trait!(D11Pin, Instance);
+pin_trait!(D12Pin, Instance);
+pin_trait!(D13Pin, Instance);
+pin_trait!(D14Pin, Instance);
+pin_trait!(D15Pin, Instance);
+pin_trait!(PdckPin, Instance);
+pin_trait!(DePin, Instance);
+pin_trait!(RdyPin, Instance);
+
+foreach_interrupt! {
+    ($inst:ident, pssi, $block:ident, GLOBAL, $irq:ident) => {
+        impl SealedInstance for crate::peripherals::$inst {
+            fn regs() -> crate::pac::pssi::Pssi {
+                crate::pac::$inst
+            }
+        }
+
+        impl Instance for crate::peripherals::$inst {
+            type Interrupt = crate::interrupt::typelevel::$irq;
+        }
+    };
+}
+
+dma_trait!(PssiDma, Instance);
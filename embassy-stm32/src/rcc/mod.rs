@@ -68,11 +68,24 @@ pub(crate) unsafe fn get_freqs() -> &'static Clocks {
 pub(crate) trait SealedRccPeripheral {
     fn frequency() -> crate::time::Hertz;
     fn enable_and_reset_with_cs(cs: CriticalSection);
+    fn enable_with_cs(cs: CriticalSection);
     fn disable_with_cs(cs: CriticalSection);
 
+    /// Whether the kernel clock keeps running in Stop mode, so the peripheral can wake the MCU.
+    fn kernel_clock_runs_in_stop() -> bool;
+    /// Let the low-power executor enter Stop mode while the peripheral is enabled.
+    ///
+    /// Must be undone with [`block_stop_with_cs`](Self::block_stop_with_cs) before disabling it.
+    fn allow_stop_with_cs(cs: CriticalSection);
+    /// Undo [`allow_stop_with_cs`](Self::allow_stop_with_cs).
+    fn block_stop_with_cs(cs: CriticalSection);
+
     fn enable_and_reset() {
         critical_section::with(|cs| Self::enable_and_reset_with_cs(cs))
     }
+    fn enable() {
+        critical_section::with(|cs| Self::enable_with_cs(cs))
+    }
     fn disable() {
         critical_section::with(|cs| Self::disable_with_cs(cs))
     }
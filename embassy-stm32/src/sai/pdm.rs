@@ -0,0 +1,266 @@
+//! PDM microphone interface.
+//!
+//! In PDM mode, sub-block A drives the bitstream clock on the `CKx` pins and de-interleaves
+//! the microphones sharing a `Dx` data line: one on the rising and one on the falling edge.
+//! Each microphone gets its own slot in the frame, so the DMA buffer holds the raw bitstreams
+//! interleaved per microphone, 8 or 16 bits at a time depending on the word type. The oldest
+//! bit is the most significant bit.
+//!
+//! The SAI doesn't filter the bitstream. Feed each microphone to a [`PdmDecimator`] to get PCM
+//! samples.
+
+use super::*;
+
+/// PDM configuration.
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct PdmConfig {
+    /// Master clock divider, applied to the SAI kernel clock.
+    ///
+    /// The bitstream clock is `kernel clock * word bits / (256 * divider)`, e.g. 1.536 MHz
+    /// with 8-bit words, a 49.152 MHz kernel clock and [`MasterClockDivider::Div1`].
+    pub master_clock_divider: MasterClockDivider,
+    /// FIFO threshold level.
+    pub fifo_threshold: FifoThreshold,
+    /// Delay of the left and right microphone of each pair, in bitstream clock periods
+    /// (0 to 7), to compensate for the distance between the microphones.
+    pub delay: [(u8, u8); 4],
+}
+
+impl Default for PdmConfig {
+    fn default() -> Self {
+        Self {
+            master_clock_divider: MasterClockDivider::Div1,
+            fifo_threshold: FifoThreshold::ThreeQuarters,
+            delay: [(0, 0); 4],
+        }
+    }
+}
+
+#[cfg(not(gpdma))]
+impl<'d, T: Instance, W: word::Word> Sai<'d, T, W> {
+    /// Create a new SAI driver for a pair of PDM microphones on `D1`.
+    ///
+    /// `W` must be `u8` or `u16`. The DMA buffer holds the left and right bitstreams
+    /// interleaved.
+    ///
+    /// You can obtain the [`SubBlock`] with [`split_subblocks`].
+    pub fn new_pdm(
+        peri: SubBlock<'d, T, A>,
+        ck1: impl Peripheral<P = impl Ck1Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        dma: impl Peripheral<P = impl Channel + Dma<T, A>> + 'd,
+        dma_buf: &'d mut [W],
+        config: PdmConfig,
+    ) -> Self {
+        into_ref!(ck1, d1);
+
+        ck1.set_as_af(ck1.af_num(), AFType::OutputPushPull);
+        ck1.set_speed(crate::gpio::Speed::VeryHigh);
+        d1.set_as_af(d1.af_num(), AFType::Input);
+
+        Self::new_pdm_inner(peri, 1, ck1.map_into(), d1.map_into(), None, dma, dma_buf, config)
+    }
+
+    /// Create a new SAI driver for two pairs of PDM microphones on `D1` and `D2`, sharing the
+    /// bitstream clock on `CK1`.
+    ///
+    /// `W` must be `u8` or `u16`. The DMA buffer holds the bitstreams of the `D1` left, `D1`
+    /// right, `D2` left and `D2` right microphones interleaved.
+    ///
+    /// You can obtain the [`SubBlock`] with [`split_subblocks`].
+    pub fn new_pdm_4mic(
+        peri: SubBlock<'d, T, A>,
+        ck1: impl Peripheral<P = impl Ck1Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        dma: impl Peripheral<P = impl Channel + Dma<T, A>> + 'd,
+        dma_buf: &'d mut [W],
+        config: PdmConfig,
+    ) -> Self {
+        into_ref!(ck1, d1, d2);
+
+        ck1.set_as_af(ck1.af_num(), AFType::OutputPushPull);
+        ck1.set_speed(crate::gpio::Speed::VeryHigh);
+        d1.set_as_af(d1.af_num(), AFType::Input);
+        d2.set_as_af(d2.af_num(), AFType::Input);
+
+        Self::new_pdm_inner(
+            peri,
+            2,
+            ck1.map_into(),
+            d1.map_into(),
+            Some(d2.map_into()),
+            dma,
+            dma_buf,
+            config,
+        )
+    }
+
+    fn new_pdm_inner(
+        peri: SubBlock<'d, T, A>,
+        pairs: u8,
+        ck: PeripheralRef<'d, AnyPin>,
+        d1: PeripheralRef<'d, AnyPin>,
+        d2: Option<PeripheralRef<'d, AnyPin>>,
+        dma: impl Peripheral<P = impl Channel + Dma<T, A>> + 'd,
+        dma_buf: &'d mut [W],
+        pdm_config: PdmConfig,
+    ) -> Self {
+        let peri = peri.peri;
+        into_ref!(dma);
+
+        let data_size = match W::size() {
+            word::WordSize::OneByte => DataSize::Data8,
+            word::WordSize::TwoBytes => DataSize::Data16,
+            word::WordSize::FourBytes => panic!("PDM words must be 8 or 16 bits"),
+        };
+        let mics = 2 * pairs;
+
+        // RM0433 "PDM interface": one slot per microphone, in a free protocol frame.
+        let mut config = Config::default();
+        config.mode = Mode::Master;
+        config.tx_rx = TxRx::Receiver;
+        config.protocol = Protocol::Free;
+        config.data_size = data_size;
+        config.slot_size = SlotSize::DataSize;
+        config.slot_count = word::U4(mics);
+        config.slot_enable = (1 << mics) - 1;
+        config.bit_order = BitOrder::MsbFirst;
+        config.frame_sync_active_level_length = word::U7(1);
+        config.frame_sync_definition = FrameSyncDefinition::StartOfFrame;
+        config.frame_length = mics * W::bits() as u8;
+        config.clock_strobe = ClockStrobe::Rising;
+        config.master_clock_divider = pdm_config.master_clock_divider;
+        config.fifo_threshold = pdm_config.fifo_threshold;
+
+        T::REGS.pdmcr().modify(|w| {
+            w.set_pdmen(false);
+            w.set_micnbr(pairs - 1);
+            w.set_cken(0, true);
+        });
+        T::REGS.pdmdly().write(|w| {
+            for (i, (left, right)) in pdm_config.delay.iter().enumerate().take(pairs as usize) {
+                assert!(*left < 8 && *right < 8);
+                w.set_dlyml(i, *left);
+                w.set_dlymr(i, *right);
+            }
+        });
+        T::REGS.pdmcr().modify(|w| w.set_pdmen(true));
+
+        let sub_block = WhichSubBlock::A;
+        let request = dma.request();
+
+        // There's no MCLK output in PDM mode, its slot holds the D2 pin so it's released on drop.
+        Self::new_inner(
+            peri,
+            sub_block,
+            Some(ck),
+            d2,
+            Some(d1),
+            None,
+            get_ring_buffer::<T, W>(dma, dma_buf, request, sub_block, config.tx_rx),
+            config,
+        )
+    }
+}
+
+/// Order of the [`PdmDecimator`] CIC filter.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CicOrder {
+    /// Third order, for sample rates with little out of band noise.
+    Third = 3,
+    /// Fourth order.
+    Fourth = 4,
+    /// Fifth order, recommended for most MEMS microphones.
+    Fifth = 5,
+}
+
+/// Software decimation filter, converting a PDM bitstream to 16-bit PCM samples.
+///
+/// This is a cascaded integrator-comb (CIC) filter: the output sample rate is the bitstream
+/// clock divided by the decimation ratio. The CIC filter droops in the passband, so audio
+/// applications usually follow it with a compensation FIR filter at the output rate.
+pub struct PdmDecimator {
+    order: usize,
+    ratio: u16,
+    shift: u32,
+    integrators: [i32; 5],
+    combs: [i32; 5],
+    count: u16,
+}
+
+impl PdmDecimator {
+    /// Create a decimator.
+    ///
+    /// `ratio` must be a power of two, at least 8, and small enough for the filter gain to fit
+    /// in 31 bits: up to 128 for [`CicOrder::Fourth`] and 64 for [`CicOrder::Fifth`].
+    pub fn new(order: CicOrder, ratio: u16) -> Self {
+        assert!(ratio >= 8 && ratio.is_power_of_two());
+        let order = order as usize;
+        let gain_bits = order as u32 * ratio.trailing_zeros();
+        assert!(gain_bits <= 30, "CIC gain doesn't fit in 31 bits");
+
+        Self {
+            order,
+            ratio,
+            shift: gain_bits.saturating_sub(15),
+            integrators: [0; 5],
+            combs: [0; 5],
+            count: 0,
+        }
+    }
+
+    /// Output sample rate for a given bitstream clock, in Hz.
+    pub fn output_rate(&self, bitstream_clock: u32) -> u32 {
+        bitstream_clock / self.ratio as u32
+    }
+
+    /// Reset the filter state.
+    pub fn reset(&mut self) {
+        self.integrators = [0; 5];
+        self.combs = [0; 5];
+        self.count = 0;
+    }
+
+    /// Filter a bitstream, and write the PCM samples to `output`.
+    ///
+    /// `input` holds the words read from the SAI. To filter one microphone out of `n`, pass
+    /// the buffer starting at the microphone's index with a `stride` of `n`. The filter state
+    /// is kept between calls, so a stream can be processed in chunks of any length.
+    ///
+    /// Returns the number of samples written. Panics if `output` is too small, which is never
+    /// the case with room for `input.len() * word bits / (stride * ratio)` samples.
+    pub fn process<W: word::Word + Into<u32>>(&mut self, input: &[W], stride: usize, output: &mut [i16]) -> usize {
+        let bits = W::bits();
+        let mut written = 0;
+
+        for word in input.iter().step_by(stride) {
+            let word: u32 = (*word).into();
+            for bit in (0..bits).rev() {
+                let x = if word & (1 << bit) != 0 { 1 } else { -1 };
+
+                let mut acc = x;
+                for integrator in &mut self.integrators[..self.order] {
+                    *integrator = integrator.wrapping_add(acc);
+                    acc = *integrator;
+                }
+
+                self.count += 1;
+                if self.count == self.ratio {
+                    self.count = 0;
+                    for comb in &mut self.combs[..self.order] {
+                        let delayed = *comb;
+                        *comb = acc;
+                        acc = acc.wrapping_sub(delayed);
+                    }
+                    output[written] = (acc >> self.shift).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                    written += 1;
+                }
+            }
+        }
+
+        written
+    }
+}
@@ -5,6 +5,7 @@ use core::default::Default;
 use core::future::poll_fn;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::slice;
 use core::task::Poll;
 
 use embassy_hal_internal::drop::OnDrop;
@@ -230,42 +231,63 @@ fn clk_div(ker_ck: Hertz, sdmmc_ck: u32) -> Result<(bool, u16, Hertz), Error> {
 type Transfer<'a> = crate::dma::Transfer<'a>;
 #[cfg(sdmmc_v2)]
 struct Transfer<'a> {
+    /// Buffer written by the IDMA, to invalidate from the data cache once the transfer is done.
+    invalidate: Option<(usize, usize)>,
     _dummy: PhantomData<&'a ()>,
 }
 
+#[cfg(sdmmc_v2)]
+impl<'a> Drop for Transfer<'a> {
+    fn drop(&mut self) {
+        if let Some((addr, len)) = self.invalidate {
+            crate::dma::cache::finish_read(addr, len);
+        }
+    }
+}
+
 #[cfg(all(sdmmc_v1, dma))]
-const DMA_TRANSFER_OPTIONS: crate::dma::TransferOptions = crate::dma::TransferOptions {
-    pburst: crate::dma::Burst::Incr4,
-    mburst: crate::dma::Burst::Incr4,
-    flow_ctrl: crate::dma::FlowControl::Peripheral,
-    fifo_threshold: Some(crate::dma::FifoThreshold::Full),
-    priority: crate::dma::Priority::VeryHigh,
-    circular: false,
-    half_transfer_ir: false,
-    complete_transfer_ir: true,
-};
+const fn dma_transfer_options(priority: crate::dma::Priority) -> crate::dma::TransferOptions {
+    crate::dma::TransferOptions {
+        pburst: crate::dma::Burst::Incr4,
+        mburst: crate::dma::Burst::Incr4,
+        flow_ctrl: crate::dma::FlowControl::Peripheral,
+        fifo_threshold: Some(crate::dma::FifoThreshold::Full),
+        priority,
+        circular: false,
+        half_transfer_ir: false,
+        complete_transfer_ir: true,
+    }
+}
 #[cfg(all(sdmmc_v1, not(dma)))]
-const DMA_TRANSFER_OPTIONS: crate::dma::TransferOptions = crate::dma::TransferOptions {
-    priority: crate::dma::Priority::VeryHigh,
-    circular: false,
-    half_transfer_ir: false,
-    complete_transfer_ir: true,
-};
+const fn dma_transfer_options(priority: crate::dma::Priority) -> crate::dma::TransferOptions {
+    crate::dma::TransferOptions {
+        priority,
+        circular: false,
+        half_transfer_ir: false,
+        complete_transfer_ir: true,
+    }
+}
 
 /// SDMMC configuration
 ///
 /// Default values:
 /// data_transfer_timeout: 5_000_000
+/// dma_priority: VeryHigh
 #[non_exhaustive]
 pub struct Config {
     /// The timeout to be set for data transfers, in card bus clock periods
     pub data_transfer_timeout: u32,
+    /// DMA request priority of the data transfers. SDMMC v2 uses its internal DMA instead.
+    #[cfg(sdmmc_v1)]
+    pub dma_priority: crate::dma::Priority,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             data_transfer_timeout: 5_000_000,
+            #[cfg(sdmmc_v1)]
+            dma_priority: crate::dma::Priority::VeryHigh,
         }
     }
 }
@@ -564,19 +586,22 @@ impl<'d, T: Instance, Dma: SdmmcDma<T> + 'd> Sdmmc<'d, T, Dma> {
         #[cfg(sdmmc_v1)]
         let transfer = unsafe {
             let request = self.dma.request();
+            let options = dma_transfer_options(self.config.dma_priority);
             Transfer::new_read(
                 &mut self.dma,
                 request,
                 regs.fifor().as_ptr() as *mut u32,
                 buffer,
-                DMA_TRANSFER_OPTIONS,
+                options,
             )
         };
         #[cfg(sdmmc_v2)]
         let transfer = {
+            crate::dma::cache::prepare_read(&mut *buffer);
             regs.idmabase0r().write(|w| w.set_idmabase0(buffer.as_mut_ptr() as u32));
             regs.idmactrlr().modify(|w| w.set_idmaen(true));
             Transfer {
+                invalidate: Some((buffer.as_ptr() as usize, buffer.len() * 4)),
                 _dummy: core::marker::PhantomData,
             }
         };
@@ -612,19 +637,22 @@ impl<'d, T: Instance, Dma: SdmmcDma<T> + 'd> Sdmmc<'d, T, Dma> {
         #[cfg(sdmmc_v1)]
         let transfer = unsafe {
             let request = self.dma.request();
+            let options = dma_transfer_options(self.config.dma_priority);
             Transfer::new_write(
                 &mut self.dma,
                 request,
                 buffer,
                 regs.fifor().as_ptr() as *mut u32,
-                DMA_TRANSFER_OPTIONS,
+                options,
             )
         };
         #[cfg(sdmmc_v2)]
         let transfer = {
+            crate::dma::cache::prepare_write(buffer);
             regs.idmabase0r().write(|w| w.set_idmabase0(buffer.as_ptr() as u32));
             regs.idmactrlr().modify(|w| w.set_idmaen(true));
             Transfer {
+                invalidate: None,
                 _dummy: core::marker::PhantomData,
             }
         };
@@ -1271,24 +1299,179 @@ impl<'d, T: Instance, Dma: SdmmcDma<T> + 'd> Sdmmc<'d, T, Dma> {
                 Self::stop_datapath();
                 drop(transfer);
 
-                // TODO: Make this configurable
-                let mut timeout: u32 = 0x00FF_FFFF;
-
-                // Try to read card status (ACMD13)
-                while timeout > 0 {
-                    match self.read_sd_status().await {
-                        Ok(_) => return Ok(()),
-                        Err(Error::Timeout) => (), // Try again
-                        Err(e) => return Err(e),
-                    }
-                    timeout -= 1;
-                }
-                Err(Error::SoftwareTimeout)
+                self.wait_write_done().await
             }
             Err(e) => Err(e),
         }
     }
 
+    /// Wait until the card is done programming the written blocks.
+    async fn wait_write_done(&mut self) -> Result<(), Error> {
+        // TODO: Make this configurable
+        let mut timeout: u32 = 0x00FF_FFFF;
+
+        // Try to read card status (ACMD13)
+        while timeout > 0 {
+            match self.read_sd_status().await {
+                Ok(_) => return Ok(()),
+                Err(Error::Timeout) => (), // Try again
+                Err(e) => return Err(e),
+            }
+            timeout -= 1;
+        }
+        Err(Error::SoftwareTimeout)
+    }
+
+    /// Wait for the end of the data transfer.
+    async fn wait_data_end() -> Result<(), Error> {
+        let regs = T::regs();
+        poll_fn(|cx| {
+            T::state().register(cx.waker());
+            let status = regs.star().read();
+
+            if status.dcrcfail() {
+                return Poll::Ready(Err(Error::Crc));
+            }
+            if status.dtimeout() {
+                return Poll::Ready(Err(Error::Timeout));
+            }
+            #[cfg(sdmmc_v1)]
+            if status.stbiterr() {
+                return Poll::Ready(Err(Error::StBitErr));
+            }
+            if status.dataend() {
+                return Poll::Ready(Ok(()));
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Read consecutive data blocks, with a single multiple block read command.
+    ///
+    /// This is much faster than reading the blocks one by one, as the card only looks up the
+    /// first one. Up to 65535 blocks can be read at once.
+    pub async fn read_blocks(&mut self, block_idx: u32, blocks: &mut [DataBlock]) -> Result<(), Error> {
+        let card_capacity = self.card()?.card_type;
+        assert!(blocks.len() < 0x1_0000, "Up to 65535 blocks per transfer");
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        let length_bytes = blocks.len() as u32 * 512;
+        // NOTE(unsafe) DataBlock uses align 4
+        let buffer = unsafe { slice::from_raw_parts_mut(blocks.as_mut_ptr() as *mut u32, blocks.len() * 128) };
+
+        // SDSC cards are byte addressed hence the blockaddress is in multiples of 512 bytes
+        let address = match card_capacity {
+            CardCapacity::SDSC => block_idx * 512,
+            _ => block_idx,
+        };
+        Self::cmd(Cmd::set_block_length(512), false)?; // CMD16
+
+        let on_drop = OnDrop::new(|| Self::on_drop());
+
+        let transfer = self.prepare_datapath_read(buffer, length_bytes, 9);
+        InterruptHandler::<T>::data_interrupts(true);
+        let sent = Self::cmd(Cmd::read_multiple_blocks(address), true); // CMD18
+
+        let res = match sent {
+            Ok(()) => Self::wait_data_end().await,
+            Err(e) => Err(e),
+        };
+        Self::clear_interrupt_flags();
+
+        match res {
+            Ok(()) => on_drop.defuse(),
+            // Aborts the transfer if it's still running.
+            Err(_) => drop(on_drop),
+        }
+        Self::stop_datapath();
+        drop(transfer);
+
+        // The card keeps sending blocks until told to stop, also after an error.
+        let stop = Self::cmd(Cmd::stop_transmission(), false); // CMD12
+        res.and(stop)
+    }
+
+    /// Write consecutive data blocks, with a single multiple block write command.
+    ///
+    /// The card is told how many blocks follow, so it can erase them ahead of the write. This is
+    /// much faster than writing the blocks one by one. Up to 65535 blocks can be written at once.
+    pub async fn write_blocks(&mut self, block_idx: u32, blocks: &[DataBlock]) -> Result<(), Error> {
+        let card = self.card.as_mut().ok_or(Error::NoCard)?;
+        assert!(blocks.len() < 0x1_0000, "Up to 65535 blocks per transfer");
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        let length_bytes = blocks.len() as u32 * 512;
+        // NOTE(unsafe) DataBlock uses align 4
+        let buffer = unsafe { slice::from_raw_parts(blocks.as_ptr() as *const u32, blocks.len() * 128) };
+
+        // SDSC cards are byte addressed hence the blockaddress is in multiples of 512 bytes
+        let address = match card.card_type {
+            CardCapacity::SDSC => block_idx * 512,
+            _ => block_idx,
+        };
+        let rca = card.rca;
+        Self::cmd(Cmd::set_block_length(512), false)?; // CMD16
+
+        // Pre-erase the blocks
+        Self::cmd(Cmd::app_cmd(rca << 16), false)?; // APP
+        Self::cmd(Cmd::set_wr_blk_erase_count(blocks.len() as u32), false)?; // ACMD23
+
+        let on_drop = OnDrop::new(|| Self::on_drop());
+
+        // sdmmc_v1 uses different cmd/dma order than v2, but only for writes
+        #[cfg(sdmmc_v1)]
+        let sent = Self::cmd(Cmd::write_multiple_blocks(address), true); // CMD25
+
+        let transfer = self.prepare_datapath_write(buffer, length_bytes, 9);
+        InterruptHandler::<T>::data_interrupts(true);
+
+        #[cfg(sdmmc_v2)]
+        let sent = Self::cmd(Cmd::write_multiple_blocks(address), true); // CMD25
+
+        let res = match sent {
+            Ok(()) => Self::wait_data_end().await,
+            Err(e) => Err(e),
+        };
+        Self::clear_interrupt_flags();
+
+        match res {
+            Ok(()) => on_drop.defuse(),
+            // Aborts the transfer if it's still running.
+            Err(_) => drop(on_drop),
+        }
+        Self::stop_datapath();
+        drop(transfer);
+
+        // The card keeps receiving blocks until told to stop, also after an error.
+        let stop = Self::cmd(Cmd::stop_transmission(), false); // CMD12
+        res.and(stop)?;
+        self.wait_write_done().await
+    }
+
+    /// Create a writer for a stream of bytes, e.g. a log, written to consecutive blocks from
+    /// `block_idx`.
+    ///
+    /// The bytes are collected in `buffer`, and written each time it's full with
+    /// [`write_blocks`](Self::write_blocks). Larger buffers give a higher throughput.
+    pub fn stream_writer<'a>(
+        &'a mut self,
+        block_idx: u32,
+        buffer: &'a mut [DataBlock],
+    ) -> BlockStreamWriter<'a, 'd, T, Dma> {
+        assert!(!buffer.is_empty());
+        BlockStreamWriter {
+            sdmmc: self,
+            buffer,
+            block_idx,
+            len: 0,
+        }
+    }
+
     /// Get a reference to the initialized card
     ///
     /// # Errors
@@ -1328,6 +1511,98 @@ impl<'d, T: Instance, Dma: SdmmcDma<T> + 'd> Drop for Sdmmc<'d, T, Dma> {
     }
 }
 
+/// Writer for a stream of bytes to consecutive blocks, created with [`Sdmmc::stream_writer`].
+///
+/// Dropping the writer discards the buffered bytes, call [`flush`](Self::flush) first.
+pub struct BlockStreamWriter<'a, 'd, T: Instance, Dma: SdmmcDma<T> + 'd> {
+    sdmmc: &'a mut Sdmmc<'d, T, Dma>,
+    buffer: &'a mut [DataBlock],
+    /// Block the buffer is written to.
+    block_idx: u32,
+    /// Number of bytes in the buffer.
+    len: usize,
+}
+
+impl<'a, 'd, T: Instance, Dma: SdmmcDma<T> + 'd> BlockStreamWriter<'a, 'd, T, Dma> {
+    /// Write bytes, writing the buffer to the card each time it's full.
+    ///
+    /// Returns the number of bytes taken from `data`. If writing the buffer to the card fails,
+    /// this is less than `data.len()`, and the error is returned by the next call instead. An
+    /// error is only returned if no byte was taken.
+    pub async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let mut written = 0;
+        while written < data.len() {
+            // Still full if writing it failed.
+            if self.len == self.buffer.len() * 512 {
+                match self.write_buffer().await {
+                    Ok(()) => {}
+                    Err(e) if written == 0 => return Err(e),
+                    Err(_) => break,
+                }
+            }
+
+            let block = &mut self.buffer[self.len / 512];
+            let offset = self.len % 512;
+            let n = (data.len() - written).min(512 - offset);
+            block[offset..offset + n].copy_from_slice(&data[written..written + n]);
+            self.len += n;
+            written += n;
+        }
+
+        // Write the buffer as soon as it's full. If this fails it's written again by the next
+        // call, the bytes are buffered already.
+        if self.len == self.buffer.len() * 512 {
+            let _ = self.write_buffer().await;
+        }
+        Ok(written)
+    }
+
+    /// Write all bytes, writing the buffer to the card each time it's full.
+    ///
+    /// On errors, the bytes taken before the error stay buffered.
+    pub async fn write_all(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let n = self.write(data).await?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Write the buffered bytes to the card.
+    ///
+    /// A partially filled block is padded with zeros, and the next bytes are written from the
+    /// start of the next block.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if self.len % 512 != 0 {
+            let block = &mut self.buffer[self.len / 512];
+            block[self.len % 512..].fill(0);
+            self.len = (self.len / 512 + 1) * 512;
+        }
+        self.write_buffer().await
+    }
+
+    /// Block the next bytes are written to.
+    pub fn block_idx(&self) -> u32 {
+        self.block_idx + (self.len / 512) as u32
+    }
+
+    /// Number of bytes buffered, not written to the card yet.
+    pub fn buffered(&self) -> usize {
+        self.len
+    }
+
+    async fn write_buffer(&mut self) -> Result<(), Error> {
+        let blocks = self.len / 512;
+        if blocks == 0 {
+            return Ok(());
+        }
+        self.sdmmc.write_blocks(self.block_idx, &self.buffer[..blocks]).await?;
+        self.block_idx += blocks as u32;
+        self.len = 0;
+        Ok(())
+    }
+}
+
 /// SD card Commands
 impl Cmd {
     const fn new(cmd: u8, arg: u32, resp: Response) -> Cmd {
@@ -1370,10 +1645,10 @@ impl Cmd {
         Cmd::new(9, rca, Response::Long)
     }
 
-    /// CMD12:
-    //const fn stop_transmission() -> Cmd {
-    //    Cmd::new(12, 0, Response::Short)
-    //}
+    /// CMD12: Stop Transmission
+    const fn stop_transmission() -> Cmd {
+        Cmd::new(12, 0, Response::Short)
+    }
 
     /// CMD13: Ask card to send status register
     /// ACMD13: SD Status
@@ -1392,15 +1667,25 @@ impl Cmd {
     }
 
     /// CMD18: Multiple Block Read
-    //const fn read_multiple_blocks(addr: u32) -> Cmd {
-    //    Cmd::new(18, addr, Response::Short)
-    //}
+    const fn read_multiple_blocks(addr: u32) -> Cmd {
+        Cmd::new(18, addr, Response::Short)
+    }
+
+    /// ACMD23: Number of blocks to pre-erase before the next multiple block write
+    const fn set_wr_blk_erase_count(count: u32) -> Cmd {
+        Cmd::new(23, count, Response::Short)
+    }
 
     /// CMD24: Block Write
     const fn write_single_block(addr: u32) -> Cmd {
         Cmd::new(24, addr, Response::Short)
     }
 
+    /// CMD25: Multiple Block Write
+    const fn write_multiple_blocks(addr: u32) -> Cmd {
+        Cmd::new(25, addr, Response::Short)
+    }
+
     const fn app_op_cmd(arg: u32) -> Cmd {
         Cmd::new(41, arg, Response::Short)
     }
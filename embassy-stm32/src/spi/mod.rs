@@ -9,7 +9,7 @@ use embassy_futures::join::join;
 use embassy_hal_internal::{into_ref, PeripheralRef};
 pub use embedded_hal_02::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
 
-use crate::dma::{slice_ptr_parts, word, ChannelAndRequest};
+use crate::dma::{slice_ptr_parts, word, ChannelAndRequest, Priority, TransferOptions};
 use crate::gpio::{AFType, AnyPin, Pull, SealedPin as _, Speed};
 use crate::mode::{Async, Blocking, Mode as PeriMode};
 use crate::pac::spi::{regs, vals, Spi as Regs};
@@ -40,6 +40,39 @@ pub enum BitOrder {
     MsbFirst,
 }
 
+/// Slave select (NSS) management.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlaveSelect {
+    /// NSS is managed by software, e.g. with a GPIO [`Output`](crate::gpio::Output).
+    Software,
+    /// NSS is driven by hardware, on the pin given to [`Spi::new_with_nss`].
+    ///
+    /// On SPI v3 and later it's active during transfers. On older versions it's active as long
+    /// as the peripheral is enabled, which is all the time after the first transfer.
+    HardwareOutput,
+    /// NSS is driven by hardware, and pulsed inactive between words, for devices latching each
+    /// word on a chip select edge.
+    ///
+    /// Only with [`Phase::CaptureOnFirstTransition`] on SPI v2.
+    #[cfg(not(any(spi_v1, spi_f1)))]
+    HardwarePulse,
+}
+
+/// Frame format.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameFormat {
+    /// Motorola SPI.
+    Motorola,
+    /// TI synchronous serial frame format.
+    ///
+    /// Each word is framed by a one clock pulse on NSS, driven by hardware. The mode and bit order
+    /// are fixed by the format, so [`Config::mode`] and [`Config::bit_order`] are ignored.
+    #[cfg(not(spi_f1))]
+    Ti,
+}
+
 /// SPI configuration.
 #[non_exhaustive]
 #[derive(Copy, Clone)]
@@ -50,6 +83,12 @@ pub struct Config {
     pub bit_order: BitOrder,
     /// Clock frequency.
     pub frequency: Hertz,
+    /// DMA request priority of the transfers.
+    pub dma_priority: Priority,
+    /// Slave select (NSS) management.
+    pub slave_select: SlaveSelect,
+    /// Frame format.
+    pub frame_format: FrameFormat,
 }
 
 impl Default for Config {
@@ -58,6 +97,9 @@ impl Default for Config {
             mode: MODE_0,
             bit_order: BitOrder::MsbFirst,
             frequency: Hertz(1_000_000),
+            dma_priority: Priority::default(),
+            slave_select: SlaveSelect::Software,
+            frame_format: FrameFormat::Motorola,
         }
     }
 }
@@ -90,6 +132,23 @@ impl Config {
             Polarity::IdleHigh => Pull::Up,
         }
     }
+
+    fn hardware_nss(&self) -> bool {
+        self.slave_select != SlaveSelect::Software
+    }
+
+    #[cfg(not(any(spi_v1, spi_f1)))]
+    fn nss_pulse(&self) -> bool {
+        self.slave_select == SlaveSelect::HardwarePulse
+    }
+
+    #[cfg(not(spi_f1))]
+    fn raw_frame_format(&self) -> u8 {
+        match self.frame_format {
+            FrameFormat::Motorola => 0,
+            FrameFormat::Ti => 1,
+        }
+    }
 }
 /// SPI driver.
 pub struct Spi<'d, T: Instance, M: PeriMode> {
@@ -97,10 +156,12 @@ pub struct Spi<'d, T: Instance, M: PeriMode> {
     sck: Option<PeripheralRef<'d, AnyPin>>,
     mosi: Option<PeripheralRef<'d, AnyPin>>,
     miso: Option<PeripheralRef<'d, AnyPin>>,
+    nss: Option<PeripheralRef<'d, AnyPin>>,
     tx_dma: Option<ChannelAndRequest<'d>>,
     rx_dma: Option<ChannelAndRequest<'d>>,
     _phantom: PhantomData<M>,
     current_word_size: word_impl::Config,
+    dma_priority: Priority,
 }
 
 impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
@@ -109,6 +170,7 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
         sck: Option<PeripheralRef<'d, AnyPin>>,
         mosi: Option<PeripheralRef<'d, AnyPin>>,
         miso: Option<PeripheralRef<'d, AnyPin>>,
+        nss: Option<PeripheralRef<'d, AnyPin>>,
         tx_dma: Option<ChannelAndRequest<'d>>,
         rx_dma: Option<ChannelAndRequest<'d>>,
         config: Config,
@@ -129,7 +191,9 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
         #[cfg(any(spi_v1, spi_f1))]
         {
             T::REGS.cr2().modify(|w| {
-                w.set_ssoe(false);
+                w.set_ssoe(config.hardware_nss());
+                #[cfg(spi_v1)]
+                w.set_frf(vals::Frf::from_bits(config.raw_frame_format()));
             });
             T::REGS.cr1().modify(|w| {
                 w.set_cpha(cpha);
@@ -140,7 +204,7 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
                 w.set_spe(true);
                 w.set_lsbfirst(lsbfirst);
                 w.set_ssi(true);
-                w.set_ssm(true);
+                w.set_ssm(!config.hardware_nss());
                 w.set_crcen(false);
                 w.set_bidimode(vals::Bidimode::UNIDIRECTIONAL);
                 if mosi.is_none() {
@@ -155,7 +219,9 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
                 let (ds, frxth) = <u8 as SealedWord>::CONFIG;
                 w.set_frxth(frxth);
                 w.set_ds(ds);
-                w.set_ssoe(false);
+                w.set_ssoe(config.hardware_nss());
+                w.set_nssp(config.nss_pulse());
+                w.set_frf(vals::Frf::from_bits(config.raw_frame_format()));
             });
             T::REGS.cr1().modify(|w| {
                 w.set_cpha(cpha);
@@ -165,7 +231,7 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
                 w.set_br(br);
                 w.set_lsbfirst(lsbfirst);
                 w.set_ssi(true);
-                w.set_ssm(true);
+                w.set_ssm(!config.hardware_nss());
                 w.set_crcen(false);
                 w.set_bidimode(vals::Bidimode::UNIDIRECTIONAL);
                 w.set_spe(true);
@@ -175,15 +241,18 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
         {
             T::REGS.ifcr().write(|w| w.0 = 0xffff_ffff);
             T::REGS.cfg2().modify(|w| {
-                //w.set_ssoe(true);
-                w.set_ssoe(false);
+                w.set_ssoe(config.hardware_nss());
                 w.set_cpha(cpha);
                 w.set_cpol(cpol);
                 w.set_lsbfirst(lsbfirst);
-                w.set_ssm(true);
+                w.set_ssm(!config.hardware_nss());
                 w.set_master(vals::Master::MASTER);
                 w.set_comm(vals::Comm::FULLDUPLEX);
-                w.set_ssom(vals::Ssom::ASSERTED);
+                w.set_ssom(match config.nss_pulse() {
+                    true => vals::Ssom::NOTASSERTED,
+                    false => vals::Ssom::ASSERTED,
+                });
+                w.set_sp(vals::Sp::from_bits(config.raw_frame_format()));
                 w.set_midi(0);
                 w.set_mssi(0);
                 w.set_afcntr(true);
@@ -209,15 +278,19 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
             sck,
             mosi,
             miso,
+            nss,
             tx_dma,
             rx_dma,
             current_word_size: <u8 as SealedWord>::CONFIG,
+            dma_priority: config.dma_priority,
             _phantom: PhantomData,
         }
     }
 
     /// Reconfigures it with the supplied config.
     pub fn set_config(&mut self, config: &Config) -> Result<(), ()> {
+        self.dma_priority = config.dma_priority;
+
         let cpha = config.raw_phase();
         let cpol = config.raw_polarity();
 
@@ -228,23 +301,52 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
         let br = compute_baud_rate(pclk, freq);
 
         #[cfg(any(spi_v1, spi_f1, spi_v2))]
-        T::REGS.cr1().modify(|w| {
-            w.set_cpha(cpha);
-            w.set_cpol(cpol);
-            w.set_br(br);
-            w.set_lsbfirst(lsbfirst);
-        });
+        {
+            // The frame format and NSS pulse mode can only be changed while the SPI is disabled.
+            T::REGS.cr1().modify(|w| {
+                w.set_spe(false);
+            });
+            T::REGS.cr2().modify(|w| {
+                w.set_ssoe(config.hardware_nss());
+                #[cfg(spi_v2)]
+                w.set_nssp(config.nss_pulse());
+                #[cfg(not(spi_f1))]
+                w.set_frf(vals::Frf::from_bits(config.raw_frame_format()));
+            });
+            T::REGS.cr1().modify(|w| {
+                w.set_cpha(cpha);
+                w.set_cpol(cpol);
+                w.set_br(br);
+                w.set_lsbfirst(lsbfirst);
+                w.set_ssm(!config.hardware_nss());
+                w.set_spe(true);
+            });
+        }
 
         #[cfg(any(spi_v3, spi_v4, spi_v5))]
         {
+            // Configuration registers are write-protected while the SPI is enabled.
+            T::REGS.cr1().modify(|w| {
+                w.set_spe(false);
+            });
             T::REGS.cfg2().modify(|w| {
                 w.set_cpha(cpha);
                 w.set_cpol(cpol);
                 w.set_lsbfirst(lsbfirst);
+                w.set_ssoe(config.hardware_nss());
+                w.set_ssm(!config.hardware_nss());
+                w.set_ssom(match config.nss_pulse() {
+                    true => vals::Ssom::NOTASSERTED,
+                    false => vals::Ssom::ASSERTED,
+                });
+                w.set_sp(vals::Sp::from_bits(config.raw_frame_format()));
             });
             T::REGS.cfg1().modify(|w| {
                 w.set_mbr(br);
             });
+            T::REGS.cr1().modify(|w| {
+                w.set_spe(true);
+            });
         }
         Ok(())
     }
@@ -283,10 +385,38 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
         let pclk = T::frequency();
         let frequency = compute_frequency(pclk, br);
 
+        #[cfg(any(spi_v1, spi_v2))]
+        let cr2 = T::REGS.cr2().read();
+
+        #[cfg(spi_v2)]
+        let nss_pulse = cr2.nssp();
+        #[cfg(any(spi_v3, spi_v4, spi_v5))]
+        let nss_pulse = cfg.ssom() == vals::Ssom::NOTASSERTED;
+
+        let slave_select = match cfg.ssm() {
+            true => SlaveSelect::Software,
+            #[cfg(not(any(spi_v1, spi_f1)))]
+            false if nss_pulse => SlaveSelect::HardwarePulse,
+            false => SlaveSelect::HardwareOutput,
+        };
+
+        #[cfg(any(spi_v1, spi_v2))]
+        let ti = cr2.frf().to_bits() != 0;
+        #[cfg(any(spi_v3, spi_v4, spi_v5))]
+        let ti = cfg.sp().to_bits() != 0;
+
+        #[cfg(not(spi_f1))]
+        let frame_format = if ti { FrameFormat::Ti } else { FrameFormat::Motorola };
+        #[cfg(spi_f1)]
+        let frame_format = FrameFormat::Motorola;
+
         Config {
             mode: Mode { polarity, phase },
             bit_order,
             frequency,
+            dma_priority: self.dma_priority,
+            slave_select,
+            frame_format,
         }
     }
 
@@ -412,6 +542,31 @@ impl<'d, T: Instance> Spi<'d, T, Blocking> {
             new_pin!(miso, AFType::Input, Speed::VeryHigh),
             None,
             None,
+            None,
+            config,
+        )
+    }
+
+    /// Create a new blocking SPI driver, with the NSS pin driven by hardware.
+    ///
+    /// Set [`Config::slave_select`] to [`SlaveSelect::HardwareOutput`] or
+    /// [`SlaveSelect::HardwarePulse`], or [`Config::frame_format`] to [`FrameFormat::Ti`].
+    pub fn new_blocking_with_nss(
+        peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        nss: impl Peripheral<P = impl CsPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            new_pin!(sck, AFType::OutputPushPull, Speed::VeryHigh, config.sck_pull_mode()),
+            new_pin!(mosi, AFType::OutputPushPull, Speed::VeryHigh),
+            new_pin!(miso, AFType::Input, Speed::VeryHigh),
+            new_pin!(nss, AFType::OutputPushPull, Speed::VeryHigh),
+            None,
+            None,
             config,
         )
     }
@@ -430,6 +585,7 @@ impl<'d, T: Instance> Spi<'d, T, Blocking> {
             new_pin!(miso, AFType::Input, Speed::VeryHigh),
             None,
             None,
+            None,
             config,
         )
     }
@@ -448,6 +604,7 @@ impl<'d, T: Instance> Spi<'d, T, Blocking> {
             None,
             None,
             None,
+            None,
             config,
         )
     }
@@ -467,6 +624,7 @@ impl<'d, T: Instance> Spi<'d, T, Blocking> {
             None,
             None,
             None,
+            None,
             config,
         )
     }
@@ -488,6 +646,33 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
             new_pin!(sck, AFType::OutputPushPull, Speed::VeryHigh, config.sck_pull_mode()),
             new_pin!(mosi, AFType::OutputPushPull, Speed::VeryHigh),
             new_pin!(miso, AFType::Input, Speed::VeryHigh),
+            None,
+            new_dma!(tx_dma),
+            new_dma!(rx_dma),
+            config,
+        )
+    }
+
+    /// Create a new SPI driver, with the NSS pin driven by hardware.
+    ///
+    /// Set [`Config::slave_select`] to [`SlaveSelect::HardwareOutput`] or
+    /// [`SlaveSelect::HardwarePulse`], or [`Config::frame_format`] to [`FrameFormat::Ti`].
+    pub fn new_with_nss(
+        peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        nss: impl Peripheral<P = impl CsPin<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            new_pin!(sck, AFType::OutputPushPull, Speed::VeryHigh, config.sck_pull_mode()),
+            new_pin!(mosi, AFType::OutputPushPull, Speed::VeryHigh),
+            new_pin!(miso, AFType::Input, Speed::VeryHigh),
+            new_pin!(nss, AFType::OutputPushPull, Speed::VeryHigh),
             new_dma!(tx_dma),
             new_dma!(rx_dma),
             config,
@@ -508,6 +693,7 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
             None,
             new_pin!(miso, AFType::Input, Speed::VeryHigh),
             None,
+            None,
             new_dma!(rx_dma),
             config,
         )
@@ -526,6 +712,7 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
             new_pin!(sck, AFType::OutputPushPull, Speed::VeryHigh, config.sck_pull_mode()),
             new_pin!(mosi, AFType::OutputPushPull, Speed::VeryHigh),
             None,
+            None,
             new_dma!(tx_dma),
             None,
             config,
@@ -546,6 +733,7 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
             None,
             new_pin!(mosi, AFType::OutputPushPull, Speed::VeryHigh),
             None,
+            None,
             new_dma!(tx_dma),
             None,
             config,
@@ -569,7 +757,7 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
         config.bit_order = BitOrder::MsbFirst;
         config.frequency = freq;
 
-        Self::new_inner(peri, None, None, None, new_dma!(tx_dma), new_dma!(rx_dma), config)
+        Self::new_inner(peri, None, None, None, None, new_dma!(tx_dma), new_dma!(rx_dma), config)
     }
 
     #[allow(dead_code)]
@@ -579,7 +767,14 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
         rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
         config: Config,
     ) -> Self {
-        Self::new_inner(peri, None, None, None, new_dma!(tx_dma), new_dma!(rx_dma), config)
+        Self::new_inner(peri, None, None, None, None, new_dma!(tx_dma), new_dma!(rx_dma), config)
+    }
+
+    fn dma_options(&self) -> TransferOptions {
+        TransferOptions {
+            priority: self.dma_priority,
+            ..Default::default()
+        }
     }
 
     /// SPI write, using DMA.
@@ -593,8 +788,9 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
             w.set_spe(false);
         });
 
+        let options = self.dma_options();
         let tx_dst = T::REGS.tx_ptr();
-        let tx_f = unsafe { self.tx_dma.as_mut().unwrap().write(data, tx_dst, Default::default()) };
+        let tx_f = unsafe { self.tx_dma.as_mut().unwrap().write(data, tx_dst, options) };
 
         set_txdmaen(T::REGS, true);
         T::REGS.cr1().modify(|w| {
@@ -631,8 +827,9 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
         let clock_byte_count = data.len();
 
+        let options = self.dma_options();
         let rx_src = T::REGS.rx_ptr();
-        let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read(rx_src, data, Default::default()) };
+        let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read(rx_src, data, options) };
 
         let tx_dst = T::REGS.tx_ptr();
         let clock_byte = 0x00u8;
@@ -640,7 +837,7 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
             self.tx_dma
                 .as_mut()
                 .unwrap()
-                .write_repeated(&clock_byte, clock_byte_count, tx_dst, Default::default())
+                .write_repeated(&clock_byte, clock_byte_count, tx_dst, options)
         };
 
         set_txdmaen(T::REGS, true);
@@ -678,16 +875,12 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
         set_rxdmaen(T::REGS, true);
 
+        let options = self.dma_options();
         let rx_src = T::REGS.rx_ptr();
-        let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read_raw(rx_src, read, Default::default()) };
+        let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read_raw(rx_src, read, options) };
 
         let tx_dst = T::REGS.tx_ptr();
-        let tx_f = unsafe {
-            self.tx_dma
-                .as_mut()
-                .unwrap()
-                .write_raw(write, tx_dst, Default::default())
-        };
+        let tx_f = unsafe { self.tx_dma.as_mut().unwrap().write_raw(write, tx_dst, options) };
 
         set_txdmaen(T::REGS, true);
         T::REGS.cr1().modify(|w| {
@@ -728,6 +921,7 @@ impl<'d, T: Instance, M: PeriMode> Drop for Spi<'d, T, M> {
         self.sck.as_ref().map(|x| x.set_as_disconnected());
         self.mosi.as_ref().map(|x| x.set_as_disconnected());
         self.miso.as_ref().map(|x| x.set_as_disconnected());
+        self.nss.as_ref().map(|x| x.set_as_disconnected());
 
         T::disable();
     }
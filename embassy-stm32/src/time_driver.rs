@@ -1,13 +1,20 @@
+//! Time driver
+//!
+//! The timer selected with the `time-driver-*` feature counts at [`TICK_HZ`] by default. Its rate
+//! can be lowered at runtime with [`set_counter_hz`], e.g. while the application is mostly idle:
+//! the timer then takes fewer interrupts to keep track of time, at the cost of a coarser
+//! resolution for timers and alarms. [`Instant`](embassy_time::Instant)s stay in [`TICK_HZ`]
+//! ticks and monotonic across the change.
 #![allow(non_snake_case)]
 
-use core::cell::Cell;
+use core::cell::{Cell, UnsafeCell};
 use core::sync::atomic::{compiler_fence, AtomicU32, AtomicU8, Ordering};
 use core::{mem, ptr};
 
 use critical_section::CriticalSection;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
-use embassy_time_driver::{AlarmHandle, Driver, TICK_HZ};
+use embassy_time_driver::{AlarmHandle, Driver, ScaledDriver, TickScaler, TICK_HZ};
 use stm32_metapac::timer::{regs, TimGp16};
 
 use crate::interrupt::typelevel::Interrupt;
@@ -15,6 +22,7 @@ use crate::pac::timer::vals;
 use crate::rcc::SealedRccPeripheral;
 #[cfg(feature = "low-power")]
 use crate::rtc::Rtc;
+use crate::time::Hertz;
 use crate::timer::{CoreInstance, GeneralInstance1Channel};
 use crate::{interrupt, peripherals};
 
@@ -210,7 +218,8 @@ fn regs_gp16() -> TimGp16 {
 }
 
 // Clock timekeeping works with something we call "periods", which are time intervals
-// of 2^15 ticks. The Clock counter value is 16 bits, so one "overflow cycle" is 2 periods.
+// of 2^15 counts of the timer. The timer counts at `TICK_HZ` unless scaled with `set_counter_hz`,
+// in which case `RtcDriver::scaler` converts counts to ticks. The Clock counter value is 16 bits, so one "overflow cycle" is 2 periods.
 //
 // A `period` count is maintained in parallel to the Timer hardware `counter`, like this:
 // - `period` and `counter` start at 0
@@ -232,6 +241,8 @@ fn calc_now(period: u32, counter: u16) -> u64 {
 
 struct AlarmState {
     timestamp: Cell<u64>,
+    /// Timer count at which to fire the alarm, as returned by `RtcDriver::counter`.
+    counter: Cell<u64>,
 
     // This is really a Option<(fn(*mut ()), *mut ())>
     // but fn pointers aren't allowed in const yet
@@ -241,10 +252,63 @@ struct AlarmState {
 
 unsafe impl Send for AlarmState {}
 
+/// The [`TickScaler`] of the driver, readable without a critical section so that `now()` stays
+/// cheap.
+///
+/// It's only written in critical sections, by `set_counter_hz`, and read optimistically: the
+/// sequence number is odd during a write, and changes with it, so readers retry when it changed
+/// under them.
+struct ScalerCell {
+    seq: AtomicU32,
+    scaler: UnsafeCell<TickScaler>,
+}
+
+unsafe impl Sync for ScalerCell {}
+
+impl ScalerCell {
+    const fn new(scaler: TickScaler) -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            scaler: UnsafeCell::new(scaler),
+        }
+    }
+
+    /// Call `f` with the scaler, and retry if it changed in the meantime, so that values read by
+    /// `f` are consistent with it.
+    fn read<R>(&self, mut f: impl FnMut(TickScaler) -> R) -> R {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                continue;
+            }
+            let scaler = unsafe { ptr::read_volatile(self.scaler.get()) };
+            let res = f(scaler);
+            compiler_fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return res;
+            }
+        }
+    }
+
+    fn get(&self) -> TickScaler {
+        self.read(|scaler| scaler)
+    }
+
+    fn set(&self, _cs: CriticalSection, scaler: TickScaler) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        compiler_fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.scaler.get(), scaler) };
+        compiler_fence(Ordering::Release);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
+
 impl AlarmState {
     const fn new() -> Self {
         Self {
             timestamp: Cell::new(u64::MAX),
+            counter: Cell::new(u64::MAX),
             callback: Cell::new(ptr::null()),
             ctx: Cell::new(ptr::null_mut()),
         }
@@ -257,6 +321,8 @@ pub(crate) struct RtcDriver {
     alarm_count: AtomicU8,
     /// Timestamp at which to fire alarm. u64::MAX if no alarm is scheduled.
     alarms: Mutex<CriticalSectionRawMutex, [AlarmState; ALARM_COUNT]>,
+    /// Conversion from timer counts to ticks.
+    scaler: ScalerCell,
     #[cfg(feature = "low-power")]
     rtc: Mutex<CriticalSectionRawMutex, Cell<Option<&'static Rtc>>>,
 }
@@ -268,6 +334,7 @@ embassy_time_driver::time_driver_impl!(static DRIVER: RtcDriver = RtcDriver {
     period: AtomicU32::new(0),
     alarm_count: AtomicU8::new(0),
     alarms: Mutex::const_new(CriticalSectionRawMutex::new(), [ALARM_STATE_NEW; ALARM_COUNT]),
+    scaler: ScalerCell::new(TickScaler::new(TICK_HZ as u32)),
     #[cfg(feature = "low-power")]
     rtc: Mutex::const_new(CriticalSectionRawMutex::new(), Cell::new(None)),
 });
@@ -355,7 +422,7 @@ impl RtcDriver {
             r.dier().modify(move |w| {
                 for n in 0..ALARM_COUNT {
                     let alarm = &self.alarms.borrow(cs)[n];
-                    let at = alarm.timestamp.get();
+                    let at = alarm.counter.get();
 
                     if at < t + 0xc000 {
                         // just enable it. `set_alarm` has already set the correct CCR val.
@@ -366,6 +433,67 @@ impl RtcDriver {
         })
     }
 
+    /// Timer count since boot.
+    fn counter(&self) -> u64 {
+        let r = regs_gp16();
+
+        let period = self.period.load(Ordering::Relaxed);
+        compiler_fence(Ordering::Acquire);
+        let counter = r.cnt().read().cnt();
+        calc_now(period, counter)
+    }
+
+    /// Set the alarms again, after the conversion from counts to ticks has changed.
+    fn rearm_alarms(&self, cs: CriticalSection) {
+        for n in 0..ALARM_COUNT {
+            let timestamp = self.alarms.borrow(cs)[n].timestamp.get();
+            if timestamp == u64::MAX {
+                continue;
+            }
+
+            // An alarm that is now in the past must still fire, nobody is waiting on `set_alarm`.
+            let alarm_handle = unsafe { AlarmHandle::new(n as u8) };
+            if !self.set_alarm(alarm_handle, timestamp) {
+                self.trigger_alarm(n, cs);
+            }
+        }
+    }
+
+    fn set_counter_hz(&self, hz: u32) -> bool {
+        let r = regs_gp16();
+        let timer_freq = T::frequency().0;
+
+        if hz == 0 || hz > timer_freq {
+            return false;
+        }
+        let psc: u16 = match (timer_freq / hz - 1).try_into() {
+            Err(_) => return false,
+            Ok(n) => n,
+        };
+
+        critical_section::with(|cs| {
+            let period = self.period.load(Ordering::Relaxed);
+            let cnt = r.cnt().read().cnt();
+            let counter = calc_now(period, cnt);
+
+            // The prescaler is only loaded on an update event. Generate one without an update
+            // interrupt, and put back the count it cleared.
+            r.psc().write_value(psc);
+            r.cr1().modify(|w| w.set_urs(vals::Urs::COUNTERONLY));
+            r.egr().write(|w| w.set_ug(true));
+            r.cr1().modify(|w| w.set_urs(vals::Urs::ANYEVENT));
+            r.cnt().write(|w| w.set_cnt(cnt));
+
+            let mut scaler = self.scaler.get();
+            scaler.set_counter_hz(counter, timer_freq / (psc as u32 + 1));
+            self.scaler.set(cs, scaler);
+
+            self.rearm_alarms(cs);
+        });
+
+        true
+    }
+
     fn get_alarm<'a>(&'a self, cs: CriticalSection<'a>, alarm: AlarmHandle) -> &'a AlarmState {
         // safety: we're allowed to assume the AlarmState is created by us, and
         // we never create one that's out of bounds.
@@ -375,6 +503,7 @@ impl RtcDriver {
     fn trigger_alarm(&self, n: usize, cs: CriticalSection) {
         let alarm = &self.alarms.borrow(cs)[n];
         alarm.timestamp.set(u64::MAX);
+        alarm.counter.set(u64::MAX);
 
         // Call after clearing alarm, so the callback can set another alarm.
 
@@ -407,7 +536,8 @@ impl RtcDriver {
     #[cfg(feature = "low-power")]
     /// Add the given offset to the current time
     fn add_time(&self, offset: embassy_time::Duration, cs: CriticalSection) {
-        let offset = offset.as_ticks();
+        let counter_hz = self.scaler.get().counter_hz();
+        let offset = (offset.as_ticks() as u128 * counter_hz as u128 / TICK_HZ as u128) as u64;
         let cnt = regs_gp16().cnt().read().cnt() as u32;
         let period = self.period.load(Ordering::SeqCst);
 
@@ -437,12 +567,7 @@ impl RtcDriver {
         regs_gp16().cnt().write(|w| w.set_cnt(cnt as u16));
 
         // Now, recompute all alarms
-        for i in 0..ALARM_COUNT {
-            let alarm_handle = unsafe { AlarmHandle::new(i as u8) };
-            let alarm = self.get_alarm(cs, alarm_handle);
-
-            self.set_alarm(alarm_handle, alarm.timestamp.get());
-        }
+        self.rearm_alarms(cs);
     }
 
     #[cfg(feature = "low-power")]
@@ -517,12 +642,7 @@ impl RtcDriver {
 
 impl Driver for RtcDriver {
     fn now(&self) -> u64 {
-        let r = regs_gp16();
-
-        let period = self.period.load(Ordering::Relaxed);
-        compiler_fence(Ordering::Acquire);
-        let counter = r.cnt().read().cnt();
-        calc_now(period, counter)
+        self.scaler.read(|scaler| scaler.to_ticks(self.counter()))
     }
 
     unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
@@ -561,16 +681,21 @@ impl Driver for RtcDriver {
                 r.dier().modify(|w| w.set_ccie(n + 1, false));
 
                 alarm.timestamp.set(u64::MAX);
+                alarm.counter.set(u64::MAX);
 
                 return false;
             }
 
+            // The first count at which the timestamp has been reached.
+            let at = self.scaler.get().to_counter(timestamp);
+            alarm.counter.set(at);
+
             // Write the CCR value regardless of whether we're going to enable it now or not.
             // This way, when we enable it later, the right value is already set.
-            r.ccr(n + 1).write(|w| w.set_ccr(timestamp as u16));
+            r.ccr(n + 1).write(|w| w.set_ccr(at as u16));
 
             // Enable it if it'll happen soon. Otherwise, `next_period` will enable it.
-            let diff = timestamp - t;
+            let diff = at.saturating_sub(self.counter());
             r.dier().modify(|w| w.set_ccie(n + 1, diff < 0xc000));
 
             // Reevaluate if the alarm timestamp is still in the future
@@ -583,6 +708,7 @@ impl Driver for RtcDriver {
                 r.dier().modify(|w| w.set_ccie(n + 1, false));
 
                 alarm.timestamp.set(u64::MAX);
+                alarm.counter.set(u64::MAX);
 
                 return false;
             }
@@ -593,6 +719,33 @@ impl Driver for RtcDriver {
     }
 }
 
+impl ScaledDriver for RtcDriver {
+    fn counter_hz(&self) -> u32 {
+        self.scaler.get().counter_hz()
+    }
+
+    fn set_counter_hz(&self, hz: u32) -> bool {
+        RtcDriver::set_counter_hz(self, hz)
+    }
+}
+
+/// Get the rate the time driver timer is counting at.
+pub fn counter_hz() -> Hertz {
+    Hertz(DRIVER.counter_hz())
+}
+
+/// Change the rate the time driver timer is counting at.
+///
+/// The timer counts at the closest rate at or above `hz` that the timer clock can be divided to,
+/// see [`counter_hz`]. Returns `false`, leaving the rate unchanged, if there is no such rate.
+///
+/// Time keeps going at [`TICK_HZ`] ticks per second from the current [`Instant`](embassy_time::Instant),
+/// and alarms fire at or after their timestamp. The resolution of timers is limited by the rate
+/// though: a timer can't expire before the next count of the timer.
+pub fn set_counter_hz(hz: Hertz) -> bool {
+    RtcDriver::set_counter_hz(&DRIVER, hz.0)
+}
+
 #[cfg(feature = "low-power")]
 pub(crate) fn get_driver() -> &'static RtcDriver {
     &DRIVER
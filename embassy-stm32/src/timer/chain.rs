@@ -0,0 +1,227 @@
+//! Timer chaining, with the trigger output of a master timer driving the slave mode controller of
+//! a slave timer.
+//!
+//! The internal triggers (`ITRx`) connecting the timers differ between chip families, so the
+//! connections available on a chip are checked at compile time with [`TriggerSource`]. Chaining
+//! is used to:
+//!
+//! - start PWM timers in sync, with the master in [`MasterMode::Enable`] and the slaves in
+//!   [`SlaveMode::Trigger`]. Stop the timers, configure the chain, then start the master.
+//! - run a timer only while another one is enabled, with [`SlaveMode::Gated`].
+//! - build a long counter out of two timers, see [`ChainedCounter`].
+//!
+//! ```ignore
+//! let master = SimplePwm::new(p.TIM1, Some(tim1_ch1), None, None, None, khz(10), Default::default());
+//! let slave = SimplePwm::new(p.TIM3, Some(tim3_ch1), None, None, None, khz(10), Default::default());
+//! master.timer().stop();
+//! slave.timer().stop();
+//!
+//! TriggerChain::new(master.timer(), slave.timer())
+//!     .master_mode(MasterMode::Enable)
+//!     .slave_mode(SlaveMode::Trigger)
+//!     .apply();
+//! master.timer().start();
+//! ```
+
+use embassy_hal_internal::Peripheral;
+
+use super::low_level::{MasterMode, SlaveMode, Timer};
+use super::{GeneralInstance4Channel, TimerBits};
+use crate::time::Hertz;
+
+/// Chains a master timer to a slave timer, see the [module documentation](crate::timer::chain).
+pub struct TriggerChain<'a, 'd, M: GeneralInstance4Channel, S: GeneralInstance4Channel> {
+    master: &'a Timer<'d, M>,
+    slave: &'a Timer<'d, S>,
+    master_mode: MasterMode,
+    slave_mode: SlaveMode,
+    synchronize: bool,
+}
+
+impl<'a, 'd, M: TriggerSource<S>, S: GeneralInstance4Channel> TriggerChain<'a, 'd, M, S> {
+    /// Chain `slave` to `master`.
+    ///
+    /// By default, the slave starts when the master is enabled: [`MasterMode::Enable`] and
+    /// [`SlaveMode::Trigger`].
+    pub fn new(master: &'a Timer<'d, M>, slave: &'a Timer<'d, S>) -> Self {
+        Self {
+            master,
+            slave,
+            master_mode: MasterMode::Enable,
+            slave_mode: SlaveMode::Trigger,
+            synchronize: false,
+        }
+    }
+
+    /// Set the trigger output of the master.
+    pub fn master_mode(mut self, mode: MasterMode) -> Self {
+        self.master_mode = mode;
+        self
+    }
+
+    /// Set the reaction of the slave to the trigger.
+    pub fn slave_mode(mut self, mode: SlaveMode) -> Self {
+        self.slave_mode = mode;
+        self
+    }
+
+    /// Delay the master by the trigger latency, so that it starts exactly in sync with the slave.
+    ///
+    /// This is only needed when the master is itself triggered by an external input or another
+    /// timer.
+    pub fn synchronize(mut self, enable: bool) -> Self {
+        self.synchronize = enable;
+        self
+    }
+
+    /// Configure the timers.
+    pub fn apply(self) {
+        self.master.set_master_mode(self.master_mode);
+        self.master.set_master_slave_mode(self.synchronize);
+
+        // The trigger input must only be changed with the slave mode controller disabled.
+        self.slave.set_slave_mode(SlaveMode::Disabled);
+        self.slave.set_trigger_source(M::ITR);
+        self.slave.set_slave_mode(self.slave_mode);
+    }
+}
+
+/// Long counter made of two timers, with the slave counting the overflows of the master.
+///
+/// Two 16-bit timers make a 32-bit counter, and a 32-bit master a 48-bit one.
+pub struct ChainedCounter<'d, M: GeneralInstance4Channel, S: GeneralInstance4Channel> {
+    master: Timer<'d, M>,
+    slave: Timer<'d, S>,
+}
+
+impl<'d, M: TriggerSource<S>, S: GeneralInstance4Channel> ChainedCounter<'d, M, S> {
+    /// Create a counter ticking at `frequency`, and start it.
+    pub fn new(master: impl Peripheral<P = M> + 'd, slave: impl Peripheral<P = S> + 'd, frequency: Hertz) -> Self {
+        let master = Timer::new(master);
+        let slave = Timer::new(slave);
+
+        master.set_tick_frequency(frequency);
+        // The slave prescaler divides the trigger edges, it must count every one of them.
+        slave.set_tick_frequency(crate::rcc::frequency::<S>());
+        TriggerChain::new(&master, &slave)
+            .master_mode(MasterMode::Update)
+            .slave_mode(SlaveMode::ExternalClock)
+            .apply();
+
+        slave.start();
+        master.start();
+
+        Self { master, slave }
+    }
+
+    /// Get the counter value.
+    ///
+    /// The counter wraps around after `2^(master bits + slave bits)` ticks.
+    pub fn count(&self) -> u64 {
+        let shift = match M::BITS {
+            TimerBits::Bits16 => 16,
+            #[cfg(not(stm32l0))]
+            TimerBits::Bits32 => 32,
+        };
+        loop {
+            // Read the slave again, in case the master overflowed in between.
+            let high = self.slave.get_counter();
+            let low = self.master.get_counter();
+            if self.slave.get_counter() == high {
+                return ((high as u64) << shift) | low as u64;
+            }
+        }
+    }
+
+    /// Reset the counter to 0.
+    pub fn reset(&self) {
+        self.master.stop();
+        self.master.reset();
+        self.slave.reset();
+        self.master.start();
+    }
+
+    /// Stop the counter.
+    pub fn stop(&self) {
+        self.master.stop();
+    }
+
+    /// Start the counter again.
+    pub fn start(&self) {
+        self.master.start();
+    }
+}
+
+pub(crate) trait SealedTriggerSource<S: GeneralInstance4Channel> {
+    const ITR: u8;
+}
+
+/// Master timer trait, implemented for the timers whose trigger output is wired to an internal
+/// trigger of the slave timer `S`.
+#[allow(private_bounds)]
+pub trait TriggerSource<S: GeneralInstance4Channel>: SealedTriggerSource<S> + GeneralInstance4Channel {}
+
+#[allow(unused_macros)]
+macro_rules! impl_trigger_source {
+    ($slave:ident, $master:ident, $itr:expr) => {
+        foreach_peripheral!(
+            (timer, $master) => {
+                impl SealedTriggerSource<crate::peripherals::$slave> for crate::peripherals::$master {
+                    const ITR: u8 = $itr;
+                }
+
+                impl TriggerSource<crate::peripherals::$slave> for crate::peripherals::$master {}
+            };
+        );
+    };
+}
+
+// Internal trigger connections, RM0008, RM0033 and RM0090 "TIMx internal trigger connection".
+#[cfg(any(stm32f1, stm32f2, stm32f4))]
+foreach_peripheral!(
+    (timer, TIM1) => {
+        impl_trigger_source!(TIM1, TIM5, 0);
+        impl_trigger_source!(TIM1, TIM2, 1);
+        impl_trigger_source!(TIM1, TIM3, 2);
+        impl_trigger_source!(TIM1, TIM4, 3);
+    };
+    (timer, TIM8) => {
+        impl_trigger_source!(TIM8, TIM1, 0);
+        impl_trigger_source!(TIM8, TIM2, 1);
+        impl_trigger_source!(TIM8, TIM4, 2);
+        impl_trigger_source!(TIM8, TIM5, 3);
+    };
+    (timer, TIM2) => {
+        impl_trigger_source!(TIM2, TIM1, 0);
+        impl_trigger_source!(TIM2, TIM8, 1);
+        impl_trigger_source!(TIM2, TIM3, 2);
+        impl_trigger_source!(TIM2, TIM4, 3);
+    };
+    (timer, TIM3) => {
+        impl_trigger_source!(TIM3, TIM1, 0);
+        impl_trigger_source!(TIM3, TIM2, 1);
+        impl_trigger_source!(TIM3, TIM5, 2);
+        impl_trigger_source!(TIM3, TIM4, 3);
+    };
+    (timer, TIM4) => {
+        impl_trigger_source!(TIM4, TIM1, 0);
+        impl_trigger_source!(TIM4, TIM2, 1);
+        impl_trigger_source!(TIM4, TIM3, 2);
+        impl_trigger_source!(TIM4, TIM8, 3);
+    };
+    (timer, TIM5) => {
+        impl_trigger_source!(TIM5, TIM2, 0);
+        impl_trigger_source!(TIM5, TIM3, 1);
+        impl_trigger_source!(TIM5, TIM4, 2);
+        impl_trigger_source!(TIM5, TIM8, 3);
+    };
+    // ITR2 and ITR3 of TIM9 and TIM12 are the compare outputs of TIM10/11 and TIM13/14, not TRGO.
+    (timer, TIM9) => {
+        impl_trigger_source!(TIM9, TIM2, 0);
+        impl_trigger_source!(TIM9, TIM3, 1);
+    };
+    (timer, TIM12) => {
+        impl_trigger_source!(TIM12, TIM4, 0);
+        impl_trigger_source!(TIM12, TIM5, 1);
+    };
+);
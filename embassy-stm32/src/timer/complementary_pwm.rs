@@ -93,6 +93,11 @@ impl<'d, T: AdvancedInstance4Channel> ComplementaryPwm<'d, T> {
         this
     }
 
+    /// Get the low-level timer driver, e.g. to [chain](super::chain) the timer to other timers.
+    pub fn timer(&self) -> &Timer<'d, T> {
+        &self.inner
+    }
+
     /// Enable the given channel.
     pub fn enable(&mut self, channel: Channel) {
         self.inner.enable_channel(channel, true);
@@ -174,6 +174,60 @@ impl From<OutputPolarity> for bool {
     }
 }
 
+/// Trigger output (TRGO) of a master timer.
+#[derive(Clone, Copy)]
+pub enum MasterMode {
+    /// TRGO pulses when the counter is reset by software or by the slave mode controller.
+    Reset,
+    /// TRGO is high while the counter is enabled.
+    Enable,
+    /// TRGO pulses on each update event, e.g. when the counter overflows.
+    Update,
+    /// TRGO pulses on each capture or compare match of channel 1.
+    ComparePulse,
+    /// TRGO follows the OCxREF signal of a channel.
+    Compare(Channel),
+}
+
+impl MasterMode {
+    fn to_bits(self) -> u8 {
+        match self {
+            MasterMode::Reset => 0b000,
+            MasterMode::Enable => 0b001,
+            MasterMode::Update => 0b010,
+            MasterMode::ComparePulse => 0b011,
+            MasterMode::Compare(channel) => 0b100 + channel.index() as u8,
+        }
+    }
+}
+
+/// Reaction of a slave timer to its trigger input.
+#[derive(Clone, Copy)]
+pub enum SlaveMode {
+    /// The slave mode controller is disabled, the counter runs on the internal clock.
+    Disabled,
+    /// A rising edge of the trigger resets the counter.
+    Reset,
+    /// The counter only runs while the trigger is high.
+    Gated,
+    /// A rising edge of the trigger starts the counter.
+    Trigger,
+    /// Rising edges of the trigger clock the counter (external clock mode 1).
+    ExternalClock,
+}
+
+impl SlaveMode {
+    fn to_bits(self) -> u8 {
+        match self {
+            SlaveMode::Disabled => 0b000,
+            SlaveMode::Reset => 0b100,
+            SlaveMode::Gated => 0b101,
+            SlaveMode::Trigger => 0b110,
+            SlaveMode::ExternalClock => 0b111,
+        }
+    }
+}
+
 /// Low-level timer driver.
 pub struct Timer<'d, T: CoreInstance> {
     tim: PeripheralRef<'d, T>,
@@ -296,6 +350,47 @@ impl<'d, T: CoreInstance> Timer<'d, T> {
         self.regs_core().cr1().modify(|r| r.set_arpe(enable));
     }
 
+    /// Get the counter value.
+    pub fn get_counter(&self) -> u32 {
+        match T::BITS {
+            TimerBits::Bits16 => self.regs_core().cnt().read().cnt() as u32,
+            #[cfg(not(stm32l0))]
+            TimerBits::Bits32 => self.regs_gp32_unchecked().cnt().read(),
+        }
+    }
+
+    /// Set the counter value.
+    pub fn set_counter(&self, value: u32) {
+        match T::BITS {
+            TimerBits::Bits16 => self.regs_core().cnt().write(|r| r.set_cnt(unwrap!(value.try_into()))),
+            #[cfg(not(stm32l0))]
+            TimerBits::Bits32 => self.regs_gp32_unchecked().cnt().write_value(value),
+        }
+    }
+
+    /// Count at `frequency`, over the whole counter range.
+    ///
+    /// Unlike [`set_frequency`](Self::set_frequency), this sets the frequency of the counter ticks
+    /// instead of its overflows: the prescaler divides the timer clock by `timer clock / frequency`,
+    /// and the auto-reload value is the maximum.
+    pub fn set_tick_frequency(&self, frequency: Hertz) {
+        let f = frequency.0;
+        assert!(f > 0);
+        let psc: u16 = unwrap!((T::frequency().0 / f - 1).try_into());
+
+        let regs = self.regs_core();
+        regs.psc().write_value(psc);
+        match T::BITS {
+            TimerBits::Bits16 => regs.arr().write(|r| r.set_arr(u16::MAX)),
+            #[cfg(not(stm32l0))]
+            TimerBits::Bits32 => self.regs_gp32_unchecked().arr().write_value(u32::MAX),
+        }
+
+        regs.cr1().modify(|r| r.set_urs(vals::Urs::COUNTERONLY));
+        regs.egr().write(|r| r.set_ug(true));
+        regs.cr1().modify(|r| r.set_urs(vals::Urs::ANYEVENT));
+    }
+
     /// Get the timer frequency.
     pub fn get_frequency(&self) -> Hertz {
         let timer_f = T::frequency();
@@ -352,6 +447,13 @@ impl<'d, T: BasicInstance> Timer<'d, T> {
     pub fn regs_basic(&self) -> crate::pac::timer::TimBasic {
         unsafe { crate::pac::timer::TimBasic::from_ptr(T::regs()) }
     }
+
+    /// Set the trigger output (TRGO), to drive the slave mode controller of other timers.
+    pub fn set_master_mode(&self, mode: MasterMode) {
+        self.regs_basic()
+            .cr2()
+            .modify(|r| r.set_mms(vals::Mms::from_bits(mode.to_bits())));
+    }
 }
 
 impl<'d, T: GeneralInstance1Channel> Timer<'d, T> {
@@ -370,6 +472,17 @@ impl<'d, T: GeneralInstance1Channel> Timer<'d, T> {
         self.regs_1ch().cr1().modify(|r| r.set_ckd(ckd));
     }
 
+    /// Set max compare value, i.e. the auto-reload value: the counter counts `0..=value`.
+    ///
+    /// With auto-reload preload disabled, the new value is used immediately.
+    pub fn set_max_compare_value(&self, value: u32) {
+        match T::BITS {
+            TimerBits::Bits16 => self.regs_1ch().arr().write(|r| r.set_arr(unwrap!(value.try_into()))),
+            #[cfg(not(stm32l0))]
+            TimerBits::Bits32 => self.regs_gp32_unchecked().arr().write_value(value),
+        }
+    }
+
     /// Get max compare value. This depends on the timer frequency and the clock frequency from RCC.
     pub fn get_max_compare_value(&self) -> u32 {
         match T::BITS {
@@ -408,6 +521,28 @@ impl<'d, T: GeneralInstance4Channel> Timer<'d, T> {
         self.tim.enable_outputs()
     }
 
+    /// Set the slave mode.
+    pub fn set_slave_mode(&self, mode: SlaveMode) {
+        self.regs_gp16()
+            .smcr()
+            .modify(|r| r.set_sms(vals::Sms::from_bits(mode.to_bits())));
+    }
+
+    /// Set the trigger input of the slave mode controller.
+    ///
+    /// `ts` is the raw value of the `TS` field, e.g. 0 to 3 for the internal triggers `ITR0` to
+    /// `ITR3`. See [`chain`](super::chain) to connect timers with compile-time checked internal
+    /// triggers.
+    pub fn set_trigger_source(&self, ts: u8) {
+        self.regs_gp16().smcr().modify(|r| r.set_ts(vals::Ts::from_bits(ts)));
+    }
+
+    /// Enable/disable master/slave mode, which delays the trigger input so that the timer and the
+    /// timers it triggers start in sync.
+    pub fn set_master_slave_mode(&self, enable: bool) {
+        self.regs_gp16().smcr().modify(|r| r.set_msm(enable));
+    }
+
     /// Set counting mode.
     pub fn set_counting_mode(&self, mode: CountingMode) {
         let (cms, dir) = mode.into();
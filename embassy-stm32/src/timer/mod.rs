@@ -1,11 +1,17 @@
 //! Timers, PWM, quadrature decoder.
 
+pub mod chain;
 #[cfg(not(stm32l0))]
 pub mod complementary_pwm;
 pub mod low_level;
+pub mod one_pulse;
 pub mod qei;
 pub mod simple_pwm;
 
+use core::marker::PhantomData;
+
+use embassy_sync::waitqueue::AtomicWaker;
+
 use crate::interrupt;
 use crate::rcc::RccPeripheral;
 
@@ -45,8 +51,25 @@ pub enum TimerBits {
     Bits32,
 }
 
+struct State {
+    up_waker: AtomicWaker,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            up_waker: AtomicWaker::new(),
+        }
+    }
+}
+
+trait SealedInstance: RccPeripheral {
+    fn state() -> &'static State;
+}
+
 /// Core timer instance.
-pub trait CoreInstance: RccPeripheral + 'static {
+#[allow(private_bounds)]
+pub trait CoreInstance: SealedInstance + 'static {
     /// Update Interrupt for this timer.
     type UpdateInterrupt: interrupt::typelevel::Interrupt;
 
@@ -140,9 +163,36 @@ dma_trait!(Ch2Dma, GeneralInstance4Channel);
 dma_trait!(Ch3Dma, GeneralInstance4Channel);
 dma_trait!(Ch4Dma, GeneralInstance4Channel);
 
+/// Update interrupt handler.
+///
+/// Wakes the tasks waiting for an update event, and disables the update interrupt.
+pub struct UpdateInterruptHandler<T: CoreInstance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: CoreInstance> interrupt::typelevel::Handler<T::UpdateInterrupt> for UpdateInterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_wakeup_irq();
+
+        let regs = crate::pac::timer::TimCore::from_ptr(T::regs());
+        if regs.sr().read().uif() {
+            regs.dier().modify(|w| w.set_uie(false));
+            T::state().up_waker.wake();
+        }
+    }
+}
+
 #[allow(unused)]
 macro_rules! impl_core_timer {
     ($inst:ident, $bits:expr) => {
+        impl SealedInstance for crate::peripherals::$inst {
+            fn state() -> &'static State {
+                static STATE: State = State::new();
+                &STATE
+            }
+        }
+
         impl CoreInstance for crate::peripherals::$inst {
             type UpdateInterrupt = crate::_generated::peripheral_interrupts::$inst::UP;
 
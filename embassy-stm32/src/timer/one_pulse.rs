@@ -0,0 +1,189 @@
+//! One-pulse mode (OPM), generating single pulses with a precise delay and width.
+//!
+//! On a trigger, the counter starts from 0: the output turns active when it reaches the delay,
+//! and inactive when it reaches the end of the pulse, where the counter stops. The trigger is
+//! either software, with [`OnePulse::trigger`], an edge on the `ETR` pin, or the trigger output
+//! of another timer, by [chaining](super::chain) [`OnePulse::timer`] in [`SlaveMode::Trigger`].
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_internal::into_ref;
+
+use super::low_level::{OutputCompareMode, OutputPolarity, SlaveMode, Timer};
+use super::simple_pwm::{Ch1, Ch2, Ch3, Ch4, PwmPin};
+use super::{Channel, ExternalTriggerPin, GeneralInstance4Channel, UpdateInterruptHandler};
+use crate::gpio::AFType;
+use crate::interrupt::typelevel::{Binding, Interrupt};
+use crate::pac::timer::vals;
+use crate::time::Hertz;
+use crate::Peripheral;
+
+/// Edge of the `ETR` pin triggering a pulse.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerEdge {
+    /// Rising edge.
+    Rising,
+    /// Falling edge.
+    Falling,
+}
+
+/// One-pulse driver.
+pub struct OnePulse<'d, T: GeneralInstance4Channel> {
+    inner: Timer<'d, T>,
+    channel: Channel,
+}
+
+macro_rules! channel_impl {
+    ($new_chx:ident, $channel:ident, $ch:expr) => {
+        impl<'d, T: GeneralInstance4Channel> OnePulse<'d, T> {
+            #[doc = concat!("Create a new one-pulse driver, with the pulse output on ", stringify!($channel), ".")]
+            ///
+            /// The delay and width of the pulse are counted in ticks of `tick_frequency`. The pulse
+            /// is 1 tick wide after 1 tick of delay until set with [`set_pulse`](Self::set_pulse).
+            pub fn $new_chx(
+                tim: impl Peripheral<P = T> + 'd,
+                _pin: PwmPin<'d, T, $channel>,
+                _irq: impl Binding<T::UpdateInterrupt, UpdateInterruptHandler<T>> + 'd,
+                tick_frequency: Hertz,
+            ) -> Self {
+                Self::new_inner(tim, $ch, tick_frequency)
+            }
+        }
+    };
+}
+
+channel_impl!(new_ch1, Ch1, Channel::Ch1);
+channel_impl!(new_ch2, Ch2, Channel::Ch2);
+channel_impl!(new_ch3, Ch3, Channel::Ch3);
+channel_impl!(new_ch4, Ch4, Channel::Ch4);
+
+impl<'d, T: GeneralInstance4Channel> OnePulse<'d, T> {
+    fn new_inner(tim: impl Peripheral<P = T> + 'd, channel: Channel, tick_frequency: Hertz) -> Self {
+        let inner = Timer::new(tim);
+
+        inner.set_tick_frequency(tick_frequency);
+        inner.regs_core().cr1().modify(|r| {
+            r.set_opm(true);
+            // Only the end of a pulse raises the update interrupt, not software updates.
+            r.set_urs(vals::Urs::COUNTERONLY);
+        });
+
+        // PWM mode 2: inactive until the counter reaches the delay, then active until it stops.
+        inner.set_output_compare_mode(channel, OutputCompareMode::PwmMode2);
+        inner.set_output_compare_preload(channel, false);
+        inner.enable_outputs(); // Required for advanced timers, see GeneralInstance4Channel for details
+        inner.enable_channel(channel, true);
+
+        T::UpdateInterrupt::unpend();
+        unsafe { T::UpdateInterrupt::enable() };
+
+        let mut this = Self { inner, channel };
+        this.set_pulse(1, 1);
+        this
+    }
+
+    /// Trigger pulses on edges of the `ETR` pin, in addition to software triggers.
+    ///
+    /// Edges during a pulse are ignored.
+    pub fn set_external_trigger(
+        &mut self,
+        etr: impl Peripheral<P = impl ExternalTriggerPin<T>> + 'd,
+        edge: TriggerEdge,
+    ) {
+        into_ref!(etr);
+        etr.set_as_af(etr.af_num(), AFType::Input);
+
+        let regs = self.inner.regs_gp16();
+        regs.smcr()
+            .modify(|w| w.set_etp(vals::Etp::from_bits((edge == TriggerEdge::Falling) as u8)));
+        self.inner.set_slave_mode(SlaveMode::Disabled);
+        // TS = ETRF.
+        self.inner.set_trigger_source(0b111);
+        self.inner.set_slave_mode(SlaveMode::Trigger);
+    }
+
+    /// Get the low-level timer driver, e.g. to trigger pulses from another timer with a
+    /// [chain](super::chain).
+    pub fn timer(&self) -> &Timer<'d, T> {
+        &self.inner
+    }
+
+    /// Set the delay from the trigger to the start of the pulse, and the width of the pulse, in
+    /// ticks.
+    ///
+    /// The delay must be at least 1 tick, and the sum must fit in the counter. A pulse in progress
+    /// is updated immediately.
+    pub fn set_pulse(&mut self, delay: u32, width: u32) {
+        assert!(delay > 0 && width > 0);
+        let end = unwrap!(delay.checked_add(width - 1));
+        self.inner.set_compare_value(self.channel, delay);
+        self.inner.set_max_compare_value(end);
+    }
+
+    /// Set the output polarity.
+    pub fn set_polarity(&mut self, polarity: OutputPolarity) {
+        self.inner.set_output_polarity(self.channel, polarity);
+    }
+
+    /// Whether a pulse is in progress, including its delay.
+    pub fn is_running(&self) -> bool {
+        self.inner.regs_core().cr1().read().cen()
+    }
+
+    /// Start a pulse. Does nothing if a pulse is in progress.
+    pub fn trigger(&mut self) {
+        self.inner.start();
+    }
+
+    /// Start a pulse, or extend the pulse in progress.
+    ///
+    /// If the output is already active, it stays active for the full width from now on. During
+    /// the delay, the pulse starts as initially planned.
+    pub fn retrigger(&mut self) {
+        critical_section::with(|_| {
+            let delay = self.inner.get_compare_value(self.channel);
+            if self.is_running() && self.inner.get_counter() >= delay {
+                self.inner.set_counter(delay);
+            }
+            self.inner.start();
+        })
+    }
+
+    /// Wait for the end of the next pulse.
+    ///
+    /// If a pulse is in progress, this waits for its end, otherwise for the end of a pulse
+    /// started later by any trigger.
+    pub async fn wait_for_pulse_end(&mut self) {
+        self.arm_pulse_end();
+        self.pulse_end().await
+    }
+
+    /// Trigger a pulse, and wait for its end.
+    pub async fn pulse(&mut self) {
+        // Armed before the trigger, in case the pulse ends before the first poll.
+        self.arm_pulse_end();
+        self.trigger();
+        self.pulse_end().await
+    }
+
+    fn arm_pulse_end(&self) {
+        let regs = self.inner.regs_core();
+        regs.sr().modify(|w| w.set_uif(false));
+        regs.dier().modify(|w| w.set_uie(true));
+    }
+
+    async fn pulse_end(&self) {
+        let regs = self.inner.regs_core();
+        poll_fn(|cx| {
+            T::state().up_waker.register(cx.waker());
+            if regs.dier().read().uie() {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await
+    }
+}
@@ -1,5 +1,5 @@
 use core::slice;
-use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicBool, AtomicU8};
 
 use embassy_hal_internal::atomic_ring_buffer::RingBuffer;
 use embassy_sync::waitqueue::AtomicWaker;
@@ -17,11 +17,15 @@ impl<T: BasicInstance> interrupt::typelevel::Handler<T::Interrupt> for Interrupt
         let state = T::buffered_state();
 
         // RX
+        let hold = update_rx_flow(r, state);
         let sr_val = sr(r).read();
         // On v1 & v2, reading DR clears the rxne, error and idle interrupt
         // flags. Keep this close to the SR read to reduce the chance of a
         // flag being set in-between.
-        let dr = if sr_val.rxne() || cfg!(any(usart_v1, usart_v2)) && (sr_val.ore() || sr_val.idle()) {
+        // While holding off the sender with RTS, the byte is left in DR to keep RTS deasserted.
+        let dr = if hold {
+            None
+        } else if sr_val.rxne() || cfg!(any(usart_v1, usart_v2)) && (sr_val.ore() || sr_val.idle()) {
             Some(rdr(r).read_volatile())
         } else {
             None
@@ -41,16 +45,31 @@ impl<T: BasicInstance> interrupt::typelevel::Handler<T::Interrupt> for Interrupt
             warn!("Overrun error");
         }
         if sr_val.rxne() {
-            let mut rx_writer = state.rx_buf.writer();
-            let buf = rx_writer.push_slice();
-            if !buf.is_empty() {
-                if let Some(byte) = dr {
-                    buf[0] = byte;
-                    rx_writer.push_done(1);
+            let xon_xoff = state.xon_xoff.load(Ordering::Relaxed);
+            match dr {
+                Some(XOFF) if xon_xoff => {
+                    state.tx_xoff.store(true, Ordering::Relaxed);
+                    state.tx_flow_waker.wake();
                 }
-            } else {
-                // FIXME: Should we disable any further RX interrupts when the buffer becomes full.
+                Some(XON) if xon_xoff => {
+                    state.tx_xoff.store(false, Ordering::Relaxed);
+                    r.cr1().modify(|w| {
+                        w.set_txeie(true);
+                    });
+                }
+                Some(byte) => {
+                    let mut rx_writer = state.rx_buf.writer();
+                    let buf = rx_writer.push_slice();
+                    if !buf.is_empty() {
+                        buf[0] = byte;
+                        rx_writer.push_done(1);
+                    } else {
+                        // FIXME: Should we disable any further RX interrupts when the buffer becomes full.
+                    }
+                }
+                None => {}
             }
+            update_rx_flow(r, state);
 
             if !state.rx_buf.is_empty() {
                 state.rx_waker.wake();
@@ -61,6 +80,18 @@ impl<T: BasicInstance> interrupt::typelevel::Handler<T::Interrupt> for Interrupt
             state.rx_waker.wake();
         }
 
+        #[cfg(any(usart_v3, usart_v4))]
+        if sr_val.ctsif() {
+            state.tx_flow_waker.wake();
+        }
+
+        // The flag is cleared above with `clear_interrupt_flags`.
+        #[cfg(any(usart_v3, usart_v4))]
+        if sr_val.wuf() && r.cr3().read().wufie() {
+            r.cr3().modify(|w| w.set_wufie(false));
+            T::state().wakeup_waker.wake();
+        }
+
         // With `usart_v4` hardware FIFO is enabled and Transmission complete (TC)
         // indicates that all bytes are pushed out from the FIFO.
         // For other usart variants it shows that last byte from the buffer was just sent.
@@ -79,6 +110,24 @@ impl<T: BasicInstance> interrupt::typelevel::Handler<T::Interrupt> for Interrupt
 
         // TX
         if sr(r).read().txe() {
+            let ctrl = state.tx_ctrl.swap(0, Ordering::Relaxed);
+            if ctrl != 0 {
+                // XON and XOFF go out ahead of the buffered data, even when paused by the peer.
+                r.cr1().modify(|w| {
+                    w.set_txeie(true);
+                });
+                tdr(r).write_volatile(ctrl);
+                return;
+            }
+
+            if state.tx_xoff.load(Ordering::Relaxed) {
+                // Paused by the peer, the XON handler enables the interrupt again.
+                r.cr1().modify(|w| {
+                    w.set_txeie(false);
+                });
+                return;
+            }
+
             let mut tx_reader = state.tx_buf.reader();
             let buf = tx_reader.pop_slice();
             if !buf.is_empty() {
@@ -105,12 +154,77 @@ impl<T: BasicInstance> interrupt::typelevel::Handler<T::Interrupt> for Interrupt
     }
 }
 
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// Asks the sender to stop or resume, depending on the receive buffer fill level.
+///
+/// Returns true if the sender is held off with RTS, and received bytes must be left in DR.
+fn update_rx_flow(r: Regs, state: &State) -> bool {
+    let len = state.rx_buf.len();
+    let rts = r.cr3().read().rtse();
+    let xon_xoff = state.xon_xoff.load(Ordering::Relaxed);
+    if len == 0 || !(rts || xon_xoff) {
+        return false;
+    }
+
+    let mut rx_writer = unsafe { state.rx_buf.writer() };
+    let free: usize = rx_writer.push_bufs().iter().map(|(_, n)| n).sum();
+    let used = (len - free) * 100;
+
+    let stopped = state.rx_stopped.load(Ordering::Relaxed);
+    let stop = used >= len * state.rx_stop_level.load(Ordering::Relaxed) as usize;
+    let resume = used <= len * state.rx_resume_level.load(Ordering::Relaxed) as usize;
+    if !stopped && stop || stopped && resume {
+        state.rx_stopped.store(!stopped, Ordering::Relaxed);
+        if xon_xoff {
+            state.tx_ctrl.store(if stopped { XON } else { XOFF }, Ordering::Relaxed);
+            r.cr1().modify(|w| {
+                w.set_txeie(true);
+            });
+        }
+        if !stopped {
+            state.rx_flow_waker.wake();
+        }
+    }
+
+    let hold = rts && state.rx_stopped.load(Ordering::Relaxed);
+    r.cr1().modify(|w| {
+        w.set_rxneie(!hold);
+        w.set_idleie(!hold);
+    });
+    hold
+}
+
+/// Whether the peer deasserts CTS. Only reported on chips where the CTS level can be read.
+fn cts_deasserted(r: Regs) -> bool {
+    #[cfg(any(usart_v3, usart_v4))]
+    let deasserted = r.cr3().read().ctse() && !sr(r).read().cts();
+    #[cfg(any(usart_v1, usart_v2))]
+    let deasserted = {
+        let _ = r;
+        false
+    };
+    deasserted
+}
+
 pub(crate) struct State {
     pub(crate) rx_waker: AtomicWaker,
     pub(crate) rx_buf: RingBuffer,
     pub(crate) tx_waker: AtomicWaker,
     pub(crate) tx_buf: RingBuffer,
     pub(crate) tx_done: AtomicBool,
+    xon_xoff: AtomicBool,
+    rx_stop_level: AtomicU8,
+    rx_resume_level: AtomicU8,
+    /// The sender was asked to stop.
+    rx_stopped: AtomicBool,
+    rx_flow_waker: AtomicWaker,
+    /// Transmission was paused by an XOFF from the peer.
+    tx_xoff: AtomicBool,
+    /// XON or XOFF to send, or 0.
+    tx_ctrl: AtomicU8,
+    tx_flow_waker: AtomicWaker,
 }
 
 impl State {
@@ -122,6 +236,23 @@ impl State {
             rx_waker: AtomicWaker::new(),
             tx_waker: AtomicWaker::new(),
             tx_done: AtomicBool::new(true),
+            xon_xoff: AtomicBool::new(false),
+            rx_stop_level: AtomicU8::new(100),
+            rx_resume_level: AtomicU8::new(0),
+            rx_stopped: AtomicBool::new(false),
+            rx_flow_waker: AtomicWaker::new(),
+            tx_xoff: AtomicBool::new(false),
+            tx_ctrl: AtomicU8::new(0),
+            tx_flow_waker: AtomicWaker::new(),
+        }
+    }
+
+    fn set_flow_control(&self, config: &Config) {
+        self.xon_xoff.store(config.xon_xoff, Ordering::Relaxed);
+        self.rx_stop_level.store(config.rx_stop_level, Ordering::Relaxed);
+        self.rx_resume_level.store(config.rx_resume_level, Ordering::Relaxed);
+        if !config.xon_xoff {
+            self.tx_xoff.store(false, Ordering::Relaxed);
         }
     }
 }
@@ -214,6 +345,9 @@ impl<'d, T: BasicInstance> BufferedUart<'d, T> {
         T::regs().cr3().write(|w| {
             w.set_rtse(true);
             w.set_ctse(true);
+            // Only used to notify flow stops, the CTS level can't be read on v1 and v2.
+            #[cfg(any(usart_v3, usart_v4))]
+            w.set_ctsie(true);
         });
 
         Self::new_inner(peri, rx, tx, tx_buffer, rx_buffer, config)
@@ -266,6 +400,8 @@ impl<'d, T: BasicInstance> BufferedUart<'d, T> {
         tx.set_as_af(tx.af_num(), AFType::OutputPushPull);
 
         configure(r, &config, T::frequency(), T::KIND, true, true)?;
+        update_stop_handoff::<T>(config.wakeup_enabled());
+        state.set_flow_control(&config);
 
         r.cr1().modify(|w| {
             w.set_rxneie(true);
@@ -281,6 +417,32 @@ impl<'d, T: BasicInstance> BufferedUart<'d, T> {
         })
     }
 
+    /// Whether the sender was asked to stop, see [`BufferedUartRx::is_flow_stopped`].
+    pub fn is_rx_flow_stopped(&self) -> bool {
+        self.rx.is_flow_stopped()
+    }
+
+    /// Wait until the sender is asked to stop, see [`BufferedUartRx::wait_flow_stopped`].
+    pub async fn wait_rx_flow_stopped(&self) {
+        self.rx.wait_flow_stopped().await
+    }
+
+    /// Whether transmission is paused by the peer, see [`BufferedUartTx::is_flow_stopped`].
+    pub fn is_tx_flow_stopped(&self) -> bool {
+        self.tx.is_flow_stopped()
+    }
+
+    /// Wait until transmission is paused by the peer, see [`BufferedUartTx::wait_flow_stopped`].
+    pub async fn wait_tx_flow_stopped(&self) {
+        self.tx.wait_flow_stopped().await
+    }
+
+    /// Wait for the [`Config::wakeup`] event, see [`BufferedUartRx::wait_for_wakeup`].
+    #[cfg(any(usart_v3, usart_v4))]
+    pub async fn wait_for_wakeup(&mut self) {
+        self.rx.wait_for_wakeup().await
+    }
+
     /// Split the driver into a Tx and Rx part (useful for sending to separate tasks)
     pub fn split(self) -> (BufferedUartTx<'d, T>, BufferedUartRx<'d, T>) {
         (self.tx, self.rx)
@@ -289,6 +451,7 @@ impl<'d, T: BasicInstance> BufferedUart<'d, T> {
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
         reconfigure::<T>(config)?;
+        T::buffered_state().set_flow_control(config);
 
         T::regs().cr1().modify(|w| {
             w.set_rxneie(true);
@@ -300,6 +463,36 @@ impl<'d, T: BasicInstance> BufferedUart<'d, T> {
 }
 
 impl<'d, T: BasicInstance> BufferedUartRx<'d, T> {
+    /// Whether the sender was asked to stop, by deasserting RTS or sending XOFF, because the
+    /// receive buffer reached [`Config::rx_stop_level`].
+    pub fn is_flow_stopped(&self) -> bool {
+        T::buffered_state().rx_stopped.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the sender is asked to stop, e.g. to catch up on reading the buffer.
+    ///
+    /// Returns immediately if it already is.
+    pub async fn wait_flow_stopped(&self) {
+        poll_fn(|cx| {
+            let state = T::buffered_state();
+            state.rx_flow_waker.register(cx.waker());
+            match state.rx_stopped.load(Ordering::Relaxed) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait for the [`Config::wakeup`] event, e.g. to keep a task asleep until the line is
+    /// active without polling the buffer.
+    ///
+    /// The received bytes are still buffered. Never completes if no wakeup event is configured.
+    #[cfg(any(usart_v3, usart_v4))]
+    pub async fn wait_for_wakeup(&mut self) {
+        wait_for_wakeup::<T>().await
+    }
+
     async fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
         poll_fn(move |cx| {
             let state = T::buffered_state();
@@ -310,7 +503,7 @@ impl<'d, T: BasicInstance> BufferedUartRx<'d, T> {
                 let len = data.len().min(buf.len());
                 buf[..len].copy_from_slice(&data[..len]);
 
-                let do_pend = state.rx_buf.is_full();
+                let do_pend = state.rx_buf.is_full() || state.rx_stopped.load(Ordering::Relaxed);
                 rx_reader.pop_done(len);
 
                 if do_pend {
@@ -336,7 +529,7 @@ impl<'d, T: BasicInstance> BufferedUartRx<'d, T> {
                 let len = data.len().min(buf.len());
                 buf[..len].copy_from_slice(&data[..len]);
 
-                let do_pend = state.rx_buf.is_full();
+                let do_pend = state.rx_buf.is_full() || state.rx_stopped.load(Ordering::Relaxed);
                 rx_reader.pop_done(len);
 
                 if do_pend {
@@ -367,7 +560,7 @@ impl<'d, T: BasicInstance> BufferedUartRx<'d, T> {
     fn consume(&self, amt: usize) {
         let state = T::buffered_state();
         let mut rx_reader = unsafe { state.rx_buf.reader() };
-        let full = state.rx_buf.is_full();
+        let full = state.rx_buf.is_full() || state.rx_stopped.load(Ordering::Relaxed);
         rx_reader.pop_done(amt);
         if full {
             T::Interrupt::pend();
@@ -377,6 +570,7 @@ impl<'d, T: BasicInstance> BufferedUartRx<'d, T> {
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
         reconfigure::<T>(config)?;
+        T::buffered_state().set_flow_control(config);
 
         T::regs().cr1().modify(|w| {
             w.set_rxneie(true);
@@ -388,6 +582,27 @@ impl<'d, T: BasicInstance> BufferedUartRx<'d, T> {
 }
 
 impl<'d, T: BasicInstance> BufferedUartTx<'d, T> {
+    /// Whether transmission is paused by the peer, with XOFF or by deasserting CTS.
+    ///
+    /// CTS is only reported on usart v3 and v4.
+    pub fn is_flow_stopped(&self) -> bool {
+        T::buffered_state().tx_xoff.load(Ordering::Relaxed) || cts_deasserted(T::regs())
+    }
+
+    /// Wait until transmission is paused by the peer.
+    ///
+    /// Returns immediately if it already is.
+    pub async fn wait_flow_stopped(&self) {
+        poll_fn(|cx| {
+            T::buffered_state().tx_flow_waker.register(cx.waker());
+            match self.is_flow_stopped() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
     async fn write(&self, buf: &[u8]) -> Result<usize, Error> {
         poll_fn(move |cx| {
             let state = T::buffered_state();
@@ -462,6 +677,7 @@ impl<'d, T: BasicInstance> BufferedUartTx<'d, T> {
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
         reconfigure::<T>(config)?;
+        T::buffered_state().set_flow_control(config);
 
         T::regs().cr1().modify(|w| {
             w.set_rxneie(true);
@@ -485,6 +701,7 @@ impl<'d, T: BasicInstance> Drop for BufferedUartRx<'d, T> {
             }
         }
 
+        update_stop_handoff::<T>(false);
         T::disable();
     }
 }
@@ -13,7 +13,7 @@ use embassy_hal_internal::{into_ref, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 use futures::future::{select, Either};
 
-use crate::dma::ChannelAndRequest;
+use crate::dma::{ChannelAndRequest, Priority, TransferOptions};
 use crate::gpio::{AFType, AnyPin, SealedPin};
 use crate::interrupt::typelevel::Interrupt;
 use crate::mode::{Async, Blocking, Mode};
@@ -42,6 +42,13 @@ impl<T: BasicInstance> interrupt::typelevel::Handler<T::Interrupt> for Interrupt
 
         let (sr, cr1, cr3) = (sr(r).read(), r.cr1().read(), r.cr3().read());
 
+        #[cfg(any(usart_v3, usart_v4))]
+        if cr3.wufie() && sr.wuf() {
+            r.icr().write(|w| w.set_wucf(true));
+            r.cr3().modify(|w| w.set_wufie(false));
+            s.wakeup_waker.wake();
+        }
+
         let has_errors = (sr.pe() && cr1.peie()) || ((sr.fe() || sr.ne() || sr.ore()) && cr3.eie());
         if has_errors {
             // clear all interrupts and DMA Rx Request
@@ -125,6 +132,23 @@ pub enum ConfigError {
     BaudrateTooHigh,
     /// Rx or Tx not enabled
     RxOrTxNotEnabled,
+    /// Flow control resume level not below the stop level, or above 100%
+    InvalidFlowControlLevels,
+    /// Wakeup from Stop mode requested on an instance that isn't an LPUART
+    WakeupNotSupported,
+}
+
+#[cfg(any(usart_v3, usart_v4))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+/// Event waking the MCU from Stop mode, see [`Config::wakeup`]
+pub enum WakeupEvent {
+    /// A byte with the most significant data bit set, and this 7-bit address in the other bits
+    AddressMatch(u8),
+    /// The start bit of any byte
+    StartBit,
+    /// A received byte
+    RxNotEmpty,
 }
 
 #[non_exhaustive]
@@ -164,8 +188,50 @@ pub struct Config {
     #[cfg(any(usart_v3, usart_v4))]
     pub invert_rx: bool,
 
+    /// Set this to true to enable software (XON/XOFF) flow control.
+    ///
+    /// Only supported by [`BufferedUart`]: received XON and XOFF bytes pause and resume
+    /// transmission instead of being buffered, and XOFF/XON is sent when the receive buffer
+    /// reaches [`rx_stop_level`](Self::rx_stop_level)/[`rx_resume_level`](Self::rx_resume_level).
+    pub xon_xoff: bool,
+
+    /// Receive buffer fill level, in percent, at which [`BufferedUart`] asks the sender to stop,
+    /// by deasserting RTS or sending XOFF.
+    ///
+    /// With RTS, bytes are left in the receive data register until the buffer drains, so
+    /// nothing is lost even at 100%. With XON/XOFF, leave room for the bytes the sender
+    /// transmits before it handles the XOFF.
+    pub rx_stop_level: u8,
+
+    /// Receive buffer fill level, in percent, at which [`BufferedUart`] asks the sender to resume.
+    /// Must be below [`rx_stop_level`](Self::rx_stop_level).
+    pub rx_resume_level: u8,
+
+    /// DMA request priority of the transfers.
+    ///
+    /// A [`RingBufferedUartRx`] keeps the priority of the [`UartRx`] it was created from.
+    pub dma_priority: Priority,
+
+    /// Event waking the MCU from Stop mode, only supported by LPUARTs.
+    ///
+    /// The LPUART keeps receiving in Stop mode if its kernel clock is LSE, or HSI which it
+    /// starts on demand, selected with the `lpuartXsel` field of [`rcc::Config::mux`](crate::rcc::Config).
+    /// With the `low-power` feature, the [low-power executor](crate::low_power) then enters
+    /// Stop mode while the receiver is enabled, and [`UartRx::wait_for_wakeup`] waits for the event.
+    /// With another kernel clock the event is still reported, but the MCU is kept out of Stop mode.
+    ///
+    /// LSE limits the baud rate to 9600.
+    #[cfg(any(usart_v3, usart_v4))]
+    pub wakeup: Option<WakeupEvent>,
+
     // private: set by new_half_duplex, not by the user.
     half_duplex: bool,
+
+    // private: set by new_smartcard, not by the user.
+    smartcard: Option<SmartcardConfig>,
+
+    // private: set by new_irda, not by the user.
+    irda: Option<IrdaConfig>,
 }
 
 impl Config {
@@ -183,6 +249,12 @@ impl Config {
         };
         AFType::Input
     }
+    fn wakeup_enabled(&self) -> bool {
+        #[cfg(any(usart_v3, usart_v4))]
+        return self.wakeup.is_some();
+        #[cfg(not(any(usart_v3, usart_v4)))]
+        false
+    }
 }
 
 impl Default for Config {
@@ -202,7 +274,93 @@ impl Default for Config {
             invert_tx: false,
             #[cfg(any(usart_v3, usart_v4))]
             invert_rx: false,
+            xon_xoff: false,
+            rx_stop_level: 75,
+            rx_resume_level: 25,
+            dma_priority: Priority::default(),
+            #[cfg(any(usart_v3, usart_v4))]
+            wakeup: None,
             half_duplex: false,
+            smartcard: None,
+            irda: None,
+        }
+    }
+}
+
+#[cfg(any(usart_v3, usart_v4))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+/// Smartcard byte convention, announced by the card in the first byte of its answer to reset
+pub enum Convention {
+    /// Direct convention: high level is 1, least significant bit first
+    Direct,
+    /// Inverse convention: low level is 1, most significant bit first
+    Inverse,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Smartcard (ISO 7816-3) configuration, see [`Uart::new_smartcard`]
+pub struct SmartcardConfig {
+    /// Frequency of the clock provided to the card on the CK pin.
+    ///
+    /// It is divided from the peripheral clock by an even number up to 62, the closest
+    /// frequency not above this one is used.
+    pub clock: Hertz,
+    /// Guard time between two transmitted characters, in bit durations
+    pub guard_time: u8,
+    /// Set this to true to send a NACK when a received character has a parity error.
+    pub nack: bool,
+    /// Number of retransmissions of a character NACKed by the card, and of NACKs sent for a
+    /// received character, before reporting a framing or parity error (0 to 7).
+    #[cfg(any(usart_v3, usart_v4))]
+    pub auto_retry_count: u8,
+    /// Byte convention
+    #[cfg(any(usart_v3, usart_v4))]
+    pub convention: Convention,
+}
+
+impl SmartcardConfig {
+    /// The baud rate used by the card after reset, before any protocol and parameter selection:
+    /// the card clock divided by the default clock rate conversion factor (372).
+    pub fn default_baudrate(&self) -> u32 {
+        self.clock.0 / 372
+    }
+}
+
+impl Default for SmartcardConfig {
+    fn default() -> Self {
+        Self {
+            clock: Hertz(3_571_200),
+            guard_time: 16,
+            nack: true,
+            #[cfg(any(usart_v3, usart_v4))]
+            auto_retry_count: 3,
+            #[cfg(any(usart_v3, usart_v4))]
+            convention: Convention::Direct,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// IrDA SIR configuration, see [`Uart::new_irda`]
+pub struct IrdaConfig {
+    /// Set this to true to use the low-power mode, where pulses last 3 periods of the low-power
+    /// clock, instead of 3/16 of a bit.
+    pub low_power: bool,
+    /// Frequency of the low-power clock, divided from the peripheral clock.
+    ///
+    /// It must be between 1.42 MHz and 2.12 MHz for pulses between 1.41 µs and 2.11 µs, the closest
+    /// frequency not above this one is used.
+    pub low_power_clock: Hertz,
+}
+
+impl Default for IrdaConfig {
+    fn default() -> Self {
+        Self {
+            low_power: false,
+            low_power_clock: Hertz(1_843_200),
         }
     }
 }
@@ -263,7 +421,9 @@ pub struct UartTx<'d, T: BasicInstance, M: Mode> {
     tx: Option<PeripheralRef<'d, AnyPin>>,
     cts: Option<PeripheralRef<'d, AnyPin>>,
     de: Option<PeripheralRef<'d, AnyPin>>,
+    ck: Option<PeripheralRef<'d, AnyPin>>,
     tx_dma: Option<ChannelAndRequest<'d>>,
+    dma_priority: Priority,
 }
 
 impl<'d, T: BasicInstance, M: Mode> SetConfig for UartTx<'d, T, M> {
@@ -310,6 +470,7 @@ pub struct UartRx<'d, T: BasicInstance, M: Mode> {
     rts: Option<PeripheralRef<'d, AnyPin>>,
     rx_dma: Option<ChannelAndRequest<'d>>,
     detect_previous_overrun: bool,
+    dma_priority: Priority,
     #[cfg(any(usart_v1, usart_v2))]
     buffered_sr: stm32_metapac::usart::regs::Sr,
 }
@@ -365,7 +526,11 @@ impl<'d, T: BasicInstance> UartTx<'d, T, Async> {
         });
         // If we don't assign future to a variable, the data register pointer
         // is held across an await and makes the future non-Send.
-        let transfer = unsafe { ch.write(buffer, tdr(T::regs()), Default::default()) };
+        let options = TransferOptions {
+            priority: self.dma_priority,
+            ..Default::default()
+        };
+        let transfer = unsafe { ch.write(buffer, tdr(T::regs()), options) };
         transfer.await;
         Ok(())
     }
@@ -423,13 +588,16 @@ impl<'d, T: BasicInstance, M: Mode> UartTx<'d, T, M> {
             tx,
             cts,
             de: None,
+            ck: None,
             tx_dma,
+            dma_priority: config.dma_priority,
             _phantom: PhantomData,
         })
     }
 
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.dma_priority = config.dma_priority;
         reconfigure::<T>(config)
     }
 
@@ -495,6 +663,15 @@ impl<'d, T: BasicInstance> UartRx<'d, T, Async> {
         self.inner_read(buffer, true).await
     }
 
+    /// Wait for the [`Config::wakeup`] event.
+    ///
+    /// The received bytes aren't read: start a read afterwards, quickly enough not to miss
+    /// them. Never completes if no wakeup event is configured.
+    #[cfg(any(usart_v3, usart_v4))]
+    pub async fn wait_for_wakeup(&mut self) {
+        wait_for_wakeup::<T>().await
+    }
+
     async fn inner_read_run(
         &mut self,
         buffer: &mut [u8],
@@ -529,7 +706,11 @@ impl<'d, T: BasicInstance> UartRx<'d, T, Async> {
         // Start USART DMA
         // will not do anything yet because DMAR is not yet set
         // future which will complete when DMA Read request completes
-        let transfer = unsafe { ch.read(rdr(T::regs()), buffer, Default::default()) };
+        let options = TransferOptions {
+            priority: self.dma_priority,
+            ..Default::default()
+        };
+        let transfer = unsafe { ch.read(rdr(T::regs()), buffer, options) };
 
         // clear ORE flag just before enabling DMA Rx Request: can be mandatory for the second transfer
         if !self.detect_previous_overrun {
@@ -736,6 +917,7 @@ impl<'d, T: BasicInstance, M: Mode> UartRx<'d, T, M> {
             w.set_rtse(rts.is_some());
         });
         configure(r, &config, T::frequency(), T::KIND, true, false)?;
+        update_stop_handoff::<T>(config.wakeup_enabled());
 
         T::Interrupt::unpend();
         unsafe { T::Interrupt::enable() };
@@ -749,6 +931,7 @@ impl<'d, T: BasicInstance, M: Mode> UartRx<'d, T, M> {
             rts,
             rx_dma,
             detect_previous_overrun: config.detect_previous_overrun,
+            dma_priority: config.dma_priority,
             #[cfg(any(usart_v1, usart_v2))]
             buffered_sr: stm32_metapac::usart::regs::Sr(0),
         })
@@ -756,6 +939,7 @@ impl<'d, T: BasicInstance, M: Mode> UartRx<'d, T, M> {
 
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.dma_priority = config.dma_priority;
         reconfigure::<T>(config)
     }
 
@@ -838,6 +1022,7 @@ impl<'d, T: BasicInstance, M: Mode> Drop for UartTx<'d, T, M> {
         self.tx.as_ref().map(|x| x.set_as_disconnected());
         self.cts.as_ref().map(|x| x.set_as_disconnected());
         self.de.as_ref().map(|x| x.set_as_disconnected());
+        self.ck.as_ref().map(|x| x.set_as_disconnected());
         T::disable();
     }
 }
@@ -846,6 +1031,7 @@ impl<'d, T: BasicInstance, M: Mode> Drop for UartRx<'d, T, M> {
     fn drop(&mut self) {
         self.rx.as_ref().map(|x| x.set_as_disconnected());
         self.rts.as_ref().map(|x| x.set_as_disconnected());
+        update_stop_handoff::<T>(false);
         T::disable();
     }
 }
@@ -1010,6 +1196,82 @@ impl<'d, T: BasicInstance> Uart<'d, T, Async> {
     pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
         self.rx.read_until_idle(buffer).await
     }
+
+    /// Wait for the [`Config::wakeup`] event, see [`UartRx::wait_for_wakeup`].
+    #[cfg(any(usart_v3, usart_v4))]
+    pub async fn wait_for_wakeup(&mut self) {
+        self.rx.wait_for_wakeup().await
+    }
+}
+
+impl<'d, T: BasicInstance + FullInstance> Uart<'d, T, Async> {
+    /// Create an IrDA SIR interface, with the encoder output on the Tx pin and the decoder input
+    /// on the Rx pin, to an infrared transceiver.
+    ///
+    /// IrDA SIR uses 1 stop bit, which overrides the setting of `config`, and baud rates up to
+    /// 115200. The link is half duplex: the application must not transmit while receiving.
+    #[doc(alias("IREN"))]
+    pub fn new_irda(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+        irda: IrdaConfig,
+    ) -> Result<Self, ConfigError> {
+        let config = irda_config(&config, irda);
+        Self::new_inner(
+            peri,
+            new_pin!(rx, config.rx_af()),
+            new_pin!(tx, config.tx_af()),
+            None,
+            None,
+            None,
+            new_dma!(tx_dma),
+            new_dma!(rx_dma),
+            config,
+        )
+    }
+
+    /// Create a smartcard (ISO 7816-3) interface, with the card I/O on the Tx pin and the card
+    /// clock on the CK pin.
+    ///
+    /// The I/O pin is open drain, and needs an external pull-up. Smartcards use 8 data bits, even
+    /// parity and 1.5 stop bits, which override the settings of `config`. Its baud rate should be
+    /// [`SmartcardConfig::default_baudrate`] until the card selects another one, see
+    /// [`set_smartcard_config`](Self::set_smartcard_config). The card reset and power are left to
+    /// GPIOs.
+    ///
+    /// A character NACKed by the card, once the retries are exhausted, is reported as a framing
+    /// error by the following read.
+    #[doc(alias("SCEN"))]
+    pub fn new_smartcard(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        ck: impl Peripheral<P = impl CkPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+        smartcard: SmartcardConfig,
+    ) -> Result<Self, ConfigError> {
+        let ck = new_pin!(ck, AFType::OutputPushPull);
+        let mut this = Self::new_inner(
+            peri,
+            None,
+            new_pin!(tx, AFType::OutputOpenDrain),
+            None,
+            None,
+            None,
+            new_dma!(tx_dma),
+            new_dma!(rx_dma),
+            smartcard_config(&config, smartcard),
+        )?;
+        this.tx.ck = ck;
+        Ok(this)
+    }
 }
 
 impl<'d, T: BasicInstance> Uart<'d, T, Blocking> {
@@ -1144,6 +1406,86 @@ impl<'d, T: BasicInstance> Uart<'d, T, Blocking> {
     }
 }
 
+impl<'d, T: BasicInstance + FullInstance> Uart<'d, T, Blocking> {
+    /// Create an IrDA SIR interface, with the encoder output on the Tx pin and the decoder input
+    /// on the Rx pin, to an infrared transceiver.
+    ///
+    /// IrDA SIR uses 1 stop bit, which overrides the setting of `config`, and baud rates up to
+    /// 115200. The link is half duplex: the application must not transmit while receiving.
+    #[doc(alias("IREN"))]
+    pub fn new_blocking_irda(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        config: Config,
+        irda: IrdaConfig,
+    ) -> Result<Self, ConfigError> {
+        let config = irda_config(&config, irda);
+        Self::new_inner(
+            peri,
+            new_pin!(rx, config.rx_af()),
+            new_pin!(tx, config.tx_af()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config,
+        )
+    }
+
+    /// Create a smartcard (ISO 7816-3) interface, with the card I/O on the Tx pin and the card
+    /// clock on the CK pin.
+    ///
+    /// The I/O pin is open drain, and needs an external pull-up. Smartcards use 8 data bits, even
+    /// parity and 1.5 stop bits, which override the settings of `config`. Its baud rate should be
+    /// [`SmartcardConfig::default_baudrate`] until the card selects another one, see
+    /// [`set_smartcard_config`](Uart::set_smartcard_config). The card reset and power are left to
+    /// GPIOs.
+    ///
+    /// A character NACKed by the card, once the retries are exhausted, is reported as a framing
+    /// error by the following read.
+    #[doc(alias("SCEN"))]
+    pub fn new_blocking_smartcard(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        ck: impl Peripheral<P = impl CkPin<T>> + 'd,
+        config: Config,
+        smartcard: SmartcardConfig,
+    ) -> Result<Self, ConfigError> {
+        let ck = new_pin!(ck, AFType::OutputPushPull);
+        let mut this = Self::new_inner(
+            peri,
+            None,
+            new_pin!(tx, AFType::OutputOpenDrain),
+            None,
+            None,
+            None,
+            None,
+            None,
+            smartcard_config(&config, smartcard),
+        )?;
+        this.tx.ck = ck;
+        Ok(this)
+    }
+}
+
+impl<'d, T: BasicInstance + FullInstance, M: Mode> Uart<'d, T, M> {
+    /// Reconfigure a smartcard interface, for instance with the baud rate selected by the card.
+    ///
+    /// Reconfiguring it with `set_config` would leave the smartcard mode.
+    pub fn set_smartcard_config(&mut self, config: &Config, smartcard: SmartcardConfig) -> Result<(), ConfigError> {
+        reconfigure::<T>(&smartcard_config(config, smartcard))
+    }
+
+    /// Reconfigure an IrDA interface.
+    ///
+    /// Reconfiguring it with `set_config` would leave the IrDA mode.
+    pub fn set_irda_config(&mut self, config: &Config, irda: IrdaConfig) -> Result<(), ConfigError> {
+        reconfigure::<T>(&irda_config(config, irda))
+    }
+}
+
 impl<'d, T: BasicInstance, M: Mode> Uart<'d, T, M> {
     fn new_inner(
         _peri: impl Peripheral<P = T> + 'd,
@@ -1169,6 +1511,7 @@ impl<'d, T: BasicInstance, M: Mode> Uart<'d, T, M> {
             w.set_dem(de.is_some());
         });
         configure(r, &config, T::frequency(), T::KIND, true, true)?;
+        update_stop_handoff::<T>(config.wakeup_enabled());
 
         T::Interrupt::unpend();
         unsafe { T::Interrupt::enable() };
@@ -1182,7 +1525,9 @@ impl<'d, T: BasicInstance, M: Mode> Uart<'d, T, M> {
                 tx,
                 cts,
                 de,
+                ck: None,
                 tx_dma,
+                dma_priority: config.dma_priority,
             },
             rx: UartRx {
                 _phantom: PhantomData,
@@ -1190,6 +1535,7 @@ impl<'d, T: BasicInstance, M: Mode> Uart<'d, T, M> {
                 rts,
                 rx_dma,
                 detect_previous_overrun: config.detect_previous_overrun,
+                dma_priority: config.dma_priority,
                 #[cfg(any(usart_v1, usart_v2))]
                 buffered_sr: stm32_metapac::usart::regs::Sr(0),
             },
@@ -1224,12 +1570,33 @@ impl<'d, T: BasicInstance, M: Mode> Uart<'d, T, M> {
     }
 }
 
+fn smartcard_config(config: &Config, smartcard: SmartcardConfig) -> Config {
+    let mut config = *config;
+    #[cfg(any(usart_v3, usart_v4))]
+    {
+        config.swap_rx_tx = false;
+    }
+    config.data_bits = DataBits::DataBits8;
+    config.parity = Parity::ParityEven;
+    config.stop_bits = StopBits::STOP1P5;
+    config.smartcard = Some(smartcard);
+    config
+}
+
+fn irda_config(config: &Config, irda: IrdaConfig) -> Config {
+    let mut config = *config;
+    config.stop_bits = StopBits::STOP1;
+    config.irda = Some(irda);
+    config
+}
+
 fn reconfigure<T: BasicInstance>(config: &Config) -> Result<(), ConfigError> {
     T::Interrupt::disable();
     let r = T::regs();
 
     let cr = r.cr1().read();
     configure(r, config, T::frequency(), T::KIND, cr.re(), cr.te())?;
+    update_stop_handoff::<T>(cr.re() && config.wakeup_enabled());
 
     T::Interrupt::unpend();
     unsafe { T::Interrupt::enable() };
@@ -1248,6 +1615,13 @@ fn configure(
     if !enable_rx && !enable_tx {
         return Err(ConfigError::RxOrTxNotEnabled);
     }
+    if config.rx_resume_level >= config.rx_stop_level || config.rx_stop_level > 100 {
+        return Err(ConfigError::InvalidFlowControlLevels);
+    }
+    #[cfg(any(usart_v3, usart_v4))]
+    if config.wakeup.is_some() && kind != Kind::Lpuart {
+        return Err(ConfigError::WakeupNotSupported);
+    }
 
     #[cfg(not(usart_v4))]
     static DIVS: [(u16, ()); 1] = [(1, ())];
@@ -1355,6 +1729,12 @@ fn configure(
             w.set_txinv(config.invert_tx);
             w.set_rxinv(config.invert_rx);
             w.set_swap(config.swap_rx_tx);
+
+            if let Some(WakeupEvent::AddressMatch(addr)) = config.wakeup {
+                // 7-bit address detection.
+                w.set_addm7(vals::Addm7::from_bits(1));
+                w.set_add(addr & 0x7F);
+            }
         }
     });
 
@@ -1362,8 +1742,77 @@ fn configure(
         #[cfg(not(usart_v1))]
         w.set_onebit(config.assume_noise_free);
         w.set_hdsel(config.half_duplex);
+
+        // WUS can only be written while the LPUART is disabled, as it is here.
+        #[cfg(any(usart_v3, usart_v4))]
+        if let Some(wakeup) = config.wakeup {
+            w.set_wus(vals::Wus::from_bits(match wakeup {
+                WakeupEvent::AddressMatch(_) => 0b00,
+                WakeupEvent::StartBit => 0b10,
+                WakeupEvent::RxNotEmpty => 0b11,
+            }));
+        }
     });
 
+    // The smartcard and IrDA modes are only supported by USARTs, which their constructors require.
+    let u = unsafe { crate::pac::usart::Usart::from_ptr(r.as_ptr()) };
+    match config.smartcard {
+        Some(smartcard) => {
+            // The card clock is the peripheral clock divided by 2 * PSC.
+            let psc = (pclk_freq.0 + 2 * smartcard.clock.0 - 1) / (2 * smartcard.clock.0);
+            let psc = psc.clamp(1, 31);
+            trace!(
+                "USART: smartcard clock: desired {}, actual {}",
+                smartcard.clock.0,
+                pclk_freq.0 / (2 * psc)
+            );
+            u.gtpr().write(|w| {
+                w.set_psc(psc as u8);
+                w.set_gt(smartcard.guard_time);
+            });
+
+            u.cr2().modify(|w| {
+                w.set_clken(true);
+                #[cfg(any(usart_v3, usart_v4))]
+                {
+                    let inverse = smartcard.convention == Convention::Inverse;
+                    w.set_datainv(inverse);
+                    w.set_msbfirst(if inverse {
+                        vals::Msbfirst::MSB
+                    } else {
+                        vals::Msbfirst::LSB
+                    });
+                }
+            });
+
+            u.cr3().modify(|w| {
+                w.set_nack(smartcard.nack);
+                #[cfg(any(usart_v3, usart_v4))]
+                w.set_scarcnt(smartcard.auto_retry_count.min(7));
+                w.set_scen(true);
+            });
+        }
+        None if kind == Kind::Uart => u.cr3().modify(|w| w.set_scen(false)),
+        None => {}
+    }
+
+    match config.irda {
+        Some(irda) => {
+            // The prescaler must be 1 in normal mode.
+            let psc = match irda.low_power {
+                true => ((pclk_freq.0 + irda.low_power_clock.0 - 1) / irda.low_power_clock.0).clamp(1, 255),
+                false => 1,
+            };
+            u.gtpr().write(|w| w.set_psc(psc as u8));
+            u.cr3().modify(|w| {
+                w.set_irlp(irda.low_power);
+                w.set_iren(true);
+            });
+        }
+        None if kind == Kind::Uart => u.cr3().modify(|w| w.set_iren(false)),
+        None => {}
+    }
+
     r.cr1().write(|w| {
         // enable uart
         w.set_ue(true);
@@ -1389,6 +1838,9 @@ fn configure(
         w.set_over8(vals::Over8::from_bits(over8 as _));
         #[cfg(usart_v4)]
         w.set_fifoen(true);
+        // enable wakeup from Stop mode
+        #[cfg(any(usart_v3, usart_v4))]
+        w.set_uesm(config.wakeup.is_some());
     });
 
     Ok(())
@@ -1622,16 +2074,68 @@ enum Kind {
 
 struct State {
     rx_waker: AtomicWaker,
+    #[cfg(any(usart_v3, usart_v4))]
+    wakeup_waker: AtomicWaker,
+    /// Whether the Stop mode is allowed, see [`update_stop_handoff`].
+    #[cfg(feature = "low-power")]
+    stop_allowed: core::sync::atomic::AtomicBool,
 }
 
 impl State {
     const fn new() -> Self {
         Self {
             rx_waker: AtomicWaker::new(),
+            #[cfg(any(usart_v3, usart_v4))]
+            wakeup_waker: AtomicWaker::new(),
+            #[cfg(feature = "low-power")]
+            stop_allowed: core::sync::atomic::AtomicBool::new(false),
         }
     }
 }
 
+#[cfg(any(usart_v3, usart_v4))]
+async fn wait_for_wakeup<T: BasicInstance>() {
+    let r = T::regs();
+    let s = T::state();
+
+    // Only wait for the next event.
+    r.icr().write(|w| w.set_wucf(true));
+    r.cr3().modify(|w| w.set_wufie(true));
+    let _on_drop = OnDrop::new(move || r.cr3().modify(|w| w.set_wufie(false)));
+
+    // The interrupt handler disables the interrupt when the event is detected.
+    poll_fn(|cx| {
+        s.wakeup_waker.register(cx.waker());
+        if r.cr3().read().wufie() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await
+}
+
+/// Let the low-power executor enter Stop mode while the receiver waits for a wakeup event, if
+/// the kernel clock keeps running in Stop mode, or keep the MCU out of it again.
+///
+/// Instances are enabled once per half, and only the receiver hands the Stop mode over, so it
+/// must be blocked again before its half is disabled.
+#[allow(unused_variables)]
+fn update_stop_handoff<T: BasicInstance>(wakeup: bool) {
+    #[cfg(feature = "low-power")]
+    critical_section::with(|cs| {
+        let allow = wakeup && T::kernel_clock_runs_in_stop();
+        let s = T::state();
+        if s.stop_allowed.load(Ordering::Relaxed) != allow {
+            s.stop_allowed.store(allow, Ordering::Relaxed);
+            match allow {
+                true => T::allow_stop_with_cs(cs),
+                false => T::block_stop_with_cs(cs),
+            }
+        }
+    });
+}
+
 trait SealedBasicInstance: crate::rcc::RccPeripheral {
     const KIND: Kind;
 
@@ -7,8 +7,10 @@ use core::task::Poll;
 use embassy_embedded_hal::SetConfig;
 use futures::future::{select, Either};
 
-use super::{clear_interrupt_flags, rdr, reconfigure, sr, BasicInstance, Config, ConfigError, Error, UartRx};
-use crate::dma::ReadableRingBuffer;
+use super::{
+    clear_interrupt_flags, rdr, reconfigure, sr, update_stop_handoff, BasicInstance, Config, ConfigError, Error, UartRx,
+};
+use crate::dma::{ReadableRingBuffer, TransferOptions};
 use crate::mode::Async;
 use crate::usart::{Regs, Sr};
 
@@ -36,7 +38,10 @@ impl<'d, T: BasicInstance> UartRx<'d, T, Async> {
     pub fn into_ring_buffered(mut self, dma_buf: &'d mut [u8]) -> RingBufferedUartRx<'d, T> {
         assert!(!dma_buf.is_empty() && dma_buf.len() <= 0xFFFF);
 
-        let opts = Default::default();
+        let opts = TransferOptions {
+            priority: self.dma_priority,
+            ..Default::default()
+        };
 
         // Safety: we forget the struct before this function returns.
         let rx_dma = self.rx_dma.as_mut().unwrap();
@@ -216,6 +221,7 @@ impl<T: BasicInstance> Drop for RingBufferedUartRx<'_, T> {
     fn drop(&mut self) {
         self.teardown_uart();
 
+        update_stop_handoff::<T>(false);
         T::disable();
     }
 }
@@ -1,8 +1,10 @@
 use core::cell::UnsafeCell;
+use core::future::Future;
 use core::marker::PhantomData;
 use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use core::task::Poll;
 
+use embassy_futures::select::{select, Either};
 use embassy_hal_internal::{into_ref, Peripheral};
 use embassy_sync::waitqueue::AtomicWaker;
 use embassy_usb_driver::{
@@ -1254,6 +1256,29 @@ pub struct ControlPipe<'d, T: Instance> {
     ep_out: Endpoint<'d, T, Out>,
 }
 
+/// Runs a data or status stage transfer, aborting it if the host sends another SETUP packet.
+async fn abort_on_setup<T: Instance, R>(
+    fut: impl Future<Output = Result<R, EndpointError>>,
+) -> Result<R, EndpointError> {
+    let setup = poll_fn(|cx| {
+        let state = T::state();
+        state.ep_out_wakers[0].register(cx.waker());
+        if state.ep0_setup_ready.load(Ordering::Relaxed) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    });
+
+    match select(fut, setup).await {
+        Either::First(res) => res,
+        Either::Second(()) => {
+            trace!("control: aborted, received another SETUP");
+            Err(EndpointError::Disabled)
+        }
+    }
+}
+
 impl<'d, T: Instance> embassy_usb_driver::ControlPipe for ControlPipe<'d, T> {
     fn max_packet_size(&self) -> usize {
         usize::from(self.max_packet_size)
@@ -1294,19 +1319,19 @@ impl<'d, T: Instance> embassy_usb_driver::ControlPipe for ControlPipe<'d, T> {
 
     async fn data_out(&mut self, buf: &mut [u8], _first: bool, _last: bool) -> Result<usize, EndpointError> {
         trace!("control: data_out");
-        let len = self.ep_out.read(buf).await?;
+        let len = abort_on_setup::<T, _>(self.ep_out.read(buf)).await?;
         trace!("control: data_out read: {:?}", &buf[..len]);
         Ok(len)
     }
 
     async fn data_in(&mut self, data: &[u8], _first: bool, last: bool) -> Result<(), EndpointError> {
         trace!("control: data_in write: {:?}", data);
-        self.ep_in.write(data).await?;
+        abort_on_setup::<T, _>(self.ep_in.write(data)).await?;
 
         // wait for status response from host after sending the last packet
         if last {
             trace!("control: data_in waiting for status");
-            self.ep_out.read(&mut []).await?;
+            abort_on_setup::<T, _>(self.ep_out.read(&mut [])).await?;
             trace!("control: complete");
         }
 
@@ -1316,7 +1341,7 @@ impl<'d, T: Instance> embassy_usb_driver::ControlPipe for ControlPipe<'d, T> {
     async fn accept(&mut self) {
         trace!("control: accept");
 
-        self.ep_in.write(&[]).await.ok();
+        abort_on_setup::<T, _>(self.ep_in.write(&[])).await.ok();
 
         trace!("control: accept OK");
     }
@@ -0,0 +1,283 @@
+//! A queue of items that become available at a deadline.
+//!
+//! Requires the `time` feature.
+use core::cell::RefCell;
+use core::cmp::Ordering;
+use core::future::{poll_fn, Future};
+use core::mem;
+use core::pin::Pin;
+use core::task::Poll;
+
+use embassy_time::{Duration, Instant, Timer};
+use heapless::binary_heap::Min;
+use heapless::BinaryHeap;
+
+use crate::blocking_mutex::raw::RawMutex;
+use crate::blocking_mutex::Mutex;
+use crate::channel::TrySendError;
+use crate::waitqueue::WakerRegistration;
+
+struct Entry<T> {
+    deadline: Instant,
+    /// Insertion order, so items with the same deadline are popped first in, first out.
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+struct State<T, const N: usize> {
+    queue: BinaryHeap<Entry<T>, Min, N>,
+    seq: u64,
+    receiver_waker: WakerRegistration,
+    senders_waker: WakerRegistration,
+}
+
+impl<T, const N: usize> State<T, N> {
+    fn try_insert(&mut self, item: T, deadline: Instant) -> Result<(), TrySendError<T>> {
+        let entry = Entry {
+            deadline,
+            seq: self.seq,
+            item,
+        };
+        match self.queue.push(entry) {
+            Ok(()) => {
+                self.seq += 1;
+                // The new item may be due before the one the receiver is waiting for.
+                self.receiver_waker.wake();
+                Ok(())
+            }
+            Err(entry) => Err(TrySendError::Full(entry.item)),
+        }
+    }
+
+    fn try_pop(&mut self, now: Instant) -> Result<T, Option<Instant>> {
+        match self.queue.peek() {
+            Some(entry) if entry.deadline <= now => {
+                // Safety: the queue isn't empty, we just peeked at it.
+                let entry = unsafe { self.queue.pop_unchecked() };
+                self.senders_waker.wake();
+                Ok(entry.item)
+            }
+            Some(entry) => Err(Some(entry.deadline)),
+            None => Err(None),
+        }
+    }
+}
+
+/// Bounded queue of items ordered by deadline.
+///
+/// Each item is inserted with a deadline, and can only be popped once its deadline has
+/// passed. Items are popped in deadline order, and items with the same deadline in the order
+/// they were inserted. This is useful for retransmission queues, or jobs scheduled from
+/// several tasks and run by a single one.
+///
+/// The queue holds up to `N` items. Like [`Channel`](crate::channel::Channel), it can have
+/// multiple inserting tasks, but only one task should wait in [`pop`](Self::pop) at a time.
+pub struct DelayQueue<M, T, const N: usize>
+where
+    M: RawMutex,
+{
+    inner: Mutex<M, RefCell<State<T, N>>>,
+}
+
+impl<M, T, const N: usize> DelayQueue<M, T, N>
+where
+    M: RawMutex,
+{
+    /// Create a new, empty queue.
+    ///
+    /// ```
+    /// use embassy_sync::delay_queue::DelayQueue;
+    /// use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    ///
+    /// // Declare a bounded delay queue of u32s with a capacity of 3.
+    /// let queue = DelayQueue::<NoopRawMutex, u32, 3>::new();
+    /// ```
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(State {
+                queue: BinaryHeap::new(),
+                seq: 0,
+                receiver_waker: WakerRegistration::new(),
+                senders_waker: WakerRegistration::new(),
+            })),
+        }
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut State<T, N>) -> R) -> R {
+        self.inner.lock(|rc| f(&mut *unwrap!(rc.try_borrow_mut())))
+    }
+
+    /// Attempt to immediately insert an item, due at `deadline`.
+    ///
+    /// Returns the item back if the queue is full.
+    pub fn try_insert(&self, item: T, deadline: Instant) -> Result<(), TrySendError<T>> {
+        self.lock(|s| s.try_insert(item, deadline))
+    }
+
+    /// Attempt to immediately insert an item, due after `delay`.
+    pub fn try_insert_after(&self, item: T, delay: Duration) -> Result<(), TrySendError<T>> {
+        self.try_insert(item, Instant::now() + delay)
+    }
+
+    /// Insert an item, due at `deadline`, waiting for space if the queue is full.
+    pub async fn insert(&self, item: T, deadline: Instant) {
+        let mut item = Some(item);
+        poll_fn(|cx| {
+            self.lock(|s| match s.try_insert(unwrap!(item.take()), deadline) {
+                Ok(()) => Poll::Ready(()),
+                Err(TrySendError::Full(i)) => {
+                    item = Some(i);
+                    s.senders_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Insert an item, due after `delay`, waiting for space if the queue is full.
+    ///
+    /// The deadline is computed when this is called, not when the item is inserted.
+    pub async fn insert_after(&self, item: T, delay: Duration) {
+        self.insert(item, Instant::now() + delay).await
+    }
+
+    /// Attempt to immediately pop the item with the earliest deadline, if it has passed.
+    pub fn try_pop(&self) -> Option<T> {
+        self.lock(|s| s.try_pop(Instant::now()).ok())
+    }
+
+    /// Pop the item with the earliest deadline, waiting until it has passed.
+    ///
+    /// Items inserted while waiting are taken into account, so an item with an earlier deadline
+    /// is popped first.
+    pub fn pop(&self) -> impl Future<Output = T> + '_ {
+        let mut timer: Option<(Instant, Timer)> = None;
+        poll_fn(move |cx| {
+            self.lock(|s| loop {
+                let deadline = match s.try_pop(Instant::now()) {
+                    Ok(item) => return Poll::Ready(item),
+                    Err(deadline) => deadline,
+                };
+
+                s.receiver_waker.register(cx.waker());
+                let Some(deadline) = deadline else {
+                    return Poll::Pending;
+                };
+
+                if !matches!(timer, Some((at, _)) if at == deadline) {
+                    timer = Some((deadline, Timer::at(deadline)));
+                }
+                let (_, t) = unwrap!(timer.as_mut());
+                if Pin::new(t).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                // The deadline passed since we checked, try again.
+            })
+        })
+    }
+
+    /// Deadline of the next item to pop, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.lock(|s| s.queue.peek().map(|entry| entry.deadline))
+    }
+
+    /// Keep only the items for which `f` returns `true`, e.g. to cancel the retransmission of
+    /// acknowledged packets.
+    pub fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        self.lock(|s| {
+            let queue = mem::replace(&mut s.queue, BinaryHeap::new());
+            let len = queue.len();
+            for entry in queue.into_vec() {
+                if f(&entry.item) {
+                    // Can't fail: the queue holds at most as many items as before.
+                    let _ = s.queue.push(entry);
+                }
+            }
+            if s.queue.len() != len {
+                s.senders_waker.wake();
+                s.receiver_waker.wake();
+            }
+        })
+    }
+
+    /// Remove all items.
+    pub fn clear(&self) {
+        self.lock(|s| {
+            s.queue.clear();
+            s.senders_waker.wake();
+            s.receiver_waker.wake();
+        })
+    }
+
+    /// Number of items in the queue, due or not.
+    pub fn len(&self) -> usize {
+        self.lock(|s| s.queue.len())
+    }
+
+    /// Whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.lock(|s| s.queue.is_empty())
+    }
+
+    /// Whether the queue is full.
+    pub fn is_full(&self) -> bool {
+        self.lock(|s| s.queue.len() == N)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+
+    use embassy_time::MockDriver;
+    use futures_util::poll;
+
+    use super::*;
+    use crate::blocking_mutex::raw::NoopRawMutex;
+
+    #[futures_test::test]
+    async fn deadline_order() {
+        let driver = MockDriver::get();
+        driver.reset();
+
+        let queue = DelayQueue::<NoopRawMutex, u32, 3>::new();
+        let start = Instant::now();
+        assert!(queue.try_insert(1, start + Duration::from_secs(2)).is_ok());
+        assert!(queue.try_insert(2, start + Duration::from_secs(1)).is_ok());
+        assert!(queue.try_insert(3, start + Duration::from_secs(1)).is_ok());
+        assert_eq!(queue.try_insert(4, start), Err(TrySendError::Full(4)));
+        assert_eq!(queue.next_deadline(), Some(start + Duration::from_secs(1)));
+
+        let mut pop = pin!(queue.pop());
+        assert!(poll!(pop.as_mut()).is_pending());
+
+        driver.advance(Duration::from_secs(1));
+        assert_eq!(poll!(pop.as_mut()), Poll::Ready(2));
+        assert_eq!(queue.try_pop(), Some(3));
+        assert_eq!(queue.try_pop(), None);
+
+        queue.retain(|&item| item != 1);
+        assert!(queue.is_empty());
+    }
+}
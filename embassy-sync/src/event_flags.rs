@@ -0,0 +1,209 @@
+//! A synchronization primitive for waiting on a set of event bits, like RTOS event groups.
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::task::{Context, Poll};
+
+use crate::blocking_mutex::raw::RawMutex;
+use crate::blocking_mutex::Mutex;
+use crate::waitqueue::MultiWakerRegistration;
+
+/// A set of 32 event flags that tasks can wait on.
+///
+/// Flags are set and cleared with a bitmask, e.g. from interrupt handlers or other tasks. Tasks
+/// wait until any or all of the flags in a mask are set, optionally clearing them when the
+/// wait completes. This is similar to event groups in FreeRTOS, or event flags in other RTOSes.
+///
+/// Unlike [`Signal`](crate::signal::Signal), any number of tasks can wait at the same time:
+/// up to `N` waiters are tracked individually, and when there are more they are all woken on
+/// every change, to check their condition again.
+///
+/// A waiter sees the flags when it runs, not when they were set: if a flag is set and cleared
+/// again before a waiting task runs, that task doesn't notice it. This also means that when
+/// several tasks wait with clear-on-exit on the same flag, only the first one to run returns.
+///
+/// ```
+/// use embassy_sync::event_flags::EventFlags;
+/// use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+///
+/// const RX_DONE: u32 = 1 << 0;
+/// const TX_DONE: u32 = 1 << 1;
+///
+/// static EVENTS: EventFlags<CriticalSectionRawMutex, 4> = EventFlags::new();
+///
+/// async fn transfer() {
+///     let flags = EVENTS.wait_any_and_clear(RX_DONE | TX_DONE).await;
+///     if flags & RX_DONE != 0 {
+///         // ...
+///     }
+/// }
+/// ```
+pub struct EventFlags<M: RawMutex, const N: usize> {
+    state: Mutex<M, RefCell<State<N>>>,
+}
+
+struct State<const N: usize> {
+    flags: u32,
+    wakers: MultiWakerRegistration<N>,
+}
+
+impl<M: RawMutex, const N: usize> EventFlags<M, N> {
+    /// Create new event flags, all cleared.
+    pub const fn new() -> Self {
+        Self::with_flags(0)
+    }
+
+    /// Create new event flags, with the flags in `flags` set.
+    pub const fn with_flags(flags: u32) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                flags,
+                wakers: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    /// Set the flags in `mask`, and wake the waiting tasks.
+    ///
+    /// Returns the flags after setting them.
+    pub fn set(&self, mask: u32) -> u32 {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.flags |= mask;
+            s.wakers.wake();
+            s.flags
+        })
+    }
+
+    /// Clear the flags in `mask`.
+    ///
+    /// Returns the flags before clearing them.
+    pub fn clear(&self, mask: u32) -> u32 {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            let flags = s.flags;
+            s.flags &= !mask;
+            flags
+        })
+    }
+
+    /// Get the current flags.
+    pub fn get(&self) -> u32 {
+        self.state.lock(|s| s.borrow().flags)
+    }
+
+    /// Wait until any flag in `mask` is set.
+    ///
+    /// Returns all flags, as they were when the wait completed.
+    pub fn wait_any(&self, mask: u32) -> impl Future<Output = u32> + '_ {
+        poll_fn(move |cx| self.poll_wait(cx, mask, false, false))
+    }
+
+    /// Wait until all flags in `mask` are set.
+    ///
+    /// Returns all flags, as they were when the wait completed.
+    pub fn wait_all(&self, mask: u32) -> impl Future<Output = u32> + '_ {
+        poll_fn(move |cx| self.poll_wait(cx, mask, true, false))
+    }
+
+    /// Wait until any flag in `mask` is set, and clear the flags in `mask`.
+    ///
+    /// Returns all flags, as they were before clearing them.
+    pub fn wait_any_and_clear(&self, mask: u32) -> impl Future<Output = u32> + '_ {
+        poll_fn(move |cx| self.poll_wait(cx, mask, false, true))
+    }
+
+    /// Wait until all flags in `mask` are set, and clear the flags in `mask`.
+    ///
+    /// Returns all flags, as they were before clearing them.
+    pub fn wait_all_and_clear(&self, mask: u32) -> impl Future<Output = u32> + '_ {
+        poll_fn(move |cx| self.poll_wait(cx, mask, true, true))
+    }
+
+    fn poll_wait(&self, cx: &mut Context<'_>, mask: u32, all: bool, clear: bool) -> Poll<u32> {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            let flags = s.flags;
+            let done = match all {
+                true => flags & mask == mask,
+                false => flags & mask != 0,
+            };
+
+            if done {
+                if clear {
+                    s.flags &= !mask;
+                }
+                Poll::Ready(flags)
+            } else {
+                s.wakers.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl<M: RawMutex, const N: usize> Default for EventFlags<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+
+    use futures_util::poll;
+
+    use super::*;
+    use crate::blocking_mutex::raw::NoopRawMutex;
+
+    #[test]
+    fn set_clear() {
+        let flags = EventFlags::<NoopRawMutex, 1>::with_flags(0b0001);
+
+        assert_eq!(flags.set(0b0110), 0b0111);
+        assert_eq!(flags.clear(0b0011), 0b0111);
+        assert_eq!(flags.get(), 0b0100);
+    }
+
+    #[futures_test::test]
+    async fn wait_any() {
+        let flags = EventFlags::<NoopRawMutex, 1>::new();
+
+        let mut fut = pin!(flags.wait_any(0b0011));
+        assert!(poll!(fut.as_mut()).is_pending());
+
+        flags.set(0b0100);
+        assert!(poll!(fut.as_mut()).is_pending());
+
+        flags.set(0b0010);
+        assert_eq!(poll!(fut.as_mut()), Poll::Ready(0b0110));
+        assert_eq!(flags.get(), 0b0110);
+    }
+
+    #[futures_test::test]
+    async fn wait_all_and_clear() {
+        let flags = EventFlags::<NoopRawMutex, 1>::new();
+
+        let mut fut = pin!(flags.wait_all_and_clear(0b0011));
+        flags.set(0b0101);
+        assert!(poll!(fut.as_mut()).is_pending());
+
+        flags.set(0b0010);
+        assert_eq!(poll!(fut.as_mut()), Poll::Ready(0b0111));
+        assert_eq!(flags.get(), 0b0100);
+    }
+
+    #[futures_test::test]
+    async fn multiple_waiters() {
+        let flags = EventFlags::<NoopRawMutex, 2>::new();
+
+        let mut a = pin!(flags.wait_any_and_clear(0b0001));
+        let mut b = pin!(flags.wait_any(0b0001));
+        assert!(poll!(a.as_mut()).is_pending());
+        assert!(poll!(b.as_mut()).is_pending());
+
+        flags.set(0b0001);
+        assert_eq!(poll!(a.as_mut()), Poll::Ready(0b0001));
+        assert!(poll!(b.as_mut()).is_pending());
+    }
+}
@@ -12,6 +12,9 @@ mod ring_buffer;
 
 pub mod blocking_mutex;
 pub mod channel;
+#[cfg(feature = "time")]
+pub mod delay_queue;
+pub mod event_flags;
 pub mod mutex;
 pub mod once_lock;
 pub mod pipe;
@@ -21,3 +24,4 @@ pub mod semaphore;
 pub mod signal;
 pub mod waitqueue;
 pub mod zerocopy_channel;
+pub mod zerocopy_pipe;
@@ -0,0 +1,465 @@
+//! A lock-free, zero-copy byte pipe for a single producer and a single consumer.
+//!
+//! Unlike [`Pipe`](crate::pipe::Pipe), this pipe never copies data on its own. The writer asks
+//! for a contiguous free region with [`Writer::write_buf`], fills it (for example by pointing a
+//! DMA transfer at it) and then publishes the bytes with [`Writer::commit`]. The reader does the
+//! opposite with [`Reader::read_buf`] and [`Reader::consume`], so a protocol parser can work
+//! directly on the bytes in the pipe's buffer.
+//!
+//! The read and write positions are only ever modified by their respective side, so the pipe
+//! does not need a mutex: it can be shared between thread mode and interrupt handlers, and
+//! between cores, as long as there is only one [`Reader`] and one [`Writer`] at a time. This is
+//! enforced by [`ZeroCopyPipe::split`] borrowing the pipe mutably.
+
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use crate::waitqueue::AtomicWaker;
+
+/// A bounded, lock-free byte pipe with zero-copy access to its buffer.
+///
+/// The pipe can hold up to `N` bytes. Data becomes available to the reader in the same
+/// order as it was committed by the writer.
+pub struct ZeroCopyPipe<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    /// Read position, in `0..2*N`. Only written by the reader.
+    start: AtomicUsize,
+    /// Write position, in `0..2*N`. Only written by the writer.
+    end: AtomicUsize,
+    read_waker: AtomicWaker,
+    write_waker: AtomicWaker,
+}
+
+unsafe impl<const N: usize> Sync for ZeroCopyPipe<N> {}
+
+impl<const N: usize> ZeroCopyPipe<N> {
+    /// Create a new, empty pipe.
+    ///
+    /// ```
+    /// use embassy_sync::zerocopy_pipe::ZeroCopyPipe;
+    ///
+    /// // Declare a pipe with a buffer of 256 bytes.
+    /// let mut pipe = ZeroCopyPipe::<256>::new();
+    /// let (reader, writer) = pipe.split();
+    /// ```
+    pub const fn new() -> Self {
+        assert!(N != 0 && N <= usize::MAX / 2);
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+            read_waker: AtomicWaker::new(),
+            write_waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Split the pipe into its reading and writing halves.
+    ///
+    /// The halves borrow the pipe mutably, which guarantees there is only one reader
+    /// and one writer at any time.
+    pub fn split(&mut self) -> (Reader<'_, N>, Writer<'_, N>) {
+        (Reader { pipe: self }, Writer { pipe: self })
+    }
+
+    /// Total byte capacity.
+    ///
+    /// This is the same as the `N` generic param.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Used byte capacity.
+    pub fn len(&self) -> usize {
+        let start = self.start.load(Ordering::Acquire);
+        let end = self.end.load(Ordering::Acquire);
+        Self::distance(start, end)
+    }
+
+    /// Free byte capacity.
+    ///
+    /// This is equivalent to `capacity() - len()`
+    pub fn free_capacity(&self) -> usize {
+        N - self.len()
+    }
+
+    /// Return whether the pipe is empty (no data buffered)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return whether the pipe is full (no free space in the buffer)
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    fn distance(start: usize, end: usize) -> usize {
+        if end >= start {
+            end - start
+        } else {
+            2 * N + end - start
+        }
+    }
+
+    fn advance(pos: usize, n: usize) -> usize {
+        let pos = pos + n;
+        if pos >= 2 * N {
+            pos - 2 * N
+        } else {
+            pos
+        }
+    }
+
+    fn index(pos: usize) -> usize {
+        if pos >= N {
+            pos - N
+        } else {
+            pos
+        }
+    }
+
+    /// Free contiguous region available to the writer, as `(offset, len)`.
+    fn write_region(&self) -> (usize, usize) {
+        let start = self.start.load(Ordering::Acquire);
+        let end = self.end.load(Ordering::Relaxed);
+        let free = N - Self::distance(start, end);
+        let offset = Self::index(end);
+        (offset, free.min(N - offset))
+    }
+
+    /// Filled contiguous region available to the reader, as `(offset, len)`.
+    fn read_region(&self) -> (usize, usize) {
+        let start = self.start.load(Ordering::Relaxed);
+        let end = self.end.load(Ordering::Acquire);
+        let used = Self::distance(start, end);
+        let offset = Self::index(start);
+        (offset, used.min(N - offset))
+    }
+
+    // safety: the range must not overlap with any other live slice into the buffer.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slice(&self, offset: usize, len: usize) -> &mut [u8] {
+        let p = self.buf.get() as *mut u8;
+        core::slice::from_raw_parts_mut(p.add(offset), len)
+    }
+}
+
+/// Write half of a [`ZeroCopyPipe`].
+pub struct Writer<'p, const N: usize> {
+    pipe: &'p ZeroCopyPipe<N>,
+}
+
+impl<'p, const N: usize> Writer<'p, N> {
+    /// Get the contiguous free region of the buffer, if there is any.
+    ///
+    /// The returned slice may be smaller than the total free capacity if the free space
+    /// wraps around the end of the buffer. Returns `None` if the pipe is full.
+    ///
+    /// Bytes written into the slice are not visible to the reader until they are
+    /// published with [`commit`](Self::commit).
+    pub fn try_write_buf(&mut self) -> Option<&mut [u8]> {
+        let (offset, len) = self.pipe.write_region();
+        if len == 0 {
+            return None;
+        }
+        // safety: the writer exclusively owns the free region, and `&mut self` ensures there
+        // is only one slice into it at a time.
+        Some(unsafe { self.pipe.slice(offset, len) })
+    }
+
+    /// Poll for a contiguous free region of the buffer.
+    ///
+    /// See [`try_write_buf`](Self::try_write_buf).
+    pub fn poll_write_buf(&mut self, cx: &mut Context) -> Poll<&mut [u8]> {
+        match self.poll_writable(cx) {
+            Poll::Ready(()) => Poll::Ready(unwrap!(self.try_write_buf())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Wait for a contiguous free region of the buffer.
+    ///
+    /// If the pipe is full, this waits until the reader consumes some data.
+    /// See [`try_write_buf`](Self::try_write_buf).
+    pub async fn write_buf(&mut self) -> &mut [u8] {
+        poll_fn(|cx| self.poll_writable(cx)).await;
+        unwrap!(self.try_write_buf())
+    }
+
+    /// Publish `n` bytes previously written into the slice returned by
+    /// [`write_buf`](Self::write_buf) to the reader.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the contiguous free region.
+    pub fn commit(&mut self, n: usize) {
+        let (_, len) = self.pipe.write_region();
+        assert!(n <= len);
+        if n == 0 {
+            return;
+        }
+        let end = self.pipe.end.load(Ordering::Relaxed);
+        self.pipe
+            .end
+            .store(ZeroCopyPipe::<N>::advance(end, n), Ordering::Release);
+        self.pipe.read_waker.wake();
+    }
+
+    /// Wait until there is free space in the pipe.
+    pub async fn wait_writable(&mut self) {
+        poll_fn(|cx| self.poll_writable(cx)).await
+    }
+
+    fn poll_writable(&mut self, cx: &mut Context) -> Poll<()> {
+        if !self.pipe.is_full() {
+            return Poll::Ready(());
+        }
+        self.pipe.write_waker.register(cx.waker());
+        // Re-check, the reader may have consumed data before the waker was registered.
+        if self.pipe.is_full() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    /// Copy as many bytes as fit contiguously from `buf` into the pipe, without waiting.
+    ///
+    /// Returns the number of bytes written, which is zero if the pipe is full.
+    pub fn try_write(&mut self, buf: &[u8]) -> usize {
+        match self.try_write_buf() {
+            Some(dst) => {
+                let n = dst.len().min(buf.len());
+                dst[..n].copy_from_slice(&buf[..n]);
+                self.commit(n);
+                n
+            }
+            None => 0,
+        }
+    }
+
+    /// Free byte capacity.
+    pub fn free_capacity(&self) -> usize {
+        self.pipe.free_capacity()
+    }
+}
+
+/// Read half of a [`ZeroCopyPipe`].
+pub struct Reader<'p, const N: usize> {
+    pipe: &'p ZeroCopyPipe<N>,
+}
+
+impl<'p, const N: usize> Reader<'p, N> {
+    /// Get the contiguous filled region of the buffer, if there is any.
+    ///
+    /// The returned slice may be smaller than the total amount of buffered data if the data
+    /// wraps around the end of the buffer. Returns `None` if the pipe is empty.
+    ///
+    /// The bytes stay in the pipe until they are released with [`consume`](Self::consume).
+    pub fn try_read_buf(&mut self) -> Option<&[u8]> {
+        let (offset, len) = self.pipe.read_region();
+        if len == 0 {
+            return None;
+        }
+        // safety: the reader exclusively owns the filled region, the writer never touches it.
+        Some(unsafe { self.pipe.slice(offset, len) })
+    }
+
+    /// Poll for the contiguous filled region of the buffer.
+    ///
+    /// See [`try_read_buf`](Self::try_read_buf).
+    pub fn poll_read_buf(&mut self, cx: &mut Context) -> Poll<&[u8]> {
+        match self.poll_readable(cx) {
+            Poll::Ready(()) => Poll::Ready(unwrap!(self.try_read_buf())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Wait for the contiguous filled region of the buffer.
+    ///
+    /// If the pipe is empty, this waits until the writer commits some data.
+    /// See [`try_read_buf`](Self::try_read_buf).
+    pub async fn read_buf(&mut self) -> &[u8] {
+        poll_fn(|cx| self.poll_readable(cx)).await;
+        unwrap!(self.try_read_buf())
+    }
+
+    /// Release `n` bytes from the start of the slice returned by
+    /// [`read_buf`](Self::read_buf), making the space available to the writer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the contiguous filled region.
+    pub fn consume(&mut self, n: usize) {
+        let (_, len) = self.pipe.read_region();
+        assert!(n <= len);
+        if n == 0 {
+            return;
+        }
+        let start = self.pipe.start.load(Ordering::Relaxed);
+        self.pipe
+            .start
+            .store(ZeroCopyPipe::<N>::advance(start, n), Ordering::Release);
+        self.pipe.write_waker.wake();
+    }
+
+    /// Wait until there is data in the pipe.
+    pub async fn wait_readable(&mut self) {
+        poll_fn(|cx| self.poll_readable(cx)).await
+    }
+
+    fn poll_readable(&mut self, cx: &mut Context) -> Poll<()> {
+        if !self.pipe.is_empty() {
+            return Poll::Ready(());
+        }
+        self.pipe.read_waker.register(cx.waker());
+        // Re-check, the writer may have committed data before the waker was registered.
+        if self.pipe.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    /// Copy as many contiguous bytes as fit into `buf` out of the pipe, without waiting.
+    ///
+    /// Returns the number of bytes read, which is zero if the pipe is empty.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> usize {
+        match self.try_read_buf() {
+            Some(src) => {
+                let n = src.len().min(buf.len());
+                buf[..n].copy_from_slice(&src[..n]);
+                self.consume(n);
+                n
+            }
+            None => 0,
+        }
+    }
+
+    /// Used byte capacity.
+    pub fn len(&self) -> usize {
+        self.pipe.len()
+    }
+
+    /// Return whether the pipe is empty (no data buffered)
+    pub fn is_empty(&self) -> bool {
+        self.pipe.is_empty()
+    }
+}
+
+impl<const N: usize> embedded_io_async::ErrorType for Reader<'_, N> {
+    type Error = Infallible;
+}
+
+impl<const N: usize> embedded_io_async::Read for Reader<'_, N> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.wait_readable().await;
+        Ok(self.try_read(buf))
+    }
+}
+
+impl<const N: usize> embedded_io_async::BufRead for Reader<'_, N> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        Ok(self.read_buf().await)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        Reader::consume(self, amt)
+    }
+}
+
+impl<const N: usize> embedded_io_async::ErrorType for Writer<'_, N> {
+    type Error = Infallible;
+}
+
+impl<const N: usize> embedded_io_async::Write for Writer<'_, N> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.wait_writable().await;
+        Ok(self.try_write(buf))
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_executor::ThreadPool;
+    use futures_util::task::SpawnExt;
+    use static_cell::StaticCell;
+
+    use super::*;
+
+    #[test]
+    fn write_commit_read_consume() {
+        let mut p = ZeroCopyPipe::<4>::new();
+        let (mut r, mut w) = p.split();
+        assert!(r.try_read_buf().is_none());
+
+        let buf = w.try_write_buf().unwrap();
+        assert_eq!(buf.len(), 4);
+        buf[..3].copy_from_slice(&[1, 2, 3]);
+        w.commit(3);
+
+        assert_eq!(r.try_read_buf().unwrap(), &[1, 2, 3]);
+        r.consume(2);
+        assert_eq!(r.try_read_buf().unwrap(), &[3]);
+        assert_eq!(r.len(), 1);
+    }
+
+    #[test]
+    fn contiguous_regions_wrap() {
+        let mut p = ZeroCopyPipe::<4>::new();
+        let (mut r, mut w) = p.split();
+        assert_eq!(w.try_write(&[1, 2, 3]), 3);
+        r.consume(2);
+
+        // Only the tail of the buffer is contiguous.
+        assert_eq!(w.try_write_buf().unwrap().len(), 1);
+        assert_eq!(w.try_write(&[4, 5, 6]), 1);
+        assert_eq!(w.try_write(&[5, 6, 7]), 2);
+        assert!(w.try_write_buf().is_none());
+        assert_eq!(w.free_capacity(), 0);
+
+        assert_eq!(r.try_read_buf().unwrap(), &[3, 4]);
+        r.consume(2);
+        assert_eq!(r.try_read_buf().unwrap(), &[5, 6]);
+        r.consume(2);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn commit_too_much() {
+        let mut p = ZeroCopyPipe::<4>::new();
+        let (_r, mut w) = p.split();
+        w.commit(5);
+    }
+
+    #[futures_test::test]
+    async fn reader_waits_for_writer() {
+        let executor = ThreadPool::new().unwrap();
+
+        static PIPE: StaticCell<ZeroCopyPipe<8>> = StaticCell::new();
+        let (mut r, mut w) = PIPE.init(ZeroCopyPipe::new()).split();
+        executor
+            .spawn(async move {
+                let buf = w.write_buf().await;
+                buf[0] = 42;
+                w.commit(1);
+            })
+            .unwrap();
+
+        assert_eq!(r.read_buf().await, &[42]);
+        r.consume(1);
+    }
+}
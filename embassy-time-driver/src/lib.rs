@@ -59,6 +59,12 @@
 //!
 //! embassy_time_driver::time_driver_impl!(static DRIVER: MyDriver = MyDriver{});
 //! ```
+//!
+//! # Scaling the counter rate
+//!
+//! A driver whose hardware counter can run at different rates can also implement [`ScaledDriver`].
+//! [`TickScaler`] converts between the counter and the timebase, so that timestamps stay monotonic
+//! and in [`TICK_HZ`] ticks whatever the counter rate.
 
 //! ## Feature flags
 #![doc = document_features::document_features!(feature_label = r#"<span class="stab portability"><code>{feature}</code></span>"#)]
@@ -134,6 +140,88 @@ pub trait Driver: Send + Sync + 'static {
     fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) -> bool;
 }
 
+/// Time driver whose hardware counter rate can change at runtime.
+///
+/// The timebase stays at [`TICK_HZ`]: the driver converts counter values, for example with a
+/// [`TickScaler`], so that [`Driver::now`] stays monotonic and alarms keep their timestamp
+/// across a change. This lets a driver count slower while the system is mostly idle, e.g. to
+/// run from a low-power clock or to take fewer overflow interrupts, and faster again for
+/// precise timing.
+///
+/// There's no global function for this trait: the rate is changed through the driver itself,
+/// usually by the HAL that knows which clocks are available.
+pub trait ScaledDriver: Driver {
+    /// Return the current rate of the hardware counter, in Hz.
+    fn counter_hz(&self) -> u32;
+
+    /// Change the rate of the hardware counter to `hz`, or to the closest rate the hardware
+    /// supports. Returns `false`, without changing anything, if there is no such rate.
+    ///
+    /// Implementations MUST ensure that:
+    /// - [`Driver::now`] continues from the timestamp it had right before the change.
+    /// - Alarms that are set still fire at or after their timestamp, never before.
+    fn set_counter_hz(&self, hz: u32) -> bool;
+}
+
+/// Conversion between a hardware counter running at a variable rate and the [`TICK_HZ`]
+/// timebase, for implementing [`ScaledDriver`].
+///
+/// The scaler remembers the counter value and timestamp of the last rate change, and
+/// extrapolates from there. Counter values must be monotonic, e.g. extended to 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickScaler {
+    ticks: u64,
+    counter: u64,
+    counter_hz: u32,
+}
+
+impl TickScaler {
+    /// Create a scaler for a counter starting at 0 at timestamp 0, running at `counter_hz`.
+    pub const fn new(counter_hz: u32) -> Self {
+        Self {
+            ticks: 0,
+            counter: 0,
+            counter_hz,
+        }
+    }
+
+    /// Return the current counter rate, in Hz.
+    pub fn counter_hz(&self) -> u32 {
+        self.counter_hz
+    }
+
+    /// Convert a counter value to a timestamp, rounding down.
+    ///
+    /// Counter values from before the last rate change return the timestamp of the change.
+    pub fn to_ticks(&self, counter: u64) -> u64 {
+        let elapsed = counter.saturating_sub(self.counter);
+        if self.counter_hz as u64 == TICK_HZ {
+            return self.ticks + elapsed;
+        }
+        self.ticks + (elapsed as u128 * TICK_HZ as u128 / self.counter_hz as u128) as u64
+    }
+
+    /// Convert a timestamp to the first counter value at which it has been reached.
+    ///
+    /// Timestamps from before the last rate change return the counter value of the change.
+    pub fn to_counter(&self, ticks: u64) -> u64 {
+        let elapsed = ticks.saturating_sub(self.ticks);
+        if self.counter_hz as u64 == TICK_HZ {
+            return self.counter.saturating_add(elapsed);
+        }
+        let counter = (elapsed as u128 * self.counter_hz as u128).div_ceil(TICK_HZ as u128);
+        self.counter.saturating_add(counter.try_into().unwrap_or(u64::MAX))
+    }
+
+    /// Change the counter rate, from the given counter value on.
+    pub fn set_counter_hz(&mut self, counter: u64, counter_hz: u32) {
+        assert!(counter_hz > 0);
+        self.ticks = self.to_ticks(counter);
+        self.counter = counter;
+        self.counter_hz = counter_hz;
+    }
+}
+
 extern "Rust" {
     fn _embassy_time_now() -> u64;
     fn _embassy_time_allocate_alarm() -> Option<AlarmHandle>;
@@ -192,3 +280,53 @@ macro_rules! time_driver_impl {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaler_is_identity_at_tick_rate() {
+        let scaler = TickScaler::new(TICK_HZ as u32);
+        for counter in [0, 1, 12345, u32::MAX as u64] {
+            assert_eq!(scaler.to_ticks(counter), counter);
+            assert_eq!(scaler.to_counter(counter), counter);
+        }
+    }
+
+    #[test]
+    fn scaler_round_trip() {
+        let mut scaler = TickScaler::new(TICK_HZ as u32);
+        scaler.set_counter_hz(1000, 32768);
+
+        for ticks in (1001..10_000_000).step_by(7919) {
+            // The first counter value at which the timestamp has been reached.
+            let counter = scaler.to_counter(ticks);
+            assert!(scaler.to_ticks(counter) >= ticks);
+            assert!(scaler.to_ticks(counter - 1) < ticks);
+        }
+        for counter in (1000..1_000_000).step_by(613) {
+            assert!(scaler.to_counter(scaler.to_ticks(counter)) <= counter);
+        }
+    }
+
+    #[test]
+    fn scaler_is_monotonic_across_rate_changes() {
+        let mut scaler = TickScaler::new(TICK_HZ as u32);
+        let mut last = 0;
+        let mut counter = 0;
+        for hz in [32768, TICK_HZ as u32 * 3, 1000, 7, TICK_HZ as u32] {
+            for _ in 0..100 {
+                counter += 37;
+                let ticks = scaler.to_ticks(counter);
+                assert!(ticks >= last);
+                last = ticks;
+            }
+            scaler.set_counter_hz(counter, hz);
+            // The timestamp continues from where it was.
+            assert_eq!(scaler.to_ticks(counter), last);
+            // Counter values from before the change don't go back in time.
+            assert_eq!(scaler.to_ticks(counter - 1), last);
+        }
+    }
+}
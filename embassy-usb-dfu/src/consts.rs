@@ -32,42 +32,71 @@ bitflags::bitflags! {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+/// State of the DFU interface, as reported to the host.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
-#[allow(unused)]
-pub(crate) enum State {
+pub enum State {
+    /// Running the application, idle.
     AppIdle = 0,
+    /// Running the application, waiting for a USB reset after a DETACH request.
     AppDetach = 1,
+    /// In DFU mode, waiting for requests.
     DfuIdle = 2,
+    /// Download block received, waiting for GETSTATUS.
     DlSync = 3,
+    /// Programming a download block.
     DlBusy = 4,
+    /// Waiting for the next download block.
     Download = 5,
+    /// Download complete, waiting for GETSTATUS to start manifestation.
     ManifestSync = 6,
+    /// Manifestation in progress.
     Manifest = 7,
+    /// Manifestation complete, waiting for a USB reset.
     ManifestWaitReset = 8,
+    /// Upload in progress.
     UploadIdle = 9,
+    /// An error occurred, waiting for CLRSTATUS.
     Error = 10,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+/// Status of the last DFU operation, as reported to the host.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
-#[allow(unused)]
-pub(crate) enum Status {
+pub enum Status {
+    /// No error.
     Ok = 0x00,
+    /// File is not targeted for use by this device.
     ErrTarget = 0x01,
+    /// File fails a vendor-specific verification test.
     ErrFile = 0x02,
+    /// Unable to write memory.
     ErrWrite = 0x03,
+    /// Memory erase failed.
     ErrErase = 0x04,
+    /// Memory erase check failed.
     ErrCheckErased = 0x05,
+    /// Program memory function failed.
     ErrProg = 0x06,
+    /// Programmed memory failed verification.
     ErrVerify = 0x07,
+    /// Received address is out of range.
     ErrAddress = 0x08,
+    /// Received DNLOAD with zero length, but the firmware is incomplete.
     ErrNotDone = 0x09,
+    /// Device firmware is corrupt.
     ErrFirmware = 0x0A,
+    /// Vendor-specific error.
     ErrVendor = 0x0B,
+    /// Unexpected USB reset.
     ErrUsbr = 0x0C,
+    /// Unexpected power on reset.
     ErrPor = 0x0D,
+    /// Unknown error.
     ErrUnknown = 0x0E,
+    /// Unexpected request.
     ErrStalledPkt = 0x0F,
 }
 
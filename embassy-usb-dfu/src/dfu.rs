@@ -1,6 +1,9 @@
 use core::marker::PhantomData;
 
-use embassy_boot::{AlignedBuffer, BlockingFirmwareUpdater};
+use embassy_boot::{AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterError};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
 use embassy_usb::control::{InResponse, OutResponse, Recipient, RequestType};
 use embassy_usb::driver::Driver;
 use embassy_usb::{Builder, Handler};
@@ -12,6 +15,30 @@ use crate::consts::{
 };
 use crate::Reset;
 
+/// Progress of a DFU download, reported through [`Control::with_events`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DfuEvent {
+    /// The host started a download.
+    Started,
+    /// A block was written to the DFU partition.
+    Progress {
+        /// Number of bytes written so far.
+        bytes_written: usize,
+    },
+    /// The download is complete and the new firmware is marked for update.
+    ///
+    /// The device resets as soon as the host reads the status, so there may be no time left to handle this event.
+    Completed {
+        /// Size of the new firmware.
+        bytes_written: usize,
+    },
+    /// The host aborted the download.
+    Aborted,
+    /// The download failed.
+    Error(Status),
+}
+
 /// Internal state for USB DFU
 pub struct Control<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> {
     updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
@@ -19,6 +46,8 @@ pub struct Control<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_S
     state: State,
     status: Status,
     offset: usize,
+    poll_timeout: Duration,
+    events: Option<&'d Signal<CriticalSectionRawMutex, DfuEvent>>,
     _rst: PhantomData<RST>,
 }
 
@@ -31,15 +60,52 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Co
             state: State::DfuIdle,
             status: Status::Ok,
             offset: 0,
+            poll_timeout: Duration::from_millis(50),
+            events: None,
             _rst: PhantomData,
         }
     }
 
+    /// Set the time the host waits after a download block before requesting the status again.
+    ///
+    /// Blocks are written to flash before the status is reported, so this only needs to be raised if the host
+    /// times out while the flash is being erased. Defaults to 50ms.
+    pub fn with_poll_timeout(mut self, poll_timeout: Duration) -> Self {
+        self.poll_timeout = poll_timeout;
+        self
+    }
+
+    /// Report the progress of downloads to the given signal, e.g. to show it on a display.
+    pub fn with_events(mut self, events: &'d Signal<CriticalSectionRawMutex, DfuEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
     fn reset_state(&mut self) {
         self.offset = 0;
         self.state = State::DfuIdle;
         self.status = Status::Ok;
     }
+
+    fn signal(&self, event: DfuEvent) {
+        if let Some(events) = self.events {
+            events.signal(event);
+        }
+    }
+
+    fn fail(&mut self, e: FirmwareUpdaterError) {
+        self.state = State::Error;
+        self.status = match e {
+            FirmwareUpdaterError::Flash(e) => match e {
+                NorFlashErrorKind::NotAligned => Status::ErrWrite,
+                NorFlashErrorKind::OutOfBounds => Status::ErrAddress,
+                _ => Status::ErrUnknown,
+            },
+            FirmwareUpdaterError::Signature(_) | FirmwareUpdaterError::Verify(_) => Status::ErrVerify,
+            FirmwareUpdaterError::BadState => Status::ErrUnknown,
+        };
+        self.signal(DfuEvent::Error(self.status));
+    }
 }
 
 impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Handler
@@ -55,6 +121,9 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
         }
         match Request::try_from(req.request) {
             Ok(Request::Abort) => {
+                if self.state != State::DfuIdle {
+                    self.signal(DfuEvent::Aborted);
+                }
                 self.reset_state();
                 Some(OutResponse::Accepted)
             }
@@ -62,6 +131,14 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                 if req.value == 0 {
                     self.state = State::Download;
                     self.offset = 0;
+                    self.signal(DfuEvent::Started);
+                }
+
+                if data.len() > BLOCK_SIZE {
+                    self.state = State::Error;
+                    self.status = Status::ErrUnknown;
+                    self.signal(DfuEvent::Error(self.status));
+                    return Some(OutResponse::Rejected);
                 }
 
                 let mut buf = AlignedBuffer([0; BLOCK_SIZE]);
@@ -72,25 +149,18 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                         Ok(_) => {
                             self.status = Status::Ok;
                             self.state = State::ManifestSync;
+                            self.signal(DfuEvent::Completed {
+                                bytes_written: self.offset,
+                            });
                         }
-                        Err(e) => {
-                            self.state = State::Error;
-                            match e {
-                                embassy_boot::FirmwareUpdaterError::Flash(e) => match e {
-                                    NorFlashErrorKind::NotAligned => self.status = Status::ErrWrite,
-                                    NorFlashErrorKind::OutOfBounds => self.status = Status::ErrAddress,
-                                    _ => self.status = Status::ErrUnknown,
-                                },
-                                embassy_boot::FirmwareUpdaterError::Signature(_) => self.status = Status::ErrVerify,
-                                embassy_boot::FirmwareUpdaterError::BadState => self.status = Status::ErrUnknown,
-                            }
-                        }
+                        Err(e) => self.fail(e),
                     }
                 } else {
                     if self.state != State::Download {
                         // Unexpected DNLOAD while chip is waiting for a GETSTATUS
                         self.status = Status::ErrUnknown;
                         self.state = State::Error;
+                        self.signal(DfuEvent::Error(self.status));
                         return Some(OutResponse::Rejected);
                     }
                     match self.updater.write_firmware(self.offset, buf.as_ref()) {
@@ -98,19 +168,11 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                             self.status = Status::Ok;
                             self.state = State::DlSync;
                             self.offset += data.len();
+                            self.signal(DfuEvent::Progress {
+                                bytes_written: self.offset,
+                            });
                         }
-                        Err(e) => {
-                            self.state = State::Error;
-                            match e {
-                                embassy_boot::FirmwareUpdaterError::Flash(e) => match e {
-                                    NorFlashErrorKind::NotAligned => self.status = Status::ErrWrite,
-                                    NorFlashErrorKind::OutOfBounds => self.status = Status::ErrAddress,
-                                    _ => self.status = Status::ErrUnknown,
-                                },
-                                embassy_boot::FirmwareUpdaterError::Signature(_) => self.status = Status::ErrVerify,
-                                embassy_boot::FirmwareUpdaterError::BadState => self.status = Status::ErrUnknown,
-                            }
-                        }
+                        Err(e) => self.fail(e),
                     }
                 }
 
@@ -135,8 +197,16 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
         }
         match Request::try_from(req.request) {
             Ok(Request::GetStatus) => {
-                //TODO: Configurable poll timeout, ability to add string for Vendor error
-                buf[0..6].copy_from_slice(&[self.status as u8, 0x32, 0x00, 0x00, self.state as u8, 0x00]);
+                //TODO: ability to add string for Vendor error
+                let poll_timeout = (self.poll_timeout.as_millis() as u32).min(0xff_ffff).to_le_bytes();
+                buf[0..6].copy_from_slice(&[
+                    self.status as u8,
+                    poll_timeout[0],
+                    poll_timeout[1],
+                    poll_timeout[2],
+                    self.state as u8,
+                    0x00,
+                ]);
                 match self.state {
                     State::DlSync => self.state = State::Download,
                     State::ManifestSync => RST::sys_reset(),
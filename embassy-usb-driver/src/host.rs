@@ -0,0 +1,132 @@
+//! USB host driver traits.
+//!
+//! A host driver performs transfers to the devices attached to the bus. Enumeration is done by
+//! the `host` module of `embassy-usb`, and class handling by the layers above, the driver only
+//! moves packets and reports device attachment.
+
+use crate::{Direction, EndpointInfo, EndpointType};
+
+/// Speed of an attached device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// Low speed, 1.5 Mbit/s.
+    Low,
+    /// Full speed, 12 Mbit/s.
+    Full,
+}
+
+/// Device attachment event.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceEvent {
+    /// A device was attached, with the given speed.
+    Connected(Speed),
+    /// The device was detached.
+    Disconnected,
+}
+
+/// Errors returned by host transfers.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HostError {
+    /// The device answered with a STALL handshake.
+    Stall,
+    /// The device did not answer.
+    Timeout,
+    /// The device sent a packet with an unexpected data toggle.
+    DataSequence,
+    /// The packet was corrupted on the bus (CRC, bit stuffing or overflow error).
+    Protocol,
+    /// The device sent more data than fits in the buffer.
+    BufferOverflow,
+    /// No device is attached.
+    Disconnected,
+}
+
+/// An endpoint of an attached device, as seen by the host.
+///
+/// Holds the data toggle of the endpoint between transfers.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Channel {
+    /// Address of the device.
+    pub device_address: u8,
+    /// Endpoint information, from the endpoint descriptor of the device.
+    pub endpoint: EndpointInfo,
+    data_toggle: bool,
+}
+
+impl Channel {
+    /// Create a channel to an endpoint of the device at `device_address`.
+    pub fn new(device_address: u8, endpoint: EndpointInfo) -> Self {
+        Self {
+            device_address,
+            endpoint,
+            data_toggle: false,
+        }
+    }
+
+    /// Create a channel to the default control endpoint of the device at `device_address`.
+    ///
+    /// Use address 0 and a max packet size of 8 until the device is addressed and its device
+    /// descriptor is read.
+    pub fn control(device_address: u8, max_packet_size: u16) -> Self {
+        Self::new(
+            device_address,
+            EndpointInfo {
+                addr: crate::EndpointAddress::from_parts(0, Direction::Out),
+                ep_type: EndpointType::Control,
+                max_packet_size,
+                interval_ms: 0,
+            },
+        )
+    }
+
+    /// Get the data toggle (`true` for DATA1) of the next packet.
+    pub fn data_toggle(&self) -> bool {
+        self.data_toggle
+    }
+
+    /// Set the data toggle (`true` for DATA1) of the next packet.
+    ///
+    /// The toggle must be reset after a CLEAR_FEATURE(ENDPOINT_HALT) or SET_CONFIGURATION.
+    pub fn set_data_toggle(&mut self, data_toggle: bool) {
+        self.data_toggle = data_toggle;
+    }
+}
+
+/// Main USB host driver trait.
+///
+/// Implement this to add host support for a new hardware platform.
+pub trait HostDriver {
+    /// Wait for a device to be attached or detached.
+    ///
+    /// Returns immediately if the attachment changed since the last call.
+    async fn wait_for_device_event(&mut self) -> DeviceEvent;
+
+    /// Reset the bus, so the attached device answers on address 0.
+    async fn bus_reset(&mut self);
+
+    /// Perform a control transfer with an IN data stage, or without data stage if `buf` is
+    /// empty.
+    ///
+    /// Returns the number of bytes received.
+    async fn control_in(&mut self, channel: &mut Channel, setup: &[u8; 8], buf: &mut [u8]) -> Result<usize, HostError>;
+
+    /// Perform a control transfer with an OUT data stage, or without data stage if `buf` is
+    /// empty.
+    async fn control_out(&mut self, channel: &mut Channel, setup: &[u8; 8], buf: &[u8]) -> Result<(), HostError>;
+
+    /// Read from a bulk or interrupt IN endpoint, until `buf` is full or a short packet is
+    /// received.
+    ///
+    /// Returns the number of bytes received.
+    async fn data_in(&mut self, channel: &mut Channel, buf: &mut [u8]) -> Result<usize, HostError>;
+
+    /// Write to a bulk or interrupt OUT endpoint.
+    ///
+    /// A zero-length packet is sent if `buf` is a multiple of the max packet size, including if
+    /// it's empty, so the device can detect the end of the transfer.
+    async fn data_out(&mut self, channel: &mut Channel, buf: &[u8]) -> Result<(), HostError>;
+}
@@ -3,6 +3,8 @@
 #![doc = include_str!("../README.md")]
 #![warn(missing_docs)]
 
+pub mod host;
+
 /// Direction of USB traffic. Note that in the USB standard the direction is always indicated from
 /// the perspective of the host, which is backward for devices, but the standard directions are used
 /// for consistency.
@@ -302,6 +304,11 @@ pub trait EndpointOut: Endpoint {
 /// calls to `data_in` or `data_out` for the status zero-length packet. The status stage should
 /// be triggered by either `accept()`, or `data_in` with `last = true`.
 ///
+/// The data and status stages must be NAKed until the stack calls the corresponding method. The
+/// stack may take a while to do so, when a handler defers the response to a control request, so
+/// the driver must not answer them on its own or time out. The stack calls `setup` in the meantime,
+/// in case the host abandons the request, so it must not disturb the pending data or status stage.
+///
 /// Note that the host can abandon a control request and send a new SETUP packet any time. If
 /// a SETUP packet arrives at any time during `data_out`, `data_in`, `accept` or `reject`,
 /// the driver must immediately return (with `EndpointError::Disabled` from `data_in`, `data_out`)
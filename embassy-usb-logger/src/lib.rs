@@ -2,14 +2,16 @@
 #![doc = include_str!("../README.md")]
 #![warn(missing_docs)]
 
+use core::cell::{Cell, RefCell};
 use core::fmt::Write as _;
 
 use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::pipe::Pipe;
 use embassy_usb::class::cdc_acm::{CdcAcmClass, Receiver, Sender, State};
-use embassy_usb::driver::Driver;
+use embassy_usb::driver::{Driver, EndpointError};
 use embassy_usb::{Builder, Config};
-use log::{Metadata, Record};
+use log::{LevelFilter, Metadata, Record};
 
 type CS = embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 
@@ -38,15 +40,189 @@ impl<'d> LoggerState<'d> {
 /// The packet size used in the usb logger, to be used with `create_future_from_class`
 pub const MAX_PACKET_SIZE: u8 = 64;
 
+/// Size of the buffer for lines received from the host, not yet read with [`UsbLogger::read_line`].
+pub const RX_BUFFER_SIZE: usize = 256;
+
+/// Maximum length of a line received from the host. Longer lines are truncated.
+pub const MAX_LINE_LEN: usize = 128;
+
+/// Maximum number of per-module log levels, set with [`UsbLogger::set_module_level`].
+pub const MAX_MODULE_LEVELS: usize = 8;
+
+/// Maximum length of a module path given to [`UsbLogger::set_module_level`].
+pub const MAX_MODULE_LEN: usize = 32;
+
+/// Error returned by [`UsbLogger::set_module_level`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ModuleLevelError {
+    /// There are already [`MAX_MODULE_LEVELS`] modules with a level.
+    TooManyModules,
+    /// The module path is longer than [`MAX_MODULE_LEN`].
+    NameTooLong,
+}
+
+#[derive(Clone, Copy)]
+struct ModuleLevel {
+    name: [u8; MAX_MODULE_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl ModuleLevel {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+
+    /// Whether `target` is this module or one of its submodules.
+    fn matches(&self, target: &str) -> bool {
+        let target = target.as_bytes();
+        target.starts_with(self.name()) && (target.len() == self.len || target[self.len..].starts_with(b"::"))
+    }
+}
+
+struct Levels {
+    default: LevelFilter,
+    modules: [Option<ModuleLevel>; MAX_MODULE_LEVELS],
+}
+
+impl Levels {
+    fn level(&self, target: &str) -> LevelFilter {
+        // The most specific module wins.
+        self.modules
+            .iter()
+            .flatten()
+            .filter(|m| m.matches(target))
+            .max_by_key(|m| m.len)
+            .map_or(self.default, |m| m.level)
+    }
+
+    /// The `log` crate skips records above this level without calling the logger.
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .flatten()
+            .map(|m| m.level)
+            .fold(self.default, core::cmp::max)
+    }
+}
+
 /// The logger handle, which contains a pipe with configurable size for buffering log messages.
+///
+/// The logger is also a console: lines typed on the host are available with [`read_line`](Self::read_line),
+/// and the application can answer with [`write`](Self::write).
+///
+/// When the host doesn't read the serial port, the buffer fills up and further log messages are
+/// dropped. The number of dropped messages is logged once there is room again.
 pub struct UsbLogger<const N: usize> {
     buffer: Pipe<CS, N>,
+    rx: Pipe<CS, RX_BUFFER_SIZE>,
+    levels: Mutex<CS, RefCell<Levels>>,
+    dropped: Mutex<CS, Cell<u32>>,
 }
 
 impl<const N: usize> UsbLogger<N> {
     /// Create a new logger instance.
     pub const fn new() -> Self {
-        Self { buffer: Pipe::new() }
+        Self {
+            buffer: Pipe::new(),
+            rx: Pipe::new(),
+            levels: Mutex::new(RefCell::new(Levels {
+                default: LevelFilter::Trace,
+                modules: [None; MAX_MODULE_LEVELS],
+            })),
+            dropped: Mutex::new(Cell::new(0)),
+        }
+    }
+
+    /// Sets the log level of the modules without a level of their own.
+    pub fn set_level(&self, level: LevelFilter) {
+        self.levels.lock(|l| {
+            let mut l = l.borrow_mut();
+            l.default = level;
+            set_max_level(l.max_level());
+        });
+    }
+
+    /// Sets the log level of a module and its submodules, for example `"embassy_net::tcp"`.
+    ///
+    /// This overrides the level set with [`set_level`](Self::set_level), in both directions.
+    pub fn set_module_level(&self, module: &str, level: LevelFilter) -> Result<(), ModuleLevelError> {
+        if module.len() > MAX_MODULE_LEN {
+            return Err(ModuleLevelError::NameTooLong);
+        }
+
+        self.levels.lock(|l| {
+            let mut l = l.borrow_mut();
+            let slot = match l
+                .modules
+                .iter()
+                .position(|m| matches!(m, Some(m) if m.name() == module.as_bytes()))
+            {
+                Some(i) => i,
+                None => l
+                    .modules
+                    .iter()
+                    .position(|m| m.is_none())
+                    .ok_or(ModuleLevelError::TooManyModules)?,
+            };
+
+            let mut name = [0; MAX_MODULE_LEN];
+            name[..module.len()].copy_from_slice(module.as_bytes());
+            l.modules[slot] = Some(ModuleLevel {
+                name,
+                len: module.len(),
+                level,
+            });
+            set_max_level(l.max_level());
+            Ok(())
+        })
+    }
+
+    /// Removes the log levels of all modules, so they use the level set with [`set_level`](Self::set_level).
+    pub fn clear_module_levels(&self) {
+        self.levels.lock(|l| {
+            let mut l = l.borrow_mut();
+            l.modules = [None; MAX_MODULE_LEVELS];
+            set_max_level(l.max_level());
+        });
+    }
+
+    /// Reads a line typed on the host, without the line terminator.
+    ///
+    /// The line is truncated to the length of `buf`. Returns the length of the line.
+    ///
+    /// Lines are buffered until read. If the application doesn't keep up, further lines are dropped.
+    pub async fn read_line(&self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        loop {
+            let mut b = [0];
+            self.rx.read(&mut b).await;
+            if b[0] == b'\n' {
+                return len;
+            }
+            if len < buf.len() {
+                buf[len] = b[0];
+                len += 1;
+            }
+        }
+    }
+
+    /// Writes a log message whole, or not at all if the buffer is full, so that it doesn't get garbled.
+    fn write_whole(&self, args: core::fmt::Arguments) -> bool {
+        let mut counter = Counter(0);
+        let _ = counter.write_fmt(args);
+        if counter.0 > self.buffer.free_capacity() {
+            return false;
+        }
+        let _ = Writer(&self.buffer).write_fmt(args);
+        true
+    }
+
+    /// Writes data to the host, waiting for room in the buffer if needed.
+    ///
+    /// Unlike log messages, the data is never dropped. Line endings are not translated, use `\r\n`.
+    pub async fn write(&self, data: &[u8]) {
+        self.buffer.write_all(data).await;
     }
 
     /// Run the USB logger using the state and USB driver. Never returns.
@@ -97,24 +273,64 @@ impl<const N: usize> UsbLogger<N> {
     {
         let log_fut = async {
             let mut rx: [u8; MAX_PACKET_SIZE as usize] = [0; MAX_PACKET_SIZE as usize];
-            sender.wait_connection().await;
             loop {
-                let len = self.buffer.read(&mut rx[..]).await;
-                let _ = sender.write_packet(&rx[..len]).await;
-                if len as u8 == MAX_PACKET_SIZE {
-                    let _ = sender.write_packet(&[]).await;
-                }
+                sender.wait_connection().await;
+                let _ = self.send_output(sender, &mut rx).await;
             }
         };
-        let discard_fut = async {
-            let mut discard_buf: [u8; MAX_PACKET_SIZE as usize] = [0; MAX_PACKET_SIZE as usize];
-            receiver.wait_connection().await;
+        let console_fut = async {
+            let mut editor = LineEditor::new();
             loop {
-                let _ = receiver.read_packet(&mut discard_buf).await;
+                receiver.wait_connection().await;
+                let _ = self.receive_input(receiver, &mut editor).await;
             }
         };
 
-        join(log_fut, discard_fut).await;
+        join(log_fut, console_fut).await;
+    }
+
+    async fn send_output<'d, D>(&self, sender: &mut Sender<'d, D>, buf: &mut [u8]) -> Result<(), EndpointError>
+    where
+        D: Driver<'d>,
+    {
+        loop {
+            let len = self.buffer.read(buf).await;
+            sender.write_packet(&buf[..len]).await?;
+            if len as u8 == MAX_PACKET_SIZE {
+                sender.write_packet(&[]).await?;
+            }
+        }
+    }
+
+    async fn receive_input<'d, D>(
+        &self,
+        receiver: &mut Receiver<'d, D>,
+        editor: &mut LineEditor,
+    ) -> Result<(), EndpointError>
+    where
+        D: Driver<'d>,
+    {
+        let mut buf: [u8; MAX_PACKET_SIZE as usize] = [0; MAX_PACKET_SIZE as usize];
+        loop {
+            let n = receiver.read_packet(&mut buf).await?;
+            for &b in &buf[..n] {
+                match editor.push(b) {
+                    Edit::Echo(echo) => {
+                        let _ = self.buffer.try_write(echo);
+                    }
+                    Edit::Line => {
+                        let _ = self.buffer.try_write(b"\r\n");
+                        let line = editor.take();
+                        // Lines are delivered whole or not at all.
+                        if self.rx.free_capacity() > line.len() {
+                            try_write_all(&self.rx, line);
+                            try_write_all(&self.rx, b"\n");
+                        }
+                    }
+                    Edit::None => {}
+                }
+            }
+        }
     }
 
     /// Creates the futures needed for the logger from a given class
@@ -131,35 +347,132 @@ impl<const N: usize> UsbLogger<N> {
 }
 
 impl<const N: usize> log::Log for UsbLogger<N> {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = self.levels.lock(|l| l.borrow().level(metadata.target()));
+        metadata.level() <= level
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let _ = write!(Writer(&self.buffer), "{}\r\n", record.args());
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let dropped = self.dropped.lock(|d| d.get());
+        if dropped > 0 {
+            if !self.write_whole(format_args!("[{} log messages dropped]\r\n", dropped)) {
+                self.dropped.lock(|d| d.set(d.get() + 1));
+                return;
+            }
+            self.dropped.lock(|d| d.set(d.get() - dropped));
+        }
+
+        if !self.write_whole(format_args!("{}\r\n", record.args())) {
+            self.dropped.lock(|d| d.set(d.get() + 1));
         }
     }
 
     fn flush(&self) {}
 }
 
+/// Sets the maximum log level of the `log` crate.
+///
+/// Calls are serialized by the `levels` lock.
+fn set_max_level(level: LevelFilter) {
+    #[cfg(target_has_atomic = "ptr")]
+    log::set_max_level(level);
+    // safety: this is never called concurrently.
+    #[cfg(not(target_has_atomic = "ptr"))]
+    unsafe {
+        log::set_max_level_racy(level)
+    };
+}
+
+/// Writes all of `data`, which must fit in the pipe.
+fn try_write_all<const N: usize>(pipe: &Pipe<CS, N>, data: &[u8]) {
+    // The Pipe is implemented in such way that we cannot
+    // write across the wraparound discontinuity.
+    if let Ok(n) = pipe.try_write(data) {
+        if n < data.len() {
+            // We wrote some data but not all, attempt again
+            // as the reason might be a wraparound in the
+            // ring buffer, which resolves on second attempt.
+            let _ = pipe.try_write(&data[n..]);
+        }
+    }
+}
+
 struct Writer<'d, const N: usize>(&'d Pipe<CS, N>);
 
 impl<'d, const N: usize> core::fmt::Write for Writer<'d, N> {
     fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
-        // The Pipe is implemented in such way that we cannot
-        // write across the wraparound discontinuity.
-        let b = s.as_bytes();
-        if let Ok(n) = self.0.try_write(b) {
-            if n < b.len() {
-                // We wrote some data but not all, attempt again
-                // as the reason might be a wraparound in the
-                // ring buffer, which resolves on second attempt.
-                let _ = self.0.try_write(&b[n..]);
+        try_write_all(self.0, s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Counts the length of formatted text.
+struct Counter(usize);
+
+impl core::fmt::Write for Counter {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Result of a byte typed on the host.
+enum Edit<'a> {
+    None,
+    /// Bytes to echo back to the host.
+    Echo(&'a [u8]),
+    /// The line is complete.
+    Line,
+}
+
+/// Minimal line editing: backspace, and Ctrl-U to erase the line.
+struct LineEditor {
+    line: [u8; MAX_LINE_LEN],
+    len: usize,
+    /// The previous byte was a carriage return, a following line feed is part of the same line end.
+    cr: bool,
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        Self {
+            line: [0; MAX_LINE_LEN],
+            len: 0,
+            cr: false,
+        }
+    }
+
+    fn push(&mut self, b: u8) -> Edit<'_> {
+        let cr = core::mem::replace(&mut self.cr, b == b'\r');
+        match b {
+            b'\n' if cr => Edit::None,
+            b'\r' | b'\n' => Edit::Line,
+            // Backspace and delete.
+            0x08 | 0x7f if self.len > 0 => {
+                self.len -= 1;
+                Edit::Echo(b"\x08 \x08")
+            }
+            // Ctrl-U
+            0x15 => {
+                self.len = 0;
+                Edit::Echo(b"\r\x1b[K")
+            }
+            0x20..=0x7e if self.len < MAX_LINE_LEN => {
+                self.line[self.len] = b;
+                self.len += 1;
+                Edit::Echo(&self.line[self.len - 1..self.len])
             }
+            _ => Edit::None,
         }
-        Ok(())
+    }
+
+    fn take(&mut self) -> &[u8] {
+        let len = core::mem::take(&mut self.len);
+        &self.line[..len]
     }
 }
 
@@ -181,7 +494,7 @@ macro_rules! run {
     ( $x:expr, $l:expr, $p:ident ) => {
         static LOGGER: ::embassy_usb_logger::UsbLogger<$x> = ::embassy_usb_logger::UsbLogger::new();
         unsafe {
-            let _ = ::log::set_logger_racy(&LOGGER).map(|()| log::set_max_level_racy($l));
+            let _ = ::log::set_logger_racy(&LOGGER).map(|()| LOGGER.set_level($l));
         }
         let _ = LOGGER.run(&mut ::embassy_usb_logger::LoggerState::new(), $p).await;
     };
@@ -205,7 +518,7 @@ macro_rules! with_class {
     ( $x:expr, $l:expr, $p:ident ) => {{
         static LOGGER: ::embassy_usb_logger::UsbLogger<$x> = ::embassy_usb_logger::UsbLogger::new();
         unsafe {
-            let _ = ::log::set_logger_racy(&LOGGER).map(|()| log::set_max_level_racy($l));
+            let _ = ::log::set_logger_racy(&LOGGER).map(|()| LOGGER.set_level($l));
         }
         LOGGER.create_future_from_class($p)
     }};
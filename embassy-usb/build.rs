@@ -8,6 +8,7 @@ static CONFIGS: &[(&str, usize)] = &[
     // Generated by gen_config.py. DO NOT EDIT.
     ("MAX_INTERFACE_COUNT", 4),
     ("MAX_HANDLER_COUNT", 4),
+    ("MAX_CONFIGURATION_COUNT", 1),
     // END AUTOGENERATED CONFIG FEATURES
 ];
 
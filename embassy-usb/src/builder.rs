@@ -1,11 +1,14 @@
 use heapless::Vec;
 
-use crate::config::MAX_HANDLER_COUNT;
-use crate::descriptor::{BosWriter, DescriptorWriter};
-use crate::driver::{Driver, Endpoint, EndpointType};
+use crate::config::{MAX_CONFIGURATION_COUNT, MAX_HANDLER_COUNT};
+use crate::descriptor::{BosWriter, DescriptorWriter, SynchronizationType, UsageType};
+use crate::driver::{Driver, Endpoint, EndpointInfo, EndpointType};
 use crate::msos::{DeviceLevelDescriptor, FunctionLevelDescriptor, MsOsDescriptorWriter};
-use crate::types::{InterfaceNumber, StringIndex};
-use crate::{Handler, Interface, UsbDevice, MAX_INTERFACE_COUNT, STRING_INDEX_CUSTOM_START};
+use crate::types::{FunctionNumber, InterfaceNumber, StringIndex};
+use crate::{
+    Configuration, Function, Handler, Interface, UsbDevice, CONFIGURATION_VALUE, MAX_INTERFACE_COUNT,
+    STRING_INDEX_CUSTOM_START,
+};
 
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -123,7 +126,10 @@ impl<'a> Config<'a> {
 pub struct Builder<'d, D: Driver<'d>> {
     config: Config<'d>,
     handlers: Vec<&'d mut dyn Handler, MAX_HANDLER_COUNT>,
+    handler_configurations: Vec<usize, MAX_HANDLER_COUNT>,
     interfaces: Vec<Interface, MAX_INTERFACE_COUNT>,
+    configurations: Vec<Configuration, MAX_CONFIGURATION_COUNT>,
+    functions: Vec<Function, MAX_INTERFACE_COUNT>,
     control_buf: &'d mut [u8],
 
     driver: D,
@@ -133,6 +139,7 @@ pub struct Builder<'d, D: Driver<'d>> {
     bos_descriptor: BosWriter<'d>,
 
     msos_descriptor: MsOsDescriptorWriter<'d>,
+    msos_configuration: u8,
 }
 
 impl<'d, D: Driver<'d>> Builder<'d, D> {
@@ -169,14 +176,20 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
         let mut config_descriptor = DescriptorWriter::new(config_descriptor_buf);
         let mut bos_descriptor = BosWriter::new(DescriptorWriter::new(bos_descriptor_buf));
 
-        config_descriptor.configuration(&config);
+        config_descriptor.configuration(&config, CONFIGURATION_VALUE);
         bos_descriptor.bos();
 
+        let mut configurations = Vec::new();
+        let _ = configurations.push(Configuration::new(0, 0));
+
         Builder {
             driver,
             config,
             interfaces: Vec::new(),
+            configurations,
+            functions: Vec::new(),
             handlers: Vec::new(),
+            handler_configurations: Vec::new(),
             control_buf,
             next_string_index: STRING_INDEX_CUSTOM_START,
 
@@ -184,14 +197,16 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
             bos_descriptor,
 
             msos_descriptor: MsOsDescriptorWriter::new(msos_descriptor_buf),
+            msos_configuration: 0,
         }
     }
 
     /// Creates the [`UsbDevice`] instance with the configuration in this builder.
     pub fn build(mut self) -> UsbDevice<'d, D> {
+        self.end_configuration();
+
         let msos_descriptor = self.msos_descriptor.build(&mut self.bos_descriptor);
 
-        self.config_descriptor.end_configuration();
         self.bos_descriptor.end_bos();
 
         // Log the number of allocator bytes actually used in descriptor buffers
@@ -204,14 +219,54 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
             self.driver,
             self.config,
             self.handlers,
-            self.config_descriptor.into_buf(),
+            self.handler_configurations,
+            self.config_descriptor.into_full_buf(),
             self.bos_descriptor.writer.into_buf(),
             msos_descriptor,
             self.interfaces,
+            self.configurations,
+            self.functions,
             self.control_buf,
         )
     }
 
+    fn end_configuration(&mut self) {
+        let position = self.config_descriptor.position();
+        let num_interfaces = self.interfaces.len();
+        let cfg = self.current_configuration();
+        cfg.end = position;
+        cfg.num_interfaces = num_interfaces - cfg.first_interface;
+        let start = cfg.start;
+        self.config_descriptor.end_configuration(start);
+    }
+
+    fn current_configuration(&mut self) -> &mut Configuration {
+        unwrap!(self.configurations.last_mut())
+    }
+
+    /// Add a configuration.
+    ///
+    /// The device starts out with a single configuration. Calling this method ends the current one
+    /// and starts a new one: the functions added afterwards belong to it, and their interface numbers
+    /// start again from 0. The host selects one of the configurations when enumerating the device,
+    /// endpoints of the functions in the other ones stay disabled.
+    ///
+    /// Endpoints are allocated from the driver for each function, so they can't be shared between
+    /// configurations.
+    pub fn configuration(&mut self) {
+        self.end_configuration();
+
+        let value = CONFIGURATION_VALUE + self.configurations.len() as u8;
+        let cfg = Configuration::new(self.config_descriptor.position(), self.interfaces.len());
+        assert!(
+            self.configurations.push(cfg).is_ok(),
+            "embassy-usb: configuration list full. Increase the `max_configuration_count` compile-time setting. Current value: {}",
+            MAX_CONFIGURATION_COUNT
+        );
+
+        self.config_descriptor.configuration(&self.config, value);
+    }
+
     /// Returns the size of the control request data buffer. Can be used by
     /// classes to validate the buffer is large enough for their needs.
     pub fn control_buf_len(&self) -> usize {
@@ -225,7 +280,10 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
     ///
     /// If it's not set, no IAD descriptor is added.
     pub fn function(&mut self, class: u8, subclass: u8, protocol: u8) -> FunctionBuilder<'_, 'd, D> {
-        let first_interface = InterfaceNumber::new(self.interfaces.len() as u8);
+        let configuration = self.configurations.len() - 1;
+        let first_interface =
+            InterfaceNumber::new((self.interfaces.len() - self.current_configuration().first_interface) as u8);
+        let start = self.config_descriptor.position();
         let iface_count_index = if self.config.composite_with_iads {
             self.config_descriptor
                 .iad(first_interface, 0, class, subclass, protocol);
@@ -235,24 +293,67 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
             None
         };
 
+        let function = self.functions.len();
+        assert!(
+            self.functions
+                .push(Function {
+                    configuration,
+                    start,
+                    end: start,
+                    num_interfaces: 0,
+                    enabled: true,
+                })
+                .is_ok(),
+            "embassy-usb: function list full. Increase the `max_interface_count` compile-time setting. Current value: {}",
+            MAX_INTERFACE_COUNT
+        );
+
         FunctionBuilder {
             builder: self,
+            function,
             iface_count_index,
 
             first_interface,
         }
     }
 
+    /// Returns the number of the function added last, if any.
+    ///
+    /// This can be used to get the number of a function created by a class constructor, to later
+    /// enable or disable it with [`UsbDevice::set_function_enabled`].
+    pub fn last_function(&self) -> Option<FunctionNumber> {
+        self.functions
+            .len()
+            .checked_sub(1)
+            .map(|n| FunctionNumber::new(n as u8))
+    }
+
+    /// Sets whether a function is enabled when the device starts.
+    ///
+    /// All functions are enabled by default. See [`UsbDevice::set_function_enabled`] for details.
+    pub fn set_function_enabled(&mut self, function: FunctionNumber, enabled: bool) {
+        let Some(f) = self.functions.get_mut(function.0 as usize) else {
+            panic!("embassy-usb: invalid function number");
+        };
+        f.enabled = enabled;
+    }
+
     /// Add a Handler.
     ///
     /// The Handler is called on some USB bus events, and to handle all control requests not already
     /// handled by the USB stack.
+    ///
+    /// The Handler belongs to the current configuration: it is only notified of
+    /// [`configured`](Handler::configured) and [`set_alternate_setting`](Handler::set_alternate_setting),
+    /// and only receives the control requests addressed to an interface, while that configuration
+    /// is selected.
     pub fn handler(&mut self, handler: &'d mut dyn Handler) {
         assert!(
             self.handlers.push(handler).is_ok(),
             "embassy-usb: handler list full. Increase the `max_handler_count` compile-time setting. Current value: {}",
             MAX_HANDLER_COUNT
         );
+        unwrap!(self.handler_configurations.push(self.configurations.len() - 1).ok());
     }
 
     /// Allocates a new string index.
@@ -288,6 +389,7 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
 /// If not, functions will not be visible as descriptors.
 pub struct FunctionBuilder<'a, 'd, D: Driver<'d>> {
     builder: &'a mut Builder<'d, D>,
+    function: usize,
     iface_count_index: Option<usize>,
 
     first_interface: InterfaceNumber,
@@ -295,20 +397,28 @@ pub struct FunctionBuilder<'a, 'd, D: Driver<'d>> {
 
 impl<'a, 'd, D: Driver<'d>> Drop for FunctionBuilder<'a, 'd, D> {
     fn drop(&mut self) {
+        self.builder.functions[self.function].end = self.builder.config_descriptor.position();
         self.builder.msos_descriptor.end_function();
     }
 }
 
 impl<'a, 'd, D: Driver<'d>> FunctionBuilder<'a, 'd, D> {
+    /// Get the function number.
+    pub fn function_number(&self) -> FunctionNumber {
+        FunctionNumber::new(self.function as u8)
+    }
+
     /// Add an interface to the function.
     ///
-    /// Interface numbers are guaranteed to be allocated consecutively, starting from 0.
+    /// Interface numbers are guaranteed to be allocated consecutively, starting from 0 in each configuration.
     pub fn interface(&mut self) -> InterfaceBuilder<'_, 'd, D> {
         if let Some(i) = self.iface_count_index {
             self.builder.config_descriptor.buf[i] += 1;
         }
+        self.builder.functions[self.function].num_interfaces += 1;
 
-        let number = self.builder.interfaces.len() as _;
+        let index = self.builder.interfaces.len();
+        let number = (index - self.builder.current_configuration().first_interface) as _;
         let iface = Interface {
             current_alt_setting: 0,
             num_alt_settings: 0,
@@ -321,6 +431,7 @@ impl<'a, 'd, D: Driver<'d>> FunctionBuilder<'a, 'd, D> {
 
         InterfaceBuilder {
             builder: self.builder,
+            interface_index: index,
             interface_number: InterfaceNumber::new(number),
             next_alt_setting_number: 0,
         }
@@ -328,8 +439,11 @@ impl<'a, 'd, D: Driver<'d>> FunctionBuilder<'a, 'd, D> {
 
     /// Add an MS OS 2.0 Function Level Feature Descriptor.
     pub fn msos_feature<T: FunctionLevelDescriptor>(&mut self, desc: T) {
-        if !self.builder.msos_descriptor.is_in_config_subset() {
-            self.builder.msos_descriptor.configuration(0);
+        // The configuration subset is identified by the configuration index, not its value.
+        let config = (self.builder.configurations.len() - 1) as u8;
+        if !self.builder.msos_descriptor.is_in_config_subset() || self.builder.msos_configuration != config {
+            self.builder.msos_descriptor.configuration(config);
+            self.builder.msos_configuration = config;
         }
 
         if !self.builder.msos_descriptor.is_in_function_subset() {
@@ -343,6 +457,7 @@ impl<'a, 'd, D: Driver<'d>> FunctionBuilder<'a, 'd, D> {
 /// Interface builder.
 pub struct InterfaceBuilder<'a, 'd, D: Driver<'d>> {
     builder: &'a mut Builder<'d, D>,
+    interface_index: usize,
     interface_number: InterfaceNumber,
     next_alt_setting_number: u8,
 }
@@ -372,7 +487,7 @@ impl<'a, 'd, D: Driver<'d>> InterfaceBuilder<'a, 'd, D> {
     ) -> InterfaceAltBuilder<'_, 'd, D> {
         let number = self.next_alt_setting_number;
         self.next_alt_setting_number += 1;
-        self.builder.interfaces[self.interface_number.0 as usize].num_alt_settings += 1;
+        self.builder.interfaces[self.interface_index].num_alt_settings += 1;
 
         self.builder.config_descriptor.interface_alt(
             self.interface_number,
@@ -417,26 +532,75 @@ impl<'a, 'd, D: Driver<'d>> InterfaceAltBuilder<'a, 'd, D> {
         self.builder.config_descriptor.write(descriptor_type, descriptor);
     }
 
-    fn endpoint_in(&mut self, ep_type: EndpointType, max_packet_size: u16, interval_ms: u8) -> D::EndpointIn {
-        let ep = self
-            .builder
+    /// Allocate an IN endpoint, without writing its descriptor.
+    ///
+    /// The descriptor must be written with [`endpoint_descriptor`](Self::endpoint_descriptor). This
+    /// allows classes to write non-standard endpoint descriptors, or to refer to an endpoint before
+    /// its descriptor is written.
+    pub fn alloc_endpoint_in(&mut self, ep_type: EndpointType, max_packet_size: u16, interval_ms: u8) -> D::EndpointIn {
+        self.builder
             .driver
             .alloc_endpoint_in(ep_type, max_packet_size, interval_ms)
-            .expect("alloc_endpoint_in failed");
+            .expect("alloc_endpoint_in failed")
+    }
 
-        self.builder.config_descriptor.endpoint(ep.info());
+    /// Allocate an OUT endpoint, without writing its descriptor.
+    ///
+    /// The descriptor must be written with [`endpoint_descriptor`](Self::endpoint_descriptor). This
+    /// allows classes to write non-standard endpoint descriptors, or to refer to an endpoint before
+    /// its descriptor is written.
+    pub fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> D::EndpointOut {
+        self.builder
+            .driver
+            .alloc_endpoint_out(ep_type, max_packet_size, interval_ms)
+            .expect("alloc_endpoint_out failed")
+    }
+
+    /// Write the descriptor of an endpoint allocated with [`alloc_endpoint_in`](Self::alloc_endpoint_in)
+    /// or [`alloc_endpoint_out`](Self::alloc_endpoint_out).
+    ///
+    /// `extra_fields` are appended to the standard fields, for classes that use longer endpoint
+    /// descriptors such as USB Audio Class 1.0.
+    ///
+    /// Descriptors are written in the order builder functions are called. Note that some
+    /// classes care about the order.
+    pub fn endpoint_descriptor(
+        &mut self,
+        endpoint: &EndpointInfo,
+        synchronization_type: SynchronizationType,
+        usage_type: UsageType,
+        extra_fields: &[u8],
+    ) {
+        self.builder
+            .config_descriptor
+            .endpoint(endpoint, synchronization_type, usage_type, extra_fields);
+    }
+
+    fn endpoint_in(&mut self, ep_type: EndpointType, max_packet_size: u16, interval_ms: u8) -> D::EndpointIn {
+        let ep = self.alloc_endpoint_in(ep_type, max_packet_size, interval_ms);
+        self.endpoint_descriptor(
+            ep.info(),
+            SynchronizationType::NoSynchronization,
+            UsageType::DataEndpoint,
+            &[],
+        );
 
         ep
     }
 
     fn endpoint_out(&mut self, ep_type: EndpointType, max_packet_size: u16, interval_ms: u8) -> D::EndpointOut {
-        let ep = self
-            .builder
-            .driver
-            .alloc_endpoint_out(ep_type, max_packet_size, interval_ms)
-            .expect("alloc_endpoint_out failed");
-
-        self.builder.config_descriptor.endpoint(ep.info());
+        let ep = self.alloc_endpoint_out(ep_type, max_packet_size, interval_ms);
+        self.endpoint_descriptor(
+            ep.info(),
+            SynchronizationType::NoSynchronization,
+            UsageType::DataEndpoint,
+            &[],
+        );
 
         ep
     }
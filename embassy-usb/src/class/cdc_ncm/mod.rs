@@ -3,6 +3,9 @@
 //! # Compatibility
 //!
 //! Windows: NOT supported in Windows 10 (though there's apparently a driver you can install?). Supported out of the box in Windows 11.
+//! If an MS OS 2.0 descriptor set is added with [`Builder::msos_descriptor`] before creating the class,
+//! the `WINNCM` compatible ID is reported so that Windows 11 binds its driver without an INF file.
+//! Use the [`rndis`](crate::class::rndis) class instead for older Windows versions.
 //!
 //! Linux: Well-supported since forever.
 //!
@@ -21,7 +24,7 @@ use core::ptr::addr_of;
 use crate::control::{self, InResponse, OutResponse, Recipient, Request, RequestType};
 use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
 use crate::types::{InterfaceNumber, StringIndex};
-use crate::{Builder, Handler};
+use crate::{msos, Builder, Handler};
 
 pub mod embassy_net;
 
@@ -210,7 +213,7 @@ impl<'d> Handler for Control<'d> {
                         divisor: 4,
                         payload_remainder: 0,
                         out_alignment: 4,
-                        max_datagram_count: 1, // We only decode 1 packet per NTB
+                        max_datagram_count: 0, // no limit
                     },
                 };
                 Some(InResponse::Accepted(byteify(buf, res)))
@@ -264,7 +267,11 @@ impl<'d, D: Driver<'d>> CdcNcmClass<'d, D> {
     ) -> Self {
         state.shared.mac_addr = mac_address;
 
+        let msos = !builder.msos_writer().is_empty();
         let mut func = builder.function(USB_CLASS_CDC, CDC_SUBCLASS_NCM, CDC_PROTOCOL_NONE);
+        if msos {
+            func.msos_feature(msos::CompatibleIdFeatureDescriptor::new("WINNCM", ""));
+        }
 
         // Control interface
         let mut iface = func.interface();
@@ -360,6 +367,10 @@ impl<'d, D: Driver<'d>> CdcNcmClass<'d, D> {
                 data_if: self.data_if,
                 comm_ep: self.comm_ep,
                 read_ep: self.read_ep,
+                ntb: [0; NTB_MAX_SIZE],
+                ntb_len: 0,
+                ndp_index: 0,
+                datagram: 0,
             },
         )
     }
@@ -438,6 +449,14 @@ pub struct Receiver<'d, D: Driver<'d>> {
     data_if: InterfaceNumber,
     comm_ep: D::EndpointIn,
     read_ep: D::EndpointOut,
+
+    /// Last NTB received, the host may aggregate several datagrams in it.
+    ntb: [u8; NTB_MAX_SIZE],
+    ntb_len: usize,
+    /// Index of the NDP being processed, or 0 if the NTB is fully processed.
+    ndp_index: usize,
+    /// Index of the next datagram pointer in the NDP.
+    datagram: usize,
 }
 
 impl<'d, D: Driver<'d>> Receiver<'d, D> {
@@ -447,21 +466,23 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
     pub async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
         // Retry loop
         loop {
+            if let Some(n) = self.next_datagram(buf) {
+                return Ok(n);
+            }
+
             // read NTB
-            let mut ntb = [0u8; NTB_MAX_SIZE];
             let mut pos = 0;
             loop {
-                let n = self.read_ep.read(&mut ntb[pos..]).await?;
+                let n = self.read_ep.read(&mut self.ntb[pos..]).await?;
                 pos += n;
                 if n < self.read_ep.info().max_packet_size as usize || pos == NTB_MAX_SIZE {
                     break;
                 }
             }
-
-            let ntb = &ntb[..pos];
+            self.ntb_len = pos;
 
             // Process NTB header (NTH)
-            let Some(nth) = ntb.get(..12) else {
+            let Some(nth) = self.ntb[..pos].get(..12) else {
                 warn!("Received too short NTB");
                 continue;
             };
@@ -470,35 +491,61 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
                 warn!("Received bad NTH sig.");
                 continue;
             }
-            let ndp_idx = u16::from_le_bytes(nth[10..12].try_into().unwrap()) as usize;
+            self.ndp_index = u16::from_le_bytes(nth[10..12].try_into().unwrap()) as usize;
+            self.datagram = 0;
+        }
+    }
 
-            // Process NTB Datagram Pointer (NDP)
-            let Some(ndp) = ntb.get(ndp_idx..ndp_idx + 12) else {
-                warn!("NTH has an NDP pointer out of range.");
-                continue;
+    /// Copies the next datagram of the current NTB to `buf`, following the chain of NTB Datagram Pointers (NDP).
+    fn next_datagram(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let ntb = &self.ntb[..self.ntb_len];
+        while self.ndp_index != 0 {
+            let ndp_index = self.ndp_index;
+            let Some(ndp) = ntb.get(ndp_index..ndp_index + 8) else {
+                warn!("NTB has an NDP pointer out of range.");
+                self.ndp_index = 0;
+                break;
             };
             let sig = u32::from_le_bytes(ndp[0..4].try_into().unwrap());
             if sig != SIG_NDP_NO_FCS && sig != SIG_NDP_WITH_FCS {
                 warn!("Received bad NDP sig.");
-                continue;
+                self.ndp_index = 0;
+                break;
             }
-            let datagram_index = u16::from_le_bytes(ndp[8..10].try_into().unwrap()) as usize;
-            let datagram_len = u16::from_le_bytes(ndp[10..12].try_into().unwrap()) as usize;
+            let ndp_len = u16::from_le_bytes(ndp[4..6].try_into().unwrap()) as usize;
+            let next_ndp_index = u16::from_le_bytes(ndp[6..8].try_into().unwrap()) as usize;
+
+            // Datagram pointers follow the NDP header, until a null entry.
+            let entry = ndp_index + 8 + self.datagram * 4;
+            let pointer = match ntb.get(entry..entry + 4) {
+                Some(pointer) if entry + 4 <= ndp_index + ndp_len => pointer,
+                _ => &[0; 4],
+            };
+            let datagram_index = u16::from_le_bytes(pointer[0..2].try_into().unwrap()) as usize;
+            let datagram_len = u16::from_le_bytes(pointer[2..4].try_into().unwrap()) as usize;
 
             if datagram_index == 0 || datagram_len == 0 {
-                // empty, ignore. This is allowed by the spec, so don't warn.
+                // End of this NDP. Only follow forward links, so that a malformed NTB can't loop forever.
+                self.ndp_index = if next_ndp_index > ndp_index { next_ndp_index } else { 0 };
+                self.datagram = 0;
                 continue;
             }
+            self.datagram += 1;
 
             // Process actual datagram, finally.
             let Some(datagram) = ntb.get(datagram_index..datagram_index + datagram_len) else {
                 warn!("NDP has a datagram pointer out of range.");
                 continue;
             };
+            if datagram_len > buf.len() {
+                warn!("Received datagram too large for buffer.");
+                continue;
+            }
             buf[..datagram_len].copy_from_slice(datagram);
 
-            return Ok(datagram_len);
+            return Some(datagram_len);
         }
+        None
     }
 
     /// Waits for the USB host to enable this interface
@@ -0,0 +1,350 @@
+//! HID report descriptor builder.
+//!
+//! Builds report descriptors from typed items instead of hand-written bytes, e.g. for a mouse:
+//!
+//! ```
+//! use embassy_usb::class::hid::descriptor::*;
+//!
+//! let mut buf = [0; 64];
+//! let mut desc = ReportDescriptorBuilder::new(&mut buf);
+//! desc.usage_page(UsagePage::GenericDesktop)
+//!     .usage(usage::MOUSE)
+//!     .collection(Collection::Application)
+//!     .usage(usage::POINTER)
+//!     .collection(Collection::Physical)
+//!     // 3 buttons
+//!     .usage_page(UsagePage::Button)
+//!     .usage_range(1, 3)
+//!     .logical_range(0, 1)
+//!     .report_size(1)
+//!     .report_count(3)
+//!     .input(ItemFlags::DATA | ItemFlags::VARIABLE | ItemFlags::ABSOLUTE)
+//!     .report_size(5)
+//!     .report_count(1)
+//!     .input(ItemFlags::CONSTANT)
+//!     // X and Y movement
+//!     .usage_page(UsagePage::GenericDesktop)
+//!     .usage(usage::X)
+//!     .usage(usage::Y)
+//!     .logical_range(-127, 127)
+//!     .report_size(8)
+//!     .report_count(2)
+//!     .input(ItemFlags::DATA | ItemFlags::VARIABLE | ItemFlags::RELATIVE)
+//!     .end_collection()
+//!     .end_collection();
+//! let report_descriptor = desc.build();
+//! ```
+
+use core::ops::BitOr;
+
+const TYPE_MAIN: u8 = 0;
+const TYPE_GLOBAL: u8 = 1;
+const TYPE_LOCAL: u8 = 2;
+
+const TAG_INPUT: u8 = 0x8;
+const TAG_OUTPUT: u8 = 0x9;
+const TAG_COLLECTION: u8 = 0xa;
+const TAG_FEATURE: u8 = 0xb;
+const TAG_END_COLLECTION: u8 = 0xc;
+
+const TAG_USAGE_PAGE: u8 = 0x0;
+const TAG_LOGICAL_MINIMUM: u8 = 0x1;
+const TAG_LOGICAL_MAXIMUM: u8 = 0x2;
+const TAG_PHYSICAL_MINIMUM: u8 = 0x3;
+const TAG_PHYSICAL_MAXIMUM: u8 = 0x4;
+const TAG_UNIT_EXPONENT: u8 = 0x5;
+const TAG_UNIT: u8 = 0x6;
+const TAG_REPORT_SIZE: u8 = 0x7;
+const TAG_REPORT_ID: u8 = 0x8;
+const TAG_REPORT_COUNT: u8 = 0x9;
+
+const TAG_USAGE: u8 = 0x0;
+const TAG_USAGE_MINIMUM: u8 = 0x1;
+const TAG_USAGE_MAXIMUM: u8 = 0x2;
+
+/// Usage page, selecting the meaning of the following usages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsagePage {
+    /// Generic desktop controls: pointers, mice, keyboards, joysticks...
+    GenericDesktop,
+    /// Simulation controls.
+    Simulation,
+    /// Game controls.
+    Game,
+    /// Keyboard/keypad keys.
+    Keyboard,
+    /// LEDs.
+    Led,
+    /// Buttons, numbered from 1.
+    Button,
+    /// Ordinals, numbered from 1.
+    Ordinal,
+    /// Telephony devices.
+    Telephony,
+    /// Consumer controls: media keys, volume...
+    Consumer,
+    /// Digitizers: pens, touch screens...
+    Digitizer,
+    /// FIDO alliance authenticators.
+    Fido,
+    /// Vendor-defined page, from `0xff00` to `0xffff`.
+    Vendor(u16),
+}
+
+impl UsagePage {
+    const fn value(self) -> u16 {
+        match self {
+            UsagePage::GenericDesktop => 0x01,
+            UsagePage::Simulation => 0x02,
+            UsagePage::Game => 0x05,
+            UsagePage::Keyboard => 0x07,
+            UsagePage::Led => 0x08,
+            UsagePage::Button => 0x09,
+            UsagePage::Ordinal => 0x0a,
+            UsagePage::Telephony => 0x0b,
+            UsagePage::Consumer => 0x0c,
+            UsagePage::Digitizer => 0x0d,
+            UsagePage::Fido => 0xf1d0,
+            UsagePage::Vendor(page) => page,
+        }
+    }
+}
+
+/// Common usages of the generic desktop and consumer pages.
+pub mod usage {
+    /// Pointer (generic desktop).
+    pub const POINTER: u16 = 0x01;
+    /// Mouse (generic desktop).
+    pub const MOUSE: u16 = 0x02;
+    /// Joystick (generic desktop).
+    pub const JOYSTICK: u16 = 0x04;
+    /// Gamepad (generic desktop).
+    pub const GAMEPAD: u16 = 0x05;
+    /// Keyboard (generic desktop).
+    pub const KEYBOARD: u16 = 0x06;
+    /// Keypad (generic desktop).
+    pub const KEYPAD: u16 = 0x07;
+    /// X axis (generic desktop).
+    pub const X: u16 = 0x30;
+    /// Y axis (generic desktop).
+    pub const Y: u16 = 0x31;
+    /// Z axis (generic desktop).
+    pub const Z: u16 = 0x32;
+    /// Wheel (generic desktop).
+    pub const WHEEL: u16 = 0x38;
+    /// Consumer control (consumer).
+    pub const CONSUMER_CONTROL: u16 = 0x01;
+    /// Horizontal scroll (consumer).
+    pub const AC_PAN: u16 = 0x238;
+}
+
+/// Collection type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Collection {
+    /// Items related to a single geometric point, e.g. the axes of a pointer.
+    Physical = 0x00,
+    /// Items forming a device the host can use, e.g. a mouse or keyboard.
+    Application = 0x01,
+    /// Items forming a data structure.
+    Logical = 0x02,
+    /// Items of a report.
+    Report = 0x03,
+    /// Array of selector usages.
+    NamedArray = 0x04,
+    /// Items modifying the meaning of a usage.
+    UsageSwitch = 0x05,
+    /// Items modifying the meaning of a usage attached to it.
+    UsageModifier = 0x06,
+}
+
+/// Flags of input, output and feature items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ItemFlags(u16);
+
+impl ItemFlags {
+    /// The fields hold data.
+    pub const DATA: Self = Self(0);
+    /// The fields are constant, e.g. padding.
+    pub const CONSTANT: Self = Self(1 << 0);
+    /// The fields are an array of indexes of the active usages, e.g. keys pressed.
+    pub const ARRAY: Self = Self(0);
+    /// Each field holds the value of one usage.
+    pub const VARIABLE: Self = Self(1 << 1);
+    /// The values are absolute.
+    pub const ABSOLUTE: Self = Self(0);
+    /// The values are relative to the previous report.
+    pub const RELATIVE: Self = Self(1 << 2);
+    /// The values roll over from the logical maximum to the logical minimum.
+    pub const WRAP: Self = Self(1 << 3);
+    /// The values are not linear.
+    pub const NON_LINEAR: Self = Self(1 << 4);
+    /// The control has no preferred state to return to when not interacted with.
+    pub const NO_PREFERRED_STATE: Self = Self(1 << 5);
+    /// The control has a state where it sends no meaningful data.
+    pub const NULL_STATE: Self = Self(1 << 6);
+    /// The value can change without host interaction. Output and feature items only.
+    pub const VOLATILE: Self = Self(1 << 7);
+    /// The fields form a fixed size stream of bytes.
+    pub const BUFFERED_BYTES: Self = Self(1 << 8);
+}
+
+impl BitOr for ItemFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// HID report descriptor builder.
+pub struct ReportDescriptorBuilder<'a> {
+    buf: &'a mut [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> ReportDescriptorBuilder<'a> {
+    /// Creates a new builder writing to `buf`.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    /// Gets the current length of the descriptor.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Finishes the descriptor, returning it.
+    ///
+    /// Panics if a collection is still open.
+    pub fn build(self) -> &'a [u8] {
+        assert!(self.depth == 0, "HID report descriptor has unclosed collections");
+        &self.buf[..self.position]
+    }
+
+    fn item(&mut self, tag: u8, item_type: u8, data: &[u8]) -> &mut Self {
+        let size = match data.len() {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            _ => 3,
+        };
+        let len = 1 + data.len();
+        assert!(
+            self.position + len <= self.buf.len(),
+            "HID report descriptor buffer full"
+        );
+        self.buf[self.position] = tag << 4 | item_type << 2 | size;
+        self.buf[self.position + 1..self.position + len].copy_from_slice(data);
+        self.position += len;
+        self
+    }
+
+    fn item_unsigned(&mut self, tag: u8, item_type: u8, val: u32) -> &mut Self {
+        let bytes = val.to_le_bytes();
+        let len = match val {
+            0..=0xff => 1,
+            0x100..=0xffff => 2,
+            _ => 4,
+        };
+        self.item(tag, item_type, &bytes[..len])
+    }
+
+    fn item_signed(&mut self, tag: u8, item_type: u8, val: i32) -> &mut Self {
+        let bytes = val.to_le_bytes();
+        let len = match val {
+            -0x80..=0x7f => 1,
+            -0x8000..=0x7fff => 2,
+            _ => 4,
+        };
+        self.item(tag, item_type, &bytes[..len])
+    }
+
+    /// Adds an input item, describing fields of input reports.
+    pub fn input(&mut self, flags: ItemFlags) -> &mut Self {
+        self.item_unsigned(TAG_INPUT, TYPE_MAIN, flags.0 as u32)
+    }
+
+    /// Adds an output item, describing fields of output reports.
+    pub fn output(&mut self, flags: ItemFlags) -> &mut Self {
+        self.item_unsigned(TAG_OUTPUT, TYPE_MAIN, flags.0 as u32)
+    }
+
+    /// Adds a feature item, describing fields of feature reports.
+    pub fn feature(&mut self, flags: ItemFlags) -> &mut Self {
+        self.item_unsigned(TAG_FEATURE, TYPE_MAIN, flags.0 as u32)
+    }
+
+    /// Opens a collection, which must be closed with [`end_collection`](Self::end_collection).
+    pub fn collection(&mut self, collection: Collection) -> &mut Self {
+        self.depth += 1;
+        self.item(TAG_COLLECTION, TYPE_MAIN, &[collection as u8])
+    }
+
+    /// Closes the last collection opened.
+    pub fn end_collection(&mut self) -> &mut Self {
+        assert!(self.depth > 0, "HID report descriptor has no open collection");
+        self.depth -= 1;
+        self.item(TAG_END_COLLECTION, TYPE_MAIN, &[])
+    }
+
+    /// Sets the usage page of the following usages.
+    pub fn usage_page(&mut self, page: UsagePage) -> &mut Self {
+        self.item_unsigned(TAG_USAGE_PAGE, TYPE_GLOBAL, page.value() as u32)
+    }
+
+    /// Sets the range of the values of the following fields.
+    pub fn logical_range(&mut self, min: i32, max: i32) -> &mut Self {
+        self.item_signed(TAG_LOGICAL_MINIMUM, TYPE_GLOBAL, min)
+            .item_signed(TAG_LOGICAL_MAXIMUM, TYPE_GLOBAL, max)
+    }
+
+    /// Sets the physical range matching the logical range of the following fields.
+    pub fn physical_range(&mut self, min: i32, max: i32) -> &mut Self {
+        self.item_signed(TAG_PHYSICAL_MINIMUM, TYPE_GLOBAL, min)
+            .item_signed(TAG_PHYSICAL_MAXIMUM, TYPE_GLOBAL, max)
+    }
+
+    /// Sets the unit of the physical range, as encoded by the HID specification.
+    pub fn unit(&mut self, unit: u32, exponent: i8) -> &mut Self {
+        self.item_unsigned(TAG_UNIT, TYPE_GLOBAL, unit)
+            .item(TAG_UNIT_EXPONENT, TYPE_GLOBAL, &[exponent as u8 & 0x0f])
+    }
+
+    /// Sets the size of the following fields, in bits.
+    pub fn report_size(&mut self, bits: u32) -> &mut Self {
+        self.item_unsigned(TAG_REPORT_SIZE, TYPE_GLOBAL, bits)
+    }
+
+    /// Sets the number of following fields.
+    pub fn report_count(&mut self, count: u32) -> &mut Self {
+        self.item_unsigned(TAG_REPORT_COUNT, TYPE_GLOBAL, count)
+    }
+
+    /// Sets the ID of the reports holding the following fields.
+    ///
+    /// Once used, all reports must have an ID, which is sent as their first byte.
+    pub fn report_id(&mut self, id: u8) -> &mut Self {
+        assert!(id != 0, "HID report ID 0 is reserved");
+        self.item(TAG_REPORT_ID, TYPE_GLOBAL, &[id])
+    }
+
+    /// Adds a usage for the next field or collection.
+    pub fn usage(&mut self, usage: u16) -> &mut Self {
+        self.item_unsigned(TAG_USAGE, TYPE_LOCAL, usage as u32)
+    }
+
+    /// Adds a range of usages for the next fields.
+    pub fn usage_range(&mut self, min: u16, max: u16) -> &mut Self {
+        self.item_unsigned(TAG_USAGE_MINIMUM, TYPE_LOCAL, min as u32)
+            .item_unsigned(TAG_USAGE_MAXIMUM, TYPE_LOCAL, max as u32)
+    }
+}
@@ -1,9 +1,13 @@
 //! USB HID (Human Interface Device) class implementation.
 
+use core::cell::Cell;
 use core::mem::MaybeUninit;
 use core::ops::Range;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_sync::signal::Signal;
 #[cfg(feature = "usbd-hid")]
 use ssmarshal::serialize;
 #[cfg(feature = "usbd-hid")]
@@ -14,6 +18,8 @@ use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
 use crate::types::InterfaceNumber;
 use crate::{Builder, Handler};
 
+pub mod descriptor;
+
 const USB_CLASS_HID: u8 = 0x03;
 const USB_SUBCLASS_NONE: u8 = 0x00;
 const USB_PROTOCOL_NONE: u8 = 0x00;
@@ -408,6 +414,150 @@ pub trait RequestHandler {
     }
 }
 
+/// A report with an ID, for devices with several reports.
+///
+/// Each report is handled separately through a [`ReportRouter`], so that e.g. the keyboard and
+/// mouse of a composite device can be driven from different tasks. `N` is the maximum report
+/// length, excluding the report ID.
+pub struct Report<const N: usize> {
+    id: u8,
+    input: CriticalSectionMutex<Cell<([u8; N], usize)>>,
+    output: Signal<CriticalSectionRawMutex, ([u8; N], usize)>,
+    feature: CriticalSectionMutex<Cell<([u8; N], usize)>>,
+    feature_changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<const N: usize> Report<N> {
+    /// Creates a new report with the given ID, as declared in the report descriptor.
+    pub const fn new(id: u8) -> Self {
+        ::core::assert!(id != 0, "HID report ID 0 is reserved");
+        Self {
+            id,
+            input: CriticalSectionMutex::new(Cell::new(([0; N], 0))),
+            output: Signal::new(),
+            feature: CriticalSectionMutex::new(Cell::new(([0; N], 0))),
+            feature_changed: Signal::new(),
+        }
+    }
+
+    /// Gets the report ID.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Writes an input report to the interrupt endpoint, prefixed with the report ID.
+    ///
+    /// The report is also returned to the host when it reads it over the control pipe.
+    pub async fn write<'d, D: Driver<'d>, const W: usize>(
+        &self,
+        writer: &mut HidWriter<'d, D, W>,
+        report: &[u8],
+    ) -> Result<(), EndpointError> {
+        assert!(report.len() <= N && report.len() < W);
+
+        let mut stored = [0; N];
+        stored[..report.len()].copy_from_slice(report);
+        self.input.lock(|x| x.set((stored, report.len())));
+
+        let mut buf = [0; W];
+        buf[0] = self.id;
+        buf[1..][..report.len()].copy_from_slice(report);
+        writer.write(&buf[..1 + report.len()]).await
+    }
+
+    /// Waits for the host to send an output report, either on the interrupt or the control pipe,
+    /// and copies it to `buf` without the report ID.
+    ///
+    /// If several reports are received before this is called, only the last one is returned.
+    pub async fn read(&self, buf: &mut [u8]) -> usize {
+        let (data, len) = self.output.wait().await;
+        buf[..len].copy_from_slice(&data[..len]);
+        len
+    }
+
+    /// Sets the value of the feature report, read by the host over the control pipe.
+    pub fn set_feature(&self, report: &[u8]) {
+        let mut stored = [0; N];
+        stored[..report.len()].copy_from_slice(report);
+        self.feature.lock(|x| x.set((stored, report.len())));
+    }
+
+    /// Copies the current value of the feature report to `buf`, returning its length.
+    pub fn feature(&self, buf: &mut [u8]) -> usize {
+        let (data, len) = self.feature.lock(Cell::get);
+        buf[..len].copy_from_slice(&data[..len]);
+        len
+    }
+
+    /// Waits for the host to set the feature report.
+    pub async fn wait_feature(&self) {
+        self.feature_changed.wait().await
+    }
+
+    fn get(&self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        let cell = match id {
+            ReportId::In(_) => &self.input,
+            ReportId::Feature(_) => &self.feature,
+            ReportId::Out(_) => return None,
+        };
+        let (data, len) = cell.lock(Cell::get);
+        buf[0] = self.id;
+        buf[1..][..len].copy_from_slice(&data[..len]);
+        Some(1 + len)
+    }
+
+    fn set(&self, id: ReportId, data: &[u8]) -> OutResponse {
+        // Reports sent with an ID start with it.
+        let Some(data) = data.get(1..).filter(|data| data.len() <= N) else {
+            return OutResponse::Rejected;
+        };
+        let mut stored = [0; N];
+        stored[..data.len()].copy_from_slice(data);
+        match id {
+            ReportId::Out(_) => self.output.signal((stored, data.len())),
+            ReportId::Feature(_) => {
+                self.feature.lock(|x| x.set((stored, data.len())));
+                self.feature_changed.signal(());
+            }
+            ReportId::In(_) => return OutResponse::Rejected,
+        }
+        OutResponse::Accepted
+    }
+}
+
+/// Routes reports to separate [`Report`] handles by their ID.
+///
+/// Use it as the [`Config::request_handler`] to handle reports sent over the control pipe,
+/// and pass it to [`HidReader::run`] to handle output reports sent over the interrupt pipe.
+pub struct ReportRouter<'d, const N: usize> {
+    reports: &'d [&'d Report<N>],
+}
+
+impl<'d, const N: usize> ReportRouter<'d, N> {
+    /// Creates a new router for the given reports.
+    pub const fn new(reports: &'d [&'d Report<N>]) -> Self {
+        Self { reports }
+    }
+
+    fn report(&self, id: ReportId) -> Option<&'d Report<N>> {
+        let (ReportId::In(id) | ReportId::Out(id) | ReportId::Feature(id)) = id;
+        self.reports.iter().copied().find(|r| r.id == id)
+    }
+}
+
+impl<'d, const N: usize> RequestHandler for ReportRouter<'d, N> {
+    fn get_report(&self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        self.report(id)?.get(id, buf)
+    }
+
+    fn set_report(&self, id: ReportId, data: &[u8]) -> OutResponse {
+        match self.report(id) {
+            Some(report) => report.set(id, data),
+            None => OutResponse::Rejected,
+        }
+    }
+}
+
 struct Control<'d> {
     if_num: InterfaceNumber,
     report_descriptor: &'d [u8],
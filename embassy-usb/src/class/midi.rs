@@ -1,7 +1,10 @@
 //! MIDI class implementation.
 
+use core::mem::MaybeUninit;
+
 use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
-use crate::Builder;
+use crate::types::StringIndex;
+use crate::{Builder, Handler};
 
 /// This should be used as `device_class` when building the `UsbDevice`.
 pub const USB_AUDIO_CLASS: u8 = 0x01;
@@ -18,8 +21,167 @@ const HEADER_SUBTYPE: u8 = 0x01;
 const MS_HEADER_SUBTYPE: u8 = 0x01;
 const MS_GENERAL: u8 = 0x01;
 const PROTOCOL_NONE: u8 = 0x00;
-const MIDI_IN_SIZE: u8 = 0x06;
-const MIDI_OUT_SIZE: u8 = 0x09;
+const MIDI_IN_SIZE: usize = 0x06;
+const MIDI_OUT_SIZE: usize = 0x09;
+
+/// Largest max packet size supported by [`Receiver::read_event`].
+const MAX_PACKET_SIZE: usize = 512;
+/// Size of the event buffer of [`SysexWriter`].
+const SYSEX_BUFFER_SIZE: usize = 64;
+
+/// Maximum number of jacks in each direction, addressed by the 4-bit cable number.
+pub const MAX_JACKS: usize = 16;
+
+const SYSEX_END: u8 = 0xf7;
+
+const CIN_SYSEX_CONTINUE: u8 = 0x4;
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SYSEX_END_2: u8 = 0x6;
+const CIN_SYSEX_END_3: u8 = 0x7;
+
+/// A MIDI jack, i.e. a virtual cable between the host and the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jack<'d> {
+    /// Name of the jack, which some hosts show as the port name.
+    pub name: Option<&'d str>,
+    /// Whether the jack is connected to a physical MIDI connector of the device, e.g. a DIN socket,
+    /// rather than to a function embedded in the device, e.g. a synthesizer.
+    pub external: bool,
+}
+
+impl<'d> Jack<'d> {
+    /// Unnamed jack connected to a physical MIDI connector.
+    pub const EXTERNAL: Jack<'static> = Jack {
+        name: None,
+        external: true,
+    };
+}
+
+const EXTERNAL_JACKS: [Jack<'static>; MAX_JACKS] = [Jack::EXTERNAL; MAX_JACKS];
+
+/// Configuration for the MIDI class.
+pub struct Config<'d> {
+    /// MIDI inputs of the device, whose messages are sent to the host. The cable number of each
+    /// jack is its index.
+    pub inputs: &'d [Jack<'d>],
+
+    /// MIDI outputs of the device, whose messages are received from the host. The cable number of
+    /// each jack is its index.
+    pub outputs: &'d [Jack<'d>],
+
+    /// Max packet size for both the IN and OUT endpoints.
+    pub max_packet_size: u16,
+}
+
+/// Internal state for the MIDI class, needed to name jacks.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+        }
+    }
+}
+
+struct Control<'d> {
+    first_string: StringIndex,
+    inputs: &'d [Jack<'d>],
+    outputs: &'d [Jack<'d>],
+}
+
+impl<'d> Handler for Control<'d> {
+    fn get_string(&mut self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        // Strings are allocated for named jacks, inputs first.
+        let n = u8::from(index).checked_sub(self.first_string.into())?;
+        self.inputs
+            .iter()
+            .chain(self.outputs)
+            .filter_map(|jack| jack.name)
+            .nth(n as usize)
+    }
+}
+
+/// USB-MIDI event packet: a MIDI message, or part of a SysEx message, on a cable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EventPacket(pub [u8; 4]);
+
+impl EventPacket {
+    /// Creates an event packet for a MIDI message of 1 to 3 bytes, which must not be part of a SysEx message.
+    ///
+    /// Returns `None` if the message is empty or doesn't start with a status byte.
+    pub fn message(cable: u8, message: &[u8]) -> Option<Self> {
+        let status = *message.first()?;
+        let code_index = match status {
+            0x80..=0xef => status >> 4,
+            // System common messages, by length
+            0xf1 | 0xf3 => 0x2,
+            0xf2 => 0x3,
+            0xf6 => 0x5,
+            // Real-time messages
+            0xf8..=0xff => 0xf,
+            _ => return None,
+        };
+        let mut packet = [cable << 4 | code_index, 0, 0, 0];
+        let len = message.len().min(3);
+        packet[1..][..len].copy_from_slice(&message[..len]);
+        Some(Self(packet))
+    }
+
+    fn sysex(cable: u8, data: &[u8], end: bool) -> Self {
+        let code_index = match (end, data.len()) {
+            (false, _) => CIN_SYSEX_CONTINUE,
+            (true, 1) => CIN_SYSEX_END_1,
+            (true, 2) => CIN_SYSEX_END_2,
+            (true, _) => CIN_SYSEX_END_3,
+        };
+        let mut packet = [cable << 4 | code_index, 0, 0, 0];
+        packet[1..][..data.len()].copy_from_slice(data);
+        Self(packet)
+    }
+
+    /// Gets the cable number, i.e. the index of the jack.
+    pub fn cable(&self) -> u8 {
+        self.0[0] >> 4
+    }
+
+    /// Gets the code index number, classifying the message.
+    pub fn code_index(&self) -> u8 {
+        self.0[0] & 0x0f
+    }
+
+    /// Gets the MIDI bytes carried by the packet.
+    pub fn data(&self) -> &[u8] {
+        let len = match self.code_index() {
+            0x5 | 0xf => 1,
+            0x2 | 0x6 | 0xc | 0xd => 2,
+            0x0 | 0x1 => 0, // reserved
+            _ => 3,
+        };
+        &self.0[1..][..len]
+    }
+
+    /// If the packet is part of a SysEx message, gets its bytes, and whether they end the message.
+    pub fn sysex_data(&self) -> Option<(&[u8], bool)> {
+        match self.code_index() {
+            CIN_SYSEX_CONTINUE => Some((self.data(), false)),
+            // This code index is shared with single-byte system common messages.
+            CIN_SYSEX_END_1 if self.0[1] != SYSEX_END => None,
+            CIN_SYSEX_END_1 | CIN_SYSEX_END_2 | CIN_SYSEX_END_3 => Some((self.data(), true)),
+            _ => None,
+        }
+    }
+}
 
 /// Packet level implementation of a USB MIDI device.
 ///
@@ -41,7 +203,45 @@ pub struct MidiClass<'d, D: Driver<'d>> {
 impl<'d, D: Driver<'d>> MidiClass<'d, D> {
     /// Creates a new `MidiClass` with the provided UsbBus, number of input and output jacks and `max_packet_size` in bytes.
     /// For full-speed devices, `max_packet_size` has to be one of 8, 16, 32 or 64.
+    ///
+    /// All jacks are unnamed and external. Use [`MidiClass::with_config`] for other topologies.
     pub fn new(builder: &mut Builder<'d, D>, n_in_jacks: u8, n_out_jacks: u8, max_packet_size: u16) -> Self {
+        let config = Config {
+            inputs: &EXTERNAL_JACKS[..n_in_jacks as usize],
+            outputs: &EXTERNAL_JACKS[..n_out_jacks as usize],
+            max_packet_size,
+        };
+        Self::build(builder, None, config)
+    }
+
+    /// Creates a new `MidiClass` with the given jack topology.
+    pub fn with_config(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config<'d>) -> Self {
+        Self::build(builder, Some(state), config)
+    }
+
+    fn build(builder: &mut Builder<'d, D>, state: Option<&'d mut State<'d>>, config: Config<'d>) -> Self {
+        let Config {
+            inputs,
+            outputs,
+            max_packet_size,
+        } = config;
+        assert!(
+            inputs.len() <= MAX_JACKS && outputs.len() <= MAX_JACKS,
+            "too many MIDI jacks"
+        );
+
+        // Strings for the jack names, inputs first.
+        let mut first_string = None;
+        let mut strings = [0u8; 2 * MAX_JACKS];
+        for (i, jack) in inputs.iter().chain(outputs).enumerate() {
+            if jack.name.is_some() {
+                let index = builder.string();
+                first_string.get_or_insert(index);
+                strings[i] = index.into();
+            }
+        }
+        let (input_strings, output_strings) = strings.split_at(inputs.len());
+
         let mut func = builder.function(USB_AUDIO_CLASS, USB_AUDIOCONTROL_SUBCLASS, PROTOCOL_NONE);
 
         // Audio control interface
@@ -56,12 +256,22 @@ impl<'d, D: Driver<'d>> MidiClass<'d, D> {
         let _midi_if = iface.interface_number();
         let mut alt = iface.alt_setting(USB_AUDIO_CLASS, USB_MIDISTREAMING_SUBCLASS, PROTOCOL_NONE, None);
 
+        // An embedded OUT jack not connected to an external IN jack has no input pin.
+        let input_jacks_length = |jack: &Jack| match jack.external {
+            true => MIDI_IN_SIZE + MIDI_OUT_SIZE,
+            false => MIDI_OUT_SIZE - 2,
+        };
+        let output_jacks_length = |jack: &Jack| match jack.external {
+            true => MIDI_IN_SIZE + MIDI_OUT_SIZE,
+            false => MIDI_IN_SIZE,
+        };
         let midi_streaming_total_length = 7
-            + (n_in_jacks + n_out_jacks) as usize * (MIDI_IN_SIZE + MIDI_OUT_SIZE) as usize
+            + inputs.iter().map(input_jacks_length).sum::<usize>()
+            + outputs.iter().map(output_jacks_length).sum::<usize>()
             + 7
-            + (4 + n_out_jacks as usize)
+            + (4 + outputs.len())
             + 7
-            + (4 + n_in_jacks as usize);
+            + (4 + inputs.len());
 
         alt.descriptor(
             CS_INTERFACE,
@@ -74,70 +284,100 @@ impl<'d, D: Driver<'d>> MidiClass<'d, D> {
             ],
         );
 
-        // Calculates the index'th external midi in jack id
-        let in_jack_id_ext = |index| 2 * index + 1;
-        // Calculates the index'th embedded midi out jack id
-        let out_jack_id_emb = |index| 2 * index + 2;
-        // Calculates the index'th external midi out jack id
-        let out_jack_id_ext = |index| 2 * n_in_jacks + 2 * index + 1;
-        // Calculates the index'th embedded midi in jack id
-        let in_jack_id_emb = |index| 2 * n_in_jacks + 2 * index + 2;
-
-        for i in 0..n_in_jacks {
-            alt.descriptor(CS_INTERFACE, &[MIDI_IN_JACK_SUBTYPE, EXTERNAL, in_jack_id_ext(i), 0x00]);
-        }
-
-        for i in 0..n_out_jacks {
-            alt.descriptor(CS_INTERFACE, &[MIDI_IN_JACK_SUBTYPE, EMBEDDED, in_jack_id_emb(i), 0x00]);
-        }
+        // Jack IDs are allocated in order: for each input, its optional external IN jack and its
+        // embedded OUT jack; then for each output, its embedded IN jack and its optional external OUT jack.
+        let mut next_id = 1;
+        let mut alloc_id = || {
+            let id = next_id;
+            next_id += 1;
+            id
+        };
 
-        for i in 0..n_out_jacks {
-            alt.descriptor(
-                CS_INTERFACE,
-                &[
-                    MIDI_OUT_JACK_SUBTYPE,
-                    EXTERNAL,
-                    out_jack_id_ext(i),
-                    0x01,
-                    in_jack_id_emb(i),
-                    0x01,
-                    0x00,
-                ],
-            );
+        let mut in_endpoint_jacks = [0u8; MAX_JACKS];
+        for (i, jack) in inputs.iter().enumerate() {
+            let embedded_id = if jack.external {
+                let external_id = alloc_id();
+                alt.descriptor(CS_INTERFACE, &[MIDI_IN_JACK_SUBTYPE, EXTERNAL, external_id, 0x00]);
+                let embedded_id = alloc_id();
+                alt.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK_SUBTYPE,
+                        EMBEDDED,
+                        embedded_id,
+                        0x01, // bNrInputPins
+                        external_id,
+                        0x01,
+                        input_strings[i],
+                    ],
+                );
+                embedded_id
+            } else {
+                let embedded_id = alloc_id();
+                alt.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK_SUBTYPE,
+                        EMBEDDED,
+                        embedded_id,
+                        0x00, // bNrInputPins
+                        input_strings[i],
+                    ],
+                );
+                embedded_id
+            };
+            in_endpoint_jacks[i] = embedded_id;
         }
 
-        for i in 0..n_in_jacks {
+        let mut out_endpoint_jacks = [0u8; MAX_JACKS];
+        for (i, jack) in outputs.iter().enumerate() {
+            let embedded_id = alloc_id();
             alt.descriptor(
                 CS_INTERFACE,
-                &[
-                    MIDI_OUT_JACK_SUBTYPE,
-                    EMBEDDED,
-                    out_jack_id_emb(i),
-                    0x01,
-                    in_jack_id_ext(i),
-                    0x01,
-                    0x00,
-                ],
+                &[MIDI_IN_JACK_SUBTYPE, EMBEDDED, embedded_id, output_strings[i]],
             );
+            if jack.external {
+                let external_id = alloc_id();
+                alt.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK_SUBTYPE,
+                        EXTERNAL,
+                        external_id,
+                        0x01,
+                        embedded_id,
+                        0x01,
+                        0x00,
+                    ],
+                );
+            }
+            out_endpoint_jacks[i] = embedded_id;
         }
 
         let mut endpoint_data = [
             MS_GENERAL, 0, // Number of jacks
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // Jack mappings
         ];
-        endpoint_data[1] = n_out_jacks;
-        for i in 0..n_out_jacks {
-            endpoint_data[2 + i as usize] = in_jack_id_emb(i);
-        }
+        endpoint_data[1] = outputs.len() as u8;
+        endpoint_data[2..][..outputs.len()].copy_from_slice(&out_endpoint_jacks[..outputs.len()]);
         let read_ep = alt.endpoint_bulk_out(max_packet_size);
-        alt.descriptor(CS_ENDPOINT, &endpoint_data[0..2 + n_out_jacks as usize]);
+        alt.descriptor(CS_ENDPOINT, &endpoint_data[0..2 + outputs.len()]);
 
-        endpoint_data[1] = n_in_jacks;
-        for i in 0..n_in_jacks {
-            endpoint_data[2 + i as usize] = out_jack_id_emb(i);
-        }
+        endpoint_data[1] = inputs.len() as u8;
+        endpoint_data[2..][..inputs.len()].copy_from_slice(&in_endpoint_jacks[..inputs.len()]);
         let write_ep = alt.endpoint_bulk_in(max_packet_size);
-        alt.descriptor(CS_ENDPOINT, &endpoint_data[0..2 + n_in_jacks as usize]);
+        alt.descriptor(CS_ENDPOINT, &endpoint_data[0..2 + inputs.len()]);
+
+        drop(func);
+
+        if let (Some(state), Some(first_string)) = (state, first_string) {
+            let control = state.control.write(Control {
+                first_string,
+                inputs,
+                outputs,
+            });
+            builder.handler(control);
+        }
 
         MidiClass { read_ep, write_ep }
     }
@@ -171,7 +411,12 @@ impl<'d, D: Driver<'d>> MidiClass<'d, D> {
             Sender {
                 write_ep: self.write_ep,
             },
-            Receiver { read_ep: self.read_ep },
+            Receiver {
+                read_ep: self.read_ep,
+                buf: [0; MAX_PACKET_SIZE],
+                pos: 0,
+                len: 0,
+            },
         )
     }
 }
@@ -195,17 +440,117 @@ impl<'d, D: Driver<'d>> Sender<'d, D> {
         self.write_ep.write(data).await
     }
 
+    /// Writes a single event packet.
+    pub async fn write_event(&mut self, event: EventPacket) -> Result<(), EndpointError> {
+        self.write_ep.write(&event.0).await
+    }
+
+    /// Writes a complete SysEx message, including its start and end bytes.
+    pub async fn write_sysex(&mut self, cable: u8, message: &[u8]) -> Result<(), EndpointError> {
+        let mut writer = self.sysex(cable);
+        writer.write(message).await?;
+        writer.finish().await
+    }
+
+    /// Starts streaming a SysEx message in chunks, e.g. for messages too large to be buffered.
+    pub fn sysex(&mut self, cable: u8) -> SysexWriter<'_, 'd, D> {
+        assert!((cable as usize) < MAX_JACKS);
+        SysexWriter {
+            sender: self,
+            cable,
+            pending: [0; 3],
+            pending_len: 0,
+            buf: [0; SYSEX_BUFFER_SIZE],
+            buf_len: 0,
+        }
+    }
+
     /// Waits for the USB host to enable this interface
     pub async fn wait_connection(&mut self) {
         self.write_ep.wait_enabled().await;
     }
 }
 
+/// Writer streaming a SysEx message in chunks.
+///
+/// You can obtain a `SysexWriter` with [`Sender::sysex`]. The message is only complete once
+/// [`finish`](Self::finish) is called.
+pub struct SysexWriter<'a, 'd, D: Driver<'d>> {
+    sender: &'a mut Sender<'d, D>,
+    cable: u8,
+    /// Last bytes written, held back until it's known whether they end the message.
+    pending: [u8; 3],
+    pending_len: usize,
+    /// Event packets waiting to be sent.
+    buf: [u8; SYSEX_BUFFER_SIZE],
+    buf_len: usize,
+}
+
+impl<'a, 'd, D: Driver<'d>> SysexWriter<'a, 'd, D> {
+    async fn push(&mut self, event: EventPacket) -> Result<(), EndpointError> {
+        let max_len = (self.sender.max_packet_size() as usize).min(SYSEX_BUFFER_SIZE);
+        if self.buf_len + 4 > max_len {
+            self.flush().await?;
+        }
+        self.buf[self.buf_len..][..4].copy_from_slice(&event.0);
+        self.buf_len += 4;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), EndpointError> {
+        if self.buf_len > 0 {
+            let len = self.buf_len;
+            self.buf_len = 0;
+            self.sender.write_ep.write(&self.buf[..len]).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the next chunk of the message. The first chunk must start with the SysEx start byte.
+    ///
+    /// The chunk is sent to the host before returning, except for up to 3 bytes held back until
+    /// the next chunk or the end of the message.
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), EndpointError> {
+        for &byte in chunk {
+            if self.pending_len == 3 {
+                let event = EventPacket::sysex(self.cable, &self.pending, false);
+                self.push(event).await?;
+                self.pending_len = 0;
+            }
+            self.pending[self.pending_len] = byte;
+            self.pending_len += 1;
+        }
+        self.flush().await
+    }
+
+    /// Ends the message, adding the SysEx end byte if it wasn't written.
+    pub async fn finish(mut self) -> Result<(), EndpointError> {
+        if self.pending_len == 0 || self.pending[self.pending_len - 1] != SYSEX_END {
+            self.write(&[SYSEX_END]).await?;
+        }
+        let event = EventPacket::sysex(self.cable, &self.pending[..self.pending_len], true);
+        self.pending_len = 0;
+        self.push(event).await?;
+        let len = self.buf_len;
+        self.flush().await?;
+
+        // A full packet is not processed by the host until a shorter one follows.
+        if len == self.sender.max_packet_size() as usize {
+            self.sender.write_ep.write(&[]).await?;
+        }
+        Ok(())
+    }
+}
+
 /// Midi class packet receiver.
 ///
 /// You can obtain a `Receiver` with [`MidiClass::split`]
 pub struct Receiver<'d, D: Driver<'d>> {
     read_ep: D::EndpointOut,
+    /// Last packet read by `read_event`, with the position of its next event.
+    buf: [u8; MAX_PACKET_SIZE],
+    pos: usize,
+    len: usize,
 }
 
 impl<'d, D: Driver<'d>> Receiver<'d, D> {
@@ -216,10 +561,31 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
     }
 
     /// Reads a single packet.
+    ///
+    /// Events buffered by [`read_event`](Self::read_event) are not returned.
     pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
         self.read_ep.read(data).await
     }
 
+    /// Reads a single event packet.
+    ///
+    /// SysEx messages are received in chunks of up to 3 bytes, see [`EventPacket::sysex_data`].
+    pub async fn read_event(&mut self) -> Result<EventPacket, EndpointError> {
+        loop {
+            if self.pos + 4 <= self.len {
+                let event = EventPacket(self.buf[self.pos..][..4].try_into().unwrap());
+                self.pos += 4;
+                // Some hosts pad packets with empty events.
+                if event.0 != [0; 4] {
+                    return Ok(event);
+                }
+                continue;
+            }
+            self.len = self.read_ep.read(&mut self.buf).await?;
+            self.pos = 0;
+        }
+    }
+
     /// Waits for the USB host to enable this interface
     pub async fn wait_connection(&mut self) {
         self.read_ep.wait_enabled().await;
@@ -3,3 +3,8 @@ pub mod cdc_acm;
 pub mod cdc_ncm;
 pub mod hid;
 pub mod midi;
+pub mod msc;
+pub mod rndis;
+pub mod uac1;
+pub mod usbtmc;
+pub mod uvc;
@@ -0,0 +1,715 @@
+//! Mass Storage Class implementation, using the Bulk-Only Transport and the SCSI transparent command set.
+//!
+//! The storage is provided by a [`BlockDevice`], such as an SD card, an external flash chip or a RAM disk.
+//! A single logical unit is exposed.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::types::InterfaceNumber;
+use crate::{Builder, Handler};
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_MSC: u8 = 0x08;
+
+const MSC_SUBCLASS_SCSI: u8 = 0x06;
+const MSC_PROTOCOL_BOT: u8 = 0x50;
+
+const REQ_GET_MAX_LUN: u8 = 0xfe;
+const REQ_BULK_ONLY_RESET: u8 = 0xff;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_LEN: usize = 31;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_LEN: usize = 13;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_MODE_SENSE_6: u8 = 0x1a;
+const SCSI_START_STOP_UNIT: u8 = 0x1b;
+const SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const SCSI_READ_FORMAT_CAPACITIES: u8 = 0x23;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2a;
+const SCSI_VERIFY_10: u8 = 0x2f;
+const SCSI_SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const SCSI_MODE_SENSE_10: u8 = 0x5a;
+
+/// Minimum length of the buffer passed to [`MscClass::run`].
+pub const MIN_BUFFER_LEN: usize = 64;
+
+/// A block device that can be exposed over USB.
+///
+/// Reads and writes always cover whole blocks: the buffer length is a multiple of [`block_size`](Self::block_size).
+#[allow(async_fn_in_trait)]
+pub trait BlockDevice {
+    /// Error type returned by the device.
+    type Error;
+
+    /// Size of a block in bytes, usually 512.
+    ///
+    /// It must be a multiple of the endpoint max packet size.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks of the device.
+    fn num_blocks(&self) -> u32;
+
+    /// Whether the device is write-protected.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Read blocks, starting at `block`.
+    async fn read(&mut self, block: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write blocks, starting at `block`.
+    async fn write(&mut self, block: u32, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Make sure all written data has reached the storage medium.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Configuration for the mass storage class.
+pub struct Config<'d> {
+    /// Vendor identification reported to the host, up to 8 ASCII characters.
+    pub vendor: &'d str,
+
+    /// Product identification reported to the host, up to 16 ASCII characters.
+    pub product: &'d str,
+
+    /// Product revision reported to the host, up to 4 ASCII characters.
+    pub revision: &'d str,
+
+    /// Whether the medium is removable. Hosts only offer to eject removable media.
+    pub removable: bool,
+
+    /// Max packet size for both the IN and OUT endpoints.
+    pub max_packet_size: u16,
+}
+
+impl<'d> Default for Config<'d> {
+    fn default() -> Self {
+        Self {
+            vendor: "Embassy",
+            product: "Mass Storage",
+            revision: "1.0",
+            removable: true,
+            max_packet_size: 64,
+        }
+    }
+}
+
+/// Internal state for the mass storage class.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    shared: Shared,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: Shared::default(),
+        }
+    }
+}
+
+/// Shared data between Control, MscClass and MediaControl.
+struct Shared {
+    present: AtomicBool,
+    /// The medium changed since the host last checked, it must be told with a unit attention.
+    changed: AtomicBool,
+    ejected: AtomicBool,
+    removal_prevented: AtomicBool,
+    reset: AtomicBool,
+    waker: RefCell<WakerRegistration>,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            present: AtomicBool::new(true),
+            changed: AtomicBool::new(false),
+            ejected: AtomicBool::new(false),
+            removal_prevented: AtomicBool::new(false),
+            reset: AtomicBool::new(false),
+            waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+}
+
+struct Control<'d> {
+    iface: InterfaceNumber,
+    shared: &'d Shared,
+}
+
+impl<'d> Handler for Control<'d> {
+    fn reset(&mut self) {
+        self.shared.removal_prevented.store(false, Ordering::Relaxed);
+    }
+
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.iface.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_BULK_ONLY_RESET => {
+                debug!("msc: bulk-only reset");
+                self.shared.reset.store(true, Ordering::Relaxed);
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.iface.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_GET_MAX_LUN => {
+                // Only one logical unit, number 0.
+                buf[0] = 0;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+/// Handle to change the medium and watch for ejects by the host.
+///
+/// You can obtain a `MediaControl` with [`MscClass::media_control`].
+#[derive(Clone, Copy)]
+pub struct MediaControl<'d> {
+    shared: &'d Shared,
+}
+
+impl<'d> MediaControl<'d> {
+    /// Sets whether a medium is present, for example when an SD card is inserted or removed.
+    ///
+    /// The host is told the medium changed on its next command.
+    pub fn set_present(&self, present: bool) {
+        self.shared.present.store(present, Ordering::Relaxed);
+        self.shared.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether a medium is present.
+    ///
+    /// This becomes `false` when the host ejects the medium.
+    pub fn is_present(&self) -> bool {
+        self.shared.present.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the host asked to prevent removal of the medium, for example while mounting it.
+    pub fn is_removal_prevented(&self) -> bool {
+        self.shared.removal_prevented.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the host to eject the medium.
+    ///
+    /// After an eject, the medium is not present anymore until [`set_present`](Self::set_present) is called.
+    pub async fn wait_ejected(&self) {
+        poll_fn(|cx| {
+            if self.shared.ejected.load(Ordering::Relaxed) {
+                self.shared.ejected.store(false, Ordering::Relaxed);
+                Poll::Ready(())
+            } else {
+                self.shared.waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Status {
+    Passed = 0,
+    Failed = 1,
+    PhaseError = 2,
+}
+
+/// SCSI sense data: key, additional sense code and qualifier.
+#[derive(Clone, Copy)]
+struct Sense(u8, u8, u8);
+
+impl Sense {
+    const NO_SENSE: Sense = Sense(0x00, 0x00, 0x00);
+    const NOT_READY_MEDIUM_NOT_PRESENT: Sense = Sense(0x02, 0x3a, 0x00);
+    const MEDIUM_ERROR_WRITE: Sense = Sense(0x03, 0x0c, 0x00);
+    const MEDIUM_ERROR_READ: Sense = Sense(0x03, 0x11, 0x00);
+    const ILLEGAL_REQUEST_INVALID_COMMAND: Sense = Sense(0x05, 0x20, 0x00);
+    const ILLEGAL_REQUEST_LBA_OUT_OF_RANGE: Sense = Sense(0x05, 0x21, 0x00);
+    const ILLEGAL_REQUEST_INVALID_FIELD: Sense = Sense(0x05, 0x24, 0x00);
+    const ILLEGAL_REQUEST_REMOVAL_PREVENTED: Sense = Sense(0x05, 0x53, 0x02);
+    const UNIT_ATTENTION_MEDIUM_CHANGED: Sense = Sense(0x06, 0x28, 0x00);
+    const DATA_PROTECT_WRITE_PROTECTED: Sense = Sense(0x07, 0x27, 0x00);
+}
+
+/// Command block wrapper.
+struct Cbw {
+    tag: u32,
+    data_len: u32,
+    dir_in: bool,
+    cb: [u8; 16],
+}
+
+impl Cbw {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() != CBW_LEN || u32::from_le_bytes(buf[0..4].try_into().unwrap()) != CBW_SIGNATURE {
+            return None;
+        }
+        let cb_len = buf[14] as usize;
+        if !(1..=16).contains(&cb_len) {
+            return None;
+        }
+        let mut cb = [0; 16];
+        cb[..cb_len].copy_from_slice(&buf[15..15 + cb_len]);
+        Some(Self {
+            tag: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            data_len: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            dir_in: buf[12] & 0x80 != 0,
+            cb,
+        })
+    }
+}
+
+/// Mass storage class.
+///
+/// The Bulk-Only Transport requires stalling endpoints in some error cases, which the driver
+/// endpoints can't do. Instead, unexpected data from the host is discarded, and IN transfers are
+/// ended early with a short packet. Hosts handle this fine in practice.
+pub struct MscClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    shared: &'d Shared,
+    vendor: &'d str,
+    product: &'d str,
+    revision: &'d str,
+    removable: bool,
+    sense: Sense,
+}
+
+impl<'d, D: Driver<'d>> MscClass<'d, D> {
+    /// Creates a new MscClass with the provided UsbBus and configuration.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config<'d>) -> Self {
+        let mut func = builder.function(USB_CLASS_MSC, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BOT);
+        let mut iface = func.interface();
+        let iface_num = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_MSC, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BOT, None);
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(config.max_packet_size);
+        drop(func);
+
+        let control = state.control.write(Control {
+            iface: iface_num,
+            shared: &state.shared,
+        });
+        builder.handler(control);
+
+        MscClass {
+            read_ep,
+            write_ep,
+            shared: &state.shared,
+            vendor: config.vendor,
+            product: config.product,
+            revision: config.revision,
+            removable: config.removable,
+            sense: Sense::NO_SENSE,
+        }
+    }
+
+    /// Gets a handle to change the medium and watch for ejects by the host.
+    pub fn media_control(&self) -> MediaControl<'d> {
+        MediaControl { shared: self.shared }
+    }
+
+    /// Serves the host's commands using the given block device.
+    ///
+    /// `buf` is used to transfer blocks. Its length must be at least [`MIN_BUFFER_LEN`] and the
+    /// device block size. A larger buffer allows transferring several blocks at once, which is
+    /// faster with most storage media.
+    pub async fn run<B: BlockDevice>(&mut self, device: &mut B, buf: &mut [u8]) -> ! {
+        let max_packet_size = self.read_ep.info().max_packet_size as usize;
+        assert!(buf.len() >= MIN_BUFFER_LEN && buf.len() >= device.block_size() && buf.len() >= max_packet_size);
+        let rem = device.block_size() % max_packet_size;
+        assert!(rem == 0, "block size must be a multiple of the max packet size");
+
+        loop {
+            self.read_ep.wait_enabled().await;
+            debug!("msc: enabled");
+
+            loop {
+                match self.process_command(device, buf).await {
+                    Ok(()) => {}
+                    Err(EndpointError::Disabled) => break,
+                    Err(EndpointError::BufferOverflow) => warn!("msc: buffer overflow"),
+                }
+            }
+
+            debug!("msc: disabled");
+        }
+    }
+
+    async fn process_command<B: BlockDevice>(&mut self, device: &mut B, buf: &mut [u8]) -> Result<(), EndpointError> {
+        self.shared.reset.store(false, Ordering::Relaxed);
+
+        let n = self.read_ep.read(buf).await?;
+        let Some(cbw) = Cbw::parse(&buf[..n]) else {
+            warn!("msc: invalid command block wrapper");
+            return Ok(());
+        };
+
+        trace!("msc: command {:02x}", cbw.cb[0]);
+        let (residue, status) = self.handle_command(device, buf, &cbw).await?;
+
+        if self.shared.reset.load(Ordering::Relaxed) {
+            // The host gave up on this command.
+            return Ok(());
+        }
+
+        let mut csw = [0; CSW_LEN];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw[4..8].copy_from_slice(&cbw.tag.to_le_bytes());
+        csw[8..12].copy_from_slice(&residue.to_le_bytes());
+        csw[12] = status as u8;
+        self.write_ep.write(&csw).await
+    }
+
+    /// Handles a command and its data stage. Returns the residue and status.
+    async fn handle_command<B: BlockDevice>(
+        &mut self,
+        device: &mut B,
+        buf: &mut [u8],
+        cbw: &Cbw,
+    ) -> Result<(u32, Status), EndpointError> {
+        let cb = &cbw.cb;
+        match cb[0] {
+            SCSI_TEST_UNIT_READY => {
+                let status = self.check_ready();
+                self.no_data(buf, cbw, status).await
+            }
+            SCSI_REQUEST_SENSE => {
+                let Sense(key, asc, ascq) = self.sense;
+                self.sense = Sense::NO_SENSE;
+                buf[..18].fill(0);
+                buf[0] = 0x70; // current error, fixed format
+                buf[2] = key;
+                buf[7] = 10; // additional sense length
+                buf[12] = asc;
+                buf[13] = ascq;
+                self.data_in(cbw, &buf[..18]).await
+            }
+            SCSI_INQUIRY => {
+                if cb[1] & 0x01 != 0 {
+                    // Vital product data pages are not supported.
+                    self.sense = Sense::ILLEGAL_REQUEST_INVALID_FIELD;
+                    return self.no_data(buf, cbw, Status::Failed).await;
+                }
+                buf[0] = 0x00; // direct access block device
+                buf[1] = if self.removable { 0x80 } else { 0x00 };
+                buf[2] = 0x04; // SPC-2
+                buf[3] = 0x02; // response data format
+                buf[4] = 31; // additional length
+                buf[5..8].fill(0);
+                ascii_field(&mut buf[8..16], self.vendor);
+                ascii_field(&mut buf[16..32], self.product);
+                ascii_field(&mut buf[32..36], self.revision);
+                self.data_in(cbw, &buf[..36]).await
+            }
+            SCSI_MODE_SENSE_6 => {
+                buf[0] = 3; // mode data length
+                buf[1] = 0; // medium type
+                buf[2] = if device.is_read_only() { 0x80 } else { 0x00 };
+                buf[3] = 0; // block descriptor length
+                self.data_in(cbw, &buf[..4]).await
+            }
+            SCSI_MODE_SENSE_10 => {
+                buf[..8].fill(0);
+                buf[1] = 6; // mode data length
+                buf[3] = if device.is_read_only() { 0x80 } else { 0x00 };
+                self.data_in(cbw, &buf[..8]).await
+            }
+            SCSI_START_STOP_UNIT => {
+                let start = cb[4] & 0x01 != 0;
+                let load_eject = cb[4] & 0x02 != 0;
+                let mut status = Status::Passed;
+                if load_eject && !start {
+                    if self.shared.removal_prevented.load(Ordering::Relaxed) {
+                        self.sense = Sense::ILLEGAL_REQUEST_REMOVAL_PREVENTED;
+                        status = Status::Failed;
+                    } else {
+                        debug!("msc: medium ejected");
+                        self.shared.present.store(false, Ordering::Relaxed);
+                        self.shared.ejected.store(true, Ordering::Relaxed);
+                        self.shared.waker.borrow_mut().wake();
+                    }
+                }
+                self.no_data(buf, cbw, status).await
+            }
+            SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL => {
+                self.shared
+                    .removal_prevented
+                    .store(cb[4] & 0x01 != 0, Ordering::Relaxed);
+                self.no_data(buf, cbw, Status::Passed).await
+            }
+            SCSI_READ_FORMAT_CAPACITIES => {
+                let block_size = device.block_size() as u32;
+                buf[..12].fill(0);
+                buf[3] = 8; // capacity list length
+                if self.shared.present.load(Ordering::Relaxed) {
+                    buf[4..8].copy_from_slice(&device.num_blocks().to_be_bytes());
+                    buf[8] = 0x02; // formatted media
+                } else {
+                    buf[4..8].fill(0xff);
+                    buf[8] = 0x03; // no media present
+                }
+                buf[9..12].copy_from_slice(&block_size.to_be_bytes()[1..]);
+                self.data_in(cbw, &buf[..12]).await
+            }
+            SCSI_READ_CAPACITY_10 => {
+                if self.check_ready() != Status::Passed {
+                    return self.no_data(buf, cbw, Status::Failed).await;
+                }
+                let last_block = device.num_blocks().saturating_sub(1);
+                buf[0..4].copy_from_slice(&last_block.to_be_bytes());
+                buf[4..8].copy_from_slice(&(device.block_size() as u32).to_be_bytes());
+                self.data_in(cbw, &buf[..8]).await
+            }
+            SCSI_READ_10 | SCSI_WRITE_10 | SCSI_VERIFY_10 => {
+                if self.check_ready() != Status::Passed {
+                    return self.no_data(buf, cbw, Status::Failed).await;
+                }
+                let block = u32::from_be_bytes(cb[2..6].try_into().unwrap());
+                let count = u16::from_be_bytes(cb[7..9].try_into().unwrap()) as u32;
+                let in_range = match block.checked_add(count) {
+                    Some(end) => end <= device.num_blocks(),
+                    None => false,
+                };
+                if !in_range {
+                    self.sense = Sense::ILLEGAL_REQUEST_LBA_OUT_OF_RANGE;
+                    return self.no_data(buf, cbw, Status::Failed).await;
+                }
+                match cb[0] {
+                    SCSI_READ_10 => self.read_blocks(device, buf, cbw, block, count).await,
+                    SCSI_WRITE_10 => self.write_blocks(device, buf, cbw, block, count).await,
+                    // The data is not checked against the medium, only the range is.
+                    _ => self.no_data(buf, cbw, Status::Passed).await,
+                }
+            }
+            SCSI_SYNCHRONIZE_CACHE_10 => {
+                let mut status = self.check_ready();
+                if status == Status::Passed && device.flush().await.is_err() {
+                    self.sense = Sense::MEDIUM_ERROR_WRITE;
+                    status = Status::Failed;
+                }
+                self.no_data(buf, cbw, status).await
+            }
+            _ => {
+                debug!("msc: unsupported command {:02x}", cb[0]);
+                self.sense = Sense::ILLEGAL_REQUEST_INVALID_COMMAND;
+                self.no_data(buf, cbw, Status::Failed).await
+            }
+        }
+    }
+
+    /// Checks the medium can be accessed, setting the sense data if not.
+    fn check_ready(&mut self) -> Status {
+        if self.shared.changed.load(Ordering::Relaxed) {
+            self.shared.changed.store(false, Ordering::Relaxed);
+            self.sense = Sense::UNIT_ATTENTION_MEDIUM_CHANGED;
+            Status::Failed
+        } else if !self.shared.present.load(Ordering::Relaxed) {
+            self.sense = Sense::NOT_READY_MEDIUM_NOT_PRESENT;
+            Status::Failed
+        } else {
+            Status::Passed
+        }
+    }
+
+    /// Completes a command without a data stage, skipping any data the host expected.
+    async fn no_data(&mut self, buf: &mut [u8], cbw: &Cbw, status: Status) -> Result<(u32, Status), EndpointError> {
+        if cbw.data_len == 0 {
+            return Ok((0, status));
+        }
+        if cbw.dir_in {
+            self.write_ep.write(&[]).await?;
+        } else {
+            self.discard(buf, cbw.data_len as usize).await?;
+        }
+        Ok((cbw.data_len, status))
+    }
+
+    /// Sends the response of a command, truncated to the length the host expects.
+    async fn data_in(&mut self, cbw: &Cbw, data: &[u8]) -> Result<(u32, Status), EndpointError> {
+        if !cbw.dir_in || cbw.data_len == 0 {
+            return Ok((cbw.data_len, Status::PhaseError));
+        }
+        let len = data.len().min(cbw.data_len as usize);
+        self.write_packets(&data[..len]).await?;
+        self.end_data_in(len, cbw).await?;
+        Ok((cbw.data_len - len as u32, Status::Passed))
+    }
+
+    async fn read_blocks<B: BlockDevice>(
+        &mut self,
+        device: &mut B,
+        buf: &mut [u8],
+        cbw: &Cbw,
+        block: u32,
+        count: u32,
+    ) -> Result<(u32, Status), EndpointError> {
+        let block_size = device.block_size();
+        let len = count as usize * block_size;
+        if len > 0 && (!cbw.dir_in || (cbw.data_len as usize) < len) {
+            return self.no_data(buf, cbw, Status::PhaseError).await;
+        }
+
+        let chunk_blocks = buf.len() / block_size;
+        let mut sent = 0;
+        let mut status = Status::Passed;
+        while sent < len {
+            let n = (chunk_blocks * block_size).min(len - sent);
+            let chunk = &mut buf[..n];
+            if device.read(block + (sent / block_size) as u32, chunk).await.is_err() {
+                self.sense = Sense::MEDIUM_ERROR_READ;
+                status = Status::Failed;
+                break;
+            }
+            self.write_packets(chunk).await?;
+            sent += n;
+        }
+
+        if cbw.data_len == 0 {
+            return Ok((0, status));
+        }
+        self.end_data_in(sent, cbw).await?;
+        Ok((cbw.data_len - sent as u32, status))
+    }
+
+    async fn write_blocks<B: BlockDevice>(
+        &mut self,
+        device: &mut B,
+        buf: &mut [u8],
+        cbw: &Cbw,
+        block: u32,
+        count: u32,
+    ) -> Result<(u32, Status), EndpointError> {
+        let block_size = device.block_size();
+        let len = count as usize * block_size;
+        if len > 0 && (cbw.dir_in || (cbw.data_len as usize) < len) {
+            return self.no_data(buf, cbw, Status::PhaseError).await;
+        }
+        if device.is_read_only() {
+            self.sense = Sense::DATA_PROTECT_WRITE_PROTECTED;
+            return self.no_data(buf, cbw, Status::Failed).await;
+        }
+
+        let chunk_blocks = buf.len() / block_size;
+        let mut received = 0;
+        let mut status = Status::Passed;
+        while received < len {
+            let n = (chunk_blocks * block_size).min(len - received);
+            let chunk = &mut buf[..n];
+            let read = self.read_packets(chunk).await?;
+            let block = block + (received / block_size) as u32;
+            received += read;
+            if read < n {
+                // The host ended the transfer early.
+                status = Status::PhaseError;
+                break;
+            }
+            if device.write(block, chunk).await.is_err() {
+                self.sense = Sense::MEDIUM_ERROR_WRITE;
+                status = Status::Failed;
+                break;
+            }
+        }
+
+        // Skip the data the host still sends.
+        let remaining = cbw.data_len as usize - received;
+        if status != Status::PhaseError && remaining > 0 {
+            self.discard(buf, remaining).await?;
+        }
+        Ok((remaining as u32, status))
+    }
+
+    async fn write_packets(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        let max_packet_size = self.write_ep.info().max_packet_size as usize;
+        for packet in data.chunks(max_packet_size) {
+            self.write_ep.write(packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Ends an IN transfer shorter than the host expects with a zero-length packet, if needed.
+    async fn end_data_in(&mut self, sent: usize, cbw: &Cbw) -> Result<(), EndpointError> {
+        let max_packet_size = self.write_ep.info().max_packet_size as usize;
+        let rem = sent % max_packet_size;
+        if sent < cbw.data_len as usize && rem == 0 {
+            self.write_ep.write(&[]).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads packets until `buf` is full or a short packet is received. Returns the number of bytes read.
+    async fn read_packets(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let max_packet_size = self.read_ep.info().max_packet_size as usize;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let n = self.read_ep.read(&mut buf[pos..]).await?;
+            pos += n;
+            if n < max_packet_size {
+                break;
+            }
+        }
+        Ok(pos)
+    }
+
+    /// Reads and drops `len` bytes from the host.
+    async fn discard(&mut self, buf: &mut [u8], mut len: usize) -> Result<(), EndpointError> {
+        while len > 0 {
+            let n = buf.len().min(len);
+            let read = self.read_packets(&mut buf[..n]).await?;
+            if read < n {
+                break;
+            }
+            len -= read;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an ASCII string into a fixed-length field, padded with spaces.
+fn ascii_field(field: &mut [u8], s: &str) {
+    field.fill(b' ');
+    for (dst, src) in field.iter_mut().zip(s.bytes()) {
+        *dst = src;
+    }
+}
@@ -0,0 +1,676 @@
+//! RNDIS class implementation, aka Ethernet over USB for Windows.
+//!
+//! RNDIS is Microsoft's proprietary protocol for Ethernet over USB. Unlike [CDC-NCM](crate::class::cdc_ncm),
+//! it is supported by all Windows versions since Windows XP, and Linux supports it too.
+//!
+//! # Compatibility
+//!
+//! Windows: If an MS OS 2.0 descriptor set is added with [`Builder::msos_descriptor`] before creating the class,
+//! the `RNDIS` compatible ID is reported, so that Windows binds its built-in driver without an INF file.
+//! MS OS 2.0 descriptors need Windows 8.1 or newer.
+//!
+//! Linux: Supported by the `rndis_host` driver, although some distributions disable it.
+//!
+//! Android, macOS: not supported.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::types::InterfaceNumber;
+use crate::{msos, Builder, Handler};
+
+pub mod embassy_net;
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_CDC: u8 = 0x02;
+
+const USB_CLASS_CDC_DATA: u8 = 0x0a;
+const CDC_SUBCLASS_ACM: u8 = 0x02;
+const CDC_PROTOCOL_VENDOR: u8 = 0xff;
+
+const CS_INTERFACE: u8 = 0x24;
+const CDC_TYPE_HEADER: u8 = 0x00;
+const CDC_TYPE_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_TYPE_ACM: u8 = 0x02;
+const CDC_TYPE_UNION: u8 = 0x06;
+
+const REQ_SEND_ENCAPSULATED_COMMAND: u8 = 0x00;
+const REQ_GET_ENCAPSULATED_RESPONSE: u8 = 0x01;
+
+const MSG_PACKET: u32 = 0x0000_0001;
+const MSG_INITIALIZE: u32 = 0x0000_0002;
+const MSG_HALT: u32 = 0x0000_0003;
+const MSG_QUERY: u32 = 0x0000_0004;
+const MSG_SET: u32 = 0x0000_0005;
+const MSG_RESET: u32 = 0x0000_0006;
+const MSG_KEEPALIVE: u32 = 0x0000_0008;
+const MSG_COMPLETION: u32 = 0x8000_0000;
+
+const STATUS_SUCCESS: u32 = 0x0000_0000;
+const STATUS_NOT_SUPPORTED: u32 = 0xc000_00bb;
+const STATUS_INVALID_DATA: u32 = 0xc001_0015;
+
+const OID_GEN_SUPPORTED_LIST: u32 = 0x0001_0101;
+const OID_GEN_HARDWARE_STATUS: u32 = 0x0001_0102;
+const OID_GEN_MEDIA_SUPPORTED: u32 = 0x0001_0103;
+const OID_GEN_MEDIA_IN_USE: u32 = 0x0001_0104;
+const OID_GEN_MAXIMUM_FRAME_SIZE: u32 = 0x0001_0106;
+const OID_GEN_LINK_SPEED: u32 = 0x0001_0107;
+const OID_GEN_TRANSMIT_BLOCK_SIZE: u32 = 0x0001_010a;
+const OID_GEN_RECEIVE_BLOCK_SIZE: u32 = 0x0001_010b;
+const OID_GEN_VENDOR_ID: u32 = 0x0001_010c;
+const OID_GEN_VENDOR_DESCRIPTION: u32 = 0x0001_010d;
+const OID_GEN_CURRENT_PACKET_FILTER: u32 = 0x0001_010e;
+const OID_GEN_MAXIMUM_TOTAL_SIZE: u32 = 0x0001_0111;
+const OID_GEN_MEDIA_CONNECT_STATUS: u32 = 0x0001_0114;
+const OID_GEN_PHYSICAL_MEDIUM: u32 = 0x0001_0202;
+const OID_GEN_XMIT_OK: u32 = 0x0002_0101;
+const OID_GEN_RCV_OK: u32 = 0x0002_0102;
+const OID_GEN_XMIT_ERROR: u32 = 0x0002_0103;
+const OID_GEN_RCV_ERROR: u32 = 0x0002_0104;
+const OID_GEN_RCV_NO_BUFFER: u32 = 0x0002_0105;
+const OID_802_3_PERMANENT_ADDRESS: u32 = 0x0101_0101;
+const OID_802_3_CURRENT_ADDRESS: u32 = 0x0101_0102;
+const OID_802_3_MULTICAST_LIST: u32 = 0x0101_0103;
+const OID_802_3_MAXIMUM_LIST_SIZE: u32 = 0x0101_0104;
+const OID_802_3_MAC_OPTIONS: u32 = 0x0101_0105;
+const OID_802_3_RCV_ERROR_ALIGNMENT: u32 = 0x0102_0101;
+const OID_802_3_XMIT_ONE_COLLISION: u32 = 0x0102_0102;
+const OID_802_3_XMIT_MORE_COLLISIONS: u32 = 0x0102_0103;
+
+const SUPPORTED_OIDS: [u32; 25] = [
+    OID_GEN_SUPPORTED_LIST,
+    OID_GEN_HARDWARE_STATUS,
+    OID_GEN_MEDIA_SUPPORTED,
+    OID_GEN_MEDIA_IN_USE,
+    OID_GEN_MAXIMUM_FRAME_SIZE,
+    OID_GEN_LINK_SPEED,
+    OID_GEN_TRANSMIT_BLOCK_SIZE,
+    OID_GEN_RECEIVE_BLOCK_SIZE,
+    OID_GEN_VENDOR_ID,
+    OID_GEN_VENDOR_DESCRIPTION,
+    OID_GEN_CURRENT_PACKET_FILTER,
+    OID_GEN_MAXIMUM_TOTAL_SIZE,
+    OID_GEN_MEDIA_CONNECT_STATUS,
+    OID_GEN_PHYSICAL_MEDIUM,
+    OID_GEN_XMIT_OK,
+    OID_GEN_RCV_OK,
+    OID_GEN_XMIT_ERROR,
+    OID_GEN_RCV_ERROR,
+    OID_GEN_RCV_NO_BUFFER,
+    OID_802_3_PERMANENT_ADDRESS,
+    OID_802_3_CURRENT_ADDRESS,
+    OID_802_3_MULTICAST_LIST,
+    OID_802_3_MAXIMUM_LIST_SIZE,
+    OID_802_3_MAC_OPTIONS,
+    OID_802_3_RCV_ERROR_ALIGNMENT,
+];
+
+const VENDOR_DESCRIPTION: &[u8] = b"embassy-usb RNDIS\0";
+
+/// Largest Ethernet frame, without FCS.
+const MAX_FRAME_SIZE: usize = 1514;
+/// Length of the header of RNDIS packet messages.
+const PACKET_HEADER_LEN: usize = 44;
+const MAX_TRANSFER_SIZE: usize = PACKET_HEADER_LEN + MAX_FRAME_SIZE;
+/// Largest response to a control message, a query of `OID_GEN_SUPPORTED_LIST`.
+const MAX_RESPONSE_SIZE: usize = 24 + SUPPORTED_OIDS.len() * 4;
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    match buf.get(offset..offset + 4) {
+        Some(b) => u32::from_le_bytes(b.try_into().unwrap()),
+        None => 0,
+    }
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// Internal state for the RNDIS class.
+pub struct State<'a> {
+    control: MaybeUninit<Control<'a>>,
+    shared: ControlShared,
+}
+
+impl<'a> Default for State<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> State<'a> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: ControlShared::default(),
+        }
+    }
+}
+
+/// Shared data between Control and `RndisClass`
+struct ControlShared {
+    mac_addr: [u8; 6],
+    /// A response to a control message is waiting to be fetched by the host.
+    response_available: AtomicBool,
+    /// The host has set a packet filter, so it's ready to exchange packets.
+    connected: AtomicBool,
+    waker: RefCell<WakerRegistration>,
+}
+
+impl Default for ControlShared {
+    fn default() -> Self {
+        ControlShared {
+            mac_addr: [0; 6],
+            response_available: AtomicBool::new(false),
+            connected: AtomicBool::new(false),
+            waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+}
+
+impl ControlShared {
+    fn wake(&self) {
+        self.waker.borrow_mut().wake();
+    }
+
+    async fn wait_response_available(&self) {
+        poll_fn(|cx| {
+            if self.response_available.load(Ordering::Relaxed) {
+                self.response_available.store(false, Ordering::Relaxed);
+                Poll::Ready(())
+            } else {
+                self.waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    async fn wait_connected(&self) {
+        poll_fn(|cx| {
+            if self.connected.load(Ordering::Relaxed) {
+                Poll::Ready(())
+            } else {
+                self.waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+struct Control<'a> {
+    shared: &'a ControlShared,
+    comm_if: InterfaceNumber,
+    packet_filter: u32,
+    response: [u8; MAX_RESPONSE_SIZE],
+    response_len: usize,
+}
+
+impl<'a> Control<'a> {
+    /// Writes the value of `oid` to `buf`, returning its length.
+    fn query(&self, oid: u32, buf: &mut [u8]) -> Option<usize> {
+        let val = match oid {
+            OID_GEN_SUPPORTED_LIST => {
+                for (i, oid) in SUPPORTED_OIDS.iter().enumerate() {
+                    put_u32(buf, i * 4, *oid);
+                }
+                return Some(SUPPORTED_OIDS.len() * 4);
+            }
+            OID_GEN_VENDOR_DESCRIPTION => {
+                buf[..VENDOR_DESCRIPTION.len()].copy_from_slice(VENDOR_DESCRIPTION);
+                return Some(VENDOR_DESCRIPTION.len());
+            }
+            OID_802_3_PERMANENT_ADDRESS | OID_802_3_CURRENT_ADDRESS => {
+                buf[..6].copy_from_slice(&self.shared.mac_addr);
+                return Some(6);
+            }
+            OID_802_3_MULTICAST_LIST => return Some(0),
+            OID_GEN_MAXIMUM_FRAME_SIZE => (MAX_FRAME_SIZE - 14) as u32,
+            OID_GEN_LINK_SPEED => 120_000, // 12 Mbit/s, in units of 100 bit/s
+            OID_GEN_TRANSMIT_BLOCK_SIZE | OID_GEN_RECEIVE_BLOCK_SIZE | OID_GEN_MAXIMUM_TOTAL_SIZE => {
+                MAX_FRAME_SIZE as u32
+            }
+            OID_GEN_VENDOR_ID => 0x00ff_ffff,
+            OID_GEN_CURRENT_PACKET_FILTER => self.packet_filter,
+            OID_802_3_MAXIMUM_LIST_SIZE => 1,
+            OID_GEN_HARDWARE_STATUS // ready
+            | OID_GEN_MEDIA_SUPPORTED // 802.3
+            | OID_GEN_MEDIA_IN_USE // 802.3
+            | OID_GEN_MEDIA_CONNECT_STATUS // connected
+            | OID_GEN_PHYSICAL_MEDIUM // unspecified
+            | OID_802_3_MAC_OPTIONS
+            // Statistics are not collected.
+            | OID_GEN_XMIT_OK
+            | OID_GEN_RCV_OK
+            | OID_GEN_XMIT_ERROR
+            | OID_GEN_RCV_ERROR
+            | OID_GEN_RCV_NO_BUFFER
+            | OID_802_3_RCV_ERROR_ALIGNMENT
+            | OID_802_3_XMIT_ONE_COLLISION
+            | OID_802_3_XMIT_MORE_COLLISIONS => 0,
+            _ => return None,
+        };
+        put_u32(buf, 0, val);
+        Some(4)
+    }
+
+    /// Applies the value of `oid`, returning the RNDIS status.
+    fn set(&mut self, oid: u32, value: &[u8]) -> u32 {
+        match oid {
+            OID_GEN_CURRENT_PACKET_FILTER if value.len() >= 4 => {
+                self.packet_filter = get_u32(value, 0);
+                let connected = self.packet_filter != 0;
+                debug!("rndis: packet filter {:08x}", self.packet_filter);
+                self.shared.connected.store(connected, Ordering::Relaxed);
+                self.shared.wake();
+                STATUS_SUCCESS
+            }
+            OID_GEN_CURRENT_PACKET_FILTER => STATUS_INVALID_DATA,
+            // Multicast packets are passed through unfiltered.
+            OID_802_3_MULTICAST_LIST => STATUS_SUCCESS,
+            _ => STATUS_NOT_SUPPORTED,
+        }
+    }
+
+    /// Handles a control message, preparing the response to it if any.
+    fn handle_message(&mut self, msg: &[u8]) {
+        let msg_type = get_u32(msg, 0);
+        let request_id = get_u32(msg, 8);
+        let res = &mut self.response;
+        res.fill(0);
+        put_u32(res, 0, msg_type | MSG_COMPLETION);
+        put_u32(res, 8, request_id);
+
+        let len = match msg_type {
+            MSG_INITIALIZE => {
+                debug!("rndis: initialize");
+                put_u32(res, 12, STATUS_SUCCESS);
+                put_u32(res, 16, 1); // MajorVersion
+                put_u32(res, 20, 0); // MinorVersion
+                put_u32(res, 24, 0x01); // DeviceFlags: connectionless
+                put_u32(res, 28, 0); // Medium: 802.3
+                put_u32(res, 32, 1); // MaxPacketsPerTransfer
+                put_u32(res, 36, MAX_TRANSFER_SIZE as u32); // MaxTransferSize
+                put_u32(res, 40, 0); // PacketAlignmentFactor
+                52
+            }
+            MSG_HALT => {
+                debug!("rndis: halt");
+                self.packet_filter = 0;
+                self.shared.connected.store(false, Ordering::Relaxed);
+                self.shared.wake();
+                // Halt has no response.
+                0
+            }
+            MSG_QUERY => {
+                let oid = get_u32(msg, 12);
+                let mut value = [0; MAX_RESPONSE_SIZE - 24];
+                match self.query(oid, &mut value) {
+                    Some(n) => {
+                        let res = &mut self.response;
+                        put_u32(res, 12, STATUS_SUCCESS);
+                        put_u32(res, 16, n as u32); // InformationBufferLength
+                        put_u32(res, 20, 16); // InformationBufferOffset
+                        res[24..][..n].copy_from_slice(&value[..n]);
+                        24 + n
+                    }
+                    None => {
+                        debug!("rndis: unsupported query {:08x}", oid);
+                        put_u32(&mut self.response, 12, STATUS_NOT_SUPPORTED);
+                        24
+                    }
+                }
+            }
+            MSG_SET => {
+                let oid = get_u32(msg, 12);
+                let value_len = get_u32(msg, 16) as usize;
+                let value_offset = 8 + get_u32(msg, 20) as usize;
+                let status = match msg.get(value_offset..value_offset + value_len) {
+                    Some(value) => self.set(oid, value),
+                    None => STATUS_INVALID_DATA,
+                };
+                if status == STATUS_NOT_SUPPORTED {
+                    debug!("rndis: unsupported set {:08x}", oid);
+                }
+                put_u32(&mut self.response, 12, status);
+                16
+            }
+            MSG_RESET => {
+                debug!("rndis: reset");
+                self.packet_filter = 0;
+                self.shared.connected.store(false, Ordering::Relaxed);
+                self.shared.wake();
+                put_u32(res, 8, STATUS_SUCCESS);
+                put_u32(res, 12, 1); // AddressingReset
+                16
+            }
+            MSG_KEEPALIVE => {
+                put_u32(res, 12, STATUS_SUCCESS);
+                16
+            }
+            _ => {
+                warn!("rndis: unknown message type {:08x}", msg_type);
+                0
+            }
+        };
+
+        put_u32(&mut self.response, 4, len as u32);
+        self.response_len = len;
+        if len != 0 {
+            self.shared.response_available.store(true, Ordering::Relaxed);
+            self.shared.wake();
+        }
+    }
+}
+
+impl<'d> Handler for Control<'d> {
+    fn reset(&mut self) {
+        self.packet_filter = 0;
+        self.response_len = 0;
+        self.shared.response_available.store(false, Ordering::Relaxed);
+        self.shared.connected.store(false, Ordering::Relaxed);
+        self.shared.wake();
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.comm_if.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_SEND_ENCAPSULATED_COMMAND => {
+                self.handle_message(data);
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.comm_if.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_GET_ENCAPSULATED_RESPONSE => {
+                let len = self.response_len;
+                self.response_len = 0;
+                if len == 0 {
+                    // No response available, the spec requires a single zero byte.
+                    buf[0] = 0;
+                    return Some(InResponse::Accepted(&buf[..1]));
+                }
+                buf[..len].copy_from_slice(&self.response[..len]);
+                Some(InResponse::Accepted(&buf[..len]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+/// RNDIS class
+pub struct RndisClass<'d, D: Driver<'d>> {
+    comm_ep: D::EndpointIn,
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+
+    shared: &'d ControlShared,
+
+    max_packet_size: usize,
+}
+
+impl<'d, D: Driver<'d>> RndisClass<'d, D> {
+    /// Create a new RNDIS class.
+    ///
+    /// `mac_address` is the MAC address of the host's network interface, not the device's.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State<'d>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+    ) -> Self {
+        assert!(builder.control_buf_len() >= MAX_RESPONSE_SIZE);
+
+        state.shared.mac_addr = mac_address;
+
+        let msos = !builder.msos_writer().is_empty();
+        let mut func = builder.function(USB_CLASS_CDC, CDC_SUBCLASS_ACM, CDC_PROTOCOL_VENDOR);
+        if msos {
+            func.msos_feature(msos::CompatibleIdFeatureDescriptor::new("RNDIS", "5162001"));
+        }
+
+        // Control interface
+        let mut iface = func.interface();
+        let comm_if = iface.interface_number();
+        let data_if = u8::from(comm_if) + 1;
+        let mut alt = iface.alt_setting(USB_CLASS_CDC, CDC_SUBCLASS_ACM, CDC_PROTOCOL_VENDOR, None);
+
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_HEADER, // bDescriptorSubtype
+                0x10,
+                0x01, // bcdCDC (1.10)
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_CALL_MANAGEMENT, // bDescriptorSubtype
+                0x00,                     // bmCapabilities
+                data_if,                  // bDataInterface
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_ACM, // bDescriptorSubtype
+                0x00,         // bmCapabilities
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_UNION, // bDescriptorSubtype
+                comm_if.into(), // bControlInterface
+                data_if,        // bSubordinateInterface
+            ],
+        );
+
+        let comm_ep = alt.endpoint_interrupt_in(8, 255);
+
+        // Data interface
+        let mut iface = func.interface();
+        let mut alt = iface.alt_setting(USB_CLASS_CDC_DATA, 0x00, 0x00, None);
+        let read_ep = alt.endpoint_bulk_out(max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(max_packet_size);
+
+        drop(func);
+
+        let control = state.control.write(Control {
+            shared: &state.shared,
+            comm_if,
+            packet_filter: 0,
+            response: [0; MAX_RESPONSE_SIZE],
+            response_len: 0,
+        });
+        builder.handler(control);
+
+        RndisClass {
+            comm_ep,
+            read_ep,
+            write_ep,
+            shared: &state.shared,
+            max_packet_size: max_packet_size as usize,
+        }
+    }
+
+    /// Split the class into a sender and receiver.
+    ///
+    /// This allows concurrently sending and receiving packets from separate tasks.
+    pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>) {
+        (
+            Sender {
+                write_ep: self.write_ep,
+                max_packet_size: self.max_packet_size,
+            },
+            Receiver {
+                comm_ep: self.comm_ep,
+                read_ep: self.read_ep,
+                shared: self.shared,
+            },
+        )
+    }
+}
+
+/// RNDIS class packet sender.
+///
+/// You can obtain a `Sender` with [`RndisClass::split`]
+pub struct Sender<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+    max_packet_size: usize,
+}
+
+impl<'d, D: Driver<'d>> Sender<'d, D> {
+    /// Write a packet.
+    ///
+    /// This waits until the packet is successfully stored in the RNDIS endpoint buffers.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        const ABS_MAX_PACKET_SIZE: usize = 512;
+
+        // RNDIS doesn't use zero-length packets, pad the message instead if it would end on a packet boundary.
+        let mut msg_len = PACKET_HEADER_LEN + data.len();
+        let rem = msg_len % self.max_packet_size;
+        let pad = rem == 0;
+        if pad {
+            msg_len += 1;
+        }
+
+        let mut buf = [0; ABS_MAX_PACKET_SIZE];
+        put_u32(&mut buf, 0, MSG_PACKET);
+        put_u32(&mut buf, 4, msg_len as u32);
+        put_u32(&mut buf, 8, (PACKET_HEADER_LEN - 8) as u32); // DataOffset
+        put_u32(&mut buf, 12, data.len() as u32); // DataLength
+
+        // Build first packet on a buffer, send next packets straight from `data`.
+        if PACKET_HEADER_LEN + data.len() < self.max_packet_size {
+            // First packet is not full, just send it.
+            buf[PACKET_HEADER_LEN..][..data.len()].copy_from_slice(data);
+            self.write_ep.write(&buf[..PACKET_HEADER_LEN + data.len()]).await?;
+        } else {
+            let (d1, d2) = data.split_at(self.max_packet_size - PACKET_HEADER_LEN);
+
+            buf[PACKET_HEADER_LEN..self.max_packet_size].copy_from_slice(d1);
+            self.write_ep.write(&buf[..self.max_packet_size]).await?;
+
+            for chunk in d2.chunks(self.max_packet_size) {
+                self.write_ep.write(chunk).await?;
+            }
+        }
+
+        if pad {
+            self.write_ep.write(&[0]).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// RNDIS class packet receiver.
+///
+/// You can obtain a `Receiver` with [`RndisClass::split`]
+///
+/// The receiver also notifies the host of responses to its control messages, so it must be
+/// polled, with [`wait_connection`](Self::wait_connection) or [`read_packet`](Self::read_packet),
+/// for the host to configure the device.
+pub struct Receiver<'d, D: Driver<'d>> {
+    comm_ep: D::EndpointIn,
+    read_ep: D::EndpointOut,
+    shared: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> Receiver<'d, D> {
+    /// Notifies the host that a response to a control message is available.
+    async fn notify(&mut self) -> Result<(), EndpointError> {
+        let buf = [
+            0x01, // RESPONSE_AVAILABLE
+            0x00, 0x00, 0x00, // reserved
+            0x00, 0x00, 0x00, 0x00, // reserved
+        ];
+        match self.comm_ep.write(&buf).await {
+            Ok(()) | Err(EndpointError::Disabled) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read a network packet.
+    ///
+    /// This waits until a packet is successfully received from the endpoint buffers.
+    pub async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let shared = self.shared;
+
+        // Retry loop
+        loop {
+            let mut msg = [0u8; MAX_TRANSFER_SIZE + 1];
+
+            // Wait for the first packet, notifying the host of control responses meanwhile.
+            let mut pos = loop {
+                match select(shared.wait_response_available(), self.read_ep.read(&mut msg)).await {
+                    Either::First(()) => self.notify().await?,
+                    Either::Second(n) => break n?,
+                }
+            };
+            let mut n = pos;
+            while n == self.read_ep.info().max_packet_size as usize && pos < msg.len() {
+                n = self.read_ep.read(&mut msg[pos..]).await?;
+                pos += n;
+            }
+            let msg = &msg[..pos];
+
+            if get_u32(msg, 0) != MSG_PACKET {
+                warn!("Received bad RNDIS message type.");
+                continue;
+            }
+            let data_offset = 8 + get_u32(msg, 8) as usize;
+            let data_len = get_u32(msg, 12) as usize;
+            let Some(data) = msg.get(data_offset..data_offset + data_len) else {
+                warn!("RNDIS packet has a data pointer out of range.");
+                continue;
+            };
+            if data_len > buf.len() {
+                warn!("Received packet too large for buffer.");
+                continue;
+            }
+            buf[..data_len].copy_from_slice(data);
+
+            return Ok(data_len);
+        }
+    }
+
+    /// Waits for the USB host to enable the network interface.
+    pub async fn wait_connection(&mut self) -> Result<(), EndpointError> {
+        let shared = self.shared;
+        loop {
+            self.read_ep.wait_enabled().await;
+            match select(shared.wait_response_available(), shared.wait_connected()).await {
+                Either::First(()) => self.notify().await?,
+                Either::Second(()) => return Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,606 @@
+//! USB Audio Class 1.0 implementation.
+//!
+//! Provides a [`Speaker`]: the host streams PCM audio to the device over an isochronous OUT endpoint,
+//! with an explicit feedback endpoint to keep the host's rate in sync with the device clock, a
+//! feature unit for volume and mute, and a selectable sample rate.
+
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use crate::descriptor::{SynchronizationType, UsageType};
+use crate::driver::{Driver, Endpoint, EndpointAddress, EndpointError, EndpointIn, EndpointOut, EndpointType};
+use crate::types::InterfaceNumber;
+use crate::{Builder, Handler};
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_AUDIO_CLASS: u8 = 0x01;
+
+/// Maximum number of audio channels.
+pub const MAX_CHANNEL_COUNT: usize = 8;
+
+const USB_AUDIOCONTROL_SUBCLASS: u8 = 0x01;
+const USB_AUDIOSTREAMING_SUBCLASS: u8 = 0x02;
+const PROTOCOL_NONE: u8 = 0x00;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+const AC_HEADER: u8 = 0x01;
+const AC_INPUT_TERMINAL: u8 = 0x02;
+const AC_OUTPUT_TERMINAL: u8 = 0x03;
+const AC_FEATURE_UNIT: u8 = 0x06;
+const AS_GENERAL: u8 = 0x01;
+const AS_FORMAT_TYPE: u8 = 0x02;
+const EP_GENERAL: u8 = 0x01;
+
+const FORMAT_TYPE_I: u8 = 0x01;
+const FORMAT_TAG_PCM: u16 = 0x0001;
+const TERMINAL_TYPE_USB_STREAMING: u16 = 0x0101;
+
+const INPUT_TERMINAL_ID: u8 = 1;
+const FEATURE_UNIT_ID: u8 = 2;
+const OUTPUT_TERMINAL_ID: u8 = 3;
+
+const REQ_SET_CUR: u8 = 0x01;
+const REQ_GET_CUR: u8 = 0x81;
+const REQ_GET_MIN: u8 = 0x82;
+const REQ_GET_MAX: u8 = 0x83;
+const REQ_GET_RES: u8 = 0x84;
+
+const MUTE_CONTROL: u8 = 0x01;
+const VOLUME_CONTROL: u8 = 0x02;
+const SAMPLING_FREQ_CONTROL: u8 = 0x01;
+
+/// Output terminal types, describing what the speaker looks like to the host.
+pub mod terminal_type {
+    /// Generic speaker.
+    pub const SPEAKER: u16 = 0x0301;
+    /// Headphones.
+    pub const HEADPHONES: u16 = 0x0302;
+    /// Desktop speaker.
+    pub const DESKTOP_SPEAKER: u16 = 0x0304;
+}
+
+/// Size of an audio sample.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleWidth {
+    /// 16-bit samples.
+    Width2Byte = 2,
+    /// 24-bit samples.
+    Width3Byte = 3,
+    /// 32-bit samples.
+    Width4Byte = 4,
+}
+
+impl SampleWidth {
+    /// Size of a sample in bytes.
+    pub const fn in_bytes(self) -> usize {
+        self as usize
+    }
+
+    /// Size of a sample in bits.
+    pub const fn in_bits(self) -> u8 {
+        8 * self as u8
+    }
+}
+
+/// Volume of an audio channel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Volume {
+    /// The channel is muted.
+    Muted,
+    /// Gain in decibels.
+    DeciBel(f32),
+}
+
+/// Configuration for the USB audio speaker.
+pub struct Config<'d> {
+    /// Number of audio channels, up to [`MAX_CHANNEL_COUNT`].
+    pub channels: u8,
+
+    /// Size of the samples.
+    pub sample_width: SampleWidth,
+
+    /// Supported sample rates in Hz. The first one is used until the host selects another one.
+    pub sample_rates_hz: &'d [u32],
+
+    /// Type of the output terminal, see [`terminal_type`].
+    pub terminal_type: u16,
+
+    /// Volume range and resolution in decibels, reported to the host.
+    pub min_volume_db: f32,
+    /// See `min_volume_db`.
+    pub max_volume_db: f32,
+    /// See `min_volume_db`.
+    pub volume_resolution_db: f32,
+
+    /// How often the host reads the feedback endpoint, as a power of two in milliseconds, from
+    /// 1 (2 ms) to 9 (512 ms).
+    pub feedback_refresh: u8,
+}
+
+impl<'d> Default for Config<'d> {
+    fn default() -> Self {
+        Self {
+            channels: 2,
+            sample_width: SampleWidth::Width2Byte,
+            sample_rates_hz: &[48_000],
+            terminal_type: terminal_type::SPEAKER,
+            min_volume_db: -100.0,
+            max_volume_db: 0.0,
+            volume_resolution_db: 1.0,
+            feedback_refresh: 3,
+        }
+    }
+}
+
+/// Internal state for the USB audio speaker.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    shared: Shared,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: Shared::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Settings {
+    mute: bool,
+    /// Volume of the master channel (index 0) and each channel, in 1/256 dB.
+    volume: [i16; MAX_CHANNEL_COUNT + 1],
+    sample_rate_hz: u32,
+}
+
+/// Shared data between Control and ControlMonitor.
+struct Shared {
+    settings: CriticalSectionMutex<Cell<Settings>>,
+    waker: RefCell<WakerRegistration>,
+    changed: AtomicBool,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            settings: CriticalSectionMutex::new(Cell::new(Settings {
+                mute: false,
+                volume: [0; MAX_CHANNEL_COUNT + 1],
+                sample_rate_hz: 0,
+            })),
+            waker: RefCell::new(WakerRegistration::new()),
+            changed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Shared {
+    fn update(&self, f: impl FnOnce(&mut Settings)) {
+        self.settings.lock(|x| {
+            let mut settings = x.get();
+            f(&mut settings);
+            x.set(settings);
+        });
+        self.changed.store(true, Ordering::Relaxed);
+        self.waker.borrow_mut().wake();
+    }
+}
+
+struct Control<'d> {
+    control_if: InterfaceNumber,
+    data_ep: EndpointAddress,
+    channels: u8,
+    sample_rates_hz: &'d [u32],
+    volume_range: [i16; 3],
+    shared: &'d Shared,
+}
+
+impl<'d> Control<'d> {
+    fn is_feature_unit_request(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == (FEATURE_UNIT_ID as u16) << 8 | self.control_if.0 as u16
+    }
+
+    fn is_data_endpoint_request(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Endpoint
+            && req.index as u8 == u8::from(self.data_ep)
+    }
+}
+
+impl<'d> Handler for Control<'d> {
+    fn reset(&mut self) {
+        let sample_rate_hz = self.sample_rates_hz[0];
+        self.shared.update(|s| s.sample_rate_hz = sample_rate_hz);
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        let selector = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+
+        if self.is_feature_unit_request(&req) {
+            return Some(match (req.request, selector) {
+                (REQ_SET_CUR, MUTE_CONTROL) if channel == 0 && !data.is_empty() => {
+                    let mute = data[0] != 0;
+                    debug!("uac1: set mute {}", mute);
+                    self.shared.update(|s| s.mute = mute);
+                    OutResponse::Accepted
+                }
+                (REQ_SET_CUR, VOLUME_CONTROL) if channel <= self.channels && data.len() >= 2 => {
+                    let [min, max, _] = self.volume_range;
+                    let volume = i16::from_le_bytes([data[0], data[1]]).clamp(min, max);
+                    debug!("uac1: set volume of channel {} to {}/256 dB", channel, volume);
+                    self.shared.update(|s| s.volume[channel as usize] = volume);
+                    OutResponse::Accepted
+                }
+                _ => OutResponse::Rejected,
+            });
+        }
+
+        if self.is_data_endpoint_request(&req) {
+            return Some(match (req.request, selector) {
+                (REQ_SET_CUR, SAMPLING_FREQ_CONTROL) if data.len() >= 3 => {
+                    let rate = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+                    if self.sample_rates_hz.contains(&rate) {
+                        debug!("uac1: set sample rate {} Hz", rate);
+                        self.shared.update(|s| s.sample_rate_hz = rate);
+                        OutResponse::Accepted
+                    } else {
+                        warn!("uac1: unsupported sample rate {} Hz", rate);
+                        OutResponse::Rejected
+                    }
+                }
+                _ => OutResponse::Rejected,
+            });
+        }
+
+        None
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        let selector = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+        let settings = self.shared.settings.lock(Cell::get);
+
+        if self.is_feature_unit_request(&req) {
+            return Some(match (req.request, selector) {
+                (REQ_GET_CUR, MUTE_CONTROL) if channel == 0 => {
+                    buf[0] = settings.mute as u8;
+                    InResponse::Accepted(&buf[..1])
+                }
+                (REQ_GET_CUR | REQ_GET_MIN | REQ_GET_MAX | REQ_GET_RES, VOLUME_CONTROL) if channel <= self.channels => {
+                    let value = match req.request {
+                        REQ_GET_CUR => settings.volume[channel as usize],
+                        REQ_GET_MIN => self.volume_range[0],
+                        REQ_GET_MAX => self.volume_range[1],
+                        _ => self.volume_range[2],
+                    };
+                    buf[..2].copy_from_slice(&value.to_le_bytes());
+                    InResponse::Accepted(&buf[..2])
+                }
+                _ => InResponse::Rejected,
+            });
+        }
+
+        if self.is_data_endpoint_request(&req) {
+            return Some(match (req.request, selector) {
+                (REQ_GET_CUR, SAMPLING_FREQ_CONTROL) => {
+                    buf[..3].copy_from_slice(&settings.sample_rate_hz.to_le_bytes()[..3]);
+                    InResponse::Accepted(&buf[..3])
+                }
+                _ => InResponse::Rejected,
+            });
+        }
+
+        None
+    }
+}
+
+/// USB audio speaker.
+///
+/// Use [`split`](Self::split) to get the audio stream, the feedback endpoint and the control
+/// monitor, which can then be used from separate tasks.
+pub struct Speaker<'d, D: Driver<'d>> {
+    stream: Stream<'d, D>,
+    feedback: Feedback<'d, D>,
+    control_monitor: ControlMonitor<'d>,
+}
+
+impl<'d, D: Driver<'d>> Speaker<'d, D> {
+    /// Creates a new USB audio speaker.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid: no sample rates, or too many channels or sample rates.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config<'d>) -> Self {
+        let channels = config.channels;
+        assert!(
+            channels >= 1 && channels as usize <= MAX_CHANNEL_COUNT,
+            "invalid channel count"
+        );
+        assert!(!config.sample_rates_hz.is_empty(), "no sample rates");
+
+        // Enough for the highest sample rate, plus one sample per channel as the host adjusts to the feedback.
+        let max_rate = config.sample_rates_hz.iter().copied().max().unwrap_or(0);
+        let frame_bytes = channels as usize * config.sample_width.in_bytes();
+        let max_packet_size = ((max_rate as usize).div_ceil(1000) + 1) * frame_bytes;
+
+        let mut func = builder.function(USB_AUDIO_CLASS, USB_AUDIOCONTROL_SUBCLASS, PROTOCOL_NONE);
+
+        // Audio control interface.
+        let mut iface = func.interface();
+        let control_if = iface.interface_number();
+        let streaming_if = u8::from(control_if) + 1;
+        let mut alt = iface.alt_setting(USB_AUDIO_CLASS, USB_AUDIOCONTROL_SUBCLASS, PROTOCOL_NONE, None);
+
+        let feature_unit_len = 7 + (channels as usize + 1);
+        let total_len = (9 + 12 + feature_unit_len + 9) as u16;
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                AC_HEADER, // bDescriptorSubtype
+                0x00,
+                0x01, // bcdADC (1.00)
+                total_len as u8,
+                (total_len >> 8) as u8, // wTotalLength
+                0x01,                   // bInCollection
+                streaming_if,           // baInterfaceNr
+            ],
+        );
+
+        let channel_config: u16 = if channels == 1 { 0 } else { (1 << channels) - 1 };
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                AC_INPUT_TERMINAL, // bDescriptorSubtype
+                INPUT_TERMINAL_ID, // bTerminalID
+                TERMINAL_TYPE_USB_STREAMING as u8,
+                (TERMINAL_TYPE_USB_STREAMING >> 8) as u8, // wTerminalType
+                0x00,                                     // bAssocTerminal
+                channels,                                 // bNrChannels
+                channel_config as u8,
+                (channel_config >> 8) as u8, // wChannelConfig
+                0x00,                        // iChannelNames
+                0x00,                        // iTerminal
+            ],
+        );
+
+        // Mute and volume on the master channel, volume on each channel.
+        let mut feature_unit = [0; 7 + MAX_CHANNEL_COUNT + 1];
+        feature_unit[..4].copy_from_slice(&[
+            AC_FEATURE_UNIT,   // bDescriptorSubtype
+            FEATURE_UNIT_ID,   // bUnitID
+            INPUT_TERMINAL_ID, // bSourceID
+            0x01,              // bControlSize
+        ]);
+        feature_unit[4] = 0x03; // bmaControls(0)
+        feature_unit[5..5 + channels as usize].fill(0x02); // bmaControls(1..)
+        alt.descriptor(CS_INTERFACE, &feature_unit[..feature_unit_len - 2]);
+
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                AC_OUTPUT_TERMINAL, // bDescriptorSubtype
+                OUTPUT_TERMINAL_ID, // bTerminalID
+                config.terminal_type as u8,
+                (config.terminal_type >> 8) as u8, // wTerminalType
+                0x00,                              // bAssocTerminal
+                FEATURE_UNIT_ID,                   // bSourceID
+                0x00,                              // iTerminal
+            ],
+        );
+
+        // Audio streaming interface. Alternate setting 0 has no endpoints, so that the host can
+        // release the bandwidth when not playing.
+        let mut iface = func.interface();
+        iface.alt_setting(USB_AUDIO_CLASS, USB_AUDIOSTREAMING_SUBCLASS, PROTOCOL_NONE, None);
+        let mut alt = iface.alt_setting(USB_AUDIO_CLASS, USB_AUDIOSTREAMING_SUBCLASS, PROTOCOL_NONE, None);
+
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                AS_GENERAL,        // bDescriptorSubtype
+                INPUT_TERMINAL_ID, // bTerminalLink
+                0x01,              // bDelay
+                FORMAT_TAG_PCM as u8,
+                (FORMAT_TAG_PCM >> 8) as u8, // wFormatTag
+            ],
+        );
+
+        let mut format = [0; 6 + 3 * 16];
+        let num_rates = config.sample_rates_hz.len();
+        assert!(num_rates <= 16, "too many sample rates");
+        format[..6].copy_from_slice(&[
+            AS_FORMAT_TYPE,                // bDescriptorSubtype
+            FORMAT_TYPE_I,                 // bFormatType
+            channels,                      // bNrChannels
+            config.sample_width as u8,     // bSubframeSize
+            config.sample_width.in_bits(), // bBitResolution
+            num_rates as u8,               // bSamFreqType
+        ]);
+        for (i, rate) in config.sample_rates_hz.iter().enumerate() {
+            format[6 + 3 * i..][..3].copy_from_slice(&rate.to_le_bytes()[..3]); // tSamFreq
+        }
+        alt.descriptor(CS_INTERFACE, &format[..6 + 3 * num_rates]);
+
+        let data_ep = alt.alloc_endpoint_out(EndpointType::Isochronous, max_packet_size as u16, 1);
+        let feedback_ep = alt.alloc_endpoint_in(EndpointType::Isochronous, 3, 1);
+
+        alt.endpoint_descriptor(
+            data_ep.info(),
+            SynchronizationType::Asynchronous,
+            UsageType::DataEndpoint,
+            &[
+                0x00,                           // bRefresh
+                feedback_ep.info().addr.into(), // bSynchAddress
+            ],
+        );
+        alt.descriptor(
+            CS_ENDPOINT,
+            &[
+                EP_GENERAL, // bDescriptorSubtype
+                0x01,       // bmAttributes: sampling frequency control
+                0x00,       // bLockDelayUnits
+                0x00, 0x00, // wLockDelay
+            ],
+        );
+        alt.endpoint_descriptor(
+            feedback_ep.info(),
+            SynchronizationType::NoSynchronization,
+            UsageType::FeedbackEndpoint,
+            &[
+                config.feedback_refresh, // bRefresh
+                0x00,                    // bSynchAddress
+            ],
+        );
+
+        drop(func);
+
+        let volume_range = [
+            (config.min_volume_db * 256.0) as i16,
+            (config.max_volume_db * 256.0) as i16,
+            (config.volume_resolution_db * 256.0) as i16,
+        ];
+        state.shared.update(|s| s.sample_rate_hz = config.sample_rates_hz[0]);
+        state.shared.changed.store(false, Ordering::Relaxed);
+
+        let control = state.control.write(Control {
+            control_if,
+            data_ep: data_ep.info().addr,
+            channels,
+            sample_rates_hz: config.sample_rates_hz,
+            volume_range,
+            shared: &state.shared,
+        });
+        builder.handler(control);
+
+        Speaker {
+            stream: Stream { ep: data_ep },
+            feedback: Feedback { ep: feedback_ep },
+            control_monitor: ControlMonitor {
+                shared: &state.shared,
+                channels,
+            },
+        }
+    }
+
+    /// Split the speaker into the audio stream, the feedback endpoint and the control monitor.
+    pub fn split(self) -> (Stream<'d, D>, Feedback<'d, D>, ControlMonitor<'d>) {
+        (self.stream, self.feedback, self.control_monitor)
+    }
+}
+
+/// Audio data received from the host.
+///
+/// You can obtain a `Stream` with [`Speaker::split`].
+pub struct Stream<'d, D: Driver<'d>> {
+    ep: D::EndpointOut,
+}
+
+impl<'d, D: Driver<'d>> Stream<'d, D> {
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.ep.info().max_packet_size
+    }
+
+    /// Reads the audio samples of one frame.
+    ///
+    /// Samples are interleaved by channel, little-endian. `buf` must be at least
+    /// [`max_packet_size`](Self::max_packet_size) bytes.
+    pub async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        self.ep.read(buf).await
+    }
+
+    /// Waits for the host to start streaming audio.
+    pub async fn wait_connection(&mut self) {
+        self.ep.wait_enabled().await;
+    }
+}
+
+/// Explicit feedback endpoint.
+///
+/// The device reports how many samples it actually consumes per frame, so that the host can
+/// adjust the amount of data it sends to the device's audio clock.
+///
+/// You can obtain a `Feedback` with [`Speaker::split`].
+pub struct Feedback<'d, D: Driver<'d>> {
+    ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> Feedback<'d, D> {
+    /// Writes a feedback value: the number of samples per 1 ms frame, in 10.14 fixed-point format.
+    ///
+    /// For example, `48 << 14` is exactly 48 kHz.
+    pub async fn write_feedback(&mut self, samples_per_frame: u32) -> Result<(), EndpointError> {
+        self.ep.write(&samples_per_frame.to_le_bytes()[..3]).await
+    }
+
+    /// Waits for the host to start streaming audio.
+    pub async fn wait_connection(&mut self) {
+        self.ep.wait_enabled().await;
+    }
+}
+
+/// Volume, mute and sample rate monitor.
+///
+/// You can obtain a `ControlMonitor` with [`Speaker::split`].
+pub struct ControlMonitor<'d> {
+    shared: &'d Shared,
+    channels: u8,
+}
+
+impl<'d> ControlMonitor<'d> {
+    /// Waits for the host to change the volume, mute or sample rate.
+    pub async fn changed(&self) {
+        poll_fn(|cx| {
+            if self.shared.changed.load(Ordering::Relaxed) {
+                self.shared.changed.store(false, Ordering::Relaxed);
+                Poll::Ready(())
+            } else {
+                self.shared.waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    /// Gets the volume of a channel, counted from 0.
+    ///
+    /// This combines the master mute and volume with the channel's own volume.
+    pub fn volume(&self, channel: u8) -> Volume {
+        assert!(channel < self.channels, "invalid channel");
+        let settings = self.shared.settings.lock(Cell::get);
+        if settings.mute {
+            Volume::Muted
+        } else {
+            let volume = settings.volume[0] as i32 + settings.volume[channel as usize + 1] as i32;
+            Volume::DeciBel(volume as f32 / 256.0)
+        }
+    }
+
+    /// Gets the sample rate selected by the host, in Hz.
+    pub fn sample_rate_hz(&self) -> u32 {
+        self.shared.settings.lock(Cell::get).sample_rate_hz
+    }
+}
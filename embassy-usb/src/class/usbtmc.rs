@@ -0,0 +1,684 @@
+//! USB Test and Measurement Class (USBTMC) implementation, with optional USB488 support.
+//!
+//! This lets instrument firmware be controlled from VISA-based tooling on the host, such as NI-VISA,
+//! PyVISA or the Linux `usbtmc` driver. Messages are opaque byte strings, usually SCPI commands and
+//! responses.
+//!
+//! No interrupt endpoint is used, so USB488 service requests (SRQ) are not supported. The host can
+//! still poll the status byte with `READ_STATUS_BYTE`.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, Recipient, Request, RequestType};
+use crate::driver::{Driver, Endpoint, EndpointAddress, EndpointError, EndpointIn, EndpointOut};
+use crate::types::InterfaceNumber;
+use crate::{Builder, Handler};
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+
+const USBTMC_SUBCLASS: u8 = 0x03;
+const USBTMC_PROTOCOL: u8 = 0x00;
+const USBTMC_PROTOCOL_USB488: u8 = 0x01;
+
+/// Largest supported max packet size, the one of high speed bulk endpoints.
+const MAX_PACKET_SIZE: usize = 512;
+
+const HEADER_LEN: usize = 12;
+
+// Bulk message IDs.
+const DEV_DEP_MSG_OUT: u8 = 1;
+const REQUEST_DEV_DEP_MSG_IN: u8 = 2;
+const DEV_DEP_MSG_IN: u8 = 2;
+const USB488_TRIGGER: u8 = 128;
+
+/// `bmTransferAttributes` bit marking the last transfer of a message.
+const ATTR_EOM: u8 = 0x01;
+
+// Class requests.
+const REQ_INITIATE_ABORT_BULK_OUT: u8 = 1;
+const REQ_CHECK_ABORT_BULK_OUT_STATUS: u8 = 2;
+const REQ_INITIATE_ABORT_BULK_IN: u8 = 3;
+const REQ_CHECK_ABORT_BULK_IN_STATUS: u8 = 4;
+const REQ_INITIATE_CLEAR: u8 = 5;
+const REQ_CHECK_CLEAR_STATUS: u8 = 6;
+const REQ_GET_CAPABILITIES: u8 = 7;
+const REQ_INDICATOR_PULSE: u8 = 64;
+const REQ_USB488_READ_STATUS_BYTE: u8 = 128;
+const REQ_USB488_REN_CONTROL: u8 = 160;
+const REQ_USB488_GO_TO_LOCAL: u8 = 161;
+const REQ_USB488_LOCAL_LOCKOUT: u8 = 162;
+
+// Request status values.
+const STATUS_SUCCESS: u8 = 0x01;
+const STATUS_FAILED: u8 = 0x80;
+const STATUS_TRANSFER_NOT_IN_PROGRESS: u8 = 0x81;
+
+/// Configuration for the USBTMC class.
+pub struct Config {
+    /// Whether to implement the USB488 subclass, for IEEE 488.2 instruments.
+    ///
+    /// This adds trigger messages, status byte reads and remote/local control.
+    pub usb488: bool,
+
+    /// Whether the instrument understands SCPI. Only reported to the host with [`usb488`](Self::usb488).
+    pub scpi: bool,
+
+    /// Whether the instrument can identify itself, for example by blinking a LED, when the host
+    /// sends an indicator pulse. See [`InstrumentControl::wait_indicator_pulse`].
+    pub indicator_pulse: bool,
+
+    /// Max packet size for both the IN and OUT endpoints. Must be at least 16.
+    pub max_packet_size: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            usb488: true,
+            scpi: true,
+            indicator_pulse: false,
+            max_packet_size: 64,
+        }
+    }
+}
+
+/// Internal state for the USBTMC class.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    shared: Shared,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: Shared::default(),
+        }
+    }
+}
+
+/// Shared data between Control, UsbTmcClass and InstrumentControl.
+struct Shared {
+    /// Set when the host aborts the transfer in progress or clears the device.
+    abort: AtomicBool,
+    out_active: AtomicBool,
+    out_tag: AtomicU8,
+    out_received: AtomicU32,
+    in_active: AtomicBool,
+    in_tag: AtomicU8,
+    in_sent: AtomicU32,
+    status_byte: AtomicU8,
+    remote: AtomicBool,
+    local_lockout: AtomicBool,
+    indicator_pulse: AtomicBool,
+    waker: RefCell<WakerRegistration>,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            abort: AtomicBool::new(false),
+            out_active: AtomicBool::new(false),
+            out_tag: AtomicU8::new(0),
+            out_received: AtomicU32::new(0),
+            in_active: AtomicBool::new(false),
+            in_tag: AtomicU8::new(0),
+            in_sent: AtomicU32::new(0),
+            status_byte: AtomicU8::new(0),
+            remote: AtomicBool::new(false),
+            local_lockout: AtomicBool::new(false),
+            indicator_pulse: AtomicBool::new(false),
+            waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+}
+
+struct Control<'d> {
+    iface: InterfaceNumber,
+    read_ep: EndpointAddress,
+    write_ep: EndpointAddress,
+    usb488: bool,
+    scpi: bool,
+    indicator_pulse: bool,
+    shared: &'d Shared,
+}
+
+impl<'d> Control<'d> {
+    fn initiate_abort(&self, active: &AtomicBool, tag: &AtomicU8, req_tag: u8) -> u8 {
+        if !active.load(Ordering::Relaxed) {
+            STATUS_FAILED
+        } else if tag.load(Ordering::Relaxed) != req_tag {
+            STATUS_TRANSFER_NOT_IN_PROGRESS
+        } else {
+            self.shared.abort.store(true, Ordering::Relaxed);
+            STATUS_SUCCESS
+        }
+    }
+
+    fn interface_request<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match req.request {
+            REQ_INITIATE_CLEAR => {
+                debug!("usbtmc: clear");
+                if self.shared.out_active.load(Ordering::Relaxed) || self.shared.in_active.load(Ordering::Relaxed) {
+                    self.shared.abort.store(true, Ordering::Relaxed);
+                }
+                buf[0] = STATUS_SUCCESS;
+                InResponse::Accepted(&buf[..1])
+            }
+            REQ_CHECK_CLEAR_STATUS => {
+                // bmClear: nothing left in the bulk IN FIFO.
+                buf[..2].copy_from_slice(&[STATUS_SUCCESS, 0]);
+                InResponse::Accepted(&buf[..2])
+            }
+            REQ_GET_CAPABILITIES => {
+                buf[..24].fill(0);
+                buf[0] = STATUS_SUCCESS;
+                // bcdUSBTMC 1.00
+                buf[2..4].copy_from_slice(&0x0100u16.to_le_bytes());
+                // Interface capabilities: indicator pulse. Device capabilities: no TermChar.
+                buf[4] = if self.indicator_pulse { 0x04 } else { 0x00 };
+                if self.usb488 {
+                    // bcdUSB488 1.00
+                    buf[12..14].copy_from_slice(&0x0100u16.to_le_bytes());
+                    // USB488.2 interface, REN_CONTROL/GO_TO_LOCAL/LOCAL_LOCKOUT, TRIGGER.
+                    buf[14] = 0x07;
+                    // SCPI, RL1 (remote/local), DT1 (device trigger).
+                    buf[15] = if self.scpi { 0x0b } else { 0x03 };
+                }
+                InResponse::Accepted(&buf[..24])
+            }
+            REQ_INDICATOR_PULSE if self.indicator_pulse => {
+                self.shared.indicator_pulse.store(true, Ordering::Relaxed);
+                self.shared.waker.borrow_mut().wake();
+                buf[0] = STATUS_SUCCESS;
+                InResponse::Accepted(&buf[..1])
+            }
+            REQ_USB488_READ_STATUS_BYTE if self.usb488 => {
+                let tag = req.value as u8;
+                if !(2..=127).contains(&tag) {
+                    buf[..3].copy_from_slice(&[STATUS_FAILED, tag, 0]);
+                } else {
+                    // Without an interrupt endpoint, the status byte is returned right away.
+                    buf[..3].copy_from_slice(&[STATUS_SUCCESS, tag, self.shared.status_byte.load(Ordering::Relaxed)]);
+                }
+                InResponse::Accepted(&buf[..3])
+            }
+            REQ_USB488_REN_CONTROL if self.usb488 => {
+                let enable = req.value & 0xff != 0;
+                debug!("usbtmc: remote enable {}", enable);
+                self.shared.remote.store(enable, Ordering::Relaxed);
+                if !enable {
+                    self.shared.local_lockout.store(false, Ordering::Relaxed);
+                }
+                buf[0] = STATUS_SUCCESS;
+                InResponse::Accepted(&buf[..1])
+            }
+            REQ_USB488_GO_TO_LOCAL if self.usb488 => {
+                self.shared.remote.store(false, Ordering::Relaxed);
+                buf[0] = STATUS_SUCCESS;
+                InResponse::Accepted(&buf[..1])
+            }
+            REQ_USB488_LOCAL_LOCKOUT if self.usb488 => {
+                self.shared.local_lockout.store(true, Ordering::Relaxed);
+                buf[0] = STATUS_SUCCESS;
+                InResponse::Accepted(&buf[..1])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+
+    fn endpoint_request<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        let ep = req.index as u8;
+        let req_tag = req.value as u8;
+        match req.request {
+            REQ_INITIATE_ABORT_BULK_OUT if ep == u8::from(self.read_ep) => {
+                let status = self.initiate_abort(&self.shared.out_active, &self.shared.out_tag, req_tag);
+                debug!("usbtmc: abort bulk out, status {:02x}", status);
+                buf[..2].copy_from_slice(&[status, req_tag]);
+                InResponse::Accepted(&buf[..2])
+            }
+            REQ_CHECK_ABORT_BULK_OUT_STATUS if ep == u8::from(self.read_ep) => {
+                buf[..4].copy_from_slice(&[STATUS_SUCCESS, 0, 0, 0]);
+                buf[4..8].copy_from_slice(&self.shared.out_received.load(Ordering::Relaxed).to_le_bytes());
+                InResponse::Accepted(&buf[..8])
+            }
+            REQ_INITIATE_ABORT_BULK_IN if ep == u8::from(self.write_ep) => {
+                let status = self.initiate_abort(&self.shared.in_active, &self.shared.in_tag, req_tag);
+                debug!("usbtmc: abort bulk in, status {:02x}", status);
+                buf[..2].copy_from_slice(&[status, req_tag]);
+                InResponse::Accepted(&buf[..2])
+            }
+            REQ_CHECK_ABORT_BULK_IN_STATUS if ep == u8::from(self.write_ep) => {
+                buf[..4].copy_from_slice(&[STATUS_SUCCESS, 0, 0, 0]);
+                buf[4..8].copy_from_slice(&self.shared.in_sent.load(Ordering::Relaxed).to_le_bytes());
+                InResponse::Accepted(&buf[..8])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+impl<'d> Handler for Control<'d> {
+    fn reset(&mut self) {
+        self.shared.remote.store(false, Ordering::Relaxed);
+        self.shared.local_lockout.store(false, Ordering::Relaxed);
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if req.request_type != RequestType::Class {
+            return None;
+        }
+
+        match req.recipient {
+            Recipient::Interface if req.index == self.iface.0 as u16 => Some(self.interface_request(req, buf)),
+            Recipient::Endpoint
+                if req.index as u8 == u8::from(self.read_ep) || req.index as u8 == u8::from(self.write_ep) =>
+            {
+                Some(self.endpoint_request(req, buf))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Handle to update the instrument status and watch for requests from the host.
+///
+/// You can obtain an `InstrumentControl` with [`UsbTmcClass::instrument_control`].
+#[derive(Clone, Copy)]
+pub struct InstrumentControl<'d> {
+    shared: &'d Shared,
+}
+
+impl<'d> InstrumentControl<'d> {
+    /// Sets the IEEE 488.2 status byte, returned to the host on `READ_STATUS_BYTE` requests.
+    pub fn set_status_byte(&self, status_byte: u8) {
+        self.shared.status_byte.store(status_byte, Ordering::Relaxed);
+    }
+
+    /// Returns the IEEE 488.2 status byte.
+    pub fn status_byte(&self) -> u8 {
+        self.shared.status_byte.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the host enabled remote control with `REN_CONTROL`.
+    ///
+    /// While remote, front panel controls that change settings should be disabled.
+    pub fn is_remote(&self) -> bool {
+        self.shared.remote.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the host locked out the front panel "return to local" control.
+    pub fn is_local_lockout(&self) -> bool {
+        self.shared.local_lockout.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the host to send an indicator pulse.
+    ///
+    /// The instrument should then identify itself to the user, for example by blinking a LED.
+    /// Only enabled with [`Config::indicator_pulse`].
+    pub async fn wait_indicator_pulse(&self) {
+        poll_fn(|cx| {
+            if self.shared.indicator_pulse.load(Ordering::Relaxed) {
+                self.shared.indicator_pulse.store(false, Ordering::Relaxed);
+                Poll::Ready(())
+            } else {
+                self.shared.waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+/// Error returned by [`UsbTmcClass::read`] and [`UsbTmcClass::write`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The message didn't fit in the buffer. The rest of it was discarded.
+    BufferOverflow,
+    /// The host aborted the transfer, or cleared the device.
+    Aborted,
+    /// The host sent a new message instead of requesting the response, which was discarded.
+    ///
+    /// The new message is discarded too. IEEE 488.2 calls this an interrupted query.
+    Interrupted,
+    /// The endpoints are disabled, the device is not configured.
+    Disabled,
+}
+
+impl From<EndpointError> for Error {
+    fn from(e: EndpointError) -> Self {
+        match e {
+            EndpointError::BufferOverflow => Error::BufferOverflow,
+            EndpointError::Disabled => Error::Disabled,
+        }
+    }
+}
+
+/// Message received from the host.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message {
+    /// A device dependent message, such as a SCPI command, of the given length.
+    Command(usize),
+    /// A USB488 trigger, equivalent to the IEEE 488.1 GET message or the `*TRG` command.
+    Trigger,
+}
+
+/// Bulk message header.
+#[derive(Clone, Copy)]
+struct Header {
+    msg_id: u8,
+    tag: u8,
+    transfer_size: u32,
+    attributes: u8,
+}
+
+impl Header {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN || buf[1] == 0 || buf[2] != !buf[1] {
+            return None;
+        }
+        Some(Self {
+            msg_id: buf[0],
+            tag: buf[1],
+            transfer_size: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            attributes: buf[8],
+        })
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut buf = [0; HEADER_LEN];
+        buf[0] = self.msg_id;
+        buf[1] = self.tag;
+        buf[2] = !self.tag;
+        buf[4..8].copy_from_slice(&self.transfer_size.to_le_bytes());
+        buf[8] = self.attributes;
+        buf
+    }
+}
+
+/// USB Test and Measurement class.
+///
+/// The host sends commands, read with [`read`](Self::read), and asks for responses, sent with
+/// [`write`](Self::write).
+pub struct UsbTmcClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    shared: &'d Shared,
+    usb488: bool,
+    /// Pending `REQUEST_DEV_DEP_MSG_IN` from the host.
+    request: Option<Header>,
+    /// Trigger received while waiting for a response request.
+    trigger: bool,
+}
+
+impl<'d, D: Driver<'d>> UsbTmcClass<'d, D> {
+    /// Creates a new UsbTmcClass with the provided UsbBus and configuration.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config) -> Self {
+        assert!(config.max_packet_size >= 16 && config.max_packet_size as usize <= MAX_PACKET_SIZE);
+
+        let protocol = if config.usb488 {
+            USBTMC_PROTOCOL_USB488
+        } else {
+            USBTMC_PROTOCOL
+        };
+
+        let mut func = builder.function(USB_CLASS_APPLICATION_SPECIFIC, USBTMC_SUBCLASS, protocol);
+        let mut iface = func.interface();
+        let iface_num = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_APPLICATION_SPECIFIC, USBTMC_SUBCLASS, protocol, None);
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(config.max_packet_size);
+        drop(func);
+
+        let control = state.control.write(Control {
+            iface: iface_num,
+            read_ep: read_ep.info().addr,
+            write_ep: write_ep.info().addr,
+            usb488: config.usb488,
+            scpi: config.scpi,
+            indicator_pulse: config.indicator_pulse,
+            shared: &state.shared,
+        });
+        builder.handler(control);
+
+        UsbTmcClass {
+            read_ep,
+            write_ep,
+            shared: &state.shared,
+            usb488: config.usb488,
+            request: None,
+            trigger: false,
+        }
+    }
+
+    /// Gets a handle to update the instrument status and watch for requests from the host.
+    pub fn instrument_control(&self) -> InstrumentControl<'d> {
+        InstrumentControl { shared: self.shared }
+    }
+
+    /// Waits for the USB host to enable this interface.
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await;
+    }
+
+    /// Reads a message from the host.
+    ///
+    /// Messages split across several transfers by the host are reassembled in `buf`. Requests for
+    /// a response are remembered for the next [`write`](Self::write).
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<Message, Error> {
+        if core::mem::take(&mut self.trigger) {
+            return Ok(Message::Trigger);
+        }
+
+        let mut len = 0;
+        let mut overflow = false;
+        loop {
+            let header = self.read_transfer(buf, &mut len, &mut overflow).await?;
+            if self.shared.abort.load(Ordering::Relaxed) {
+                self.shared.abort.store(false, Ordering::Relaxed);
+                debug!("usbtmc: message aborted");
+                self.shared.out_active.store(false, Ordering::Relaxed);
+                len = 0;
+                overflow = false;
+                continue;
+            }
+            let Some(header) = header else {
+                continue;
+            };
+
+            match header.msg_id {
+                DEV_DEP_MSG_OUT => {
+                    if header.attributes & ATTR_EOM == 0 {
+                        continue;
+                    }
+                    self.shared.out_active.store(false, Ordering::Relaxed);
+                    if overflow {
+                        return Err(Error::BufferOverflow);
+                    }
+                    return Ok(Message::Command(len));
+                }
+                REQUEST_DEV_DEP_MSG_IN => self.request = Some(header),
+                USB488_TRIGGER if self.usb488 => return Ok(Message::Trigger),
+                id => warn!("usbtmc: unsupported message {}", id),
+            }
+        }
+    }
+
+    /// Sends a response to the host.
+    ///
+    /// This waits for the host to request it. The response is split across several transfers if
+    /// the host asks for less than its length.
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        loop {
+            let request = match self.request.take() {
+                Some(request) => request,
+                None => self.wait_request().await?,
+            };
+
+            let n = data.len().min(request.transfer_size as usize);
+            let header = Header {
+                msg_id: DEV_DEP_MSG_IN,
+                tag: request.tag,
+                transfer_size: n as u32,
+                attributes: if n == data.len() { ATTR_EOM } else { 0 },
+            };
+            self.write_transfer(header, &data[..n]).await?;
+
+            data = &data[n..];
+            if data.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn wait_request(&mut self) -> Result<Header, Error> {
+        loop {
+            let mut len = 0;
+            let mut overflow = false;
+            let header = self.read_transfer(&mut [], &mut len, &mut overflow).await?;
+            if self.shared.abort.load(Ordering::Relaxed) {
+                self.shared.abort.store(false, Ordering::Relaxed);
+                self.shared.out_active.store(false, Ordering::Relaxed);
+                return Err(Error::Aborted);
+            }
+            let Some(header) = header else {
+                continue;
+            };
+
+            match header.msg_id {
+                REQUEST_DEV_DEP_MSG_IN => return Ok(header),
+                DEV_DEP_MSG_OUT => {
+                    if header.attributes & ATTR_EOM != 0 {
+                        self.shared.out_active.store(false, Ordering::Relaxed);
+                    }
+                    warn!("usbtmc: query interrupted");
+                    return Err(Error::Interrupted);
+                }
+                USB488_TRIGGER if self.usb488 => self.trigger = true,
+                id => warn!("usbtmc: unsupported message {}", id),
+            }
+        }
+    }
+
+    /// Reads one bulk OUT transfer, appending its payload to `buf[*len..]`.
+    ///
+    /// Payload that doesn't fit is dropped and sets `overflow`. Returns `None` for invalid transfers.
+    async fn read_transfer(
+        &mut self,
+        buf: &mut [u8],
+        len: &mut usize,
+        overflow: &mut bool,
+    ) -> Result<Option<Header>, EndpointError> {
+        let max_packet_size = self.read_ep.info().max_packet_size as usize;
+        let mut packet = [0; MAX_PACKET_SIZE];
+
+        let mut n = self.read_ep.read(&mut packet[..max_packet_size]).await?;
+        let Some(header) = Header::parse(&packet[..n]) else {
+            warn!("usbtmc: invalid bulk out header");
+            return Ok(None);
+        };
+
+        let payload_len = match header.msg_id {
+            DEV_DEP_MSG_OUT => header.transfer_size as usize,
+            _ => 0,
+        };
+        // The payload is padded to a multiple of 4 bytes.
+        let total = HEADER_LEN + payload_len.div_ceil(4) * 4;
+
+        if header.msg_id == DEV_DEP_MSG_OUT {
+            self.shared.out_tag.store(header.tag, Ordering::Relaxed);
+            self.shared.out_active.store(true, Ordering::Relaxed);
+        }
+
+        let mut received = n;
+        let mut payload_pos = 0;
+        let mut start = HEADER_LEN;
+        loop {
+            self.shared.out_received.store(received as u32, Ordering::Relaxed);
+
+            let chunk = &packet[start..n];
+            let chunk = &chunk[..chunk.len().min(payload_len - payload_pos)];
+            let space = buf.len() - *len;
+            if chunk.len() > space {
+                *overflow = true;
+            }
+            let copied = chunk.len().min(space);
+            buf[*len..*len + copied].copy_from_slice(&chunk[..copied]);
+            *len += copied;
+            payload_pos += chunk.len();
+
+            if received >= total || n < max_packet_size || self.shared.abort.load(Ordering::Relaxed) {
+                break;
+            }
+
+            n = self.read_ep.read(&mut packet[..max_packet_size]).await?;
+            received += n;
+            start = 0;
+        }
+
+        Ok(Some(header))
+    }
+
+    /// Writes one bulk IN transfer: the header, the payload and its padding.
+    async fn write_transfer(&mut self, header: Header, data: &[u8]) -> Result<(), Error> {
+        let max_packet_size = self.write_ep.info().max_packet_size as usize;
+        let mut packet = [0; MAX_PACKET_SIZE];
+
+        let header = header.to_bytes();
+        let padding = [0; 3];
+        let mut stream = header.iter().chain(data).chain(&padding[..(4 - data.len() % 4) % 4]);
+
+        self.shared.in_tag.store(header[1], Ordering::Relaxed);
+        self.shared.in_sent.store(0, Ordering::Relaxed);
+        self.shared.in_active.store(true, Ordering::Relaxed);
+
+        let result = loop {
+            if self.shared.abort.load(Ordering::Relaxed) {
+                self.shared.abort.store(false, Ordering::Relaxed);
+                // End the transfer with a short packet.
+                debug!("usbtmc: response aborted");
+                break self
+                    .write_ep
+                    .write(&[])
+                    .await
+                    .map_err(Error::from)
+                    .and(Err(Error::Aborted));
+            }
+
+            let mut n = 0;
+            for (dst, src) in packet[..max_packet_size].iter_mut().zip(&mut stream) {
+                *dst = *src;
+                n += 1;
+            }
+            if let Err(e) = self.write_ep.write(&packet[..n]).await {
+                break Err(e.into());
+            }
+            let sent = self.shared.in_sent.load(Ordering::Relaxed);
+            self.shared.in_sent.store(sent + n as u32, Ordering::Relaxed);
+
+            // A short packet, possibly empty, ends the transfer.
+            if n < max_packet_size {
+                break Ok(());
+            }
+        };
+
+        self.shared.in_active.store(false, Ordering::Relaxed);
+        result
+    }
+}
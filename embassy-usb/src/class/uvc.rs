@@ -0,0 +1,545 @@
+//! USB Video Class 1.1 implementation, for streaming video to the host as a standard webcam.
+//!
+//! A single video format, Motion-JPEG or uncompressed YUY2, is offered with one or more frame
+//! sizes. The host negotiates the frame size with the probe/commit controls, then the device
+//! streams frames over an isochronous or bulk endpoint.
+//!
+//! The UVC specification requires interface association descriptors, so the device must be built
+//! with [`Config::composite_with_iads`](crate::Config::composite_with_iads) set.
+
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use crate::descriptor::{SynchronizationType, UsageType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointType};
+use crate::types::InterfaceNumber;
+use crate::{Builder, Handler};
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_VIDEO_CLASS: u8 = 0x0e;
+
+/// Largest max packet size supported.
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+const SC_VIDEOCONTROL: u8 = 0x01;
+const SC_VIDEOSTREAMING: u8 = 0x02;
+const SC_VIDEO_INTERFACE_COLLECTION: u8 = 0x03;
+const PC_PROTOCOL_UNDEFINED: u8 = 0x00;
+
+const CS_INTERFACE: u8 = 0x24;
+
+const VC_HEADER: u8 = 0x01;
+const VC_INPUT_TERMINAL: u8 = 0x02;
+const VC_OUTPUT_TERMINAL: u8 = 0x03;
+const VS_INPUT_HEADER: u8 = 0x01;
+const VS_FORMAT_UNCOMPRESSED: u8 = 0x04;
+const VS_FRAME_UNCOMPRESSED: u8 = 0x05;
+const VS_FORMAT_MJPEG: u8 = 0x06;
+const VS_FRAME_MJPEG: u8 = 0x07;
+const VS_COLORFORMAT: u8 = 0x0d;
+
+const ITT_CAMERA: u16 = 0x0201;
+const TT_STREAMING: u16 = 0x0101;
+
+const CAMERA_TERMINAL_ID: u8 = 1;
+const OUTPUT_TERMINAL_ID: u8 = 2;
+
+const REQ_SET_CUR: u8 = 0x01;
+const REQ_GET_CUR: u8 = 0x81;
+const REQ_GET_MIN: u8 = 0x82;
+const REQ_GET_MAX: u8 = 0x83;
+const REQ_GET_RES: u8 = 0x84;
+const REQ_GET_LEN: u8 = 0x85;
+const REQ_GET_INFO: u8 = 0x86;
+const REQ_GET_DEF: u8 = 0x87;
+
+const VS_PROBE_CONTROL: u8 = 0x01;
+const VS_COMMIT_CONTROL: u8 = 0x02;
+
+/// Length of the video probe and commit controls, as of UVC 1.1.
+const PROBE_LEN: usize = 34;
+/// Length of the video probe and commit controls in UVC 1.0, which some hosts still use.
+const PROBE_LEN_1_0: usize = 26;
+
+/// Frame interval unit used by UVC, in hertz.
+const INTERVAL_UNITS_PER_SECOND: u32 = 10_000_000;
+
+const HEADER_LEN: usize = 2;
+const HEADER_FID: u8 = 0x01;
+const HEADER_EOF: u8 = 0x02;
+const HEADER_EOH: u8 = 0x80;
+
+const GUID_YUY2: [u8; 16] = [
+    0x59, 0x55, 0x59, 0x32, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Video format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Format {
+    /// Motion-JPEG: each frame is a JPEG image.
+    Mjpeg,
+    /// Uncompressed YUY2 (YUV 4:2:2), 2 bytes per pixel.
+    Yuy2,
+}
+
+/// Frame size and rate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    /// Width in pixels.
+    pub width: u16,
+    /// Height in pixels.
+    pub height: u16,
+    /// Frames per second.
+    pub fps: u32,
+}
+
+impl Frame {
+    /// Largest size of a frame in bytes. Motion-JPEG frames are usually much smaller.
+    pub const fn max_size(&self) -> u32 {
+        self.width as u32 * self.height as u32 * 2
+    }
+
+    const fn interval(&self) -> u32 {
+        INTERVAL_UNITS_PER_SECOND / self.fps
+    }
+}
+
+/// Endpoint type used to stream the video.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Transfer {
+    /// Isochronous endpoint, with guaranteed bandwidth but no retries. The host reserves the
+    /// bandwidth only while streaming.
+    Isochronous,
+    /// Bulk endpoint, using the bandwidth left by other devices.
+    Bulk,
+}
+
+/// Configuration for the USB video class.
+pub struct Config<'d> {
+    /// Video format.
+    pub format: Format,
+
+    /// Supported frame sizes. The first one is the default.
+    pub frames: &'d [Frame],
+
+    /// Endpoint type used to stream the video.
+    pub transfer: Transfer,
+
+    /// Max packet size of the streaming endpoint, up to [`MAX_PACKET_SIZE`].
+    pub max_packet_size: u16,
+}
+
+/// Internal state for the USB video class.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    shared: Shared,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: Shared::default(),
+        }
+    }
+}
+
+/// Shared data between Control and UvcClass.
+struct Shared {
+    /// Frame index negotiated with the probe control, from 1.
+    probe: CriticalSectionMutex<Cell<u8>>,
+    /// Frame index committed by the host, from 1, or 0 if not streaming.
+    commit: CriticalSectionMutex<Cell<u8>>,
+    waker: RefCell<WakerRegistration>,
+    changed: AtomicBool,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            probe: CriticalSectionMutex::new(Cell::new(1)),
+            commit: CriticalSectionMutex::new(Cell::new(0)),
+            waker: RefCell::new(WakerRegistration::new()),
+            changed: AtomicBool::new(false),
+        }
+    }
+}
+
+struct Control<'d> {
+    streaming_if: InterfaceNumber,
+    frames: &'d [Frame],
+    max_packet_size: u16,
+    shared: &'d Shared,
+}
+
+impl<'d> Control<'d> {
+    /// Writes the probe/commit control for the given frame index.
+    fn probe_control<'a>(&self, frame_index: u8, buf: &'a mut [u8], len: usize) -> &'a [u8] {
+        let frame = &self.frames[frame_index as usize - 1];
+        let len = len.min(PROBE_LEN);
+        buf[..PROBE_LEN].fill(0);
+        buf[0..2].copy_from_slice(&1u16.to_le_bytes()); // bmHint: frame interval is fixed
+        buf[2] = 1; // bFormatIndex
+        buf[3] = frame_index; // bFrameIndex
+        buf[4..8].copy_from_slice(&frame.interval().to_le_bytes()); // dwFrameInterval
+        buf[18..22].copy_from_slice(&frame.max_size().to_le_bytes()); // dwMaxVideoFrameSize
+        buf[22..26].copy_from_slice(&(self.max_packet_size as u32).to_le_bytes()); // dwMaxPayloadTransferSize
+        buf[26..30].copy_from_slice(&48_000_000u32.to_le_bytes()); // dwClockFrequency
+        buf[30] = 0x03; // bmFramingInfo: FID and EOF are used
+        buf[31] = 1; // bPreferedVersion
+        buf[32] = 1; // bMinVersion
+        buf[33] = 1; // bMaxVersion
+        &buf[..len]
+    }
+}
+
+impl<'d> Handler for Control<'d> {
+    fn reset(&mut self) {
+        self.shared.probe.lock(|x| x.set(1));
+        self.shared.commit.lock(|x| x.set(0));
+    }
+
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        // With isochronous transfers, the host stops streaming by selecting alternate setting 0.
+        if iface == self.streaming_if && alternate_setting == 0 {
+            self.shared.commit.lock(|x| x.set(0));
+        }
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index as u8)
+            != (RequestType::Class, Recipient::Interface, self.streaming_if.0)
+        {
+            return None;
+        }
+
+        let selector = (req.value >> 8) as u8;
+        match (req.request, selector) {
+            (REQ_SET_CUR, VS_PROBE_CONTROL | VS_COMMIT_CONTROL) if data.len() >= PROBE_LEN_1_0 => {
+                // Only the frame size is negotiable, the host's other wishes are ignored.
+                let mut frame_index = data[3];
+                if frame_index == 0 || frame_index as usize > self.frames.len() {
+                    frame_index = 1;
+                }
+                if selector == VS_PROBE_CONTROL {
+                    self.shared.probe.lock(|x| x.set(frame_index));
+                } else {
+                    debug!("uvc: commit frame {}", frame_index);
+                    self.shared.commit.lock(|x| x.set(frame_index));
+                    self.shared.changed.store(true, Ordering::Relaxed);
+                    self.shared.waker.borrow_mut().wake();
+                }
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if (req.request_type, req.recipient, req.index as u8)
+            != (RequestType::Class, Recipient::Interface, self.streaming_if.0)
+        {
+            return None;
+        }
+
+        let selector = (req.value >> 8) as u8;
+        if selector != VS_PROBE_CONTROL && selector != VS_COMMIT_CONTROL {
+            return Some(InResponse::Rejected);
+        }
+
+        let len = req.length as usize;
+        Some(match req.request {
+            REQ_GET_INFO => {
+                buf[0] = 0x03; // supports GET and SET
+                InResponse::Accepted(&buf[..1])
+            }
+            REQ_GET_LEN => {
+                buf[..2].copy_from_slice(&(PROBE_LEN as u16).to_le_bytes());
+                InResponse::Accepted(&buf[..2])
+            }
+            REQ_GET_CUR => {
+                let frame_index = match selector {
+                    VS_PROBE_CONTROL => self.shared.probe.lock(Cell::get),
+                    _ => self.shared.commit.lock(Cell::get).max(1),
+                };
+                InResponse::Accepted(self.probe_control(frame_index, buf, len))
+            }
+            REQ_GET_MIN | REQ_GET_DEF | REQ_GET_RES => InResponse::Accepted(self.probe_control(1, buf, len)),
+            REQ_GET_MAX => InResponse::Accepted(self.probe_control(self.frames.len() as u8, buf, len)),
+            _ => InResponse::Rejected,
+        })
+    }
+}
+
+/// USB video class.
+pub struct UvcClass<'d, D: Driver<'d>> {
+    ep: D::EndpointIn,
+    frames: &'d [Frame],
+    shared: &'d Shared,
+    fid: bool,
+}
+
+impl<'d, D: Driver<'d>> UvcClass<'d, D> {
+    /// Creates a new UvcClass with the provided UsbBus and configuration.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config<'d>) -> Self {
+        assert!(!config.frames.is_empty(), "no frame sizes");
+        assert!(config.frames.len() <= u8::MAX as usize, "too many frame sizes");
+        assert!(
+            config.max_packet_size as usize <= MAX_PACKET_SIZE,
+            "max packet size too large"
+        );
+        assert!(builder.control_buf_len() >= PROBE_LEN);
+
+        let mut func = builder.function(USB_VIDEO_CLASS, SC_VIDEO_INTERFACE_COLLECTION, PC_PROTOCOL_UNDEFINED);
+
+        // Video control interface.
+        let mut iface = func.interface();
+        let control_if = iface.interface_number();
+        let streaming_if = u8::from(control_if) + 1;
+        let mut alt = iface.alt_setting(USB_VIDEO_CLASS, SC_VIDEOCONTROL, PC_PROTOCOL_UNDEFINED, None);
+
+        let total_len: u16 = 13 + 18 + 9;
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                VC_HEADER, // bDescriptorSubtype
+                0x10,
+                0x01, // bcdUVC (1.10)
+                total_len as u8,
+                (total_len >> 8) as u8, // wTotalLength
+                0x00,
+                0x6c,
+                0xdc,
+                0x02,         // dwClockFrequency (48 MHz)
+                0x01,         // bInCollection
+                streaming_if, // baInterfaceNr(1)
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                VC_INPUT_TERMINAL,  // bDescriptorSubtype
+                CAMERA_TERMINAL_ID, // bTerminalID
+                ITT_CAMERA as u8,
+                (ITT_CAMERA >> 8) as u8, // wTerminalType
+                0x00,                    // bAssocTerminal
+                0x00,                    // iTerminal
+                0x00,
+                0x00, // wObjectiveFocalLengthMin
+                0x00,
+                0x00, // wObjectiveFocalLengthMax
+                0x00,
+                0x00, // wOcularFocalLength
+                0x03, // bControlSize
+                0x00,
+                0x00,
+                0x00, // bmControls
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                VC_OUTPUT_TERMINAL, // bDescriptorSubtype
+                OUTPUT_TERMINAL_ID, // bTerminalID
+                TT_STREAMING as u8,
+                (TT_STREAMING >> 8) as u8, // wTerminalType
+                0x00,                      // bAssocTerminal
+                CAMERA_TERMINAL_ID,        // bSourceID
+                0x00,                      // iTerminal
+            ],
+        );
+
+        // Video streaming interface. With isochronous transfers, alternate setting 0 has no
+        // endpoints, so that the host can release the bandwidth when not streaming.
+        let mut iface = func.interface();
+        let streaming_if = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_VIDEO_CLASS, SC_VIDEOSTREAMING, PC_PROTOCOL_UNDEFINED, None);
+        if config.transfer == Transfer::Isochronous {
+            alt = iface.alt_setting(USB_VIDEO_CLASS, SC_VIDEOSTREAMING, PC_PROTOCOL_UNDEFINED, None);
+        }
+        let ep = match config.transfer {
+            Transfer::Isochronous => alt.alloc_endpoint_in(EndpointType::Isochronous, config.max_packet_size, 1),
+            Transfer::Bulk => alt.alloc_endpoint_in(EndpointType::Bulk, config.max_packet_size, 0),
+        };
+
+        let num_frames = config.frames.len();
+        let format_len = match config.format {
+            Format::Mjpeg => 11,
+            Format::Yuy2 => 27,
+        };
+        let vs_total_len = (14 + format_len + 30 * num_frames + 6) as u16;
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                VS_INPUT_HEADER, // bDescriptorSubtype
+                0x01,            // bNumFormats
+                vs_total_len as u8,
+                (vs_total_len >> 8) as u8, // wTotalLength
+                ep.info().addr.into(),     // bEndpointAddress
+                0x00,                      // bmInfo
+                OUTPUT_TERMINAL_ID,        // bTerminalLink
+                0x00,                      // bStillCaptureMethod
+                0x00,                      // bTriggerSupport
+                0x00,                      // bTriggerUsage
+                0x01,                      // bControlSize
+                0x00,                      // bmaControls(1)
+            ],
+        );
+
+        match config.format {
+            Format::Mjpeg => alt.descriptor(
+                CS_INTERFACE,
+                &[
+                    VS_FORMAT_MJPEG,  // bDescriptorSubtype
+                    0x01,             // bFormatIndex
+                    num_frames as u8, // bNumFrameDescriptors
+                    0x01,             // bmFlags: fixed size samples
+                    0x01,             // bDefaultFrameIndex
+                    0x00,             // bAspectRatioX
+                    0x00,             // bAspectRatioY
+                    0x00,             // bmInterlaceFlags
+                    0x00,             // bCopyProtect
+                ],
+            ),
+            Format::Yuy2 => {
+                let mut format = [0; 25];
+                format[0] = VS_FORMAT_UNCOMPRESSED; // bDescriptorSubtype
+                format[1] = 0x01; // bFormatIndex
+                format[2] = num_frames as u8; // bNumFrameDescriptors
+                format[3..19].copy_from_slice(&GUID_YUY2); // guidFormat
+                format[19] = 16; // bBitsPerPixel
+                format[20] = 0x01; // bDefaultFrameIndex
+                alt.descriptor(CS_INTERFACE, &format);
+            }
+        }
+
+        let frame_subtype = match config.format {
+            Format::Mjpeg => VS_FRAME_MJPEG,
+            Format::Yuy2 => VS_FRAME_UNCOMPRESSED,
+        };
+        for (i, frame) in config.frames.iter().enumerate() {
+            let bit_rate = frame.max_size() * 8 * frame.fps;
+            let mut desc = [0; 28];
+            desc[0] = frame_subtype; // bDescriptorSubtype
+            desc[1] = i as u8 + 1; // bFrameIndex
+            desc[2] = 0x00; // bmCapabilities
+            desc[3..5].copy_from_slice(&frame.width.to_le_bytes()); // wWidth
+            desc[5..7].copy_from_slice(&frame.height.to_le_bytes()); // wHeight
+            desc[7..11].copy_from_slice(&bit_rate.to_le_bytes()); // dwMinBitRate
+            desc[11..15].copy_from_slice(&bit_rate.to_le_bytes()); // dwMaxBitRate
+            desc[15..19].copy_from_slice(&frame.max_size().to_le_bytes()); // dwMaxVideoFrameBufferSize
+            desc[19..23].copy_from_slice(&frame.interval().to_le_bytes()); // dwDefaultFrameInterval
+            desc[23] = 1; // bFrameIntervalType: one discrete interval
+            desc[24..28].copy_from_slice(&frame.interval().to_le_bytes()); // dwFrameInterval(1)
+            alt.descriptor(CS_INTERFACE, &desc);
+        }
+
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                VS_COLORFORMAT, // bDescriptorSubtype
+                0x01,           // bColorPrimaries: BT.709, sRGB
+                0x01,           // bTransferCharacteristics: BT.709
+                0x04,           // bMatrixCoefficients: SMPTE 170M
+            ],
+        );
+
+        alt.endpoint_descriptor(
+            ep.info(),
+            SynchronizationType::Asynchronous,
+            UsageType::DataEndpoint,
+            &[],
+        );
+
+        drop(func);
+
+        let control = state.control.write(Control {
+            streaming_if,
+            frames: config.frames,
+            max_packet_size: config.max_packet_size,
+            shared: &state.shared,
+        });
+        builder.handler(control);
+
+        UvcClass {
+            ep,
+            frames: config.frames,
+            shared: &state.shared,
+            fid: false,
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.ep.info().max_packet_size
+    }
+
+    /// Returns the frame size committed by the host, if it is streaming.
+    pub fn frame(&self) -> Option<Frame> {
+        match self.shared.commit.lock(Cell::get) {
+            0 => None,
+            index => Some(self.frames[index as usize - 1]),
+        }
+    }
+
+    /// Waits for the host to start streaming, and returns the frame size it selected.
+    pub async fn wait_streaming(&mut self) -> Frame {
+        loop {
+            self.ep.wait_enabled().await;
+            if let Some(frame) = self.frame() {
+                return frame;
+            }
+            poll_fn(|cx| {
+                if self.shared.changed.load(Ordering::Relaxed) {
+                    self.shared.changed.store(false, Ordering::Relaxed);
+                    Poll::Ready(())
+                } else {
+                    self.shared.waker.borrow_mut().register(cx.waker());
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Sends a video frame.
+    ///
+    /// The frame is split in payloads of one packet each, with a payload header.
+    pub async fn write_frame(&mut self, frame: &[u8]) -> Result<(), EndpointError> {
+        let mut packet = [0; MAX_PACKET_SIZE];
+        let max_payload = self.ep.info().max_packet_size as usize - HEADER_LEN;
+        let fid = if self.fid { HEADER_FID } else { 0 };
+        self.fid = !self.fid;
+
+        let mut chunks = frame.chunks(max_payload).peekable();
+        if chunks.peek().is_none() {
+            packet[..HEADER_LEN].copy_from_slice(&[HEADER_LEN as u8, HEADER_EOH | HEADER_EOF | fid]);
+            return self.ep.write(&packet[..HEADER_LEN]).await;
+        }
+        while let Some(chunk) = chunks.next() {
+            let eof = if chunks.peek().is_none() { HEADER_EOF } else { 0 };
+            packet[0] = HEADER_LEN as u8; // bHeaderLength
+            packet[1] = HEADER_EOH | eof | fid; // bmHeaderInfo
+            packet[HEADER_LEN..][..chunk.len()].copy_from_slice(chunk);
+            self.ep.write(&packet[..HEADER_LEN + chunk.len()]).await?;
+        }
+        Ok(())
+    }
+}
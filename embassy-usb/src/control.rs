@@ -119,6 +119,28 @@ impl Request {
         }
     }
 
+    /// Serializes the request into a SETUP packet, for USB hosts.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let direction = match self.direction {
+            Direction::Out => 0,
+            Direction::In => 0x80,
+        };
+        let rt = direction | (self.request_type as u8) << 5 | (self.recipient as u8 & 0b11111);
+        let [value_lo, value_hi] = self.value.to_le_bytes();
+        let [index_lo, index_hi] = self.index.to_le_bytes();
+        let [length_lo, length_hi] = self.length.to_le_bytes();
+        [
+            rt,
+            self.request,
+            value_lo,
+            value_hi,
+            index_lo,
+            index_hi,
+            length_lo,
+            length_hi,
+        ]
+    }
+
     /// Gets the descriptor type and index from the value field of a GET_DESCRIPTOR request.
     pub const fn descriptor_type_index(&self) -> (u8, u8) {
         ((self.value >> 8) as u8, self.value as u8)
@@ -133,6 +155,10 @@ pub enum OutResponse {
     Accepted,
     /// The request was rejected.
     Rejected,
+    /// The request will be answered later, by [`Handler::poll_deferred_out`](crate::Handler::poll_deferred_out).
+    ///
+    /// The status stage is NAKed until then.
+    Deferred,
 }
 
 /// Response for a CONTROL IN request.
@@ -143,4 +169,8 @@ pub enum InResponse<'a> {
     Accepted(&'a [u8]),
     /// The request was rejected.
     Rejected,
+    /// The request will be answered later, by [`Handler::poll_deferred_in`](crate::Handler::poll_deferred_in).
+    ///
+    /// The data stage is NAKed until then.
+    Deferred,
 }
@@ -3,7 +3,6 @@
 use crate::builder::Config;
 use crate::driver::EndpointInfo;
 use crate::types::{InterfaceNumber, StringIndex};
-use crate::CONFIGURATION_VALUE;
 
 /// Standard descriptor types
 #[allow(missing_docs)]
@@ -36,6 +35,34 @@ pub mod capability_type {
     pub const PLATFORM: u8 = 5;
 }
 
+/// Synchronization type of an isochronous endpoint, written in its descriptor's `bmAttributes`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SynchronizationType {
+    /// No synchronization.
+    NoSynchronization = 0b00,
+    /// The endpoint's clock is not synchronized to the bus.
+    Asynchronous = 0b01,
+    /// The endpoint's clock follows the host.
+    Adaptive = 0b10,
+    /// The endpoint's clock is synchronized to the bus start-of-frame.
+    Synchronous = 0b11,
+}
+
+/// Usage type of an isochronous endpoint, written in its descriptor's `bmAttributes`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum UsageType {
+    /// Data endpoint.
+    DataEndpoint = 0b00,
+    /// Explicit feedback endpoint, reporting the data rate of an asynchronous endpoint.
+    FeedbackEndpoint = 0b01,
+    /// Data endpoint whose data also serves as implicit feedback.
+    ImplicitFeedbackDataEndpoint = 0b10,
+}
+
 /// A writer for USB descriptors.
 pub(crate) struct DescriptorWriter<'a> {
     pub buf: &'a mut [u8],
@@ -54,6 +81,11 @@ impl<'a> DescriptorWriter<'a> {
         }
     }
 
+    /// Returns the whole buffer, not only the part written so far.
+    pub(crate) fn into_full_buf(self) -> &'a mut [u8] {
+        self.buf
+    }
+
     pub fn into_buf(self) -> &'a mut [u8] {
         &mut self.buf[..self.position]
     }
@@ -82,17 +114,17 @@ impl<'a> DescriptorWriter<'a> {
         self.position = start + length;
     }
 
-    pub(crate) fn configuration(&mut self, config: &Config) {
+    pub(crate) fn configuration(&mut self, config: &Config, value: u8) {
         self.num_interfaces_mark = Some(self.position + 4);
 
         self.write(
             descriptor_type::CONFIGURATION,
             &[
                 0,
-                0,                   // wTotalLength
-                0,                   // bNumInterfaces
-                CONFIGURATION_VALUE, // bConfigurationValue
-                0,                   // iConfiguration
+                0,     // wTotalLength
+                0,     // bNumInterfaces
+                value, // bConfigurationValue
+                0,     // iConfiguration
                 0x80 | if config.self_powered { 0x40 } else { 0x00 }
                     | if config.supports_remote_wakeup { 0x20 } else { 0x00 }, // bmAttributes
                 (config.max_power / 2) as u8, // bMaxPower
@@ -105,9 +137,10 @@ impl<'a> DescriptorWriter<'a> {
         self.num_endpoints_mark = None;
     }
 
-    pub(crate) fn end_configuration(&mut self) {
-        let position = self.position as u16;
-        self.buf[2..4].copy_from_slice(&position.to_le_bytes());
+    /// Ends the configuration descriptor that was started at `start`.
+    pub(crate) fn end_configuration(&mut self, start: usize) {
+        let len = (self.position - start) as u16;
+        self.buf[start + 2..start + 4].copy_from_slice(&len.to_le_bytes());
     }
 
     /// Writes a interface association descriptor. Call from `UsbClass::get_configuration_descriptors`
@@ -202,22 +235,37 @@ impl<'a> DescriptorWriter<'a> {
     ///
     /// * `endpoint` - Endpoint previously allocated with
     ///   [`UsbDeviceBuilder`](crate::bus::UsbDeviceBuilder).
-    pub fn endpoint(&mut self, endpoint: &EndpointInfo) {
+    /// * `synchronization_type` - Synchronization type, only meaningful for isochronous endpoints.
+    /// * `usage_type` - Usage type, only meaningful for isochronous endpoints.
+    /// * `extra_fields` - Fields appended to the standard ones, for classes that use longer endpoint
+    ///   descriptors such as USB Audio Class 1.0.
+    pub fn endpoint(
+        &mut self,
+        endpoint: &EndpointInfo,
+        synchronization_type: SynchronizationType,
+        usage_type: UsageType,
+        extra_fields: &[u8],
+    ) {
         match self.num_endpoints_mark {
             Some(mark) => self.buf[mark] += 1,
             None => panic!("you can only call `endpoint` after `interface/interface_alt`."),
         };
 
-        self.write(
-            descriptor_type::ENDPOINT,
-            &[
-                endpoint.addr.into(),   // bEndpointAddress
-                endpoint.ep_type as u8, // bmAttributes
-                endpoint.max_packet_size as u8,
-                (endpoint.max_packet_size >> 8) as u8, // wMaxPacketSize
-                endpoint.interval_ms,                  // bInterval
-            ],
-        );
+        const STANDARD_FIELDS_LEN: usize = 5;
+        let mut descriptor = [0; 16];
+        let len = STANDARD_FIELDS_LEN + extra_fields.len();
+        assert!(len <= descriptor.len(), "endpoint descriptor too long");
+
+        descriptor[..STANDARD_FIELDS_LEN].copy_from_slice(&[
+            endpoint.addr.into(), // bEndpointAddress
+            (usage_type as u8) << 4 | (synchronization_type as u8) << 2 | endpoint.ep_type as u8, // bmAttributes
+            endpoint.max_packet_size as u8,
+            (endpoint.max_packet_size >> 8) as u8, // wMaxPacketSize
+            endpoint.interval_ms,                  // bInterval
+        ]);
+        descriptor[STANDARD_FIELDS_LEN..len].copy_from_slice(extra_fields);
+
+        self.write(descriptor_type::ENDPOINT, &descriptor[..len]);
     }
 
     /// Writes a string descriptor.
@@ -249,7 +297,7 @@ impl<'a> DescriptorWriter<'a> {
 ///
 /// All device descriptors are always 18 bytes, so there's no need for
 /// a variable-length buffer or DescriptorWriter.
-pub(crate) fn device_descriptor(config: &Config) -> [u8; 18] {
+pub(crate) fn device_descriptor(config: &Config, num_configurations: u8) -> [u8; 18] {
     [
         18,   // bLength
         0x01, // bDescriptorType
@@ -268,7 +316,7 @@ pub(crate) fn device_descriptor(config: &Config) -> [u8; 18] {
         config.manufacturer.map_or(0, |_| 1),  // iManufacturer
         config.product.map_or(0, |_| 2),       // iProduct
         config.serial_number.map_or(0, |_| 3), // iSerialNumber
-        1,                                     // bNumConfigurations
+        num_configurations,                    // bNumConfigurations
     ]
 }
 
@@ -0,0 +1,308 @@
+//! USB host enumeration, on top of a [`HostDriver`].
+//!
+//! [`enumerate`] resets a newly attached device, gives it an address and reads its device
+//! descriptor. The class driver then reads the configuration descriptor with
+//! [`Device::get_configuration_descriptor`], looks for its interface and endpoints with
+//! [`foreach_endpoint`], and selects the configuration with [`Device::set_configuration`].
+//!
+//! ```ignore
+//! loop {
+//!     if let DeviceEvent::Connected(speed) = host.wait_for_device_event().await {
+//!         let mut device = enumerate(&mut host, speed, 1).await?;
+//!         let mut buf = [0; 256];
+//!         let config = device.get_configuration_descriptor(&mut host, 0, &mut buf).await?;
+//!         foreach_endpoint(config, |interface, endpoint| { /* find the endpoints of the class */ })?;
+//!         device.set_configuration(&mut host, config[5]).await?;
+//!     }
+//! }
+//! ```
+//!
+//! Requires the `time` feature, for the delays of the enumeration.
+
+use embassy_time::Timer;
+
+use crate::control::{Recipient, Request, RequestType};
+use crate::descriptor::descriptor_type;
+use crate::descriptor_reader::{ReadError, Reader};
+use crate::driver::host::{Channel, HostDriver, HostError, Speed};
+use crate::driver::{Direction, EndpointAddress, EndpointInfo, EndpointType};
+
+/// Errors returned during enumeration.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EnumerationError {
+    /// A transfer failed.
+    Host(HostError),
+    /// The device returned an invalid descriptor.
+    InvalidDescriptor,
+    /// The configuration descriptor doesn't fit in the buffer.
+    BufferTooSmall,
+}
+
+impl From<HostError> for EnumerationError {
+    fn from(e: HostError) -> Self {
+        Self::Host(e)
+    }
+}
+
+impl From<ReadError> for EnumerationError {
+    fn from(_: ReadError) -> Self {
+        Self::InvalidDescriptor
+    }
+}
+
+/// Device descriptor of an attached device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceDescriptor {
+    /// USB version, in BCD.
+    pub usb_version: u16,
+    /// Device class, or 0 if the class is given by the interfaces.
+    pub class: u8,
+    /// Device subclass.
+    pub subclass: u8,
+    /// Device protocol.
+    pub protocol: u8,
+    /// Max packet size of the default control endpoint.
+    pub max_packet_size_0: u8,
+    /// Vendor ID.
+    pub vendor_id: u16,
+    /// Product ID.
+    pub product_id: u16,
+    /// Device release number, in BCD.
+    pub device_release: u16,
+    /// Index of the manufacturer string.
+    pub manufacturer: u8,
+    /// Index of the product string.
+    pub product: u8,
+    /// Index of the serial number string.
+    pub serial_number: u8,
+    /// Number of configurations.
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    /// Length of a device descriptor.
+    pub const LEN: usize = 18;
+
+    /// Parse a device descriptor.
+    pub fn parse(data: &[u8]) -> Result<Self, EnumerationError> {
+        let mut r = Reader::new(data);
+        let [len, kind] = r.read()?;
+        if (len as usize) < Self::LEN || kind != descriptor_type::DEVICE {
+            return Err(EnumerationError::InvalidDescriptor);
+        }
+        Ok(Self {
+            usb_version: r.read_u16()?,
+            class: r.read_u8()?,
+            subclass: r.read_u8()?,
+            protocol: r.read_u8()?,
+            max_packet_size_0: r.read_u8()?,
+            vendor_id: r.read_u16()?,
+            product_id: r.read_u16()?,
+            device_release: r.read_u16()?,
+            manufacturer: r.read_u8()?,
+            product: r.read_u8()?,
+            serial_number: r.read_u8()?,
+            num_configurations: r.read_u8()?,
+        })
+    }
+}
+
+/// Interface descriptor, given to the callback of [`foreach_endpoint`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceDescriptor {
+    /// Interface number.
+    pub number: u8,
+    /// Alternate setting.
+    pub alternate_setting: u8,
+    /// Interface class.
+    pub class: u8,
+    /// Interface subclass.
+    pub subclass: u8,
+    /// Interface protocol.
+    pub protocol: u8,
+}
+
+/// Call `f` for each endpoint of a configuration descriptor, with the interface it belongs to.
+///
+/// The endpoint information is ready to create a [`Channel`] to the endpoint.
+pub fn foreach_endpoint(
+    config: &[u8],
+    mut f: impl FnMut(&InterfaceDescriptor, EndpointInfo),
+) -> Result<(), EnumerationError> {
+    let mut interface = None;
+    for res in Reader::new(config).read_descriptors() {
+        let (kind, mut r) = res?;
+        match kind {
+            descriptor_type::INTERFACE => {
+                let [number, alternate_setting, _num_endpoints, class, subclass, protocol] = r.read()?;
+                interface = Some(InterfaceDescriptor {
+                    number,
+                    alternate_setting,
+                    class,
+                    subclass,
+                    protocol,
+                });
+            }
+            descriptor_type::ENDPOINT => {
+                let interface = interface.as_ref().ok_or(EnumerationError::InvalidDescriptor)?;
+                let addr = EndpointAddress::from(r.read_u8()?);
+                let ep_type = match r.read_u8()? & 0b11 {
+                    0b00 => EndpointType::Control,
+                    0b01 => EndpointType::Isochronous,
+                    0b10 => EndpointType::Bulk,
+                    _ => EndpointType::Interrupt,
+                };
+                let max_packet_size = r.read_u16()? & 0x7ff;
+                let interval_ms = r.read_u8()?;
+                f(
+                    interface,
+                    EndpointInfo {
+                        addr,
+                        ep_type,
+                        max_packet_size,
+                        interval_ms,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// An enumerated device.
+pub struct Device {
+    /// Address of the device.
+    pub address: u8,
+    /// Speed of the device.
+    pub speed: Speed,
+    /// Device descriptor.
+    pub descriptor: DeviceDescriptor,
+    /// Channel to the default control endpoint.
+    pub control: Channel,
+}
+
+impl Device {
+    /// Read a descriptor into `buf`, returning its length.
+    pub async fn get_descriptor<H: HostDriver>(
+        &mut self,
+        host: &mut H,
+        descriptor_type: u8,
+        index: u8,
+        language_id: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        get_descriptor(host, &mut self.control, descriptor_type, index, language_id, buf).await
+    }
+
+    /// Read the full configuration descriptor with the given index, with its interface and endpoint
+    /// descriptors.
+    pub async fn get_configuration_descriptor<'b, H: HostDriver>(
+        &mut self,
+        host: &mut H,
+        index: u8,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], EnumerationError> {
+        let mut header = [0; 9];
+        let n = self
+            .get_descriptor(host, descriptor_type::CONFIGURATION, index, 0, &mut header)
+            .await?;
+        if n < 4 || header[1] != descriptor_type::CONFIGURATION {
+            return Err(EnumerationError::InvalidDescriptor);
+        }
+        let total_length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let buf = buf.get_mut(..total_length).ok_or(EnumerationError::BufferTooSmall)?;
+        let n = self
+            .get_descriptor(host, descriptor_type::CONFIGURATION, index, 0, buf)
+            .await?;
+        Ok(&buf[..n])
+    }
+
+    /// Select a configuration, by its value in the configuration descriptor.
+    ///
+    /// The data toggles of the channels to the endpoints of the device must be reset afterwards.
+    pub async fn set_configuration<H: HostDriver>(&mut self, host: &mut H, value: u8) -> Result<(), HostError> {
+        let req = Request {
+            direction: Direction::Out,
+            request_type: RequestType::Standard,
+            recipient: Recipient::Device,
+            request: Request::SET_CONFIGURATION,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        };
+        host.control_out(&mut self.control, &req.to_bytes(), &[]).await
+    }
+}
+
+async fn get_descriptor<H: HostDriver>(
+    host: &mut H,
+    control: &mut Channel,
+    descriptor_type: u8,
+    index: u8,
+    language_id: u16,
+    buf: &mut [u8],
+) -> Result<usize, HostError> {
+    let req = Request {
+        direction: Direction::In,
+        request_type: RequestType::Standard,
+        recipient: Recipient::Device,
+        request: Request::GET_DESCRIPTOR,
+        value: (descriptor_type as u16) << 8 | index as u16,
+        index: language_id,
+        length: buf.len() as u16,
+    };
+    host.control_in(control, &req.to_bytes(), buf).await
+}
+
+/// Enumerate a newly attached device, giving it `address`.
+///
+/// The device must be the only one answering on address 0, so devices must be enumerated one at a
+/// time.
+pub async fn enumerate<H: HostDriver>(host: &mut H, speed: Speed, address: u8) -> Result<Device, EnumerationError> {
+    assert!(address > 0 && address < 128);
+
+    // Wait for the device to be stable after the attachment (USB 2.0, 9.1.2).
+    Timer::after_millis(100).await;
+    host.bus_reset().await;
+
+    // Read the max packet size of the default control endpoint first, with the smallest one.
+    let mut control = Channel::control(0, 8);
+    let mut buf = [0; DeviceDescriptor::LEN];
+    let n = get_descriptor(host, &mut control, descriptor_type::DEVICE, 0, 0, &mut buf[..8]).await?;
+    if n < 8 || buf[1] != descriptor_type::DEVICE {
+        return Err(EnumerationError::InvalidDescriptor);
+    }
+    let max_packet_size_0 = buf[7];
+    if !matches!(max_packet_size_0, 8 | 16 | 32 | 64) {
+        return Err(EnumerationError::InvalidDescriptor);
+    }
+
+    let mut control = Channel::control(0, max_packet_size_0 as u16);
+    let req = Request {
+        direction: Direction::Out,
+        request_type: RequestType::Standard,
+        recipient: Recipient::Device,
+        request: Request::SET_ADDRESS,
+        value: address as u16,
+        index: 0,
+        length: 0,
+    };
+    host.control_out(&mut control, &req.to_bytes(), &[]).await?;
+    // SetAddress recovery interval (USB 2.0, 9.2.6.3).
+    Timer::after_millis(2).await;
+
+    let mut control = Channel::control(address, max_packet_size_0 as u16);
+    let n = get_descriptor(host, &mut control, descriptor_type::DEVICE, 0, 0, &mut buf).await?;
+    let descriptor = DeviceDescriptor::parse(&buf[..n])?;
+
+    Ok(Device {
+        address,
+        speed,
+        descriptor,
+        control,
+    })
+}
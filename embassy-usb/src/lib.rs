@@ -12,24 +12,31 @@ pub mod class;
 pub mod control;
 pub mod descriptor;
 mod descriptor_reader;
+#[cfg(feature = "time")]
+pub mod host;
 pub mod msos;
+pub mod power;
 pub mod types;
+pub mod vendor;
 
 mod config {
     #![allow(unused)]
     include!(concat!(env!("OUT_DIR"), "/config.rs"));
 }
 
-use embassy_futures::select::{select, Either};
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+use embassy_futures::select::{select, select4, Either, Either4};
 use heapless::Vec;
 
 pub use crate::builder::{Builder, Config, FunctionBuilder, InterfaceAltBuilder, InterfaceBuilder};
-use crate::config::{MAX_HANDLER_COUNT, MAX_INTERFACE_COUNT};
+use crate::config::{MAX_CONFIGURATION_COUNT, MAX_HANDLER_COUNT, MAX_INTERFACE_COUNT};
 use crate::control::{InResponse, OutResponse, Recipient, Request, RequestType};
 use crate::descriptor::{descriptor_type, lang_id};
 use crate::descriptor_reader::foreach_endpoint;
 use crate::driver::{Bus, ControlPipe, Direction, Driver, EndpointAddress, Event};
-use crate::types::{InterfaceNumber, StringIndex};
+use crate::types::{FunctionNumber, InterfaceNumber, StringIndex};
 
 /// The global state of the USB device.
 ///
@@ -58,8 +65,11 @@ pub enum UsbDeviceState {
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RemoteWakeupError {
-    /// The USB device is not suspended, or remote wakeup was not enabled.
+    /// The USB device is not suspended.
     InvalidState,
+    /// The host did not enable remote wakeup, or the device doesn't advertise it in
+    /// [`Config::supports_remote_wakeup`].
+    NotEnabled,
     /// The underlying driver doesn't support remote wakeup.
     Unsupported,
 }
@@ -73,9 +83,15 @@ impl From<driver::Unsupported> for RemoteWakeupError {
 /// The bConfiguration value for the not configured state.
 pub const CONFIGURATION_NONE: u8 = 0;
 
-/// The bConfiguration value for the single configuration supported by this device.
+/// The bConfiguration value for the first configuration of this device.
+///
+/// Configurations added with [`Builder::configuration`] get the following values.
 pub const CONFIGURATION_VALUE: u8 = 1;
 
+/// Time after which a deferred control request is rejected.
+#[cfg(feature = "time")]
+const DEFERRED_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_secs(5);
+
 const STRING_INDEX_MANUFACTURER: u8 = 1;
 const STRING_INDEX_PRODUCT: u8 = 2;
 const STRING_INDEX_SERIAL_NUMBER: u8 = 3;
@@ -98,7 +114,8 @@ pub trait Handler {
     /// Called when the host has enabled or disabled the configuration of the device.
     fn configured(&mut self, _configured: bool) {}
 
-    /// Called when the bus has entered or exited the suspend state.
+    /// Called when the bus has entered or exited the suspend state, including after a remote wakeup
+    /// by [`UsbDevice::remote_wakeup`].
     fn suspended(&mut self, _suspended: bool) {}
 
     /// Called when remote wakeup feature is enabled or disabled.
@@ -152,6 +169,37 @@ pub trait Handler {
         None
     }
 
+    /// Polls for the response to a control request deferred by [`control_out`](Self::control_out).
+    ///
+    /// This is called after `control_out` returned `Some(OutResponse::Deferred)`, until it returns
+    /// `Poll::Ready`. It allows handling requests that need to wait for something, such as a flash
+    /// write.
+    ///
+    /// The request is abandoned, and [`cancel_deferred`](Self::cancel_deferred) called, if the host
+    /// sends another control request or the bus is reset or suspended in the meantime. With the
+    /// `time` feature, it is also rejected if the response takes more than 5 seconds, after which
+    /// hosts usually give up.
+    fn poll_deferred_out(&mut self, cx: &mut Context<'_>) -> Poll<OutResponse> {
+        let _ = cx;
+        Poll::Ready(OutResponse::Rejected)
+    }
+
+    /// Polls for the response to a control request deferred by [`control_in`](Self::control_in).
+    ///
+    /// This is called after `control_in` returned `Some(InResponse::Deferred)`, until it returns
+    /// `Poll::Ready`. Return `Some(len)` to accept the request with the response written to `buf[..len]`,
+    /// or `None` to reject it. See [`poll_deferred_out`](Self::poll_deferred_out).
+    fn poll_deferred_in(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Option<usize>> {
+        let _ = (cx, buf);
+        Poll::Ready(None)
+    }
+
+    /// Called when the control request deferred by this handler is abandoned before its response is
+    /// ready, see [`poll_deferred_out`](Self::poll_deferred_out).
+    ///
+    /// The `poll_deferred_*` methods aren't called for this request anymore.
+    fn cancel_deferred(&mut self) {}
+
     /// Called when a GET_DESCRIPTOR STRING control request is received.
     fn get_string(&mut self, index: StringIndex, lang_id: u16) -> Option<&str> {
         let _ = (index, lang_id);
@@ -164,6 +212,42 @@ struct Interface {
     num_alt_settings: u8,
 }
 
+#[derive(Clone, Copy)]
+struct Configuration {
+    /// Range of the configuration in the descriptors written by the builder.
+    start: usize,
+    end: usize,
+    /// Range of the configuration descriptor sent to the host, without the disabled functions.
+    active_start: usize,
+    active_end: usize,
+    /// Index of the configuration's first interface in `interfaces`.
+    first_interface: usize,
+    num_interfaces: usize,
+}
+
+impl Configuration {
+    const fn new(start: usize, first_interface: usize) -> Self {
+        Self {
+            start,
+            end: start,
+            active_start: start,
+            active_end: start,
+            first_interface,
+            num_interfaces: 0,
+        }
+    }
+}
+
+struct Function {
+    /// Index of the function's configuration.
+    configuration: usize,
+    /// Range of the function in the descriptors written by the builder.
+    start: usize,
+    end: usize,
+    num_interfaces: u8,
+    enabled: bool,
+}
+
 /// A report of the used size of the runtime allocated buffers
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -182,6 +266,8 @@ pub struct UsbBufferReport {
 pub struct UsbDevice<'d, D: Driver<'d>> {
     control_buf: &'d mut [u8],
     control: D::ControlPipe,
+    /// SETUP packet received while waiting for a deferred response, to be handled next.
+    pending_setup: Option<[u8; 8]>,
     inner: Inner<'d, D>,
 }
 
@@ -190,7 +276,10 @@ struct Inner<'d, D: Driver<'d>> {
 
     config: Config<'d>,
     device_descriptor: [u8; 18],
-    config_descriptor: &'d [u8],
+    /// Descriptors written by the builder, followed by the copy without the disabled functions
+    /// if there are any.
+    config_descriptor: &'d mut [u8],
+    config_descriptor_len: usize,
     bos_descriptor: &'d [u8],
     msos_descriptor: crate::msos::MsOsDescriptorSet<'d>,
 
@@ -205,9 +294,17 @@ struct Inner<'d, D: Driver<'d>> {
     /// This flag indicates that requests must be handled by `ControlPipe::accept_set_address()`
     /// instead of regular `accept()`.
     set_address_pending: bool,
+    /// The bConfigurationValue selected by the host, or `CONFIGURATION_NONE`.
+    configuration: u8,
 
     interfaces: Vec<Interface, MAX_INTERFACE_COUNT>,
+    configurations: Vec<Configuration, MAX_CONFIGURATION_COUNT>,
+    functions: Vec<Function, MAX_INTERFACE_COUNT>,
     handlers: Vec<&'d mut dyn Handler, MAX_HANDLER_COUNT>,
+    /// Index of the configuration of each handler.
+    handler_configurations: Vec<usize, MAX_HANDLER_COUNT>,
+    /// Index of the handler that deferred the current control request.
+    deferred_handler: usize,
 }
 
 impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
@@ -215,25 +312,31 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
         driver: D,
         config: Config<'d>,
         handlers: Vec<&'d mut dyn Handler, MAX_HANDLER_COUNT>,
-        config_descriptor: &'d [u8],
+        handler_configurations: Vec<usize, MAX_HANDLER_COUNT>,
+        config_descriptor: &'d mut [u8],
         bos_descriptor: &'d [u8],
         msos_descriptor: crate::msos::MsOsDescriptorSet<'d>,
         interfaces: Vec<Interface, MAX_INTERFACE_COUNT>,
+        configurations: Vec<Configuration, MAX_CONFIGURATION_COUNT>,
+        functions: Vec<Function, MAX_INTERFACE_COUNT>,
         control_buf: &'d mut [u8],
     ) -> UsbDevice<'d, D> {
         // Start the USB bus.
         // This prevent further allocation by consuming the driver.
         let (bus, control) = driver.start(config.max_packet_size_0 as u16);
-        let device_descriptor = descriptor::device_descriptor(&config);
+        let device_descriptor = descriptor::device_descriptor(&config, configurations.len() as u8);
+        let config_descriptor_len = unwrap!(configurations.last()).end;
 
-        Self {
+        let mut this = Self {
             control_buf,
             control,
+            pending_setup: None,
             inner: Inner {
                 bus,
                 config,
                 device_descriptor,
                 config_descriptor,
+                config_descriptor_len,
                 bos_descriptor,
                 msos_descriptor,
 
@@ -243,10 +346,17 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
                 self_powered: false,
                 address: 0,
                 set_address_pending: false,
+                configuration: CONFIGURATION_NONE,
                 interfaces,
+                configurations,
+                functions,
                 handlers,
+                handler_configurations,
+                deferred_handler: 0,
             },
-        }
+        };
+        this.inner.update_active_descriptors();
+        this
     }
 
     /// Returns a report of the consumed buffers
@@ -254,7 +364,7 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
     /// Useful for tuning buffer sizes for actual usage
     pub fn buffer_usage(&self) -> UsbBufferReport {
         UsbBufferReport {
-            config_descriptor_used: self.inner.config_descriptor.len(),
+            config_descriptor_used: unwrap!(self.inner.configurations.last()).active_end,
             bos_descriptor_used: self.inner.bos_descriptor.len(),
             msos_descriptor_used: self.inner.msos_descriptor.len(),
             control_buffer_size: self.control_buf.len(),
@@ -282,6 +392,11 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
     /// peripheral.
     pub async fn run_until_suspend(&mut self) {
         while !self.inner.suspended {
+            if let Some(req) = self.pending_setup.take() {
+                self.handle_control(req).await;
+                continue;
+            }
+
             let control_fut = self.control.setup();
             let bus_fut = self.inner.bus.poll();
             match select(bus_fut, control_fut).await {
@@ -305,6 +420,52 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
         }
     }
 
+    /// Enables or disables a function.
+    ///
+    /// Disabled functions are left out of the configuration descriptor, so the host doesn't see
+    /// them, and their endpoints are never enabled. If the device is enabled, it is disconnected
+    /// from the bus and connected again, so that the host enumerates it again.
+    ///
+    /// Leaving out a function that isn't the last one of its configuration leaves a gap in the
+    /// interface numbers, which some hosts don't accept. Functions that can be disabled should be
+    /// added last.
+    ///
+    /// The configuration descriptor buffer passed to the [`Builder`] must have room for a second
+    /// copy of the descriptors, without the disabled functions.
+    pub async fn set_function_enabled(&mut self, function: FunctionNumber, enabled: bool) {
+        let Some(f) = self.inner.functions.get_mut(function.0 as usize) else {
+            panic!("embassy-usb: invalid function number");
+        };
+        if f.enabled == enabled {
+            return;
+        }
+        f.enabled = enabled;
+        self.inner.update_active_descriptors();
+
+        if matches!(
+            self.inner.device_state,
+            UsbDeviceState::Unpowered | UsbDeviceState::Disabled
+        ) {
+            return;
+        }
+
+        if self.inner.device_state == UsbDeviceState::Configured {
+            let inner = &mut self.inner;
+            let cfg = inner.current_configuration_index();
+            for h in handlers_of(&mut inner.handlers, &inner.handler_configurations, cfg) {
+                h.configured(false);
+            }
+        }
+        self.disable().await;
+
+        self.inner.bus.enable().await;
+        self.inner.device_state = UsbDeviceState::Default;
+        self.inner.configuration = CONFIGURATION_NONE;
+        for h in &mut self.inner.handlers {
+            h.enabled(true);
+        }
+    }
+
     /// Waits for a resume condition on the USB bus.
     ///
     /// This future is cancel-safe.
@@ -317,14 +478,19 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
 
     /// Initiates a device remote wakeup on the USB bus.
     ///
-    /// If the bus is not suspended or remote wakeup is not enabled, an error
-    /// will be returned.
+    /// If the bus is not suspended or the host did not enable remote wakeup, an
+    /// error will be returned. The host can only enable it if
+    /// [`Config::supports_remote_wakeup`] is set.
     ///
     /// This future may leave the bus in an inconsistent state if dropped.
     /// After dropping the future, [`UsbDevice::disable()`] should be called
     /// before calling any other `UsbDevice` methods to fully reset the peripheral.
     pub async fn remote_wakeup(&mut self) -> Result<(), RemoteWakeupError> {
-        if self.inner.suspended && self.inner.remote_wakeup_enabled {
+        if !self.inner.suspended {
+            Err(RemoteWakeupError::InvalidState)
+        } else if !self.inner.remote_wakeup_enabled {
+            Err(RemoteWakeupError::NotEnabled)
+        } else {
             self.inner.bus.remote_wakeup().await?;
             self.inner.suspended = false;
 
@@ -333,8 +499,6 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
             }
 
             Ok(())
-        } else {
-            Err(RemoteWakeupError::InvalidState)
         }
     }
 
@@ -364,7 +528,16 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
             resp_length = max_packet_size;
         }
 
-        match self.inner.handle_control_in(req, self.control_buf) {
+        let resp = match self.inner.handle_control_in(req, self.control_buf) {
+            InResponse::Deferred => match self.wait_deferred(|h, buf, cx| h.poll_deferred_in(cx, buf)).await {
+                Some(Some(len)) => InResponse::Accepted(&self.control_buf[..len]),
+                Some(None) => InResponse::Rejected,
+                None => return,
+            },
+            resp => resp,
+        };
+
+        match resp {
             InResponse::Accepted(data) => {
                 let len = data.len().min(resp_length);
                 let need_zlp = len != resp_length && (len % max_packet_size) == 0;
@@ -383,7 +556,7 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
                     }
                 }
             }
-            InResponse::Rejected => self.control.reject().await,
+            InResponse::Rejected | InResponse::Deferred => self.control.reject().await,
         }
     }
 
@@ -423,7 +596,15 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
         #[cfg(not(feature = "defmt"))]
         trace!("  control out data: {:02x?}", data);
 
-        match self.inner.handle_control_out(req, data) {
+        let resp = match self.inner.handle_control_out(req, data) {
+            OutResponse::Deferred => match self.wait_deferred(|h, _, cx| h.poll_deferred_out(cx)).await {
+                Some(resp) => resp,
+                None => return,
+            },
+            resp => resp,
+        };
+
+        match resp {
             OutResponse::Accepted => {
                 if self.inner.set_address_pending {
                     self.control.accept_set_address(self.inner.address).await;
@@ -432,9 +613,58 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
                     self.control.accept().await;
                 }
             }
-            OutResponse::Rejected => self.control.reject().await,
+            OutResponse::Rejected | OutResponse::Deferred => self.control.reject().await,
         }
     }
+
+    /// Waits for the response to a deferred control request, with `poll` polling the handler that
+    /// deferred it.
+    ///
+    /// Returns `None` if the request was abandoned, after handling the event that abandoned it.
+    async fn wait_deferred<R>(
+        &mut self,
+        mut poll: impl FnMut(&mut dyn Handler, &mut [u8], &mut Context<'_>) -> Poll<R>,
+    ) -> Option<R> {
+        let h = &mut *self.inner.handlers[self.inner.deferred_handler];
+        let buf = &mut *self.control_buf;
+        let response_fut = poll_fn(|cx| poll(h, buf, cx));
+        let control_fut = self.control.setup();
+        let bus_fut = self.inner.bus.poll();
+        #[cfg(feature = "time")]
+        let timeout_fut = embassy_time::Timer::after(DEFERRED_TIMEOUT);
+        #[cfg(not(feature = "time"))]
+        let timeout_fut = core::future::pending::<()>();
+
+        enum AbandonedBy {
+            Setup([u8; 8]),
+            Bus(Event),
+            Timeout,
+        }
+
+        let abandoned_by = match select4(response_fut, control_fut, bus_fut, timeout_fut).await {
+            Either4::First(resp) => return Some(resp),
+            Either4::Second(req) => AbandonedBy::Setup(req),
+            Either4::Third(evt) => AbandonedBy::Bus(evt),
+            Either4::Fourth(()) => AbandonedBy::Timeout,
+        };
+
+        self.inner.handlers[self.inner.deferred_handler].cancel_deferred();
+        match abandoned_by {
+            AbandonedBy::Setup(req) => {
+                debug!("deferred control request abandoned: new SETUP");
+                self.pending_setup = Some(req);
+            }
+            AbandonedBy::Bus(evt) => {
+                debug!("deferred control request abandoned: bus event");
+                self.inner.handle_bus_event(evt).await;
+            }
+            AbandonedBy::Timeout => {
+                warn!("deferred control request timed out, rejecting");
+                self.control.reject().await;
+            }
+        }
+        None
+    }
 }
 
 impl<'d, D: Driver<'d>> Inner<'d, D> {
@@ -446,16 +676,23 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 self.suspended = false;
                 self.remote_wakeup_enabled = false;
                 self.address = 0;
+                self.configuration = CONFIGURATION_NONE;
 
                 for h in &mut self.handlers {
                     h.reset();
                 }
 
-                for (i, iface) in self.interfaces.iter_mut().enumerate() {
-                    iface.current_alt_setting = 0;
+                for (index, cfg) in self.configurations.iter().enumerate() {
+                    let ifaces = &mut self.interfaces[cfg.first_interface..][..cfg.num_interfaces];
+                    for iface in ifaces.iter_mut() {
+                        iface.current_alt_setting = 0;
+                    }
 
-                    for h in &mut self.handlers {
-                        h.set_alternate_setting(InterfaceNumber::new(i as _), 0);
+                    // Interface numbers start from 0 in every configuration.
+                    for h in handlers_of(&mut self.handlers, &self.handler_configurations, index) {
+                        for i in 0..cfg.num_interfaces {
+                            h.set_alternate_setting(InterfaceNumber::new(i as _), 0);
+                        }
                     }
                 }
             }
@@ -496,7 +733,6 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
 
     fn handle_control_out(&mut self, req: Request, data: &[u8]) -> OutResponse {
         const CONFIGURATION_NONE_U16: u16 = CONFIGURATION_NONE as u16;
-        const CONFIGURATION_VALUE_U16: u16 = CONFIGURATION_VALUE as u16;
 
         match (req.request_type, req.recipient) {
             (RequestType::Standard, Recipient::Device) => match (req.request, req.value) {
@@ -508,6 +744,10 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                     OutResponse::Accepted
                 }
                 (Request::SET_FEATURE, Request::FEATURE_DEVICE_REMOTE_WAKEUP) => {
+                    if !self.config.supports_remote_wakeup {
+                        // The configuration descriptor doesn't advertise it.
+                        return OutResponse::Rejected;
+                    }
                     self.remote_wakeup_enabled = true;
                     for h in &mut self.handlers {
                         h.remote_wakeup_enabled(true);
@@ -523,48 +763,68 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                     }
                     OutResponse::Accepted
                 }
-                (Request::SET_CONFIGURATION, CONFIGURATION_VALUE_U16) => {
-                    debug!("SET_CONFIGURATION: configured");
+                (Request::SET_CONFIGURATION, CONFIGURATION_NONE_U16) => {
+                    if self.device_state != UsbDeviceState::Default {
+                        debug!("SET_CONFIGURATION: unconfigured");
+                        self.disable_configuration_endpoints();
+                        let was_configured = self.device_state == UsbDeviceState::Configured;
+                        let cfg = self.current_configuration_index();
+                        self.device_state = UsbDeviceState::Addressed;
+                        self.configuration = CONFIGURATION_NONE;
+
+                        // Notify handlers.
+                        if was_configured {
+                            for h in handlers_of(&mut self.handlers, &self.handler_configurations, cfg) {
+                                h.configured(false);
+                            }
+                        }
+                    }
+                    OutResponse::Accepted
+                }
+                (Request::SET_CONFIGURATION, value) if (value as usize) <= self.configurations.len() => {
+                    debug!("SET_CONFIGURATION: configured {}", value);
+
+                    // Endpoints of the previously selected configuration may not be part of the new one.
+                    if self.configuration != value as u8 {
+                        self.disable_configuration_endpoints();
+
+                        if self.device_state == UsbDeviceState::Configured {
+                            let cfg = self.current_configuration_index();
+                            for h in handlers_of(&mut self.handlers, &self.handler_configurations, cfg) {
+                                h.configured(false);
+                            }
+                        }
+                    }
                     self.device_state = UsbDeviceState::Configured;
+                    self.configuration = value as u8;
 
                     // Enable all endpoints of selected alt settings.
-                    foreach_endpoint(self.config_descriptor, |ep| {
-                        let iface = &self.interfaces[ep.interface.0 as usize];
-                        self.bus
-                            .endpoint_set_enabled(ep.ep_address, iface.current_alt_setting == ep.interface_alt);
+                    let cfg = self.current_configuration();
+                    let interfaces = &self.interfaces[cfg.first_interface..][..cfg.num_interfaces];
+                    foreach_endpoint(&self.config_descriptor[cfg.active_start..cfg.active_end], |ep| {
+                        let enabled = interfaces
+                            .get(ep.interface.0 as usize)
+                            .is_some_and(|iface| iface.current_alt_setting == ep.interface_alt);
+                        self.bus.endpoint_set_enabled(ep.ep_address, enabled);
                     })
                     .unwrap();
 
                     // Notify handlers.
-                    for h in &mut self.handlers {
+                    let cfg = self.current_configuration_index();
+                    for h in handlers_of(&mut self.handlers, &self.handler_configurations, cfg) {
                         h.configured(true);
                     }
 
                     OutResponse::Accepted
                 }
-                (Request::SET_CONFIGURATION, CONFIGURATION_NONE_U16) => {
-                    if self.device_state != UsbDeviceState::Default {
-                        debug!("SET_CONFIGURATION: unconfigured");
-                        self.device_state = UsbDeviceState::Addressed;
-
-                        // Disable all endpoints.
-                        foreach_endpoint(self.config_descriptor, |ep| {
-                            self.bus.endpoint_set_enabled(ep.ep_address, false);
-                        })
-                        .unwrap();
-
-                        // Notify handlers.
-                        for h in &mut self.handlers {
-                            h.configured(false);
-                        }
-                    }
-                    OutResponse::Accepted
-                }
                 _ => OutResponse::Rejected,
             },
             (RequestType::Standard, Recipient::Interface) => {
                 let iface_num = InterfaceNumber::new(req.index as _);
-                let Some(iface) = self.interfaces.get_mut(iface_num.0 as usize) else {
+                let cfg = self.current_configuration();
+                let Some(iface) =
+                    self.interfaces[cfg.first_interface..][..cfg.num_interfaces].get_mut(req.index as usize)
+                else {
                     return OutResponse::Rejected;
                 };
 
@@ -580,7 +840,7 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                         iface.current_alt_setting = new_altsetting;
 
                         // Enable/disable EPs of this interface as needed.
-                        foreach_endpoint(self.config_descriptor, |ep| {
+                        foreach_endpoint(&self.config_descriptor[cfg.active_start..cfg.active_end], |ep| {
                             if ep.interface == iface_num {
                                 self.bus
                                     .endpoint_set_enabled(ep.ep_address, iface.current_alt_setting == ep.interface_alt);
@@ -590,7 +850,8 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
 
                         // TODO check it is valid (not out of range)
 
-                        for h in &mut self.handlers {
+                        let cfg = self.current_configuration_index();
+                        for h in handlers_of(&mut self.handlers, &self.handler_configurations, cfg) {
                             h.set_alternate_setting(iface_num, new_altsetting);
                         }
                         OutResponse::Accepted
@@ -632,7 +893,7 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 Request::GET_DESCRIPTOR => self.handle_get_descriptor(req, buf),
                 Request::GET_CONFIGURATION => {
                     let status = match self.device_state {
-                        UsbDeviceState::Configured => CONFIGURATION_VALUE,
+                        UsbDeviceState::Configured => self.configuration,
                         _ => CONFIGURATION_NONE,
                     };
                     buf[0] = status;
@@ -641,7 +902,9 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 _ => InResponse::Rejected,
             },
             (RequestType::Standard, Recipient::Interface) => {
-                let Some(iface) = self.interfaces.get_mut(req.index as usize) else {
+                let cfg = self.current_configuration();
+                let Some(iface) = self.interfaces[cfg.first_interface..][..cfg.num_interfaces].get(req.index as usize)
+                else {
                     return InResponse::Rejected;
                 };
 
@@ -686,9 +949,85 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
         }
     }
 
+    /// Returns the index of the configuration selected by the host, or 0 if none is.
+    fn current_configuration_index(&self) -> usize {
+        self.configuration.saturating_sub(CONFIGURATION_VALUE) as usize
+    }
+
+    /// Returns the configuration selected by the host, or the first one if none is.
+    fn current_configuration(&self) -> Configuration {
+        self.configurations[self.current_configuration_index()]
+    }
+
+    fn disable_configuration_endpoints(&mut self) {
+        let cfg = self.current_configuration();
+        foreach_endpoint(&self.config_descriptor[cfg.active_start..cfg.active_end], |ep| {
+            self.bus.endpoint_set_enabled(ep.ep_address, false);
+        })
+        .unwrap();
+    }
+
+    /// Writes the configuration descriptors sent to the host, leaving out the disabled functions.
+    fn update_active_descriptors(&mut self) {
+        if self.functions.iter().all(|f| f.enabled) {
+            for cfg in &mut self.configurations {
+                cfg.active_start = cfg.start;
+                cfg.active_end = cfg.end;
+            }
+            return;
+        }
+
+        fn copy(buf: &mut [u8], pos: &mut usize, start: usize, end: usize) {
+            let len = end - start;
+            assert!(
+                *pos + len <= buf.len(),
+                "embassy-usb: config descriptor buffer too small to disable functions"
+            );
+            buf.copy_within(start..end, *pos);
+            *pos += len;
+        }
+
+        let buf = &mut *self.config_descriptor;
+        let mut pos = self.config_descriptor_len;
+        for (i, cfg) in self.configurations.iter_mut().enumerate() {
+            let active_start = pos;
+            let mut num_interfaces = cfg.num_interfaces;
+            let mut cursor = cfg.start;
+            for f in self.functions.iter().filter(|f| f.configuration == i) {
+                copy(buf, &mut pos, cursor, f.start);
+                if f.enabled {
+                    copy(buf, &mut pos, f.start, f.end);
+                } else {
+                    num_interfaces -= f.num_interfaces as usize;
+                }
+                cursor = f.end;
+            }
+            copy(buf, &mut pos, cursor, cfg.end);
+
+            // Patch wTotalLength and bNumInterfaces.
+            let len = (pos - active_start) as u16;
+            buf[active_start + 2..active_start + 4].copy_from_slice(&len.to_le_bytes());
+            buf[active_start + 4] = num_interfaces as u8;
+
+            cfg.active_start = active_start;
+            cfg.active_end = pos;
+        }
+    }
+
+    /// Whether the handler at `index` may receive `req`: requests addressed to an interface only go
+    /// to the handlers of the current configuration, where the interface number is valid.
+    fn handler_accepts(&self, index: usize, req: &Request) -> bool {
+        req.recipient != Recipient::Interface
+            || self.handler_configurations[index] == self.current_configuration_index()
+    }
+
     fn handle_control_out_delegated(&mut self, req: Request, data: &[u8]) -> OutResponse {
-        for h in &mut self.handlers {
-            if let Some(res) = h.control_out(req, data) {
+        for i in 0..self.handlers.len() {
+            if !self.handler_accepts(i, &req) {
+                continue;
+            }
+            if let Some(res) = self.handlers[i].control_out(req, data) {
+                self.deferred_handler = i;
                 return res;
             }
         }
@@ -700,8 +1039,12 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
             core::mem::transmute(r)
         }
 
-        for h in &mut self.handlers {
-            if let Some(res) = h.control_in(req, buf) {
+        for i in 0..self.handlers.len() {
+            if !self.handler_accepts(i, &req) {
+                continue;
+            }
+            if let Some(res) = self.handlers[i].control_in(req, buf) {
+                self.deferred_handler = i;
                 // safety: the borrow checker isn't smart enough to know this pattern (returning a
                 // borrowed value from inside the loop) is sound. Workaround by unsafely extending lifetime.
                 // Also, Polonius (the WIP new borrow checker) does accept it.
@@ -718,7 +1061,10 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
         match dtype {
             descriptor_type::BOS => InResponse::Accepted(self.bos_descriptor),
             descriptor_type::DEVICE => InResponse::Accepted(&self.device_descriptor),
-            descriptor_type::CONFIGURATION => InResponse::Accepted(self.config_descriptor),
+            descriptor_type::CONFIGURATION => match self.configurations.get(index as usize) {
+                Some(cfg) => InResponse::Accepted(&self.config_descriptor[cfg.active_start..cfg.active_end]),
+                None => InResponse::Rejected,
+            },
             descriptor_type::STRING => {
                 if index == 0 {
                     buf[0] = 4; // len
@@ -769,6 +1115,19 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
     }
 }
 
+/// Returns the handlers of the configuration at index `configuration`.
+fn handlers_of<'a, 'd>(
+    handlers: &'a mut [&'d mut dyn Handler],
+    handler_configurations: &'a [usize],
+    configuration: usize,
+) -> impl Iterator<Item = &'a mut &'d mut dyn Handler> {
+    handlers
+        .iter_mut()
+        .zip(handler_configurations)
+        .filter(move |(_, c)| **c == configuration)
+        .map(|(h, _)| h)
+}
+
 fn first_last<T: Iterator>(iter: T) -> impl Iterator<Item = (bool, bool, T::Item)> {
     let mut iter = iter.peekable();
     let mut first = true;
@@ -0,0 +1,166 @@
+//! Bus power management events.
+//!
+//! [`PowerEvents`] lets the application follow suspend, resume and reset of the bus from any task,
+//! for example to turn off peripherals and meet the suspend current budget of the USB specification.
+//! Class implementations get the same events through the [`Handler`] callbacks.
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+
+use crate::driver::Driver;
+use crate::{Builder, Handler};
+
+/// Number of events buffered before the oldest ones are dropped.
+const EVENT_QUEUE_LEN: usize = 8;
+
+/// Bus event reported by [`PowerEvents`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusEvent {
+    /// The USB peripheral was enabled (`true`), or disabled or unpowered (`false`).
+    Enabled(bool),
+    /// The host reset the bus.
+    Reset,
+    /// The host selected (`true`) or deselected (`false`) a configuration.
+    Configured(bool),
+    /// The bus was suspended. The device must reduce its current draw within 10 ms.
+    Suspended,
+    /// The bus resumed, either by the host or by a remote wakeup.
+    Resumed,
+    /// The host enabled (`true`) or disabled (`false`) remote wakeup.
+    RemoteWakeupEnabled(bool),
+}
+
+/// Internal state for [`PowerEvents`].
+pub struct State<'d> {
+    handler: MaybeUninit<EventHandler<'d>>,
+    shared: Shared,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            handler: MaybeUninit::uninit(),
+            shared: Shared::default(),
+        }
+    }
+}
+
+/// Shared data between EventHandler and PowerEvents.
+struct Shared {
+    events: Channel<CriticalSectionRawMutex, BusEvent, EVENT_QUEUE_LEN>,
+    suspended: AtomicBool,
+    configured: AtomicBool,
+    remote_wakeup_enabled: AtomicBool,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            events: Channel::new(),
+            suspended: AtomicBool::new(false),
+            configured: AtomicBool::new(false),
+            remote_wakeup_enabled: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Shared {
+    fn push(&self, event: BusEvent) {
+        if self.events.is_full() {
+            let _ = self.events.try_receive();
+        }
+        let _ = self.events.try_send(event);
+    }
+}
+
+struct EventHandler<'d> {
+    shared: &'d Shared,
+}
+
+impl<'d> Handler for EventHandler<'d> {
+    fn enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.shared.suspended.store(false, Ordering::Relaxed);
+            self.shared.configured.store(false, Ordering::Relaxed);
+            self.shared.remote_wakeup_enabled.store(false, Ordering::Relaxed);
+        }
+        self.shared.push(BusEvent::Enabled(enabled));
+    }
+
+    fn reset(&mut self) {
+        self.shared.suspended.store(false, Ordering::Relaxed);
+        self.shared.configured.store(false, Ordering::Relaxed);
+        self.shared.remote_wakeup_enabled.store(false, Ordering::Relaxed);
+        self.shared.push(BusEvent::Reset);
+    }
+
+    fn configured(&mut self, configured: bool) {
+        self.shared.configured.store(configured, Ordering::Relaxed);
+        self.shared.push(BusEvent::Configured(configured));
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        self.shared.suspended.store(suspended, Ordering::Relaxed);
+        self.shared.push(if suspended {
+            BusEvent::Suspended
+        } else {
+            BusEvent::Resumed
+        });
+    }
+
+    fn remote_wakeup_enabled(&mut self, enabled: bool) {
+        self.shared.remote_wakeup_enabled.store(enabled, Ordering::Relaxed);
+        self.shared.push(BusEvent::RemoteWakeupEnabled(enabled));
+    }
+}
+
+/// Stream of bus power management events.
+///
+/// Events are queued, the oldest ones are dropped if the application doesn't keep up. The current
+/// state is always available with [`is_suspended`](Self::is_suspended) and the other getters.
+#[derive(Clone, Copy)]
+pub struct PowerEvents<'d> {
+    shared: &'d Shared,
+}
+
+impl<'d> PowerEvents<'d> {
+    /// Registers a handler with the builder, and returns a `PowerEvents` to receive its events.
+    pub fn new<D: Driver<'d>>(builder: &mut Builder<'d, D>, state: &'d mut State<'d>) -> Self {
+        let handler = state.handler.write(EventHandler { shared: &state.shared });
+        builder.handler(handler);
+
+        PowerEvents { shared: &state.shared }
+    }
+
+    /// Waits for the next bus event.
+    pub async fn next(&self) -> BusEvent {
+        self.shared.events.receive().await
+    }
+
+    /// Returns whether the bus is suspended.
+    pub fn is_suspended(&self) -> bool {
+        self.shared.suspended.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the host configured the device.
+    pub fn is_configured(&self) -> bool {
+        self.shared.configured.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the host enabled remote wakeup, so [`UsbDevice::remote_wakeup`](crate::UsbDevice::remote_wakeup)
+    /// can be used while suspended.
+    pub fn is_remote_wakeup_enabled(&self) -> bool {
+        self.shared.remote_wakeup_enabled.load(Ordering::Relaxed)
+    }
+}
@@ -18,6 +18,27 @@ impl From<InterfaceNumber> for u8 {
     }
 }
 
+/// A handle for a USB function that contains its number.
+///
+/// Functions are numbered consecutively in the order they are added to the [`Builder`](crate::Builder),
+/// starting from 0, across all configurations.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(transparent)]
+pub struct FunctionNumber(pub u8);
+
+impl FunctionNumber {
+    pub(crate) const fn new(index: u8) -> FunctionNumber {
+        FunctionNumber(index)
+    }
+}
+
+impl From<FunctionNumber> for u8 {
+    fn from(n: FunctionNumber) -> u8 {
+        n.0
+    }
+}
+
 /// A handle for a USB string descriptor that contains its index.
 #[derive(Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -0,0 +1,265 @@
+//! Routing of vendor control requests to an async task.
+//!
+//! [`Handler`] callbacks must answer control requests right away. [`VendorRequests`] instead hands
+//! vendor requests over to a task, which can take its time to answer them, for example to read or
+//! write flash. The USB stack NAKs the request in the meantime. A request abandoned by the host
+//! before it is answered is dropped, and its answer ignored.
+//!
+//! Vendor requests that an earlier registered handler answers don't reach [`VendorRequests`].
+
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::task::{Context, Poll};
+
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, OutResponse, Request, RequestType};
+use crate::driver::{Direction, Driver};
+use crate::{Builder, Handler};
+
+/// Internal state for [`VendorRequests`].
+///
+/// `N` is the size of the buffer for the data stage of the requests, OUT requests with more data
+/// are rejected.
+pub struct State<'d, const N: usize> {
+    handler: MaybeUninit<VendorHandler<'d, N>>,
+    shared: Shared<N>,
+}
+
+impl<'d, const N: usize> Default for State<'d, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d, const N: usize> State<'d, N> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            handler: MaybeUninit::uninit(),
+            shared: Shared::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Response {
+    /// Accepted, with the length of the response data for IN requests.
+    Accepted(usize),
+    Rejected,
+}
+
+/// Shared data between VendorHandler and VendorRequests.
+struct Shared<const N: usize> {
+    /// Request waiting to be received by the application.
+    request: Cell<Option<Request>>,
+    /// Answer of the application, waiting to be sent by the USB stack.
+    response: Cell<Option<Response>>,
+    /// Incremented when a request is deferred or abandoned, so that answers to abandoned requests
+    /// are ignored.
+    serial: Cell<u32>,
+    /// Data stage of OUT requests, or response of IN requests.
+    data: RefCell<[u8; N]>,
+    data_len: Cell<usize>,
+    request_waker: RefCell<WakerRegistration>,
+    response_waker: RefCell<WakerRegistration>,
+}
+
+impl<const N: usize> Default for Shared<N> {
+    fn default() -> Self {
+        Shared {
+            request: Cell::new(None),
+            response: Cell::new(None),
+            serial: Cell::new(0),
+            data: RefCell::new([0; N]),
+            data_len: Cell::new(0),
+            request_waker: RefCell::new(WakerRegistration::new()),
+            response_waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+}
+
+impl<const N: usize> Shared<N> {
+    fn defer(&self, req: Request) {
+        self.serial.set(self.serial.get().wrapping_add(1));
+        self.response.set(None);
+        self.request.set(Some(req));
+        self.request_waker.borrow_mut().wake();
+    }
+
+    fn cancel(&self) {
+        self.serial.set(self.serial.get().wrapping_add(1));
+        self.request.set(None);
+        self.response.set(None);
+    }
+
+    fn poll_response(&self, cx: &mut Context<'_>) -> Poll<Response> {
+        match self.response.take() {
+            Some(response) => Poll::Ready(response),
+            None => {
+                self.response_waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+struct VendorHandler<'d, const N: usize> {
+    shared: &'d Shared<N>,
+}
+
+impl<'d, const N: usize> Handler for VendorHandler<'d, N> {
+    fn reset(&mut self) {
+        self.shared.cancel();
+    }
+
+    fn cancel_deferred(&mut self) {
+        self.shared.cancel();
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if req.request_type != RequestType::Vendor {
+            return None;
+        }
+        if data.len() > N {
+            warn!("vendor: request data too long, rejecting");
+            return Some(OutResponse::Rejected);
+        }
+
+        self.shared.data.borrow_mut()[..data.len()].copy_from_slice(data);
+        self.shared.data_len.set(data.len());
+        self.shared.defer(req);
+        Some(OutResponse::Deferred)
+    }
+
+    fn poll_deferred_out(&mut self, cx: &mut Context<'_>) -> Poll<OutResponse> {
+        self.shared.poll_response(cx).map(|response| match response {
+            Response::Accepted(_) => OutResponse::Accepted,
+            Response::Rejected => OutResponse::Rejected,
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, _buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if req.request_type != RequestType::Vendor {
+            return None;
+        }
+
+        self.shared.data_len.set(0);
+        self.shared.defer(req);
+        Some(InResponse::Deferred)
+    }
+
+    fn poll_deferred_in(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Option<usize>> {
+        self.shared.poll_response(cx).map(|response| match response {
+            Response::Accepted(len) => {
+                let len = len.min(buf.len());
+                buf[..len].copy_from_slice(&self.shared.data.borrow()[..len]);
+                Some(len)
+            }
+            Response::Rejected => None,
+        })
+    }
+}
+
+/// Receiver of vendor control requests.
+///
+/// Each request received with [`receive`](Self::receive) must be answered with [`accept`](Self::accept),
+/// [`respond`](Self::respond) or [`reject`](Self::reject) before the next one.
+pub struct VendorRequests<'d, const N: usize> {
+    shared: &'d Shared<N>,
+    /// Direction and serial number of the request being handled, if any.
+    pending: Option<(Direction, u32)>,
+}
+
+impl<'d, const N: usize> VendorRequests<'d, N> {
+    /// Registers a handler for vendor requests with the builder.
+    pub fn new<D: Driver<'d>>(builder: &mut Builder<'d, D>, state: &'d mut State<'d, N>) -> Self {
+        let handler = state.handler.write(VendorHandler { shared: &state.shared });
+        builder.handler(handler);
+
+        VendorRequests {
+            shared: &state.shared,
+            pending: None,
+        }
+    }
+
+    /// Waits for a vendor request.
+    ///
+    /// For OUT requests, the data stage is copied to `buf`, and its length is returned. `buf` should
+    /// be `N` bytes long, data that doesn't fit is dropped.
+    ///
+    /// A request that wasn't answered yet is rejected.
+    pub async fn receive(&mut self, buf: &mut [u8]) -> (Request, usize) {
+        if self.pending.is_some() {
+            self.reject();
+        }
+
+        let req = poll_fn(|cx| match self.shared.request.take() {
+            Some(req) => Poll::Ready(req),
+            None => {
+                self.shared.request_waker.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await;
+
+        let len = self.shared.data_len.get().min(buf.len());
+        buf[..len].copy_from_slice(&self.shared.data.borrow()[..len]);
+        self.pending = Some((req.direction, self.shared.serial.get()));
+        (req, len)
+    }
+
+    /// Accepts the request being handled.
+    ///
+    /// IN requests are answered with no data.
+    pub fn accept(&mut self) {
+        self.answer(Response::Accepted(0));
+    }
+
+    /// Accepts an IN request, with `data` as response.
+    ///
+    /// The response is truncated to `N` bytes, and to the length requested by the host.
+    pub fn respond(&mut self, data: &[u8]) {
+        if matches!(self.pending, Some((Direction::Out, _))) {
+            warn!("vendor: response data for an OUT request, ignoring it");
+            return self.accept();
+        }
+        if self.take_pending() {
+            // The data buffer may hold the data stage of a newer request if this one was abandoned,
+            // only write it after checking.
+            let len = data.len().min(N);
+            self.shared.data.borrow_mut()[..len].copy_from_slice(&data[..len]);
+            self.send(Response::Accepted(len));
+        }
+    }
+
+    /// Rejects the request being handled, stalling the control pipe.
+    pub fn reject(&mut self) {
+        self.answer(Response::Rejected);
+    }
+
+    fn answer(&mut self, response: Response) {
+        if self.take_pending() {
+            self.send(response);
+        }
+    }
+
+    /// Ends the request being handled, returns whether the USB stack still waits for its answer.
+    fn take_pending(&mut self) -> bool {
+        let Some((_, serial)) = self.pending.take() else {
+            warn!("vendor: no request to answer");
+            return false;
+        };
+        if serial != self.shared.serial.get() {
+            debug!("vendor: request abandoned by the host, dropping the answer");
+            return false;
+        }
+        true
+    }
+
+    fn send(&mut self, response: Response) {
+        self.shared.response.set(Some(response));
+        self.shared.response_waker.borrow_mut().wake();
+    }
+}